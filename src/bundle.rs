@@ -0,0 +1,186 @@
+//! Export a description of a running experiment.
+//!
+//! See [`Tsunami::export_bundle`](crate::Tsunami::export_bundle).
+
+use crate::providers::MachineState;
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+const README: &str = "\
+This directory describes a set of machines launched with tsunami.
+
+launcher      the launcher configuration: provider, regions, and each machine's setup
+              (instance type, image, username, ...). Setup functions are Rust closures, and
+              are not included; they must be supplied again to re-run the experiment.
+machines      one line per machine: nickname, state, public ip, public dns, private ip, user,
+              private key path, ssh port. tsunami::observer::Observer reads this file.
+env/<name>/   each machine's software environment, see tsunami::provenance. Machines that
+              could not be reached when the bundle was written have none.
+replay.sh     re-creates the environment of one machine on a fresh host launched with the
+              same setup: ./replay.sh <name> <user@host>
+";
+
+// Installs the recorded package versions and checks out the recorded commits of one machine on
+// another host. Package pinning only works on dpkg-based images.
+const REPLAY: &str = r#"#!/bin/sh
+set -e
+cd "$(dirname "$0")"
+[ $# -eq 2 ] || { echo "usage: $0 <name> <user@host>" >&2; exit 1; }
+env="env/$1"
+[ -d "$env" ] || { echo "no environment was recorded for $1" >&2; exit 1; }
+if [ -s "$env/packages" ]; then
+  awk -F '\t' 'NF == 2 { print $1 "=" $2 }' "$env/packages" |
+    ssh "$2" 'xargs -r sudo apt-get install -y -q --allow-downgrades'
+fi
+if [ -s "$env/git" ]; then
+  while IFS="$(printf '\t')" read -r dir url commit; do
+    ssh -n "$2" "[ -d '$dir' ] || git clone -q '$url' '$dir'; cd '$dir' && git fetch -q origin '$commit' && git checkout -q '$commit'"
+  done < "$env/git"
+fi
+echo "recorded kernel: $(cat "$env/kernel")"
+echo "current kernel:  $(ssh "$2" uname -a)"
+"#;
+
+pub(crate) fn machines_table(
+    machines: &HashMap<String, crate::LazyMachine<'_>>,
+    states: &HashMap<String, MachineState>,
+) -> String {
+    let mut names: Vec<_> = states.keys().chain(machines.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    let mut s = String::new();
+    for name in names {
+        let state = states
+            .get(name)
+            .map(ToString::to_string)
+            .unwrap_or_else(|| String::from("unknown"));
+        let _ = match machines.get(name) {
+            Some(m) => writeln!(
+                s,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                name,
+                state,
+                m.public_ip,
+                m.public_dns,
+                m.private_ip.as_deref().unwrap_or("-"),
+                m.username,
+                m.private_key
+                    .as_ref()
+                    .map(|k| k.display().to_string())
+                    .unwrap_or_else(|| String::from("-")),
+                m.port
+            ),
+            None => writeln!(s, "{}\t{}\t-\t-\t-\t-\t-\t-", name, state),
+        };
+    }
+    s
+}
+
+pub(crate) async fn export(
+    launcher: String,
+    machines: HashMap<String, crate::LazyMachine<'_>>,
+    states: HashMap<String, MachineState>,
+    dir: &Path,
+) -> Result<PathBuf, Report> {
+    std::fs::create_dir_all(dir).wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+    let write = |file: &str, contents: &str| {
+        let path = dir.join(file);
+        std::fs::write(&path, contents)
+            .wrap_err_with(|| format!("failed to write {}", path.display()))
+    };
+
+    write("README", README)?;
+    write("launcher", &launcher)?;
+    write("machines", &machines_table(&machines, &states))?;
+    write("replay.sh", REPLAY)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let path = dir.join("replay.sh");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .wrap_err_with(|| format!("failed to make {} executable", path.display()))?;
+    }
+
+    // a bundle is most needed when something went wrong, so machines that cannot be reached are
+    // left out rather than failing the export.
+    for (name, m) in &machines {
+        if states.get(name) != Some(&MachineState::Ready) {
+            continue;
+        }
+        let captured = match m.connect().await {
+            Ok(vm) => crate::provenance::capture(vm, dir.join("env")).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = captured {
+            tracing::warn!(%name, "failed to record environment: {:?}", e);
+        }
+    }
+
+    let tarball = tarball_path(dir)?;
+    let parent = match dir.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let out = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(&tarball)
+        .arg("-C")
+        .arg(parent)
+        .arg(dir.file_name().unwrap_or_default())
+        .output()
+        .wrap_err("failed to run tar")?;
+    eyre::ensure!(
+        out.status.success(),
+        "failed to pack {}: {}",
+        dir.display(),
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+
+    tracing::info!(dir = %dir.display(), tarball = %tarball.display(), "exported bundle");
+    Ok(tarball)
+}
+
+// `dir.tar.gz`, next to `dir`.
+fn tarball_path(dir: &Path) -> Result<PathBuf, Report> {
+    let mut name = dir
+        .file_name()
+        .ok_or_else(|| eyre::eyre!("{} does not name a directory", dir.display()))?
+        .to_os_string();
+    name.push(".tar.gz");
+    Ok(dir.with_file_name(name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unreachable_machines_are_listed() {
+        let states: HashMap<_, _> = vec![
+            (String::from("b"), MachineState::Unreachable),
+            (String::from("a"), MachineState::Terminated),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            machines_table(&HashMap::new(), &states),
+            "a\tterminated\t-\t-\t-\t-\t-\t-\nb\tunreachable\t-\t-\t-\t-\t-\t-\n"
+        );
+    }
+
+    #[test]
+    fn tarball_next_to_dir() {
+        assert_eq!(
+            tarball_path(Path::new("results/bundle")).unwrap(),
+            Path::new("results/bundle.tar.gz")
+        );
+        assert_eq!(
+            tarball_path(Path::new("bundle")).unwrap(),
+            Path::new("bundle.tar.gz")
+        );
+        assert!(tarball_path(Path::new("..")).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Check that files on machines are intact.
+//!
+//! An upload that was cut short, or a build that produced something different on one machine,
+//! often goes unnoticed until hours later when the results turn out to be garbage.
+//! [`verify_files`] compares the SHA-256 of each file on each machine against a local copy, or,
+//! when there is no local copy, against what the majority of machines have:
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::checksum::{verify_files, File};
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! verify_files(
+//!     &vms,
+//!     &[
+//!         File::new("data/input.bin").local("input.bin"),
+//!         File::new("target/release/server"),
+//!     ],
+//! )
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use color_eyre::{eyre::WrapErr, Report};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A file to check, see [`verify_files`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    remote: String,
+    local: Option<PathBuf>,
+}
+
+impl File {
+    /// Check the file at `remote_path` on each machine.
+    ///
+    /// Relative paths are relative to the ssh user's home directory.
+    pub fn new(remote_path: impl ToString) -> Self {
+        File {
+            remote: remote_path.to_string(),
+            local: None,
+        }
+    }
+
+    /// Compare against the local file at `path`, instead of across machines.
+    pub fn local(mut self, path: impl AsRef<Path>) -> Self {
+        self.local = Some(path.as_ref().to_path_buf());
+        self
+    }
+}
+
+/// One file on one machine that did not have the expected contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FileMismatch {
+    /// The machine's nickname.
+    pub nickname: String,
+    /// The file's path on the machine.
+    pub path: String,
+    /// The expected SHA-256, or `None` if no majority of machines agreed on one.
+    pub expected: Option<String>,
+    /// The file's SHA-256, or `None` if the file could not be read.
+    pub found: Option<String>,
+}
+
+/// The error returned by [`verify_files`] when some files do not match.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct FileMismatches {
+    /// Every mismatch, sorted by path and then nickname.
+    pub mismatches: Vec<FileMismatch>,
+}
+
+impl std::fmt::Display for FileMismatches {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} files do not match", self.mismatches.len())?;
+        for m in &self.mismatches {
+            write!(
+                f,
+                "\n  {}:{}: {}, expected {}",
+                m.nickname,
+                m.path,
+                m.found.as_deref().unwrap_or("missing"),
+                m.expected.as_deref().unwrap_or("(no majority)")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for FileMismatches {}
+
+fn sha256_of(mut r: impl Read) -> std::io::Result<String> {
+    let mut h = Sha256::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        h.update(&buf[..n]);
+    }
+    Ok(h.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Compute the SHA-256 of the local file at `path`, as lowercase hex.
+pub fn sha256(path: impl AsRef<Path>) -> Result<String, Report> {
+    let path = path.as_ref();
+    std::fs::File::open(path)
+        .and_then(sha256_of)
+        .wrap_err_with(|| format!("failed to read {}", path.display()))
+}
+
+// Parses `sha256sum` output into path -> digest.
+fn parse_sums(out: &str) -> HashMap<String, String> {
+    out.lines()
+        .filter_map(|l| {
+            let (sum, path) = l.split_once(' ')?;
+            // sha256sum separates with two spaces, or " *" for binary mode.
+            let path = path.strip_prefix(|c| c == ' ' || c == '*')?;
+            Some((path.to_string(), sum.to_string()))
+        })
+        .collect()
+}
+
+/// Compute the SHA-256 of each of `paths` on `m`.
+///
+/// Files that cannot be read are left out of the result.
+pub async fn remote_sha256s(
+    m: &crate::Machine<'_>,
+    paths: &[&str],
+) -> Result<HashMap<String, String>, Report> {
+    let mut cmd = m.ssh.command("sha256sum");
+    cmd.arg("--");
+    for p in paths {
+        cmd.arg(*p);
+    }
+    // sha256sum exits non-zero if any file is missing, but still prints the others.
+    let out = cmd.output().await?;
+    Ok(parse_sums(&String::from_utf8_lossy(&out.stdout)))
+}
+
+// Finds the digest most machines agree on, if more than half of them do.
+fn majority<'a>(sums: impl Iterator<Item = Option<&'a String>>) -> Option<String> {
+    let mut counts: HashMap<Option<&String>, usize> = HashMap::new();
+    let mut total = 0;
+    for s in sums {
+        *counts.entry(s).or_default() += 1;
+        total += 1;
+    }
+    counts
+        .into_iter()
+        .find(|(_, n)| 2 * n > total)
+        .and_then(|(s, _)| s.cloned())
+}
+
+fn compare(
+    files: &[File],
+    local: &[Option<String>],
+    remote: &HashMap<String, HashMap<String, String>>,
+) -> Vec<FileMismatch> {
+    let mut mismatches = Vec::new();
+    for (f, local) in files.iter().zip(local) {
+        let expected = match local {
+            Some(l) => Some(l.clone()),
+            None => majority(remote.values().map(|sums| sums.get(&f.remote))),
+        };
+        for (name, sums) in remote {
+            let found = sums.get(&f.remote);
+            if expected.is_none() || found != expected.as_ref() {
+                mismatches.push(FileMismatch {
+                    nickname: name.clone(),
+                    path: f.remote.clone(),
+                    expected: expected.clone(),
+                    found: found.cloned(),
+                });
+            }
+        }
+    }
+    mismatches.sort_by(|a, b| (&a.path, &a.nickname).cmp(&(&b.path, &b.nickname)));
+    mismatches
+}
+
+/// Check that every machine in `machines` has the expected contents for each of `files`.
+///
+/// The checksums are computed on all machines in parallel. If any file is missing or differs, the
+/// error is a [`FileMismatches`] listing every mismatch. A file without a local copy is compared
+/// across machines, and every machine that differs from the majority is reported; if there is no
+/// majority, every machine is.
+pub async fn verify_files(
+    machines: &HashMap<String, crate::Machine<'_>>,
+    files: &[File],
+) -> Result<(), Report> {
+    let local = files
+        .iter()
+        .map(|f| f.local.as_ref().map(sha256).transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let paths: Vec<_> = files.iter().map(|f| f.remote.as_str()).collect();
+    let paths = &paths;
+    let remote = crate::each::for_each(machines, machines.len(), |m| async move {
+        remote_sha256s(m, paths).await
+    })
+    .await?;
+
+    let mismatches = compare(files, &local, &remote);
+    if !mismatches.is_empty() {
+        return Err(Report::new(FileMismatches { mismatches }));
+    }
+    tracing::debug!(
+        files = files.len(),
+        machines = machines.len(),
+        "files verified"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn digest() {
+        assert_eq!(
+            sha256_of(&b"abc"[..]).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn mismatches() {
+        let sums = parse_sums("aaa  bin/server\nbbb *data\n");
+        assert_eq!(sums["bin/server"], "aaa");
+        assert_eq!(sums["data"], "bbb");
+
+        let remote: HashMap<_, _> = vec![
+            ("m0", sums.clone()),
+            ("m1", sums.clone()),
+            ("m2", parse_sums("ccc  bin/server\n")),
+        ]
+        .into_iter()
+        .map(|(n, s)| (n.to_string(), s))
+        .collect();
+        let files = [File::new("bin/server"), File::new("data").local("data")];
+        let ms = compare(&files, &[None, Some(String::from("bbb"))], &remote);
+        let ms: Vec<_> = ms
+            .iter()
+            .map(|m| (m.nickname.as_str(), m.path.as_str(), m.found.as_deref()))
+            .collect();
+        assert_eq!(
+            ms,
+            vec![("m2", "bin/server", Some("ccc")), ("m2", "data", None)]
+        );
+    }
+}
@@ -0,0 +1,865 @@
+//! Get the code of an experiment onto the machines.
+//!
+//! Most experiments with Rust code start the same way: compile the program, get it onto every
+//! machine, and run it. [`Binary::deploy`] cross-compiles a binary of the local cargo project as a
+//! static musl executable for each architecture among the machines (so the machines need neither
+//! a Rust toolchain nor matching libraries), uploads it to all of them, and returns where it
+//! ended up:
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::deploy::Binary;
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! let server = Binary::new("server").features(["metrics"]).deploy(&vms).await?;
+//! vms["server"].ssh.command(&server).arg("--port=9000").spawn()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! By default, this builds with the local `cargo`, which needs the musl targets to be installed
+//! (e.g. `rustup target add aarch64-unknown-linux-musl`) along with a linker for them.
+//! [`Binary::cross`] builds with [`cross`](https://github.com/cross-rs/cross) instead, which
+//! brings its own toolchains.
+//!
+//! When the code should instead be built on the machines themselves, or is not a Rust project,
+//! [`Repository::deploy`] checks out one pinned commit of a git repository on every machine, and
+//! optionally builds it there:
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::deploy::Repository;
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! let commit = Repository::new("git@github.com:me/bench.git", "v1.2")
+//!     .deploy_key("keys/bench-deploy")
+//!     .build("make -j")
+//!     .deploy(&vms)
+//!     .await?;
+//! println!("running bench at {}", commit);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The deployed commit is recorded on each machine, and so ends up in the environment that
+//! [`provenance::capture`](crate::provenance::capture) saves.
+//!
+//! Work that gives the same result on every machine, such as a slow build from source or
+//! generating an input dataset, only needs to happen once. An [`Artifact`] is produced on one
+//! machine of a group, the leader, and then copied to the rest of the group:
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::deploy::Artifact;
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! Artifact::new("data/input.bin")
+//!     .group("role=client".parse()?)
+//!     .distribute(&vms, |leader| async move {
+//!         let status = leader
+//!             .ssh
+//!             .command("python3")
+//!             .arg("gen.py")
+//!             .arg("--out=data/input.bin")
+//!             .status()
+//!             .await?;
+//!         color_eyre::eyre::ensure!(status.success(), "gen.py failed");
+//!         Ok(())
+//!     })
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::run::{quote, Target};
+use crate::Machine;
+use color_eyre::{
+    eyre::{self, eyre, WrapErr},
+    Help, Report,
+};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+
+/// A CPU architecture that binaries can be built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Arch {
+    /// 64-bit x86.
+    X86_64,
+    /// 64-bit ARM, such as AWS Graviton.
+    Aarch64,
+}
+
+impl Arch {
+    /// The target triple of static Linux executables for this architecture.
+    pub fn musl_target(&self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64-unknown-linux-musl",
+            Arch::Aarch64 => "aarch64-unknown-linux-musl",
+        }
+    }
+
+    // The architecture that `uname -m` prints `s` for.
+    fn from_uname(s: &str) -> Result<Self, Report> {
+        match s.trim() {
+            "x86_64" | "amd64" => Ok(Arch::X86_64),
+            "aarch64" | "arm64" => Ok(Arch::Aarch64),
+            s => eyre::bail!("unsupported architecture {:?}", s),
+        }
+    }
+
+    /// The architecture of `vm`.
+    pub async fn of(vm: &Machine<'_>) -> Result<Self, Report> {
+        let out = vm
+            .ssh
+            .command("uname")
+            .arg("-m")
+            .output()
+            .await
+            .wrap_err("failed to run uname")?;
+        eyre::ensure!(out.status.success(), "uname failed");
+        Self::from_uname(&String::from_utf8_lossy(&out.stdout))
+            .wrap_err_with(|| format!("cannot build for {}", vm.nickname))
+    }
+}
+
+/// A binary of the local cargo project, to build and copy to machines.
+#[derive(Debug, Clone)]
+pub struct Binary {
+    bin: String,
+    package: Option<String>,
+    manifest_path: Option<PathBuf>,
+    features: Vec<String>,
+    release: bool,
+    builder: String,
+    remote_dir: String,
+}
+
+impl Binary {
+    /// The binary target `bin`, built in release mode with the local `cargo`.
+    ///
+    /// It is uploaded to `bin/<bin>` in the ssh user's home directory.
+    pub fn new(bin: impl ToString) -> Self {
+        Binary {
+            bin: bin.to_string(),
+            package: None,
+            manifest_path: None,
+            features: Vec::new(),
+            release: true,
+            builder: String::from("cargo"),
+            remote_dir: String::from("bin"),
+        }
+    }
+
+    /// Build the binary of the workspace member `package`.
+    pub fn package(mut self, package: impl ToString) -> Self {
+        self.package = Some(package.to_string());
+        self
+    }
+
+    /// Build the project at `path` (a `Cargo.toml`), instead of the one in the current directory.
+    pub fn manifest_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.manifest_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Enable the cargo features `features`.
+    pub fn features(mut self, features: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.features
+            .extend(features.into_iter().map(|f| f.to_string()));
+        self
+    }
+
+    /// Build in debug mode rather than release mode.
+    pub fn debug(mut self) -> Self {
+        self.release = false;
+        self
+    }
+
+    /// Build with `cross`, which runs the build in a container with the target's toolchain.
+    pub fn cross(mut self) -> Self {
+        self.builder = String::from("cross");
+        self
+    }
+
+    /// Upload to the directory `dir` on the machines, relative to the ssh user's home directory
+    /// unless it is absolute. The default is `bin`.
+    pub fn remote_dir(mut self, dir: impl ToString) -> Self {
+        self.remote_dir = dir.to_string();
+        self
+    }
+
+    /// Where the binary ends up on the machines.
+    pub fn remote_path(&self) -> String {
+        format!("{}/{}", self.remote_dir.trim_end_matches('/'), self.bin)
+    }
+
+    fn build_args(&self, arch: Arch) -> Vec<String> {
+        let mut args = vec![
+            String::from("build"),
+            String::from("--message-format=json-render-diagnostics"),
+            format!("--target={}", arch.musl_target()),
+            format!("--bin={}", self.bin),
+        ];
+        if self.release {
+            args.push(String::from("--release"));
+        }
+        if let Some(ref p) = self.package {
+            args.push(format!("--package={}", p));
+        }
+        if let Some(ref m) = self.manifest_path {
+            args.push(format!("--manifest-path={}", m.display()));
+        }
+        if !self.features.is_empty() {
+            args.push(format!("--features={}", self.features.join(",")));
+        }
+        args
+    }
+
+    /// Build the binary for `arch`, and return the path to the executable.
+    #[tracing::instrument(level = "debug")]
+    pub async fn build(&self, arch: Arch) -> Result<PathBuf, Report> {
+        tracing::info!(target = arch.musl_target(), "building binary");
+        // diagnostics go to stderr, which the user gets to see
+        let out = tokio::process::Command::new(&self.builder)
+            .args(self.build_args(arch))
+            .stderr(std::process::Stdio::inherit())
+            .output()
+            .await
+            .wrap_err_with(|| format!("failed to run {}", self.builder))?;
+        if !out.status.success() {
+            return Err(eyre::eyre!("failed to build {}", self.bin)).with_suggestion(|| {
+                format!(
+                    "Install the target with `rustup target add {}`, or build with Binary::cross",
+                    arch.musl_target()
+                )
+            });
+        }
+        executable(&String::from_utf8_lossy(&out.stdout), &self.bin)
+            .ok_or_else(|| eyre::eyre!("{} did not produce an executable", self.builder))
+    }
+
+    /// Build the binary for every architecture among `machines`, upload it to all of them, and
+    /// return its [path](Binary::remote_path) on the machines.
+    ///
+    /// Each architecture is built once. The uploads run concurrently, and replace any earlier
+    /// copy of the binary.
+    pub async fn deploy(&self, machines: &HashMap<String, Machine<'_>>) -> Result<String, Report> {
+        let arches = futures_util::future::join_all(
+            machines
+                .iter()
+                .map(|(name, vm)| async move { Ok::<_, Report>((name, Arch::of(vm).await?)) }),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<HashMap<_, _>, Report>>()?;
+
+        let mut builds = HashMap::new();
+        for arch in arches.values() {
+            if !builds.contains_key(arch) {
+                builds.insert(*arch, self.build(*arch).await?);
+            }
+        }
+
+        let remote = self.remote_path();
+        futures_util::future::join_all(machines.iter().map(|(name, vm)| {
+            let local = &builds[&arches[name]];
+            let remote = &remote;
+            async move {
+                upload(vm, local, &self.remote_dir, remote, "+x")
+                    .await
+                    .wrap_err_with(|| format!("failed to upload {} to {}", self.bin, name))
+            }
+        }))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, Report>>()?;
+        Ok(remote)
+    }
+}
+
+// The executable of the binary target `bin` among cargo's JSON messages.
+fn executable(messages: &str, bin: &str) -> Option<PathBuf> {
+    messages
+        .lines()
+        .filter_map(|l| serde_json::from_str::<serde_json::Value>(l).ok())
+        .filter(|m| m["reason"] == "compiler-artifact" && m["target"]["name"] == bin)
+        .filter_map(|m| m["executable"].as_str().map(PathBuf::from))
+        .next_back()
+}
+
+// Copies `local` to `remote` on `vm` with the permissions `mode` (as given to chmod), through a
+// temporary file so that a running copy of the binary is not disturbed.
+async fn upload(
+    vm: &Machine<'_>,
+    local: &Path,
+    dir: &str,
+    remote: &str,
+    mode: &str,
+) -> Result<(), Report> {
+    let tmp = format!("{}.tsunami-upload", remote);
+    let status = vm.ssh.command("mkdir").arg("-p").arg(dir).status().await?;
+    eyre::ensure!(status.success(), "failed to create {}", dir);
+
+    let host = if vm.public_ip.contains(':') {
+        format!("[{}]", vm.public_ip)
+    } else {
+        vm.public_ip.clone()
+    };
+    let out = tokio::process::Command::new("scp")
+        .arg("-q")
+        .args(vm.ssh_options())
+        .arg(local)
+        .arg(format!("{}@{}:{}", vm.username, host, tmp))
+        .output()
+        .await
+        .wrap_err("failed to run scp")?;
+    eyre::ensure!(
+        out.status.success(),
+        "scp failed: {}",
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+
+    let status = vm
+        .ssh
+        .command("sh")
+        .arg("-c")
+        .arg(format!(
+            "chmod {0} '{1}' && mv -f '{1}' '{2}'",
+            mode, tmp, remote
+        ))
+        .status()
+        .await?;
+    eyre::ensure!(status.success(), "failed to install {}", remote);
+    Ok(())
+}
+
+// Copies `remote` on `vm` to `local`.
+async fn download(vm: &Machine<'_>, remote: &str, local: &Path) -> Result<(), Report> {
+    let host = if vm.public_ip.contains(':') {
+        format!("[{}]", vm.public_ip)
+    } else {
+        vm.public_ip.clone()
+    };
+    let out = tokio::process::Command::new("scp")
+        .arg("-q")
+        .args(vm.ssh_options())
+        .arg(format!("{}@{}:{}", vm.username, host, remote))
+        .arg(local)
+        .output()
+        .await
+        .wrap_err("failed to run scp")?;
+    eyre::ensure!(
+        out.status.success(),
+        "scp failed: {}",
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+    Ok(())
+}
+
+/// Where [`Repository::deploy`] records what it checked out, relative to the ssh user's home
+/// directory.
+///
+/// Each line holds the checkout directory, the repository URL, and the commit, separated by tabs.
+pub const DEPLOYED_COMMITS: &str = ".tsunami/git";
+
+// Where the deploy key is stored on the machines, relative to the ssh user's home directory.
+const REMOTE_DEPLOY_KEY: &str = ".tsunami/deploy-key";
+
+fn is_commit_hash(rev: &str) -> bool {
+    rev.len() == 40 && rev.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// The ssh command git should use to authenticate with the private key `key`, which is already
+// quoted for sh.
+fn git_ssh_command(key: &str) -> String {
+    format!(
+        "ssh -i {} -o IdentitiesOnly=yes -o StrictHostKeyChecking=accept-new",
+        key
+    )
+}
+
+// The commit that `git ls-remote <url> <rev>` printed `out` for. For annotated tags, this is the
+// commit the tag points to rather than the tag object.
+fn ls_remote_commit(out: &str) -> Option<String> {
+    let refs: Vec<_> = out
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.split('\t');
+            Some((fields.next()?.trim(), fields.next()?.trim()))
+        })
+        .filter(|(hash, _)| is_commit_hash(hash))
+        .collect();
+    refs.iter()
+        .find(|(_, name)| name.ends_with("^{}"))
+        .or_else(|| refs.first())
+        .map(|(hash, _)| hash.to_lowercase())
+}
+
+/// A git repository to check out on machines at one pinned commit.
+#[derive(Debug, Clone)]
+pub struct Repository {
+    url: String,
+    rev: String,
+    dir: String,
+    deploy_key: Option<PathBuf>,
+    build: Option<String>,
+}
+
+impl Repository {
+    /// The repository at `url`, at the revision `rev`: a commit hash, a branch, or a tag.
+    ///
+    /// It is checked out into a directory named after the repository in the ssh user's home
+    /// directory, such as `bench` for `git@github.com:me/bench.git`.
+    pub fn new(url: impl ToString, rev: impl ToString) -> Self {
+        let url = url.to_string();
+        let dir = url
+            .trim_end_matches('/')
+            .rsplit(['/', ':'])
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches(".git")
+            .to_string();
+        Repository {
+            url,
+            rev: rev.to_string(),
+            dir,
+            deploy_key: None,
+            build: None,
+        }
+    }
+
+    /// Check out into the directory `dir` on the machines, relative to the ssh user's home
+    /// directory unless it is absolute.
+    pub fn dir(mut self, dir: impl ToString) -> Self {
+        self.dir = dir.to_string();
+        self
+    }
+
+    /// Authenticate to the repository's host with the ssh private key at `path`, such as a GitHub
+    /// deploy key.
+    ///
+    /// The key is used locally to resolve the revision, and is copied to the machines, readable
+    /// only by the ssh user, so that they can fetch the commit.
+    pub fn deploy_key(mut self, path: impl AsRef<Path>) -> Self {
+        self.deploy_key = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Run `cmd` with `sh -c` in the checkout on every machine, such as `cargo build --release`.
+    ///
+    /// The machines build concurrently.
+    pub fn build(mut self, cmd: impl ToString) -> Self {
+        self.build = Some(cmd.to_string());
+        self
+    }
+
+    /// The full hash of the commit that the revision names.
+    ///
+    /// A full commit hash is returned as is. Anything else is looked up with the local
+    /// `git ls-remote`, so a branch is resolved only once, and all machines get the same commit
+    /// even if the branch moves in the meantime.
+    #[tracing::instrument(level = "debug")]
+    pub async fn resolve(&self) -> Result<String, Report> {
+        if is_commit_hash(&self.rev) {
+            return Ok(self.rev.to_lowercase());
+        }
+        let mut cmd = tokio::process::Command::new("git");
+        cmd.arg("ls-remote").arg(&self.url).arg(&self.rev);
+        if let Some(ref k) = self.deploy_key {
+            cmd.env(
+                "GIT_SSH_COMMAND",
+                git_ssh_command(&quote(&k.display().to_string())),
+            );
+        }
+        let out = cmd.output().await.wrap_err("failed to run git")?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to list {}: {}",
+            self.url,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+        ls_remote_commit(&String::from_utf8_lossy(&out.stdout))
+            .ok_or_else(|| eyre::eyre!("{} has no branch or tag {:?}", self.url, self.rev))
+            .suggestion("Give the full hash of the commit instead")
+    }
+
+    // The script that checks out `commit` on a machine, and records it in `DEPLOYED_COMMITS`.
+    fn checkout_script(&self, commit: &str) -> String {
+        let mut script = String::from("set -e\n");
+        if self.deploy_key.is_some() {
+            let key = format!("\"$HOME\"/{}", REMOTE_DEPLOY_KEY);
+            let _ = writeln!(
+                script,
+                "export GIT_SSH_COMMAND={}",
+                quote(&git_ssh_command(&key))
+            );
+        }
+        let dir = quote(&self.dir);
+        let url = quote(&self.url);
+        let _ = writeln!(script, "[ -d {0}/.git ] || git init -q {0}", dir);
+        let _ = writeln!(script, "cd {}", dir);
+        // not every server lets clients fetch a commit by its hash.
+        let _ = writeln!(
+            script,
+            "git fetch -q {0} {1} || git fetch -q {0} '+refs/heads/*:refs/remotes/origin/*' '+refs/tags/*:refs/tags/*'",
+            url, commit
+        );
+        let _ = writeln!(
+            script,
+            "git -c advice.detachedHead=false checkout -q -f {}",
+            commit
+        );
+        let _ = writeln!(script, "cd && mkdir -p .tsunami");
+        let _ = writeln!(
+            script,
+            "{{ awk -F'\\t' -v d={0} '$1 != d' {1} 2>/dev/null || true; printf '%s\\t%s\\t%s\\n' {0} {2} {3}; }} > {1}.new",
+            dir, DEPLOYED_COMMITS, url, commit
+        );
+        let _ = writeln!(script, "mv -f {0}.new {0}", DEPLOYED_COMMITS);
+        script
+    }
+
+    // Checks out `commit` on `vm`, and builds it there.
+    async fn deploy_to(&self, vm: &Machine<'_>, commit: &str) -> Result<(), Report> {
+        if let Some(ref k) = self.deploy_key {
+            upload(vm, k, ".tsunami", REMOTE_DEPLOY_KEY, "600")
+                .await
+                .wrap_err("failed to upload deploy key")?;
+        }
+
+        let out = vm
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(self.checkout_script(commit))
+            .output()
+            .await
+            .wrap_err("failed to run git")?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to check out {}: {}",
+            commit,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+
+        if let Some(ref cmd) = self.build {
+            tracing::debug!(nickname = %vm.nickname, %cmd, "building checkout");
+            let out = vm
+                .ssh
+                .command("sh")
+                .arg("-c")
+                .arg(format!("cd {} && {}", quote(&self.dir), cmd))
+                .output()
+                .await
+                .wrap_err("failed to run build command")?;
+            eyre::ensure!(
+                out.status.success(),
+                "build failed: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    /// Check out the [resolved](Repository::resolve) commit on every machine in `machines`,
+    /// build it there if a [build command](Repository::build) is set, and return the commit hash.
+    ///
+    /// The machines need `git`. An existing checkout in the directory is reused, and forcibly
+    /// switched to the commit. The commit is recorded on each machine in [`DEPLOYED_COMMITS`].
+    #[tracing::instrument(level = "debug", skip(machines))]
+    pub async fn deploy(&self, machines: &HashMap<String, Machine<'_>>) -> Result<String, Report> {
+        let commit = self.resolve().await?;
+        tracing::info!(%commit, "deploying repository");
+        let commit = &commit;
+        futures_util::future::join_all(machines.iter().map(|(name, vm)| async move {
+            self.deploy_to(vm, commit)
+                .await
+                .wrap_err_with(|| format!("failed to deploy {} to {}", self.url, name))
+        }))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, Report>>()?;
+        Ok(commit.clone())
+    }
+}
+
+/// A file or directory that one machine of a group, the leader, produces, and that is then copied
+/// to the other machines of the group.
+///
+/// See the [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct Artifact {
+    path: String,
+    group: Option<Target>,
+    leader: Option<String>,
+}
+
+impl Artifact {
+    /// The file or directory at `path` on the machines, relative to the ssh user's home directory
+    /// unless it is absolute.
+    ///
+    /// By default, the group is all the machines, and the leader is the first of them by
+    /// nickname.
+    pub fn new(path: impl ToString) -> Self {
+        Artifact {
+            path: path.to_string(),
+            group: None,
+            leader: None,
+        }
+    }
+
+    /// Produce the artifact for, and copy it to, only the machines that match `target`.
+    pub fn group(mut self, target: Target) -> Self {
+        self.group = Some(target);
+        self
+    }
+
+    /// Produce the artifact on the machine with nickname `nickname`, which must be in the group.
+    pub fn leader(mut self, nickname: impl ToString) -> Self {
+        self.leader = Some(nickname.to_string());
+        self
+    }
+
+    // The directory that holds the artifact, and the artifact's name in it.
+    fn split_path(&self) -> (String, String) {
+        let path = Path::new(&self.path);
+        let dir = match path.parent().map(Path::to_string_lossy) {
+            Some(dir) if !dir.is_empty() => dir.into_owned(),
+            _ => String::from("."),
+        };
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.path.clone());
+        (dir, name)
+    }
+
+    // Packs the artifact into the tarball `archive`.
+    fn pack_script(&self, archive: &str) -> String {
+        let (dir, name) = self.split_path();
+        format!(
+            "mkdir -p .tsunami && tar -czf {} -C {} {}",
+            quote(archive),
+            quote(&dir),
+            quote(&name)
+        )
+    }
+
+    // Replaces the artifact with the contents of the tarball `archive`, and removes the tarball.
+    fn unpack_script(&self, archive: &str) -> String {
+        let (dir, name) = self.split_path();
+        format!(
+            "mkdir -p {0} && rm -rf {0}/{1} && tar -xzf {2} -C {0} && rm -f {2}",
+            quote(&dir),
+            quote(&name),
+            quote(archive)
+        )
+    }
+
+    // Copies the tarball at `local` to `archive` on `vm`, and unpacks it there.
+    async fn copy_to(&self, vm: &Machine<'_>, local: &Path, archive: &str) -> Result<(), Report> {
+        upload(vm, local, ".tsunami", archive, "600").await?;
+        let out = vm
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(self.unpack_script(archive))
+            .output()
+            .await
+            .wrap_err("failed to run tar")?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to unpack: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+        Ok(())
+    }
+
+    /// Run `produce` on the leader of the group in `machines`, and then copy the artifact it
+    /// produced to the other machines of the group. Returns the nickname of the leader.
+    ///
+    /// The artifact travels through the local machine, as a tarball, and replaces whatever is at
+    /// its path on the other machines. The copies are made concurrently. The machines need `tar`.
+    #[tracing::instrument(level = "debug", skip(machines, produce))]
+    pub async fn distribute<'m, 't, F, Fut>(
+        &self,
+        machines: &'m HashMap<String, Machine<'t>>,
+        produce: F,
+    ) -> Result<String, Report>
+    where
+        F: FnOnce(&'m Machine<'t>) -> Fut,
+        Fut: Future<Output = Result<(), Report>>,
+    {
+        let mut group: Vec<_> = machines
+            .iter()
+            .filter(|(name, _)| self.group.as_ref().map_or(true, |t| t.matches(name)))
+            .collect();
+        group.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let (leader_name, leader) = match self.leader {
+            Some(ref l) => group
+                .iter()
+                .find(|(name, _)| *name == l)
+                .copied()
+                .ok_or_else(|| eyre!("leader {} is not in the group", l))?,
+            None => group
+                .first()
+                .copied()
+                .ok_or_else(|| eyre!("no machines in the group"))?,
+        };
+
+        tracing::info!(leader = %leader_name, "producing artifact");
+        produce(leader)
+            .await
+            .wrap_err_with(|| format!("failed to produce {} on {}", self.path, leader_name))?;
+        if group.len() == 1 {
+            return Ok(leader_name.clone());
+        }
+
+        let archive = format!(".tsunami/artifact-{:016x}.tar.gz", rand::random::<u64>());
+        let out = leader
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(self.pack_script(&archive))
+            .output()
+            .await
+            .wrap_err("failed to run tar")?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to pack {} on {}: {}",
+            self.path,
+            leader_name,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+        let local = tempfile::NamedTempFile::new().wrap_err("failed to create local copy")?;
+        let fetched = download(leader, &archive, local.path())
+            .await
+            .wrap_err_with(|| format!("failed to fetch {} from {}", self.path, leader_name));
+        // the leader does not need the tarball either way
+        let _ = leader
+            .ssh
+            .command("rm")
+            .arg("-f")
+            .arg(&archive)
+            .status()
+            .await;
+        fetched?;
+
+        tracing::info!(leader = %leader_name, "copying artifact to group");
+        let (local, archive) = (local.path(), &archive);
+        futures_util::future::join_all(group.iter().filter(|(name, _)| *name != leader_name).map(
+            |(name, vm)| async move {
+                self.copy_to(vm, local, archive)
+                    .await
+                    .wrap_err_with(|| format!("failed to copy {} to {}", self.path, name))
+            },
+        ))
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, Report>>()?;
+        Ok(leader_name.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn arches() {
+        assert_eq!(Arch::from_uname("x86_64\n").unwrap(), Arch::X86_64);
+        assert_eq!(Arch::from_uname("aarch64\n").unwrap(), Arch::Aarch64);
+        assert!(Arch::from_uname("riscv64").is_err());
+    }
+
+    #[test]
+    fn build_args() {
+        let b = Binary::new("server").package("bench").features(["a", "b"]);
+        assert_eq!(
+            b.build_args(Arch::Aarch64),
+            [
+                "build",
+                "--message-format=json-render-diagnostics",
+                "--target=aarch64-unknown-linux-musl",
+                "--bin=server",
+                "--release",
+                "--package=bench",
+                "--features=a,b",
+            ]
+        );
+        assert!(!b
+            .debug()
+            .build_args(Arch::X86_64)
+            .contains(&String::from("--release")));
+        assert_eq!(
+            Binary::new("server").remote_dir("/opt/").remote_path(),
+            "/opt/server"
+        );
+    }
+
+    #[test]
+    fn artifacts() {
+        let messages = r#"{"reason":"compiler-artifact","target":{"kind":["lib"],"name":"bench"},"executable":null}
+{"reason":"compiler-artifact","target":{"kind":["bin"],"name":"client"},"executable":"/p/target/x86_64-unknown-linux-musl/release/client"}
+{"reason":"compiler-artifact","target":{"kind":["bin"],"name":"server"},"executable":"/p/target/x86_64-unknown-linux-musl/release/server"}
+{"reason":"build-finished","success":true}"#;
+        assert_eq!(
+            executable(messages, "server"),
+            Some(PathBuf::from(
+                "/p/target/x86_64-unknown-linux-musl/release/server"
+            ))
+        );
+        assert_eq!(executable(messages, "bench"), None);
+    }
+
+    #[test]
+    fn repositories() {
+        let r = Repository::new("git@github.com:me/bench.git", "main");
+        assert_eq!(r.dir, "bench");
+        assert_eq!(Repository::new("https://example.com/a/b/", "main").dir, "b");
+
+        let hash = "0123456789abcdef0123456789abcdef01234567";
+        let out = format!(
+            "{}\trefs/tags/v1\n{}\trefs/tags/v1^{{}}\n",
+            "f".repeat(40),
+            hash
+        );
+        assert_eq!(ls_remote_commit(&out).as_deref(), Some(hash));
+        let out = format!("{}\trefs/heads/main\n", hash.to_uppercase());
+        assert_eq!(ls_remote_commit(&out).as_deref(), Some(hash));
+        assert_eq!(ls_remote_commit("fatal: not a ref\n"), None);
+
+        let script = r.deploy_key("k").checkout_script(hash);
+        assert!(script.starts_with(
+            "set -e\nexport GIT_SSH_COMMAND='ssh -i \"$HOME\"/.tsunami/deploy-key -o"
+        ));
+        assert!(script.contains(&format!(
+            "git fetch -q 'git@github.com:me/bench.git' {} ||",
+            hash
+        )));
+        assert!(script.ends_with("> .tsunami/git.new\nmv -f .tsunami/git.new .tsunami/git\n"));
+    }
+
+    #[test]
+    fn artifact_scripts() {
+        let a = Artifact::new("data/input set");
+        assert_eq!(
+            a.pack_script(".tsunami/a.tar.gz"),
+            "mkdir -p .tsunami && tar -czf '.tsunami/a.tar.gz' -C 'data' 'input set'"
+        );
+        assert_eq!(
+            a.unpack_script(".tsunami/a.tar.gz"),
+            "mkdir -p 'data' && rm -rf 'data'/'input set' && tar -xzf '.tsunami/a.tar.gz' -C 'data' && rm -f '.tsunami/a.tar.gz'"
+        );
+        assert_eq!(
+            Artifact::new("bin").split_path(),
+            (".".into(), "bin".into())
+        );
+        assert_eq!(
+            Artifact::new("/opt/build/").split_path(),
+            ("/opt".into(), "build".into())
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Run a task on every machine, with bounded concurrency.
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! // at most 16 machines build at a time
+//! let outputs = tsunami::each::for_each(&vms, 16, |vm| async move {
+//!     let out = vm.ssh.command("cargo").arg("build").output().await?;
+//!     Ok(out.status.success())
+//! })
+//! .await?;
+//! assert!(outputs.values().all(|ok| *ok));
+//! # Ok(())
+//! # }
+//! ```
+
+use color_eyre::Report;
+use futures_util::stream::StreamExt;
+use std::collections::HashMap;
+use std::future::Future;
+
+/// The error returned by [`for_each`] when the task fails on some machines.
+///
+/// Every failure is kept, not just the first one.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct MachineErrors {
+    /// The nickname of each machine the task failed on, with its error.
+    pub errors: Vec<(String, Report)>,
+    /// How many machines the task ran on in total.
+    pub total: usize,
+}
+
+impl std::fmt::Display for MachineErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed on {} of {} machines",
+            self.errors.len(),
+            self.total
+        )?;
+        for (name, e) in &self.errors {
+            write!(f, "\n  {}: {}", name, e)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MachineErrors {}
+
+/// Run `f` on each of `machines`, with at most `limit` running at once.
+///
+/// `machines` is usually the map returned by [`connect_all`](crate::Tsunami::connect_all), but
+/// any map of nickname to machine works. If `f` fails on any machine, the others still run to
+/// completion, and the error is a [`MachineErrors`] listing every failure.
+pub async fn for_each<'m, M, T, F, Fut>(
+    machines: impl IntoIterator<Item = (&'m String, &'m M)>,
+    limit: usize,
+    f: F,
+) -> Result<HashMap<String, T>, Report>
+where
+    M: 'm,
+    F: Fn(&'m M) -> Fut,
+    Fut: Future<Output = Result<T, Report>>,
+{
+    let f = &f;
+    let results: Vec<_> = futures_util::stream::iter(machines)
+        .map(|(name, m)| async move { (name.clone(), f(m).await) })
+        .buffer_unordered(limit.max(1))
+        .collect()
+        .await;
+
+    let total = results.len();
+    let mut oks = HashMap::new();
+    let mut errors = Vec::new();
+    for (name, r) in results {
+        match r {
+            Ok(t) => {
+                oks.insert(name, t);
+            }
+            Err(e) => errors.push((name, e)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(oks)
+    } else {
+        errors.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Err(Report::new(MachineErrors { errors, total }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use color_eyre::eyre;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn limits_and_aggregates() {
+        let ms: HashMap<String, usize> = (0..10).map(|i| (format!("m{}", i), i)).collect();
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+
+        let res = for_each(&ms, 3, |&i| {
+            let (running, peak) = (&running, &peak);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::task::yield_now().await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                eyre::ensure!(i % 4 != 1, "bad machine");
+                Ok(i * 2)
+            }
+        })
+        .await;
+
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        let e = res.unwrap_err();
+        let e = e.downcast_ref::<MachineErrors>().unwrap();
+        assert_eq!(e.total, 10);
+        let failed: Vec<_> = e.errors.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(failed, vec!["m1", "m5", "m9"]);
+
+        let ok = for_each(&ms, 3, |&i| async move { Ok(i) }).await.unwrap();
+        assert_eq!(ok["m7"], 7);
+    }
+}
@@ -0,0 +1,164 @@
+//! An owned handle for driving a launcher step by step.
+//!
+//! The [`Tsunami`](crate::Tsunami) API hands out [`Machine`](crate::Machine)s that borrow the
+//! launcher they came from, which is awkward in a REPL such as
+//! [evcxr](https://github.com/google/evcxr), where every statement is its own scope. A [`Handle`]
+//! instead owns the launcher and the SSH sessions, and all its methods return owned values, so it
+//! can be kept in a variable and used across cells:
+//!
+//! ```rust,no_run
+//! # async fn f() -> Result<(), color_eyre::Report> {
+//! use tsunami::handle::Handle;
+//! use tsunami::providers::aws;
+//!
+//! let mut h = Handle::new(aws::Launcher::default());
+//! h.launch(vec![(String::from("server"), aws::Setup::default())]).await?;
+//! let out = h.run("server", "uname -a").await?;
+//! println!("{}", String::from_utf8_lossy(&out.stdout));
+//!
+//! // later...
+//! h.launch(tsunami::make_multiple(2, "client", aws::Setup::default())).await?;
+//! for m in h.machines() {
+//!     println!("{} {}", m.nickname, m.public_ip);
+//! }
+//! h.terminate().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::providers::{Launcher, MachineState};
+use color_eyre::{eyre, Report};
+use std::collections::HashMap;
+use std::process::Output;
+
+/// Everything about a launched machine except its SSH session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MachineInfo {
+    /// See [`Machine::nickname`](crate::Machine::nickname).
+    pub nickname: String,
+    /// See [`Machine::public_dns`](crate::Machine::public_dns).
+    pub public_dns: String,
+    /// See [`Machine::public_ip`](crate::Machine::public_ip).
+    pub public_ip: String,
+    /// See [`Machine::private_ip`](crate::Machine::private_ip).
+    pub private_ip: Option<String>,
+    /// See [`Machine::port`](crate::Machine::port).
+    pub port: u16,
+    /// See [`Machine::username`](crate::Machine::username).
+    pub username: String,
+    /// See [`Machine::private_key`](crate::Machine::private_key).
+    pub private_key: Option<std::path::PathBuf>,
+    /// See [`Machine::os`](crate::Machine::os).
+    pub os: Option<crate::OsFamily>,
+}
+
+/// An owned handle to a launcher and the machines it has launched.
+///
+/// See the [module documentation](self) for an example.
+#[derive(Debug)]
+pub struct Handle<L> {
+    launcher: L,
+    machines: HashMap<String, (MachineInfo, openssh::Session)>,
+}
+
+impl<L: Launcher> Handle<L> {
+    /// Take ownership of `launcher`.
+    pub fn new(launcher: L) -> Self {
+        Handle {
+            launcher,
+            machines: HashMap::new(),
+        }
+    }
+
+    /// Launch more machines, and connect to them.
+    ///
+    /// Machines launched by earlier calls stay up.
+    pub async fn launch<I>(&mut self, descriptors: I) -> Result<(), Report>
+    where
+        I: IntoIterator<Item = (String, L::MachineDescriptor)> + Send + std::fmt::Debug + 'static,
+        I::IntoIter: Send,
+    {
+        self.launcher.spawn(descriptors, None).await?;
+        self.reconnect().await
+    }
+
+    /// Re-establish the SSH sessions to all machines.
+    pub async fn reconnect(&mut self) -> Result<(), Report> {
+        self.machines = self
+            .launcher
+            .connect_all()
+            .await?
+            .into_iter()
+            .map(|(name, m)| {
+                let info = MachineInfo {
+                    nickname: m.nickname,
+                    public_dns: m.public_dns,
+                    public_ip: m.public_ip,
+                    private_ip: m.private_ip,
+                    port: m.port,
+                    username: m.username,
+                    private_key: m.private_key,
+                    os: m.os,
+                };
+                (name, (info, m.ssh))
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// The machines launched so far, sorted by nickname.
+    pub fn machines(&self) -> Vec<MachineInfo> {
+        let mut ms: Vec<_> = self.machines.values().map(|(i, _)| i.clone()).collect();
+        ms.sort_by(|a, b| a.nickname.cmp(&b.nickname));
+        ms
+    }
+
+    /// The SSH session to `nickname`, for anything [`Handle::run`] cannot do.
+    pub fn ssh(&self, nickname: &str) -> Option<&openssh::Session> {
+        self.machines.get(nickname).map(|(_, s)| s)
+    }
+
+    /// Run `cmd` with `sh -c` on `nickname`, and collect its output.
+    pub async fn run(&self, nickname: &str, cmd: &str) -> Result<Output, Report> {
+        let ssh = self
+            .ssh(nickname)
+            .ok_or_else(|| eyre::eyre!("no machine named {}", nickname))?;
+        Ok(ssh.shell(cmd).output().await?)
+    }
+
+    /// Run `cmd` with `sh -c` on every machine concurrently, and collect the outputs.
+    pub async fn run_all(&self, cmd: &str) -> Result<HashMap<String, Output>, Report> {
+        let outs = futures_util::future::join_all(self.machines.iter().map(
+            |(name, (_, ssh))| async move {
+                Ok::<_, Report>((name.clone(), ssh.shell(cmd).output().await?))
+            },
+        ))
+        .await;
+        outs.into_iter().collect()
+    }
+
+    /// Run `cmd` with `sh -c` on the machines that match `target`.
+    ///
+    /// See [`run::run_on`](crate::run::run_on).
+    pub async fn run_on(
+        &self,
+        target: &crate::run::Target,
+        cmd: &str,
+    ) -> Result<HashMap<String, crate::run::CommandOutput>, Report> {
+        crate::run::run_sessions(self.machines.iter().map(|(n, (_, s))| (n, s)), target, cmd).await
+    }
+
+    /// See [`Launcher::status`].
+    pub async fn status(&self) -> Result<HashMap<String, MachineState>, Report> {
+        self.launcher.status().await
+    }
+
+    /// Close the SSH sessions and shut down all machines.
+    pub async fn terminate(self) -> Result<(), Report> {
+        for (_, (_, ssh)) in self.machines {
+            let _ = ssh.close().await;
+        }
+        self.launcher.terminate_all().await
+    }
+}
@@ -0,0 +1,112 @@
+//! Machine images that are described independently of any provider.
+//!
+//! Each provider names images differently: AWS by region-specific AMI ids, Azure by
+//! `publisher:offer:sku:version` URNs, and Docker by image tags. An [`ImageSpec`] is resolved by
+//! the provider at launch time, so one experiment configuration can target several providers:
+//!
+//! ```rust
+//! use tsunami::image::ImageSpec;
+//! use tsunami::providers::{aws, azure, docker};
+//!
+//! let image: ImageSpec = "ubuntu-22.04".parse().unwrap();
+//! let on_aws = aws::Setup::default().image_spec(image.clone());
+//! let on_azure = azure::Setup::default().image_spec(image.clone());
+//! let locally = docker::Setup::default().image_spec(image);
+//! ```
+
+use crate::OsFamily;
+use color_eyre::Report;
+
+/// A machine image, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ImageSpec {
+    /// The latest Ubuntu 20.04 LTS image.
+    Ubuntu2004,
+    /// The latest Ubuntu 22.04 LTS image.
+    Ubuntu2204,
+    /// The latest Debian 11 image.
+    Debian11,
+    /// An image you built yourself, found by name.
+    ///
+    /// On AWS, this is the name of an AMI owned by your account, and may contain `*` wildcards;
+    /// the newest match is used. On Azure, it is the name of a managed image in the subscription.
+    /// On Docker, it is the base image.
+    CustomByName(String),
+    /// An image in the provider's own terms, used as is.
+    ///
+    /// This is an AMI id on AWS, an image URN or resource id on Azure, and the base image on
+    /// Docker.
+    ProviderSpecific(String),
+}
+
+impl ImageSpec {
+    /// The operating system family of the image, if it is known without looking at the image.
+    pub fn os(&self) -> Option<OsFamily> {
+        match self {
+            ImageSpec::Ubuntu2004 | ImageSpec::Ubuntu2204 => Some(OsFamily::Ubuntu),
+            ImageSpec::Debian11 => Some(OsFamily::Debian),
+            ImageSpec::CustomByName(_) | ImageSpec::ProviderSpecific(_) => None,
+        }
+    }
+}
+
+/// Displays as `ubuntu-20.04`, `ubuntu-22.04`, `debian-11`, `name:<name>`, or `id:<id>`.
+impl std::fmt::Display for ImageSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageSpec::Ubuntu2004 => f.write_str("ubuntu-20.04"),
+            ImageSpec::Ubuntu2204 => f.write_str("ubuntu-22.04"),
+            ImageSpec::Debian11 => f.write_str("debian-11"),
+            ImageSpec::CustomByName(n) => write!(f, "name:{}", n),
+            ImageSpec::ProviderSpecific(id) => write!(f, "id:{}", id),
+        }
+    }
+}
+
+/// Parses the `Display` form. Anything else is taken to be [`ImageSpec::ProviderSpecific`].
+impl std::str::FromStr for ImageSpec {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        color_eyre::eyre::ensure!(!s.is_empty(), "empty image");
+        Ok(match s {
+            "ubuntu-20.04" => ImageSpec::Ubuntu2004,
+            "ubuntu-22.04" => ImageSpec::Ubuntu2204,
+            "debian-11" => ImageSpec::Debian11,
+            _ => {
+                if let Some(n) = s.strip_prefix("name:") {
+                    ImageSpec::CustomByName(n.to_string())
+                } else {
+                    ImageSpec::ProviderSpecific(s.strip_prefix("id:").unwrap_or(s).to_string())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        for spec in [
+            ImageSpec::Ubuntu2004,
+            ImageSpec::Ubuntu2204,
+            ImageSpec::Debian11,
+            ImageSpec::CustomByName(String::from("server-*")),
+            ImageSpec::ProviderSpecific(String::from("ami-0123")),
+        ] {
+            assert_eq!(spec.to_string().parse::<ImageSpec>().unwrap(), spec);
+        }
+        assert_eq!(
+            "Canonical:UbuntuServer:18.04-LTS:latest"
+                .parse::<ImageSpec>()
+                .unwrap(),
+            ImageSpec::ProviderSpecific(String::from("Canonical:UbuntuServer:18.04-LTS:latest"))
+        );
+        assert_eq!(ImageSpec::Debian11.os(), Some(OsFamily::Debian));
+        assert!("".parse::<ImageSpec>().is_err());
+    }
+}
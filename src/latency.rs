@@ -0,0 +1,268 @@
+//! Expected round-trip times between cloud regions.
+//!
+//! Tsunami ships a small dataset of approximate median RTTs between AWS regions, which is what
+//! [`expected_rtt`] and the default [`placement::Planner`](crate::placement::Planner) use. The
+//! numbers drift as providers change their backbones, so an [`RttMatrix`] can also be built from
+//! your own measurements (see [`RttMatrix::measure`]), saved with [`RttMatrix::to_csv`], and loaded
+//! again later with [`RttMatrix::parse`].
+//!
+//! ```rust
+//! use std::time::Duration;
+//! let rtt = tsunami::latency::expected_rtt("us-east-1", "eu-west-1").unwrap();
+//! assert!(rtt > Duration::from_millis(50));
+//! ```
+
+use color_eyre::{
+    eyre::{self, WrapErr},
+    Report,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+// Median RTTs in milliseconds, as `region,region,ms` lines. Only one direction is listed.
+const BUILTIN: &str = "\
+us-east-1,us-east-2,12
+us-east-1,us-west-1,62
+us-east-1,us-west-2,67
+us-east-1,ca-central-1,15
+us-east-1,eu-west-1,68
+us-east-1,eu-west-2,76
+us-east-1,eu-central-1,90
+us-east-1,ap-south-1,187
+us-east-1,ap-northeast-1,145
+us-east-1,ap-southeast-1,215
+us-east-1,ap-southeast-2,198
+us-east-1,sa-east-1,115
+us-east-2,us-west-1,50
+us-east-2,us-west-2,50
+us-east-2,ca-central-1,25
+us-east-2,eu-west-1,80
+us-east-2,eu-west-2,87
+us-east-2,eu-central-1,98
+us-east-2,ap-south-1,200
+us-east-2,ap-northeast-1,132
+us-east-2,ap-southeast-1,195
+us-east-2,ap-southeast-2,187
+us-east-2,sa-east-1,125
+us-west-1,us-west-2,22
+us-west-1,ca-central-1,78
+us-west-1,eu-west-1,130
+us-west-1,eu-west-2,140
+us-west-1,eu-central-1,148
+us-west-1,ap-south-1,230
+us-west-1,ap-northeast-1,105
+us-west-1,ap-southeast-1,170
+us-west-1,ap-southeast-2,140
+us-west-1,sa-east-1,175
+us-west-2,ca-central-1,60
+us-west-2,eu-west-1,120
+us-west-2,eu-west-2,130
+us-west-2,eu-central-1,140
+us-west-2,ap-south-1,220
+us-west-2,ap-northeast-1,100
+us-west-2,ap-southeast-1,165
+us-west-2,ap-southeast-2,140
+us-west-2,sa-east-1,180
+ca-central-1,eu-west-1,70
+ca-central-1,eu-west-2,80
+ca-central-1,eu-central-1,92
+ca-central-1,ap-south-1,195
+ca-central-1,ap-northeast-1,145
+ca-central-1,ap-southeast-1,215
+ca-central-1,ap-southeast-2,200
+ca-central-1,sa-east-1,125
+eu-west-1,eu-west-2,12
+eu-west-1,eu-central-1,25
+eu-west-1,ap-south-1,122
+eu-west-1,ap-northeast-1,210
+eu-west-1,ap-southeast-1,175
+eu-west-1,ap-southeast-2,255
+eu-west-1,sa-east-1,180
+eu-west-2,eu-central-1,15
+eu-west-2,ap-south-1,112
+eu-west-2,ap-northeast-1,215
+eu-west-2,ap-southeast-1,165
+eu-west-2,ap-southeast-2,265
+eu-west-2,sa-east-1,190
+eu-central-1,ap-south-1,110
+eu-central-1,ap-northeast-1,225
+eu-central-1,ap-southeast-1,160
+eu-central-1,ap-southeast-2,250
+eu-central-1,sa-east-1,200
+ap-south-1,ap-northeast-1,130
+ap-south-1,ap-southeast-1,60
+ap-south-1,ap-southeast-2,150
+ap-south-1,sa-east-1,300
+ap-northeast-1,ap-southeast-1,70
+ap-northeast-1,ap-southeast-2,105
+ap-northeast-1,sa-east-1,255
+ap-southeast-1,ap-southeast-2,92
+ap-southeast-1,sa-east-1,325
+ap-southeast-2,sa-east-1,310
+";
+
+// The RTT assumed between two machines in the same region.
+const INTRA_REGION: Duration = Duration::from_millis(1);
+
+/// A symmetric table of round-trip times between regions.
+#[derive(Debug, Clone, Default)]
+pub struct RttMatrix {
+    rtts: HashMap<(String, String), Duration>,
+}
+
+impl RttMatrix {
+    /// The dataset that ships with tsunami.
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN).expect("built-in RTT dataset is well-formed")
+    }
+
+    /// Parse a dataset of `region,region,milliseconds` lines.
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn parse(s: &str) -> Result<Self, Report> {
+        let mut m = Self::default();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split(',').map(str::trim);
+            match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                (Some(a), Some(b), Some(ms), None) => {
+                    let rtt = ms
+                        .parse()
+                        .wrap_err_with(|| format!("bad RTT in line {:?}", line))?;
+                    let rtt = from_millis(rtt)
+                        .ok_or_else(|| eyre::eyre!("bad RTT in line {:?}", line))?;
+                    m.insert(a, b, rtt);
+                }
+                _ => eyre::bail!("malformed RTT line {:?}", line),
+            }
+        }
+        Ok(m)
+    }
+
+    /// Serialize this matrix in the format [`RttMatrix::parse`] accepts.
+    pub fn to_csv(&self) -> String {
+        let mut lines: Vec<_> = self
+            .rtts
+            .iter()
+            .filter(|((a, b), _)| a < b)
+            .map(|((a, b), rtt)| format!("{},{},{}", a, b, rtt.as_secs_f64() * 1000.0))
+            .collect();
+        lines.sort();
+        lines.join("\n")
+    }
+
+    /// Record the RTT between `a` and `b`, replacing any earlier value.
+    pub fn insert(&mut self, a: impl ToString, b: impl ToString, rtt: Duration) {
+        let (a, b) = (a.to_string(), b.to_string());
+        self.rtts.insert((a.clone(), b.clone()), rtt);
+        self.rtts.insert((b, a), rtt);
+    }
+
+    /// Look up the RTT between `a` and `b`.
+    pub fn get(&self, a: &str, b: &str) -> Option<Duration> {
+        if a == b {
+            return Some(INTRA_REGION);
+        }
+        self.rtts.get(&(a.to_string(), b.to_string())).copied()
+    }
+
+    /// Measure the RTTs between all pairs of `machines`, each given with the region it is in.
+    ///
+    /// Each pair is measured once, by pinging from the first machine to the second.
+    pub async fn measure(machines: &[(&str, &crate::Machine<'_>)]) -> Result<Self, Report> {
+        let mut m = Self::default();
+        for (i, (ra, a)) in machines.iter().enumerate() {
+            for (rb, b) in &machines[i + 1..] {
+                let rtt = ping(a, &b.public_ip)
+                    .await
+                    .wrap_err_with(|| format!("failed to measure RTT {} -> {}", ra, rb))?;
+                m.insert(ra, rb, rtt);
+            }
+        }
+        Ok(m)
+    }
+}
+
+/// The expected RTT between regions `a` and `b` according to the built-in dataset.
+///
+/// Returns `None` if either region is not in the dataset.
+pub fn expected_rtt(a: &str, b: &str) -> Option<Duration> {
+    thread_local! {
+        static BUILTIN_MATRIX: RttMatrix = RttMatrix::builtin();
+    }
+    BUILTIN_MATRIX.with(|m| m.get(a, b))
+}
+
+async fn ping(from: &crate::Machine<'_>, to: &str) -> Result<Duration, Report> {
+    let out = from
+        .ssh
+        .command("ping")
+        .arg("-c")
+        .arg("5")
+        .arg(to)
+        .output()
+        .await?;
+    eyre::ensure!(out.status.success(), "ping failed");
+    parse_ping(&String::from_utf8_lossy(&out.stdout))
+        .ok_or_else(|| eyre::eyre!("could not parse ping output"))
+}
+
+// parses the average out of e.g. `rtt min/avg/max/mdev = 67.1/67.4/68.0/0.3 ms`
+fn parse_ping(out: &str) -> Option<Duration> {
+    let summary = out.lines().find(|l| l.contains("min/avg/max"))?;
+    let avg: f64 = summary
+        .split('=')
+        .nth(1)?
+        .trim()
+        .split('/')
+        .nth(1)?
+        .parse()
+        .ok()?;
+    from_millis(avg)
+}
+
+// `None` for values that are not a valid duration, such as negative or NaN ones.
+fn from_millis(ms: f64) -> Option<Duration> {
+    let secs = ms / 1000.0;
+    if secs.is_finite() && secs >= 0.0 && secs < u64::MAX as f64 {
+        Some(Duration::from_secs_f64(secs))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builtin_is_symmetric() {
+        assert_eq!(
+            expected_rtt("eu-west-1", "us-east-1"),
+            expected_rtt("us-east-1", "eu-west-1")
+        );
+        assert_eq!(expected_rtt("us-east-1", "us-east-1"), Some(INTRA_REGION));
+        assert_eq!(expected_rtt("us-east-1", "mars-north-1"), None);
+    }
+
+    #[test]
+    fn csv_roundtrip() {
+        let m = RttMatrix::builtin();
+        let m2 = RttMatrix::parse(&m.to_csv()).unwrap();
+        assert_eq!(m.rtts, m2.rtts);
+        assert!(RttMatrix::parse("a,b").is_err());
+        assert!(RttMatrix::parse("a,b,-1").is_err());
+        assert!(RttMatrix::parse("a,b,NaN").is_err());
+        assert!(RttMatrix::parse("a,b,inf").is_err());
+    }
+
+    #[test]
+    fn ping_summary() {
+        let out = "5 packets transmitted, 5 received, 0% packet loss, time 4005ms\n\
+                   rtt min/avg/max/mdev = 67.101/67.400/68.012/0.311 ms\n";
+        assert_eq!(parse_ping(out), Some(Duration::from_micros(67400)));
+    }
+}
@@ -175,20 +175,159 @@
 )]
 #![allow(clippy::type_complexity)]
 
-use color_eyre::Report;
-use std::collections::HashMap;
+use color_eyre::{
+    eyre::{self, WrapErr},
+    Report,
+};
+use std::collections::{BTreeMap, HashMap};
 use std::future::Future;
 use std::pin::Pin;
 use tracing::instrument;
 
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+mod bundle;
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+pub mod checksum;
+#[cfg(feature = "deploy")]
+pub mod deploy;
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "deploy",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+pub mod each;
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+pub mod handle;
+pub mod image;
+pub mod latency;
+#[cfg(feature = "logs")]
+pub mod logs;
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "deploy",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+pub mod mpi;
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+pub mod observer;
+mod os;
+pub mod placement;
+pub mod provenance;
 pub mod providers;
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "deploy",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+pub mod queue;
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "deploy",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+pub mod run;
+pub mod runs;
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "deploy",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+pub mod spark;
+pub mod steps;
+pub mod storage;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod verify;
 
-#[derive(Debug)]
+pub use os::OsFamily;
+
+#[derive(Debug, Clone)]
 struct MachineDescriptor<'tsunami> {
     pub(crate) nickname: String,
     pub(crate) public_dns: Option<String>,
     pub(crate) public_ip: String,
     pub(crate) private_ip: Option<String>,
+    pub(crate) os: Option<OsFamily>,
+    // an ssh `ProxyCommand` that reaches the machine when its address cannot be reached directly.
+    pub(crate) proxy_command: Option<String>,
+    // whether to check the host key against `~/.ssh/known_hosts`, which does not work for
+    // machines whose address is reused with a new key from run to run.
+    pub(crate) check_host_key: bool,
 
     // tie the lifetime of the machine to the Tsunami.
     _tsunami: std::marker::PhantomData<&'tsunami ()>,
@@ -205,8 +344,10 @@ pub struct Machine<'tsunami> {
     pub nickname: String,
     /// The public DNS name of the machine.
     ///
-    /// If the instance doesn't have a DNS name, this field will be
-    /// equivalent to `public_ip`.
+    /// On AWS and Azure this is the name the provider assigns to the instance. On baremetal it is
+    /// the name given with `baremetal::Setup::public_dns`, or else the result of a reverse lookup
+    /// of the machine's address. If the instance doesn't have a DNS name, this field will be
+    /// equivalent to `public_ip`; use [`Machine::has_dns`] to tell the two apart.
     pub public_dns: String,
     /// The public IP address of the machine.
     pub public_ip: String,
@@ -216,17 +357,301 @@ pub struct Machine<'tsunami> {
     /// An established SSH session to this host.
     pub ssh: openssh::Session,
 
+    /// The port the host's SSH server listens on.
+    pub port: u16,
     /// Username that can be used to SSH into the host.
     pub username: String,
     /// Private key that can be used to SSH into the host.
     pub private_key: Option<std::path::PathBuf>,
 
+    /// The operating system family of the machine's image, if known.
+    ///
+    /// Use [`OsFamily::detect`] to find out if this is `None`.
+    pub os: Option<OsFamily>,
+
+    /// The ssh `ProxyCommand` that the connection goes through, if the machine is not reached
+    /// directly, such as over AWS Session Manager. [`Machine::ssh_options`] includes it.
+    pub proxy_command: Option<String>,
+
+    /// Whether the machine's host key is checked against `~/.ssh/known_hosts`.
+    ///
+    /// This is `false` for machines whose address is reused with a new host key from run to run,
+    /// such as local Vagrant machines. [`Machine::ssh_options`] follows it.
+    pub check_host_key: bool,
+
     // tie the lifetime of the machine to the Tsunami.
     _tsunami: std::marker::PhantomData<&'tsunami ()>,
 }
 
+impl Machine<'_> {
+    /// Whether [`Machine::public_dns`] is a real DNS name rather than a copy of the public IP.
+    pub fn has_dns(&self) -> bool {
+        self.public_dns != self.public_ip
+    }
+
+    /// The options a local `ssh`, `scp`, or `sftp` needs to log in the same way [`Machine::ssh`]
+    /// does.
+    ///
+    /// These are all `-o` options, so they can also be handed to tools that run `ssh` themselves,
+    /// such as `rsync -e` or Ansible's `ansible_ssh_common_args`:
+    ///
+    /// ```rust,no_run
+    /// # fn f(vm: &tsunami::Machine<'_>) -> Result<(), color_eyre::Report> {
+    /// let opts: Vec<_> = vm.ssh_options().iter().map(|o| o.to_string_lossy().into_owned()).collect();
+    /// std::process::Command::new("rsync")
+    ///     .arg("-a")
+    ///     .arg("-e")
+    ///     .arg(format!("ssh {}", opts.join(" ")))
+    ///     .arg("data/")
+    ///     .arg(format!("{}@{}:data/", vm.username, vm.public_ip))
+    ///     .status()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn ssh_options(&self) -> Vec<std::ffi::OsString> {
+        let mut opts: Vec<std::ffi::OsString> =
+            vec!["-o".into(), format!("Port={}", self.port).into()];
+        if self.check_host_key {
+            opts.extend(vec!["-o".into(), "StrictHostKeyChecking=accept-new".into()]);
+        } else {
+            opts.extend(vec![
+                "-o".into(),
+                "StrictHostKeyChecking=no".into(),
+                "-o".into(),
+                "UserKnownHostsFile=/dev/null".into(),
+            ]);
+        }
+        if let Some(ref k) = self.private_key {
+            let mut o = std::ffi::OsString::from("IdentityFile=");
+            o.push(k);
+            opts.push("-o".into());
+            opts.push(o);
+        }
+        if let Some(ref p) = self.proxy_command {
+            opts.push("-o".into());
+            opts.push(format!("ProxyCommand={}", p).into());
+        }
+        opts
+    }
+
+    /// The contents of [`Machine::private_key`], for tools that want the key itself rather than
+    /// a path to it.
+    ///
+    /// Returns `None` if the machine is reached with the local ssh configuration's default key.
+    pub fn private_key_bytes(&self) -> Result<Option<Vec<u8>>, Report> {
+        self.private_key
+            .as_ref()
+            .map(|k| std::fs::read(k).wrap_err_with(|| format!("failed to read {}", k.display())))
+            .transpose()
+    }
+
+    /// Add [`Machine::private_key`] to the local `ssh-agent`, so that any tool that uses the
+    /// agent can reach this machine.
+    ///
+    /// If `lifetime` is given, the agent forgets the key after that long. This runs `ssh-add`, and
+    /// does nothing if the machine uses the default key.
+    pub fn add_to_agent(&self, lifetime: Option<std::time::Duration>) -> Result<(), Report> {
+        let key = match self.private_key {
+            Some(ref k) => k,
+            None => return Ok(()),
+        };
+        let mut cmd = std::process::Command::new("ssh-add");
+        if let Some(t) = lifetime {
+            cmd.arg("-t").arg(t.as_secs().max(1).to_string());
+        }
+        let out = cmd.arg(key).output().wrap_err("failed to run ssh-add")?;
+        eyre::ensure!(
+            out.status.success(),
+            "ssh-add failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+        tracing::debug!(nickname = %self.nickname, key = %key.display(), "added key to ssh-agent");
+        Ok(())
+    }
+
+    fn ssh_destination(&self) -> String {
+        format!("{}@{}", self.username, self.public_ip)
+    }
+
+    /// A local `ssh` command that logs into this machine the same way [`Machine::ssh`] does.
+    ///
+    /// Add arguments to run a remote command, or use it as is for a login shell.
+    pub fn ssh_command(&self) -> std::process::Command {
+        let mut cmd = std::process::Command::new("ssh");
+        cmd.args(self.ssh_options()).arg(self.ssh_destination());
+        cmd
+    }
+
+    /// Open an interactive shell on this machine in the local terminal, and wait for it to exit.
+    ///
+    /// This is an escape hatch for debugging: the shell gets a pseudo-terminal, and the local
+    /// terminal is handed over to it until the user logs out, after which the program carries on
+    /// where it left off. [`Machine::ssh`] is unaffected. The local `ssh` binary must be in your
+    /// `$PATH`, and standard input must be a terminal.
+    ///
+    /// This blocks the calling thread, and so any other tasks on it, until the shell exits.
+    ///
+    /// ```rust,no_run
+    /// # fn f(vm: &tsunami::Machine<'_>) -> Result<(), color_eyre::Report> {
+    /// println!("benchmark failed; dropping into {}", vm.nickname);
+    /// vm.interactive_shell()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn interactive_shell(&self) -> Result<std::process::ExitStatus, Report> {
+        use std::io::IsTerminal;
+        eyre::ensure!(
+            std::io::stdin().is_terminal(),
+            "an interactive shell needs standard input to be a terminal"
+        );
+        tracing::info!(nickname = %self.nickname, "starting interactive shell");
+        let status = std::process::Command::new("ssh")
+            .args(self.ssh_options())
+            .arg("-t")
+            .arg(self.ssh_destination())
+            .status()
+            .wrap_err("failed to run ssh")?;
+        tracing::info!(nickname = %self.nickname, %status, "interactive shell exited");
+        Ok(status)
+    }
+}
+
+/// A machine of a tsunami that is reachable, but that has no SSH session open yet.
+///
+/// The session is established the first time [`LazyMachine::connect`] is called, and kept until
+/// [`LazyMachine::disconnect`]. This lets a controller hold on to a large fleet without keeping an
+/// idle connection to every machine in it.
+///
+/// ```rust,no_run
+/// # async fn f(vm: tsunami::LazyMachine<'_>) -> Result<(), color_eyre::Report> {
+/// vm.connect().await?.ssh.command("uptime").status().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+#[non_exhaustive]
+#[derive(Debug)]
+pub struct LazyMachine<'tsunami> {
+    /// The friendly name for this machine.
+    pub nickname: String,
+    /// The public DNS name of the machine, or its public IP if it has none.
+    pub public_dns: String,
+    /// The public IP address of the machine.
+    pub public_ip: String,
+    /// The private IP address of the machine, if available.
+    pub private_ip: Option<String>,
+    /// The port the host's SSH server listens on.
+    pub port: u16,
+    /// Username that can be used to SSH into the host.
+    pub username: String,
+    /// Private key that can be used to SSH into the host.
+    pub private_key: Option<std::path::PathBuf>,
+
+    descriptor: MachineDescriptor<'tsunami>,
+    machine: tokio::sync::OnceCell<Machine<'tsunami>>,
+}
+
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+impl<'t> LazyMachine<'t> {
+    /// The connected machine, establishing its SSH session first if there is none yet.
+    ///
+    /// Concurrent callers share a single connection attempt. If it fails, the next call tries
+    /// again.
+    pub async fn connect(&self) -> Result<&Machine<'t>, Report> {
+        self.machine
+            .get_or_try_init(|| {
+                self.descriptor.clone().connect_ssh(
+                    &self.username,
+                    self.private_key.as_deref(),
+                    None,
+                    self.port,
+                )
+            })
+            .await
+            .wrap_err_with(|| format!("failed to connect to {}", self.nickname))
+    }
+
+    /// Whether the SSH session to this machine is currently open.
+    pub fn is_connected(&self) -> bool {
+        self.machine.initialized()
+    }
+
+    /// Close the SSH session to this machine, if one is open.
+    ///
+    /// The next call to [`LazyMachine::connect`] opens a new one.
+    pub fn disconnect(&mut self) {
+        if let Some(m) = self.machine.take() {
+            tracing::trace!(nickname = %m.nickname, "disconnecting");
+        }
+    }
+}
+
 impl<'t> MachineDescriptor<'t> {
-    #[cfg(any(feature = "aws", feature = "azure", feature = "baremetal"))]
+    // A machine that connects to this one with the given credentials when it is first used.
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn lazy(
+        self,
+        username: &str,
+        key_path: Option<&std::path::Path>,
+        port: u16,
+    ) -> LazyMachine<'t> {
+        LazyMachine {
+            nickname: self.nickname.clone(),
+            public_dns: self
+                .public_dns
+                .clone()
+                .unwrap_or_else(|| self.public_ip.clone()),
+            public_ip: self.public_ip.clone(),
+            private_ip: self.private_ip.clone(),
+            port,
+            username: username.to_string(),
+            private_key: key_path.map(|path| path.to_path_buf()),
+            descriptor: self,
+            machine: tokio::sync::OnceCell::new(),
+        }
+    }
+
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
     #[instrument(level = "debug", skip(key_path, timeout))]
     async fn connect_ssh(
         self,
@@ -247,8 +672,36 @@ impl<'t> MachineDescriptor<'t> {
             sess.connect_timeout(t);
         }
 
+        // the ssh master process only reads its configuration when it starts.
+        let mut options = String::new();
+        if let Some(ref p) = self.proxy_command {
+            options.push_str(&format!("  ProxyCommand {}\n", p));
+        }
+        if !self.check_host_key {
+            options.push_str("  StrictHostKeyChecking no\n  UserKnownHostsFile /dev/null\n");
+        }
+        let config = if options.is_empty() {
+            None
+        } else {
+            use rand::Rng;
+            let suffix: String = rand::thread_rng()
+                .sample_iter(&rand::distributions::Alphanumeric)
+                .take(10)
+                .map(char::from)
+                .collect();
+            let config = std::env::temp_dir().join(format!("tsunami-ssh-config-{}", suffix));
+            std::fs::write(&config, format!("Host *\n{}", options))
+                .wrap_err("failed to write ssh configuration")?;
+            sess.config_file(&config);
+            Some(config)
+        };
+
         tracing::trace!("connecting");
-        let sess = sess.connect(&self.public_ip).await?;
+        let sess = sess.connect(&self.public_ip).await;
+        if let Some(config) = config {
+            let _ = std::fs::remove_file(config);
+        }
+        let sess = sess?;
         tracing::trace!("connected");
 
         let public_ip = self.public_ip;
@@ -258,8 +711,12 @@ impl<'t> MachineDescriptor<'t> {
             public_dns: self.public_dns.unwrap_or_else(|| public_ip.clone()),
             public_ip,
             private_ip: self.private_ip,
+            os: self.os,
+            proxy_command: self.proxy_command,
+            check_host_key: self.check_host_key,
             _tsunami: self._tsunami,
             ssh: sess,
+            port,
             username: username.to_string(),
             private_key: key_path.map(|path| path.to_path_buf()),
         })
@@ -317,14 +774,262 @@ pub trait Tsunami: sealed::Sealed {
         I::IntoIter: Send;
 
     /// Return connections to the [`Machine`s](crate::Machine) that `spawn` spawned.
+    ///
+    /// The machines are keyed by nickname, and the order in which the map iterates over them is
+    /// arbitrary: it changes from one run to the next, even with the same nicknames. Do not hand
+    /// out work in that order if the experiment is to be reproducible; use
+    /// [`connect_all_sorted`](Tsunami::connect_all_sorted) instead.
     fn connect_all<'l>(
         &'l self,
     ) -> Pin<
         Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
     >;
 
+    /// Describe how to reach the machines that `spawn` spawned, without connecting to them yet.
+    ///
+    /// Each [`LazyMachine`] opens its SSH session the first time it is used. Prefer this over
+    /// [`connect_all`](Tsunami::connect_all) for fleets too large to keep a connection open to
+    /// every machine at once, and where only the machines' addresses are needed.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+    /// use tsunami::Tsunami;
+    /// let machines = aws.describe_all()?;
+    /// tsunami::each::for_each(&machines, 16, |vm| async move {
+    ///     vm.connect().await?.ssh.command("uptime").status().await?;
+    ///     Ok(())
+    /// })
+    /// .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, LazyMachine<'l>>, Report>;
+
+    /// Like [`connect_all`](Tsunami::connect_all), but return the machines ordered by nickname.
+    ///
+    /// Iterating over the map always yields the machines in the same order, that of their
+    /// nicknames compared as strings, so assigning e.g. workload shards in that order gives each
+    /// machine the same shard in every run. Note that with string order, `vm-10` comes before
+    /// `vm-2`. The helpers in this crate that pick one machine out of many, like the leader of an
+    /// [MPI cluster](crate::mpi::Cluster::bootstrap), use the same order. To walk the map that
+    /// `connect_all` returns in this order, use [`by_nickname`].
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+    /// use tsunami::Tsunami;
+    /// let vms = aws.connect_all_sorted().await?;
+    /// for (shard, name) in vms.keys().enumerate() {
+    ///     println!("{} runs shard {}", name, shard);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn connect_all_sorted<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<BTreeMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        let machines = self.connect_all();
+        Box::pin(async move { Ok(machines.await?.into_iter().collect()) })
+    }
+
     /// Shut down all instances.
     fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>>;
+
+    /// Report the current state of every machine that `spawn` was asked to spawn.
+    ///
+    /// The state is refreshed from the provider on every call, so this is suitable for polling.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+    /// use tsunami::Tsunami;
+    /// for (name, state) in aws.status().await? {
+    ///     println!("{}: {}", name, state);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<HashMap<String, providers::MachineState>, Report>>
+                + Send
+                + 'l,
+        >,
+    >;
+
+    /// Run the setup functions of the machines in `nicknames` again, on the running machines.
+    ///
+    /// This shortens the edit-test loop of a setup recipe: after changing what the setup function
+    /// uploads or runs, re-apply it without relaunching the machines. The setup function runs on
+    /// the machine as the previous run left it, so it should be safe to run more than once.
+    /// Machines that were spawned without a setup function are left alone.
+    ///
+    /// The machines are set up concurrently. If any of them fails, the first error is returned
+    /// once all of them are done.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+    /// use tsunami::Tsunami;
+    /// aws.rerun_setup(vec!["server", "client-0"]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn rerun_setup<'l>(
+        &'l self,
+        nicknames: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>>;
+
+    /// Like [`rerun_setup`](Tsunami::rerun_setup), but first return the machines to the clean
+    /// snapshot that was taken after they booted, and before their setup functions first ran.
+    ///
+    /// The setup function then runs on a machine in the same state as at launch, so it does not
+    /// have to be safe to run more than once, and leftovers of the previous run cannot hide
+    /// mistakes in the new recipe. Only launchers that take clean snapshots support this, such as
+    /// the Vagrant launcher with `clean_snapshots(true)`.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn f(l: impl tsunami::Tsunami) -> Result<(), color_eyre::Report> {
+    /// use tsunami::Tsunami;
+    /// l.rerun_setup_clean(vec!["server"]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn rerun_setup_clean<'l>(
+        &'l self,
+        nicknames: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>>;
+
+    /// Write a description of the launched machines to the directory `dir`, so that the
+    /// experiment can be documented, shared, or re-created later, and pack it up as `dir.tar.gz`.
+    ///
+    /// The directory holds the launcher configuration (including every machine's setup, except
+    /// for the setup functions themselves), each machine's address and current
+    /// [state](Tsunami::status), each reachable machine's
+    /// [environment](crate::provenance::capture), and a `replay.sh` script that re-creates a
+    /// machine's environment on a fresh host. Machines that cannot be reached are still listed,
+    /// so the export also works when part of the experiment is down. Returns the path of the
+    /// tarball; packing it needs `tar` in your `$PATH`.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+    /// use tsunami::Tsunami;
+    /// aws.export_bundle("results/bundle").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn export_bundle<'l>(
+        &'l self,
+        dir: impl AsRef<std::path::Path> + Send + 'l,
+    ) -> Pin<Box<dyn Future<Output = Result<std::path::PathBuf, Report>> + Send + 'l>>
+    where
+        Self: std::fmt::Debug + Sync,
+    {
+        Box::pin(async move {
+            let states = self.status().await?;
+            let machines = self.describe_all()?;
+            bundle::export(format!("{:#?}", self), machines, states, dir.as_ref()).await
+        })
+    }
+
+    /// Save a record of this run, with the launcher configuration and the launched machines, to
+    /// `runs`, and return the run's id.
+    ///
+    /// See [`runs`] for how to share run records, and how to mark a run as finished.
+    ///
+    /// # Example
+    /// ```rust,no_run
+    /// # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+    /// use tsunami::runs::{Dir, Runs};
+    /// use tsunami::Tsunami;
+    /// let runs = Runs::new(Dir::new("/shared/tsunami/runs"));
+    /// let id = aws.record_run(&runs).await?;
+    /// println!("recorded run {}", id);
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn record_run<'l, S: runs::Store>(
+        &'l self,
+        runs: &'l runs::Runs<S>,
+    ) -> Pin<Box<dyn Future<Output = Result<String, Report>> + Send + 'l>>
+    where
+        Self: std::fmt::Debug + Sync,
+    {
+        Box::pin(async move {
+            let states = self.status().await?;
+            let machines = self.describe_all()?;
+            let run = runs::Run::new(
+                format!("{:#?}", self),
+                bundle::machines_table(&machines, &states),
+            );
+            runs.save(&run).await?;
+            runs.log(&run.id, format!("launched {} machines", states.len()))
+                .await?;
+            Ok(run.id)
+        })
+    }
 }
 
 impl<L: providers::Launcher> Tsunami for L {
@@ -342,6 +1047,77 @@ impl<L: providers::Launcher> Tsunami for L {
         self.terminate_all()
     }
 
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, LazyMachine<'l>>, Report> {
+        self.describe_all()
+    }
+
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<HashMap<String, providers::MachineState>, Report>>
+                + Send
+                + 'l,
+        >,
+    > {
+        self.status()
+    }
+
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn rerun_setup<'l>(
+        &'l self,
+        nicknames: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        let nicknames = nicknames.into_iter().map(Into::into).collect();
+        let setup_fns = self.setup_fns();
+        let machines = self.connect_all();
+        Box::pin(providers::rerun_setup(nicknames, setup_fns, machines))
+    }
+
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn rerun_setup_clean<'l>(
+        &'l self,
+        nicknames: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        let nicknames: Vec<String> = nicknames.into_iter().map(Into::into).collect();
+        Box::pin(async move {
+            self.restore_clean(nicknames.clone()).await?;
+            let setup_fns = self.setup_fns();
+            providers::rerun_setup(nicknames, setup_fns, self.connect_all()).await
+        })
+    }
+
     fn spawn<'l, I>(
         &'l mut self,
         descriptors: I,
@@ -362,6 +1138,28 @@ mod sealed {
     impl<L: crate::providers::Launcher> Sealed for L {}
 }
 
+/// The entries of `machines` ordered by nickname, the order of
+/// [`connect_all_sorted`](Tsunami::connect_all_sorted).
+///
+/// This takes the map that [`connect_all`](Tsunami::connect_all) or `connect_all_sorted`
+/// returns, or any other nickname and machine pairs.
+///
+/// ```rust,no_run
+/// # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+/// use tsunami::Tsunami;
+/// let vms = aws.connect_all().await?;
+/// let (leader, _) = tsunami::by_nickname(&vms)[0];
+/// # Ok(())
+/// # }
+/// ```
+pub fn by_nickname<'a, V: 'a>(
+    machines: impl IntoIterator<Item = (&'a String, &'a V)>,
+) -> Vec<(&'a String, &'a V)> {
+    let mut ms: Vec<_> = machines.into_iter().collect();
+    ms.sort_by(|(a, _), (b, _)| a.cmp(b));
+    ms
+}
+
 /// Make multiple machine descriptors.
 ///
 /// The `nickname_prefix` is used to name the machines, indexed from 0 to `n`:
@@ -383,8 +1181,7 @@ mod sealed {
 /// }
 /// ```
 pub fn make_multiple<M: Clone>(n: usize, nickname_prefix: &str, m: M) -> Vec<(String, M)> {
-    std::iter::repeat(m)
-        .take(n)
+    std::iter::repeat_n(m, n)
         .enumerate()
         .map(|(i, m)| {
             let name = format!("{}-{}", nickname_prefix, i);
@@ -0,0 +1,527 @@
+//! Log drains for large launches and for machines.
+//!
+//! The providers run every region, and every machine in it, inside a [`tracing`] span that
+//! carries its `region`, and the machine's `nickname` or `name` and (on EC2) `instance_id`. Any
+//! log line emitted while working on a machine thus already has those fields attached.
+//!
+//! [`PerRegion`] is a [`tracing_subscriber::Layer`] that writes each log line, with the fields of
+//! all its enclosing spans, to a separate drain for each region. The drains come from a factory
+//! that is called the first time a region logs anything, so a launch across dozens of regions can
+//! have, e.g., one file per region instead of one interleaved stream.
+//!
+//! ```rust,no_run
+//! use tracing_subscriber::prelude::*;
+//! use tsunami::Tsunami;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     // logs/us-east-1.log, logs/eu-west-1.log, ..., and logs/tsunami.log for the rest.
+//!     let logs = tsunami::logs::PerRegion::files("logs");
+//!     tracing_subscriber::registry().with(logs).init();
+//!
+//!     let mut aws = tsunami::providers::aws::Launcher::default();
+//!     let ms = tsunami::providers::aws::Setup::default()
+//!         .in_each_region(tsunami::providers::aws::GLOBAL_COVERAGE.iter().cloned())
+//!         .await?;
+//!     aws.spawn(ms, None).await?;
+//!     aws.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The region and machine spans are mostly at the `DEBUG` level. If the subscriber filters by
+//! level, keep them enabled for `tsunami`, or events will land in the drain for no region.
+//!
+//! [`Json`] is a layer that writes every event as one JSON object per line instead, for programs
+//! such as CI pipelines that need to parse launch outcomes and timings. See its documentation for
+//! the format.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+type Factory = dyn Fn(Option<&str>) -> io::Result<Box<dyn Write + Send>> + Send + Sync;
+
+type Drain = Arc<Mutex<Box<dyn Write + Send>>>;
+
+struct Drains {
+    factory: Box<Factory>,
+    open: Mutex<HashMap<Option<String>, Drain>>,
+    // the first error since the last `flush`.
+    error: Mutex<Option<io::Error>>,
+}
+
+impl Drains {
+    fn failed(&self, e: io::Error) {
+        // this runs inside the subscriber, so the error cannot be logged through `tracing`.
+        self.error.lock().unwrap().get_or_insert(e);
+    }
+
+    fn drain(&self, region: Option<String>) -> Drain {
+        if let Some(out) = self.open.lock().unwrap().get(&region) {
+            return Arc::clone(out);
+        }
+
+        // open the drain without holding the lock, so other regions can keep logging.
+        let out: Box<dyn Write + Send> = match (self.factory)(region.as_deref()) {
+            Ok(out) => out,
+            Err(e) => {
+                self.failed(e);
+                Box::new(io::sink())
+            }
+        };
+        // if another thread won the race, theirs is kept and this one is dropped.
+        Arc::clone(
+            self.open
+                .lock()
+                .unwrap()
+                .entry(region)
+                .or_insert_with(|| Arc::new(Mutex::new(out))),
+        )
+    }
+
+    fn write(&self, region: Option<String>, line: &str) {
+        let out = self.drain(region);
+        let written = out.lock().unwrap().write_all(line.as_bytes());
+        if let Err(e) = written {
+            self.failed(e);
+        }
+    }
+}
+
+/// A [`Layer`] that writes the log lines of each region to a drain of its own.
+///
+/// See the [module documentation](self) for an example. Clones share the same drains.
+#[derive(Clone)]
+pub struct PerRegion(Arc<Drains>);
+
+impl std::fmt::Debug for PerRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let open = self.0.open.lock().unwrap();
+        f.debug_struct("PerRegion")
+            .field("regions", &open.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl PerRegion {
+    /// Write the log lines of region `r` to the drain returned by `drain(Some(r))`, and those
+    /// emitted outside of any region to the one returned by `drain(None)`.
+    ///
+    /// `drain` is called at most once per region. If it fails, the region's log lines are
+    /// dropped. That error, like any error writing to a drain, is returned by the next call to
+    /// [`flush`](PerRegion::flush).
+    pub fn new(
+        drain: impl Fn(Option<&str>) -> io::Result<Box<dyn Write + Send>> + Send + Sync + 'static,
+    ) -> Self {
+        PerRegion(Arc::new(Drains {
+            factory: Box::new(drain),
+            open: Mutex::new(HashMap::new()),
+            error: Mutex::new(None),
+        }))
+    }
+
+    /// Append the log lines of each region to `<region>.log` in `dir`, and all other log lines
+    /// to `tsunami.log`.
+    ///
+    /// Characters in region names that do not belong in file names are replaced by `_`.
+    pub fn files(dir: impl AsRef<Path>) -> Self {
+        let dir = dir.as_ref().to_path_buf();
+        Self::new(move |region| {
+            std::fs::create_dir_all(&dir)?;
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join(format!("{}.log", file_name(region))))?;
+            Ok(Box::new(file))
+        })
+    }
+
+    /// Flush every drain, e.g. before exiting when the drains are buffered.
+    ///
+    /// Also returns the first error opening or writing to a drain since the last call, if any.
+    pub fn flush(&self) -> io::Result<()> {
+        if let Some(e) = self.0.error.lock().unwrap().take() {
+            return Err(e);
+        }
+        let open: Vec<_> = self.0.open.lock().unwrap().values().cloned().collect();
+        for out in open {
+            out.lock().unwrap().flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn file_name(region: Option<&str>) -> String {
+    region
+        .unwrap_or("tsunami")
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' | '@' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// The fields of a span or event, formatted as `key=value` pairs.
+#[derive(Default)]
+struct Fields {
+    region: Option<String>,
+    message: Option<String>,
+    text: String,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+            return;
+        }
+        if field.name() == "region" {
+            self.region = Some(format!("{:?}", value));
+        }
+        if !self.text.is_empty() {
+            self.text.push(' ');
+        }
+        let _ = write!(self.text, "{}={:?}", field.name(), value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{}", value))
+    }
+}
+
+impl<S> Layer<S> for PerRegion
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            let mut ext = span.extensions_mut();
+            // another `PerRegion` may have recorded them already.
+            if ext.get_mut::<Fields>().is_none() {
+                ext.insert(fields);
+            }
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut line = format!("{:>5} ", meta.level());
+        let mut region = None;
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                let ext = span.extensions();
+                match ext.get::<Fields>() {
+                    Some(fields) if !fields.text.is_empty() => {
+                        let _ = write!(line, "{}{{{}}}:", span.name(), fields.text);
+                        // the innermost region wins
+                        region = fields.region.clone().or(region);
+                    }
+                    _ => {
+                        let _ = write!(line, "{}:", span.name());
+                    }
+                }
+            }
+            line.push(' ');
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let _ = write!(line, "{}:", meta.target());
+        if let Some(message) = fields.message {
+            let _ = write!(line, " {}", message);
+        }
+        if !fields.text.is_empty() {
+            let _ = write!(line, " {}", fields.text);
+        }
+        line.push('\n');
+
+        self.0.write(fields.region.or(region), &line);
+    }
+}
+
+/// A [`Layer`] that writes every event as a line of JSON.
+///
+/// Each line is an object with these keys:
+///
+///  - `event`: `"progress"` for the machine state changes the providers announce on
+///    [`PROGRESS_TARGET`](crate::providers::PROGRESS_TARGET), and `"log"` for everything else.
+///  - `ts_ms`: when the event happened, in milliseconds since the Unix epoch.
+///  - `elapsed_ms`: when the event happened, in milliseconds since the layer was created.
+///  - `level`: e.g. `"INFO"` or `"WARN"`.
+///  - `target`: the module the event came from, e.g. `"tsunami::providers::aws"`.
+///  - `fields`: the event's fields, including `message` if it has one. For `progress` events,
+///    these are `nickname` and `state` (the `Display` form of a
+///    [`MachineState`](crate::providers::MachineState), e.g. `"ready"` or `"setup-failed"`).
+///  - `spans`: the enclosing spans, outermost first, each an object with its `name` and `fields`.
+///    The fields include e.g. `region` and `instance_id` where the provider knows them.
+///
+/// The keys and progress events are stable; the `log` events are meant for humans, and may
+/// change between releases.
+///
+/// ```rust,no_run
+/// use tracing_subscriber::prelude::*;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let events = tsunami::logs::Json::file("tsunami-events.jsonl")?;
+/// tracing_subscriber::registry().with(events).init();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Json(Arc<Mutex<JsonOut>>);
+
+struct JsonOut {
+    out: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+impl std::fmt::Debug for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Json")
+            .field("start", &self.0.lock().unwrap().start)
+            .finish()
+    }
+}
+
+impl Json {
+    /// Write the events to `out`.
+    pub fn to_writer(out: impl Write + Send + 'static) -> Self {
+        Json(Arc::new(Mutex::new(JsonOut {
+            out: Box::new(out),
+            start: Instant::now(),
+        })))
+    }
+
+    /// Append the events to the file at `path`, creating it if need be.
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self::to_writer(file))
+    }
+}
+
+// The fields of a span or event, as JSON values.
+#[derive(Default)]
+struct JsonFields(serde_json::Map<String, serde_json::Value>);
+
+impl Visit for JsonFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
+
+impl<S> Layer<S> for Json
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = JsonFields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            let mut ext = span.extensions_mut();
+            // another `Json` may have recorded them already.
+            if ext.get_mut::<JsonFields>().is_none() {
+                ext.insert(fields);
+            }
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<JsonFields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut spans = Vec::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                let ext = span.extensions();
+                let fields = ext
+                    .get::<JsonFields>()
+                    .map(|f| f.0.clone())
+                    .unwrap_or_default();
+                spans.push(serde_json::json!({ "name": span.name(), "fields": fields }));
+            }
+        }
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let kind = if meta.target() == crate::providers::PROGRESS_TARGET {
+            "progress"
+        } else {
+            "log"
+        };
+        let mut out = self.0.lock().unwrap();
+        let line = serde_json::json!({
+            "event": kind,
+            "ts_ms": now.as_millis() as u64,
+            "elapsed_ms": out.start.elapsed().as_millis() as u64,
+            "level": meta.level().to_string(),
+            "target": meta.target(),
+            "fields": fields.0,
+            "spans": spans,
+        });
+        let _ = writeln!(out.out, "{}", line);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[derive(Clone, Default)]
+    struct Buffers(Arc<Mutex<HashMap<Option<String>, Vec<u8>>>>);
+
+    struct Buffer(Buffers, Option<String>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut bufs = (self.0).0.lock().unwrap();
+            bufs.entry(self.1.clone())
+                .or_default()
+                .extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn routing() {
+        let bufs = Buffers::default();
+        let b = bufs.clone();
+        let logs =
+            PerRegion::new(move |region| Ok(Box::new(Buffer(b.clone(), region.map(String::from)))));
+        let subscriber = tracing_subscriber::registry().with(logs);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("launching");
+            let region = tracing::debug_span!("region", region = "us-east-1");
+            let _r = region.enter();
+            let instance = tracing::debug_span!("instance", instance_id = "i-0123");
+            let _i = instance.enter();
+            tracing::warn!(nickname = "server", "spot request failed");
+        });
+
+        let bufs = bufs.0.lock().unwrap();
+        let log = |region: Option<&str>| {
+            String::from_utf8(bufs[&region.map(String::from)].clone()).unwrap()
+        };
+        assert_eq!(log(None), " INFO tsunami::logs::test: launching\n");
+        assert_eq!(
+            log(Some("us-east-1")),
+            " WARN region{region=us-east-1}:instance{instance_id=i-0123}: \
+             tsunami::logs::test: spot request failed nickname=server\n"
+        );
+    }
+
+    #[test]
+    fn failed_drain() {
+        let logs = PerRegion::new(|region| match region {
+            None => Ok(Box::new(io::sink())),
+            Some(_) => Err(io::Error::new(io::ErrorKind::PermissionDenied, "read-only")),
+        });
+        let subscriber = tracing_subscriber::registry().with(logs.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("launching");
+            let region = tracing::debug_span!("region", region = "us-east-1");
+            let _r = region.enter();
+            tracing::warn!("spot request failed");
+        });
+
+        let e = logs.flush().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        // the error is only reported once.
+        logs.flush().unwrap();
+    }
+
+    #[test]
+    fn json() {
+        let bufs = Buffers::default();
+        let events = Json::to_writer(Buffer(bufs.clone(), None));
+        let subscriber = tracing_subscriber::registry().with(events);
+        tracing::subscriber::with_default(subscriber, || {
+            let region = tracing::debug_span!("region", region = "us-east-1");
+            let _r = region.enter();
+            tracing::info!(
+                target: crate::providers::PROGRESS_TARGET,
+                nickname = "server",
+                state = "ready"
+            );
+            tracing::warn!(attempt = 2, "spot request failed");
+        });
+
+        let bufs = bufs.0.lock().unwrap();
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&bufs[&None])
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "progress");
+        assert_eq!(lines[0]["fields"]["nickname"], "server");
+        assert_eq!(lines[0]["fields"]["state"], "ready");
+        assert_eq!(lines[0]["spans"][0]["name"], "region");
+        assert_eq!(lines[0]["spans"][0]["fields"]["region"], "us-east-1");
+        assert_eq!(lines[1]["event"], "log");
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["fields"]["message"], "spot request failed");
+        assert_eq!(lines[1]["fields"]["attempt"], 2);
+        assert!(lines[1]["elapsed_ms"].as_u64().is_some());
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name(None), "tsunami");
+        assert_eq!(file_name(Some("us-east-1-1")), "us-east-1-1");
+        assert_eq!(file_name(Some("docker:local")), "docker_local");
+    }
+}
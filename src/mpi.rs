@@ -0,0 +1,395 @@
+//! Turn machines into an MPI cluster.
+//!
+//! [`Cluster::bootstrap`] does what every MPI job on fresh cloud machines needs first: it installs
+//! OpenMPI on all of them and checks that they ended up with the same version, lets the machines
+//! SSH to each other without a password, and writes a hostfile with a slot for each core. The
+//! returned [`Mpi`] then runs `mpirun` jobs across all the machines:
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::mpi::Cluster;
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! let mpi = Cluster::new().bootstrap(&vms).await?;
+//! let out = mpi.run("hostname").await?;
+//! assert_eq!(out.stdout.lines().count(), mpi.np());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! On AWS, launch the machines with [`aws::Setup::efa`](crate::providers::aws::Setup::efa) and
+//! bootstrap them with [`Cluster::efa`] to have MPI use the Elastic Fabric Adapter.
+
+use crate::run::{address, quote, CommandOutput};
+use crate::{Machine, OsFamily};
+use color_eyre::{
+    eyre::{self, eyre, WrapErr},
+    Report,
+};
+use std::fmt::Write as _;
+
+/// The hostfile that [`Cluster::bootstrap`] writes on the leader, relative to the ssh user's home
+/// directory.
+pub const HOSTFILE: &str = ".tsunami/mpi-hosts";
+
+// The key the machines use to SSH to each other, relative to the ssh user's home directory.
+const MESH_KEY: &str = ".ssh/tsunami-mpi";
+
+// Where the EFA installer puts its build of OpenMPI.
+const EFA_MPIRUN: &str = "/opt/amazon/openmpi/bin/mpirun";
+
+const EFA_INSTALL: &str = "set -e
+cd /tmp
+curl -sSfO https://efa-installer.amazonaws.com/aws-efa-installer-latest.tar.gz
+tar -xzf aws-efa-installer-latest.tar.gz
+cd aws-efa-installer
+sudo ./efa_installer.sh -y
+";
+
+/// How to set up an MPI cluster. See the [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct Cluster {
+    slots: Option<usize>,
+    efa: bool,
+    mpirun: Option<String>,
+}
+
+impl Cluster {
+    /// Install OpenMPI from the machines' package manager, and give each machine a slot per core.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give each machine `slots` slots, rather than one per core.
+    pub fn slots(mut self, slots: usize) -> Self {
+        self.slots = Some(slots.max(1));
+        self
+    }
+
+    /// Install the [EFA software](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/efa-start.html),
+    /// and use the OpenMPI it comes with over the Elastic Fabric Adapter.
+    ///
+    /// The machines need an EFA, see [`aws::Setup::efa`](crate::providers::aws::Setup::efa).
+    pub fn efa(mut self) -> Self {
+        self.efa = true;
+        self
+    }
+
+    /// Do not install anything, and run MPI jobs with the `mpirun` at `path`.
+    ///
+    /// This is for images that come with MPI, such as the AWS Deep Learning AMIs.
+    pub fn preinstalled(mut self, path: impl ToString) -> Self {
+        self.mpirun = Some(path.to_string());
+        self
+    }
+
+    // The packages that provide OpenMPI on `os`, and where `mpirun` ends up.
+    fn packages(os: OsFamily) -> (&'static [&'static str], &'static str) {
+        match os {
+            OsFamily::AmazonLinux => (
+                &["openmpi", "openmpi-devel"][..],
+                "/usr/lib64/openmpi/bin/mpirun",
+            ),
+            OsFamily::FreeBsd => (&["openmpi"][..], "/usr/local/mpi/openmpi/bin/mpirun"),
+            _ => (&["openmpi-bin", "libopenmpi-dev"][..], "mpirun"),
+        }
+    }
+
+    // Installs MPI on `vm`, and returns the path of its `mpirun`.
+    async fn install(&self, vm: &Machine<'_>) -> Result<String, Report> {
+        if let Some(ref mpirun) = self.mpirun {
+            return Ok(mpirun.clone());
+        }
+
+        let (script, mpirun) = if self.efa {
+            (String::from(EFA_INSTALL), EFA_MPIRUN)
+        } else {
+            let os = match vm.os {
+                Some(os) => os,
+                None => OsFamily::detect(&vm.ssh).await?,
+            };
+            let (packages, mpirun) = Self::packages(os);
+            let mut script = String::new();
+            if let OsFamily::Ubuntu | OsFamily::Debian = os {
+                script.push_str("sudo apt-get update -q && ");
+            }
+            let _ = write!(
+                script,
+                "sudo DEBIAN_FRONTEND=noninteractive {}",
+                os.install_command(packages).join(" ")
+            );
+            (script, mpirun)
+        };
+
+        let out = vm
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(script)
+            .output()
+            .await
+            .wrap_err("failed to run installer")?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to install MPI: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+        Ok(mpirun.to_string())
+    }
+
+    // Installs MPI on `vm`, and returns its `mpirun`, the MPI version, and the number of slots.
+    async fn prepare(&self, vm: &Machine<'_>) -> Result<(String, String, usize), Report> {
+        let mpirun = self.install(vm).await?;
+        let out = vm
+            .ssh
+            .command(&mpirun)
+            .arg("--version")
+            .output()
+            .await
+            .wrap_err("failed to run mpirun")?;
+        eyre::ensure!(out.status.success(), "mpirun --version failed");
+        let version = String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+
+        let slots = match self.slots {
+            Some(slots) => slots,
+            None => {
+                let out = vm.ssh.command("nproc").output().await?;
+                eyre::ensure!(out.status.success(), "nproc failed");
+                String::from_utf8_lossy(&out.stdout)
+                    .trim()
+                    .parse()
+                    .wrap_err("nproc printed something other than a number")?
+            }
+        };
+        Ok((mpirun, version, slots))
+    }
+
+    /// Set up `machines` as an MPI cluster, and return a handle for running jobs on it.
+    ///
+    /// `machines` is the map that [`connect_all`](crate::Tsunami::connect_all) or
+    /// [`connect_all_sorted`](crate::Tsunami::connect_all_sorted) returns, or any other nickname
+    /// and machine pairs. The machine that comes first by nickname is the leader, which runs
+    /// `mpirun`. The machines
+    /// reach each other over their private addresses where they have one. Bootstrapping again
+    /// replaces the SSH key and the hostfile of an earlier bootstrap.
+    #[tracing::instrument(level = "debug", skip(machines))]
+    pub async fn bootstrap<'m, 't>(
+        &self,
+        machines: impl IntoIterator<Item = (&'m String, &'m Machine<'t>)>,
+    ) -> Result<Mpi<'m, 't>, Report> {
+        let vms = crate::by_nickname(machines);
+        let (leader_name, leader) = *vms.first().ok_or_else(|| eyre!("no machines"))?;
+
+        tracing::info!("installing MPI");
+        let limit = vms.len();
+        let prepared =
+            crate::each::for_each(vms.iter().copied(), limit, |vm| self.prepare(vm)).await?;
+        let mut versions: Vec<_> = prepared.values().map(|(_, v, _)| v.as_str()).collect();
+        versions.sort_unstable();
+        versions.dedup();
+        eyre::ensure!(
+            versions.len() == 1,
+            "machines have different MPI versions: {}",
+            versions.join(", ")
+        );
+
+        let hosts: Vec<_> = vms
+            .iter()
+            .map(|(name, vm)| {
+                (
+                    address(vm).to_string(),
+                    vm.username.clone(),
+                    prepared[*name].2,
+                )
+            })
+            .collect();
+
+        tracing::info!(leader = %leader_name, "connecting MPI hosts");
+        let key = leader
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(format!(
+                "mkdir -p .ssh && rm -f {0} {0}.pub && ssh-keygen -q -t ed25519 -N '' -C tsunami-mpi -f {0} && cat {0}",
+                MESH_KEY
+            ))
+            .output()
+            .await
+            .wrap_err("failed to run ssh-keygen")?;
+        eyre::ensure!(key.status.success(), "ssh-keygen failed");
+        let public = leader
+            .ssh
+            .command("cat")
+            .arg(format!("{}.pub", MESH_KEY))
+            .output()
+            .await?;
+        eyre::ensure!(public.status.success(), "failed to read the public key");
+        let script = mesh_script(
+            &String::from_utf8_lossy(&key.stdout),
+            String::from_utf8_lossy(&public.stdout).trim(),
+            &hosts,
+        );
+        let script = &script;
+        crate::each::for_each(vms.iter().copied(), limit, |vm| async move {
+            let out = vm.ssh.command("sh").arg("-c").arg(script).output().await?;
+            eyre::ensure!(
+                out.status.success(),
+                "failed to set up ssh: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            Ok(())
+        })
+        .await?;
+
+        let out = leader
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(format!(
+                "mkdir -p .tsunami && printf '%s' {} > {}",
+                quote(&hostfile(&hosts)),
+                HOSTFILE
+            ))
+            .output()
+            .await?;
+        eyre::ensure!(out.status.success(), "failed to write hostfile");
+
+        Ok(Mpi {
+            leader,
+            mpirun: prepared[leader_name].0.clone(),
+            np: hosts.iter().map(|(_, _, slots)| slots).sum(),
+            efa: self.efa,
+        })
+    }
+}
+
+/// A handle for running MPI jobs on a bootstrapped [`Cluster`].
+#[derive(Debug)]
+pub struct Mpi<'m, 't> {
+    leader: &'m Machine<'t>,
+    mpirun: String,
+    np: usize,
+    efa: bool,
+}
+
+impl<'m, 't> Mpi<'m, 't> {
+    /// The machine that runs `mpirun`.
+    pub fn leader(&self) -> &'m Machine<'t> {
+        self.leader
+    }
+
+    /// The number of slots across all machines.
+    pub fn np(&self) -> usize {
+        self.np
+    }
+
+    /// The command line that runs `program` as `np` processes across the cluster.
+    ///
+    /// `program` is passed on to `mpirun` as is, so it can include arguments.
+    pub fn command(&self, np: usize, program: &str) -> String {
+        let mut cmd = format!("{} --hostfile {} -np {}", self.mpirun, HOSTFILE, np);
+        if self.efa {
+            cmd.push_str(" -x FI_PROVIDER=efa");
+        }
+        let _ = write!(cmd, " {}", program);
+        cmd
+    }
+
+    /// Run `program` on every slot of the cluster, and wait for it to finish.
+    ///
+    /// Like with [`run_on`](crate::run::run_on), a non-zero exit status is not an error.
+    pub async fn run(&self, program: &str) -> Result<CommandOutput, Report> {
+        self.run_np(self.np, program).await
+    }
+
+    /// Run `program` as `np` processes, and wait for it to finish.
+    pub async fn run_np(&self, np: usize, program: &str) -> Result<CommandOutput, Report> {
+        let cmd = self.command(np, program);
+        tracing::debug!(%cmd, "running MPI job");
+        Ok(CommandOutput::from(
+            self.leader.ssh.shell(cmd).output().await?,
+        ))
+    }
+}
+
+// An OpenMPI hostfile for `hosts`, given as (address, user, slots).
+fn hostfile(hosts: &[(String, String, usize)]) -> String {
+    hosts
+        .iter()
+        .map(|(addr, _, slots)| format!("{} slots={}\n", addr, slots))
+        .collect()
+}
+
+// Installs the key pair of the mesh, authorizes its public key, and points ssh at the key for
+// each of `hosts`, replacing any earlier bootstrap's configuration.
+fn mesh_script(private: &str, public: &str, hosts: &[(String, String, usize)]) -> String {
+    let mut config = String::from("# tsunami-mpi begin\n");
+    for (addr, user, _) in hosts {
+        let _ = write!(
+            config,
+            "Host {}\n    User {}\n    IdentityFile ~/{}\n    StrictHostKeyChecking no\n    UserKnownHostsFile /dev/null\n    LogLevel ERROR\n",
+            addr, user, MESH_KEY
+        );
+    }
+    config.push_str("# tsunami-mpi end\n");
+
+    let mut script = String::from("set -e\nmkdir -p .ssh && chmod 700 .ssh\n");
+    let _ = writeln!(
+        script,
+        "(umask 077 && printf '%s' {} > {})",
+        quote(private),
+        MESH_KEY
+    );
+    let _ = writeln!(
+        script,
+        "printf '%s\\n' {} > {}.pub",
+        quote(public),
+        MESH_KEY
+    );
+    let _ = writeln!(
+        script,
+        "grep -qxF {0} .ssh/authorized_keys 2>/dev/null || printf '%s\\n' {0} >> .ssh/authorized_keys",
+        quote(public)
+    );
+    script.push_str("touch .ssh/config && chmod 600 .ssh/config\n");
+    script.push_str("sed -i '/^# tsunami-mpi begin$/,/^# tsunami-mpi end$/d' .ssh/config\n");
+    let _ = writeln!(script, "printf '%s' {} >> .ssh/config", quote(&config));
+    script
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bootstrap_files() {
+        let hosts = vec![
+            (String::from("10.0.0.1"), String::from("ubuntu"), 4),
+            (String::from("10.0.0.2"), String::from("ec2-user"), 8),
+        ];
+        assert_eq!(hostfile(&hosts), "10.0.0.1 slots=4\n10.0.0.2 slots=8\n");
+
+        let script = mesh_script("KEY\n", "ssh-ed25519 AAAA tsunami-mpi", &hosts);
+        assert!(script.contains("printf '%s' 'KEY\n' > .ssh/tsunami-mpi)\n"));
+        assert!(script.contains(
+            "grep -qxF 'ssh-ed25519 AAAA tsunami-mpi' .ssh/authorized_keys 2>/dev/null ||"
+        ));
+        assert!(script
+            .contains("Host 10.0.0.2\n    User ec2-user\n    IdentityFile ~/.ssh/tsunami-mpi\n"));
+        assert!(script.ends_with("# tsunami-mpi end\n' >> .ssh/config\n"));
+    }
+
+    #[test]
+    fn packages() {
+        assert_eq!(Cluster::packages(OsFamily::Ubuntu).1, "mpirun");
+        assert_eq!(
+            Cluster::packages(OsFamily::AmazonLinux).0,
+            ["openmpi", "openmpi-devel"]
+        );
+    }
+}
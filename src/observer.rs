@@ -0,0 +1,235 @@
+//! Read-only access to someone else's machines.
+//!
+//! An [`Observer`] is built from the bundle that [`Tsunami::export_bundle`](crate::Tsunami::export_bundle)
+//! writes, and lets collaborators check on a long-running experiment: see the state of each
+//! machine, connect to them, and collect files. It cannot launch or terminate anything, so there
+//! is no risk of tearing the experiment down by accident.
+//!
+//! ```rust,no_run
+//! # async fn f() -> Result<(), color_eyre::Report> {
+//! use tsunami::observer::Observer;
+//!
+//! // the key is not part of the bundle; get a copy from whoever launched the machines.
+//! let o = Observer::from_bundle("shared/bundle")?.private_key("keys/experiment.pem");
+//! for (name, state) in o.status().await {
+//!     println!("{}: {}", name, state);
+//! }
+//! o.collect("/var/log/server.log", "logs/").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::providers::MachineState;
+use color_eyre::{
+    eyre::{self, eyre, WrapErr},
+    Report,
+};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// How to reach one observed machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ObservedMachine {
+    /// See [`Machine::nickname`](crate::Machine::nickname).
+    pub nickname: String,
+    /// See [`Machine::public_ip`](crate::Machine::public_ip).
+    pub public_ip: String,
+    /// See [`Machine::public_dns`](crate::Machine::public_dns).
+    pub public_dns: String,
+    /// See [`Machine::private_ip`](crate::Machine::private_ip).
+    pub private_ip: Option<String>,
+    /// See [`Machine::username`](crate::Machine::username).
+    pub username: String,
+    /// See [`Machine::private_key`](crate::Machine::private_key).
+    pub private_key: Option<PathBuf>,
+    /// The port the machine's SSH server listens on.
+    pub port: u16,
+}
+
+fn parse_machines(table: &str) -> Result<Vec<ObservedMachine>, Report> {
+    let opt = |s: &str| Some(s).filter(|s| *s != "-").map(String::from);
+    table
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| {
+            let f: Vec<_> = l.split('\t').collect();
+            eyre::ensure!(f.len() >= 6, "malformed machine line {:?}", l);
+            // bundles written before the port was recorded only have machines on port 22.
+            let port = match f.get(7).and_then(|p| opt(p)) {
+                Some(p) => p
+                    .parse()
+                    .wrap_err_with(|| format!("bad port in machine line {:?}", l))?,
+                None => 22,
+            };
+            Ok(ObservedMachine {
+                nickname: f[0].to_string(),
+                public_ip: f[2].to_string(),
+                public_dns: f[3].to_string(),
+                private_ip: opt(f[4]),
+                username: f[5].to_string(),
+                private_key: f.get(6).and_then(|k| opt(k)).map(PathBuf::from),
+                port,
+            })
+        })
+        // machines that were not running when the bundle was made have no address.
+        .filter(|m| !matches!(m, Ok(m) if m.public_ip == "-"))
+        .collect()
+}
+
+/// Read-only access to a set of machines launched by someone else.
+///
+/// See the [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct Observer {
+    machines: Vec<ObservedMachine>,
+}
+
+impl Observer {
+    /// Observe the machines listed in the bundle in directory `dir`.
+    pub fn from_bundle(dir: impl AsRef<Path>) -> Result<Self, Report> {
+        let path = dir.as_ref().join("machines");
+        let table = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+        Ok(Observer {
+            machines: parse_machines(&table)?,
+        })
+    }
+
+    /// Use the private key at `path` for every machine, instead of the one in the bundle.
+    pub fn private_key(mut self, path: impl AsRef<Path>) -> Self {
+        for m in &mut self.machines {
+            m.private_key = Some(path.as_ref().to_path_buf());
+        }
+        self
+    }
+
+    /// The observed machines.
+    pub fn machines(&self) -> &[ObservedMachine] {
+        &self.machines
+    }
+
+    async fn connect<'o>(&'o self, m: &ObservedMachine) -> Result<crate::Machine<'o>, Report> {
+        let d = crate::MachineDescriptor {
+            nickname: m.nickname.clone(),
+            public_dns: Some(m.public_dns.clone()),
+            public_ip: m.public_ip.clone(),
+            private_ip: m.private_ip.clone(),
+            os: None,
+            proxy_command: None,
+            check_host_key: true,
+            _tsunami: Default::default(),
+        };
+        d.connect_ssh(
+            &m.username,
+            m.private_key.as_deref(),
+            Some(std::time::Duration::from_secs(10)),
+            m.port,
+        )
+        .await
+        .wrap_err_with(|| format!("failed to connect to {}", m.nickname))
+    }
+
+    /// Check whether each machine can be reached.
+    ///
+    /// Machines that accept an SSH connection are [`MachineState::Ready`], and all others are
+    /// [`MachineState::Unreachable`]; an observer cannot ask the provider for more.
+    pub async fn status(&self) -> HashMap<String, MachineState> {
+        futures_util::future::join_all(self.machines.iter().map(|m| async move {
+            let state = match self.connect(m).await {
+                Ok(c) => {
+                    let _ = c.ssh.close().await;
+                    MachineState::Ready
+                }
+                Err(_) => MachineState::Unreachable,
+            };
+            (m.nickname.clone(), state)
+        }))
+        .await
+        .into_iter()
+        .collect()
+    }
+
+    /// Connect to every machine.
+    pub async fn connect_all(&self) -> Result<HashMap<String, crate::Machine<'_>>, Report> {
+        futures_util::future::join_all(
+            self.machines.iter().map(|m| async move {
+                Ok::<_, Report>((m.nickname.clone(), self.connect(m).await?))
+            }),
+        )
+        .await
+        .into_iter()
+        .collect()
+    }
+
+    /// Copy the file at `remote_path` from every machine to `local_dir/<nickname>/`, and return
+    /// the local paths.
+    ///
+    /// Machines that cannot be reached, or that do not have the file, are skipped with a warning.
+    pub async fn collect(
+        &self,
+        remote_path: &str,
+        local_dir: impl AsRef<Path>,
+    ) -> Result<HashMap<String, PathBuf>, Report> {
+        let file = Path::new(remote_path)
+            .file_name()
+            .ok_or_else(|| eyre!("{} is not a file", remote_path))?;
+        let mut collected = HashMap::new();
+        for om in &self.machines {
+            let name = om.nickname.clone();
+            let m = match self.connect(om).await {
+                Ok(m) => m,
+                Err(e) => {
+                    tracing::warn!(nickname = %name, "skipping unreachable machine: {:?}", e);
+                    continue;
+                }
+            };
+            let out = match m.ssh.command("cat").arg(remote_path).output().await {
+                Ok(out) => out,
+                Err(e) => {
+                    tracing::warn!(nickname = %name, "could not read {}: {:?}", remote_path, e);
+                    continue;
+                }
+            };
+            if !out.status.success() {
+                tracing::warn!(
+                    nickname = %name,
+                    "could not read {}: {}",
+                    remote_path,
+                    String::from_utf8_lossy(&out.stderr).trim()
+                );
+                continue;
+            }
+
+            let dir = local_dir.as_ref().join(&name);
+            std::fs::create_dir_all(&dir)
+                .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+            let path = dir.join(file);
+            std::fs::write(&path, &out.stdout)
+                .wrap_err_with(|| format!("failed to write {}", path.display()))?;
+            collected.insert(name, path);
+        }
+        Ok(collected)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_bundle_table() {
+        let ms = parse_machines(
+            "client\tready\t10.0.0.2\tec2-10-0-0-2\t-\tubuntu\t/tmp/key\n\
+             gone\tterminated\t-\t-\t-\t-\t-\n\
+             server\tready\t10.0.0.1\t10.0.0.1\t172.31.0.1\tubuntu\t-\t2222\n",
+        )
+        .unwrap();
+        assert_eq!(ms.len(), 2);
+        assert_eq!(ms[0].port, 22);
+        assert_eq!(ms[1].port, 2222);
+        assert_eq!(ms[0].private_key, Some(PathBuf::from("/tmp/key")));
+        assert_eq!(ms[1].private_ip.as_deref(), Some("172.31.0.1"));
+        assert_eq!(ms[1].private_key, None);
+    }
+}
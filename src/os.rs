@@ -0,0 +1,130 @@
+//! Operating-system awareness for machine images.
+
+use color_eyre::{eyre, Report};
+
+/// The family of operating system a machine image runs.
+///
+/// Tsunami's defaults assume Ubuntu conventions (the `ubuntu` user, `bash`, and `apt`). Other
+/// images differ in all three, so descriptors that know which family they are launching use this
+/// to pick the SSH user, and setup functions can use it to issue the right package commands.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum OsFamily {
+    /// Ubuntu, as used by the default images.
+    #[default]
+    Ubuntu,
+    /// Debian.
+    Debian,
+    /// Amazon Linux (2 or 2023).
+    AmazonLinux,
+    /// FreeBSD.
+    FreeBsd,
+}
+
+impl OsFamily {
+    /// The user that cloud images of this family let you SSH in as.
+    pub fn default_username(&self) -> &'static str {
+        match self {
+            OsFamily::Ubuntu => "ubuntu",
+            OsFamily::Debian => "admin",
+            OsFamily::AmazonLinux => "ec2-user",
+            OsFamily::FreeBsd => "ec2-user",
+        }
+    }
+
+    /// A shell that is guaranteed to exist on images of this family.
+    ///
+    /// FreeBSD does not ship `bash` in its base system, so this is `sh` there.
+    pub fn shell(&self) -> &'static str {
+        match self {
+            OsFamily::FreeBsd => "sh",
+            _ => "bash",
+        }
+    }
+
+    /// The command line (without `sudo`) that installs `packages` non-interactively.
+    pub fn install_command(&self, packages: &[&str]) -> Vec<String> {
+        let mut cmd: Vec<String> = match self {
+            OsFamily::Ubuntu | OsFamily::Debian => vec!["apt-get", "install", "-y"],
+            OsFamily::AmazonLinux => vec!["yum", "install", "-y"],
+            OsFamily::FreeBsd => vec!["pkg", "install", "-y"],
+        }
+        .into_iter()
+        .map(String::from)
+        .collect();
+        cmd.extend(packages.iter().map(|p| p.to_string()));
+        cmd
+    }
+
+    /// Install `packages` on the machine behind `ssh` using this family's package manager.
+    ///
+    /// ```rust,no_run
+    /// # async fn f(vm: &tsunami::Machine<'_>) -> Result<(), color_eyre::Report> {
+    /// tsunami::OsFamily::FreeBsd
+    ///     .install_packages(&vm.ssh, &["git", "rust"])
+    ///     .status()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn install_packages<'s>(
+        &self,
+        ssh: &'s openssh::Session,
+        packages: &[&str],
+    ) -> openssh::Command<'s> {
+        let mut cmd = ssh.command("sudo");
+        cmd.args(self.install_command(packages));
+        cmd
+    }
+
+    /// Determine the family of the machine behind `ssh`.
+    ///
+    /// This inspects `uname -s` and, on Linux, `/etc/os-release`.
+    pub async fn detect(ssh: &openssh::Session) -> Result<Self, Report> {
+        let uname = ssh.command("uname").arg("-s").output().await?;
+        let uname = String::from_utf8_lossy(&uname.stdout).to_string();
+        let os_release = ssh.command("cat").arg("/etc/os-release").output().await?;
+        let os_release = String::from_utf8_lossy(&os_release.stdout).to_string();
+        Self::from_release_info(&uname, &os_release)
+            .ok_or_else(|| eyre::eyre!("unrecognized operating system: {}", uname.trim()))
+    }
+
+    fn from_release_info(uname: &str, os_release: &str) -> Option<Self> {
+        if uname.trim() == "FreeBSD" {
+            return Some(OsFamily::FreeBsd);
+        }
+
+        let id = os_release
+            .lines()
+            .find_map(|l| l.strip_prefix("ID="))?
+            .trim_matches('"');
+        match id {
+            "ubuntu" => Some(OsFamily::Ubuntu),
+            "debian" => Some(OsFamily::Debian),
+            "amzn" => Some(OsFamily::AmazonLinux),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OsFamily;
+
+    #[test]
+    fn release_info() {
+        assert_eq!(
+            OsFamily::from_release_info("FreeBSD\n", ""),
+            Some(OsFamily::FreeBsd)
+        );
+        assert_eq!(
+            OsFamily::from_release_info("Linux\n", "NAME=\"Amazon Linux\"\nID=\"amzn\"\n"),
+            Some(OsFamily::AmazonLinux)
+        );
+        assert_eq!(
+            OsFamily::from_release_info("Linux\n", "NAME=\"Ubuntu\"\nID=ubuntu\n"),
+            Some(OsFamily::Ubuntu)
+        );
+        assert_eq!(OsFamily::from_release_info("Linux\n", "ID=arch\n"), None);
+    }
+}
@@ -0,0 +1,515 @@
+//! Topology-aware placement of machines before launch.
+//!
+//! A [`Planner`] takes a list of candidate regions and a set of [`Constraint`]s over machine
+//! nicknames, and finds a region (and availability-zone group) for every constrained machine. The
+//! resulting [`Placement`]s are then applied to each provider's descriptor, e.g. with
+//! [`aws::Setup::placed`](crate::providers::aws::Setup::placed).
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use tsunami::placement::{Constraint, Planner};
+//!
+//! let plan = Planner::new(vec!["us-east-1", "us-west-2", "eu-west-1"])
+//!     .with_rtt(|a, b| match (a, b) {
+//!         ("us-east-1", "eu-west-1") | ("eu-west-1", "us-east-1") => Some(Duration::from_millis(70)),
+//!         _ => Some(Duration::from_millis(30)),
+//!     })
+//!     .constrain(Constraint::same_zone(vec!["server", "client"]))
+//!     .constrain(Constraint::apart(
+//!         "server",
+//!         "replica",
+//!         Duration::from_millis(50),
+//!     ))
+//!     .plan()
+//!     .unwrap();
+//! assert_eq!(plan["server"].region, plan["client"].region);
+//! assert_ne!(plan["server"].region, plan["replica"].region);
+//! ```
+//!
+//! Traffic between regions is billed, and it adds up quickly. Declare the traffic you expect
+//! between machines with [`Planner::flow`], and [`Planner::plan`] warns about every flow that the
+//! placement sends across regions, with an estimate of what it will cost. The same estimates are
+//! available from [`Planner::transfer_costs`]:
+//!
+//! ```rust
+//! use tsunami::placement::{Constraint, Planner};
+//!
+//! let planner = Planner::new(vec!["us-east-1", "eu-west-1"])
+//!     .constrain(Constraint::one_per_region(
+//!         vec!["server", "client"],
+//!         vec!["us-east-1", "eu-west-1"],
+//!     ))
+//!     .flow("server", "client", 500.0);
+//! let plan = planner.plan().unwrap();
+//! let costs = planner.transfer_costs(&plan);
+//! assert_eq!(costs.len(), 1);
+//! assert!(costs[0].usd > 0.0);
+//! ```
+
+use color_eyre::{eyre, Report};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A requirement on where a set of machines is placed.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Constraint {
+    /// All the named machines are placed in the same availability zone of the same region.
+    SameZone(Vec<String>),
+    /// The two named machines are placed in different regions at least `min_rtt` apart.
+    Apart {
+        /// The first machine.
+        a: String,
+        /// The second machine.
+        b: String,
+        /// The minimum round-trip time between the two regions.
+        min_rtt: Duration,
+    },
+    /// Each of the named machines is placed in a different region from `regions`.
+    OnePerRegion {
+        /// The machines to spread out.
+        machines: Vec<String>,
+        /// The regions to spread them across.
+        regions: Vec<String>,
+    },
+}
+
+impl Constraint {
+    /// Place all of `machines` in the same availability zone.
+    pub fn same_zone<S: ToString>(machines: impl IntoIterator<Item = S>) -> Self {
+        Constraint::SameZone(machines.into_iter().map(|s| s.to_string()).collect())
+    }
+
+    /// Place `a` and `b` in regions at least `min_rtt` apart.
+    pub fn apart(a: impl ToString, b: impl ToString, min_rtt: Duration) -> Self {
+        Constraint::Apart {
+            a: a.to_string(),
+            b: b.to_string(),
+            min_rtt,
+        }
+    }
+
+    /// Place each of `machines` in a different region from `regions`.
+    pub fn one_per_region<S: ToString, R: ToString>(
+        machines: impl IntoIterator<Item = S>,
+        regions: impl IntoIterator<Item = R>,
+    ) -> Self {
+        Constraint::OnePerRegion {
+            machines: machines.into_iter().map(|s| s.to_string()).collect(),
+            regions: regions.into_iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    fn machines(&self) -> Vec<&String> {
+        match self {
+            Constraint::SameZone(ms) => ms.iter().collect(),
+            Constraint::Apart { a, b, .. } => vec![a, b],
+            Constraint::OnePerRegion { machines, .. } => machines.iter().collect(),
+        }
+    }
+}
+
+/// Traffic expected from one machine to another, declared with [`Planner::flow`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flow {
+    /// The sending machine.
+    pub from: String,
+    /// The receiving machine.
+    pub to: String,
+    /// How much data is sent, in gigabytes.
+    pub gigabytes: f64,
+}
+
+/// The estimated cost of a [`Flow`] that crosses regions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferCost {
+    /// The flow.
+    pub flow: Flow,
+    /// The region of the sending machine.
+    pub from_region: String,
+    /// The region of the receiving machine.
+    pub to_region: String,
+    /// The estimated cost in US dollars, or `None` if the price between the regions is unknown.
+    pub usd: Option<f64>,
+}
+
+impl std::fmt::Display for TransferCost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} -> {} ({} -> {}, {} GB)",
+            self.flow.from, self.flow.to, self.from_region, self.to_region, self.flow.gigabytes
+        )?;
+        match self.usd {
+            Some(usd) => write!(f, ": ~${:.2}", usd),
+            None => write!(f, ": unknown price"),
+        }
+    }
+}
+
+/// Where the planner decided to put a machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /// The region to launch the machine in.
+    pub region: String,
+    /// Machines with the same `zone_group` must be launched in the same availability zone.
+    ///
+    /// This is `None` for machines without a [`Constraint::SameZone`].
+    pub zone_group: Option<usize>,
+}
+
+/// Finds placements that satisfy a set of [`Constraint`]s.
+///
+/// See the [module documentation](self) for an example.
+pub struct Planner {
+    regions: Vec<String>,
+    constraints: Vec<Constraint>,
+    flows: Vec<Flow>,
+    rtt: Box<dyn Fn(&str, &str) -> Option<Duration> + Send + Sync>,
+    price: Box<dyn Fn(&str, &str) -> Option<f64> + Send + Sync>,
+}
+
+impl std::fmt::Debug for Planner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Planner")
+            .field("regions", &self.regions)
+            .field("constraints", &self.constraints)
+            .field("flows", &self.flows)
+            .finish()
+    }
+}
+
+impl Planner {
+    /// Plan placements across the candidate `regions`, in order of preference.
+    ///
+    /// Round-trip times come from [`latency::expected_rtt`](crate::latency::expected_rtt) unless
+    /// overridden with [`Planner::with_rtt`].
+    pub fn new<R: ToString>(regions: impl IntoIterator<Item = R>) -> Self {
+        Planner {
+            regions: regions.into_iter().map(|r| r.to_string()).collect(),
+            constraints: Vec::new(),
+            flows: Vec::new(),
+            rtt: Box::new(crate::latency::expected_rtt),
+            price: Box::new(expected_transfer_price),
+        }
+    }
+
+    /// Use `rtt` to look up the round-trip time between two regions.
+    pub fn with_rtt(
+        mut self,
+        rtt: impl Fn(&str, &str) -> Option<Duration> + Send + Sync + 'static,
+    ) -> Self {
+        self.rtt = Box::new(rtt);
+        self
+    }
+
+    /// Use `price` to look up the price, in US dollars per gigabyte, of sending data from one
+    /// region to another.
+    ///
+    /// Defaults to [`expected_transfer_price`].
+    pub fn with_transfer_price(
+        mut self,
+        price: impl Fn(&str, &str) -> Option<f64> + Send + Sync + 'static,
+    ) -> Self {
+        self.price = Box::new(price);
+        self
+    }
+
+    /// Add a constraint.
+    pub fn constrain(mut self, c: Constraint) -> Self {
+        self.constraints.push(c);
+        self
+    }
+
+    /// Declare that `from` is expected to send `gigabytes` of data to `to`.
+    ///
+    /// Flows do not change the placement. They are only used to estimate the cost of the traffic
+    /// that crosses regions (see [`Planner::transfer_costs`]).
+    pub fn flow(mut self, from: impl ToString, to: impl ToString, gigabytes: f64) -> Self {
+        self.flows.push(Flow {
+            from: from.to_string(),
+            to: to.to_string(),
+            gigabytes,
+        });
+        self
+    }
+
+    /// Estimate the cost of each declared flow that `plan` sends across regions.
+    ///
+    /// Flows whose machines are not both in `plan` are skipped, as are flows within a region.
+    pub fn transfer_costs(&self, plan: &HashMap<String, Placement>) -> Vec<TransferCost> {
+        self.flows
+            .iter()
+            .filter_map(|flow| {
+                let from = &plan.get(&flow.from)?.region;
+                let to = &plan.get(&flow.to)?.region;
+                if from == to {
+                    return None;
+                }
+                Some(TransferCost {
+                    flow: flow.clone(),
+                    from_region: from.clone(),
+                    to_region: to.clone(),
+                    usd: (self.price)(from, to).map(|p| p * flow.gigabytes),
+                })
+            })
+            .collect()
+    }
+
+    /// Find a placement for every machine named in a constraint.
+    ///
+    /// Candidates are tried in the order given to [`Planner::new`], so the first satisfying
+    /// assignment in that order is returned.
+    pub fn plan(&self) -> Result<HashMap<String, Placement>, Report> {
+        let mut machines: Vec<&String> = Vec::new();
+        for m in self.constraints.iter().flat_map(Constraint::machines) {
+            if !machines.contains(&m) {
+                machines.push(m);
+            }
+        }
+
+        let mut assignment: HashMap<&str, &str> = HashMap::new();
+        eyre::ensure!(
+            self.search(&machines, &mut assignment),
+            "no placement satisfies all constraints"
+        );
+
+        let groups = self.zone_groups();
+        let plan: HashMap<_, _> = assignment
+            .into_iter()
+            .map(|(m, r)| {
+                (
+                    m.to_string(),
+                    Placement {
+                        region: r.to_string(),
+                        zone_group: groups.get(m).copied(),
+                    },
+                )
+            })
+            .collect();
+
+        let costs = self.transfer_costs(&plan);
+        for cost in &costs {
+            tracing::warn!("cross-region traffic: {}", cost);
+        }
+        if costs.len() > 1 {
+            let total: f64 = costs.iter().filter_map(|c| c.usd).sum();
+            tracing::warn!(
+                "cross-region traffic is estimated to cost ~${:.2} in total",
+                total
+            );
+        }
+        Ok(plan)
+    }
+
+    fn search<'s>(
+        &'s self,
+        machines: &[&'s String],
+        assignment: &mut HashMap<&'s str, &'s str>,
+    ) -> bool {
+        let (m, rest) = match machines.split_first() {
+            Some(x) => x,
+            None => return true,
+        };
+
+        for r in self.candidates(m) {
+            assignment.insert(m, r);
+            if self.consistent(assignment) && self.search(rest, assignment) {
+                return true;
+            }
+            assignment.remove(m.as_str());
+        }
+        false
+    }
+
+    fn candidates<'s>(&'s self, m: &str) -> Vec<&'s str> {
+        let mut cs: Vec<&str> = self.regions.iter().map(String::as_str).collect();
+        for c in &self.constraints {
+            if let Constraint::OnePerRegion { machines, regions } = c {
+                if machines.iter().any(|x| x == m) {
+                    cs.retain(|r| regions.iter().any(|x| x == r));
+                }
+            }
+        }
+        cs
+    }
+
+    // checks all constraints whose machines have been assigned so far
+    fn consistent(&self, a: &HashMap<&str, &str>) -> bool {
+        self.constraints.iter().all(|c| match c {
+            Constraint::SameZone(ms) => {
+                let mut rs = ms.iter().filter_map(|m| a.get(m.as_str()));
+                match rs.next() {
+                    Some(first) => rs.all(|r| r == first),
+                    None => true,
+                }
+            }
+            Constraint::Apart {
+                a: x,
+                b: y,
+                min_rtt,
+            } => match (a.get(x.as_str()), a.get(y.as_str())) {
+                (Some(rx), Some(ry)) => {
+                    rx != ry && matches!((self.rtt)(rx, ry), Some(rtt) if rtt >= *min_rtt)
+                }
+                _ => true,
+            },
+            Constraint::OnePerRegion { machines, .. } => {
+                let rs: Vec<_> = machines.iter().filter_map(|m| a.get(m.as_str())).collect();
+                rs.iter().enumerate().all(|(i, r)| !rs[..i].contains(r))
+            }
+        })
+    }
+
+    // machines that share a SameZone constraint, directly or transitively, share a group
+    fn zone_groups(&self) -> HashMap<&str, usize> {
+        let mut groups: HashMap<&str, usize> = HashMap::new();
+        let mut next = 0;
+        for c in &self.constraints {
+            if let Constraint::SameZone(ms) = c {
+                let existing: Vec<usize> = ms
+                    .iter()
+                    .filter_map(|m| groups.get(m.as_str()).copied())
+                    .collect();
+                let g = existing.iter().copied().min().unwrap_or_else(|| {
+                    next += 1;
+                    next - 1
+                });
+                for v in groups.values_mut() {
+                    if existing.contains(v) {
+                        *v = g;
+                    }
+                }
+                for m in ms {
+                    groups.insert(m, g);
+                }
+            }
+        }
+        groups
+    }
+}
+
+// Approximate AWS prices, in US dollars per gigabyte, for data sent out of a region to another
+// AWS region. Regions not listed here have no known price.
+const TRANSFER_PRICES: &[(&str, f64)] = &[
+    ("us-east-1", 0.02),
+    ("us-east-2", 0.02),
+    ("us-west-1", 0.02),
+    ("us-west-2", 0.02),
+    ("ca-central-1", 0.02),
+    ("eu-central-1", 0.02),
+    ("eu-west-1", 0.02),
+    ("eu-west-2", 0.02),
+    ("eu-west-3", 0.02),
+    ("eu-north-1", 0.02),
+    ("eu-south-1", 0.02),
+    ("ap-northeast-1", 0.09),
+    ("ap-northeast-2", 0.08),
+    ("ap-northeast-3", 0.09),
+    ("ap-south-1", 0.086),
+    ("ap-southeast-1", 0.09),
+    ("ap-southeast-2", 0.098),
+    ("ap-east-1", 0.09),
+    ("me-south-1", 0.1105),
+    ("af-south-1", 0.147),
+    ("sa-east-1", 0.138),
+];
+
+/// The approximate price, in US dollars per gigabyte, of sending data from AWS region `from` to
+/// AWS region `to`.
+///
+/// Traffic within a region is treated as free, even though AWS bills traffic between
+/// availability zones. Returns `None` if the price for `from` is unknown. Prices change, so check
+/// the [EC2 pricing page](https://aws.amazon.com/ec2/pricing/on-demand/#Data_Transfer) before
+/// relying on them.
+pub fn expected_transfer_price(from: &str, to: &str) -> Option<f64> {
+    if from == to {
+        return Some(0.0);
+    }
+    // traffic between us-east-1 and us-east-2 is discounted
+    if matches!(
+        (from, to),
+        ("us-east-1", "us-east-2") | ("us-east-2", "us-east-1")
+    ) {
+        return Some(0.01);
+    }
+    TRANSFER_PRICES
+        .iter()
+        .find(|(r, _)| *r == from)
+        .map(|(_, p)| *p)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn one_per_region() {
+        let plan = Planner::new(vec!["a", "b", "c"])
+            .constrain(Constraint::one_per_region(vec!["x", "y"], vec!["b", "c"]))
+            .plan()
+            .unwrap();
+        assert_eq!(plan["x"].region, "b");
+        assert_eq!(plan["y"].region, "c");
+        assert_eq!(plan["x"].zone_group, None);
+    }
+
+    #[test]
+    fn zone_groups_merge() {
+        let plan = Planner::new(vec!["a"])
+            .constrain(Constraint::same_zone(vec!["x", "y"]))
+            .constrain(Constraint::same_zone(vec!["z"]))
+            .constrain(Constraint::same_zone(vec!["y", "z"]))
+            .plan()
+            .unwrap();
+        assert!(plan["x"].zone_group.is_some());
+        assert_eq!(plan["x"].zone_group, plan["z"].zone_group);
+    }
+
+    #[test]
+    fn builtin_rtts() {
+        let plan = Planner::new(vec!["us-east-1", "us-east-2", "eu-west-1"])
+            .constrain(Constraint::apart("x", "y", Duration::from_millis(50)))
+            .plan()
+            .unwrap();
+        assert_eq!(plan["x"].region, "us-east-1");
+        assert_eq!(plan["y"].region, "eu-west-1");
+    }
+
+    #[test]
+    fn transfer_costs() {
+        let planner = Planner::new(vec!["us-east-1", "ap-south-1", "mars-north-1"])
+            .constrain(Constraint::one_per_region(
+                vec!["x", "y", "z"],
+                vec!["us-east-1", "ap-south-1", "mars-north-1"],
+            ))
+            .constrain(Constraint::same_zone(vec!["x", "w"]))
+            .flow("x", "w", 1000.0)
+            .flow("x", "y", 100.0)
+            .flow("y", "x", 100.0)
+            .flow("z", "x", 1.0)
+            .flow("x", "unplaced", 1.0);
+        let plan = planner.plan().unwrap();
+        let costs = planner.transfer_costs(&plan);
+        assert_eq!(costs.len(), 3);
+        assert_eq!(costs[0].from_region, "us-east-1");
+        assert_eq!(costs[0].usd, Some(100.0 * 0.02));
+        assert_eq!(costs[1].usd, Some(100.0 * 0.086));
+        assert_eq!(costs[2].usd, None);
+    }
+
+    #[test]
+    fn unsatisfiable() {
+        assert!(Planner::new(vec!["a", "b"])
+            .constrain(Constraint::apart("x", "y", Duration::from_millis(1)))
+            .plan()
+            .is_err());
+        assert!(Planner::new(vec!["a", "b"])
+            .constrain(Constraint::one_per_region(
+                vec!["x", "y", "z"],
+                vec!["a", "b"]
+            ))
+            .plan()
+            .is_err());
+    }
+}
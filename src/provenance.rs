@@ -0,0 +1,71 @@
+//! Record the software environment of machines, for reproducibility.
+//!
+//! [`capture`] saves what is needed to state exactly which environment produced a set of results:
+//! the kernel, the OS release, the installed packages, the CPU model and flags, the commits
+//! deployed with `tsunami::deploy::Repository`, and, on EC2, the instance identity document
+//! (instance type, region, AMI). Each machine gets its own directory:
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! for vm in vms.values() {
+//!     // writes results/env/<nickname>/kernel, results/env/<nickname>/packages, ...
+//!     tsunami::provenance::capture(vm, "results/env").await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use color_eyre::{eyre::WrapErr, Report};
+use std::path::{Path, PathBuf};
+
+// The file each piece of the environment is saved to, and the command that produces it. Commands
+// that do not apply to a machine (e.g. rpm on Ubuntu) produce empty files.
+const RECORDS: &[(&str, &str)] = &[
+    ("kernel", "uname -a"),
+    ("os-release", "cat /etc/os-release"),
+    (
+        "packages",
+        "dpkg-query -W -f '${Package}\\t${Version}\\n' 2>/dev/null || rpm -qa 2>/dev/null",
+    ),
+    ("cpu", "lscpu"),
+    (
+        "cpu-flags",
+        "grep -m1 '^flags' /proc/cpuinfo | cut -d: -f2 | tr ' ' '\\n' | sed '/^$/d'",
+    ),
+    ("git", "cat .tsunami/git 2>/dev/null"),
+    (
+        "instance-identity.json",
+        "t=$(curl -sf -m 2 -X PUT -H 'X-aws-ec2-metadata-token-ttl-seconds: 60' \
+         http://169.254.169.254/latest/api/token); \
+         curl -sf -m 2 -H \"X-aws-ec2-metadata-token: $t\" \
+         http://169.254.169.254/latest/dynamic/instance-identity/document",
+    ),
+];
+
+/// Save the software environment of `m` under `dir/<nickname>`, and return that directory.
+///
+/// Existing files in the directory are overwritten.
+pub async fn capture(m: &crate::Machine<'_>, dir: impl AsRef<Path>) -> Result<PathBuf, Report> {
+    let dir = dir.as_ref().join(&m.nickname);
+    std::fs::create_dir_all(&dir)
+        .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+
+    for (file, cmd) in RECORDS {
+        let out = m
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(cmd)
+            .output()
+            .await
+            .wrap_err_with(|| format!("failed to record {}", file))?;
+        let path = dir.join(file);
+        std::fs::write(&path, &out.stdout)
+            .wrap_err_with(|| format!("failed to write {}", path.display()))?;
+    }
+
+    tracing::debug!(nickname = %m.nickname, dir = %dir.display(), "captured environment");
+    Ok(dir)
+}
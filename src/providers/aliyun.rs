@@ -0,0 +1,1560 @@
+//! Alibaba Cloud ECS backend for tsunami.
+//!
+//! Alibaba Cloud has more Asia-Pacific regions than the other providers, which makes it the
+//! closest cloud for many experimenters there.
+//!
+//! The provider talks to the [ECS
+//! API](https://www.alibabacloud.com/help/en/ecs/developer-reference/api-ecs-2014-05-26-overview)
+//! directly. It authenticates with an AccessKey pair, read from the `ALIBABA_CLOUD_ACCESS_KEY_ID`
+//! and `ALIBABA_CLOUD_ACCESS_KEY_SECRET` environment variables (the same ones the `aliyun` CLI
+//! and SDKs use), or set with [`Launcher::with_credentials`].
+//!
+//! Like the AWS provider, each launcher imports a temporary key pair and creates a temporary
+//! security group in every region it launches into. Instances go into the region's default VPC,
+//! so machines in the same region can reach each other on their [`crate::Machine::private_ip`]s.
+//! All resources are tagged `tsunami=<run id>`, and `terminate_all()` deletes them. *If your
+//! tsunami crashes or you forget to call `terminate_all()`, you must delete them yourself*, e.g.
+//! by filtering on the `tsunami` tag in the ECS console.
+//!
+//! Instances are pay-as-you-go by default. [`Setup::spot`] launches preemptible instances
+//! instead, which cost a fraction as much but may be reclaimed after their first hour.
+//!
+//! # Example
+//! ```rust,no_run
+//! use tsunami::providers::aliyun;
+//! use tsunami::Tsunami;
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     let mut l = aliyun::Launcher::default();
+//!     let m = aliyun::Setup::default()
+//!         .region(aliyun::Region::ApNortheast1)
+//!         .instance_type("ecs.c7.xlarge")
+//!         .spot(true);
+//!     l.spawn(tsunami::make_multiple(4, "worker", m), None).await?;
+//!     let vms = l.connect_all().await?;
+//!     for (name, vm) in &vms {
+//!         println!("{}: {}", name, vm.public_ip);
+//!     }
+//!     l.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use crate::providers::MachineState;
+use color_eyre::{
+    eyre::{self, eyre},
+    Help, Report,
+};
+use educe::Educe;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+/// An Alibaba Cloud region.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Region {
+    CnHangzhou,
+    CnShanghai,
+    CnBeijing,
+    CnShenzhen,
+    CnHongkong,
+    /// Singapore.
+    #[default]
+    ApSoutheast1,
+    /// Kuala Lumpur.
+    ApSoutheast3,
+    /// Jakarta.
+    ApSoutheast5,
+    /// Tokyo.
+    ApNortheast1,
+    /// Seoul.
+    ApNortheast2,
+    /// Silicon Valley.
+    UsWest1,
+    /// Virginia.
+    UsEast1,
+    /// Frankfurt.
+    EuCentral1,
+    /// London.
+    EuWest1,
+}
+
+impl Region {
+    /// Every region.
+    pub const ALL: &'static [Region] = &[
+        Region::CnHangzhou,
+        Region::CnShanghai,
+        Region::CnBeijing,
+        Region::CnShenzhen,
+        Region::CnHongkong,
+        Region::ApSoutheast1,
+        Region::ApSoutheast3,
+        Region::ApSoutheast5,
+        Region::ApNortheast1,
+        Region::ApNortheast2,
+        Region::UsWest1,
+        Region::UsEast1,
+        Region::EuCentral1,
+        Region::EuWest1,
+    ];
+}
+
+impl AsRef<str> for Region {
+    fn as_ref(&self) -> &str {
+        match self {
+            Region::CnHangzhou => "cn-hangzhou",
+            Region::CnShanghai => "cn-shanghai",
+            Region::CnBeijing => "cn-beijing",
+            Region::CnShenzhen => "cn-shenzhen",
+            Region::CnHongkong => "cn-hongkong",
+            Region::ApSoutheast1 => "ap-southeast-1",
+            Region::ApSoutheast3 => "ap-southeast-3",
+            Region::ApSoutheast5 => "ap-southeast-5",
+            Region::ApNortheast1 => "ap-northeast-1",
+            Region::ApNortheast2 => "ap-northeast-2",
+            Region::UsWest1 => "us-west-1",
+            Region::UsEast1 => "us-east-1",
+            Region::EuCentral1 => "eu-central-1",
+            Region::EuWest1 => "eu-west-1",
+        }
+    }
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_ref())
+    }
+}
+
+impl std::str::FromStr for Region {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Region::ALL
+            .iter()
+            .find(|r| r.as_ref() == s)
+            .copied()
+            .ok_or_else(|| eyre!("unknown alibaba cloud region {:?}", s))
+            .suggestion("Region ids look like ap-southeast-1; see `aliyun ecs DescribeRegions`")
+    }
+}
+
+/// A descriptor for a single ECS instance.
+///
+/// The default is a pay-as-you-go `ecs.g7.large` instance running Ubuntu 22.04 in Singapore,
+/// logged into as `root`, with up to 100 Mbit/s of outbound bandwidth billed by traffic.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct Setup {
+    region: Region,
+    zone: Option<String>,
+    instance_type: String,
+    image: Option<String>,
+    image_family: String,
+    image_spec: Option<crate::image::ImageSpec>,
+    spot: bool,
+    bandwidth: u32,
+    username: String,
+    os: Option<crate::OsFamily>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<
+        Arc<
+            dyn for<'r> Fn(
+                    &'r crate::Machine<'_>,
+                )
+                    -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >,
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Setup {
+            region: Region::default(),
+            zone: None,
+            instance_type: String::from("ecs.g7.large"),
+            image: None,
+            image_family: String::from("acs:ubuntu_22_04_x64"),
+            image_spec: None,
+            spot: false,
+            bandwidth: 100,
+            username: String::from("root"),
+            os: Some(crate::OsFamily::Ubuntu),
+            setup_fn: None,
+        }
+    }
+}
+
+impl super::MachineSetup for Setup {
+    type Region = Region;
+
+    fn region(&self) -> Self::Region {
+        self.region
+    }
+}
+
+impl Setup {
+    /// Launch the instance in `region`.
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = region;
+        self
+    }
+
+    /// Launch the instance in the zone `zone` of its region, such as `ap-southeast-1a`.
+    ///
+    /// By default, the instance goes into whichever zone the first vSwitch of the region's
+    /// default VPC is in. The zone must have a vSwitch in the default VPC.
+    pub fn zone(mut self, zone: impl ToString) -> Self {
+        self.zone = Some(zone.to_string());
+        self
+    }
+
+    /// Set the instance type, such as `ecs.c7.xlarge`.
+    ///
+    /// `aliyun ecs DescribeInstanceTypes` lists the available types.
+    pub fn instance_type(mut self, instance_type: impl ToString) -> Self {
+        self.instance_type = instance_type.to_string();
+        self
+    }
+
+    /// Set the image, by id, such as that of one of your custom images.
+    ///
+    /// Image ids are region-specific. This clears any previously set [`Setup::os`].
+    pub fn image(mut self, image: impl ToString) -> Self {
+        self.image = Some(image.to_string());
+        self.image_spec = None;
+        self.os = None;
+        self
+    }
+
+    /// Launch from the latest image of the image family `family`, such as
+    /// `acs:debian_12_x64`.
+    ///
+    /// This clears any previously set image and [`Setup::os`].
+    pub fn image_family(mut self, family: impl ToString) -> Self {
+        self.image_family = family.to_string();
+        self.image = None;
+        self.image_spec = None;
+        self.os = None;
+        self
+    }
+
+    /// Use the image described by `image`, which is resolved when the instance is launched.
+    ///
+    /// [`ImageSpec::CustomByName`](crate::image::ImageSpec::CustomByName) is the name of one of
+    /// your custom images. Images of a known operating system also set [`Setup::os`].
+    pub fn image_spec(mut self, image: crate::image::ImageSpec) -> Self {
+        self.os = image.os();
+        self.image_spec = Some(image);
+        self
+    }
+
+    /// Launch a preemptible (spot) instance instead of a pay-as-you-go one.
+    ///
+    /// Spot instances are billed at the current market price, which is usually much lower, but
+    /// may be reclaimed once they have run for an hour. Launching fails if the zone has no spot
+    /// capacity for the instance type.
+    pub fn spot(mut self, spot: bool) -> Self {
+        self.spot = spot;
+        self
+    }
+
+    /// Set the maximum outbound Internet bandwidth of the instance, in Mbit/s.
+    ///
+    /// The instance only gets a public IP address if this is not 0. Traffic is billed by volume.
+    pub fn internet_bandwidth(mut self, mbps: u32) -> Self {
+        self.bandwidth = mbps;
+        self
+    }
+
+    /// Declare which operating system family the image runs.
+    ///
+    /// Alibaba Cloud images are always logged into as `root`, so this does not change the
+    /// username. The family is available to setup functions as [`crate::Machine::os`].
+    pub fn os(mut self, os: crate::OsFamily) -> Self {
+        self.os = Some(os);
+        self
+    }
+
+    /// Set the username, for custom images that allow logging in as someone other than `root`.
+    pub fn username(mut self, username: impl ToString) -> Self {
+        self.username = username.to_string();
+        self
+    }
+
+    /// Specify instance setup.
+    ///
+    /// The provided callback, `setup`, is called once for every spawned instance of this type
+    /// with a handle to the instance. Use [`crate::Machine::ssh`] to issue commands on it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use tsunami::providers::aliyun::Setup;
+    /// let m = Setup::default().setup(|vm| {
+    ///     Box::pin(async move {
+    ///         vm.ssh
+    ///             .command("apt-get")
+    ///             .arg("update")
+    ///             .status()
+    ///             .await?;
+    ///         Ok(())
+    ///     })
+    /// });
+    /// ```
+    pub fn setup(
+        mut self,
+        setup: impl for<'r> Fn(
+                &'r crate::Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.setup_fn = Some(Arc::new(setup));
+        self
+    }
+
+    /// Check that the instance matches `expect` once it is up, before running the
+    /// [`setup`](Setup::setup) function.
+    ///
+    /// See the [`verify`](crate::verify) module for what happens when the check fails, and for
+    /// when to call this.
+    pub fn verify(mut self, expect: crate::verify::Expectations) -> Self {
+        self.setup_fn = Some(super::before_setup(
+            expect.into_setup_fn(),
+            self.setup_fn.take(),
+        ));
+        self
+    }
+}
+
+/// An error returned by the Alibaba Cloud API.
+///
+/// Errors from the Alibaba Cloud provider can be downcast to this type to find out what went
+/// wrong, e.g. to retry in another zone when a spot instance type is out of stock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ApiError {
+    /// The HTTP status of the response.
+    pub status: u16,
+    /// Alibaba Cloud's error code, such as `OperationDenied.NoStock` or `Throttling`.
+    pub code: String,
+    /// The description of the error.
+    pub message: String,
+    /// The id of the failed request, for support tickets.
+    pub request_id: Option<String>,
+}
+
+impl ApiError {
+    fn from_body(status: u16, body: &str) -> Self {
+        let v: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+        ApiError {
+            status,
+            code: v["Code"].as_str().unwrap_or("Unknown").to_string(),
+            message: v["Message"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| body.trim().to_string()),
+            request_id: v["RequestId"].as_str().map(String::from),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.code, self.status, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IpInfo {
+    public_ip: String,
+    private_ip: Option<String>,
+}
+
+// The addresses of an instance, once it has its public address.
+fn ip_info(instance: &serde_json::Value) -> Option<IpInfo> {
+    let public_ip = instance["PublicIpAddress"]["IpAddress"][0]
+        .as_str()
+        .or_else(|| instance["EipAddress"]["IpAddress"].as_str())
+        .filter(|ip| !ip.is_empty())?;
+    Some(IpInfo {
+        public_ip: public_ip.to_string(),
+        private_ip: instance["VpcAttributes"]["PrivateIpAddress"]["IpAddress"][0]
+            .as_str()
+            .map(String::from),
+    })
+}
+
+// The image an instance is launched from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Image {
+    Id(String),
+    Family(String),
+}
+
+// The image for the `ImageSpec`s that do not need a lookup.
+fn spec_image(spec: &crate::image::ImageSpec) -> Option<Image> {
+    use crate::image::ImageSpec;
+    Some(match spec {
+        ImageSpec::Ubuntu2004 => Image::Family(String::from("acs:ubuntu_20_04_x64")),
+        ImageSpec::Ubuntu2204 => Image::Family(String::from("acs:ubuntu_22_04_x64")),
+        ImageSpec::Debian11 => Image::Family(String::from("acs:debian_11_x64")),
+        ImageSpec::ProviderSpecific(id) => Image::Id(id.clone()),
+        ImageSpec::CustomByName(_) => return None,
+    })
+}
+
+// A client for the Alibaba Cloud RPC APIs.
+mod api {
+    use super::ApiError;
+    use color_eyre::{
+        eyre::{self, WrapErr},
+        Help, Report,
+    };
+    use educe::Educe;
+    use hmac::{Hmac, Mac, NewMac};
+    use serde_json::Value;
+    use sha2::{Digest, Sha256};
+    use std::time::{Duration, Instant};
+    use tracing::instrument;
+
+    // An Alibaba Cloud product, by its endpoint prefix and API version.
+    #[derive(Debug, Clone, Copy)]
+    pub(super) struct Product {
+        endpoint: &'static str,
+        version: &'static str,
+    }
+
+    pub(super) const ECS: Product = Product {
+        endpoint: "ecs",
+        version: "2014-05-26",
+    };
+
+    pub(super) const VPC: Product = Product {
+        endpoint: "vpc",
+        version: "2016-04-28",
+    };
+
+    #[derive(Clone, Default, Educe)]
+    #[educe(Debug)]
+    pub(super) struct Client {
+        http: reqwest::Client,
+        #[educe(Debug(ignore))]
+        credentials: Option<(String, String)>,
+    }
+
+    pub(super) fn is_code(e: &Report, prefix: &str) -> bool {
+        matches!(e.downcast_ref::<ApiError>(), Some(e) if e.code.starts_with(prefix))
+    }
+
+    // Encodes `s` as RFC 3986 requires, which the signature depends on.
+    pub(super) fn percent_encode(s: &str) -> String {
+        let mut out = String::with_capacity(s.len());
+        for b in s.bytes() {
+            match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    out.push(b as char)
+                }
+                b => out.push_str(&format!("%{:02X}", b)),
+            }
+        }
+        out
+    }
+
+    // The query string of `params`, sorted by name as the signature requires.
+    pub(super) fn canonical_query(params: &[(&str, String)]) -> String {
+        let mut pairs: Vec<_> = params
+            .iter()
+            .map(|(k, v)| (percent_encode(k), percent_encode(v)))
+            .collect();
+        pairs.sort();
+        pairs
+            .into_iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    pub(super) fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub(super) fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_varkey(key).expect("hmac takes keys of any length");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    // The `Authorization` header of a bodyless POST with the query string `query`, signed with
+    // ACS3-HMAC-SHA256. `headers` are the signed headers, lowercase and sorted by name.
+    //
+    // See https://www.alibabacloud.com/help/en/sdk/product-overview/v3-request-structure-and-signature
+    pub(super) fn authorization(
+        id: &str,
+        secret: &str,
+        query: &str,
+        headers: &[(&str, String)],
+    ) -> String {
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "POST\n/\n{}\n{}\n{}\n{}",
+            query,
+            canonical_headers,
+            signed_headers,
+            hex(&Sha256::digest(b""))
+        );
+        let to_sign = format!(
+            "ACS3-HMAC-SHA256\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        format!(
+            "ACS3-HMAC-SHA256 Credential={},SignedHeaders={},Signature={}",
+            id,
+            signed_headers,
+            hex(&hmac_sha256(secret.as_bytes(), to_sign.as_bytes()))
+        )
+    }
+
+    impl Client {
+        pub(super) fn with_credentials(id: String, secret: String) -> Self {
+            Client {
+                http: Default::default(),
+                credentials: Some((id, secret)),
+            }
+        }
+
+        fn credentials(&self) -> Result<(String, String), Report> {
+            if let Some(ref c) = self.credentials {
+                return Ok(c.clone());
+            }
+            match (
+                std::env::var("ALIBABA_CLOUD_ACCESS_KEY_ID"),
+                std::env::var("ALIBABA_CLOUD_ACCESS_KEY_SECRET"),
+            ) {
+                (Ok(id), Ok(secret)) => Ok((id, secret)),
+                _ => Err(eyre::eyre!("no Alibaba Cloud AccessKey")).suggestion(
+                    "Set ALIBABA_CLOUD_ACCESS_KEY_ID and ALIBABA_CLOUD_ACCESS_KEY_SECRET",
+                ),
+            }
+        }
+
+        // Calls `action` of `product` in `region`.
+        #[instrument(level = "trace", skip(self, params))]
+        pub(super) async fn call(
+            &self,
+            product: Product,
+            region: &str,
+            action: &str,
+            params: &[(&str, String)],
+        ) -> Result<Value, Report> {
+            let (id, secret) = self.credentials()?;
+            let host = format!("{}.{}.aliyuncs.com", product.endpoint, region);
+            let query = canonical_query(params);
+            let headers = [
+                ("host", host.clone()),
+                ("x-acs-action", action.to_string()),
+                ("x-acs-content-sha256", hex(&Sha256::digest(b""))),
+                (
+                    "x-acs-date",
+                    super::super::iso8601(std::time::SystemTime::now()),
+                ),
+                (
+                    "x-acs-signature-nonce",
+                    super::super::rand_name_sep("nonce", "-"),
+                ),
+                ("x-acs-version", product.version.to_string()),
+            ];
+            let mut req = self
+                .http
+                .post(format!("https://{}/?{}", host, query))
+                .header(
+                    "authorization",
+                    authorization(&id, &secret, &query, &headers),
+                );
+            // reqwest sets the host header itself.
+            for (k, v) in &headers[1..] {
+                req = req.header(*k, v);
+            }
+            let resp = req
+                .send()
+                .await
+                .wrap_err("failed to reach the Alibaba Cloud API")?;
+            let status = resp.status().as_u16();
+            let body = resp.text().await.unwrap_or_default();
+            eyre::ensure!(
+                (200..300).contains(&status),
+                ApiError::from_body(status, &body)
+            );
+            serde_json::from_str(&body).wrap_err("malformed Alibaba Cloud API response")
+        }
+
+        /// The id and address range of the default VPC of `region`.
+        pub(super) async fn default_vpc(&self, region: &str) -> Result<(String, String), Report> {
+            let vpcs = self
+                .call(
+                    VPC,
+                    region,
+                    "DescribeVpcs",
+                    &[
+                        ("RegionId", region.to_string()),
+                        ("IsDefault", String::from("true")),
+                    ],
+                )
+                .await
+                .wrap_err("failed to list vpcs")?;
+            let vpc = &vpcs["Vpcs"]["Vpc"][0];
+            match (vpc["VpcId"].as_str(), vpc["CidrBlock"].as_str()) {
+                (Some(id), Some(cidr)) => Ok((id.to_string(), cidr.to_string())),
+                _ => Err(eyre::eyre!("{} has no default vpc", region))
+                    .suggestion("Create a default VPC in the VPC console"),
+            }
+        }
+
+        /// A vSwitch of `vpc`, in `zone` if given.
+        pub(super) async fn vswitch(
+            &self,
+            region: &str,
+            vpc: &str,
+            zone: Option<&str>,
+        ) -> Result<String, Report> {
+            let mut params = vec![
+                ("RegionId", region.to_string()),
+                ("VpcId", vpc.to_string()),
+                ("PageSize", String::from("50")),
+            ];
+            if let Some(zone) = zone {
+                params.push(("ZoneId", zone.to_string()));
+            }
+            let vswitches = self
+                .call(VPC, region, "DescribeVSwitches", &params)
+                .await
+                .wrap_err("failed to list vswitches")?;
+            vswitches["VSwitches"]["VSwitch"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|v| v["Status"].as_str() == Some("Available"))
+                .filter_map(|v| Some((v["ZoneId"].as_str()?, v["VSwitchId"].as_str()?)))
+                .min()
+                .map(|(_, id)| id.to_string())
+                .ok_or_else(|| eyre::eyre!("no vswitch in {} of {}", zone.unwrap_or(region), vpc))
+                .suggestion("Create a vSwitch for the zone in the default VPC")
+        }
+
+        /// Import `public_key` as the key pair `name`.
+        pub(super) async fn import_key_pair(
+            &self,
+            region: &str,
+            name: &str,
+            public_key: &str,
+            run_id: &str,
+        ) -> Result<(), Report> {
+            self.call(
+                ECS,
+                region,
+                "ImportKeyPair",
+                &[
+                    ("RegionId", region.to_string()),
+                    ("KeyPairName", name.to_string()),
+                    ("PublicKeyBody", public_key.trim().to_string()),
+                    ("Tag.1.Key", String::from("tsunami")),
+                    ("Tag.1.Value", run_id.to_string()),
+                ],
+            )
+            .await
+            .wrap_err("failed to import key pair")
+            .map(drop)
+        }
+
+        /// Create a security group in `vpc`, and return its id.
+        pub(super) async fn create_security_group(
+            &self,
+            region: &str,
+            vpc: &str,
+            name: &str,
+            run_id: &str,
+        ) -> Result<String, Report> {
+            let sg = self
+                .call(
+                    ECS,
+                    region,
+                    "CreateSecurityGroup",
+                    &[
+                        ("RegionId", region.to_string()),
+                        ("VpcId", vpc.to_string()),
+                        ("SecurityGroupName", name.to_string()),
+                        (
+                            "Description",
+                            String::from("temporary tsunami security group"),
+                        ),
+                        ("Tag.1.Key", String::from("tsunami")),
+                        ("Tag.1.Value", run_id.to_string()),
+                    ],
+                )
+                .await
+                .wrap_err("failed to create security group")?;
+            sg["SecurityGroupId"]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| eyre::eyre!("security group has no id"))
+        }
+
+        /// Allow inbound `protocol` traffic on `ports` (such as `22/22`, or `-1/-1` for all) from
+        /// `cidr` into the security group `sg`.
+        pub(super) async fn authorize(
+            &self,
+            region: &str,
+            sg: &str,
+            protocol: &str,
+            ports: &str,
+            cidr: &str,
+        ) -> Result<(), Report> {
+            self.call(
+                ECS,
+                region,
+                "AuthorizeSecurityGroup",
+                &[
+                    ("RegionId", region.to_string()),
+                    ("SecurityGroupId", sg.to_string()),
+                    ("IpProtocol", protocol.to_string()),
+                    ("PortRange", ports.to_string()),
+                    ("SourceCidrIp", cidr.to_string()),
+                ],
+            )
+            .await
+            .wrap_err_with(|| format!("failed to allow {} {} from {}", protocol, ports, cidr))
+            .map(drop)
+        }
+
+        /// The id of the newest custom image named `name`.
+        pub(super) async fn find_image(&self, region: &str, name: &str) -> Result<String, Report> {
+            let images = self
+                .call(
+                    ECS,
+                    region,
+                    "DescribeImages",
+                    &[
+                        ("RegionId", region.to_string()),
+                        ("ImageOwnerAlias", String::from("self")),
+                        ("ImageName", name.to_string()),
+                    ],
+                )
+                .await
+                .wrap_err("failed to list images")?;
+            images["Images"]["Image"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|i| Some((i["CreationTime"].as_str()?, i["ImageId"].as_str()?)))
+                .max()
+                .map(|(_, id)| id.to_string())
+                .ok_or_else(|| eyre::eyre!("no image named {} in {}", name, region))
+        }
+
+        /// Launch one instance with the `RunInstances` parameters `params`, and return its id.
+        #[instrument(level = "trace", skip(self))]
+        pub(super) async fn run_instance(
+            &self,
+            region: &str,
+            params: &[(&str, String)],
+        ) -> Result<String, Report> {
+            let res = self
+                .call(ECS, region, "RunInstances", params)
+                .await
+                .wrap_err("failed to launch instance")?;
+            res["InstanceIdSets"]["InstanceIdSet"][0]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| eyre::eyre!("launched instance has no id"))
+        }
+
+        /// The instances in `ids` that still exist.
+        pub(super) async fn instances(
+            &self,
+            region: &str,
+            ids: &[&str],
+        ) -> Result<Vec<Value>, Report> {
+            let mut instances = Vec::with_capacity(ids.len());
+            // at most 100 ids per request
+            for ids in ids.chunks(100) {
+                let res = self
+                    .call(
+                        ECS,
+                        region,
+                        "DescribeInstances",
+                        &[
+                            ("RegionId", region.to_string()),
+                            ("InstanceIds", serde_json::to_string(ids)?),
+                            ("PageSize", String::from("100")),
+                        ],
+                    )
+                    .await
+                    .wrap_err("failed to describe instances")?;
+                instances.extend(
+                    res["Instances"]["Instance"]
+                        .as_array()
+                        .cloned()
+                        .unwrap_or_default(),
+                );
+            }
+            Ok(instances)
+        }
+
+        /// Release the instances in `ids`, even if they are running. Instances that do not exist
+        /// are already released.
+        pub(super) async fn delete_instances(
+            &self,
+            region: &str,
+            ids: &[&str],
+        ) -> Result<(), Report> {
+            for ids in ids.chunks(100) {
+                let mut params = vec![
+                    ("RegionId", region.to_string()),
+                    ("Force", String::from("true")),
+                ];
+                let keys: Vec<_> = (1..=ids.len())
+                    .map(|i| format!("InstanceId.{}", i))
+                    .collect();
+                params.extend(
+                    keys.iter()
+                        .map(String::as_str)
+                        .zip(ids.iter().map(|id| id.to_string())),
+                );
+                match self.call(ECS, region, "DeleteInstances", &params).await {
+                    Err(e) if is_code(&e, "InvalidInstanceId.NotFound") => {}
+                    r => {
+                        r.wrap_err("failed to delete instances")?;
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Wait until none of `ids` exist any more, or `max_wait` elapses.
+        pub(super) async fn wait_deleted(
+            &self,
+            region: &str,
+            ids: &[&str],
+            max_wait: Duration,
+        ) -> Result<(), Report> {
+            let start = Instant::now();
+            loop {
+                let left = self.instances(region, ids).await?;
+                if left.is_empty() {
+                    return Ok(());
+                }
+                eyre::ensure!(
+                    start.elapsed() <= max_wait,
+                    "{} instance(s) in {} were not released within {:?}",
+                    left.len(),
+                    region,
+                    max_wait
+                );
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+
+        /// Delete the security group `sg`. Released instances keep it in use for a little while,
+        /// so this retries for up to two minutes.
+        pub(super) async fn delete_security_group(
+            &self,
+            region: &str,
+            sg: &str,
+        ) -> Result<(), Report> {
+            let start = Instant::now();
+            loop {
+                match self
+                    .call(
+                        ECS,
+                        region,
+                        "DeleteSecurityGroup",
+                        &[
+                            ("RegionId", region.to_string()),
+                            ("SecurityGroupId", sg.to_string()),
+                        ],
+                    )
+                    .await
+                {
+                    Ok(_) => return Ok(()),
+                    Err(e) if is_code(&e, "InvalidSecurityGroupId.NotFound") => return Ok(()),
+                    Err(e)
+                        if is_code(&e, "DependencyViolation")
+                            && start.elapsed() < Duration::from_secs(120) =>
+                    {
+                        tracing::trace!("security group still in use");
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                    Err(e) => return Err(e.wrap_err("failed to delete security group")),
+                }
+            }
+        }
+
+        /// Delete the key pair `name`.
+        pub(super) async fn delete_key_pair(&self, region: &str, name: &str) -> Result<(), Report> {
+            self.call(
+                ECS,
+                region,
+                "DeleteKeyPairs",
+                &[
+                    ("RegionId", region.to_string()),
+                    ("KeyPairNames", serde_json::to_string(&[name])?),
+                ],
+            )
+            .await
+            .wrap_err("failed to delete key pair")
+            .map(drop)
+        }
+    }
+}
+
+// What a launcher has created in one region.
+#[derive(Debug, Default)]
+struct RegionResources {
+    vpc: Option<(String, String)>,
+    key_pair: bool,
+    security_group: Option<String>,
+}
+
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub(super) struct Descriptor {
+    name: String,
+    region: Region,
+    id: String,
+    username: String,
+    os: Option<crate::OsFamily>,
+    ip: IpInfo,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<super::SetupFn>,
+}
+
+impl Descriptor {
+    fn machine<'l>(&self) -> crate::MachineDescriptor<'l> {
+        crate::MachineDescriptor {
+            nickname: self.name.clone(),
+            public_dns: None,
+            public_ip: self.ip.public_ip.clone(),
+            private_ip: self.ip.private_ip.clone(),
+            os: self.os,
+            proxy_command: None,
+            check_host_key: true,
+            _tsunami: Default::default(),
+        }
+    }
+}
+
+/// Launcher type for Alibaba Cloud ECS.
+///
+/// See the [module documentation](self) for how to authenticate. Machines are launched
+/// concurrently within each region, and their setup functions run in parallel.
+#[derive(Debug, Default)]
+pub struct Launcher {
+    client: api::Client,
+    run_id: String,
+    key: Option<(tempfile::TempDir, std::path::PathBuf, String)>,
+    use_open_ports: bool,
+    all_or_nothing: bool,
+    regions: HashMap<Region, RegionResources>,
+    machines: Vec<Descriptor>,
+}
+
+impl Launcher {
+    /// Use the AccessKey pair `id` and `secret` instead of the one in the environment.
+    pub fn with_credentials(id: impl ToString, secret: impl ToString) -> Self {
+        Launcher {
+            client: api::Client::with_credentials(id.to_string(), secret.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// The machines spawned on this launcher will have ports open to the public Internet.
+    ///
+    /// By default, only SSH is open to the Internet, and everything else only to the VPC.
+    pub fn open_ports(&mut self) -> &mut Self {
+        self.use_open_ports = true;
+        self
+    }
+
+    /// Either launch every machine of a `launch` or `spawn`, or none of them.
+    ///
+    /// With `all_or_nothing(true)`, a failed launch releases every instance it created, in all
+    /// regions, before returning the error. By default, the instances that came up stay running.
+    /// Instances from earlier, successful launches are not affected.
+    pub fn all_or_nothing(&mut self, enabled: bool) -> &mut Self {
+        self.all_or_nothing = enabled;
+        self
+    }
+
+    /// The path to the private key used to log into the machines.
+    pub fn private_key_path(&self) -> Option<&std::path::Path> {
+        self.key.as_ref().map(|(_, k, _)| k.as_path())
+    }
+
+    // Imports the key pair and creates the security group in `region`, unless that has been
+    // done. Whatever is created is recorded right away, so that `terminate_all` cleans it up even
+    // if a later step fails.
+    #[instrument(level = "debug", skip(self))]
+    async fn prepare(&mut self, region: Region) -> Result<(), Report> {
+        if self.run_id.is_empty() {
+            self.run_id = super::rand_name_sep("run", "-").to_lowercase();
+        }
+        if self.key.is_none() {
+            self.key = Some(super::generate_key().await?);
+        }
+        let public_key = &self.key.as_ref().unwrap().2;
+        let client = &self.client;
+        let run_id = &self.run_id;
+        let r = self.regions.entry(region).or_default();
+        let region = region.as_ref();
+
+        let (vpc, cidr) = match r.vpc {
+            Some(ref vpc) => vpc.clone(),
+            None => {
+                let vpc = client.default_vpc(region).await?;
+                r.vpc = Some(vpc.clone());
+                vpc
+            }
+        };
+        if !r.key_pair {
+            client
+                .import_key_pair(region, run_id, public_key, run_id)
+                .await?;
+            r.key_pair = true;
+        }
+        if r.security_group.is_none() {
+            let sg = client
+                .create_security_group(region, &vpc, run_id, run_id)
+                .await?;
+            r.security_group = Some(sg.clone());
+            if self.use_open_ports {
+                client
+                    .authorize(region, &sg, "all", "-1/-1", "0.0.0.0/0")
+                    .await?;
+            } else {
+                client
+                    .authorize(region, &sg, "tcp", "22/22", "0.0.0.0/0")
+                    .await?;
+                client.authorize(region, &sg, "all", "-1/-1", &cidr).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Resolves the image of `s`.
+    async fn image(&self, s: &Setup) -> Result<Image, Report> {
+        Ok(match (&s.image_spec, &s.image) {
+            (Some(spec), _) => match spec_image(spec) {
+                Some(image) => image,
+                None => match spec {
+                    crate::image::ImageSpec::CustomByName(name) => {
+                        Image::Id(self.client.find_image(s.region.as_ref(), name).await?)
+                    }
+                    _ => eyre::bail!("unsupported image {}", spec),
+                },
+            },
+            (None, Some(id)) => Image::Id(id.clone()),
+            (None, None) => Image::Family(s.image_family.clone()),
+        })
+    }
+
+    // The parameters of the `RunInstances` request that launches `s` as `name`.
+    fn run_params(
+        &self,
+        name: &str,
+        s: &Setup,
+        image: &Image,
+        vswitch: &str,
+    ) -> Vec<(&'static str, String)> {
+        let sg = self
+            .regions
+            .get(&s.region)
+            .and_then(|r| r.security_group.clone())
+            .unwrap_or_default();
+        let mut params = vec![
+            ("RegionId", s.region.to_string()),
+            ("InstanceType", s.instance_type.clone()),
+            ("VSwitchId", vswitch.to_string()),
+            ("SecurityGroupId", sg),
+            ("KeyPairName", self.run_id.clone()),
+            // instance names must be valid hostnames
+            (
+                "InstanceName",
+                super::rand_name_sep("vm", "-").to_lowercase(),
+            ),
+            ("InstanceChargeType", String::from("PostPaid")),
+            (
+                "SpotStrategy",
+                String::from(if s.spot { "SpotAsPriceGo" } else { "NoSpot" }),
+            ),
+            ("InternetChargeType", String::from("PayByTraffic")),
+            ("InternetMaxBandwidthOut", s.bandwidth.to_string()),
+            ("Amount", String::from("1")),
+            ("Tag.1.Key", String::from("tsunami")),
+            ("Tag.1.Value", self.run_id.clone()),
+            ("Tag.2.Key", String::from("tsunami:nickname")),
+            ("Tag.2.Value", name.to_string()),
+        ];
+        params.push(match image {
+            Image::Id(id) => ("ImageId", id.clone()),
+            Image::Family(family) => ("ImageFamily", family.clone()),
+        });
+        params
+    }
+
+    // Polls the instance every two seconds until it is running, or `max_wait` (if not `None`)
+    // elapses.
+    #[instrument(level = "trace", skip(self, max_wait))]
+    async fn wait_running(
+        &self,
+        region: Region,
+        id: &str,
+        max_wait: Option<Duration>,
+    ) -> Result<IpInfo, Report> {
+        let start = Instant::now();
+        loop {
+            let instances = self.client.instances(region.as_ref(), &[id]).await?;
+            // new instances take a moment to show up.
+            match instances.first().map(|i| (i, i["Status"].as_str())) {
+                Some((i, Some("Running"))) => {
+                    return ip_info(i)
+                        .ok_or_else(|| eyre!("instance has no public ip"))
+                        .suggestion(
+                            "Give the instance Internet bandwidth with Setup::internet_bandwidth",
+                        )
+                }
+                None | Some((_, Some("Pending"))) | Some((_, Some("Starting"))) => {}
+                Some((_, s)) => eyre::bail!("instance is {}", s.unwrap_or("in an unknown state")),
+            }
+            if let Some(wait_limit) = max_wait {
+                eyre::ensure!(start.elapsed() <= wait_limit, "wait limit reached");
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+    }
+
+    // Launches one instance, and returns it even if it did not come up, so that it can be
+    // released later.
+    #[instrument(level = "debug", skip(self, s, max_wait))]
+    async fn start(
+        &self,
+        name: &str,
+        s: &Setup,
+        image: &Image,
+        vswitch: &str,
+        max_wait: Option<Duration>,
+    ) -> (Option<Descriptor>, Result<(), Report>) {
+        super::report_progress(name, MachineState::Booting);
+        let params = self.run_params(name, s, image, vswitch);
+        let id = match self.client.run_instance(s.region.as_ref(), &params).await {
+            Ok(id) => id,
+            Err(e) => return (None, Err(e)),
+        };
+
+        let mut desc = Descriptor {
+            name: name.to_string(),
+            region: s.region,
+            id,
+            username: s.username.clone(),
+            os: s.os,
+            setup_fn: s.setup_fn.clone(),
+            ip: IpInfo {
+                public_ip: String::new(),
+                private_ip: None,
+            },
+        };
+        let res = async {
+            desc.ip = self.wait_running(s.region, &desc.id, max_wait).await?;
+            super::set_up_when_reachable(
+                name,
+                desc.machine(),
+                &desc.username,
+                self.private_key_path(),
+                s.setup_fn.as_ref(),
+                max_wait,
+            )
+            .await
+        }
+        .await;
+        (Some(desc), res)
+    }
+}
+
+impl super::EachMachine for Launcher {
+    type Machine = Descriptor;
+
+    fn machines(&self) -> &[Descriptor] {
+        &self.machines
+    }
+
+    fn machines_mut(&mut self) -> &mut Vec<Descriptor> {
+        &mut self.machines
+    }
+
+    fn nickname(d: &Descriptor) -> &str {
+        &d.name
+    }
+
+    fn ssh<'l>(
+        &'l self,
+        d: &'l Descriptor,
+    ) -> (
+        crate::MachineDescriptor<'l>,
+        &'l str,
+        Option<&'l std::path::Path>,
+    ) {
+        (d.machine(), &d.username, self.private_key_path())
+    }
+
+    fn rolls_back(&self) -> bool {
+        self.all_or_nothing
+    }
+
+    // Releases the instances of `gone`.
+    fn delete<'l>(
+        &'l self,
+        gone: Vec<Descriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(async move {
+            for region in Region::ALL {
+                let ids: Vec<_> = gone
+                    .iter()
+                    .filter(|d| d.region == *region)
+                    .map(|d| d.id.as_str())
+                    .collect();
+                if !ids.is_empty() {
+                    self.client.delete_instances(region.as_ref(), &ids).await?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+impl super::Launcher for Launcher {
+    type MachineDescriptor = Setup;
+
+    #[instrument(level = "debug", skip(self))]
+    fn launch<'l>(
+        &'l mut self,
+        l: super::LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(
+            async move {
+                self.prepare(l.region).await?;
+                let (vpc, _) = self.regions[&l.region].vpc.clone().unwrap();
+
+                let mut vswitches: HashMap<Option<String>, String> = HashMap::new();
+                let mut placements = Vec::with_capacity(l.machines.len());
+                for (_, s) in &l.machines {
+                    let image = self.image(s).await?;
+                    let vswitch = match vswitches.get(&s.zone) {
+                        Some(v) => v.clone(),
+                        None => {
+                            let v = self
+                                .client
+                                .vswitch(l.region.as_ref(), &vpc, s.zone.as_deref())
+                                .await?;
+                            vswitches.insert(s.zone.clone(), v.clone());
+                            v
+                        }
+                    };
+                    placements.push((image, vswitch));
+                }
+
+                let this = &*self;
+                let started =
+                    futures_util::future::join_all(l.machines.iter().zip(&placements).map(
+                        |((name, s), (image, vswitch))| {
+                            let machine_span = tracing::debug_span!("machine", %name);
+                            this.start(name, s, image, vswitch, l.max_wait)
+                                .instrument(machine_span)
+                        },
+                    ))
+                    .await;
+
+                let names = l.machines.into_iter().map(|(name, _)| name);
+                super::keep_started(self, names, started).await
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self, max_wait))]
+    fn spawn<'l, I>(
+        &'l mut self,
+        descriptors: I,
+        max_wait: Option<std::time::Duration>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>>
+    where
+        I: IntoIterator<Item = (String, Self::MachineDescriptor)> + Send + 'static,
+        I: std::fmt::Debug,
+        I::IntoIter: Send,
+    {
+        Box::pin(super::spawn_each(self, descriptors, max_wait).in_current_span())
+    }
+
+    #[instrument(level = "debug")]
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        Box::pin(super::connect_each(self).in_current_span())
+    }
+
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        Ok(super::describe_each(self))
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        Ok(self
+            .machines
+            .iter()
+            .filter_map(|d| Some((d.name.clone(), d.setup_fn.clone()?)))
+            .collect())
+    }
+
+    #[instrument(level = "debug")]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<Box<dyn Future<Output = Result<HashMap<String, MachineState>, Report>> + Send + 'l>>
+    {
+        Box::pin(
+            async move {
+                let mut statuses = HashMap::new();
+                for region in self.regions.keys() {
+                    let ids: Vec<_> = self
+                        .machines
+                        .iter()
+                        .filter(|d| d.region == *region)
+                        .map(|d| d.id.as_str())
+                        .collect();
+                    if ids.is_empty() {
+                        continue;
+                    }
+                    for i in self.client.instances(region.as_ref(), &ids).await? {
+                        if let (Some(id), Some(status)) =
+                            (i["InstanceId"].as_str(), i["Status"].as_str())
+                        {
+                            statuses.insert(id.to_string(), status.to_string());
+                        }
+                    }
+                }
+
+                let statuses = &statuses;
+                Ok(
+                    futures_util::future::join_all(self.machines.iter().map(|desc| async move {
+                        let state = match statuses.get(&desc.id).map(String::as_str) {
+                            Some("Running") => {
+                                super::ssh_state(
+                                    &desc.ip.public_ip,
+                                    &desc.username,
+                                    self.private_key_path(),
+                                    22,
+                                    desc.machine().check_host_key,
+                                )
+                                .await
+                            }
+                            Some("Pending") | Some("Starting") => MachineState::Booting,
+                            // stopping, stopped, or released
+                            _ => MachineState::Terminated,
+                        };
+                        (desc.name.clone(), state)
+                    }))
+                    .await
+                    .into_iter()
+                    .collect(),
+                )
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug")]
+    fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        Box::pin(
+            async move {
+                for (region, r) in &self.regions {
+                    let region = region.as_ref();
+                    let ids: Vec<_> = self
+                        .machines
+                        .iter()
+                        .filter(|d| d.region.as_ref() == region)
+                        .map(|d| d.id.as_str())
+                        .collect();
+                    if !ids.is_empty() {
+                        tracing::info!(%region, "releasing instances");
+                        self.client.delete_instances(region, &ids).await?;
+                        self.client
+                            .wait_deleted(region, &ids, Duration::from_secs(5 * 60))
+                            .await?;
+                    }
+                    // security groups and keys can only go once no instance uses them
+                    if let Some(ref sg) = r.security_group {
+                        self.client.delete_security_group(region, sg).await?;
+                    }
+                    if r.key_pair {
+                        self.client.delete_key_pair(region, &self.run_id).await?;
+                    }
+                }
+                Ok(())
+            }
+            .in_current_span(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn regions() {
+        for r in Region::ALL {
+            assert_eq!(r.to_string().parse::<Region>().unwrap(), *r);
+        }
+        assert!("singapore".parse::<Region>().is_err());
+    }
+
+    #[test]
+    fn signing() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            api::hex(&api::hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(api::percent_encode("a b*~/"), "a%20b%2A~%2F");
+        assert_eq!(
+            api::canonical_query(&[
+                ("RegionId", String::from("cn-hangzhou")),
+                ("InstanceIds", String::from(r#"["i-1"]"#)),
+            ]),
+            "InstanceIds=%5B%22i-1%22%5D&RegionId=cn-hangzhou"
+        );
+
+        let headers = [
+            ("host", String::from("ecs.cn-hangzhou.aliyuncs.com")),
+            ("x-acs-action", String::from("DescribeInstances")),
+        ];
+        let auth = api::authorization("id", "secret", "RegionId=cn-hangzhou", &headers);
+        assert!(auth.starts_with(
+            "ACS3-HMAC-SHA256 Credential=id,SignedHeaders=host;x-acs-action,Signature="
+        ));
+        // the signature covers the query
+        assert_ne!(
+            auth,
+            api::authorization("id", "secret", "RegionId=cn-beijing", &headers)
+        );
+    }
+
+    #[test]
+    fn responses() {
+        // a trimmed `DescribeInstances` instance
+        let instance: serde_json::Value = serde_json::from_str(
+            r#"{
+            "InstanceId": "i-bp67acfmxazb4p****",
+            "Status": "Running",
+            "PublicIpAddress": { "IpAddress": ["47.98.1.2"] },
+            "EipAddress": { "IpAddress": "", "AllocationId": "" },
+            "VpcAttributes": { "PrivateIpAddress": { "IpAddress": ["172.16.0.5"] } }
+        }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ip_info(&instance),
+            Some(IpInfo {
+                public_ip: String::from("47.98.1.2"),
+                private_ip: Some(String::from("172.16.0.5")),
+            })
+        );
+        let mut private = instance.clone();
+        private["PublicIpAddress"]["IpAddress"] = serde_json::json!([]);
+        assert_eq!(ip_info(&private), None);
+
+        let e = ApiError::from_body(
+            403,
+            r#"{"RequestId": "E1B2", "Code": "OperationDenied.NoStock", "Message": "sold out"}"#,
+        );
+        assert_eq!(e.code, "OperationDenied.NoStock");
+        assert_eq!(e.request_id.as_deref(), Some("E1B2"));
+        assert_eq!(
+            ApiError::from_body(502, "bad gateway").message,
+            "bad gateway"
+        );
+    }
+
+    #[test]
+    fn launch_requests() {
+        use crate::image::ImageSpec;
+        assert_eq!(
+            spec_image(&ImageSpec::Debian11),
+            Some(Image::Family(String::from("acs:debian_11_x64")))
+        );
+        assert_eq!(
+            spec_image(&ImageSpec::CustomByName(String::from("golden"))),
+            None
+        );
+
+        let mut l = Launcher {
+            run_id: String::from("tsunami-run-abc"),
+            ..Default::default()
+        };
+        l.regions.insert(
+            Region::ApSoutheast1,
+            RegionResources {
+                security_group: Some(String::from("sg-1")),
+                ..Default::default()
+            },
+        );
+        let s = Setup::default().spot(true);
+        let params: HashMap<_, _> = l
+            .run_params("worker-0", &s, &Image::Id(String::from("m-1")), "vsw-1")
+            .into_iter()
+            .collect();
+        assert_eq!(params["SpotStrategy"], "SpotAsPriceGo");
+        assert_eq!(params["SecurityGroupId"], "sg-1");
+        assert_eq!(params["ImageId"], "m-1");
+        assert_eq!(params["Tag.2.Value"], "worker-0");
+        assert!(!params.contains_key("ImageFamily"));
+    }
+
+    #[test]
+    #[ignore]
+    fn aliyun_instances() -> Result<(), Report> {
+        use crate::providers::Launcher as _;
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let mut l = super::Launcher::default();
+            l.spawn(
+                crate::make_multiple(2, "s", Setup::default().spot(true)),
+                None,
+            )
+            .await?;
+            let vms = l.connect_all().await?;
+            assert!(vms["s-0"].private_ip.is_some());
+            let out = vms["s-1"].ssh.command("hostname").output().await?;
+            assert!(out.status.success());
+            l.terminate_all().await
+        })
+    }
+}
@@ -91,17 +91,21 @@
 //! }
 //! ```
 
+use super::MachineState;
 use color_eyre::{
     eyre::{self, eyre, WrapErr},
-    Report,
+    Help, Report,
 };
 use educe::Educe;
+use futures_util::stream::{Stream, StreamExt, TryStreamExt};
 use itertools::Itertools;
 use rusoto_core::credential::{DefaultCredentialsProvider, ProvideAwsCredentials};
 use rusoto_core::request::HttpClient;
 pub use rusoto_core::Region;
+/// Re-exported so that [`RegionLauncher::client`] can be used without a separate dependency.
+pub use rusoto_ec2;
 use rusoto_ec2::Ec2;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::future::Future;
 use std::io::Write;
 use std::pin::Pin;
@@ -110,6 +114,27 @@ use std::time;
 use tracing::instrument;
 use tracing_futures::Instrument;
 
+// How many SSH connections `connect_all` sets up at a time by default.
+const CONNECT_CONCURRENCY: usize = 64;
+
+/// A curated set of regions in North America, South America, Europe, Asia, and Oceania.
+///
+/// Only regions that are enabled by default in every account are included, so this leaves out
+/// the opt-in regions in Africa (`af-south-1`) and the Middle East (`me-south-1`); add those if
+/// your account has them enabled. Use with [`Setup::in_each_region`] for geo-distributed
+/// measurements.
+pub const GLOBAL_COVERAGE: &[Region] = &[
+    Region::UsEast1,
+    Region::UsWest2,
+    Region::SaEast1,
+    Region::EuWest1,
+    Region::EuCentral1,
+    Region::ApSouth1,
+    Region::ApNortheast1,
+    Region::ApSoutheast1,
+    Region::ApSoutheast2,
+];
+
 /// Dictate how a set of instances should be launched.
 #[derive(Debug, Clone)]
 #[allow(missing_copy_implementations)]
@@ -131,6 +156,13 @@ pub enum LaunchMode {
     },
     /// Use regular AWS on-demand instances.
     OnDemand,
+    /// Launch spot instances through [EC2 Fleet](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ec2-fleet.html).
+    ///
+    /// Each fleet may use any of a machine's [alternative instance
+    /// types](Setup::alternative_instance_types), in any availability zone, which makes it more
+    /// likely to find capacity for large launches. Fails with a [`SpotRequestError`] if there is
+    /// no spot capacity for some of the machines.
+    Fleet,
 }
 
 impl LaunchMode {
@@ -161,838 +193,5619 @@ impl LaunchMode {
     pub fn on_demand() -> Self {
         Self::OnDemand
     }
+
+    /// Launch spot instances through EC2 Fleet.
+    pub fn fleet() -> Self {
+        Self::Fleet
+    }
 }
 
-/// Available configurations of availability zone specifiers.
-///
-/// See [the aws docs](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/using-regions-availability-zones.html#using-regions-availability-zones-launching) for more information.
-#[derive(Debug, Clone)]
-pub enum AvailabilityZoneSpec {
-    /// `Any` (the default) will place the instance anywhere there is capacity.
-    Any,
-    /// `Cluster` will group instances by the given `usize` id, and ensure that each group is
-    /// placed in the same availability zone. To specify exactly which availability zone the
-    /// machines should be placed in, see `AvailabilityZoneSpec::Specify`.
-    Cluster(usize),
-    /// `Specify` will place all the instances in the named availability zone.
+/// When EC2 considers an instance up, so that tsunami starts connecting to it. See
+/// [`Launcher::readiness`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Readiness {
+    /// The instance is running and has an address. This is the default.
+    #[default]
+    Running,
+    /// The instance is running, and has also passed EC2's [system and instance status
+    /// checks](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/monitoring-system-instance-status-check.html).
     ///
-    /// The string should give the full name of the availability zone, such as `us-east-1a`.
-    Specify(String),
+    /// The checks take a few minutes to pass, but weed out instances on broken hosts before any
+    /// time is spent connecting to them.
+    StatusChecks,
+}
+
+/// How tsunami keeps trying to connect to an instance once it is [ready](Readiness). See
+/// [`Launcher::ssh_retry`].
+///
+/// Each instance is retried on its own, so one that is slow to start its SSH server does not hold
+/// up the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SshRetry {
+    /// How long a single connection attempt may take.
+    pub timeout: time::Duration,
+    /// How long to wait after a failed attempt before the next one.
+    pub interval: time::Duration,
+    /// How long to keep trying an instance before giving up on it. With `None`, the instance is
+    /// tried until the `max_wait` of the launch runs out, or forever without one.
+    pub give_up_after: Option<time::Duration>,
 }
 
-impl Default for AvailabilityZoneSpec {
+impl Default for SshRetry {
     fn default() -> Self {
-        Self::Any
+        SshRetry {
+            timeout: time::Duration::from_secs(30),
+            interval: time::Duration::from_secs(1),
+            give_up_after: None,
+        }
     }
 }
 
-impl std::fmt::Display for AvailabilityZoneSpec {
+/// What happens to an on-demand instance when it is shut down from inside, e.g. with `shutdown -h`.
+///
+/// See [the aws docs](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/terminating-instances.html#Using_ChangingInstanceInitiatedShutdownBehavior).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShutdownBehavior {
+    /// The instance is stopped, and can be started again. Its EBS volumes are still billed.
+    Stop,
+    /// The instance is terminated. This is the default.
+    Terminate,
+}
+
+impl std::fmt::Display for ShutdownBehavior {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match *self {
-            AvailabilityZoneSpec::Any => write!(f, "any"),
-            AvailabilityZoneSpec::Cluster(ref i) => write!(f, "cluster({})", i),
-            AvailabilityZoneSpec::Specify(ref s) => write!(f, "{}", s),
+        match self {
+            ShutdownBehavior::Stop => write!(f, "stop"),
+            ShutdownBehavior::Terminate => write!(f, "terminate"),
         }
     }
 }
 
-/// A descriptor for a particular machine setup in a tsunami.
+/// What happens to a spot instance when EC2 interrupts it, or when it is shut down from inside.
 ///
-/// The default region and ami is Ubuntu 18.04 LTS in us-east-1. The default AMI is updated on a
-/// passive basis, so you almost certainly want to call one of:
-/// - [`Setup::region_with_ubuntu_ami`]
-/// - [`Setup::ami`]
-/// - [`Setup::region`]
-/// to change these defaults.
-#[derive(Clone, Educe)]
-#[educe(Debug)]
-pub struct Setup {
-    region: Region,
-    availability_zone: AvailabilityZoneSpec,
-    instance_type: String,
-    ami: String,
-    username: String,
-    #[educe(Debug(ignore))]
-    setup_fn: Option<
-        Arc<
-            dyn for<'r> Fn(
-                    &'r crate::Machine<'_>,
-                )
-                    -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
-                + Send
-                + Sync
-                + 'static,
-        >,
-    >,
+/// See [the aws docs](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/interruption-behavior.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InterruptionBehavior {
+    /// The instance is terminated. This is the default.
+    Terminate,
+    /// The instance is stopped, and restarted when capacity is available again.
+    Stop,
+    /// The instance hibernates, and resumes when capacity is available again.
+    Hibernate,
 }
 
-impl super::MachineSetup for Setup {
-    type Region = String;
-
-    fn region(&self) -> Self::Region {
-        match self.availability_zone {
-            AvailabilityZoneSpec::Specify(ref id) => format!("{}-{}", self.region.name(), id),
-            AvailabilityZoneSpec::Cluster(id) => format!("{}-{}", self.region.name(), id),
-            AvailabilityZoneSpec::Any => self.region.name().to_string(),
+impl std::fmt::Display for InterruptionBehavior {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterruptionBehavior::Terminate => write!(f, "terminate"),
+            InterruptionBehavior::Stop => write!(f, "stop"),
+            InterruptionBehavior::Hibernate => write!(f, "hibernate"),
         }
     }
 }
 
-impl Default for Setup {
-    fn default() -> Self {
-        Setup {
-            region: Region::UsEast1,
-            availability_zone: AvailabilityZoneSpec::Any,
-            instance_type: "t3.small".into(),
-            ami: String::from("ami-085925f297f89fce1"),
-            username: "ubuntu".into(),
-            setup_fn: None,
+/// One CloudWatch data point, as returned by [`RegionLauncher::metrics`].
+#[cfg(feature = "cloudwatch")]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct MetricSample {
+    /// The start of the period this sample covers, in ISO 8601.
+    pub timestamp: String,
+    /// The average over the period.
+    pub average: Option<f64>,
+    /// The maximum over the period.
+    pub maximum: Option<f64>,
+    /// The unit of the values, e.g. `Percent` or `Bytes`.
+    pub unit: Option<String>,
+}
+
+// One `timestamp,average,maximum,unit` line per sample.
+#[cfg(feature = "cloudwatch")]
+fn metrics_csv(samples: &[MetricSample]) -> String {
+    let field = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+    samples
+        .iter()
+        .map(|s| {
+            format!(
+                "{},{},{},{}\n",
+                s.timestamp,
+                field(s.average),
+                field(s.maximum),
+                s.unit.as_deref().unwrap_or("")
+            )
+        })
+        .collect()
+}
+
+/// How a [burstable](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/burstable-performance-instances.html)
+/// (T-class) instance may use CPU beyond its baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CpuCredits {
+    /// The instance is throttled to its baseline once it runs out of CPU credits.
+    Standard,
+    /// The instance can burst indefinitely, and surplus credits are billed.
+    Unlimited,
+}
+
+impl std::fmt::Display for CpuCredits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CpuCredits::Standard => write!(f, "standard"),
+            CpuCredits::Unlimited => write!(f, "unlimited"),
         }
     }
 }
 
-impl Setup {
-    /// Set up the machine in a specific EC2
-    /// [`Region`](http://rusoto.github.io/rusoto/rusoto_core/region/enum.Region.html).
-    ///
-    /// The default region is us-east-1. [Available regions are listed
-    /// here.](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/using-regions-availability-zones.html#concepts-available-regions)
-    ///
-    /// AMIs are region-specific.  This method uses
-    /// [`ubuntu-ami`](https://crates.io/crates/ubuntu-ami), which queries [Ubuntu's cloud image
-    /// list](https://cloud-images.ubuntu.com/) to get the latest Ubuntu 18.04 LTS AMI in the
-    /// selected region.
-    pub async fn region_with_ubuntu_ami(mut self, region: Region) -> Result<Self, Report> {
-        self.region = region.clone();
-        let ami: String = UbuntuAmi::new(region).await?.into();
-        Ok(self.ami(ami, "ubuntu"))
+/// How much a machine matters to the experiment. See [`Setup::priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    /// The machine is launched before any best-effort machines in its region, and the launch
+    /// fails if it does not come up.
+    #[default]
+    Critical,
+    /// The machine is launched once the critical machines in its region are up. If it does not
+    /// come up, a warning is logged and the launch carries on without it.
+    BestEffort,
+}
+
+/// A cluster placement group to launch a machine into. See [`Setup::placement_group`].
+///
+/// A name converts into [`PlacementGroup::Named`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PlacementGroup {
+    /// A placement group that tsunami creates for the machines of a [`RegionSpec`], and deletes
+    /// at teardown.
+    Auto,
+    /// The placement group with this name. If there is none, tsunami creates it, and deletes it
+    /// at teardown.
+    Named(String),
+}
+
+impl From<&str> for PlacementGroup {
+    fn from(name: &str) -> Self {
+        PlacementGroup::Named(name.to_string())
     }
+}
 
-    /// Set the username used to ssh into the machine.
-    ///
-    /// If the user sets a custom AMI, they must call this method to
-    /// set a username.
-    pub fn username(self, username: impl ToString) -> Self {
-        Self {
-            username: username.to_string(),
-            ..self
+impl From<String> for PlacementGroup {
+    fn from(name: String) -> Self {
+        PlacementGroup::Named(name)
+    }
+}
+
+/// Traffic to admit into the temporary security group of the machines. See [`Setup::allow`] and
+/// [`Launcher::ingress`].
+///
+/// By default, a rule admits traffic from the machines' own network: the default VPC, or the
+/// network a machine is launched [near](Setup::near) and the networks peered with it.
+///
+/// ```rust
+/// use tsunami::providers::aws::IngressRule;
+/// let http = IngressRule::tcp(80..=80).from_anywhere();
+/// let workers = IngressRule::udp(9000..=9100);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IngressRule {
+    protocol: &'static str,
+    from_port: i64,
+    to_port: i64,
+    cidr: Option<String>,
+}
+
+impl IngressRule {
+    /// Admit TCP connections to `ports`.
+    pub fn tcp(ports: std::ops::RangeInclusive<u16>) -> Self {
+        Self::ports("tcp", ports)
+    }
+
+    /// Admit UDP datagrams to `ports`.
+    pub fn udp(ports: std::ops::RangeInclusive<u16>) -> Self {
+        Self::ports("udp", ports)
+    }
+
+    /// Admit ICMP messages of any type, such as pings.
+    pub fn icmp() -> Self {
+        IngressRule {
+            protocol: "icmp",
+            from_port: -1,
+            to_port: -1,
+            cidr: None,
         }
     }
 
-    /// The new instance will start out in the state dictated by the Amazon Machine Image specified
-    /// in `ami`. Default is Ubuntu 18.04 LTS.
-    pub fn ami(self, ami: impl ToString, username: impl ToString) -> Self {
+    fn ports(protocol: &'static str, ports: std::ops::RangeInclusive<u16>) -> Self {
+        IngressRule {
+            protocol,
+            from_port: i64::from(*ports.start()),
+            to_port: i64::from(*ports.end()),
+            cidr: None,
+        }
+    }
+
+    /// Admit the traffic from the addresses in `cidr`, e.g. `10.0.0.0/8`, rather than from the
+    /// machines' own network.
+    pub fn from_cidr(self, cidr: impl ToString) -> Self {
         Self {
-            ami: ami.to_string(),
-            username: username.to_string(),
+            cidr: Some(cidr.to_string()),
             ..self
         }
     }
 
-    /// The given AWS EC2 instance type will be used.
-    ///
-    /// Note that only [EC2 Defined Duration Spot
-    /// Instance types](https://aws.amazon.com/ec2/spot/pricing/) are allowed.
-    pub fn instance_type(mut self, typ: impl ToString) -> Self {
-        self.instance_type = typ.to_string();
-        self
+    /// Admit the traffic from anywhere on the Internet.
+    pub fn from_anywhere(self) -> Self {
+        self.from_cidr("0.0.0.0/0")
     }
 
-    /// Specify instance setup.
-    ///
-    /// The provided callback, `setup`, is called once
-    /// for every spawned instances of this type with a handle
-    /// to the target machine. Use [`Machine::ssh`] to issue
-    /// commands on the host in question.
-    ///
-    /// # Example
-    ///
-    /// ```rust
-    /// use tsunami::providers::aws::Setup;
-    ///
-    /// let m = Setup::default().setup(|vm| {
-    ///     Box::pin(async move {
-    ///         vm.ssh
-    ///             .command("sudo")
-    ///             .arg("apt")
-    ///             .arg("update")
-    ///             .status()
-    ///             .await?;
-    ///         Ok(())
-    ///     })
-    /// });
-    /// ```
-    pub fn setup(
-        mut self,
-        setup: impl for<'r> Fn(
-                &'r crate::Machine<'_>,
-            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
-            + Send
-            + Sync
-            + 'static,
-    ) -> Self {
-        self.setup_fn = Some(Arc::new(setup));
-        self
+    // Every port of `protocol`.
+    fn all(protocol: &'static str) -> Self {
+        Self::ports(protocol, 0..=65535)
     }
+}
 
-    /// Set up the machine in a specific EC2
-    /// [`Region`](http://rusoto.github.io/rusoto/rusoto_core/region/enum.Region.html).
-    ///
-    /// The default region is us-east-1. [Available regions are listed
-    /// here](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/using-regions-availability-zones.html#concepts-available-regions)
-    ///
-    /// AMIs are region-specific. Therefore, when changing the region a new ami must be given, with
-    /// a corresponding username. For a shortcut helper function that provides an Ubunti ami, see
-    /// `region_with_ubuntu_ami`.
-    pub fn region(mut self, region: Region, ami: impl ToString, username: impl ToString) -> Self {
-        self.region = region;
-        self.ami(ami, username)
+impl std::fmt::Display for IngressRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.protocol)?;
+        if self.from_port == self.to_port && self.from_port >= 0 {
+            write!(f, " {}", self.from_port)?;
+        } else if self.from_port >= 0 {
+            write!(f, " {}-{}", self.from_port, self.to_port)?;
+        }
+        match self.cidr {
+            Some(ref cidr) => write!(f, " from {}", cidr),
+            None => write!(f, " from the machines' network"),
+        }
     }
+}
 
-    /// Set up the machine in a specific EC2 availability zone.
-    ///
-    /// The default availability zone is unspecified - EC2 will launch the machine wherever there
-    /// is capacity.
-    pub fn availability_zone(self, az: AvailabilityZoneSpec) -> Self {
-        Self {
-            availability_zone: az,
-            ..self
-        }
+// Splits `machines` into batches of at most `size`, or a single batch if there is no size.
+fn batches(machines: Vec<(String, Setup)>, size: Option<usize>) -> Vec<Vec<(String, Setup)>> {
+    if machines.is_empty() {
+        return Vec::new();
+    }
+    match size {
+        Some(n) => machines.chunks(n).map(<[_]>::to_vec).collect(),
+        None => vec![machines],
     }
 }
 
-/// AWS EC2 spot instance launcher.
-///
-/// This is a lower-level API. Most users will use [`crate::TsunamiBuilder::spawn`].
-///
-/// Each individual region is handled by `RegionLauncher`.
-///
-/// While the regions are initialized serially, the setup functions for each machine are executed
-/// in parallel (within each region).
+/// Whether `instance_type` is a burstable (T-class) type, whose CPU is throttled once it runs out
+/// of CPU credits.
+pub fn is_burstable(instance_type: &str) -> bool {
+    let family = instance_type.split('.').next().unwrap_or_default();
+    family.starts_with('t') && matches!(family.chars().nth(1), Some(c) if c.is_ascii_digit())
+}
+
+/// The type of an EBS volume that an instance can boot from. See [`Setup::root_volume`].
 ///
-/// By default, `Launcher` launches instances using 6-hour [defined
-/// duration](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/spot-requests.html#fixed-duration-spot-instances)
-/// spot requests.
-#[derive(Educe)]
-#[educe(Debug)]
-pub struct Launcher<P = DefaultCredentialsProvider> {
-    #[educe(Debug(ignore))]
-    credential_provider: Box<dyn Fn() -> Result<P, Report> + Send + Sync>,
-    mode: LaunchMode,
-    use_open_ports: bool,
-    regions: HashMap<<Setup as super::MachineSetup>::Region, RegionLauncher>,
+/// See [the aws docs](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/ebs-volume-types.html)
+/// for how they differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VolumeType {
+    /// Previous-generation magnetic storage.
+    Standard,
+    /// General purpose SSD, whose performance grows with its size.
+    Gp2,
+    /// General purpose SSD with a baseline of 3000 IOPS regardless of its size.
+    Gp3,
+    /// Provisioned IOPS SSD.
+    Io1 {
+        /// The number of I/O operations per second to provision.
+        iops: i64,
+    },
+    /// Provisioned IOPS SSD, with higher durability than `Io1`.
+    Io2 {
+        /// The number of I/O operations per second to provision.
+        iops: i64,
+    },
 }
 
-impl Default for Launcher {
-    fn default() -> Self {
-        Launcher {
-            credential_provider: Box::new(|| Ok(DefaultCredentialsProvider::new()?)),
-            mode: LaunchMode::DefinedDuration { hours: 6 },
-            use_open_ports: false,
-            regions: Default::default(),
+impl VolumeType {
+    fn iops(&self) -> Option<i64> {
+        match *self {
+            VolumeType::Io1 { iops } | VolumeType::Io2 { iops } => Some(iops),
+            _ => None,
         }
     }
 }
 
-impl<P> Launcher<P> {
-    /// Set defined duration instance max instance duration.
-    ///
-    /// The lifetime of such instances must be declared in advance (1-6 hours).
-    /// This method thus clamps `t` to be between 1 and 6.
-    ///
-    /// By default, we use 6 hours (the maximum).
-    ///
-    /// This method also changes the mode to [`LaunchMode::DefinedDuration`].
-    #[deprecated(note = "prefer set_mode")]
-    pub fn set_max_instance_duration(&mut self, t: usize) -> &mut Self {
-        self.mode = LaunchMode::duration_spot(t);
-        self
+impl std::fmt::Display for VolumeType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VolumeType::Standard => write!(f, "standard"),
+            VolumeType::Gp2 => write!(f, "gp2"),
+            VolumeType::Gp3 => write!(f, "gp3"),
+            VolumeType::Io1 { .. } => write!(f, "io1"),
+            VolumeType::Io2 { .. } => write!(f, "io2"),
+        }
     }
+}
 
-    /// Set the launch mode to use for future instances.
+/// An EBS volume to attach to an instance, in addition to its root volume.
+///
+/// See [`Setup::attach_volume`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Volume {
+    /// An existing volume, by id. It must be in the same availability zone as the instance, and is
+    /// left alone when the instance terminates.
+    Existing(String),
+    /// A new `gp3` volume, which is deleted when the instance terminates.
+    New {
+        /// The size of the volume in GiB. May be omitted when restoring from a snapshot, in which
+        /// case the volume is the size of the snapshot.
+        size_gb: Option<i64>,
+        /// The id of a snapshot to restore the volume from.
+        from_snapshot: Option<String>,
+    },
+}
+
+// A volume for `Setup` to attach, where to mount it, and whether to snapshot it at teardown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AttachedVolume {
+    volume: Volume,
+    mount_point: Option<String>,
+    snapshot: bool,
+}
+
+// The device name for the `i`th extra volume of an instance: /dev/sdf, /dev/sdg, ..., /dev/sdz.
+// EC2 recommends these names for EBS volumes, so there is none past the 21st.
+fn volume_device(i: usize) -> Option<String> {
+    (b'f'..=b'z')
+        .nth(i)
+        .map(|c| format!("/dev/sd{}", c as char))
+}
+
+/// A new EBS volume that is created along with the instance. See [`Setup::data_volume`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DataVolume {
+    size_gb: i64,
+    volume_type: VolumeType,
+    device: Option<String>,
+    delete_on_termination: bool,
+}
+
+impl DataVolume {
+    /// A volume of `size_gb` GiB of type `volume_type`, which is deleted when the instance
+    /// terminates.
+    pub fn new(size_gb: i64, volume_type: VolumeType) -> Self {
+        DataVolume {
+            size_gb,
+            volume_type,
+            device: None,
+            delete_on_termination: true,
+        }
+    }
+
+    /// Attach the volume as `device`, such as `/dev/sdh`.
     ///
-    /// See [`LaunchMode`] for more details.
-    pub fn set_mode(&mut self, mode: LaunchMode) -> &mut Self {
-        self.mode = mode;
+    /// By default, data volumes are attached as `/dev/sdf`, `/dev/sdg`, and so on, skipping the
+    /// devices that other data volumes ask for.
+    pub fn device(mut self, device: impl ToString) -> Self {
+        self.device = Some(device.to_string());
         self
     }
 
-    /// The machines spawned on this launcher will have
-    /// ports open to the public Internet.
-    pub fn open_ports(&mut self) -> &mut Self {
-        self.use_open_ports = true;
+    /// Keep the volume around when the instance terminates.
+    ///
+    /// Tsunami does not delete the volume at teardown either, so delete it yourself once its data
+    /// is no longer needed.
+    pub fn keep_on_termination(mut self) -> Self {
+        self.delete_on_termination = false;
         self
     }
+}
 
-    /// Set the credential provider used to authenticate to EC2.
-    ///
-    /// The provided function is called once for each region, and is expected to produce a
-    /// [`P: ProvideAwsCredentials`](https://docs.rs/rusoto_core/0.40.0/rusoto_core/trait.ProvideAwsCredentials.html)
-    /// that gives access to the region in question.
-    pub fn with_credentials<P2>(
-        self,
-        f: impl Fn() -> Result<P2, Report> + Send + Sync + 'static,
-    ) -> Launcher<P2> {
-        Launcher {
-            credential_provider: Box::new(f),
-            mode: self.mode,
-            use_open_ports: self.use_open_ports,
-            regions: self.regions,
-        }
+// The device name of each of `volumes`: the one it asks for, or otherwise the next free one.
+fn data_volume_devices(volumes: &[DataVolume]) -> Result<Vec<String>, Report> {
+    let mut taken = HashSet::new();
+    for d in volumes.iter().filter_map(|v| v.device.as_ref()) {
+        eyre::ensure!(
+            d.starts_with("/dev/"),
+            "device name {} does not start with /dev/",
+            d
+        );
+        eyre::ensure!(taken.insert(d.clone()), "device {} is used twice", d);
     }
+    let mut free = free_devices(&taken);
+    volumes
+        .iter()
+        .map(|v| match v.device {
+            Some(ref d) => Ok(d.clone()),
+            None => free.next().ok_or_else(no_free_device),
+        })
+        .collect()
 }
 
-impl<P> super::Launcher for Launcher<P>
-where
-    P: ProvideAwsCredentials + Send + Sync + 'static,
-{
-    type MachineDescriptor = Setup;
-
-    #[instrument(level = "debug", skip(self))]
-    fn launch<'l>(
-        &'l mut self,
-        l: super::LaunchDescriptor<Self::MachineDescriptor>,
-    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
-        Box::pin(async move {
-            let prov = (*self.credential_provider)()?;
-            let Self {
-                use_open_ports,
-                mode,
-                ref mut regions,
-                ..
-            } = self;
+// The device names from /dev/sdf to /dev/sdz that are not in `taken`.
+fn free_devices(taken: &HashSet<String>) -> impl Iterator<Item = String> + '_ {
+    (0..)
+        .map(volume_device)
+        .take_while(Option::is_some)
+        .flatten()
+        .filter(move |d| !taken.contains(d))
+}
 
-            if !regions.contains_key(&l.region) {
-                let region_span = tracing::debug_span!("new_region", name = %l.region, az = %l.machines[0].1.availability_zone);
-                let awsregion = RegionLauncher::new(
-                    // region name and availability_zone spec are guaranteed to be the same because
-                    // they are included in the region specifier.
-                    l.machines[0].1.region.name(),
-                    l.machines[0].1.availability_zone.clone(),
-                    prov,
-                    *use_open_ports,
-                )
-                .instrument(region_span)
-                .await?;
-                regions.insert(l.region.clone(), awsregion);
-            }
+fn no_free_device() -> Report {
+    eyre!("too many volumes: only /dev/sdf through /dev/sdz are available")
+}
 
-            let region_span = tracing::debug_span!("region", name = %l.region);
-            regions
-                .get_mut(&l.region)
-                .unwrap()
-                .launch(mode.clone(), l.max_wait, l.machines)
-                .instrument(region_span)
-                .await?;
-            Ok(())
-        }.in_current_span())
+// A mapping that creates an EBS volume for `device` when the instance launches.
+fn ebs_mapping(
+    device: String,
+    size_gb: i64,
+    volume_type: VolumeType,
+    delete_on_termination: bool,
+) -> rusoto_ec2::BlockDeviceMapping {
+    rusoto_ec2::BlockDeviceMapping {
+        device_name: Some(device),
+        ebs: Some(rusoto_ec2::EbsBlockDevice {
+            delete_on_termination: Some(delete_on_termination),
+            volume_size: Some(size_gb),
+            volume_type: Some(volume_type.to_string()),
+            iops: volume_type.iops(),
+            ..Default::default()
+        }),
+        ..Default::default()
     }
+}
 
-    #[instrument(level = "debug", skip(self, max_wait))]
-    fn spawn<'l, I>(
-        &'l mut self,
-        descriptors: I,
-        max_wait: Option<std::time::Duration>,
-    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>>
-    where
-        I: IntoIterator<Item = (String, Self::MachineDescriptor)> + Send + 'static,
-        I: std::fmt::Debug,
-        I::IntoIter: Send,
-    {
-        use super::MachineSetup;
-        Box::pin(
-            async move {
-                tracing::info!("spinning up tsunami");
-
-                // group by region
-                let names_to_setups = descriptors
-                    .into_iter()
-                    .map(|(name, setup)| (MachineSetup::region(&setup), (name, setup)))
-                    .into_group_map();
-
-                // separate into two lists:
-                // 1. we already have a RegionLauncher
-                // 2. we don't
-                let (mut haves, have_nots): (Vec<_>, Vec<_>) = names_to_setups
-                    .into_iter()
-                    .partition(|(region_name, _)| self.regions.contains_key(region_name));
-
-                // check that this works before unwrap() below
-                let _prov = (*self.credential_provider)()?;
-                let use_open_ports = self.use_open_ports;
-
-                let newly_initialized: Vec<Result<_, _>> =
-                    futures_util::future::join_all(have_nots.iter().map(|(region_name, s)| {
-                        let region_span = tracing::debug_span!("new_region", region = %region_name);
-                        let prov = (*self.credential_provider)().unwrap();
-                        async move {
-                            let awsregion = RegionLauncher::new(
-                                // region name and availability_zone spec are guaranteed to be the
-                                // same because they are included in the region specifier.
-                                s[0].1.region.name(),
-                                s[0].1.availability_zone.clone(),
-                                prov,
-                                use_open_ports,
-                            )
-                            .await?;
-                            Ok::<_, Report>((region_name.clone(), awsregion))
-                        }
-                        .instrument(region_span)
-                    }))
-                    .await;
-                self.regions.extend(
-                    newly_initialized
-                        .into_iter()
-                        .collect::<Result<Vec<_>, _>>()?,
-                );
+// The launch template version of block device mapping `m`.
+fn template_block_device(
+    m: rusoto_ec2::BlockDeviceMapping,
+) -> rusoto_ec2::LaunchTemplateBlockDeviceMappingRequest {
+    rusoto_ec2::LaunchTemplateBlockDeviceMappingRequest {
+        device_name: m.device_name,
+        ebs: m
+            .ebs
+            .map(|ebs| rusoto_ec2::LaunchTemplateEbsBlockDeviceRequest {
+                delete_on_termination: ebs.delete_on_termination,
+                encrypted: ebs.encrypted,
+                iops: ebs.iops,
+                kms_key_id: ebs.kms_key_id,
+                snapshot_id: ebs.snapshot_id,
+                volume_size: ebs.volume_size,
+                volume_type: ebs.volume_type,
+                ..Default::default()
+            }),
+        no_device: m.no_device,
+        virtual_name: m.virtual_name,
+    }
+}
 
-                // the have-nots are now haves
-                haves.extend(have_nots);
-
-                // Launch instances in the regions concurrently.
-                //
-                // The borrow checker can't know that each future only accesses one entry of the
-                // hashmap - for its RegionLauncher (guaranteed by the `into_group_map()` above).
-                // So, we help it by taking the appropriate RegionLauncher out of the hashmap,
-                // running `launch()`, then putting everything back later.
-                let max_wait = max_wait;
-                let regions = futures_util::future::join_all(haves.into_iter().map(
-                    |(region_name, machines)| {
-                        // unwrap ok because everything is a have now
-                        let mut region_launcher = self.regions.remove(&region_name).unwrap();
-                        let region_span = tracing::debug_span!("region", region = %region_name);
-                        let mode = self.mode.clone();
-                        async move {
-                            if let Err(e) = region_launcher.launch(mode, max_wait, machines).await {
-                                Err((region_name, region_launcher, e))
-                            } else {
-                                Ok((region_name, region_launcher))
-                            }
-                        }
-                        .instrument(region_span)
-                    },
-                ))
-                .await;
+// Finds the device `volume_id` was attached as, formats it if it has no filesystem yet, and mounts
+// it at `mount_point`, owned by the ssh user.
+//
+// On Nitro instances EBS volumes show up as NVMe devices whose serial is the volume id, so they are
+// found through /dev/disk/by-id; on Xen instances /dev/sdX shows up as /dev/xvdX.
+fn mount_script(volume_id: &str, device: &str, mount_point: &str) -> String {
+    format!(
+        r#"set -e
+for i in $(seq 60); do
+  for d in /dev/disk/by-id/nvme-Amazon_Elastic_Block_Store_{serial} {device} {xen}; do
+    if [ -e "$d" ]; then dev="$d"; break 2; fi
+  done
+  sleep 1
+done
+[ -n "$dev" ] || {{ echo "{volume_id} did not show up" >&2; exit 1; }}
+sudo blkid "$dev" >/dev/null || sudo mkfs.ext4 -q "$dev"
+sudo mkdir -p '{mount_point}'
+sudo mount "$dev" '{mount_point}'
+sudo chown "$(id -u):$(id -g)" '{mount_point}'
+"#,
+        serial = volume_id.replace('-', ""),
+        device = device,
+        xen = device.replacen("/dev/sd", "/dev/xvd", 1),
+        volume_id = volume_id,
+        mount_point = mount_point,
+    )
+}
 
-                // Put our stuff back where we found it.
-                let (regions, res) =
-                    regions
-                        .into_iter()
-                        .fold((vec![], None), |acc, r| match (acc, r) {
-                            ((mut rs, x), Ok((name, rl))) => {
-                                rs.push((name, rl));
-                                (rs, x)
-                            }
-                            ((mut rs, None), Err((name, rl, e))) => {
-                                rs.push((name, rl));
-                                (rs, Some(e))
-                            }
-                            ((mut rs, x @ Some(_)), Err((name, rl, _))) => {
-                                rs.push((name, rl));
-                                (rs, x)
-                            }
-                        });
-                self.regions.extend(regions.into_iter());
+/// A spot request that EC2 did not fulfil.
+///
+/// See [the aws docs](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/spot-request-status.html#spot-instance-request-status-codes)
+/// for the possible status codes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SpotRequestFailure {
+    /// The nickname of the machine the request was for.
+    pub nickname: String,
+    /// The id of the spot request.
+    pub request_id: String,
+    /// The state of the request, e.g. `closed` or `failed`.
+    pub state: String,
+    /// The status code of the request, e.g. `capacity-not-available`, `price-too-low`, or
+    /// `bad-parameters`.
+    pub code: String,
+    /// The explanation EC2 gave for the status, if any.
+    pub message: Option<String>,
+}
 
-                if let Some(e) = res {
-                    Err(e)
-                } else {
-                    Ok(())
-                }
-            }
-            .in_current_span(),
-        )
+impl SpotRequestFailure {
+    /// Whether the request failed because EC2 had no spare capacity, as opposed to, e.g., a
+    /// malformed request. Launching on-demand instances instead may succeed in this case.
+    pub fn is_capacity(&self) -> bool {
+        is_capacity_code(&self.code)
     }
+}
 
-    #[instrument(level = "debug")]
-    fn connect_all<'l>(
-        &'l self,
-    ) -> Pin<
-        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
-    > {
-        Box::pin(async move { collect!(self.regions) }.in_current_span())
+// Whether a spot request or fleet status code means that EC2 had no spare capacity.
+fn is_capacity_code(code: &str) -> bool {
+    matches!(
+        code,
+        "capacity-not-available"
+            | "capacity-oversubscribed"
+            | "price-too-low"
+            | "InsufficientInstanceCapacity"
+            | "UnfulfillableCapacity"
+    )
+}
+
+impl std::fmt::Display for SpotRequestFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (request {} {}: {}",
+            self.nickname, self.request_id, self.state, self.code
+        )?;
+        if let Some(ref m) = self.message {
+            write!(f, ", {}", m)?;
+        }
+        write!(f, ")")
     }
+}
 
-    #[instrument(level = "debug")]
-    fn terminate_all(mut self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
-        Box::pin(
-            async move {
-                if self.regions.is_empty() {
-                    return Ok(());
-                }
+/// The error returned when some spot requests are not fulfilled.
+///
+/// Recover it from the [`Report`] returned by [`crate::Tsunami::spawn`] with
+/// `report.downcast_ref::<SpotRequestError>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SpotRequestError {
+    /// One entry per machine whose request failed.
+    pub failures: Vec<SpotRequestFailure>,
+}
 
-                let res =
-                    futures_util::future::join_all(self.regions.drain().map(|(region, mut rl)| {
-                        let region_span = tracing::debug_span!("region", %region);
-                        async move { rl.terminate_all().await }.instrument(region_span)
-                    }))
-                    .await;
-                res.into_iter().fold(Ok(()), |acc, x| match acc {
-                    Ok(_) => x,
-                    Err(a) => match x {
-                        Ok(_) => Err(a),
-                        Err(e) => Err(a.wrap_err(e)),
-                    },
-                })
+impl std::fmt::Display for SpotRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} spot request(s) failed: ", self.failures.len())?;
+        for (i, failure) in self.failures.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
             }
-            .in_current_span(),
-        )
+            write!(f, "{}", failure)?;
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
-struct IpInfo {
-    public_dns: String,
-    public_ip: String,
-    private_ip: String,
+impl std::error::Error for SpotRequestError {}
+
+/// The error returned when machines are not up within the `max_wait` given to
+/// [`crate::Tsunami::spawn`].
+///
+/// Recover it from the [`Report`] with `report.downcast_ref::<WaitLimitError>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WaitLimitError {
+    /// How long tsunami waited.
+    pub waited: time::Duration,
 }
 
-// Internal representation of an instance.
-//
-// Tagged with its nickname, and ip_info gets populated once it is available.
-#[derive(Debug, Clone)]
-struct TaggedSetup {
-    name: String,
-    setup: Setup,
-    ip_info: Option<IpInfo>,
+impl std::fmt::Display for WaitLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "wait limit reached after {:?}", self.waited)
+    }
 }
 
-/// Region specific. Launch AWS EC2 instances.
-///
-/// This implementation uses [rusoto](https://crates.io/crates/rusoto_core) to connect to AWS.
+impl std::error::Error for WaitLimitError {}
+
+/// Available configurations of availability zone specifiers.
 ///
-/// By default, `RegionLauncher` launches uses AWS [defined
-/// duration](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/spot-requests.html#fixed-duration-spot-instances)
-/// spot instances. These cost slightly more than regular spot instances, but are never prematurely
-/// terminated.  The lifetime of such instances must be declared in advance (1-6 hours). By
-/// default, we use 6 hours (the maximum). To change this, or to switch to on-demand instances, use
-/// [`Launcher::set_mode`].
+/// Machines pinned to an availability zone by `Cluster` or `Specify`, and that do not ask for a
+/// [placement group](Setup::placement_group) of their own, are launched into a new cluster
+/// placement group for each launch request.
 ///
-/// You must call [`RegionLauncher::shutdown`] to terminate the instances.
-#[derive(Educe, Default)]
-#[educe(Debug)]
-pub struct RegionLauncher {
-    /// The region this RegionLauncher is connected to.
-    pub region: rusoto_core::region::Region,
-    availability_zone: AvailabilityZoneSpec,
-    security_group_id: String,
-    ssh_key_name: String,
-    private_key_path: Option<tempfile::NamedTempFile>,
-    #[educe(Debug(ignore))]
-    client: Option<rusoto_ec2::Ec2Client>,
-    spot_requests: HashMap<String, TaggedSetup>,
-    instances: HashMap<String, TaggedSetup>,
-}
-
-impl RegionLauncher {
-    /// Connect to AWS region `region`, using credentials provider `provider`.
+/// See [the aws docs](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/using-regions-availability-zones.html#using-regions-availability-zones-launching) for more information.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum AvailabilityZoneSpec {
+    /// `Any` (the default) will place the instance anywhere there is capacity.
     ///
-    /// This is a lower-level API, you may want [`Launcher`] instead.
+    /// Spot instances are requested in one zone at a time: the first, by name, that offers the
+    /// machines' instance types and that spot requests are not
+    /// [avoiding](RegionLauncher::avoided_zones).
+    #[default]
+    Any,
+    /// `Cluster` will group instances by the given `usize` id, and ensure that each group is
+    /// placed in the same availability zone. The zone is the first, by name, that offers the
+    /// instance types of all the group's machines. To specify exactly which availability zone the
+    /// machines should be placed in, see `AvailabilityZoneSpec::Specify`.
+    Cluster(usize),
+    /// `Specify` will place all the instances in the named availability zone.
     ///
-    /// This will create a temporary security group and SSH key in the given AWS region.
-    pub async fn new<P>(
-        region: &str,
-        availability_zone: AvailabilityZoneSpec,
-        provider: P,
-        use_open_ports: bool,
-    ) -> Result<Self, Report>
-    where
-        P: ProvideAwsCredentials + Send + Sync + 'static,
-    {
-        let region = region.parse()?;
-        let ec2 = RegionLauncher::connect(region, availability_zone, provider)
-            .wrap_err("failed to connect to region")?
-            .make_security_group(use_open_ports)
-            .await
-            .wrap_err("failed to make security groups")?
-            .make_ssh_key()
-            .await
-            .wrap_err("failed to make ssh key")?;
+    /// The string should give the full name of the availability zone, such as `us-east-1a`.
+    Specify(String),
+}
 
-        Ok(ec2)
+impl std::fmt::Display for AvailabilityZoneSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            AvailabilityZoneSpec::Any => write!(f, "any"),
+            AvailabilityZoneSpec::Cluster(ref i) => write!(f, "cluster({})", i),
+            AvailabilityZoneSpec::Specify(ref s) => write!(f, "{}", s),
+        }
     }
+}
 
-    #[instrument(level = "debug", skip(provider))]
-    fn connect<P>(
-        region: rusoto_core::region::Region,
-        availability_zone: AvailabilityZoneSpec,
-        provider: P,
-    ) -> Result<Self, Report>
-    where
-        P: ProvideAwsCredentials + Send + Sync + 'static,
-    {
-        tracing::debug!("connecting to ec2");
-        let ec2 = rusoto_ec2::Ec2Client::new_with(
-            HttpClient::new().wrap_err("failed to construct new http client")?,
-            provider,
-            region.clone(),
-        );
+/// An existing resource whose network new machines should join. See [`Setup::near`].
+///
+/// Displays as, and parses from, `instance:<id>`, `subnet:<id>`, `vpc:<id>`, or
+/// `tag:<key>=<value>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Affinity {
+    /// The subnet of the EC2 instance with this id, such as a database server.
+    Instance(String),
+    /// The subnet with this id.
+    Subnet(String),
+    /// A subnet of the VPC with this id.
+    ///
+    /// Subnets that assign public IP addresses, and that are in the machines' availability zone
+    /// if they have one, are preferred.
+    Vpc(String),
+    /// The instance, subnet, or VPC tagged `key=value`, looked for in that order.
+    Tagged {
+        /// The tag key.
+        key: String,
+        /// The tag value.
+        value: String,
+    },
+}
 
-        Ok(Self {
-            region,
-            availability_zone,
-            security_group_id: Default::default(),
-            ssh_key_name: Default::default(),
-            private_key_path: Some(
-                tempfile::NamedTempFile::new()
-                    .wrap_err("failed to create temporary file for keypair")?,
-            ),
-            spot_requests: Default::default(),
-            instances: Default::default(),
-            client: Some(ec2),
-        })
+impl Affinity {
+    /// The resource tagged `key=value`.
+    pub fn tagged(key: impl ToString, value: impl ToString) -> Self {
+        Affinity::Tagged {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
     }
+}
 
-    /// Region-specific instance setup.
-    ///
-    /// Make spot instance requests, wait for the instances, and then call the
-    /// instance setup functions.
+impl std::fmt::Display for Affinity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Affinity::Instance(id) => write!(f, "instance:{}", id),
+            Affinity::Subnet(id) => write!(f, "subnet:{}", id),
+            Affinity::Vpc(id) => write!(f, "vpc:{}", id),
+            Affinity::Tagged { key, value } => write!(f, "tag:{}={}", key, value),
+        }
+    }
+}
+
+impl std::str::FromStr for Affinity {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, id) = s
+            .split_once(':')
+            .ok_or_else(|| eyre!("no resource type in {:?}", s))?;
+        Ok(match kind {
+            "instance" => Affinity::Instance(id.to_string()),
+            "subnet" => Affinity::Subnet(id.to_string()),
+            "vpc" => Affinity::Vpc(id.to_string()),
+            "tag" => {
+                let (key, value) = id
+                    .split_once('=')
+                    .ok_or_else(|| eyre!("tag {:?} is not of the form key=value", id))?;
+                Affinity::tagged(key, value)
+            }
+            _ => eyre::bail!("unknown resource type {:?}", kind),
+        })
+    }
+}
+
+/// The existing network a [`RegionLauncher`] launches into. See [`Setup::near`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Network {
+    /// The VPC the instances are in.
+    pub vpc_id: String,
+    /// The subnet the instances are in.
+    pub subnet_id: String,
+    /// The availability zone of `subnet_id`.
+    pub availability_zone: String,
+    /// The address ranges of the VPC.
+    pub cidr_blocks: Vec<String>,
+    /// The address ranges of the VPCs that the VPC has active peering connections with.
+    ///
+    /// The security group admits traffic from these as well as from `cidr_blocks`, so that
+    /// services in a peered VPC can reach the instances. Whether the instances can reach the
+    /// peered VPC also depends on the subnet's route table.
+    pub peered_cidr_blocks: Vec<String>,
+}
+
+/// The group of machines that share a [`RegionLauncher`]: a region, availability zone spec, and
+/// network.
+///
+/// This is the [`MachineSetup::Region`](super::MachineSetup::Region) for [`Setup`]. It is
+/// displayed as the region name, followed by `-` and the availability zone or cluster id if there
+/// is one, `#` and the security group if it is an existing one, and `@` and the [`Affinity`] if
+/// there is one (e.g. `us-east-1`, `us-east-1-us-east-1a`, or `us-east-1-2#sg-0123@vpc:vpc-0123`),
+/// and can be parsed back from that form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RegionSpec {
+    /// The EC2 region.
+    pub region: Region,
+    /// Where in `region` the machines go.
+    pub availability_zone: AvailabilityZoneSpec,
+    /// The existing network the machines join, if not the default VPC.
+    pub near: Option<Affinity>,
+    /// The existing security group the machines are launched into, if any. See
+    /// [`Setup::security_group`].
+    pub security_group: Option<String>,
+}
+
+impl From<Region> for RegionSpec {
+    fn from(region: Region) -> Self {
+        RegionSpec {
+            region,
+            availability_zone: AvailabilityZoneSpec::Any,
+            near: None,
+            security_group: None,
+        }
+    }
+}
+
+impl std::fmt::Display for RegionSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.availability_zone {
+            AvailabilityZoneSpec::Specify(ref id) => write!(f, "{}-{}", self.region.name(), id)?,
+            AvailabilityZoneSpec::Cluster(id) => write!(f, "{}-{}", self.region.name(), id)?,
+            AvailabilityZoneSpec::Any => write!(f, "{}", self.region.name())?,
+        }
+        if let Some(ref sg) = self.security_group {
+            write!(f, "#{}", sg)?;
+        }
+        if let Some(ref near) = self.near {
+            write!(f, "@{}", near)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for RegionSpec {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((s, near)) = s.split_once('@') {
+            return Ok(RegionSpec {
+                near: Some(near.parse()?),
+                ..s.parse()?
+            });
+        }
+        if let Some((s, sg)) = s.split_once('#') {
+            eyre::ensure!(!sg.is_empty(), "empty security group in {:?}", s);
+            return Ok(RegionSpec {
+                security_group: Some(sg.to_string()),
+                ..s.parse()?
+            });
+        }
+        if let Ok(region) = s.parse::<Region>() {
+            return Ok(Self::from(region));
+        }
+
+        // region names contain `-` themselves, so find the prefix that is one.
+        for (i, _) in s.match_indices('-') {
+            if let Ok(region) = s[..i].parse::<Region>() {
+                let az = &s[i + 1..];
+                let availability_zone = match az.parse() {
+                    Ok(id) => AvailabilityZoneSpec::Cluster(id),
+                    Err(_) => AvailabilityZoneSpec::Specify(az.to_string()),
+                };
+                return Ok(RegionSpec {
+                    region,
+                    availability_zone,
+                    near: None,
+                    security_group: None,
+                });
+            }
+        }
+
+        eyre::bail!("unknown region {:?}", s)
+    }
+}
+
+/// A descriptor for a particular machine setup in a tsunami.
+///
+/// The default region is us-east-1, and the default image is the latest Ubuntu 22.04 LTS AMI that
+/// Canonical has published in the machine's region, looked up when the machine is launched (see
+/// [`Setup::region_with_ubuntu_ami`]). To change these defaults, call one of:
+/// - [`Setup::region_with_ubuntu_ami`]
+/// - [`Setup::image_spec`]
+/// - [`Setup::ami`]
+/// - [`Setup::region`]
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct Setup {
+    region: Region,
+    availability_zone: AvailabilityZoneSpec,
+    near: Option<Affinity>,
+    security_group: Option<String>,
+    instance_type: String,
+    alternative_instance_types: Vec<String>,
+    ami: String,
+    image: Option<crate::image::ImageSpec>,
+    username: String,
+    os: Option<crate::OsFamily>,
+    shutdown_behavior: ShutdownBehavior,
+    interruption_behavior: InterruptionBehavior,
+    detailed_monitoring: bool,
+    cpu_credits: Option<CpuCredits>,
+    spot_price: Option<f64>,
+    root_volume: Option<(i64, VolumeType)>,
+    data_volumes: Vec<DataVolume>,
+    efa: bool,
+    instance_profile: Option<String>,
+    volumes: Vec<AttachedVolume>,
+    priority: Priority,
+    on_demand: bool,
+    ingress: Vec<IngressRule>,
+    placement_group: Option<PlacementGroup>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<
+        Arc<
+            dyn for<'r> Fn(
+                    &'r crate::Machine<'_>,
+                )
+                    -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >,
+    #[educe(Debug(ignore))]
+    teardown_fn: Option<
+        Arc<
+            dyn for<'r> Fn(
+                    &'r crate::Machine<'_>,
+                )
+                    -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >,
+}
+
+impl super::MachineSetup for Setup {
+    type Region = RegionSpec;
+
+    fn region(&self) -> Self::Region {
+        RegionSpec {
+            region: self.region.clone(),
+            availability_zone: self.availability_zone.clone(),
+            near: self.near.clone(),
+            security_group: self.security_group.clone(),
+        }
+    }
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Setup {
+            region: Region::UsEast1,
+            availability_zone: AvailabilityZoneSpec::Any,
+            near: None,
+            security_group: None,
+            instance_type: "t3.small".into(),
+            alternative_instance_types: Vec::new(),
+            // resolved from `image` at launch
+            ami: String::new(),
+            image: Some(crate::image::ImageSpec::Ubuntu2204),
+            username: "ubuntu".into(),
+            os: Some(crate::OsFamily::Ubuntu),
+            shutdown_behavior: ShutdownBehavior::Terminate,
+            interruption_behavior: InterruptionBehavior::Terminate,
+            detailed_monitoring: false,
+            cpu_credits: None,
+            spot_price: None,
+            root_volume: None,
+            data_volumes: Vec::new(),
+            efa: false,
+            instance_profile: None,
+            volumes: Vec::new(),
+            priority: Priority::Critical,
+            on_demand: false,
+            ingress: Vec::new(),
+            placement_group: None,
+            setup_fn: None,
+            teardown_fn: None,
+        }
+    }
+}
+
+impl Setup {
+    /// Set up the machine in a specific EC2
+    /// [`Region`](http://rusoto.github.io/rusoto/rusoto_core/region/enum.Region.html).
+    ///
+    /// The default region is us-east-1. [Available regions are listed
+    /// here.](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/using-regions-availability-zones.html#concepts-available-regions)
+    ///
+    /// AMIs are region-specific, so this uses [`ImageSpec::Ubuntu2204`](crate::image::ImageSpec),
+    /// which is resolved to the latest Ubuntu 22.04 LTS AMI that Canonical has published in the
+    /// region when the machine is launched. If EC2 does not list one, tsunami falls back to
+    /// [Ubuntu's cloud image list](https://cloud-images.ubuntu.com/).
+    pub async fn region_with_ubuntu_ami(mut self, region: Region) -> Result<Self, Report> {
+        self.region = region;
+        Ok(self.image_spec(crate::image::ImageSpec::Ubuntu2204))
+    }
+
+    /// Make one copy of this descriptor for each of `regions`, using the latest Ubuntu AMI in
+    /// each (see [`Setup::region_with_ubuntu_ami`]).
+    ///
+    /// Each machine is nicknamed after its region. This is the usual starting point for
+    /// geo-distributed measurements; [`GLOBAL_COVERAGE`] is a reasonable set of regions.
+    ///
+    /// ```rust,no_run
+    /// # async fn f() -> Result<(), color_eyre::Report> {
+    /// use tsunami::providers::aws;
+    /// use tsunami::Tsunami;
+    /// let mut l = aws::Launcher::default();
+    /// let ms = aws::Setup::default()
+    ///     .in_each_region(aws::GLOBAL_COVERAGE.iter().cloned())
+    ///     .await?;
+    /// l.spawn(ms, None).await?;
+    /// let vms = l.connect_all().await?;
+    /// let tokyo = &vms["ap-northeast-1"];
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn in_each_region(
+        self,
+        regions: impl IntoIterator<Item = Region>,
+    ) -> Result<Vec<(String, Self)>, Report> {
+        futures_util::future::join_all(regions.into_iter().map(|r| {
+            let s = self.clone();
+            async move {
+                let name = r.name().to_string();
+                Ok((name, s.region_with_ubuntu_ami(r).await?))
+            }
+        }))
+        .await
+        .into_iter()
+        .collect()
+    }
+
+    /// Set the username used to ssh into the machine.
+    ///
+    /// The default is `ubuntu`. [`Setup::os`] and [`Setup::image_spec`] pick the right user for
+    /// images they know about (e.g., `ec2-user` for Amazon Linux and `admin` for Debian), and
+    /// [`Setup::ami`] takes one along with the AMI. Use this for images whose user differs from
+    /// their family's default; since those methods replace the username, call it after them.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::Setup;
+    /// use tsunami::OsFamily;
+    /// let m = Setup::default().ami("ami-0", "ec2-user").os(OsFamily::AmazonLinux).username("lab");
+    /// ```
+    pub fn username(self, username: impl ToString) -> Self {
+        Self {
+            username: username.to_string(),
+            ..self
+        }
+    }
+
+    /// The new instance will start out in the state dictated by the Amazon Machine Image specified
+    /// in `ami`. Default is the latest Ubuntu 22.04 LTS AMI in the machine's region.
+    ///
+    /// Since tsunami cannot know what operating system a custom AMI runs, this clears any
+    /// previously set [`Setup::os`].
+    pub fn ami(self, ami: impl ToString, username: impl ToString) -> Self {
+        Self {
+            ami: ami.to_string(),
+            image: None,
+            username: username.to_string(),
+            os: None,
+            ..self
+        }
+    }
+
+    /// Use the image described by `image`, which is resolved to an AMI in the machine's region
+    /// when it is launched.
+    ///
+    /// Images of a known operating system also set [`Setup::os`], and with it the username.
+    /// For other images, call [`Setup::username`] as well.
+    ///
+    /// ```rust
+    /// use tsunami::image::ImageSpec;
+    /// use tsunami::providers::aws::Setup;
+    /// let m = Setup::default().image_spec(ImageSpec::Debian11);
+    /// ```
+    pub fn image_spec(self, image: crate::image::ImageSpec) -> Self {
+        let os = image.os();
+        let s = Self {
+            image: Some(image),
+            os: None,
+            ..self
+        };
+        match os {
+            Some(os) => s.os(os),
+            None => s,
+        }
+    }
+
+    /// Declare which operating system family the AMI runs.
+    ///
+    /// This also sets the username to the family's default (e.g., `ec2-user` for Amazon Linux
+    /// and FreeBSD). Since [`Setup::ami`] and [`Setup::region`] reset this, call it after them.
+    /// The family is available to setup functions as [`crate::Machine::os`].
+    pub fn os(self, os: crate::OsFamily) -> Self {
+        Self {
+            username: os.default_username().to_string(),
+            os: Some(os),
+            ..self
+        }
+    }
+
+    /// The given AWS EC2 instance type will be used.
+    ///
+    /// Note that only [EC2 Defined Duration Spot
+    /// Instance types](https://aws.amazon.com/ec2/spot/pricing/) are allowed.
+    ///
+    /// [Image specs](Setup::image_spec) resolve to an AMI for the type's architecture, so ARM
+    /// (Graviton) types like `t4g.small` or `c6g.large` get an arm64 image, including the default
+    /// Ubuntu one. An AMI set with [`Setup::ami`] must match the architecture.
+    pub fn instance_type(mut self, typ: impl ToString) -> Self {
+        self.instance_type = typ.to_string();
+        self
+    }
+
+    /// Let a [fleet](LaunchMode::Fleet) launch the machine as any of `types` as well as
+    /// [`Setup::instance_type`], whichever it finds spot capacity for.
+    ///
+    /// Pick types that are equivalent for the experiment, and of the same architecture, since
+    /// the machine has a single AMI. Other launch modes only use [`Setup::instance_type`].
+    pub fn alternative_instance_types<S: ToString>(
+        mut self,
+        types: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.alternative_instance_types = types.into_iter().map(|t| t.to_string()).collect();
+        self
+    }
+
+    /// What should happen when an on-demand instance is shut down from inside.
+    ///
+    /// The default is [`ShutdownBehavior::Terminate`]. Spot instances ignore this, and follow
+    /// [`Setup::interruption_behavior`] instead.
+    pub fn shutdown_behavior(mut self, b: ShutdownBehavior) -> Self {
+        self.shutdown_behavior = b;
+        self
+    }
+
+    /// What should happen when a spot instance is interrupted or shut down from inside.
+    ///
+    /// The default is [`InterruptionBehavior::Terminate`]. EC2 only supports the other behaviors
+    /// for persistent spot requests, so with those the instance is requested with a persistent
+    /// request instead of a defined-duration one, and the request is cancelled in
+    /// [`RegionLauncher::terminate_all`]. On-demand instances ignore this.
+    pub fn interruption_behavior(mut self, b: InterruptionBehavior) -> Self {
+        self.interruption_behavior = b;
+        self
+    }
+
+    /// Enable [detailed
+    /// monitoring](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/using-cloudwatch-new.html),
+    /// so that CloudWatch collects metrics every minute instead of every five.
+    ///
+    /// Detailed monitoring is billed separately. With the `cloudwatch` feature, the metrics can be
+    /// fetched with [`RegionLauncher::metrics`].
+    pub fn detailed_monitoring(mut self, enabled: bool) -> Self {
+        self.detailed_monitoring = enabled;
+        self
+    }
+
+    /// Set the CPU credit mode of a burstable (T-class) instance.
+    ///
+    /// Burstable instances, including the default `t3.small`, are throttled once they run out of
+    /// CPU credits, which silently skews benchmarks. Use [`CpuCredits::Unlimited`] to avoid that,
+    /// or better, a non-burstable instance type. Launching a burstable instance without setting
+    /// this logs a warning. With the `cloudwatch` feature, the remaining credits can be fetched as
+    /// the `CPUCreditBalance` metric with [`RegionLauncher::metrics`].
+    pub fn cpu_credits(mut self, credits: CpuCredits) -> Self {
+        self.cpu_credits = Some(credits);
+        self
+    }
+
+    /// Set how much this machine matters. See [`Priority`].
+    ///
+    /// By default, every machine is [`Priority::Critical`]. Best-effort machines that fail to
+    /// launch are terminated, and are then left out of
+    /// [`connect_all`](super::Launcher::connect_all). Priorities only order the launches within a
+    /// region; regions are still launched concurrently.
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Always launch this machine as a regular on-demand instance, whatever the launcher's
+    /// [`LaunchMode`].
+    ///
+    /// Use this for the machines of an experiment that cannot tolerate spot unavailability, or
+    /// whose instance types spot requests rarely fulfil, while the others still use spot
+    /// instances.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::Setup;
+    ///
+    /// let coordinator = Setup::default().instance_type("m5.large").on_demand();
+    /// let workers = Setup::default().instance_type("c5.xlarge");
+    /// ```
+    pub fn on_demand(mut self) -> Self {
+        self.on_demand = true;
+        self
+    }
+
+    /// Whether this machine is always launched as an on-demand instance. See
+    /// [`Setup::on_demand`].
+    pub fn is_on_demand(&self) -> bool {
+        self.on_demand
+    }
+
+    /// Pay at most `usd_per_hour` US dollars per hour for this machine's spot instance.
+    ///
+    /// By default, the maximum price is the on-demand price of the instance type, or the default
+    /// set with [`Launcher::spot_price`]. A spot request whose maximum is below the current spot
+    /// price is not fulfilled, and fails with `price-too-low`; note that with
+    /// [`LaunchMode::TrySpot`], the machine is then launched as an on-demand instance instead.
+    /// The price does not apply to on-demand instances.
+    ///
+    /// Launching the machine fails if `usd_per_hour` is not a positive number.
+    pub fn spot_price(mut self, usd_per_hour: f64) -> Self {
+        self.spot_price = Some(usd_per_hour);
+        self
+    }
+
+    /// Attach an EBS volume to the instance once it is running, before the
+    /// [`setup`](Setup::setup) function runs.
+    ///
+    /// Volumes are attached as `/dev/sdf`, `/dev/sdg`, and so on, in the order they are added.
+    /// To instead make the root volume bigger, see [`Setup::root_volume`].
+    /// Note that on Nitro instance types, the volume shows up as an NVMe device instead, e.g.
+    /// `/dev/nvme1n1`. Use [`Setup::attach_volume_at`] to have tsunami find the device and mount
+    /// it for you.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{Setup, Volume};
+    ///
+    /// let m = Setup::default().attach_volume(Volume::New {
+    ///     size_gb: None,
+    ///     from_snapshot: Some(String::from("snap-0123456789abcdef0")),
+    /// });
+    /// ```
+    pub fn attach_volume(mut self, volume: Volume) -> Self {
+        self.volumes.push(AttachedVolume {
+            volume,
+            mount_point: None,
+            snapshot: false,
+        });
+        self
+    }
+
+    /// Boot the machine from a root volume of `size_gb` GiB of type `volume_type`, instead of the
+    /// AMI's default, which for the Ubuntu AMIs is 8 GiB of `gp2`.
+    ///
+    /// The size must be at least that of the AMI's snapshot. The root filesystem of the Ubuntu
+    /// AMIs grows to fill the volume when the machine first boots. The volume is deleted when the
+    /// instance terminates.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{Setup, VolumeType};
+    ///
+    /// let m = Setup::default().root_volume(200, VolumeType::Gp3);
+    /// ```
+    pub fn root_volume(mut self, size_gb: i64, volume_type: VolumeType) -> Self {
+        self.root_volume = Some((size_gb, volume_type));
+        self
+    }
+
+    /// Create a new EBS volume, such as a large scratch disk, along with the instance.
+    ///
+    /// Unlike with [`Setup::attach_volume`], the volume is part of the launch request, so it is
+    /// there by the time the machine boots, but it is neither formatted nor mounted. Volumes added
+    /// with `attach_volume` are attached as the devices that no data volume uses.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{DataVolume, Setup, VolumeType};
+    ///
+    /// let m = Setup::default()
+    ///     .data_volume(DataVolume::new(1000, VolumeType::Gp3))
+    ///     .data_volume(DataVolume::new(100, VolumeType::Io2 { iops: 20000 }).device("/dev/sdk"));
+    /// ```
+    pub fn data_volume(mut self, volume: DataVolume) -> Self {
+        self.data_volumes.push(volume);
+        self
+    }
+
+    /// Give the machine an [Elastic Fabric
+    /// Adapter](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/efa.html), for low-latency
+    /// communication between the machines of an MPI or NCCL job.
+    ///
+    /// Only some instance types, such as `c5n.18xlarge`, support EFA. EFA traffic does not cross
+    /// availability zones, so combine this with [`Setup::placement_group`]. The temporary security
+    /// group is made to admit all traffic between the machines, which EFA requires. The machine still
+    /// needs the EFA software, which [`mpi::Cluster::efa`](crate::mpi::Cluster::efa) installs.
+    pub fn efa(mut self) -> Self {
+        self.efa = true;
+        self
+    }
+
+    /// Launch the machine with the IAM instance profile `arn_or_name`, given either as its ARN
+    /// or as its name.
+    ///
+    /// Programs on the machine then get the credentials of the profile's role from the instance
+    /// metadata service, so they can use S3, DynamoDB, and other AWS services without credentials
+    /// having to be copied onto the machine. The credentials tsunami launches with need the
+    /// `iam:PassRole` permission for the role.
+    ///
+    /// ```rust
+    /// let m = tsunami::providers::aws::Setup::default().instance_profile("experiment-s3-access");
+    /// ```
+    pub fn instance_profile(mut self, arn_or_name: impl ToString) -> Self {
+        self.instance_profile = Some(arn_or_name.to_string());
+        self
+    }
+
+    /// Attach an EBS volume like [`Setup::attach_volume`], and mount it at `mount_point`.
+    ///
+    /// The volume is formatted as ext4 if it does not have a filesystem yet, and the mount point is
+    /// owned by the ssh user.
+    pub fn attach_volume_at(mut self, volume: Volume, mount_point: impl ToString) -> Self {
+        self.volumes.push(AttachedVolume {
+            volume,
+            mount_point: Some(mount_point.to_string()),
+            snapshot: false,
+        });
+        self
+    }
+
+    /// Attach and mount an EBS volume like [`Setup::attach_volume_at`], and snapshot it when the
+    /// machine is torn down.
+    ///
+    /// This is for runs that produce more data than is practical to download right away: write
+    /// the results to `mount_point`, and they are kept in an EBS snapshot after the machine is
+    /// gone. The snapshot ids are returned by [`Launcher::snapshot_results`] and listed in
+    /// [`Resources::snapshots`], and the snapshots are tagged with the machine's nickname and the
+    /// run id. Restore one later with [`Volume::New`]'s `from_snapshot`.
+    ///
+    /// Snapshots are crash-consistent: writes the machine has not flushed to the volume yet are
+    /// not included, so run `sync` on the machine before tearing it down.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{Setup, Volume};
+    ///
+    /// let m = Setup::default().results_volume_at(
+    ///     Volume::New {
+    ///         size_gb: Some(500),
+    ///         from_snapshot: None,
+    ///     },
+    ///     "/results",
+    /// );
+    /// ```
+    pub fn results_volume_at(mut self, volume: Volume, mount_point: impl ToString) -> Self {
+        self.volumes.push(AttachedVolume {
+            volume,
+            mount_point: Some(mount_point.to_string()),
+            snapshot: true,
+        });
+        self
+    }
+
+    /// Format and mount the instance's local NVMe disks at `path` before the
+    /// [`setup`](Setup::setup) function runs.
+    ///
+    /// This is for instance types with an instance store, such as `i3` or `c5d`. See
+    /// [`storage::mount_instance_store`](crate::storage::mount_instance_store) for where the disks
+    /// end up. Call this after `setup`, since `setup` replaces any earlier setup function.
+    pub fn instance_store_at(mut self, path: impl ToString) -> Self {
+        let path = path.to_string();
+        let step: super::SetupFn = Arc::new(move |vm| {
+            let path = path.clone();
+            Box::pin(async move {
+                crate::storage::mount_instance_store(vm, &path).await?;
+                Ok(())
+            })
+        });
+        self.setup_fn = Some(super::before_setup(step, self.setup_fn.take()));
+        self
+    }
+
+    /// Specify instance setup.
+    ///
+    /// The provided callback, `setup`, is called once
+    /// for every spawned instances of this type with a handle
+    /// to the target machine. Use [`Machine::ssh`] to issue
+    /// commands on the host in question.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::Setup;
+    ///
+    /// let m = Setup::default().setup(|vm| {
+    ///     Box::pin(async move {
+    ///         vm.ssh
+    ///             .command("sudo")
+    ///             .arg("apt")
+    ///             .arg("update")
+    ///             .status()
+    ///             .await?;
+    ///         Ok(())
+    ///     })
+    /// });
+    /// ```
+    pub fn setup(
+        mut self,
+        setup: impl for<'r> Fn(
+                &'r crate::Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.setup_fn = Some(Arc::new(setup));
+        self
+    }
+
+    /// Specify how to wind the machine down before it is terminated by
+    /// [`Launcher::terminate_gracefully`].
+    ///
+    /// Use this to stop the experiment's background processes so that they write out buffered
+    /// results. [`terminate_all`](super::Launcher::terminate_all) does not run it.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::Setup;
+    ///
+    /// let m = Setup::default().teardown(|vm| {
+    ///     Box::pin(async move {
+    ///         vm.ssh.command("pkill").arg("-INT").arg("server").status().await?;
+    ///         Ok(())
+    ///     })
+    /// });
+    /// ```
+    pub fn teardown(
+        mut self,
+        teardown: impl for<'r> Fn(
+                &'r crate::Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.teardown_fn = Some(Arc::new(teardown));
+        self
+    }
+
+    /// Check that the machine matches `expect` once it is up, before running the
+    /// [`setup`](Setup::setup) function.
+    ///
+    /// See the [`verify`](crate::verify) module for what happens when the check fails, and for
+    /// when to call this.
+    pub fn verify(mut self, expect: crate::verify::Expectations) -> Self {
+        self.setup_fn = Some(super::before_setup(
+            expect.into_setup_fn(),
+            self.setup_fn.take(),
+        ));
+        self
+    }
+
+    /// Set up the machine in a specific EC2
+    /// [`Region`](http://rusoto.github.io/rusoto/rusoto_core/region/enum.Region.html).
+    ///
+    /// The default region is us-east-1. [Available regions are listed
+    /// here](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/using-regions-availability-zones.html#concepts-available-regions)
+    ///
+    /// AMIs are region-specific. Therefore, when changing the region a new ami must be given, with
+    /// a corresponding username. For a shortcut helper function that provides an Ubunti ami, see
+    /// `region_with_ubuntu_ami`.
+    pub fn region(mut self, region: Region, ami: impl ToString, username: impl ToString) -> Self {
+        self.region = region;
+        self.ami(ami, username)
+    }
+
+    /// Apply a [`Placement`](crate::placement::Placement) computed by the placement planner.
+    ///
+    /// This moves the machine to the planned region, and places machines that share a zone group
+    /// in the same availability zone. Images, including the default Ubuntu one, are looked up in
+    /// the planned region; an AMI given with [`Setup::ami`] is kept, so a custom AMI must exist in
+    /// the planned region.
+    pub async fn placed(mut self, p: &crate::placement::Placement) -> Result<Self, Report> {
+        self.region = p.region.parse()?;
+        if let Some(g) = p.zone_group {
+            self.availability_zone = AvailabilityZoneSpec::Cluster(g);
+        }
+        Ok(self)
+    }
+
+    /// Set up the machine in a specific EC2 availability zone.
+    ///
+    /// The default availability zone is unspecified - EC2 will launch the machine wherever there
+    /// is capacity.
+    pub fn availability_zone(self, az: AvailabilityZoneSpec) -> Self {
+        Self {
+            availability_zone: az,
+            ..self
+        }
+    }
+
+    /// Launch the machine into the same subnet as `affinity`, an existing resource in the
+    /// machine's region, such as a long-lived database server, rather than into the default VPC.
+    ///
+    /// The machines get public IP addresses, and a security group in the resource's VPC that
+    /// admits traffic from the VPC and from the VPCs it is peered with; see [`Network`].
+    /// Machines near different resources are in separate [`RegionSpec`]s.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{Affinity, Setup};
+    /// let m = Setup::default().near(Affinity::tagged("Name", "experiment-db"));
+    /// ```
+    pub fn near(self, affinity: Affinity) -> Self {
+        Self {
+            near: Some(affinity),
+            ..self
+        }
+    }
+
+    /// Launch the machine into the existing subnet `subnet_id`, rather than into the default VPC.
+    ///
+    /// This is short for `near(Affinity::Subnet(subnet_id))`; see [`Setup::near`]. To launch
+    /// into a private subnet, which gives its instances no public addresses, also enable
+    /// [`Launcher::ssm_fallback`].
+    pub fn subnet(self, subnet_id: impl ToString) -> Self {
+        self.near(Affinity::Subnet(subnet_id.to_string()))
+    }
+
+    /// Launch the machine into a subnet of the existing VPC `vpc_id`, rather than into the
+    /// default VPC.
+    ///
+    /// This is short for `near(Affinity::Vpc(vpc_id))`; see [`Setup::near`].
+    pub fn vpc(self, vpc_id: impl ToString) -> Self {
+        self.near(Affinity::Vpc(vpc_id.to_string()))
+    }
+
+    /// Launch the machine into the existing security group `group_id`, rather than into a
+    /// temporary one that tsunami creates, and deletes again at teardown.
+    ///
+    /// Use this in accounts that do not allow creating security groups. The group must be in the
+    /// VPC the machine is launched into, and must admit SSH from the machine running tsunami;
+    /// [`Launcher::open_ports`] does not change it. Machines in different security groups are in
+    /// separate [`RegionSpec`]s.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::Setup;
+    /// let m = Setup::default()
+    ///     .subnet("subnet-0123456789abcdef0")
+    ///     .security_group("sg-0123456789abcdef0");
+    /// ```
+    pub fn security_group(self, group_id: impl ToString) -> Self {
+        Self {
+            security_group: Some(group_id.to_string()),
+            ..self
+        }
+    }
+
+    /// Admit the traffic of `rule` into the machine's security group, in addition to what the
+    /// [launcher admits](Launcher::ingress).
+    ///
+    /// Security groups are shared by all the machines of a [`RegionSpec`], so this admits the
+    /// traffic to those machines too. Rules cannot be added to an [existing security
+    /// group](Setup::security_group).
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{IngressRule, Setup};
+    /// let server = Setup::default()
+    ///     .allow(IngressRule::tcp(8080..=8080).from_anywhere())
+    ///     .allow(IngressRule::icmp());
+    /// ```
+    pub fn allow(mut self, rule: IngressRule) -> Self {
+        self.ingress.push(rule);
+        self
+    }
+
+    /// Launch the machine into a [cluster placement
+    /// group](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/placement-groups.html#placement-groups-cluster),
+    /// so that it sits close to the other machines in the group, with low-latency, high-bandwidth
+    /// networking between them.
+    ///
+    /// Pass [`PlacementGroup::Auto`] to share a group with the other machines of the
+    /// [`RegionSpec`] that do, or a name to share one with every machine that names it. A cluster
+    /// placement group only spans one availability zone, and EC2 may not have room for many
+    /// machines in it, so keep the groups to what the experiment needs.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{PlacementGroup, Setup};
+    /// let server = Setup::default()
+    ///     .instance_type("c5n.large")
+    ///     .placement_group(PlacementGroup::Auto);
+    /// let client = Setup::default()
+    ///     .instance_type("c5n.large")
+    ///     .placement_group("latency-experiment");
+    /// ```
+    pub fn placement_group(self, group: impl Into<PlacementGroup>) -> Self {
+        Self {
+            placement_group: Some(group.into()),
+            ..self
+        }
+    }
+}
+
+/// Make `n` machine descriptors like `m`, of which at least `on_demand` are on-demand instances.
+///
+/// The machines are named like with [`make_multiple`](crate::make_multiple). The first
+/// `on_demand` of them are [on-demand](Setup::on_demand) and [critical](Priority::Critical), so
+/// that the core of the experiment is sure to come up, and the rest are launched according to the
+/// launcher's [`LaunchMode`], usually as cheaper spot instances. With [`LaunchMode::TrySpot`],
+/// those may end up on-demand as well. [`Launcher::lifecycles`] tells which machines are which.
+///
+/// Give `m` [`Priority::BestEffort`] to have the launch carry on without the spot instances
+/// that do not come up.
+///
+/// ```rust
+/// use tsunami::providers::aws::{self, Priority, Setup};
+/// let workers = aws::hybrid(32, "worker", 4, Setup::default().priority(Priority::BestEffort));
+/// assert!(workers[3].1.is_on_demand());
+/// assert!(!workers[4].1.is_on_demand());
+/// ```
+pub fn hybrid(n: usize, nickname_prefix: &str, on_demand: usize, m: Setup) -> Vec<(String, Setup)> {
+    crate::make_multiple(n, nickname_prefix, m)
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, m))| {
+            if i < on_demand {
+                (name, m.on_demand().priority(Priority::Critical))
+            } else {
+                (name, m)
+            }
+        })
+        .collect()
+}
+
+/// How an instance was purchased. See [`Launcher::lifecycles`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lifecycle {
+    /// A regular on-demand instance.
+    OnDemand,
+    /// A spot instance, which EC2 may reclaim.
+    Spot,
+}
+
+/// A limit on what a set of machines may cost per hour.
+///
+/// tsunami does not know EC2's prices, so give it the hourly on-demand price of each instance type
+/// the machines use with [`Budget::price`]. [`Budget::fit`] then checks the machines against the
+/// limit. By default it fails if they cost too much; with [`Budget::downsize_to`], it shrinks
+/// them until they fit instead, and reports what it changed.
+///
+/// Machines are priced as on-demand instances unless the budget is [for spot
+/// instances](Budget::spot), in which case those that are not [`Setup::on_demand`] cost their
+/// [spot price](Budget::spot_price). Use [`Launcher::budget`] to have the launcher fit the
+/// machines before it launches them.
+///
+/// ```rust
+/// use tsunami::providers::aws::{self, Budget, Priority, Setup};
+/// let m = Setup::default().instance_type("c5.4xlarge");
+/// let ms = aws::hybrid(8, "worker", 2, m.priority(Priority::BestEffort));
+/// let budget = Budget::per_hour(2.0)
+///     .price("c5.4xlarge", 0.68)
+///     .price("c5.2xlarge", 0.34)
+///     .downsize_to(vec!["c5.4xlarge", "c5.2xlarge"]);
+/// let (ms, changes) = budget.fit(ms).unwrap();
+/// assert!(budget.estimate(&ms).unwrap() <= 2.0);
+/// for change in changes {
+///     println!("{}", change);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Budget {
+    per_hour: f64,
+    prices: HashMap<String, f64>,
+    spot_prices: HashMap<String, f64>,
+    spot: bool,
+    ranked: Option<Vec<String>>,
+}
+
+/// A change [`Budget::fit`] made to the machines to fit them into the budget.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Downsized {
+    /// The machine was given a smaller instance type.
+    Resized {
+        /// The nickname of the machine.
+        nickname: String,
+        /// The instance type it had.
+        from: String,
+        /// The instance type it has now.
+        to: String,
+    },
+    /// The machine was left out.
+    Dropped {
+        /// The nickname of the machine.
+        nickname: String,
+        /// The instance type it had.
+        instance_type: String,
+    },
+}
+
+impl std::fmt::Display for Downsized {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Downsized::Resized { nickname, from, to } => {
+                write!(f, "resized {} from {} to {}", nickname, from, to)
+            }
+            Downsized::Dropped {
+                nickname,
+                instance_type,
+            } => write!(f, "dropped {} ({})", nickname, instance_type),
+        }
+    }
+}
+
+impl Budget {
+    /// A budget of `dollars` per hour.
+    pub fn per_hour(dollars: f64) -> Self {
+        Budget {
+            per_hour: dollars,
+            prices: HashMap::new(),
+            spot_prices: HashMap::new(),
+            spot: false,
+            ranked: None,
+        }
+    }
+
+    /// Set the hourly on-demand price, in US dollars, of `instance_type`.
+    pub fn price(mut self, instance_type: impl ToString, dollars_per_hour: f64) -> Self {
+        self.prices
+            .insert(instance_type.to_string(), dollars_per_hour);
+        self
+    }
+
+    /// Set the expected hourly spot price, in US dollars, of `instance_type`.
+    ///
+    /// Spot machines of a type without a spot price are priced at its on-demand price, which is
+    /// the most EC2 charges for them by default.
+    pub fn spot_price(mut self, instance_type: impl ToString, dollars_per_hour: f64) -> Self {
+        self.spot_prices
+            .insert(instance_type.to_string(), dollars_per_hour);
+        self
+    }
+
+    /// Price the machines that are not [`Setup::on_demand`] as spot instances.
+    ///
+    /// [`Launcher::budget`] sets this from its [`LaunchMode`].
+    pub fn spot(mut self) -> Self {
+        self.spot = true;
+        self
+    }
+
+    /// Downsize machines that do not fit into the budget, rather than failing.
+    ///
+    /// `instance_types` ranks the types machines may be shrunk to, from most to least preferred.
+    /// A machine of one of these types can be moved down the list, one step at a time. Downsizing
+    /// goes in this order, until the machines fit:
+    ///
+    /// 1. [best-effort](Priority::BestEffort) machines are moved to smaller types;
+    /// 2. best-effort machines are left out, the most expensive first;
+    /// 3. [critical](Priority::Critical) machines are moved to smaller types.
+    ///
+    /// Critical machines are never left out. Resized machines also lose their
+    /// [alternative instance types](Setup::alternative_instance_types).
+    pub fn downsize_to<S: ToString>(mut self, instance_types: impl IntoIterator<Item = S>) -> Self {
+        self.ranked = Some(instance_types.into_iter().map(|t| t.to_string()).collect());
+        self
+    }
+
+    /// The estimated cost of `machines`, in US dollars per hour.
+    pub fn estimate(&self, machines: &[(String, Setup)]) -> Result<f64, Report> {
+        self.check()?;
+        machines.iter().map(|(_, m)| self.price_of(m)).sum()
+    }
+
+    // Checks that the limit and every price are non-negative numbers.
+    fn check(&self) -> Result<(), Report> {
+        eyre::ensure!(
+            self.per_hour.is_finite() && self.per_hour >= 0.0,
+            "budget must be a non-negative number of US dollars per hour, not {}",
+            self.per_hour
+        );
+        for (t, price) in self.prices.iter().chain(&self.spot_prices) {
+            eyre::ensure!(
+                price.is_finite() && *price >= 0.0,
+                "price of instance type {} must be a non-negative number of US dollars per hour, \
+                 not {}",
+                t,
+                price
+            );
+        }
+        Ok(())
+    }
+
+    fn price_of(&self, m: &Setup) -> Result<f64, Report> {
+        self.type_price(m, &m.instance_type)
+            .ok_or_else(|| eyre!("no price for instance type {}", m.instance_type))
+            .suggestion("Set it with Budget::price")
+    }
+
+    // What `m` would cost with `instance_type`.
+    fn type_price(&self, m: &Setup, instance_type: &str) -> Option<f64> {
+        if self.spot && !m.on_demand {
+            if let Some(&price) = self.spot_prices.get(instance_type) {
+                return Some(price);
+            }
+        }
+        self.prices.get(instance_type).copied()
+    }
+
+    /// Check that `machines` fit into the budget, and downsize them if they do not and
+    /// [`Budget::downsize_to`] was set.
+    ///
+    /// Returns the machines to launch, and what was changed to get there.
+    pub fn fit(
+        &self,
+        mut machines: Vec<(String, Setup)>,
+    ) -> Result<(Vec<(String, Setup)>, Vec<Downsized>), Report> {
+        let over = |ms: &[(String, Setup)]| -> Result<Option<f64>, Report> {
+            let cost = self.estimate(ms)?;
+            Ok(if cost > self.per_hour {
+                Some(cost)
+            } else {
+                None
+            })
+        };
+        let mut cost = match over(&machines)? {
+            None => return Ok((machines, Vec::new())),
+            Some(cost) => cost,
+        };
+        let ranked = match self.ranked {
+            Some(ref ranked) => ranked,
+            None => {
+                return Err(eyre!(
+                    "machines are estimated to cost ${:.2}/h, over the budget of ${:.2}/h",
+                    cost,
+                    self.per_hour
+                ))
+                .suggestion("Use fewer or smaller machines, or allow Budget::downsize_to");
+            }
+        };
+        // every price that downsizing may need has to be known up front.
+        for t in ranked {
+            eyre::ensure!(
+                self.prices.contains_key(t),
+                "no price for instance type {}",
+                t
+            );
+        }
+
+        machines.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut changes = Vec::new();
+        for phase in 0..3 {
+            let priority = match phase {
+                0 | 1 => Priority::BestEffort,
+                _ => Priority::Critical,
+            };
+            loop {
+                let step = if phase == 1 {
+                    self.drop_one(&mut machines, priority)
+                } else {
+                    self.shrink_one(&mut machines, priority, ranked)
+                };
+                match step {
+                    Some(change) => {
+                        tracing::warn!(%change, "downsizing to fit the budget");
+                        changes.push(change);
+                    }
+                    None => break,
+                }
+                match over(&machines)? {
+                    None => return Ok((machines, changes)),
+                    Some(c) => cost = c,
+                }
+            }
+        }
+
+        let changed = changes.iter().map(ToString::to_string).join(", ");
+        Err(eyre!(
+            "machines are estimated to cost ${:.2}/h even after downsizing ({}), over the budget \
+             of ${:.2}/h",
+            cost,
+            changed,
+            self.per_hour
+        ))
+        .suggestion("Use fewer critical machines, or rank smaller instance types")
+    }
+
+    // Moves the machine of `priority` whose next smaller type saves the most one step down
+    // `ranked`.
+    fn shrink_one(
+        &self,
+        machines: &mut [(String, Setup)],
+        priority: Priority,
+        ranked: &[String],
+    ) -> Option<Downsized> {
+        let (_, i, to) = machines
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, m))| m.priority == priority)
+            .filter_map(|(i, (_, m))| {
+                let at = ranked.iter().position(|t| *t == m.instance_type)?;
+                let to = ranked.get(at + 1)?;
+                let saving = self.type_price(m, &m.instance_type)? - self.type_price(m, to)?;
+                Some((saving, i, to))
+            })
+            .filter(|(saving, _, _)| *saving > 0.0)
+            // the biggest saving, and then the first machine by nickname
+            .max_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)))?;
+        let (nickname, m) = &mut machines[i];
+        let from = std::mem::replace(&mut m.instance_type, to.clone());
+        m.alternative_instance_types.clear();
+        Some(Downsized::Resized {
+            nickname: nickname.clone(),
+            from,
+            to: to.clone(),
+        })
+    }
+
+    // Leaves out the most expensive machine of `priority`.
+    fn drop_one(
+        &self,
+        machines: &mut Vec<(String, Setup)>,
+        priority: Priority,
+    ) -> Option<Downsized> {
+        let i = machines
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, m))| m.priority == priority)
+            .filter_map(|(i, (_, m))| Some((i, self.type_price(m, &m.instance_type)?)))
+            .max_by(|(i, a), (j, b)| a.total_cmp(b).then(j.cmp(i)))
+            .map(|(i, _)| i)?;
+        let (nickname, m) = machines.remove(i);
+        Some(Downsized::Dropped {
+            nickname,
+            instance_type: m.instance_type,
+        })
+    }
+}
+
+/// AWS EC2 spot instance launcher.
+///
+/// This is a lower-level API. Most users will use [`crate::TsunamiBuilder::spawn`].
+///
+/// Each individual region is handled by `RegionLauncher`.
+///
+/// Regions are initialized and launched concurrently, and the setup functions for each machine
+/// are executed in parallel within each region.
+///
+/// By default, `Launcher` launches instances using 6-hour [defined
+/// duration](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/spot-requests.html#fixed-duration-spot-instances)
+/// spot requests.
+#[derive(Educe)]
+#[educe(Debug)]
+pub struct Launcher<P = DefaultCredentialsProvider> {
+    #[educe(Debug(ignore))]
+    credential_provider: Box<dyn Fn() -> Result<P, Report> + Send + Sync>,
+    mode: LaunchMode,
+    use_open_ports: bool,
+    max_experiment_duration: Option<time::Duration>,
+    all_or_nothing: bool,
+    ssm_fallback: bool,
+    elastic_ips: bool,
+    batch_size: Option<usize>,
+    request_size: Option<usize>,
+    spot_price: Option<f64>,
+    spot_fallback: Option<time::Duration>,
+    connect_concurrency: Option<usize>,
+    readiness: Readiness,
+    ssh_retry: SshRetry,
+    ingress: Option<Vec<IngressRule>>,
+    final_ingress: Option<Vec<IngressRule>>,
+    restrict_ssh: bool,
+    caller_ip: Option<std::net::Ipv4Addr>,
+    near: Option<Affinity>,
+    security_group: Option<String>,
+    key_pair: Option<(String, std::path::PathBuf)>,
+    tags: BTreeMap<String, String>,
+    budget: Option<Budget>,
+    downsized: Vec<Downsized>,
+    regions: HashMap<RegionSpec, RegionLauncher>,
+}
+
+impl Default for Launcher {
+    fn default() -> Self {
+        Launcher {
+            credential_provider: Box::new(|| Ok(DefaultCredentialsProvider::new()?)),
+            mode: LaunchMode::DefinedDuration { hours: 6 },
+            use_open_ports: false,
+            max_experiment_duration: None,
+            all_or_nothing: false,
+            ssm_fallback: false,
+            elastic_ips: false,
+            batch_size: None,
+            request_size: None,
+            spot_price: None,
+            spot_fallback: None,
+            connect_concurrency: None,
+            readiness: Readiness::default(),
+            ssh_retry: SshRetry::default(),
+            ingress: None,
+            final_ingress: None,
+            restrict_ssh: false,
+            caller_ip: None,
+            near: None,
+            security_group: None,
+            key_pair: None,
+            tags: Default::default(),
+            budget: None,
+            downsized: Vec::new(),
+            regions: Default::default(),
+        }
+    }
+}
+
+impl<P> Launcher<P> {
+    /// Set defined duration instance max instance duration.
+    ///
+    /// The lifetime of such instances must be declared in advance (1-6 hours).
+    /// This method thus clamps `t` to be between 1 and 6.
+    ///
+    /// By default, we use 6 hours (the maximum).
+    ///
+    /// This method also changes the mode to [`LaunchMode::DefinedDuration`].
+    #[deprecated(note = "prefer set_mode")]
+    pub fn set_max_instance_duration(&mut self, t: usize) -> &mut Self {
+        self.mode = LaunchMode::duration_spot(t);
+        self
+    }
+
+    /// Set the launch mode to use for future instances.
+    ///
+    /// See [`LaunchMode`] for more details.
+    pub fn set_mode(&mut self, mode: LaunchMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// The machines spawned on this launcher will have
+    /// ports open to the public Internet.
+    ///
+    /// This has no effect once [`Launcher::ingress`] chooses the rules.
+    pub fn open_ports(&mut self) -> &mut Self {
+        self.use_open_ports = true;
+        self
+    }
+
+    /// Have the instances shut themselves down once `d` has passed since they booted.
+    ///
+    /// This is a safety net in case the controller crashes and never gets to
+    /// [`terminate_all`](super::Launcher::terminate_all): each instance is launched with a user
+    /// data script that schedules a shutdown, so it takes effect even if tsunami never connects
+    /// to the instance. With [`ShutdownBehavior::Terminate`] and
+    /// [`InterruptionBehavior::Terminate`] (the defaults), shutting down terminates the instance;
+    /// otherwise, it is only stopped. The image must run user data scripts, as images with
+    /// `cloud-init` do. The shutdown time is rounded up to the minute.
+    ///
+    /// Defined duration spot instances (see [`LaunchMode::DefinedDuration`]) already have a
+    /// kill switch in the form of their duration.
+    pub fn max_experiment_duration(&mut self, d: time::Duration) -> &mut Self {
+        self.max_experiment_duration = Some(d);
+        self
+    }
+
+    /// Either launch every machine of a `launch` or `spawn`, or none of them.
+    ///
+    /// By default, machines that came up stay running when others fail to, so that a failed
+    /// launch can be inspected or retried. With `all_or_nothing(true)`, a failed launch instead
+    /// cancels its spot requests and terminates every instance it started, in all regions, before
+    /// returning the error. Use this for experiments that need exactly the requested number of
+    /// machines, where a partial fleet only costs money.
+    ///
+    /// Machines from earlier, successful launches are not affected.
+    pub fn all_or_nothing(&mut self, enabled: bool) -> &mut Self {
+        self.all_or_nothing = enabled;
+        self
+    }
+
+    /// Reach instances through AWS Systems Manager Session Manager when direct SSH does not work.
+    ///
+    /// Instances in private subnets, or behind firewalls that block port 22, are never reachable
+    /// from the machine running tsunami. With `ssm_fallback(true)`, an instance that does not
+    /// accept SSH connections is instead tried through an SSH session tunnelled over Session
+    /// Manager, and if that works, all later connections to it go the same way. Instances
+    /// launched into an existing [subnet](Setup::subnet) then also get a public IP address only if
+    /// the subnet assigns one by default.
+    ///
+    /// This needs the [AWS CLI](https://aws.amazon.com/cli/) and the [Session Manager
+    /// plugin](https://docs.aws.amazon.com/systems-manager/latest/userguide/session-manager-working-with-install-plugin.html)
+    /// on the local machine, and the instances must be registered with Systems Manager, e.g.
+    /// through an instance profile that grants `AmazonSSMManagedInstanceCore`. Instances that
+    /// have no public IP address at all are then reachable too, and report their private address
+    /// as [`Machine::public_ip`](crate::Machine::public_ip).
+    pub fn ssm_fallback(&mut self, enabled: bool) -> &mut Self {
+        self.ssm_fallback = enabled;
+        self
+    }
+
+    /// Give each instance an [Elastic IP
+    /// address](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/elastic-ip-addresses-eip.html).
+    ///
+    /// An instance's regular public address changes when it is stopped and started again, while
+    /// an Elastic IP address stays the same, so machines that are rebooted during a long
+    /// experiment keep their [`Machine::public_ip`](crate::Machine::public_ip). The addresses are
+    /// allocated once the instances are running, and released at teardown.
+    ///
+    /// EC2 allows only five Elastic IP addresses per region by default.
+    pub fn elastic_ips(&mut self, enabled: bool) -> &mut Self {
+        self.elastic_ips = enabled;
+        self
+    }
+
+    /// Launch at most `n` machines at a time in each region.
+    ///
+    /// By default, all the machines of a region are requested at once, so none of them are ready
+    /// until EC2 has found capacity for all of them. With a batch size, the machines are
+    /// requested, waited for, and set up `n` at a time, [critical](Priority::Critical) machines
+    /// first, so that the first ones are usable as soon as possible. Requesting fewer spot
+    /// instances at once also makes each request easier to fulfil.
+    pub fn batch_size(&mut self, n: usize) -> &mut Self {
+        self.batch_size = Some(n.max(1));
+        self
+    }
+
+    /// Ask for at most `n` instances in each EC2 request.
+    ///
+    /// Machines that agree on everything that goes into a request, like their instance type, are
+    /// normally launched with a single spot or on-demand request for all of them. A request for
+    /// very many instances can run into EC2's limits, and if it fails, all of its machines fail
+    /// with it. With a request size, such machines are split up into several requests of at most
+    /// `n` instances, which are all made right away and tracked together. Unlike
+    /// [`Launcher::batch_size`], this does not change when the machines are ready.
+    pub fn request_size(&mut self, n: usize) -> &mut Self {
+        self.request_size = Some(n.max(1));
+        self
+    }
+
+    /// Pay at most `usd_per_hour` US dollars per hour for each spot instance, unless its
+    /// [`Setup::spot_price`] says otherwise.
+    ///
+    /// By default, spot instances cost at most their on-demand price. Launches fail if
+    /// `usd_per_hour` is not a positive number.
+    pub fn spot_price(&mut self, usd_per_hour: f64) -> &mut Self {
+        self.spot_price = Some(usd_per_hour);
+        self
+    }
+
+    /// Launch machines as on-demand instances if their spot requests are not fulfilled within
+    /// `wait`.
+    ///
+    /// By default, spot requests that EC2 leaves open fail the launch with a [`WaitLimitError`]
+    /// once the `max_wait` given to `spawn` runs out, or wait forever without one. With a fallback, the spot requests of a
+    /// launch that are still open after `wait` are cancelled, any spot instances they did get are
+    /// terminated, and the same machines are then launched as on-demand instances, with the full
+    /// `max_wait` to come up. This applies in every spot [`LaunchMode`].
+    pub fn spot_fallback_after(&mut self, wait: time::Duration) -> &mut Self {
+        self.spot_fallback = Some(wait);
+        self
+    }
+
+    /// Set up at most `n` SSH connections at a time in
+    /// [`connect_all`](super::Launcher::connect_all) and [`Launcher::connect_stream`].
+    ///
+    /// Connecting to a large fleet all at once exhausts local file descriptors and processes, and
+    /// trips rate limits on the way, so by default at most 64 connections are set up at a time,
+    /// across all regions.
+    pub fn connect_concurrency(&mut self, n: usize) -> &mut Self {
+        self.connect_concurrency = Some(n.max(1));
+        self
+    }
+
+    /// Only start connecting to instances once they are as ready as `r` says.
+    ///
+    /// Launching waits for every instance to be ready first, and then connects to each of them
+    /// under the [`Launcher::ssh_retry`] policy. By default, instances are ready as soon as they
+    /// are [running](Readiness::Running).
+    pub fn readiness(&mut self, r: Readiness) -> &mut Self {
+        self.readiness = r;
+        self
+    }
+
+    /// Retry connecting to each ready instance as `policy` says.
+    ///
+    /// By default, each connection attempt may take 30 seconds, and a failed one is retried after
+    /// a second for as long as the `max_wait` of the launch allows. The launch fails, naming every
+    /// instance that could not be reached, only once some instance has run out of attempts.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tsunami::providers::aws::{Launcher, SshRetry};
+    /// let mut l = Launcher::default();
+    /// l.ssh_retry(SshRetry {
+    ///     timeout: Duration::from_secs(10),
+    ///     give_up_after: Some(Duration::from_secs(5 * 60)),
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn ssh_retry(&mut self, policy: SshRetry) -> &mut Self {
+        self.ssh_retry = policy;
+        self
+    }
+
+    /// Admit only the traffic of `rules` into the temporary security groups, plus SSH and ICMP
+    /// from the machine running tsunami (see [`Launcher::restrict_ssh`]), and whatever each
+    /// machine [allows](Setup::allow).
+    ///
+    /// By default, the machines admit all TCP and UDP traffic from their own network, or from
+    /// anywhere with [`Launcher::open_ports`].
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{IngressRule, Launcher};
+    /// let mut l = Launcher::default();
+    /// l.ingress(vec![IngressRule::tcp(5000..=5010), IngressRule::udp(5000..=5010)]);
+    /// ```
+    pub fn ingress(&mut self, rules: impl IntoIterator<Item = IngressRule>) -> &mut Self {
+        self.ingress = Some(rules.into_iter().collect());
+        self
+    }
+
+    /// Once the machines of a `launch` or `spawn` are set up, restrict their security groups to
+    /// `rules`, plus SSH and ICMP from the machine running tsunami (see [`Launcher::restrict_ssh`]),
+    /// and ICMP from the machines' own network so that path MTU discovery between them keeps
+    /// working.
+    ///
+    /// Setup often needs more access than the experiment itself, e.g. to download packages or to
+    /// bootstrap a cluster. With this, the machines start out with the usual
+    /// [rules](Launcher::ingress), and every other rule is revoked before the launch returns, so
+    /// the measured phase runs under the final policy. Each revoked rule, and the final policy, is
+    /// logged at the `info` level.
+    ///
+    /// This is specific to EC2: the network security group that the Azure launcher creates admits
+    /// all inbound traffic for the whole run.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{IngressRule, Launcher};
+    /// let mut l = Launcher::default();
+    /// l.open_ports()
+    ///     .tighten_after_setup(vec![IngressRule::tcp(7000..=7000)]);
+    /// ```
+    pub fn tighten_after_setup(
+        &mut self,
+        rules: impl IntoIterator<Item = IngressRule>,
+    ) -> &mut Self {
+        self.final_ingress = Some(rules.into_iter().collect());
+        self
+    }
+
+    /// Admit SSH and ICMP only from the public IP address of the machine running tsunami, rather
+    /// than from the whole Internet.
+    ///
+    /// The address is looked up with [`caller_ip`] when machines are first launched. This only
+    /// works if all of tsunami's traffic leaves from that one address, so it is off by default;
+    /// behind a NAT with several public addresses, use [`RegionLauncher::ssh_from`] with the whole
+    /// range instead.
+    ///
+    /// ```rust
+    /// let mut l = tsunami::providers::aws::Launcher::default();
+    /// l.restrict_ssh(true);
+    /// ```
+    pub fn restrict_ssh(&mut self, enabled: bool) -> &mut Self {
+        self.restrict_ssh = enabled;
+        self
+    }
+
+    // Where the machines admit SSH from, looking up the caller's address on first use.
+    async fn ssh_from(&mut self) -> Result<Option<String>, Report> {
+        if !self.restrict_ssh {
+            return Ok(None);
+        }
+        let ip = match self.caller_ip {
+            Some(ip) => ip,
+            None => {
+                let ip = caller_ip()
+                    .await
+                    .wrap_err("failed to find the public address to admit SSH from")?;
+                tracing::info!(%ip, "admitting SSH only from this machine");
+                *self.caller_ip.insert(ip)
+            }
+        };
+        Ok(Some(format!("{}/32", ip)))
+    }
+
+    /// Launch machines into the network of `affinity`, e.g. an existing subnet or VPC, unless
+    /// their [`Setup::near`] says otherwise.
+    ///
+    /// The affinity applies in every region, so it is only useful if all machines go to the region
+    /// the resource is in.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{Affinity, Launcher};
+    /// let mut l = Launcher::default();
+    /// l.near(Affinity::Subnet(String::from("subnet-0123456789abcdef0")))
+    ///     .ssm_fallback(true);
+    /// ```
+    pub fn near(&mut self, affinity: Affinity) -> &mut Self {
+        self.near = Some(affinity);
+        self
+    }
+
+    /// Launch machines into the existing security group `group_id`, unless their
+    /// [`Setup::security_group`] says otherwise.
+    ///
+    /// With this, tsunami does not create any security groups. Like [`Launcher::near`], it applies
+    /// in every region, and security groups belong to a single region.
+    pub fn with_security_group(&mut self, group_id: impl ToString) -> &mut Self {
+        self.security_group = Some(group_id.to_string());
+        self
+    }
+
+    /// Launch machines with the existing EC2 key pair `name`, whose private key is at
+    /// `private_key_path`, rather than with a temporary key pair.
+    ///
+    /// This is for accounts that only allow pre-registered keys, or that do not allow creating
+    /// key pairs at all. Since the key outlives the launcher, it also lets you SSH to the machines
+    /// yourself, e.g. to inspect them after a failed run. tsunami neither deletes the key pair nor
+    /// the private key. Key pairs belong to a single region, so the key pair must have been
+    /// imported under `name` into every region that machines are launched in.
+    ///
+    /// ```rust
+    /// let mut l = tsunami::providers::aws::Launcher::default();
+    /// l.with_key_pair("lab-key", "/home/alice/.ssh/lab-key.pem");
+    /// ```
+    pub fn with_key_pair(
+        &mut self,
+        name: impl ToString,
+        private_key_path: impl Into<std::path::PathBuf>,
+    ) -> &mut Self {
+        self.key_pair = Some((name.to_string(), private_key_path.into()));
+        self
+    }
+
+    /// Tag every resource that tsunami creates with `tags`, in addition to its own [`RUN_TAG`].
+    ///
+    /// That is the instances, spot requests, security groups, key pairs, placement groups, results
+    /// snapshots, and volumes (except those that spot instances launch with). Accounts that are
+    /// shared between people or projects often need tags like `owner` or a cost center to
+    /// attribute and audit what runs in them. Tags from several calls add up, and a later value
+    /// for a key replaces an earlier one.
+    ///
+    /// Tags only apply to resources created after they are set, so set them before the first
+    /// launch. `Name` and the `tsunami:` tags are reserved for tsunami's own use, and keys cannot
+    /// start with `aws:`.
+    ///
+    /// ```rust
+    /// let mut l = tsunami::providers::aws::Launcher::default();
+    /// l.with_tags(vec![("owner", "alice"), ("cost-center", "systems-lab")]);
+    /// ```
+    pub fn with_tags<K: ToString, V: ToString>(
+        &mut self,
+        tags: impl IntoIterator<Item = (K, V)>,
+    ) -> &mut Self {
+        self.tags.extend(
+            tags.into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        );
+        self
+    }
+
+    /// Fit the machines of every launch into `budget` before launching them.
+    ///
+    /// A launch whose machines do not fit fails before anything is launched, unless the budget
+    /// [downsizes](Budget::downsize_to) them; what it changed is logged and available from
+    /// [`Launcher::downsized`]. The launch mode decides how the machines are priced: they are
+    /// priced as [spot instances](Budget::spot) with [`LaunchMode::DefinedDuration`] and
+    /// [`LaunchMode::Fleet`], and as on-demand instances otherwise, since
+    /// [`LaunchMode::TrySpot`] may fall back to them.
+    ///
+    /// ```rust
+    /// use tsunami::providers::aws::{Budget, Launcher};
+    /// let mut l = Launcher::default();
+    /// l.budget(Budget::per_hour(5.0).price("t3.small", 0.0208));
+    /// ```
+    pub fn budget(&mut self, budget: Budget) -> &mut Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// The changes [`Launcher::budget`] made to the machines launched so far.
+    pub fn downsized(&self) -> &[Downsized] {
+        &self.downsized
+    }
+
+    // Fits `machines` into the budget, if there is one, and records what that changed.
+    fn fit_budget(
+        &mut self,
+        machines: Vec<(String, Setup)>,
+    ) -> Result<Vec<(String, Setup)>, Report> {
+        let mut budget = match self.budget {
+            Some(ref budget) => budget.clone(),
+            None => return Ok(machines),
+        };
+        budget.spot = matches!(
+            self.mode,
+            LaunchMode::DefinedDuration { .. } | LaunchMode::Fleet
+        );
+        let (machines, changes) = budget
+            .fit(machines)
+            .wrap_err("machines do not fit into the budget")?;
+        self.downsized.extend(changes);
+        Ok(machines)
+    }
+
+    // Gives the machines that do not choose a network, security group, or spot price the default
+    // ones, and checks the spot price they end up with.
+    fn with_defaults(&self, mut m: Setup) -> Result<Setup, Report> {
+        if m.near.is_none() {
+            m.near = self.near.clone();
+        }
+        if m.security_group.is_none() {
+            m.security_group = self.security_group.clone();
+        }
+        if m.spot_price.is_none() {
+            m.spot_price = self.spot_price;
+        }
+        if let Some(price) = m.spot_price {
+            eyre::ensure!(
+                price.is_finite() && price > 0.0,
+                "spot price must be a positive number of US dollars per hour, not {}",
+                price
+            );
+        }
+        Ok(m)
+    }
+
+    /// Set the credential provider used to authenticate to EC2.
+    ///
+    /// The provided function is called once for each region, and is expected to produce a
+    /// [`P: ProvideAwsCredentials`](https://docs.rs/rusoto_core/0.40.0/rusoto_core/trait.ProvideAwsCredentials.html)
+    /// that gives access to the region in question.
+    pub fn with_credentials<P2>(
+        self,
+        f: impl Fn() -> Result<P2, Report> + Send + Sync + 'static,
+    ) -> Launcher<P2> {
+        Launcher {
+            credential_provider: Box::new(f),
+            mode: self.mode,
+            use_open_ports: self.use_open_ports,
+            max_experiment_duration: self.max_experiment_duration,
+            all_or_nothing: self.all_or_nothing,
+            ssm_fallback: self.ssm_fallback,
+            elastic_ips: self.elastic_ips,
+            batch_size: self.batch_size,
+            request_size: self.request_size,
+            spot_price: self.spot_price,
+            spot_fallback: self.spot_fallback,
+            connect_concurrency: self.connect_concurrency,
+            readiness: self.readiness,
+            ssh_retry: self.ssh_retry,
+            ingress: self.ingress,
+            final_ingress: self.final_ingress,
+            restrict_ssh: self.restrict_ssh,
+            caller_ip: self.caller_ip,
+            near: self.near,
+            security_group: self.security_group,
+            key_pair: self.key_pair,
+            tags: self.tags,
+            budget: self.budget,
+            downsized: self.downsized,
+            regions: self.regions,
+        }
+    }
+
+    /// Access the [`RegionLauncher`] for `region`, if any machines have been launched there.
+    ///
+    /// Machines launched with an [`AvailabilityZoneSpec`] other than `Any` are in a separate
+    /// [`RegionSpec`] from the rest of their region.
+    ///
+    /// Use this to issue EC2 calls of your own against the same client and resources:
+    ///
+    /// ```rust,no_run
+    /// # async fn f(l: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+    /// use tsunami::providers::aws::rusoto_ec2::{self, Ec2};
+    /// let r = l.region(tsunami::providers::aws::Region::UsEast1).unwrap();
+    /// let client = r.client().expect("region launchers are connected once they launch");
+    /// for (name, instance_id) in r.instance_ids() {
+    ///     client
+    ///         .monitor_instances(rusoto_ec2::MonitorInstancesRequest {
+    ///             instance_ids: vec![instance_id.to_string()],
+    ///             ..Default::default()
+    ///         })
+    ///         .await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn region(&self, region: impl Into<RegionSpec>) -> Option<&RegionLauncher> {
+        self.regions.get(&region.into())
+    }
+
+    /// Mutable access to the [`RegionLauncher`] for `region`. See [`Launcher::region`].
+    pub fn region_mut(&mut self, region: impl Into<RegionSpec>) -> Option<&mut RegionLauncher> {
+        self.regions.get_mut(&region.into())
+    }
+
+    /// Iterate over all the [`RegionLauncher`]s.
+    pub fn regions(&self) -> impl Iterator<Item = (&RegionSpec, &RegionLauncher)> {
+        self.regions.iter()
+    }
+
+    /// Snapshot the [results volumes](Setup::results_volume_at) of every machine, and return the
+    /// ids of all the results snapshots taken so far, by nickname.
+    ///
+    /// [`terminate_all`](super::Launcher::terminate_all) snapshots the results volumes too, but
+    /// since it consumes the launcher, call this first to learn the snapshot ids. Each volume is
+    /// only snapshotted once, so `terminate_all` then has nothing left to do.
+    pub async fn snapshot_results(&mut self) -> Result<HashMap<String, Vec<String>>, Report> {
+        let mut snapshots = HashMap::new();
+        for r in self.regions.values_mut() {
+            snapshots.extend(r.snapshot_results().await?);
+        }
+        Ok(snapshots)
+    }
+
+    /// Ask EC2 for the final state of every machine, and return it by nickname. See
+    /// [`RegionLauncher::instance_reports`].
+    ///
+    /// [`terminate_all`](super::Launcher::terminate_all) records and logs these reports too, but
+    /// since it consumes the launcher, call this first to keep them.
+    pub async fn instance_reports(&mut self) -> Result<HashMap<String, InstanceReport>, Report> {
+        let mut reports = HashMap::new();
+        for r in self.regions.values_mut() {
+            reports.extend(r.instance_reports().await?);
+        }
+        Ok(reports)
+    }
+
+    /// Establish SSH connections to the machines in every region, and yield each one as soon as
+    /// it is up.
+    ///
+    /// Unlike [`connect_all`](super::Launcher::connect_all), this lets the first machines of a
+    /// large fleet be used while the others are still being connected to, and does not give up on
+    /// the rest when one fails. At most [`Launcher::connect_concurrency`] connections are set up at
+    /// a time.
+    ///
+    /// ```rust,no_run
+    /// # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+    /// use futures_util::stream::StreamExt;
+    /// let mut machines = aws.connect_stream();
+    /// while let Some(m) = machines.next().await {
+    ///     let (name, vm) = m?;
+    ///     vm.ssh.command("uptime").status().await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connect_stream<'l>(
+        &'l self,
+    ) -> impl Stream<Item = Result<(String, crate::Machine<'l>), Report>> + Send + 'l {
+        let instances = self.regions.values().flat_map(|r| {
+            r.instances
+                .iter()
+                .map(move |(instance_id, info)| (r, instance_id, info))
+        });
+        futures_util::stream::iter(instances)
+            .map(|(r, instance_id, info)| r.connect_instance(instance_id, info))
+            .buffer_unordered(self.connect_concurrency.unwrap_or(CONNECT_CONCURRENCY))
+    }
+
+    /// Whether each machine is an on-demand or a spot instance, by nickname.
+    ///
+    /// Machines that asked for spot instances may have been launched on-demand instead, with
+    /// [`LaunchMode::TrySpot`]. See [`hybrid`] for mixing the two on purpose.
+    pub fn lifecycles(&self) -> HashMap<String, Lifecycle> {
+        self.regions
+            .values()
+            .flat_map(RegionLauncher::lifecycles)
+            .collect()
+    }
+
+    /// The [`Resources`] created in every region so far.
+    pub fn resources(&self) -> Vec<Resources> {
+        self.regions
+            .values()
+            .map(RegionLauncher::resources)
+            .collect()
+    }
+
+    /// Fetch a CloudWatch metric for every machine. See [`RegionLauncher::metrics`].
+    #[cfg(feature = "cloudwatch")]
+    pub async fn metrics(
+        &self,
+        metric: &str,
+        period: time::Duration,
+        since: time::SystemTime,
+    ) -> Result<HashMap<String, Vec<MetricSample>>, Report> {
+        let mut samples = HashMap::new();
+        for r in self.regions.values() {
+            samples.extend(r.metrics(metric, period, since).await?);
+        }
+        Ok(samples)
+    }
+
+    /// Fetch a CloudWatch metric for every machine, like [`Launcher::metrics`], and save it with
+    /// the run's other results as `dir/<nickname>/<metric>.csv`.
+    ///
+    /// Each line is `timestamp,average,maximum,unit`, with empty fields for values CloudWatch did
+    /// not report. Existing files are overwritten.
+    #[cfg(feature = "cloudwatch")]
+    pub async fn save_metrics(
+        &self,
+        metric: &str,
+        period: time::Duration,
+        since: time::SystemTime,
+        dir: impl AsRef<std::path::Path>,
+    ) -> Result<HashMap<String, Vec<MetricSample>>, Report> {
+        let samples = self.metrics(metric, period, since).await?;
+        for (name, points) in &samples {
+            let dir = dir.as_ref().join(name);
+            std::fs::create_dir_all(&dir)
+                .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+            let path = dir.join(format!("{}.csv", metric));
+            std::fs::write(&path, metrics_csv(points))
+                .wrap_err_with(|| format!("failed to write {}", path.display()))?;
+        }
+        Ok(samples)
+    }
+
+    /// Wind down the machine `nickname` and terminate it, and leave all other machines running.
+    ///
+    /// See [`RegionLauncher::terminate_gracefully`].
+    pub async fn terminate_gracefully(
+        &mut self,
+        nickname: &str,
+        grace: time::Duration,
+    ) -> Result<(), Report> {
+        let r = self
+            .regions
+            .values_mut()
+            .find(|r| r.has_machine(nickname))
+            .ok_or_else(|| eyre!("no machine named {}", nickname))?;
+        r.terminate_gracefully(nickname, grace).await
+    }
+}
+
+impl<P> super::Launcher for Launcher<P>
+where
+    P: ProvideAwsCredentials + Send + Sync + 'static,
+{
+    type MachineDescriptor = Setup;
+
+    #[instrument(level = "debug", skip(self))]
+    fn launch<'l>(
+        &'l mut self,
+        l: super::LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(async move {
+            let prov = (*self.credential_provider)()?;
+            let mut l = l;
+            if l.region.near.is_none() {
+                l.region.near = self.near.clone();
+            }
+            if l.region.security_group.is_none() {
+                l.region.security_group = self.security_group.clone();
+            }
+            l.machines = l
+                .machines
+                .into_iter()
+                .map(|(name, m)| Ok((name, self.with_defaults(m)?)))
+                .collect::<Result<_, Report>>()?;
+            l.machines = self.fit_budget(l.machines)?;
+            let names: HashSet<_> = l.machines.iter().map(|(name, _)| name.clone()).collect();
+            check_tags(&self.tags)?;
+            let ssh_from = self.ssh_from().await?;
+            let Self {
+                use_open_ports,
+                mode,
+                max_experiment_duration,
+                all_or_nothing,
+                ssm_fallback,
+                elastic_ips,
+                batch_size,
+                request_size,
+                spot_fallback,
+                readiness,
+                ssh_retry,
+                ingress,
+                final_ingress,
+                tags,
+                key_pair,
+                ref mut regions,
+                ..
+            } = self;
+
+            if !regions.contains_key(&l.region) {
+                let region_span = tracing::debug_span!("new_region", region = %l.region.region.name(), az = %l.region.availability_zone);
+                let awsregion = RegionLauncher::open(&l.region, prov, *use_open_ports, tags.clone(), key_pair.as_ref())
+                .instrument(region_span)
+                .await?;
+                regions.insert(l.region.clone(), awsregion);
+            }
+
+            let region_span = tracing::debug_span!("region", region = %l.region);
+            let region = regions.get_mut(&l.region).unwrap();
+            region.ssm_fallback(*ssm_fallback);
+            region.elastic_ips(*elastic_ips);
+            region.max_experiment_duration = *max_experiment_duration;
+            region.batch_size = *batch_size;
+            region.request_size = *request_size;
+            region.spot_fallback = *spot_fallback;
+            region.readiness = *readiness;
+            region.ssh_retry = *ssh_retry;
+            region.ingress = ingress.clone();
+            region.ssh_from = ssh_from;
+            region.tags = tags.clone();
+            match region
+                .launch(mode.clone(), l.max_wait, l.machines)
+                .instrument(region_span)
+                .await
+            {
+                Err(e) if *all_or_nothing => return Err(super::rolled_back(e, region.roll_back(&names).await)),
+                r => r.map(drop)?,
+            }
+            if let Some(rules) = final_ingress {
+                region
+                    .tighten_ingress(rules.clone())
+                    .await
+                    .wrap_err("failed to tighten security group after setup")?;
+            }
+            Ok(())
+        }.in_current_span())
+    }
+
+    #[instrument(level = "debug", skip(self, max_wait))]
+    fn spawn<'l, I>(
+        &'l mut self,
+        descriptors: I,
+        max_wait: Option<std::time::Duration>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>>
+    where
+        I: IntoIterator<Item = (String, Self::MachineDescriptor)> + Send + 'static,
+        I: std::fmt::Debug,
+        I::IntoIter: Send,
+    {
+        Box::pin(
+            async move {
+                tracing::info!("spinning up tsunami");
+
+                // check that this works before unwrap() below
+                let _prov = (*self.credential_provider)()?;
+                let descriptors: Vec<_> = descriptors
+                    .into_iter()
+                    .map(|(name, m)| Ok((name, self.with_defaults(m)?)))
+                    .collect::<Result<_, Report>>()?;
+                let descriptors = self.fit_budget(descriptors)?;
+                check_tags(&self.tags)?;
+                let ssh_from = self.ssh_from().await?;
+                let Self {
+                    credential_provider,
+                    mode,
+                    use_open_ports,
+                    max_experiment_duration,
+                    all_or_nothing,
+                    ssm_fallback,
+                    elastic_ips,
+                    batch_size,
+                    request_size,
+                    spot_fallback,
+                    readiness,
+                    ssh_retry,
+                    ingress,
+                    final_ingress,
+                    tags,
+                    key_pair,
+                    regions,
+                    ..
+                } = self;
+                let use_open_ports = *use_open_ports;
+                let ssm_fallback = *ssm_fallback;
+                let elastic_ips = *elastic_ips;
+                let max_experiment_duration = *max_experiment_duration;
+                let batch_size = *batch_size;
+                let request_size = *request_size;
+                let spot_fallback = *spot_fallback;
+                let readiness = *readiness;
+                let ssh_retry = *ssh_retry;
+                let ingress = &*ingress;
+                let ssh_from = &ssh_from;
+                let tags = &*tags;
+                let key_pair = &*key_pair;
+
+                let plan = super::plan_descriptors(descriptors, max_wait)?;
+                let launched: HashSet<_> = plan.iter().map(|d| d.region.clone()).collect();
+                let names: HashSet<_> = plan
+                    .iter()
+                    .flat_map(|d| d.machines.iter().map(|(name, _)| name.clone()))
+                    .collect();
+                let res = super::spawn_regions(
+                    regions,
+                    plan,
+                    |spec| {
+                        let prov = (*credential_provider)().unwrap();
+                        let tags = tags.clone();
+                        async move {
+                            RegionLauncher::open(
+                                &spec,
+                                prov,
+                                use_open_ports,
+                                tags,
+                                key_pair.as_ref(),
+                            )
+                            .await
+                        }
+                    },
+                    |mut region_launcher, d| {
+                        let mode = mode.clone();
+                        let ingress = ingress.clone();
+                        let ssh_from = ssh_from.clone();
+                        let tags = tags.clone();
+                        async move {
+                            region_launcher.ssm_fallback(ssm_fallback);
+                            region_launcher.elastic_ips(elastic_ips);
+                            region_launcher.max_experiment_duration = max_experiment_duration;
+                            region_launcher.batch_size = batch_size;
+                            region_launcher.request_size = request_size;
+                            region_launcher.spot_fallback = spot_fallback;
+                            region_launcher.readiness = readiness;
+                            region_launcher.ssh_retry = ssh_retry;
+                            region_launcher.ingress = ingress;
+                            region_launcher.ssh_from = ssh_from;
+                            region_launcher.tags = tags;
+                            let res = region_launcher.launch(mode, d.max_wait, d.machines).await;
+                            (region_launcher, res.map(drop))
+                        }
+                    },
+                )
+                .await;
+
+                match res {
+                    Err(e) if *all_or_nothing => {
+                        let names = &names;
+                        let rollback = futures_util::future::join_all(
+                            regions.values_mut().map(|rl| rl.roll_back(names)),
+                        )
+                        .await
+                        .into_iter()
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(drop);
+                        return Err(super::rolled_back(e, rollback));
+                    }
+                    r => r?,
+                }
+
+                if let Some(rules) = final_ingress {
+                    // only once every region is set up, since setup may reach across regions.
+                    for spec in &launched {
+                        if let Some(region_launcher) = regions.get_mut(spec) {
+                            region_launcher
+                                .tighten_ingress(rules.clone())
+                                .await
+                                .wrap_err_with(|| {
+                                    format!("failed to tighten security group in {}", spec)
+                                })?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug")]
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        Box::pin(
+            async move { self.connect_stream().try_collect::<HashMap<_, _>>().await }
+                .in_current_span(),
+        )
+    }
+
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        let mut machines = HashMap::new();
+        for r in self.regions.values() {
+            machines.extend(r.describe_all()?);
+        }
+        Ok(machines)
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        Ok(self
+            .regions
+            .values()
+            .flat_map(|r| r.instances.values())
+            .filter_map(|t| Some((t.name.clone(), t.setup.setup_fn.clone()?)))
+            .collect())
+    }
+
+    #[instrument(level = "debug")]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<Box<dyn Future<Output = Result<HashMap<String, MachineState>, Report>> + Send + 'l>>
+    {
+        Box::pin(
+            async move {
+                let states = futures_util::future::join_all(
+                    self.regions.values().map(RegionLauncher::status),
+                )
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, Report>>()?;
+                Ok(states.into_iter().flatten().collect())
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug")]
+    fn terminate_all(mut self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        Box::pin(
+            async move {
+                if self.regions.is_empty() {
+                    return Ok(());
+                }
+
+                let res =
+                    futures_util::future::join_all(self.regions.drain().map(|(region, mut rl)| {
+                        let region_span = tracing::debug_span!("region", %region);
+                        async move { rl.terminate_all().await }.instrument(region_span)
+                    }))
+                    .await;
+                let mut errs = res.into_iter().filter_map(Result::err);
+                match errs.next() {
+                    None => Ok(()),
+                    Some(first) => Err(errs.fold(first, |a, e| a.wrap_err(e))),
+                }
+            }
+            .in_current_span(),
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SpotRequestStatus {
+    request_id: String,
+    state: String,
+    code: String,
+    message: Option<String>,
+    instance_id: Option<String>,
+    zone: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct IpInfo {
+    public_dns: String,
+    public_ip: String,
+    private_ip: String,
+}
+
+// The fields that vary between tsunami instance requests. Machines that agree on all of them are
+// launched with a single request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RequestGroup {
+    ami: String,
+    instance_type: String,
+    alternative_instance_types: Vec<String>,
+    shutdown_behavior: ShutdownBehavior,
+    interruption_behavior: InterruptionBehavior,
+    detailed_monitoring: bool,
+    cpu_credits: Option<CpuCredits>,
+    spot_price: Option<String>,
+    root_volume: Option<(i64, VolumeType)>,
+    data_volumes: Vec<DataVolume>,
+    efa: bool,
+    instance_profile: Option<String>,
+    placement_group: Option<PlacementGroup>,
+}
+
+impl RequestGroup {
+    // The part of `m` that goes into its launch request.
+    //
+    // This names every field of `Setup`, so that a new one cannot be added without deciding
+    // whether machines that differ in it can still share a request.
+    fn of(m: &Setup) -> Self {
+        let Setup {
+            // machines are grouped by `RegionSpec` before they get here.
+            region: _,
+            availability_zone: _,
+            near: _,
+            security_group: _,
+            // resolved to `ami` before launch.
+            image: _,
+            // only used once the instance is running.
+            username: _,
+            os: _,
+            volumes: _,
+            setup_fn: _,
+            teardown_fn: _,
+            // decide which batch the machine is launched in.
+            priority: _,
+            on_demand: _,
+            // applied to the region's security group before launch.
+            ingress: _,
+            ami,
+            instance_type,
+            alternative_instance_types,
+            shutdown_behavior,
+            interruption_behavior,
+            detailed_monitoring,
+            cpu_credits,
+            spot_price,
+            root_volume,
+            data_volumes,
+            efa,
+            instance_profile,
+            placement_group,
+        } = m;
+        RequestGroup {
+            ami: ami.clone(),
+            instance_type: instance_type.clone(),
+            alternative_instance_types: alternative_instance_types.clone(),
+            shutdown_behavior: *shutdown_behavior,
+            interruption_behavior: *interruption_behavior,
+            detailed_monitoring: *detailed_monitoring,
+            cpu_credits: *cpu_credits,
+            spot_price: spot_price.map(|p| p.to_string()),
+            root_volume: *root_volume,
+            data_volumes: data_volumes.clone(),
+            efa: *efa,
+            instance_profile: instance_profile.clone(),
+            placement_group: placement_group.clone(),
+        }
+    }
+}
+
+// Internal representation of an instance.
+//
+// Tagged with its nickname, and ip_info gets populated once it is available.
+#[derive(Debug, Clone)]
+struct TaggedSetup {
+    name: String,
+    setup: Setup,
+    ip_info: Option<IpInfo>,
+    setup_failed: bool,
+    via_ssm: bool,
+    lifecycle: Lifecycle,
+    // the ids of the attached volumes to snapshot at teardown.
+    results_volumes: Vec<String>,
+    // whether the instance got its nickname tags when it was launched.
+    name_tagged: bool,
+}
+
+/// The AWS resources a [`RegionLauncher`] has created.
+///
+/// Useful for referring to them in your own EC2 API calls, or for cleaning them up if the process
+/// that launched them went away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Resources {
+    /// The region the resources are in.
+    pub region: Region,
+    /// The id of the security group the instances are in.
+    pub security_group_id: String,
+    /// Whether the security group was created by tsunami, rather than given with
+    /// [`Setup::security_group`]. Only a created group should be deleted when cleaning up.
+    pub owns_security_group: bool,
+    /// The name of the EC2 key pair the instances were launched with.
+    pub key_name: String,
+    /// Whether the key pair was created by tsunami, rather than given with
+    /// [`Launcher::with_key_pair`]. Only a created key pair should be deleted when cleaning up.
+    pub owns_key_pair: bool,
+    /// The location of the private key for `key_name`.
+    ///
+    /// If tsunami created the key pair, the file is removed when the [`RegionLauncher`] is
+    /// dropped.
+    pub private_key_path: Option<std::path::PathBuf>,
+    /// The first placement group tsunami created for the instances, if any. It is deleted at
+    /// teardown.
+    pub placement_group: Option<String>,
+    /// The availability zone the instances are pinned to, if any.
+    pub availability_zone: Option<String>,
+    /// The VPC the instances are in, if it is not the default VPC.
+    pub vpc_id: Option<String>,
+    /// The subnet the instances are in, if it is not in the default VPC.
+    pub subnet_id: Option<String>,
+    /// The EC2 instance id of each machine, by nickname.
+    pub instances: HashMap<String, String>,
+    /// Whether each machine is an on-demand or a spot instance, by nickname.
+    pub lifecycles: HashMap<String, Lifecycle>,
+    /// The ids of any spot instance requests that are still open.
+    pub spot_request_ids: Vec<String>,
+    /// The allocation ids of the [Elastic IP addresses](Launcher::elastic_ips) tsunami allocated.
+    /// They are released at teardown.
+    pub elastic_ips: Vec<String>,
+    /// The value of the [`RUN_TAG`] on the instances and spot requests. See
+    /// [`RegionLauncher::tagged_instances`].
+    pub run_id: String,
+    /// The ids of the snapshots taken of each machine's [results
+    /// volumes](Setup::results_volume_at), by nickname.
+    pub snapshots: HashMap<String, Vec<String>>,
+}
+
+/// What EC2 reported about an instance at the end of a run.
+///
+/// Use it to exclude machines that EC2 reclaimed or stopped mid-run from the analysis of the
+/// results. See [`Launcher::instance_reports`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct InstanceReport {
+    /// The EC2 instance id.
+    pub instance_id: String,
+    /// The instance type, e.g. `t3.small`.
+    pub instance_type: String,
+    /// Whether this is a spot instance.
+    pub spot: bool,
+    /// The last state of the instance, e.g. `running` or `terminated`.
+    pub state: String,
+    /// Why the instance last changed state, e.g. `Server.SpotInstanceTermination: Spot instance
+    /// termination`, if EC2 said.
+    pub state_reason: Option<String>,
+    /// How long the instance had been running: until it stopped, if it did.
+    pub uptime: Option<time::Duration>,
+    /// Whether EC2 interrupted the spot instance, i.e., stopped, hibernated, or terminated it to
+    /// reclaim capacity.
+    pub interrupted: bool,
+}
+
+impl InstanceReport {
+    fn from_instance(i: rusoto_ec2::Instance, now: time::SystemTime) -> Option<Self> {
+        let state = i.state.and_then(|s| s.name).unwrap_or_default();
+        let (reason_code, reason_message) = i
+            .state_reason
+            .map(|r| (r.code, r.message))
+            .unwrap_or_default();
+        // the message repeats the code
+        let state_reason = reason_message.or_else(|| reason_code.clone());
+
+        // e.g. "User initiated (2021-03-01 17:32:13 GMT)"
+        let stopped_at = i.state_transition_reason.as_deref().and_then(|r| {
+            let (_, at) = r.rsplit_once('(')?;
+            parse_timestamp(at.trim_end_matches(')').trim_end_matches(" GMT"))
+        });
+        let end = match &*state {
+            "pending" | "running" => Some(now),
+            _ => stopped_at,
+        };
+        let uptime = i
+            .launch_time
+            .as_deref()
+            .and_then(parse_timestamp)
+            .zip(end)
+            .and_then(|(start, end)| end.duration_since(start).ok());
+
+        Some(InstanceReport {
+            instance_id: i.instance_id?,
+            instance_type: i.instance_type.unwrap_or_default(),
+            spot: i.instance_lifecycle.as_deref() == Some("spot"),
+            interrupted: reason_code
+                .as_deref()
+                .map_or(false, |c| c.starts_with("Server.SpotInstance")),
+            state,
+            state_reason,
+            uptime,
+        })
+    }
+}
+
+// Parses a UTC timestamp as EC2 reports it, e.g. `2021-03-01T17:32:13.000Z` or
+// `2021-03-01 17:32:13`. Fractional seconds are ignored.
+fn parse_timestamp(s: &str) -> Option<time::SystemTime> {
+    let s = s.trim().trim_end_matches('Z');
+    let s = s.split('.').next()?;
+    let (date, clock) = s.split_once(|c| c == 'T' || c == ' ')?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut clock = clock.splitn(3, ':').map(str::parse::<u64>);
+    let (h, m, sec) = (
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || h > 23 || m > 59 || sec > 60 {
+        return None;
+    }
+
+    // https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    if days < 0 {
+        return None;
+    }
+    let days = days as u64;
+    Some(time::UNIX_EPOCH + time::Duration::from_secs(days * 86400 + h * 3600 + m * 60 + sec))
+}
+
+/// A warning from EC2 that a spot instance may soon be reclaimed. See [`watch_spot_notices`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SpotNotice {
+    /// EC2 recommends moving work off the instance, since it is at an elevated risk of
+    /// interruption. This usually comes well before an [`Interruption`](SpotNotice::Interruption),
+    /// if one comes at all.
+    ///
+    /// See [rebalance
+    /// recommendations](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/rebalance-recommendations.html).
+    Rebalance {
+        /// When EC2 issued the recommendation.
+        issued: time::SystemTime,
+    },
+    /// EC2 is about to interrupt the instance, usually two minutes after issuing the notice.
+    Interruption {
+        /// What happens to the instance: `terminate`, `stop`, or `hibernate`.
+        action: String,
+        /// When it happens.
+        at: time::SystemTime,
+    },
+}
+
+impl std::fmt::Display for SpotNotice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpotNotice::Rebalance { .. } => write!(f, "rebalance recommended"),
+            SpotNotice::Interruption { action, at } => {
+                let secs = at
+                    .duration_since(time::SystemTime::now())
+                    .unwrap_or_default()
+                    .as_secs();
+                write!(f, "{} in {}s", action, secs)
+            }
+        }
+    }
+}
+
+// Asks the instance metadata service for the rebalance recommendation and the interruption
+// notice, each on a line prefixed with its kind. Missing ones print nothing after the prefix.
+const SPOT_NOTICES_SCRIPT: &str = "\
+t=$(curl -sf -m 2 -X PUT -H 'X-aws-ec2-metadata-token-ttl-seconds: 60' \
+http://169.254.169.254/latest/api/token); \
+echo \"rebalance $(curl -sf -m 2 -H \"X-aws-ec2-metadata-token: $t\" \
+http://169.254.169.254/latest/meta-data/events/recommendations/rebalance)\"; \
+echo \"interruption $(curl -sf -m 2 -H \"X-aws-ec2-metadata-token: $t\" \
+http://169.254.169.254/latest/meta-data/spot/instance-action)\"";
+
+// Parses the output of `SPOT_NOTICES_SCRIPT`.
+fn parse_spot_notices(out: &str) -> Vec<SpotNotice> {
+    out.lines()
+        .filter_map(|l| {
+            let (kind, json) = l.split_once(' ')?;
+            let json: serde_json::Value = serde_json::from_str(json).ok()?;
+            let field = |key: &str| json.get(key)?.as_str();
+            match kind {
+                "rebalance" => Some(SpotNotice::Rebalance {
+                    issued: parse_timestamp(field("noticeTime")?)?,
+                }),
+                "interruption" => Some(SpotNotice::Interruption {
+                    action: field("action")?.to_string(),
+                    at: parse_timestamp(field("time")?)?,
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The spot notices that EC2 has issued for the instance `m` is on, if any.
+///
+/// This asks the instance metadata service over SSH, and so works for any machine that runs on
+/// EC2, whether tsunami launched it or not.
+pub async fn spot_notices(m: &crate::Machine<'_>) -> Result<Vec<SpotNotice>, Report> {
+    let out = m
+        .ssh
+        .command("sh")
+        .arg("-c")
+        .arg(SPOT_NOTICES_SCRIPT)
+        .output()
+        .await
+        .wrap_err("failed to query instance metadata")?;
+    Ok(parse_spot_notices(&String::from_utf8_lossy(&out.stdout)))
+}
+
+/// Check `machines` for [spot notices](SpotNotice) every `interval`, and yield each new notice
+/// once, along with the nickname of the machine it is for.
+///
+/// A long experiment can use this to move work off machines that are likely to be reclaimed soon,
+/// rather than only finding out two minutes before they are. Each notice is also logged as a
+/// warning. Machines that cannot be checked are skipped until the next round.
+///
+/// ```rust,no_run
+/// # async fn f(vms: std::collections::HashMap<String, tsunami::Machine<'_>>) {
+/// use futures_util::stream::StreamExt;
+/// use std::time::Duration;
+/// use tsunami::providers::aws::{watch_spot_notices, SpotNotice};
+/// let mut notices = Box::pin(watch_spot_notices(&vms, Duration::from_secs(30)));
+/// while let Some((name, notice)) = notices.next().await {
+///     if let SpotNotice::Rebalance { .. } = notice {
+///         println!("moving work off {}", name);
+///     }
+/// }
+/// # }
+/// ```
+pub fn watch_spot_notices<'a, 't: 'a>(
+    machines: &'a HashMap<String, crate::Machine<'t>>,
+    interval: time::Duration,
+) -> impl Stream<Item = (String, SpotNotice)> + 'a {
+    let state = (HashSet::new(), std::collections::VecDeque::new(), true);
+    futures_util::stream::unfold(
+        state,
+        move |(mut seen, mut pending, mut first)| async move {
+            loop {
+                if let Some(n) = pending.pop_front() {
+                    return Some((n, (seen, pending, first)));
+                }
+                if !first {
+                    tokio::time::sleep(interval).await;
+                }
+                first = false;
+
+                let checks = machines
+                    .iter()
+                    .map(|(name, m)| async move { (name, spot_notices(m).await) });
+                for (name, res) in futures_util::future::join_all(checks).await {
+                    match res {
+                        Ok(notices) => {
+                            for n in notices {
+                                if seen.insert((name.clone(), n.clone())) {
+                                    tracing::warn!(nickname = %name, notice = %n, "spot notice");
+                                    pending.push_back((name.clone(), n));
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            tracing::debug!(nickname = %name, err = ?e, "could not check for spot notices")
+                        }
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// The tag that holds the id of the [`RegionLauncher`] that launched an instance or spot request.
+pub const RUN_TAG: &str = "tsunami:run";
+
+/// The tag that holds the nickname of an instance.
+pub const NICKNAME_TAG: &str = "tsunami:nickname";
+
+// The IAM instance profile `arn_or_name`, which is an ARN if it looks like one.
+fn instance_profile(arn_or_name: &str) -> rusoto_ec2::IamInstanceProfileSpecification {
+    if arn_or_name.starts_with("arn:") {
+        rusoto_ec2::IamInstanceProfileSpecification {
+            arn: Some(arn_or_name.to_string()),
+            name: None,
+        }
+    } else {
+        rusoto_ec2::IamInstanceProfileSpecification {
+            arn: None,
+            name: Some(arn_or_name.to_string()),
+        }
+    }
+}
+
+// Checks that EC2 accepts `tags` as tags, and that they do not clash with tsunami's own.
+fn check_tags(tags: &BTreeMap<String, String>) -> Result<(), Report> {
+    // every instance also gets `Name` and the two tsunami tags, out of at most 50.
+    eyre::ensure!(
+        tags.len() <= 47,
+        "{} tags is more than the 47 that EC2 leaves room for",
+        tags.len()
+    );
+    for (key, value) in tags {
+        eyre::ensure!(
+            !key.is_empty() && key.len() <= 128 && value.len() <= 256,
+            "tag {}={} is too long or has an empty key",
+            key,
+            value
+        );
+        eyre::ensure!(
+            !key.starts_with("aws:"),
+            "tag key {} is reserved by AWS",
+            key
+        );
+        if key == "Name" || key.starts_with("tsunami:") {
+            return Err(eyre!("tag key {} is reserved by tsunami", key)).suggestion(
+                "Instances are named after their nicknames, and tsunami: tags identify the run",
+            );
+        }
+    }
+    Ok(())
+}
+
+// The private key of the key pair that a region's instances are launched with.
+#[derive(Debug)]
+enum PrivateKey {
+    // Made along with a temporary key pair, and removed when the launcher is dropped.
+    Temporary(tempfile::NamedTempFile),
+    // Of an existing key pair, see `Launcher::with_key_pair`.
+    Existing(std::path::PathBuf),
+}
+
+impl PrivateKey {
+    fn path(&self) -> &std::path::Path {
+        match self {
+            PrivateKey::Temporary(f) => f.path(),
+            PrivateKey::Existing(p) => p,
+        }
+    }
+}
+
+/// Region specific. Launch AWS EC2 instances.
+///
+/// This implementation uses [rusoto](https://crates.io/crates/rusoto_core) to connect to AWS.
+///
+/// By default, `RegionLauncher` launches uses AWS [defined
+/// duration](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/spot-requests.html#fixed-duration-spot-instances)
+/// spot instances. These cost slightly more than regular spot instances, but are never prematurely
+/// terminated.  The lifetime of such instances must be declared in advance (1-6 hours). By
+/// default, we use 6 hours (the maximum). To change this, or to switch to on-demand instances, use
+/// [`Launcher::set_mode`].
+///
+/// You must call [`RegionLauncher::shutdown`] to terminate the instances.
+#[derive(Educe, Default)]
+#[educe(Debug)]
+pub struct RegionLauncher {
+    /// The region this RegionLauncher is connected to.
+    pub region: rusoto_core::region::Region,
+    availability_zone: AvailabilityZoneSpec,
+    security_group_id: String,
+    existing_security_group: bool,
+    use_open_ports: bool,
+    ingress: Option<Vec<IngressRule>>,
+    ssh_from: Option<String>,
+    authorized: HashSet<IngressRule>,
+    admits_own_traffic: bool,
+    auto_placement_group: Option<String>,
+    placement_groups: Vec<String>,
+    zone: Option<String>,
+    // how many rounds of spot requests failed for lack of capacity in each availability zone.
+    spot_strikes: HashMap<String, usize>,
+    ssh_key_name: String,
+    private_key_path: Option<PrivateKey>,
+    #[educe(Debug(ignore))]
+    client: Option<rusoto_ec2::Ec2Client>,
+    #[cfg(feature = "cloudwatch")]
+    #[educe(Debug(ignore))]
+    cloudwatch: Option<rusoto_cloudwatch::CloudWatchClient>,
+    run_id: String,
+    tags: BTreeMap<String, String>,
+    network: Option<Network>,
+    ssm_fallback: bool,
+    elastic_ips: bool,
+    // the allocation id of the elastic ip of each instance, by instance id.
+    addresses: HashMap<String, String>,
+    batch_size: Option<usize>,
+    request_size: Option<usize>,
+    spot_fallback: Option<time::Duration>,
+    connect_concurrency: Option<usize>,
+    readiness: Readiness,
+    ssh_retry: SshRetry,
+    spot_requests: HashMap<String, TaggedSetup>,
+    instances: HashMap<String, TaggedSetup>,
+    max_experiment_duration: Option<time::Duration>,
+    snapshots: HashMap<String, Vec<String>>,
+    reports: HashMap<String, InstanceReport>,
+}
+
+impl RegionLauncher {
+    /// Connect to AWS region `region`, using credentials provider `provider`.
+    ///
+    /// This is a lower-level API, you may want [`Launcher`] instead.
+    ///
+    /// This will create a temporary security group and SSH key in the given AWS region.
+    pub async fn new<P>(
+        region: &str,
+        availability_zone: AvailabilityZoneSpec,
+        provider: P,
+        use_open_ports: bool,
+    ) -> Result<Self, Report>
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        Self::new_near(region, availability_zone, None, provider, use_open_ports).await
+    }
+
+    /// Like [`RegionLauncher::new`], but launch into the network of `near` if given. See
+    /// [`Setup::near`].
+    pub async fn new_near<P>(
+        region: &str,
+        availability_zone: AvailabilityZoneSpec,
+        near: Option<Affinity>,
+        provider: P,
+        use_open_ports: bool,
+    ) -> Result<Self, Report>
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        let spec = RegionSpec {
+            region: region.parse()?,
+            availability_zone,
+            near,
+            security_group: None,
+        };
+        Self::new_in(&spec, provider, use_open_ports).await
+    }
+
+    /// Like [`RegionLauncher::new_near`], but take everything about where the instances go from
+    /// `spec`, including an existing [security group](Setup::security_group) to use instead of
+    /// creating one.
+    pub async fn new_in<P>(
+        spec: &RegionSpec,
+        provider: P,
+        use_open_ports: bool,
+    ) -> Result<Self, Report>
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        Self::new_tagged(spec, provider, use_open_ports, BTreeMap::new()).await
+    }
+
+    /// Like [`RegionLauncher::new_in`], but tag the security group, key pair, and everything
+    /// launched later with `tags`. See [`Launcher::with_tags`].
+    pub async fn new_tagged<P>(
+        spec: &RegionSpec,
+        provider: P,
+        use_open_ports: bool,
+        tags: BTreeMap<String, String>,
+    ) -> Result<Self, Report>
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        Self::open(spec, provider, use_open_ports, tags, None).await
+    }
+
+    // Like `new_tagged`, but launch with the existing key pair `key_pair` if given. See
+    // `Launcher::with_key_pair`.
+    async fn open<P>(
+        spec: &RegionSpec,
+        provider: P,
+        use_open_ports: bool,
+        tags: BTreeMap<String, String>,
+        key_pair: Option<&(String, std::path::PathBuf)>,
+    ) -> Result<Self, Report>
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        check_tags(&tags)?;
+        let mut ec2 = RegionLauncher::connect(
+            spec.region.clone(),
+            spec.availability_zone.clone(),
+            provider,
+        )
+        .wrap_err("failed to connect to region")?;
+        ec2.tags = tags;
+        let ec2 = ec2
+            .join_network(spec.near.clone())
+            .await
+            .wrap_err("failed to find the network to launch into")?;
+        let ec2 = match spec.security_group {
+            Some(ref group_id) => ec2
+                .use_security_group(group_id)
+                .await
+                .wrap_err("failed to find the security group to launch into")?,
+            None => ec2
+                .make_security_group(use_open_ports)
+                .await
+                .wrap_err("failed to make security groups")?,
+        };
+        let ec2 = match key_pair {
+            Some((name, path)) => ec2
+                .use_key_pair(name, path)
+                .await
+                .wrap_err("failed to find the key pair to launch with")?,
+            None => ec2
+                .make_ssh_key()
+                .await
+                .wrap_err("failed to make ssh key")?,
+        };
+
+        Ok(ec2)
+    }
+
+    #[instrument(level = "debug", skip(provider))]
+    fn connect<P>(
+        region: rusoto_core::region::Region,
+        availability_zone: AvailabilityZoneSpec,
+        provider: P,
+    ) -> Result<Self, Report>
+    where
+        P: ProvideAwsCredentials + Send + Sync + 'static,
+    {
+        tracing::debug!("connecting to ec2");
+        let client = rusoto_core::Client::new_with(
+            provider,
+            HttpClient::new().wrap_err("failed to construct new http client")?,
+        );
+        #[cfg(feature = "cloudwatch")]
+        let cloudwatch = Some(rusoto_cloudwatch::CloudWatchClient::new_with_client(
+            client.clone(),
+            region.clone(),
+        ));
+        let ec2 = rusoto_ec2::Ec2Client::new_with_client(client, region.clone());
+
+        Ok(Self {
+            region,
+            availability_zone,
+            security_group_id: Default::default(),
+            existing_security_group: false,
+            use_open_ports: false,
+            ingress: None,
+            ssh_from: None,
+            authorized: Default::default(),
+            admits_own_traffic: false,
+            auto_placement_group: None,
+            placement_groups: Vec::new(),
+            zone: None,
+            spot_strikes: Default::default(),
+            ssh_key_name: Default::default(),
+            private_key_path: Some(PrivateKey::Temporary(
+                tempfile::NamedTempFile::new()
+                    .wrap_err("failed to create temporary file for keypair")?,
+            )),
+            spot_requests: Default::default(),
+            instances: Default::default(),
+            max_experiment_duration: None,
+            snapshots: Default::default(),
+            reports: Default::default(),
+            client: Some(ec2),
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch,
+            run_id: super::rand_name("run"),
+            tags: Default::default(),
+            network: None,
+            ssm_fallback: false,
+            elastic_ips: false,
+            addresses: Default::default(),
+            batch_size: None,
+            request_size: None,
+            spot_fallback: None,
+            connect_concurrency: None,
+            readiness: Readiness::default(),
+            ssh_retry: SshRetry::default(),
+        })
+    }
+
+    /// The EC2 client this region was set up with, or `None` if it was never connected.
+    pub fn client(&self) -> Option<&rusoto_ec2::Ec2Client> {
+        self.client.as_ref()
+    }
+
+    /// The id of the security group the instances are launched into.
+    ///
+    /// This is a temporary group, unless the instances were launched into an existing one with
+    /// [`Setup::security_group`].
+    pub fn security_group_id(&self) -> &str {
+        &self.security_group_id
+    }
+
+    /// The name of the EC2 key pair the instances are launched with.
+    ///
+    /// This is a temporary key pair, unless the launcher was given one with
+    /// [`Launcher::with_key_pair`].
+    pub fn ssh_key_name(&self) -> &str {
+        &self.ssh_key_name
+    }
+
+    /// The location of the private key for [`RegionLauncher::ssh_key_name`].
+    pub fn private_key_path(&self) -> Option<&std::path::Path> {
+        self.private_key_path.as_ref().map(|k| k.path())
+    }
+
+    /// The existing network the instances are launched into, if any. See [`Setup::near`].
+    pub fn network(&self) -> Option<&Network> {
+        self.network.as_ref()
+    }
+
+    /// Reach instances through Session Manager when direct SSH fails. See
+    /// [`Launcher::ssm_fallback`].
+    pub fn ssm_fallback(&mut self, enabled: bool) -> &mut Self {
+        self.ssm_fallback = enabled;
+        self
+    }
+
+    /// Give each instance an Elastic IP address. See [`Launcher::elastic_ips`].
+    pub fn elastic_ips(&mut self, enabled: bool) -> &mut Self {
+        self.elastic_ips = enabled;
+        self
+    }
+
+    /// Launch at most `n` machines at a time. See [`Launcher::batch_size`].
+    pub fn batch_size(&mut self, n: usize) -> &mut Self {
+        self.batch_size = Some(n.max(1));
+        self
+    }
+
+    /// Ask for at most `n` instances in each EC2 request. See [`Launcher::request_size`].
+    pub fn request_size(&mut self, n: usize) -> &mut Self {
+        self.request_size = Some(n.max(1));
+        self
+    }
+
+    /// Launch machines as on-demand instances if their spot requests are not fulfilled within
+    /// `wait`. See [`Launcher::spot_fallback_after`].
+    pub fn spot_fallback_after(&mut self, wait: time::Duration) -> &mut Self {
+        self.spot_fallback = Some(wait);
+        self
+    }
+
+    /// Set up at most `n` SSH connections at a time in
+    /// [`connect_all`](RegionLauncher::connect_all). See [`Launcher::connect_concurrency`].
+    pub fn connect_concurrency(&mut self, n: usize) -> &mut Self {
+        self.connect_concurrency = Some(n.max(1));
+        self
+    }
+
+    /// Only start connecting to instances once they are as ready as `r` says. See
+    /// [`Launcher::readiness`].
+    pub fn readiness(&mut self, r: Readiness) -> &mut Self {
+        self.readiness = r;
+        self
+    }
+
+    /// Retry connecting to each ready instance as `policy` says. See [`Launcher::ssh_retry`].
+    pub fn ssh_retry(&mut self, policy: SshRetry) -> &mut Self {
+        self.ssh_retry = policy;
+        self
+    }
+
+    /// Admit only the traffic of `rules` into the security group of machines launched from now
+    /// on, plus what they [allow](Setup::allow). See [`Launcher::ingress`].
+    ///
+    /// Rules that earlier launches admitted stay in place.
+    pub fn ingress(&mut self, rules: impl IntoIterator<Item = IngressRule>) -> &mut Self {
+        self.ingress = Some(rules.into_iter().collect());
+        self
+    }
+
+    /// Admit SSH and ICMP only from the addresses in `cidr`, rather than from anywhere, into the
+    /// security group of machines launched from now on. See [`Launcher::restrict_ssh`].
+    pub fn ssh_from(&mut self, cidr: impl ToString) -> &mut Self {
+        self.ssh_from = Some(cidr.to_string());
+        self
+    }
+
+    /// The nickname and EC2 instance id of every instance launched in this region.
+    pub fn instance_ids(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.instances
+            .iter()
+            .map(|(id, info)| (info.name.as_str(), id.as_str()))
+    }
+
+    /// Whether each instance launched in this region is an on-demand or a spot instance, by
+    /// nickname.
+    pub fn lifecycles(&self) -> HashMap<String, Lifecycle> {
+        self.instances
+            .values()
+            .map(|info| (info.name.clone(), info.lifecycle))
+            .collect()
+    }
+
+    /// Everything this region has created so far.
+    pub fn resources(&self) -> Resources {
+        let mut spot_request_ids: Vec<_> = self.spot_requests.keys().cloned().collect();
+        spot_request_ids.sort();
+        Resources {
+            region: self.region.clone(),
+            security_group_id: self.security_group_id.clone(),
+            owns_security_group: !self.existing_security_group,
+            key_name: self.ssh_key_name.clone(),
+            owns_key_pair: !self.existing_key_pair(),
+            private_key_path: self.private_key_path().map(ToOwned::to_owned),
+            placement_group: self.placement_groups.first().cloned(),
+            availability_zone: self.zone.clone(),
+            vpc_id: self.network.as_ref().map(|n| n.vpc_id.clone()),
+            subnet_id: self.network.as_ref().map(|n| n.subnet_id.clone()),
+            instances: self
+                .instance_ids()
+                .map(|(name, id)| (name.to_string(), id.to_string()))
+                .collect(),
+            lifecycles: self.lifecycles(),
+            spot_request_ids,
+            elastic_ips: self.addresses.values().cloned().sorted().collect(),
+            run_id: self.run_id.clone(),
+            snapshots: self.snapshots.clone(),
+        }
+    }
+
+    /// The nickname and EC2 instance id of every instance of `run_id` that has not terminated,
+    /// as recorded in their tags.
+    ///
+    /// Every instance is tagged with its nickname ([`NICKNAME_TAG`], and `Name`) and the run that
+    /// launched it ([`RUN_TAG`]) as soon as it exists, so this recovers the mapping from the
+    /// [`Resources::run_id`] of a launcher that went away.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn tagged_instances(&self, run_id: &str) -> Result<HashMap<String, String>, Report> {
+        let req = rusoto_ec2::DescribeInstancesRequest {
+            filters: Some(vec![
+                rusoto_ec2::Filter {
+                    name: Some(format!("tag:{}", RUN_TAG)),
+                    values: Some(vec![run_id.to_string()]),
+                },
+                rusoto_ec2::Filter {
+                    name: Some("instance-state-name".to_string()),
+                    values: Some(
+                        ["pending", "running", "stopping", "stopped"]
+                            .iter()
+                            .map(|s| s.to_string())
+                            .collect(),
+                    ),
+                },
+            ]),
+            ..Default::default()
+        };
+        let instances = self
+            .client
+            .as_ref()
+            .unwrap()
+            .describe_instances(req)
+            .await
+            .wrap_err("could not query AWS for tagged instances")?
+            .reservations
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|r| r.instances.unwrap_or_default())
+            .filter_map(|i| {
+                let nickname = i
+                    .tags?
+                    .into_iter()
+                    .find(|t| t.key.as_deref() == Some(NICKNAME_TAG))?
+                    .value?;
+                Some((nickname, i.instance_id?))
+            })
+            .collect();
+        Ok(instances)
+    }
+
+    /// Fetch the CloudWatch metric `metric` for every instance in this region, from `since` until
+    /// now, aggregated over `period`.
+    ///
+    /// `metric` is one of the [EC2 instance
+    /// metrics](https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/viewing_metrics_with_cloudwatch.html#ec2-cloudwatch-metrics),
+    /// such as `CPUUtilization`, `NetworkIn`, or `EBSWriteBytes`. `period` is rounded down to a
+    /// whole minute, and must be at least five minutes unless the instances were launched with
+    /// [`Setup::detailed_monitoring`]. The samples are keyed by nickname, in time order.
+    #[cfg(feature = "cloudwatch")]
+    #[instrument(level = "debug", skip(self))]
+    pub async fn metrics(
+        &self,
+        metric: &str,
+        period: time::Duration,
+        since: time::SystemTime,
+    ) -> Result<HashMap<String, Vec<MetricSample>>, Report> {
+        use rusoto_cloudwatch::CloudWatch;
+        let cw = self
+            .cloudwatch
+            .as_ref()
+            .ok_or_else(|| eyre!("RegionLauncher for {} is not connected", self.region.name()))?;
+        let period = std::cmp::max(period.as_secs() / 60, 1) * 60;
+        let start_time = super::iso8601(since);
+        let end_time = super::iso8601(time::SystemTime::now());
+
+        let mut samples = HashMap::new();
+        for (name, id) in self.instance_ids() {
+            let req = rusoto_cloudwatch::GetMetricStatisticsInput {
+                namespace: String::from("AWS/EC2"),
+                metric_name: metric.to_string(),
+                dimensions: Some(vec![rusoto_cloudwatch::Dimension {
+                    name: String::from("InstanceId"),
+                    value: id.to_string(),
+                }]),
+                start_time: start_time.clone(),
+                end_time: end_time.clone(),
+                period: period as i64,
+                statistics: Some(vec![String::from("Average"), String::from("Maximum")]),
+                ..Default::default()
+            };
+            let mut points: Vec<_> = cw
+                .get_metric_statistics(req)
+                .await
+                .wrap_err_with(|| format!("failed to fetch {} for {}", metric, name))?
+                .datapoints
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|d| {
+                    Some(MetricSample {
+                        timestamp: d.timestamp?,
+                        average: d.average,
+                        maximum: d.maximum,
+                        unit: d.unit,
+                    })
+                })
+                .collect();
+            // the timestamps are all ISO 8601 in UTC, so they sort as strings.
+            points.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+            samples.insert(name.to_string(), points);
+        }
+        Ok(samples)
+    }
+
+    /// Have the instances shut themselves down once `d` has passed since they booted. See
+    /// [`Launcher::max_experiment_duration`].
+    pub fn max_experiment_duration(&mut self, d: time::Duration) -> &mut Self {
+        self.max_experiment_duration = Some(d);
+        self
+    }
+
+    // The base64-encoded user data script of new instances, if any.
+    fn user_data(&self) -> Option<String> {
+        self.max_experiment_duration
+            .map(|d| base64::encode(shutdown_script(d)))
+    }
+
+    /// Region-specific instance setup.
+    ///
+    /// Make spot instance requests, wait for the instances, and then call the
+    /// instance setup functions. Returns the resources the region now holds.
+    ///
+    /// [`Priority::Critical`] machines are launched before [`Priority::BestEffort`] ones, and
+    /// each in batches of at most [`RegionLauncher::batch_size`] machines. Machines set up with
+    /// [`Setup::on_demand`] are launched as on-demand instances regardless of `mode`.
     #[instrument(level = "debug", skip(self, max_wait))]
     pub async fn launch<M>(
         &mut self,
-        mode: LaunchMode,
-        mut max_wait: Option<time::Duration>,
-        machines: M,
-    ) -> Result<(), Report>
-    where
-        M: IntoIterator<Item = (String, Setup)> + std::fmt::Debug,
-    {
-        let machines: Vec<_> = machines.into_iter().collect();
-        let machines = machines.clone();
-        let mut do_ondemand = false;
-        match mode {
-            LaunchMode::TrySpot {
-                hours: max_instance_duration_hours,
+        mode: LaunchMode,
+        max_wait: Option<time::Duration>,
+        machines: M,
+    ) -> Result<Resources, Report>
+    where
+        M: IntoIterator<Item = (String, Setup)> + std::fmt::Debug,
+    {
+        let machines = self.resolve_images(machines.into_iter().collect()).await?;
+        self.admit_traffic(&machines)
+            .await
+            .wrap_err("failed to fill in security group for new machines")?;
+        self.pin_zone(&machines)
+            .await
+            .wrap_err("failed to pick an availability zone")?;
+        for (name, m) in &machines {
+            if m.cpu_credits.is_none() && is_burstable(&m.instance_type) {
+                tracing::warn!(
+                    nickname = %name,
+                    instance_type = %m.instance_type,
+                    "burstable instance type may be throttled when it runs out of CPU credits"
+                );
+            }
+        }
+
+        let start = time::Instant::now();
+        let remaining = |max_wait: Option<time::Duration>| {
+            max_wait.map(|d| d.checked_sub(start.elapsed()).unwrap_or_default())
+        };
+        let (critical, best_effort): (Vec<_>, Vec<_>) = machines
+            .into_iter()
+            .partition(|(_, m)| m.priority == Priority::Critical);
+        for batch in batches(critical, self.batch_size) {
+            self.launch_batch(mode.clone(), remaining(max_wait), batch)
+                .await?;
+        }
+        for batch in batches(best_effort, self.batch_size) {
+            let names: HashSet<_> = batch.iter().map(|(name, _)| name.clone()).collect();
+            if let Err(e) = self
+                .launch_batch(mode.clone(), remaining(max_wait), batch)
+                .await
+            {
+                tracing::warn!(?names, err = ?e, "best-effort machines failed to launch");
+                if let Err(e) = self.roll_back(&names).await {
+                    tracing::warn!(?names, err = ?e, "failed to terminate best-effort machines");
+                }
+            }
+        }
+        Ok(self.resources())
+    }
+
+    // Launches one batch of machines, leaving the machines of earlier launches alone.
+    async fn launch_batch(
+        &mut self,
+        mode: LaunchMode,
+        max_wait: Option<time::Duration>,
+        machines: Vec<(String, Setup)>,
+    ) -> Result<(), Report> {
+        // machines that asked for on-demand instances do not follow the launcher's mode
+        let (on_demand, machines): (Vec<_>, Vec<_>) =
+            machines.into_iter().partition(|(_, m)| m.on_demand);
+        for (mode, machines) in [(LaunchMode::OnDemand, on_demand), (mode, machines)] {
+            if machines.is_empty() {
+                continue;
+            }
+            let spot_requests = std::mem::take(&mut self.spot_requests);
+            let instances = std::mem::take(&mut self.instances);
+            let res = self.launch_new(mode, max_wait, machines).await;
+            self.spot_requests.extend(spot_requests);
+            self.instances.extend(instances);
+            res?;
+        }
+        Ok(())
+    }
+
+    // Launches `machines`, assuming that they are the only ones this `RegionLauncher` has.
+    async fn launch_new(
+        &mut self,
+        mode: LaunchMode,
+        mut max_wait: Option<time::Duration>,
+        machines: Vec<(String, Setup)>,
+    ) -> Result<(), Report> {
+        let mut do_ondemand = false;
+        match mode {
+            LaunchMode::TrySpot {
+                hours: max_instance_duration_hours,
+            }
+            | LaunchMode::DefinedDuration {
+                hours: max_instance_duration_hours,
+            } => {
+                let machines = machines.clone();
+                self.steer_zone(&machines)
+                    .await
+                    .wrap_err("failed to pick an availability zone")?;
+
+                // leave this to short-circuit: we only want to fall back to OnDemand if there is
+                // no spot capacity, not if we can't make the request in the first place.
+                self.make_spot_instance_requests(
+                    max_instance_duration_hours * 60, // 60 mins/hr
+                    machines,
+                )
+                .await
+                .wrap_err("failed to make spot instance requests")?;
+
+                let start = time::Instant::now();
+                let spot_wait = match (max_wait, self.spot_fallback) {
+                    (Some(max), Some(fallback)) => Some(max.min(fallback)),
+                    (max, fallback) => max.or(fallback),
+                };
+                let waited = self
+                    .wait_for_spot_instance_requests(spot_wait)
+                    .await
+                    .wrap_err(eyre!(
+                        "failed while waiting for spot instances fulfilment in {}",
+                        self.region.name()
+                    ));
+                if self.availability_zone == AvailabilityZoneSpec::Any {
+                    // the zone was only picked for these spot requests
+                    self.zone = None;
+                }
+                if let Err(e) = waited {
+                    // if wait_for_spot_instance_requests returned an Err, it will have cleaned up
+                    // the spot instance requests already.
+                    let fallback_first = self.spot_fallback.map_or(false, |fallback| {
+                        max_wait.map_or(true, |max| fallback <= max)
+                    });
+                    if fall_back_on_demand(&mode, fallback_first, &e) {
+                        tracing::info!(err = ?e, "spot requests not fulfilled, launching on-demand");
+                        do_ondemand = true;
+                    } else {
+                        return Err(e);
+                    }
+                } else {
+                    if let Some(ref mut d) = max_wait {
+                        *d -= time::Instant::now().duration_since(start);
+                    }
+                    self.set_spot_cpu_credits()
+                        .await
+                        .wrap_err("failed to set cpu credit mode of spot instances")?;
+                }
+            }
+            LaunchMode::Fleet => {
+                self.make_fleet_requests(machines.clone())
+                    .await
+                    .wrap_err_with(|| {
+                        format!("failed to launch fleet in {}", self.region.name())
+                    })?;
+            }
+            LaunchMode::OnDemand => {
+                do_ondemand = true;
+            }
+        }
+
+        if do_ondemand {
+            self.make_on_demand_requests(machines)
+                .await
+                .wrap_err(eyre!(
+                    "failed to start on demand instances in {}",
+                    self.region.name()
+                ))?;
+
+            // give EC2 a bit of time to discover the instances
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        }
+
+        self.tag_instances()
+            .await
+            .wrap_err("failed to tag instances with their nicknames")?;
+        self.wait_for_instances(max_wait)
+            .await
+            .wrap_err("failed while waiting for instances to come up")
+    }
+
+    // Polls EC2 until every instance is running with an address and is as ready as
+    // `self.readiness` asks, and returns the addresses by instance id.
+    #[instrument(level = "trace", skip(self, max_wait))]
+    async fn wait_for_running(
+        &mut self,
+        max_wait: Option<time::Duration>,
+    ) -> Result<HashMap<String, IpInfo>, Report> {
+        let start = time::Instant::now();
+        let desc_req = rusoto_ec2::DescribeInstancesRequest {
+            instance_ids: Some(self.instances.keys().cloned().collect()),
+            ..Default::default()
+        };
+        let mut running = HashMap::new();
+        while !self.instances.is_empty() {
+            let client = self.client.as_ref().unwrap();
+            for reservation in client
+                .describe_instances(desc_req.clone())
+                .await
+                .wrap_err("could not query AWS for instance state")?
+                .reservations
+                .unwrap_or_else(Vec::new)
+            {
+                for instance in reservation.instances.unwrap_or_else(Vec::new) {
+                    // https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_InstanceState.html
+                    // code 16 means "Running"
+                    if let rusoto_ec2::Instance {
+                        state: Some(rusoto_ec2::InstanceState { code: Some(16), .. }),
+                        instance_id: Some(instance_id),
+                        public_dns_name: public_dns,
+                        public_ip_address: public_ip,
+                        private_ip_address: Some(private_ip),
+                        ..
+                    } = instance
+                    {
+                        if public_ip.is_none() && !self.ssm_fallback {
+                            continue;
+                        }
+                        running
+                            .entry(instance_id)
+                            .or_insert_with_key(|instance_id| {
+                                tracing::trace!(%instance_id, ip = ?public_ip, "instance running");
+                                IpInfo {
+                                    public_dns: public_dns.unwrap_or_default(),
+                                    public_ip: public_ip.unwrap_or_default(),
+                                    private_ip,
+                                }
+                            });
+                    }
+                }
+            }
+
+            if running.len() >= self.instances.len() {
+                match self.readiness {
+                    Readiness::Running => break,
+                    Readiness::StatusChecks => {
+                        if self.status_checks_passed().await? {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            // let's not hammer the API
+            tokio::time::sleep(time::Duration::from_secs(1)).await;
+
+            if let Some(wait_limit) = max_wait {
+                if start.elapsed() <= wait_limit {
+                    continue;
+                }
+                self.cancel_spot_instance_requests().await?;
+                return Err(Report::new(WaitLimitError {
+                    waited: start.elapsed(),
+                }));
+            }
+        }
+
+        Ok(running)
+    }
+
+    // Allocates an elastic ip for each new instance in `running` if asked to, and returns the
+    // instances' addresses with the elastic ips in place.
+    #[instrument(level = "trace", skip(self, running))]
+    async fn assign_elastic_ips(
+        &mut self,
+        mut running: HashMap<String, IpInfo>,
+    ) -> Result<HashMap<String, IpInfo>, Report> {
+        if !self.elastic_ips {
+            return Ok(running);
+        }
+        let client = self.client.as_ref().unwrap();
+        let new: Vec<_> = running
+            .keys()
+            .filter(|id| !self.addresses.contains_key(*id))
+            .filter(|id| {
+                self.instances
+                    .get(*id)
+                    .map_or(false, |t| t.ip_info.is_none())
+            })
+            .cloned()
+            .collect();
+        if new.is_empty() {
+            return Ok(running);
+        }
+        for instance_id in &new {
+            let res = client
+                .allocate_address(rusoto_ec2::AllocateAddressRequest {
+                    domain: Some(String::from("vpc")),
+                    ..Default::default()
+                })
+                .await
+                .wrap_err("failed to allocate an elastic ip address")
+                .suggestion(
+                    "EC2 allows five elastic ip addresses per region unless you ask for more",
+                )?;
+            let (allocation_id, ip) = match (res.allocation_id, res.public_ip) {
+                (Some(a), Some(ip)) => (a, ip),
+                _ => eyre::bail!("elastic ip allocation is incomplete"),
+            };
+            // remember it right away, so that it is released even if the rest fails.
+            self.addresses
+                .insert(instance_id.clone(), allocation_id.clone());
+            tracing::debug!(%instance_id, %ip, %allocation_id, "allocated elastic ip");
+            client
+                .create_tags(rusoto_ec2::CreateTagsRequest {
+                    resources: vec![allocation_id.clone()],
+                    tags: self.tags(),
+                    ..Default::default()
+                })
+                .await
+                .wrap_err_with(|| format!("failed to tag elastic ip {}", allocation_id))?;
+            client
+                .associate_address(rusoto_ec2::AssociateAddressRequest {
+                    allocation_id: Some(allocation_id.clone()),
+                    instance_id: Some(instance_id.clone()),
+                    ..Default::default()
+                })
+                .await
+                .wrap_err_with(|| format!("failed to associate {} with {}", ip, instance_id))?;
+        }
+
+        // the public dns name follows the new address
+        let desc_req = rusoto_ec2::DescribeInstancesRequest {
+            instance_ids: Some(new),
+            ..Default::default()
+        };
+        for reservation in client
+            .describe_instances(desc_req)
+            .await
+            .wrap_err("could not query AWS for instance addresses")?
+            .reservations
+            .unwrap_or_default()
+        {
+            for instance in reservation.instances.unwrap_or_default() {
+                if let (Some(id), Some(ip)) = (instance.instance_id, instance.public_ip_address) {
+                    if let Some(info) = running.get_mut(&id) {
+                        info.public_ip = ip;
+                        info.public_dns = instance.public_dns_name.unwrap_or_default();
+                    }
+                }
+            }
+        }
+        Ok(running)
+    }
+
+    // Whether every instance has passed EC2's system and instance status checks.
+    async fn status_checks_passed(&self) -> Result<bool, Report> {
+        let client = self.client.as_ref().unwrap();
+        let ids: Vec<_> = self.instances.keys().cloned().collect();
+        let ok = |s: &Option<rusoto_ec2::InstanceStatusSummary>| {
+            s.as_ref().and_then(|s| s.status.as_deref()) == Some("ok")
+        };
+        let mut passed = 0;
+        // at most 100 instances can be named in one request.
+        for ids in ids.chunks(100) {
+            let req = rusoto_ec2::DescribeInstanceStatusRequest {
+                instance_ids: Some(ids.to_vec()),
+                ..Default::default()
+            };
+            passed += client
+                .describe_instance_status(req)
+                .await
+                .wrap_err("could not query AWS for instance status checks")?
+                .instance_statuses
+                .unwrap_or_default()
+                .iter()
+                .filter(|s| ok(&s.system_status) && ok(&s.instance_status))
+                .count();
+        }
+        tracing::trace!(passed, total = ids.len(), "status checks");
+        Ok(passed == ids.len())
+    }
+
+    // Connects to each of the `running` instances that has not been reached yet, retrying each
+    // under `self.ssh_retry` for at most `max_wait`, and records how it was reached.
+    #[instrument(level = "trace", skip(self, running, max_wait))]
+    async fn wait_for_ssh(
+        &mut self,
+        running: HashMap<String, IpInfo>,
+        max_wait: Option<time::Duration>,
+    ) -> Result<(), Report> {
+        let private_key_path = self.private_key_path.as_ref().unwrap().path();
+        let retry = self.ssh_retry;
+        let give_up_after = match (retry.give_up_after, max_wait) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let probes = running
+            .into_iter()
+            .filter(|(instance_id, _)| {
+                self.instances
+                    .get(instance_id)
+                    .map_or(false, |t| t.ip_info.is_none())
+            })
+            .map(|(instance_id, ip)| {
+                let username = self.instances[&instance_id].setup.username.clone();
+                let ssm_proxy = if self.ssm_fallback {
+                    Some(self.ssm_proxy(&instance_id))
+                } else {
+                    None
+                };
+                let instance_span =
+                    tracing::debug_span!("instance", %instance_id, ip = %ip.public_ip);
+                async move {
+                    let start = time::Instant::now();
+                    let res = loop {
+                        let res = probe_ssh(
+                            &ip,
+                            &username,
+                            private_key_path,
+                            ssm_proxy.as_deref(),
+                            retry.timeout,
+                        )
+                        .await;
+                        match res {
+                            Ok(via_ssm) => break Ok(via_ssm),
+                            Err(e) => {
+                                tracing::trace!("ssh failed: {}", e);
+                                if give_up_after
+                                    .map_or(false, |d| start.elapsed() + retry.interval >= d)
+                                {
+                                    break Err(e);
+                                }
+                            }
+                        }
+                        tokio::time::sleep(retry.interval).await;
+                    };
+                    (instance_id, ip, res)
+                }
+                .instrument(instance_span)
+            });
+        let results: Vec<_> = futures_util::stream::iter(probes)
+            .buffer_unordered(self.connect_concurrency.unwrap_or(CONNECT_CONCURRENCY))
+            .collect()
+            .await;
+
+        let total = results.len();
+        let mut errors = Vec::new();
+        for (instance_id, ip, res) in results {
+            let tag_setup = self.instances.get_mut(&instance_id).unwrap();
+            match res {
+                Ok(via_ssm) => {
+                    tracing::debug!(%instance_id, via_ssm, "instance ready");
+                    tag_setup.via_ssm = via_ssm;
+                    // over session manager, the address only names the host to ssh.
+                    tag_setup.ip_info = Some(IpInfo {
+                        public_ip: if ip.public_ip.is_empty() {
+                            ip.private_ip.clone()
+                        } else {
+                            ip.public_ip
+                        },
+                        ..ip
+                    });
+                }
+                Err(e) => errors.push((tag_setup.name.clone(), e)),
+            }
+        }
+        if !errors.is_empty() {
+            errors.sort_by(|(a, _), (b, _)| a.cmp(b));
+            return Err(Report::new(crate::each::MachineErrors { errors, total })
+                .wrap_err("could not connect to some instances"));
+        }
+
+        Ok(())
+    }
+
+    // Finds the subnet, VPC, and peered address ranges of `near`.
+    #[instrument(level = "trace", skip(self))]
+    async fn join_network(mut self, near: Option<Affinity>) -> Result<Self, Report> {
+        let near = match near {
+            Some(near) => near,
+            None => return Ok(self),
+        };
+        let ec2 = self.client.as_ref().expect("RegionLauncher unconnected");
+        let filter = |name: &str, value: &str| rusoto_ec2::Filter {
+            name: Some(name.to_string()),
+            values: Some(vec![value.to_string()]),
+        };
+        let instance_subnet = |filters: Vec<rusoto_ec2::Filter>| async move {
+            let reservations = ec2
+                .describe_instances(rusoto_ec2::DescribeInstancesRequest {
+                    filters: Some(filters),
+                    ..Default::default()
+                })
+                .await
+                .wrap_err("failed to look up instance")?
+                .reservations
+                .unwrap_or_default();
+            Ok::<_, Report>(
+                reservations
+                    .into_iter()
+                    .flat_map(|r| r.instances.unwrap_or_default())
+                    .find_map(|i| i.subnet_id),
+            )
+        };
+        let subnets = |filters: Vec<rusoto_ec2::Filter>| async move {
+            Ok::<_, Report>(
+                ec2.describe_subnets(rusoto_ec2::DescribeSubnetsRequest {
+                    filters: Some(filters),
+                    ..Default::default()
+                })
+                .await
+                .wrap_err("failed to look up subnets")?
+                .subnets
+                .unwrap_or_default(),
+            )
+        };
+        let vpc_subnet = |vpc: String| {
+            let az = self.availability_zone.clone();
+            async move {
+                pick_subnet(subnets(vec![filter("vpc-id", &vpc)]).await?, &az)
+                    .ok_or_else(|| eyre!("vpc {} has no subnet to launch into", vpc))
+            }
+        };
+
+        let subnet = match near {
+            Affinity::Instance(ref id) => instance_subnet(vec![filter("instance-id", id)])
+                .await?
+                .ok_or_else(|| eyre!("no instance {} in a vpc", id))?,
+            Affinity::Subnet(ref id) => id.clone(),
+            Affinity::Vpc(ref id) => vpc_subnet(id.clone()).await?,
+            Affinity::Tagged { ref key, ref value } => {
+                let tag = format!("tag:{}", key);
+                let alive = rusoto_ec2::Filter {
+                    name: Some(String::from("instance-state-name")),
+                    values: Some(vec![String::from("pending"), String::from("running")]),
+                };
+                if let Some(subnet) = instance_subnet(vec![filter(&tag, value), alive]).await? {
+                    subnet
+                } else if let Some(subnet) = subnets(vec![filter(&tag, value)])
+                    .await?
+                    .into_iter()
+                    .find_map(|s| s.subnet_id)
+                {
+                    subnet
+                } else {
+                    let vpc = ec2
+                        .describe_vpcs(rusoto_ec2::DescribeVpcsRequest {
+                            filters: Some(vec![filter(&tag, value)]),
+                            ..Default::default()
+                        })
+                        .await
+                        .wrap_err("failed to look up vpcs")?
+                        .vpcs
+                        .unwrap_or_default()
+                        .into_iter()
+                        .find_map(|v| v.vpc_id)
+                        .ok_or_else(|| eyre!("nothing tagged {}={}", key, value))
+                        .suggestion(
+                            "Only instances, subnets, and VPCs in the machines' region are \
+                             considered",
+                        )?;
+                    vpc_subnet(vpc).await?
+                }
+            }
+        };
+
+        let subnet = subnets(vec![filter("subnet-id", &subnet)])
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("no subnet {}", subnet))?;
+        let (subnet_id, vpc_id, availability_zone) =
+            match (subnet.subnet_id, subnet.vpc_id, subnet.availability_zone) {
+                (Some(s), Some(v), Some(az)) => (s, v, az),
+                _ => eyre::bail!("subnet description is incomplete"),
+            };
+        if let AvailabilityZoneSpec::Specify(ref az) = self.availability_zone {
+            eyre::ensure!(
+                *az == availability_zone,
+                "subnet {} is in {}, not in {}",
+                subnet_id,
+                availability_zone,
+                az
+            );
+        }
+
+        let vpc = ec2
+            .describe_vpcs(rusoto_ec2::DescribeVpcsRequest {
+                vpc_ids: Some(vec![vpc_id.clone()]),
+                ..Default::default()
+            })
+            .await
+            .wrap_err("failed to look up vpc")?
+            .vpcs
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("no vpc {}", vpc_id))?;
+        let mut cidr_blocks: Vec<_> = vpc
+            .cidr_block_association_set
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|a| {
+                a.cidr_block_state.as_ref().and_then(|s| s.state.as_deref()) == Some("associated")
+            })
+            .filter_map(|a| a.cidr_block)
+            .collect();
+        if cidr_blocks.is_empty() {
+            cidr_blocks.extend(vpc.cidr_block);
+        }
+
+        let mut peerings = Vec::new();
+        for side in ["requester-vpc-info.vpc-id", "accepter-vpc-info.vpc-id"] {
+            peerings.extend(
+                ec2.describe_vpc_peering_connections(
+                    rusoto_ec2::DescribeVpcPeeringConnectionsRequest {
+                        filters: Some(vec![filter(side, &vpc_id), filter("status-code", "active")]),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .wrap_err("failed to look up vpc peering connections")?
+                .vpc_peering_connections
+                .unwrap_or_default(),
+            );
+        }
+        let peered_cidr_blocks = peered_cidrs(&vpc_id, &peerings);
+
+        tracing::debug!(%vpc_id, %subnet_id, peered = ?peered_cidr_blocks, "joining network");
+        self.network = Some(Network {
+            vpc_id,
+            subnet_id,
+            availability_zone,
+            cidr_blocks,
+            peered_cidr_blocks,
+        });
+        Ok(self)
+    }
+
+    // Instances in the default VPC name their security group directly, while instances that join
+    // a network, or that need an EFA, get theirs through their network interface.
+    fn security_group_ids(&self, efa: bool) -> Option<Vec<String>> {
+        match self.network {
+            None if !efa => Some(vec![self.security_group_id.clone()]),
+            _ => None,
+        }
+    }
+
+    fn network_interfaces(
+        &self,
+        efa: bool,
+    ) -> Option<Vec<rusoto_ec2::InstanceNetworkInterfaceSpecification>> {
+        if self.network.is_none() && !efa {
+            return None;
+        }
+        Some(vec![rusoto_ec2::InstanceNetworkInterfaceSpecification {
+            device_index: Some(0),
+            // without a subnet, EC2 picks the default subnet of the instance's availability zone.
+            subnet_id: self.network.as_ref().map(|n| n.subnet_id.clone()),
+            groups: Some(vec![self.security_group_id.clone()]),
+            // tsunami connects to the machines over their public address, unless it can go through
+            // SSM, in which case a private subnet's instances can stay private.
+            associate_public_ip_address: if self.network.is_some() && self.ssm_fallback {
+                None
+            } else {
+                Some(true)
+            },
+            delete_on_termination: Some(true),
+            interface_type: if efa { Some(String::from("efa")) } else { None },
+            ..Default::default()
+        }])
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn use_security_group(mut self, group_id: &str) -> Result<Self, Report> {
+        let ec2 = self.client.as_mut().expect("RegionLauncher unconnected");
+        let req = rusoto_ec2::DescribeSecurityGroupsRequest {
+            group_ids: Some(vec![group_id.to_string()]),
+            ..Default::default()
+        };
+        let group = ec2
+            .describe_security_groups(req)
+            .await
+            .wrap_err_with(|| format!("could not look up security group {}", group_id))?
+            .security_groups
+            .and_then(|gs| gs.into_iter().next())
+            .ok_or_else(|| eyre!("security group {} does not exist", group_id))?;
+        if let (Some(n), Some(vpc_id)) = (self.network.as_ref(), group.vpc_id.as_ref()) {
+            eyre::ensure!(
+                *vpc_id == n.vpc_id,
+                "security group {} is in {}, but the machines are launched into {}",
+                group_id,
+                vpc_id,
+                n.vpc_id
+            );
+        }
+        tracing::debug!(id = %group_id, "using existing security group");
+
+        self.security_group_id = group_id.to_string();
+        self.existing_security_group = true;
+        Ok(self)
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn make_security_group(mut self, use_open_ports: bool) -> Result<Self, Report> {
+        let ec2 = self.client.as_ref().expect("RegionLauncher unconnected");
+
+        // set up network firewall for machines
+        let group_name = super::rand_name("security");
+        tracing::debug!(name = %group_name, "creating security group");
+        let req = rusoto_ec2::CreateSecurityGroupRequest {
+            group_name,
+            description: "temporary access group for tsunami VMs".to_string(),
+            vpc_id: self.network.as_ref().map(|n| n.vpc_id.clone()),
+            tag_specifications: Some(self.run_tags("security-group")),
+            ..Default::default()
+        };
+        let res = ec2
+            .create_security_group(req)
+            .await
+            .wrap_err("failed to create security group for new machines")?;
+        let group_id = res
+            .group_id
+            .expect("aws created security group with no group id");
+        tracing::trace!(id = %group_id, "security group created");
+
+        self.security_group_id = group_id;
+        self.use_open_ports = use_open_ports;
+        // the traffic to the machines is admitted when they are launched.
+        Ok(self)
+    }
+
+    // The SSH and ICMP rules that let the machine running tsunami reach the machines.
+    fn control_ingress(&self) -> Vec<IngressRule> {
+        let from = self.ssh_from.as_deref().unwrap_or("0.0.0.0/0");
+        vec![
+            IngressRule::icmp().from_cidr(from),
+            IngressRule::tcp(22..=22).from_cidr(from),
+        ]
+    }
+
+    // Admits the traffic that the launcher and `machines` ask for into the security group.
+    async fn admit_traffic(&mut self, machines: &[(String, Setup)]) -> Result<(), Report> {
+        let asked: Vec<_> = machines
+            .iter()
+            .flat_map(|(_, m)| m.ingress.iter().cloned())
+            .collect();
+        if self.existing_security_group {
+            if self.ingress.is_some() || !asked.is_empty() {
+                return Err(eyre!(
+                    "cannot add ingress rules to existing security group {}",
+                    self.security_group_id
+                ))
+                .suggestion("Add the rules to the security group itself instead");
             }
-            | LaunchMode::DefinedDuration {
-                hours: max_instance_duration_hours,
-            } => {
-                let machines = machines.clone();
+            return Ok(());
+        }
 
-                // leave this to short-circuit: we only want to fall back to OnDemand if there is
-                // no spot capacity, not if we can't make the request in the first place.
-                self.make_spot_instance_requests(
-                    max_instance_duration_hours * 60, // 60 mins/hr
-                    machines,
-                )
+        let mut rules = self.control_ingress();
+        match self.ingress {
+            Some(ref r) => rules.extend(r.iter().cloned()),
+            None if self.use_open_ports => rules.extend(vec![
+                IngressRule::all("tcp").from_anywhere(),
+                IngressRule::all("udp").from_anywhere(),
+            ]),
+            None => rules.extend(vec![IngressRule::all("tcp"), IngressRule::all("udp")]),
+        }
+        rules.extend(asked);
+        if machines.iter().any(|(_, m)| m.efa) {
+            self.admit_own_traffic().await?;
+        }
+        self.authorize_ingress(rules).await
+    }
+
+    // EFA only works between instances whose security group admits all traffic from itself.
+    async fn admit_own_traffic(&mut self) -> Result<(), Report> {
+        if self.admits_own_traffic {
+            return Ok(());
+        }
+        let ec2 = self.client.as_ref().expect("RegionLauncher unconnected");
+        tracing::debug!(group = %self.security_group_id, "admitting all traffic from the group");
+        let req = rusoto_ec2::AuthorizeSecurityGroupIngressRequest {
+            group_id: Some(self.security_group_id.clone()),
+            ip_permissions: Some(vec![rusoto_ec2::IpPermission {
+                ip_protocol: Some(String::from("-1")),
+                user_id_group_pairs: Some(vec![rusoto_ec2::UserIdGroupPair {
+                    group_id: Some(self.security_group_id.clone()),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+        ec2.authorize_security_group_ingress(req)
+            .await
+            .wrap_err("failed to admit traffic from the security group itself")?;
+        self.admits_own_traffic = true;
+        Ok(())
+    }
+
+    // The rules the security group needs to admit `rules`, with the machines' own network spelled
+    // out.
+    fn resolve_ingress(&self, rules: Vec<IngressRule>) -> Vec<IngressRule> {
+        // The default VPC uses IPs in range 172.31.0.0/16:
+        // https://docs.aws.amazon.com/vpc/latest/userguide/default-vpc.html
+        let network: Vec<String> = match self.network {
+            Some(ref n) => n
+                .cidr_blocks
+                .iter()
+                .chain(&n.peered_cidr_blocks)
+                .cloned()
+                .collect(),
+            None => vec![String::from("172.31.0.0/16")],
+        };
+        rules
+            .into_iter()
+            .flat_map(|rule| {
+                let cidrs = match rule.cidr {
+                    Some(ref cidr) => vec![cidr.clone()],
+                    None => network.clone(),
+                };
+                cidrs.into_iter().map(move |cidr| IngressRule {
+                    cidr: Some(cidr),
+                    ..rule.clone()
+                })
+            })
+            .collect()
+    }
+
+    // Admits the traffic of `rules` into the temporary security group, skipping the rules it
+    // already admits.
+    #[instrument(level = "trace", skip(self))]
+    async fn authorize_ingress(&mut self, rules: Vec<IngressRule>) -> Result<(), Report> {
+        let ec2 = self.client.as_ref().expect("RegionLauncher unconnected");
+        for rule in self.resolve_ingress(rules) {
+            if self.authorized.contains(&rule) {
+                continue;
+            }
+            tracing::debug!(group = %self.security_group_id, %rule, "admitting traffic");
+            let req = rusoto_ec2::AuthorizeSecurityGroupIngressRequest {
+                group_id: Some(self.security_group_id.clone()),
+                ip_protocol: Some(rule.protocol.to_string()),
+                from_port: Some(rule.from_port),
+                to_port: Some(rule.to_port),
+                cidr_ip: rule.cidr.clone(),
+                ..Default::default()
+            };
+            ec2.authorize_security_group_ingress(req)
                 .await
-                .wrap_err("failed to make spot instance requests")?;
+                .wrap_err_with(|| format!("failed to admit {}", rule))?;
+            self.authorized.insert(rule);
+        }
+        Ok(())
+    }
 
-                let start = time::Instant::now();
-                if let Err(e) = self
-                    .wait_for_spot_instance_requests(max_wait)
-                    .await
-                    .wrap_err(eyre!(
-                        "failed while waiting for spot instances fulfilment in {}",
-                        self.region.name()
-                    ))
-                {
-                    // if wait_for_spot_instance_requests returned an Err, it will have cleaned up
-                    // the spot instance requests already.
-                    if let LaunchMode::TrySpot { .. } = mode {
-                        tracing::debug!(err = ?e, "re-trying with OnDemand instace");
-                        do_ondemand = true;
-                    } else {
-                        return Err(e);
-                    }
-                } else {
-                    if let Some(ref mut d) = max_wait {
-                        *d -= time::Instant::now().duration_since(start);
-                    }
+    /// Restrict the temporary security group to `rules`, plus SSH and ICMP from the machine running
+    /// tsunami and ICMP from the machines' network, revoking every other rule it has. See
+    /// [`Launcher::tighten_after_setup`].
+    ///
+    /// Each revoked rule, and the final policy, is logged at the `info` level.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn tighten_ingress(&mut self, rules: Vec<IngressRule>) -> Result<(), Report> {
+        eyre::ensure!(
+            !self.existing_security_group,
+            "cannot change the rules of existing security group {}",
+            self.security_group_id
+        );
+        let mut rules = rules;
+        rules.extend(self.control_ingress());
+        rules.push(IngressRule::icmp());
+        let keep: HashSet<_> = self.resolve_ingress(rules).into_iter().collect();
+
+        let ec2 = self.client.as_ref().expect("RegionLauncher unconnected");
+        let stale: Vec<_> = self
+            .authorized
+            .iter()
+            .filter(|r| !keep.contains(r))
+            .cloned()
+            .collect();
+        for rule in stale {
+            let req = rusoto_ec2::RevokeSecurityGroupIngressRequest {
+                group_id: Some(self.security_group_id.clone()),
+                ip_protocol: Some(rule.protocol.to_string()),
+                from_port: Some(rule.from_port),
+                to_port: Some(rule.to_port),
+                cidr_ip: rule.cidr.clone(),
+                ..Default::default()
+            };
+            ec2.revoke_security_group_ingress(req)
+                .await
+                .wrap_err_with(|| format!("failed to revoke {}", rule))?;
+            tracing::info!(group = %self.security_group_id, %rule, "revoked ingress rule");
+            self.authorized.remove(&rule);
+        }
+
+        let policy = keep.iter().map(ToString::to_string).sorted().join(", ");
+        self.authorize_ingress(keep.into_iter().collect()).await?;
+        tracing::info!(group = %self.security_group_id, %policy, "security group tightened");
+        Ok(())
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn make_ssh_key(mut self) -> Result<Self, Report> {
+        let tag_specifications = Some(self.run_tags("key-pair"));
+        let ec2 = self.client.as_mut().expect("RegionLauncher unconnected");
+        let private_key_path = match self.private_key_path {
+            Some(PrivateKey::Temporary(ref mut f)) => f,
+            _ => unreachable!("RegionLauncher unconnected"),
+        };
+
+        // construct keypair for ssh access
+        tracing::debug!("creating keypair");
+        let key_name = super::rand_name("key");
+        let req = rusoto_ec2::CreateKeyPairRequest {
+            key_name: key_name.clone(),
+            tag_specifications,
+            ..Default::default()
+        };
+        let res = ec2
+            .create_key_pair(req)
+            .await
+            .context("failed to generate new key pair")?;
+        tracing::trace!(fingerprint = ?res.key_fingerprint, "created keypair");
+
+        // write keypair to disk
+        let private_key = res
+            .key_material
+            .expect("aws did not generate key material for new key");
+        private_key_path
+            .write_all(private_key.as_bytes())
+            .context("could not write private key to file")?;
+        tracing::debug!(
+            filename = %private_key_path.path().display(),
+            "wrote keypair to file"
+        );
+
+        self.ssh_key_name = key_name;
+        Ok(self)
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    async fn use_key_pair(
+        mut self,
+        name: &str,
+        private_key_path: &std::path::Path,
+    ) -> Result<Self, Report> {
+        eyre::ensure!(
+            private_key_path.is_file(),
+            "private key {} does not exist",
+            private_key_path.display()
+        );
+        let ec2 = self.client.as_ref().expect("RegionLauncher unconnected");
+        let req = rusoto_ec2::DescribeKeyPairsRequest {
+            key_names: Some(vec![name.to_string()]),
+            ..Default::default()
+        };
+        // EC2 reports an error for key pairs that do not exist.
+        ec2.describe_key_pairs(req)
+            .await
+            .wrap_err_with(|| format!("key pair {} is not in {}", name, self.region.name()))
+            .suggestion("Import the public key with `aws ec2 import-key-pair`")?;
+        tracing::debug!(%name, "using existing key pair");
+
+        self.ssh_key_name = name.to_string();
+        self.private_key_path = Some(PrivateKey::Existing(private_key_path.to_owned()));
+        Ok(self)
+    }
+
+    fn existing_key_pair(&self) -> bool {
+        matches!(self.private_key_path, Some(PrivateKey::Existing(_)))
+    }
+
+    /// Make a new placement for a launch request.
+    ///
+    /// This method takes a "placement maker" (`mk`) to allow using this method for both
+    /// `SpotPlacement` and `Placement`. The `mk` function is passed a placement group name and an
+    /// availability zone, and is expected to return an appropriate placement type. It is not
+    /// called if there is neither.
+    #[instrument(level = "trace", skip(self, mk))]
+    async fn make_placement<R>(
+        &mut self,
+        group: Option<&PlacementGroup>,
+        mk: impl FnOnce(Option<String>, Option<String>) -> R,
+    ) -> Result<Option<R>, Report> {
+        let name = match group {
+            Some(PlacementGroup::Named(name)) => Some(self.named_placement_group(name).await?),
+            Some(PlacementGroup::Auto) => match self.auto_placement_group {
+                Some(ref name) => Some(name.clone()),
+                None => {
+                    let name = self
+                        .create_placement_group(super::rand_name("placement"))
+                        .await?;
+                    Some(self.auto_placement_group.insert(name).clone())
                 }
-            }
-            LaunchMode::OnDemand => {
-                do_ondemand = true;
-            }
+            },
+            None if self.availability_zone == AvailabilityZoneSpec::Any => None,
+            // machines pinned to a zone also sit close together within it
+            None => Some(
+                self.create_placement_group(super::rand_name("placement"))
+                    .await?,
+            ),
+        };
+
+        if name.is_none() && self.zone.is_none() {
+            return Ok(None);
         }
+        Ok(Some(mk(name, self.zone.clone())))
+    }
 
-        if do_ondemand {
-            self.make_on_demand_requests(machines)
+    // The block device mappings for the root volume and the data volumes of `group`, if any.
+    async fn block_device_mappings(
+        &self,
+        group: &RequestGroup,
+    ) -> Result<Option<Vec<rusoto_ec2::BlockDeviceMapping>>, Report> {
+        let mut mappings = Vec::new();
+        if let Some((size_gb, volume_type)) = group.root_volume {
+            let root = self
+                .root_block_device(&group.ami, size_gb, volume_type)
                 .await
-                .wrap_err(eyre!(
-                    "failed to start on demand instances in {}",
-                    self.region.name()
-                ))?;
+                .wrap_err("failed to size root volume")?;
+            mappings.push(root);
+        }
+        let devices = data_volume_devices(&group.data_volumes)?;
+        for (v, device) in group.data_volumes.iter().zip(devices) {
+            mappings.push(ebs_mapping(
+                device,
+                v.size_gb,
+                v.volume_type,
+                v.delete_on_termination,
+            ));
+        }
+        Ok(if mappings.is_empty() {
+            None
+        } else {
+            Some(mappings)
+        })
+    }
 
-            // give EC2 a bit of time to discover the instances
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    // The block device mapping that gives instances of `ami` a root volume of `size_gb` GiB.
+    async fn root_block_device(
+        &self,
+        ami: &str,
+        size_gb: i64,
+        volume_type: VolumeType,
+    ) -> Result<rusoto_ec2::BlockDeviceMapping, Report> {
+        let image = self
+            .client
+            .as_ref()
+            .expect("RegionLauncher unconnected")
+            .describe_images(rusoto_ec2::DescribeImagesRequest {
+                image_ids: Some(vec![ami.to_string()]),
+                ..Default::default()
+            })
+            .await
+            .wrap_err_with(|| format!("failed to look up AMI {}", ami))?
+            .images
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .ok_or_else(|| eyre!("no AMI {}", ami))?;
+        eyre::ensure!(
+            image.root_device_type.as_deref() == Some("ebs"),
+            "AMI {} does not boot from an EBS volume",
+            ami
+        );
+        let device = image
+            .root_device_name
+            .ok_or_else(|| eyre!("AMI {} has no root device", ami))?;
+        let snapshot_gb = image
+            .block_device_mappings
+            .iter()
+            .flatten()
+            .find(|m| m.device_name.as_ref() == Some(&device))
+            .and_then(|m| m.ebs.as_ref()?.volume_size);
+        if let Some(snapshot_gb) = snapshot_gb {
+            eyre::ensure!(
+                size_gb >= snapshot_gb,
+                "a root volume of {} GiB is smaller than the {} GiB snapshot of AMI {}",
+                size_gb,
+                snapshot_gb,
+                ami
+            );
         }
 
-        self.wait_for_instances(max_wait)
+        Ok(ebs_mapping(device, size_gb, volume_type, true))
+    }
+
+    // Decides which availability zone to launch `machines` in, if they should all be in one.
+    async fn pin_zone(&mut self, machines: &[(String, Setup)]) -> Result<(), Report> {
+        if self.zone.is_some() {
+            return Ok(());
+        }
+
+        self.zone = match self.availability_zone {
+            AvailabilityZoneSpec::Any => None,
+            AvailabilityZoneSpec::Specify(ref az) => Some(az.clone()),
+            AvailabilityZoneSpec::Cluster(_) => match self.network {
+                // the subnet decides the zone
+                Some(ref n) => Some(n.availability_zone.clone()),
+                None => Some(self.offering_zone(machines).await?),
+            },
+        };
+        if let Some(ref az) = self.zone {
+            tracing::debug!(%az, "launching in availability zone");
+        }
+        Ok(())
+    }
+
+    /// The availability zones in which spot requests have repeatedly gone unfulfilled for lack
+    /// of capacity during this run.
+    ///
+    /// Machines that may go in any zone are launched elsewhere from then on. Machines that were
+    /// told which zone to use, that share a cluster, or that join a subnet stay where they are.
+    pub fn avoided_zones(&self) -> impl Iterator<Item = &str> {
+        self.spot_strikes
+            .iter()
+            .filter(|(_, n)| **n >= SPOT_ZONE_STRIKES)
+            .map(|(az, _)| az.as_str())
+    }
+
+    // Counts one more round of failed spot requests in each of `zones`.
+    fn strike_zones(&mut self, zones: impl IntoIterator<Item = String>) {
+        let zones: HashSet<_> = zones.into_iter().collect();
+        for az in zones {
+            let n = self.spot_strikes.entry(az.clone()).or_default();
+            *n += 1;
+            if *n == SPOT_ZONE_STRIKES {
+                tracing::warn!(%az, "avoiding availability zone after repeated spot capacity failures");
+            }
+        }
+    }
+
+    // Picks the zone to request spot instances for machines that may go in any zone in, leaving
+    // out the zones that keep failing spot requests.
+    //
+    // The zone is asked for explicitly, since EC2 does not say where it looked for capacity for
+    // a request that it could not fulfill, and the zone could not be struck otherwise.
+    async fn steer_zone(&mut self, machines: &[(String, Setup)]) -> Result<(), Report> {
+        // placement groups live in the zone of their first instance
+        let placed = machines.iter().any(|(_, m)| m.placement_group.is_some());
+        if self.availability_zone != AvailabilityZoneSpec::Any || placed || self.network.is_some() {
+            return Ok(());
+        }
+        match self.offering_zone(machines).await {
+            Ok(az) => {
+                tracing::debug!(%az, "requesting spot instances in availability zone");
+                self.zone = Some(az);
+            }
+            Err(e) => {
+                tracing::warn!(err = ?e, "no availability zone to steer to, letting EC2 pick");
+                self.zone = None;
+            }
+        }
+        Ok(())
+    }
+
+    // The first availability zone that offers the instance types of all of `machines`, and that
+    // spot requests are not avoiding.
+    async fn offering_zone(&self, machines: &[(String, Setup)]) -> Result<String, Report> {
+        let mut types: Vec<_> = machines
+            .iter()
+            .map(|(_, m)| m.instance_type.clone())
+            .collect();
+        types.sort();
+        types.dedup();
+        common_zone(self.instance_type_offerings(&types).await?, &types)
+            .ok_or_else(|| eyre!("no availability zone offers all of {}", types.join(", ")))
+            .suggestion("use fewer instance types, or launch the machines in separate clusters")
+    }
+
+    // The (instance type, zone) pairs in which `types` are offered, leaving out the zones that
+    // spot requests are avoiding.
+    async fn instance_type_offerings(
+        &self,
+        types: &[String],
+    ) -> Result<Vec<(String, String)>, Report> {
+        let ec2 = self.client.as_ref().expect("RegionLauncher unconnected");
+        let offerings = ec2
+            .describe_instance_type_offerings(rusoto_ec2::DescribeInstanceTypeOfferingsRequest {
+                location_type: Some(String::from("availability-zone")),
+                filters: Some(vec![rusoto_ec2::Filter {
+                    name: Some(String::from("instance-type")),
+                    values: Some(types.to_vec()),
+                }]),
+                ..Default::default()
+            })
+            .await
+            .wrap_err("failed to look up instance type offerings")?
+            .instance_type_offerings
+            .unwrap_or_default();
+        let avoided: HashSet<_> = self.avoided_zones().collect();
+        Ok(offerings
+            .into_iter()
+            .filter_map(|o| Some((o.instance_type?, o.location?)))
+            .filter(|(_, az)| !avoided.contains(az.as_str()))
+            .collect())
+    }
+
+    // Creates the cluster placement group `name`, and remembers to delete it at teardown.
+    async fn create_placement_group(&mut self, name: String) -> Result<String, Report> {
+        let ec2 = self.client.as_ref().expect("RegionLauncher unconnected");
+        tracing::trace!(%name, "creating placement group");
+        let req = rusoto_ec2::CreatePlacementGroupRequest {
+            group_name: Some(name.clone()),
+            strategy: Some(String::from("cluster")),
+            tag_specifications: Some(self.run_tags("placement-group")),
+            ..Default::default()
+        };
+        ec2.create_placement_group(req)
+            .await
+            .wrap_err_with(|| format!("failed to create placement group {}", name))?;
+        tracing::trace!(%name, "created placement group");
+        self.placement_groups.push(name.clone());
+        Ok(name)
+    }
+
+    // The placement group `name`, which is created if it does not exist yet.
+    async fn named_placement_group(&mut self, name: &str) -> Result<String, Report> {
+        if self.placement_groups.iter().any(|g| g == name) {
+            return Ok(name.to_string());
+        }
+        let ec2 = self.client.as_ref().expect("RegionLauncher unconnected");
+        let req = rusoto_ec2::DescribePlacementGroupsRequest {
+            filters: Some(vec![rusoto_ec2::Filter {
+                name: Some(String::from("group-name")),
+                values: Some(vec![name.to_string()]),
+            }]),
+            ..Default::default()
+        };
+        let exists = !ec2
+            .describe_placement_groups(req)
             .await
-            .wrap_err("failed while waiting for instances to come up")?;
+            .wrap_err_with(|| format!("failed to look up placement group {}", name))?
+            .placement_groups
+            .unwrap_or_default()
+            .is_empty();
+        if exists {
+            tracing::debug!(%name, "using existing placement group");
+            Ok(name.to_string())
+        } else {
+            self.create_placement_group(name.to_string()).await
+        }
+    }
+
+    // Replaces the image spec of each machine that has one with the matching AMI in this region.
+    #[instrument(level = "trace", skip(self, machines))]
+    async fn resolve_images(
+        &self,
+        mut machines: Vec<(String, Setup)>,
+    ) -> Result<Vec<(String, Setup)>, Report> {
+        let mut amis: HashMap<_, String> = HashMap::new();
+        for (_, m) in &mut machines {
+            let arch = architecture(&m.instance_type);
+            for alt in &m.alternative_instance_types {
+                eyre::ensure!(
+                    architecture(alt) == arch,
+                    "{} is {}, but its alternative instance type {} is {}",
+                    m.instance_type,
+                    arch,
+                    alt,
+                    architecture(alt)
+                );
+            }
+            let spec = match m.image.take() {
+                Some(spec) => spec,
+                None => continue,
+            };
+            m.ami = match amis.entry((spec, arch)) {
+                std::collections::hash_map::Entry::Occupied(e) => e.get().clone(),
+                std::collections::hash_map::Entry::Vacant(e) => {
+                    let (spec, arch) = e.key();
+                    let ami = self
+                        .find_ami(spec, arch)
+                        .await
+                        .wrap_err_with(|| format!("failed to find an {} AMI for {}", arch, spec))?;
+                    tracing::debug!(image = %spec, %arch, %ami, "resolved image");
+                    e.insert(ami).clone()
+                }
+            };
+        }
+        Ok(machines)
+    }
+
+    // `arch` is an EC2 architecture name, see `architecture`.
+    async fn find_ami(&self, spec: &crate::image::ImageSpec, arch: &str) -> Result<String, Report> {
+        if let crate::image::ImageSpec::ProviderSpecific(ami) = spec {
+            return Ok(ami.clone());
+        }
+        let (owner, name) =
+            ami_query(spec, arch).ok_or_else(|| eyre!("unsupported image {}", spec))?;
+        match (
+            self.describe_ami(owner, &name, arch).await,
+            ubuntu_release(spec),
+        ) {
+            (Ok(ami), _) => Ok(ami),
+            (Err(e), Some(release)) => {
+                // new regions sometimes show up in Ubuntu's image list before EC2 lists the image
+                tracing::warn!(image = %spec, err = %e, "falling back to Ubuntu's image list");
+                let ami = UbuntuAmi::new(self.region.clone(), release, arch)
+                    .await
+                    .wrap_err_with(|| format!("EC2 did not list an AMI either: {}", e))?;
+                Ok(ami.into())
+            }
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    // The newest available AMI for `arch` owned by `owner` whose name matches `name`.
+    async fn describe_ami(&self, owner: &str, name: &str, arch: &str) -> Result<String, Report> {
+        let filter = |name: &str, value: &str| rusoto_ec2::Filter {
+            name: Some(name.to_string()),
+            values: Some(vec![value.to_string()]),
+        };
+        let images = self
+            .client
+            .as_ref()
+            .unwrap()
+            .describe_images(rusoto_ec2::DescribeImagesRequest {
+                owners: Some(vec![owner.to_string()]),
+                filters: Some(vec![
+                    filter("name", name),
+                    filter("state", "available"),
+                    filter("architecture", arch),
+                ]),
+                ..Default::default()
+            })
+            .await?
+            .images
+            .unwrap_or_default();
+        images
+            .into_iter()
+            .filter(|i| i.image_id.is_some())
+            .max_by(|a, b| a.creation_date.cmp(&b.creation_date))
+            .and_then(|i| i.image_id)
+            .ok_or_else(|| eyre!("no {} AMI named {} owned by {}", arch, name, owner))
+    }
+
+    // Groups `machines` by their launch request. Both the groups and the machines within each
+    // group are ordered by nickname, so the same machines always make the same requests.
+    fn for_each_machine_group<M>(
+        machines: M,
+        request_size: Option<usize>,
+    ) -> impl Iterator<Item = (RequestGroup, Vec<(String, Setup)>)> + Send
+    where
+        M: IntoIterator<Item = (String, Setup)>,
+        M: std::fmt::Debug,
+    {
+        // minimize the number of instance requests:
+        let mut groups: Vec<_> = machines
+            .into_iter()
+            .map(|(name, m)| (RequestGroup::of(&m), (name, m)))
+            .into_group_map()
+            .into_iter()
+            .map(|(group, mut reqs)| {
+                reqs.sort_by(|(a, _), (b, _)| a.cmp(b));
+                (group, reqs)
+            })
+            .collect();
+        groups.sort_by(|(_, a), (_, b)| a[0].0.cmp(&b[0].0));
+        // and split up the ones that are too large for one request.
+        groups.into_iter().flat_map(move |(group, reqs)| {
+            batches(reqs, request_size)
+                .into_iter()
+                .map(move |reqs| (group.clone(), reqs))
+        })
+    }
+
+    // An idempotency token for the request that launches `reqs`, so that retrying the request
+    // cannot launch the same machines twice.
+    //
+    // See https://docs.aws.amazon.com/AWSEC2/latest/APIReference/Run_Instance_Idempotency.html
+    fn client_token(&self, kind: &str, reqs: &[(String, Setup)]) -> String {
+        use std::hash::{Hash, Hasher};
+        let mut names: Vec<_> = reqs.iter().map(|(name, _)| name).collect();
+        names.sort();
+        let mut h = std::collections::hash_map::DefaultHasher::new();
+        names.hash(&mut h);
+        format!("{}-{}-{:016x}", self.run_id, kind, h.finish())
+    }
+
+    // The tags of everything this launcher creates: its run id, and the tags it was given.
+    fn tags(&self) -> Vec<rusoto_ec2::Tag> {
+        std::iter::once((RUN_TAG, &self.run_id))
+            .chain(self.tags.iter().map(|(k, v)| (k.as_str(), v)))
+            .map(|(k, v)| rusoto_ec2::Tag {
+                key: Some(k.to_string()),
+                value: Some(v.clone()),
+            })
+            .collect()
+    }
+
+    // Tags a newly created resource with this launcher's run id and tags.
+    fn run_tags(&self, resource_type: &str) -> Vec<rusoto_ec2::TagSpecification> {
+        vec![rusoto_ec2::TagSpecification {
+            resource_type: Some(resource_type.to_string()),
+            tags: Some(self.tags()),
+        }]
+    }
+
+    // Tags every instance that was not tagged at launch with its nickname.
+    //
+    // The instances of one request come up in no particular order, so which of them gets which
+    // nickname is arbitrary, and cannot be decided before they exist. That is fine because the
+    // machines of a request only differ in what happens after launch (see `RequestGroup::of`),
+    // but the tags are then the only record of the choice outside this process.
+    #[instrument(level = "trace", skip(self))]
+    async fn tag_instances(&self) -> Result<(), Report> {
+        let client = self.client.as_ref().unwrap();
+        let untagged = self.instances.iter().filter(|(_, t)| !t.name_tagged);
+        futures_util::future::try_join_all(untagged.map(|(instance_id, t)| {
+            let req = rusoto_ec2::CreateTagsRequest {
+                resources: vec![instance_id.clone()],
+                tags: name_tags(&t.name).into_iter().chain(self.tags()).collect(),
+                ..Default::default()
+            };
+            async move {
+                let mut tries = 0;
+                loop {
+                    match retry_dispatch(|| client.create_tags(req.clone())).await {
+                        // new instances take a moment to become visible to the rest of EC2.
+                        Err(rusoto_core::RusotoError::Unknown(ref r))
+                            if tries < 10
+                                && ec2_error_code(&r.body_as_str())
+                                    .map_or(false, |c| c.starts_with("InvalidInstanceID")) =>
+                        {
+                            tries += 1;
+                            tracing::trace!(%instance_id, "instance not yet visible for tagging");
+                            tokio::time::sleep(time::Duration::from_secs(1)).await;
+                        }
+                        r => {
+                            break r.wrap_err_with(|| format!("failed to tag {}", instance_id));
+                        }
+                    }
+                }
+            }
+        }))
+        .await?;
         Ok(())
     }
 
-    #[instrument(level = "trace", skip(self))]
-    async fn make_security_group(mut self, use_open_ports: bool) -> Result<Self, Report> {
-        let ec2 = self.client.as_mut().expect("RegionLauncher unconnected");
-
-        // set up network firewall for machines
-        let group_name = super::rand_name("security");
-        tracing::debug!(name = %group_name, "creating security group");
-        let req = rusoto_ec2::CreateSecurityGroupRequest {
-            group_name,
-            description: "temporary access group for tsunami VMs".to_string(),
-            ..Default::default()
-        };
-        let res = ec2
-            .create_security_group(req)
-            .await
-            .wrap_err("failed to create security group for new machines")?;
-        let group_id = res
-            .group_id
-            .expect("aws created security group with no group id");
-        tracing::trace!(id = %group_id, "security group created");
+    // Attach (and possibly create and mount) the extra volumes of every instance. Returns the ids
+    // of the volumes to snapshot at teardown, by instance id.
+    #[instrument(level = "trace", skip(self, max_wait))]
+    async fn attach_volumes(
+        &self,
+        max_wait: Option<time::Duration>,
+    ) -> Result<HashMap<String, Vec<String>>, Report> {
+        let want: Vec<_> = self
+            .instances
+            .iter()
+            .filter(|(_, t)| !t.setup.volumes.is_empty())
+            .collect();
+        if want.is_empty() {
+            return Ok(HashMap::new());
+        }
 
-        let mut req = rusoto_ec2::AuthorizeSecurityGroupIngressRequest {
-            group_id: Some(group_id.clone()),
-            // icmp access
-            ip_protocol: Some("icmp".to_string()),
-            from_port: Some(-1),
-            to_port: Some(-1),
-            cidr_ip: Some("0.0.0.0/0".to_string()),
+        let client = self.client.as_ref().unwrap();
+        let desc_req = rusoto_ec2::DescribeInstancesRequest {
+            instance_ids: Some(want.iter().map(|(id, _)| id.to_string()).collect()),
             ..Default::default()
         };
-        tracing::trace!("adding icmp access");
-        ec2.authorize_security_group_ingress(req.clone())
+        let zones: HashMap<_, _> = client
+            .describe_instances(desc_req)
             .await
-            .wrap_err("failed to fill in security group for new machines")?;
+            .wrap_err("could not query AWS for instance placement")?
+            .reservations
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|r| r.instances.unwrap_or_default())
+            .filter_map(|i| Some((i.instance_id?, i.placement?.availability_zone?)))
+            .collect();
+
+        let private_key_path = self.private_key_path.as_ref().unwrap().path();
+        let results = futures_util::future::join_all(want.into_iter().map(|(instance_id, t)| {
+            let zone = zones.get(instance_id).cloned();
+            let instance_span = tracing::debug_span!("instance", %instance_id, nickname = %t.name);
+            async move {
+                let zone = zone.ok_or_else(|| eyre!("{} has no availability zone", instance_id))?;
+                let mut mounts = Vec::new();
+                let mut results_volumes = Vec::new();
+                let taken = data_volume_devices(&t.setup.data_volumes)?
+                    .into_iter()
+                    .collect();
+                let mut free = free_devices(&taken);
+                for v in &t.setup.volumes {
+                    let device = free.next().ok_or_else(no_free_device)?;
+                    let volume_id = self
+                        .attach_volume(instance_id, &zone, &v.volume, &device)
+                        .await
+                        .wrap_err_with(|| format!("failed to attach {:?}", v.volume))?;
+                    if let Some(ref mount_point) = v.mount_point {
+                        mounts.push(mount_script(&volume_id, &device, mount_point));
+                    }
+                    if v.snapshot {
+                        results_volumes.push(volume_id);
+                    }
+                }
 
-        // allow SSH from anywhere
-        req.ip_protocol = Some("tcp".to_string());
-        req.from_port = Some(22);
-        req.to_port = Some(22);
-        req.cidr_ip = Some("0.0.0.0/0".to_string());
-        tracing::trace!("adding ssh access");
-        ec2.authorize_security_group_ingress(req.clone())
-            .await
-            .wrap_err("failed to fill in security group for new machines")?;
+                if !mounts.is_empty() {
+                    let m = self
+                        .descriptor(instance_id, t)
+                        .ok_or_else(|| eyre!("machine has no ip information"))?;
+                    let m = m
+                        .connect_ssh(&t.setup.username, Some(private_key_path), max_wait, 22)
+                        .await?;
+                    for script in mounts {
+                        let out = m.ssh.command("sh").arg("-c").arg(script).output().await?;
+                        eyre::ensure!(
+                            out.status.success(),
+                            "failed to mount volume: {}",
+                            String::from_utf8_lossy(&out.stderr)
+                        );
+                    }
+                }
+                Ok::<_, Report>((instance_id.clone(), results_volumes))
+            }
+            .instrument(instance_span)
+        }))
+        .await;
+        results.into_iter().collect()
+    }
 
-        // The default VPC uses IPs in range 172.31.0.0/16:
-        // https://docs.aws.amazon.com/vpc/latest/userguide/default-vpc.html
-        // TODO(might-be-nice) Support configurable rules for other VPCs
-        req.ip_protocol = Some("tcp".to_string());
-        req.from_port = Some(0);
-        req.to_port = Some(65535);
-        if use_open_ports {
-            req.cidr_ip = Some("0.0.0.0/0".to_string());
-        } else {
-            req.cidr_ip = Some("172.31.0.0/16".to_string());
+    /// Snapshot the [results volumes](Setup::results_volume_at) of every instance, and return the
+    /// ids of all the results snapshots taken so far, by nickname.
+    ///
+    /// Each volume is snapshotted only once. The snapshots are tagged with [`NICKNAME_TAG`] and
+    /// [`RUN_TAG`], and complete in the background, so the instances may be terminated as soon as
+    /// this returns.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn snapshot_results(&mut self) -> Result<HashMap<String, Vec<String>>, Report> {
+        let client = self.client.as_ref().unwrap();
+        let tag = |k: &str, v: &str| rusoto_ec2::Tag {
+            key: Some(k.to_string()),
+            value: Some(v.to_string()),
+        };
+        let tags = self.tags();
+        for t in self.instances.values_mut() {
+            while let Some(volume_id) = t.results_volumes.first().cloned() {
+                let req = rusoto_ec2::CreateSnapshotRequest {
+                    volume_id: volume_id.clone(),
+                    description: Some(format!("tsunami results of {} ({})", t.name, self.run_id)),
+                    tag_specifications: Some(vec![rusoto_ec2::TagSpecification {
+                        resource_type: Some(String::from("snapshot")),
+                        tags: Some(
+                            vec![tag("Name", &t.name), tag(NICKNAME_TAG, &t.name)]
+                                .into_iter()
+                                .chain(tags.iter().cloned())
+                                .collect(),
+                        ),
+                    }]),
+                    ..Default::default()
+                };
+                let snapshot_id = client
+                    .create_snapshot(req)
+                    .await
+                    .map_err(Report::new)
+                    .and_then(snapshot_id)
+                    .wrap_err_with(|| format!("failed to snapshot {} of {}", volume_id, t.name))?;
+                tracing::info!(
+                    nickname = %t.name,
+                    volume = %volume_id,
+                    snapshot = %snapshot_id,
+                    "snapshotted results volume"
+                );
+                t.results_volumes.remove(0);
+                self.snapshots
+                    .entry(t.name.clone())
+                    .or_default()
+                    .push(snapshot_id);
+            }
         }
+        Ok(self.snapshots.clone())
+    }
 
-        tracing::trace!("adding intra-vm tcp access");
-        ec2.authorize_security_group_ingress(req.clone())
-            .await
-            .wrap_err("failed to fill in security group for new machines")?;
-
-        req.ip_protocol = Some("udp".to_string());
-        req.from_port = Some(0);
-        req.to_port = Some(65535);
-        if use_open_ports {
-            req.cidr_ip = Some("0.0.0.0/0".to_string());
-        } else {
-            req.cidr_ip = Some("172.31.0.0/16".to_string());
+    /// Ask EC2 for the current state of every instance, and return the reports gathered so far,
+    /// by nickname.
+    ///
+    /// Instances that EC2 has already forgotten about, e.g. because they were terminated more than
+    /// an hour ago, keep their last report.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn instance_reports(&mut self) -> Result<HashMap<String, InstanceReport>, Report> {
+        if !self.instances.is_empty() {
+            let client = self.client.as_ref().unwrap();
+            // filter rather than list the ids, so that an instance EC2 has already forgotten
+            // about does not fail the whole request with InvalidInstanceID.NotFound.
+            let desc_req = rusoto_ec2::DescribeInstancesRequest {
+                filters: Some(vec![rusoto_ec2::Filter {
+                    name: Some(String::from("instance-id")),
+                    values: Some(self.instances.keys().cloned().collect()),
+                }]),
+                ..Default::default()
+            };
+            let now = time::SystemTime::now();
+            let instances = client
+                .describe_instances(desc_req)
+                .await
+                .wrap_err("could not query AWS for instance state")?
+                .reservations
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|r| r.instances.unwrap_or_default());
+            for report in instances.filter_map(|i| InstanceReport::from_instance(i, now)) {
+                if let Some(t) = self.instances.get(&report.instance_id) {
+                    self.reports.insert(t.name.clone(), report);
+                }
+            }
         }
+        Ok(self.reports.clone())
+    }
 
-        tracing::trace!("adding intra-vm udp access");
-        ec2.authorize_security_group_ingress(req)
-            .await
-            .wrap_err("failed to fill in security group for new machines")?;
+    // Attach `volume` to `instance_id` as `device`, creating it first if need be, and wait until
+    // it is attached. Returns the volume id.
+    async fn attach_volume(
+        &self,
+        instance_id: &str,
+        zone: &str,
+        volume: &Volume,
+        device: &str,
+    ) -> Result<String, Report> {
+        let client = self.client.as_ref().unwrap();
+        let volume_id = match volume {
+            Volume::Existing(id) => id.clone(),
+            Volume::New {
+                size_gb,
+                from_snapshot,
+            } => {
+                let req = rusoto_ec2::CreateVolumeRequest {
+                    availability_zone: zone.to_string(),
+                    size: *size_gb,
+                    snapshot_id: from_snapshot.clone(),
+                    volume_type: Some(String::from("gp3")),
+                    tag_specifications: Some(self.run_tags("volume")),
+                    ..Default::default()
+                };
+                let id = client
+                    .create_volume(req)
+                    .await?
+                    .volume_id
+                    .ok_or_else(|| eyre!("aws created volume with no volume id"))?;
+                tracing::debug!(volume = %id, "created volume");
+                id
+            }
+        };
 
-        self.security_group_id = group_id;
-        Ok(self)
+        let res = self
+            .attach_created_volume(instance_id, volume, &volume_id, device)
+            .await;
+        if res.is_err() {
+            if let Volume::New { .. } = volume {
+                // it would otherwise outlive the run, since nothing else knows about it.
+                let req = rusoto_ec2::DeleteVolumeRequest {
+                    volume_id: volume_id.clone(),
+                    ..Default::default()
+                };
+                if let Err(e) = client.delete_volume(req).await {
+                    tracing::warn!(volume = %volume_id, "failed to delete volume: {:?}", e);
+                }
+            }
+        }
+        res.map(|_| volume_id)
     }
 
-    #[instrument(level = "trace", skip(self))]
-    async fn make_ssh_key(mut self) -> Result<Self, Report> {
-        let ec2 = self.client.as_mut().expect("RegionLauncher unconnected");
-        let private_key_path = self
-            .private_key_path
-            .as_mut()
-            .expect("RegionLauncher unconnected");
+    // The part of `attach_volume` after `volume` exists as `volume_id`.
+    async fn attach_created_volume(
+        &self,
+        instance_id: &str,
+        volume: &Volume,
+        volume_id: &str,
+        device: &str,
+    ) -> Result<(), Report> {
+        let client = self.client.as_ref().unwrap();
+        if let Volume::New { .. } = volume {
+            self.wait_for_volume(volume_id, |v| v.state.as_deref() == Some("available"))
+                .await?;
+        }
 
-        // construct keypair for ssh access
-        tracing::debug!("creating keypair");
-        let key_name = super::rand_name("key");
-        let req = rusoto_ec2::CreateKeyPairRequest {
-            key_name: key_name.clone(),
+        let req = rusoto_ec2::AttachVolumeRequest {
+            device: device.to_string(),
+            instance_id: instance_id.to_string(),
+            volume_id: volume_id.to_string(),
             ..Default::default()
         };
-        let res = ec2
-            .create_key_pair(req)
-            .await
-            .context("failed to generate new key pair")?;
-        tracing::trace!(fingerprint = ?res.key_fingerprint, "created keypair");
-
-        // write keypair to disk
-        let private_key = res
-            .key_material
-            .expect("aws did not generate key material for new key");
-        private_key_path
-            .write_all(private_key.as_bytes())
-            .context("could not write private key to file")?;
-        tracing::debug!(
-            filename = %private_key_path.path().display(),
-            "wrote keypair to file"
-        );
+        client.attach_volume(req).await?;
+        self.wait_for_volume(volume_id, |v| {
+            v.attachments
+                .iter()
+                .flatten()
+                .any(|a| a.state.as_deref() == Some("attached"))
+        })
+        .await?;
+        tracing::debug!(volume = %volume_id, %device, "attached volume");
+
+        if let Volume::New { .. } = volume {
+            let req = rusoto_ec2::ModifyInstanceAttributeRequest {
+                instance_id: instance_id.to_string(),
+                block_device_mappings: Some(vec![
+                    rusoto_ec2::InstanceBlockDeviceMappingSpecification {
+                        device_name: Some(device.to_string()),
+                        ebs: Some(rusoto_ec2::EbsInstanceBlockDeviceSpecification {
+                            delete_on_termination: Some(true),
+                            volume_id: Some(volume_id.to_string()),
+                        }),
+                        ..Default::default()
+                    },
+                ]),
+                ..Default::default()
+            };
+            client
+                .modify_instance_attribute(req)
+                .await
+                .wrap_err("failed to mark new volume for deletion on termination")?;
+        }
 
-        self.ssh_key_name = key_name;
-        Ok(self)
+        Ok(())
     }
 
-    /// Make a new placement for a launch request.
-    ///
-    /// This method takes a "placement maker" (`mk`) to allow using this method for both
-    /// `SpotPlacement` and `Placement`. The `mk` function is passed a placement name and an
-    /// availability zone, and is expected to return an appropriate placement type.
-    #[instrument(level = "trace", skip(self, mk))]
-    async fn make_placement<R>(
-        &mut self,
-        mk: impl FnOnce(String, Option<String>) -> R,
-    ) -> Result<Option<R>, Report> {
-        if let AvailabilityZoneSpec::Any = self.availability_zone {
-            Ok(None)
-        } else {
-            let ec2 = self.client.as_mut().expect("RegionLauncher unconnected");
-            tracing::trace!("creating placement group");
-            let placement_name = super::rand_name("placement");
-            let req = rusoto_ec2::CreatePlacementGroupRequest {
-                group_name: Some(placement_name.clone()),
-                strategy: Some(String::from("cluster")),
+    // Poll volume `volume_id` until `done` holds, for up to five minutes.
+    async fn wait_for_volume(
+        &self,
+        volume_id: &str,
+        done: impl Fn(&rusoto_ec2::Volume) -> bool,
+    ) -> Result<(), Report> {
+        let client = self.client.as_ref().unwrap();
+        let start = time::Instant::now();
+        loop {
+            let req = rusoto_ec2::DescribeVolumesRequest {
+                volume_ids: Some(vec![volume_id.to_string()]),
                 ..Default::default()
             };
-            ec2.create_placement_group(req).await?;
-            tracing::trace!("created placement group");
-
-            Ok(Some(mk(
-                placement_name,
-                match self.availability_zone {
-                    AvailabilityZoneSpec::Cluster(_) => None,
-                    AvailabilityZoneSpec::Specify(ref av) => Some(av.clone()),
-                    _ => unreachable!(),
-                },
-            )))
+            let vols = client
+                .describe_volumes(req)
+                .await
+                .wrap_err("could not query AWS for volume state")?
+                .volumes
+                .unwrap_or_default();
+            if vols.iter().any(&done) {
+                return Ok(());
+            }
+            eyre::ensure!(
+                start.elapsed() < time::Duration::from_secs(5 * 60),
+                "volume {} did not become ready within 5 minutes",
+                volume_id
+            );
+            tokio::time::sleep(time::Duration::from_secs(2)).await;
         }
     }
 
-    fn for_each_machine_group<M>(
-        machines: M,
-    ) -> impl Iterator<Item = ((String, String), Vec<(String, Setup)>)> + Send
-    where
-        M: IntoIterator<Item = (String, Setup)>,
-        M: std::fmt::Debug,
-    {
-        // minimize the number of instance requests:
-        machines
-            .into_iter()
-            .map(|(name, m)| {
-                // attach labels (ami name, instance type):
-                // the only fields that vary between tsunami spot instance requests
-                ((m.ami.clone(), m.instance_type.clone()), (name, m))
+    // Spot requests cannot carry a credit specification, so set it once the instances exist.
+    #[instrument(level = "trace", skip(self))]
+    async fn set_spot_cpu_credits(&self) -> Result<(), Report> {
+        let specs: Vec<_> = self
+            .instances
+            .iter()
+            .filter_map(|(id, t)| {
+                Some(rusoto_ec2::InstanceCreditSpecificationRequest {
+                    instance_id: Some(id.clone()),
+                    cpu_credits: Some(t.setup.cpu_credits?.to_string()),
+                })
             })
-            .into_group_map()
-            .into_iter()
+            .collect();
+        if specs.is_empty() {
+            return Ok(());
+        }
+
+        let req = rusoto_ec2::ModifyInstanceCreditSpecificationRequest {
+            instance_credit_specifications: specs,
+            ..Default::default()
+        };
+        self.client
+            .as_ref()
+            .unwrap()
+            .modify_instance_credit_specification(req)
+            .await?;
+        Ok(())
     }
 
     #[instrument(level = "trace", skip(self))]
@@ -1004,38 +5817,66 @@ impl RegionLauncher {
         tracing::info!("launching on demand instances");
 
         // minimize the number of instance requests:
-        for ((ami, instance_type), reqs) in Self::for_each_machine_group(machines) {
-            let inst_span = tracing::debug_span!("run_instance", ?ami, ?instance_type);
+        for (group, reqs) in Self::for_each_machine_group(machines, self.request_size) {
+            let inst_span = tracing::debug_span!("run_instance", ami = ?group.ami, instance_type = ?group.instance_type);
             async {
                 // and issue one spot request per group
                 let placement = self
-                    .make_placement(|group_name, az| rusoto_ec2::Placement {
-                        group_name: Some(group_name),
-                        availability_zone: az,
-                        ..Default::default()
+                    .make_placement(group.placement_group.as_ref(), |group_name, az| {
+                        rusoto_ec2::Placement {
+                            group_name,
+                            availability_zone: az,
+                            ..Default::default()
+                        }
                     })
                     .await
                     .wrap_err("create new placement group")?;
+                let block_device_mappings = self
+                    .block_device_mappings(&group)
+                    .await
+                    .wrap_err("failed to lay out volumes")?;
+                // with a single instance, there is no choice of which one gets which nickname,
+                // so the instance can be tagged with its nickname right away.
+                let mut tag_specifications =
+                    [self.run_tags("instance"), self.run_tags("volume")].concat();
+                let name_tagged = reqs.len() == 1;
+                if name_tagged {
+                    for spec in &mut tag_specifications {
+                        if spec.resource_type.as_deref() == Some("instance") {
+                            let tags = spec.tags.get_or_insert_with(Vec::new);
+                            tags.extend(name_tags(&reqs[0].0));
+                        }
+                    }
+                }
                 let req = rusoto_ec2::RunInstancesRequest {
-                    image_id: Some(ami),
-                    instance_type: Some(instance_type),
+                    image_id: Some(group.ami),
+                    instance_type: Some(group.instance_type),
+                    monitoring: Some(rusoto_ec2::RunInstancesMonitoringEnabled {
+                        enabled: group.detailed_monitoring,
+                    }),
+                    credit_specification: group.cpu_credits.map(|c| {
+                        rusoto_ec2::CreditSpecificationRequest {
+                            cpu_credits: c.to_string(),
+                        }
+                    }),
                     placement,
-                    security_group_ids: Some(vec![self.security_group_id.clone()]),
+                    block_device_mappings,
+                    security_group_ids: self.security_group_ids(group.efa),
+                    network_interfaces: self.network_interfaces(group.efa),
+                    iam_instance_profile: group.instance_profile.as_deref().map(instance_profile),
                     key_name: Some(self.ssh_key_name.clone()),
                     min_count: reqs.len() as i64,
                     max_count: reqs.len() as i64,
-                    instance_initiated_shutdown_behavior: Some("terminate".to_string()),
+                    instance_initiated_shutdown_behavior: Some(group.shutdown_behavior.to_string()),
+                    user_data: self.user_data(),
+                    client_token: Some(self.client_token("run", &reqs)),
+                    tag_specifications: Some(tag_specifications),
                     ..Default::default()
                 };
 
-                // TODO: VPC
-
                 tracing::trace!("issuing request");
-                let res = self
-                    .client
-                    .as_mut()
-                    .unwrap()
-                    .run_instances(req)
+                let client = self.client.as_ref().unwrap();
+                let res = retry_dispatch(|| client.run_instances(req.clone()))
                     .await
                     .wrap_err("failed to request on demand instances")?;
 
@@ -1061,10 +5902,16 @@ impl RegionLauncher {
                 self.instances
                     .extend(instances.into_iter().zip_eq(reqs.into_iter()).map(
                         |(instance_id, (name, setup))| {
+                            super::report_progress(&name, MachineState::Booting);
                             let setup = TaggedSetup {
                                 name,
                                 setup,
                                 ip_info: None,
+                                setup_failed: false,
+                                via_ssm: false,
+                                lifecycle: Lifecycle::OnDemand,
+                                results_volumes: Vec::new(),
+                                name_tagged,
                             };
                             (instance_id, setup)
                         },
@@ -1102,45 +5949,73 @@ impl RegionLauncher {
         tracing::info!("launching spot requests");
 
         // minimize the number of spot requests:
-        for ((ami, instance_type), reqs) in Self::for_each_machine_group(machines) {
-            let spot_span = tracing::debug_span!("spot_request", ?ami, ?instance_type);
+        for (group, reqs) in Self::for_each_machine_group(machines, self.request_size) {
+            let spot_span = tracing::debug_span!("spot_request", ami = ?group.ami, instance_type = ?group.instance_type);
             async {
                 // and issue one spot request per group
                 let placement = self
-                    .make_placement(|group_name, az| rusoto_ec2::SpotPlacement {
-                        group_name: Some(group_name),
-                        availability_zone: az,
-                        ..Default::default()
+                    .make_placement(group.placement_group.as_ref(), |group_name, az| {
+                        rusoto_ec2::SpotPlacement {
+                            group_name,
+                            availability_zone: az,
+                            ..Default::default()
+                        }
                     })
                     .await
                     .wrap_err("create new placement group")?;
+                let block_device_mappings = self
+                    .block_device_mappings(&group)
+                    .await
+                    .wrap_err("failed to lay out volumes")?;
                 let launch = rusoto_ec2::RequestSpotLaunchSpecification {
-                    image_id: Some(ami),
-                    instance_type: Some(instance_type),
+                    image_id: Some(group.ami),
+                    instance_type: Some(group.instance_type),
+                    monitoring: Some(rusoto_ec2::RunInstancesMonitoringEnabled {
+                        enabled: group.detailed_monitoring,
+                    }),
                     placement,
-                    security_group_ids: Some(vec![self.security_group_id.clone()]),
+                    block_device_mappings,
+                    security_group_ids: self.security_group_ids(group.efa),
+                    network_interfaces: self.network_interfaces(group.efa),
+                    iam_instance_profile: group.instance_profile.as_deref().map(instance_profile),
                     key_name: Some(self.ssh_key_name.clone()),
+                    user_data: self.user_data(),
                     ..Default::default()
                 };
 
-                // TODO: VPC
-
-                let req = rusoto_ec2::RequestSpotInstancesRequest {
-                    instance_count: Some(reqs.len() as i64),
-                    block_duration_minutes: Some(max_duration as i64),
-                    launch_specification: Some(launch),
-                    // one-time spot instances are only fulfilled once and therefore do not need to be
-                    // cancelled.
-                    type_: Some("one-time".into()),
-                    ..Default::default()
+                let req = if let InterruptionBehavior::Terminate = group.interruption_behavior {
+                    rusoto_ec2::RequestSpotInstancesRequest {
+                        instance_count: Some(reqs.len() as i64),
+                        block_duration_minutes: Some(max_duration as i64),
+                        launch_specification: Some(launch),
+                        // one-time spot instances are only fulfilled once and therefore do not need to be
+                        // cancelled.
+                        type_: Some("one-time".into()),
+                        spot_price: group.spot_price,
+                        client_token: Some(self.client_token("spot", &reqs)),
+                        tag_specifications: Some(self.run_tags("spot-instances-request")),
+                        ..Default::default()
+                    }
+                } else {
+                    // stopping and hibernating need a persistent request, which cannot have a
+                    // defined duration. terminate_all cancels it.
+                    rusoto_ec2::RequestSpotInstancesRequest {
+                        instance_count: Some(reqs.len() as i64),
+                        instance_interruption_behavior: Some(
+                            group.interruption_behavior.to_string(),
+                        ),
+                        launch_specification: Some(launch),
+                        type_: Some("persistent".into()),
+                        spot_price: group.spot_price,
+                        client_token: Some(self.client_token("spot", &reqs)),
+                        tag_specifications: Some(self.run_tags("spot-instances-request")),
+                        ..Default::default()
+                    }
                 };
 
                 tracing::trace!("issuing spot request");
-                let res = self
-                    .client
-                    .as_mut()
-                    .unwrap()
-                    .request_spot_instances(req)
+                let client = self.client.as_ref().unwrap();
+                let res = retry_dispatch(|| client.request_spot_instances(req.clone()))
                     .await
                     .wrap_err("failed to request spot instance")?;
 
@@ -1149,42 +6024,333 @@ impl RegionLauncher {
                     .spot_instance_requests
                     .expect("request_spot_instances should always return spot instance requests")
                     .into_iter()
-                    .filter_map(|sir| sir.spot_instance_request_id)
-                    .inspect(|request_id| {
-                        tracing::trace!(id = %request_id, "activated spot request");
+                    .filter_map(|sir| sir.spot_instance_request_id)
+                    .inspect(|request_id| {
+                        tracing::trace!(id = %request_id, "activated spot request");
+                    })
+                    .collect();
+
+                // zip_eq will panic if lengths not equal, so check beforehand
+                eyre::ensure!(
+                    spot_instance_requests.len() == reqs.len(),
+                    "Got {} spot instance requests but expected {}",
+                    spot_instance_requests.len(),
+                    reqs.len(),
+                );
+
+                for (request_id, (name, setup)) in
+                    spot_instance_requests.into_iter().zip_eq(reqs.into_iter())
+                {
+                    super::report_progress(&name, MachineState::Pending);
+                    self.spot_requests.insert(
+                        request_id,
+                        TaggedSetup {
+                            name,
+                            setup,
+                            ip_info: None,
+                            setup_failed: false,
+                            via_ssm: false,
+                            lifecycle: Lifecycle::Spot,
+                            results_volumes: Vec::new(),
+                            name_tagged: false,
+                        },
+                    );
+                }
+
+                Ok(())
+            }
+            .instrument(spot_span)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    // Launches `machines` as spot instances through EC2 Fleet, with one instant fleet per request
+    // group. Unlike spot requests, an instant fleet returns its instances (or its errors) right
+    // away, so there is nothing to poll.
+    #[instrument(level = "trace", skip(self))]
+    async fn make_fleet_requests<M>(&mut self, machines: M) -> Result<(), Report>
+    where
+        M: IntoIterator<Item = (String, Setup)>,
+        M: std::fmt::Debug,
+    {
+        tracing::info!("launching fleets");
+
+        for (group, reqs) in Self::for_each_machine_group(machines, self.request_size) {
+            let fleet_span = tracing::debug_span!("fleet", ami = ?group.ami, instance_type = ?group.instance_type);
+            async {
+                if group.efa {
+                    return Err(eyre!("machines with an EFA cannot be launched as a fleet"))
+                        .suggestion("Launch them with a different LaunchMode");
+                }
+                let overrides = self
+                    .fleet_overrides(&group)
+                    .await
+                    .wrap_err("failed to pick instance types and zones")?;
+                let template = self
+                    .make_launch_template(&group)
+                    .await
+                    .wrap_err("failed to create launch template")?;
+                let req = rusoto_ec2::CreateFleetRequest {
+                    type_: Some(String::from("instant")),
+                    launch_template_configs: vec![rusoto_ec2::FleetLaunchTemplateConfigRequest {
+                        launch_template_specification: Some(
+                            rusoto_ec2::FleetLaunchTemplateSpecificationRequest {
+                                launch_template_id: Some(template.clone()),
+                                version: Some(String::from("$Latest")),
+                                ..Default::default()
+                            },
+                        ),
+                        overrides: Some(overrides),
+                    }],
+                    target_capacity_specification: rusoto_ec2::TargetCapacitySpecificationRequest {
+                        total_target_capacity: reqs.len() as i64,
+                        default_target_capacity_type: Some(String::from("spot")),
+                        ..Default::default()
+                    },
+                    spot_options: Some(rusoto_ec2::SpotOptionsRequest {
+                        allocation_strategy: Some(String::from("capacity-optimized")),
+                        instance_interruption_behavior: Some(
+                            group.interruption_behavior.to_string(),
+                        ),
+                        // a cluster placement group cannot span zones
+                        single_availability_zone: (group.placement_group.is_some()
+                            || self.availability_zone != AvailabilityZoneSpec::Any)
+                            .then(|| true),
+                        ..Default::default()
+                    }),
+                    client_token: Some(self.client_token("fleet", &reqs)),
+                    tag_specifications: Some(self.run_tags("fleet")),
+                    ..Default::default()
+                };
+
+                tracing::trace!("creating fleet");
+                let client = self.client.as_ref().unwrap();
+                let res = retry_dispatch(|| client.create_fleet(req.clone())).await;
+                // an instant fleet has no more use for its launch template once it returns
+                let delete = rusoto_ec2::DeleteLaunchTemplateRequest {
+                    launch_template_id: Some(template.clone()),
+                    ..Default::default()
+                };
+                if let Err(e) = client.delete_launch_template(delete).await {
+                    tracing::warn!(%template, err = %e, "failed to delete launch template");
+                }
+                let res = res.wrap_err("failed to create fleet")?;
+
+                let launched: Vec<_> = res
+                    .instances
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flat_map(|i| {
+                        let instance_type = i.instance_type;
+                        let lifecycle = match i.lifecycle.as_deref() {
+                            Some("on-demand") => Lifecycle::OnDemand,
+                            _ => Lifecycle::Spot,
+                        };
+                        i.instance_ids
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(move |id| (id, instance_type.clone(), lifecycle))
                     })
                     .collect();
-
-                // zip_eq will panic if lengths not equal, so check beforehand
-                eyre::ensure!(
-                    spot_instance_requests.len() == reqs.len(),
-                    "Got {} spot instance requests but expected {}",
-                    spot_instance_requests.len(),
-                    reqs.len(),
-                );
-
-                for (request_id, (name, setup)) in
-                    spot_instance_requests.into_iter().zip_eq(reqs.into_iter())
+                let mut reqs = reqs.into_iter();
+                for ((instance_id, instance_type, lifecycle), (name, mut setup)) in
+                    launched.into_iter().zip(reqs.by_ref())
                 {
-                    self.spot_requests.insert(
-                        request_id,
-                        TaggedSetup {
-                            name,
-                            setup,
-                            ip_info: None,
-                        },
-                    );
+                    tracing::trace!(id = %instance_id, instance_type = ?instance_type, "launched fleet instance");
+                    super::report_progress(&name, MachineState::Booting);
+                    if let Some(t) = instance_type {
+                        setup.instance_type = t;
+                    }
+                    let setup = TaggedSetup {
+                        name,
+                        setup,
+                        ip_info: None,
+                        setup_failed: false,
+                        via_ssm: false,
+                        lifecycle,
+                        results_volumes: Vec::new(),
+                        name_tagged: false,
+                    };
+                    self.instances.insert(instance_id, setup);
                 }
 
-                Ok(())
+                // the instances that did launch are kept, so that they are cleaned up.
+                let missing: Vec<_> = reqs.collect();
+                if missing.is_empty() {
+                    return Ok(());
+                }
+                let errors = res.errors.unwrap_or_default();
+                for e in &errors {
+                    tracing::warn!(code = ?e.error_code, message = ?e.error_message, "fleet error");
+                }
+                self.strike_zones(
+                    errors
+                        .iter()
+                        .filter(|e| e.error_code.as_deref().map_or(false, is_capacity_code))
+                        .filter_map(|e| {
+                            e.launch_template_and_overrides
+                                .as_ref()?
+                                .overrides
+                                .as_ref()?
+                                .availability_zone
+                                .clone()
+                        }),
+                );
+                let error = errors.into_iter().next();
+                let fleet_id = res.fleet_id.unwrap_or_default();
+                Err(Report::new(SpotRequestError {
+                    failures: missing
+                        .into_iter()
+                        .map(|(nickname, _)| SpotRequestFailure {
+                            nickname,
+                            request_id: fleet_id.clone(),
+                            state: String::from("failed"),
+                            code: error
+                                .as_ref()
+                                .and_then(|e| e.error_code.clone())
+                                .unwrap_or_else(|| String::from("UnfulfillableCapacity")),
+                            message: error.as_ref().and_then(|e| e.error_message.clone()),
+                        })
+                        .collect(),
+                }))
             }
-            .instrument(spot_span)
+            .instrument(fleet_span)
             .await?;
         }
 
         Ok(())
     }
 
+    // The instance types and availability zones, or subnet, that a fleet for `group` may pick
+    // from.
+    async fn fleet_overrides(
+        &self,
+        group: &RequestGroup,
+    ) -> Result<Vec<rusoto_ec2::FleetLaunchTemplateOverridesRequest>, Report> {
+        let types: Vec<_> = std::iter::once(&group.instance_type)
+            .chain(&group.alternative_instance_types)
+            .unique()
+            .cloned()
+            .collect();
+        let over = |instance_type: String, availability_zone: Option<String>| {
+            rusoto_ec2::FleetLaunchTemplateOverridesRequest {
+                instance_type: Some(instance_type),
+                availability_zone,
+                // the subnet decides the zone
+                subnet_id: self.network.as_ref().map(|n| n.subnet_id.clone()),
+                max_price: group.spot_price.clone(),
+                ..Default::default()
+            }
+        };
+        if self.network.is_some() {
+            return Ok(types.into_iter().map(|t| over(t, None)).collect());
+        }
+        if let Some(ref az) = self.zone {
+            return Ok(types
+                .into_iter()
+                .map(|t| over(t, Some(az.clone())))
+                .collect());
+        }
+
+        let mut offerings = self.instance_type_offerings(&types).await?;
+        eyre::ensure!(
+            !offerings.is_empty(),
+            "no availability zone offers any of {}",
+            types.join(", ")
+        );
+        offerings.sort();
+        Ok(offerings
+            .into_iter()
+            .map(|(t, az)| over(t, Some(az)))
+            .collect())
+    }
+
+    // Creates a launch template for the instances of `group`, and returns its id. The fleet
+    // overrides the instance type and zone.
+    async fn make_launch_template(&mut self, group: &RequestGroup) -> Result<String, Report> {
+        let placement = self
+            .make_placement(group.placement_group.as_ref(), |group_name, az| {
+                rusoto_ec2::LaunchTemplatePlacementRequest {
+                    group_name,
+                    availability_zone: az,
+                    ..Default::default()
+                }
+            })
+            .await
+            .wrap_err("create new placement group")?;
+        let block_device_mappings = self
+            .block_device_mappings(group)
+            .await
+            .wrap_err("failed to lay out volumes")?
+            .map(|ms| ms.into_iter().map(template_block_device).collect());
+        let network_interfaces = self.network_interfaces(false).map(|nis| {
+            nis.into_iter()
+                .map(
+                    |ni| rusoto_ec2::LaunchTemplateInstanceNetworkInterfaceSpecificationRequest {
+                        device_index: ni.device_index,
+                        groups: ni.groups,
+                        associate_public_ip_address: ni.associate_public_ip_address,
+                        delete_on_termination: ni.delete_on_termination,
+                        ..Default::default()
+                    },
+                )
+                .collect()
+        });
+        let tag_specifications = ["instance", "volume"]
+            .iter()
+            .flat_map(|r| self.run_tags(r))
+            .map(|t| rusoto_ec2::LaunchTemplateTagSpecificationRequest {
+                resource_type: t.resource_type,
+                tags: t.tags,
+            })
+            .collect();
+        let data = rusoto_ec2::RequestLaunchTemplateData {
+            image_id: Some(group.ami.clone()),
+            instance_type: Some(group.instance_type.clone()),
+            monitoring: Some(rusoto_ec2::LaunchTemplatesMonitoringRequest {
+                enabled: Some(group.detailed_monitoring),
+            }),
+            credit_specification: group.cpu_credits.map(|c| {
+                rusoto_ec2::CreditSpecificationRequest {
+                    cpu_credits: c.to_string(),
+                }
+            }),
+            placement,
+            block_device_mappings,
+            security_group_ids: self.security_group_ids(false),
+            network_interfaces,
+            iam_instance_profile: group.instance_profile.as_deref().map(|p| {
+                let p = instance_profile(p);
+                rusoto_ec2::LaunchTemplateIamInstanceProfileSpecificationRequest {
+                    arn: p.arn,
+                    name: p.name,
+                }
+            }),
+            key_name: Some(self.ssh_key_name.clone()),
+            tag_specifications: Some(tag_specifications),
+            user_data: self.user_data(),
+            ..Default::default()
+        };
+
+        let name = super::rand_name("fleet");
+        let req = rusoto_ec2::CreateLaunchTemplateRequest {
+            launch_template_name: name.clone(),
+            launch_template_data: data,
+            tag_specifications: Some(self.run_tags("launch-template")),
+            ..Default::default()
+        };
+        let client = self.client.as_ref().unwrap();
+        client
+            .create_launch_template(req)
+            .await
+            .wrap_err_with(|| format!("failed to create launch template {}", name))?
+            .launch_template
+            .and_then(|t| t.launch_template_id)
+            .ok_or_else(|| eyre!("launch template {} has no id", name))
+    }
+
     /// Poll AWS once a second until either `max_wait` (if not `None`) elapses, or
     /// the spot requests are fulfilled.
     ///
@@ -1207,31 +6373,46 @@ impl RegionLauncher {
             let instances = self.describe_spot_instance_requests().await?;
 
             let mut any_pending = false;
-            for (request_id, state, status, instance_id) in &instances {
-                match &**state {
-                    "active" if instance_id.is_some() => {
-                        tracing::trace!(%request_id, %state, ?instance_id, "spot instance request ready");
+            let mut failures = Vec::new();
+            for sir in &instances {
+                match &*sir.state {
+                    "active" if sir.instance_id.is_some() => {
+                        tracing::trace!(request_id = %sir.request_id, instance_id = ?sir.instance_id, "spot instance request ready");
                     }
                     "active" | "open" => {
                         any_pending = true;
                     }
-                    s => {
+                    _ => {
                         // closed | failed | cancelled
-                        let _ = self.cancel_spot_instance_requests().await;
-                        eyre::bail!("spot request unexpectedly {}: {}", s, status);
+                        let failure = SpotRequestFailure {
+                            nickname: self.spot_requests[&sir.request_id].name.clone(),
+                            request_id: sir.request_id.clone(),
+                            state: sir.state.clone(),
+                            code: sir.code.clone(),
+                            message: sir.message.clone(),
+                        };
+                        tracing::warn!(%failure, "spot request failed");
+                        failures.push(failure);
                     }
                 }
             }
-            let all_active = !any_pending;
 
-            if all_active {
+            if !failures.is_empty() {
+                let short = short_zones(&instances, false, self.zone.as_ref());
+                self.strike_zones(short);
+                let _ = self.cancel_spot_instance_requests().await;
+                return Err(Report::new(SpotRequestError { failures }));
+            }
+
+            if !any_pending {
                 // unwraps okay because they are the same as expects above
                 self.instances = instances
                     .into_iter()
-                    .map(|(request_id, state, _, instance_id)| {
-                        assert_eq!(state, "active");
-                        let instance_id = instance_id.unwrap();
-                        let setup = self.spot_requests[&request_id].clone();
+                    .map(|sir| {
+                        assert_eq!(sir.state, "active");
+                        let instance_id = sir.instance_id.unwrap();
+                        let setup = self.spot_requests[&sir.request_id].clone();
+                        super::report_progress(&setup.name, MachineState::Booting);
                         (instance_id, setup)
                     })
                     .collect();
@@ -1245,8 +6426,13 @@ impl RegionLauncher {
                 if start.elapsed() <= wait_limit {
                     continue;
                 }
+                // EC2 keeps requests it has no capacity for open rather than failing them.
+                let stuck = short_zones(&instances, true, self.zone.as_ref());
+                self.strike_zones(stuck);
                 self.cancel_spot_instance_requests().await?;
-                eyre::bail!("wait limit reached");
+                return Err(Report::new(WaitLimitError {
+                    waited: start.elapsed(),
+                }));
             }
         }
 
@@ -1254,114 +6440,37 @@ impl RegionLauncher {
     }
 
     /// Poll AWS until `max_wait` (if not `None`) or the instances are ready to SSH to.
+    ///
+    /// Waiting for EC2 to report the instances [ready](Readiness) and connecting to them are
+    /// separate steps, and only the latter is retried under [`SshRetry`].
     #[instrument(level = "trace", skip(self, max_wait))]
     async fn wait_for_instances(&mut self, max_wait: Option<time::Duration>) -> Result<(), Report> {
         let start = time::Instant::now();
-        let desc_req = rusoto_ec2::DescribeInstancesRequest {
-            instance_ids: Some(self.instances.keys().cloned().collect()),
-            ..Default::default()
-        };
-        let client = self.client.as_ref().unwrap();
+        let running = self.wait_for_running(max_wait).await?;
+        let running = self
+            .assign_elastic_ips(running)
+            .await
+            .wrap_err("failed to assign elastic ip addresses")?;
+        let remaining = max_wait.map(|w| w.saturating_sub(start.elapsed()));
+        self.wait_for_ssh(running, remaining).await?;
         let private_key_path = self.private_key_path.as_ref().unwrap();
-        let mut all_ready = self.instances.is_empty();
-        while !all_ready {
-            all_ready = true;
-
-            for reservation in client
-                .describe_instances(desc_req.clone())
-                .await
-                .wrap_err("could not query AWS for instance state")?
-                .reservations
-                .unwrap_or_else(Vec::new)
-            {
-                for instance in reservation.instances.unwrap_or_else(Vec::new) {
-                    match instance {
-                        // https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_InstanceState.html
-                        // code 16 means "Running"
-                        rusoto_ec2::Instance {
-                            state: Some(rusoto_ec2::InstanceState { code: Some(16), .. }),
-                            instance_id: Some(instance_id),
-                            public_dns_name: Some(public_dns),
-                            public_ip_address: Some(public_ip),
-                            private_ip_address: Some(private_ip),
-                            ..
-                        } => {
-                            let instance_span =
-                                tracing::debug_span!("instance", %instance_id, ip = %public_ip);
-                            let instances = &mut self.instances;
-                            async {
-                                tracing::trace!("instance running");
-
-                                // try connecting. If can't, not ready.
-                                let tag_setup = instances.get_mut(&instance_id).unwrap();
-
-                                // no need to set public dns nor private ip since `connect_ssh` only uses the public ip
-                                let m = crate::MachineDescriptor {
-                                    nickname: Default::default(),
-                                    public_dns: Default::default(),
-                                    public_ip: public_ip.to_string(),
-                                    private_ip: Default::default(),
-                                    _tsunami: Default::default(),
-                                };
-
-                                if let Err(e) = m
-                                    .connect_ssh(
-                                        &tag_setup.setup.username,
-                                        Some(private_key_path.path()),
-                                        max_wait,
-                                        22,
-                                    )
-                                    .await
-                                {
-                                    tracing::trace!("ssh failed: {}", e);
-                                    all_ready = false;
-                                } else {
-                                    tracing::debug!("instance ready");
-
-                                    tag_setup.ip_info = Some(IpInfo {
-                                        public_dns: public_dns.clone(),
-                                        public_ip: public_ip.clone(),
-                                        private_ip: private_ip.clone(),
-                                    });
-                                }
-                            }
-                            .instrument(instance_span)
-                            .await
-                        }
-                        _ => {
-                            all_ready = false;
-                        }
-                    }
-                }
-            }
-
-            // let's not hammer the API
-            tokio::time::sleep(time::Duration::from_secs(1)).await;
 
-            if let Some(wait_limit) = max_wait {
-                if start.elapsed() <= wait_limit {
-                    continue;
-                }
-                self.cancel_spot_instance_requests().await?;
-                eyre::bail!("wait limit reached");
+        let results_volumes = self
+            .attach_volumes(max_wait)
+            .await
+            .wrap_err("failed to attach volumes")?;
+        for (instance_id, volumes) in results_volumes {
+            if let Some(t) = self.instances.get_mut(&instance_id) {
+                t.results_volumes = volumes;
             }
         }
 
-        futures_util::future::join_all(self.instances.iter().map(
-            |(
-                instance_id,
-                TaggedSetup {
-                    ip_info,
-                    name,
-                    setup,
-                },
-            )| {
-                let IpInfo {
-                    public_dns,
-                    public_ip,
-                    private_ip,
-                } = ip_info.as_ref().unwrap();
-                let instance_span = tracing::debug_span!("instance", %instance_id, ip = %public_ip);
+        let results =
+            futures_util::future::join_all(self.instances.iter().map(|(instance_id, t)| {
+                let m = self.descriptor(instance_id, t).unwrap();
+                let TaggedSetup { name, setup, .. } = t;
+                let instance_span =
+                    tracing::debug_span!("instance", %instance_id, nickname = %name, ip = %m.public_ip);
                 async move {
                     if let Setup {
                         username,
@@ -1370,84 +6479,364 @@ impl RegionLauncher {
                     } = setup
                     {
                         super::setup_machine(
-                            &name,
-                            Some(&public_dns),
-                            &public_ip,
-                            Some(&private_ip),
-                            &username,
+                            name,
+                            m,
+                            username,
                             max_wait,
                             Some(private_key_path.path()),
                             f.as_ref(),
                         )
-                        .await?;
+                        .await
+                        .map_err(|e| (instance_id.clone(), e))?;
+                    } else {
+                        super::report_progress(name, MachineState::Ready);
                     }
 
                     Ok(())
                 }
                 .instrument(instance_span)
+            }))
+            .await;
+
+        // remember which machines failed so that `status` can report them.
+        let mut res = Ok(());
+        for (instance_id, e) in results.into_iter().filter_map(Result::err) {
+            if let Some(tag_setup) = self.instances.get_mut(&instance_id) {
+                tag_setup.setup_failed = true;
+            }
+            if res.is_ok() {
+                res = Err(e);
+            }
+        }
+        res
+    }
+
+    // The `ProxyCommand` that reaches `instance_id` over SSH through Session Manager.
+    fn ssm_proxy(&self, instance_id: &str) -> String {
+        format!(
+            "aws ssm start-session --target {} --document-name AWS-StartSSHSession --parameters portNumber=%p --region {}",
+            instance_id,
+            self.region.name()
+        )
+    }
+
+    // How to reach `instance_id` once it is up.
+    fn descriptor(
+        &self,
+        instance_id: &str,
+        t: &TaggedSetup,
+    ) -> Option<crate::MachineDescriptor<'static>> {
+        let ip = t.ip_info.as_ref()?;
+        Some(crate::MachineDescriptor {
+            nickname: t.name.clone(),
+            public_dns: Some(ip.public_dns.clone()).filter(|d| !d.is_empty()),
+            public_ip: ip.public_ip.clone(),
+            private_ip: Some(ip.private_ip.clone()),
+            os: t.setup.os,
+            proxy_command: if t.via_ssm {
+                Some(self.ssm_proxy(instance_id))
+            } else {
+                None
             },
-        ))
-        .await
-        .into_iter()
-        .collect()
+            check_host_key: true,
+            _tsunami: Default::default(),
+        })
     }
 
     /// Establish SSH connections to the machines. The `Ok` value is a `HashMap` associating the
     /// friendly name for each `Setup` with the corresponding SSH connection.
+    ///
+    /// At most [`RegionLauncher::connect_concurrency`] connections are set up at a time.
     #[instrument(level = "debug")]
     pub async fn connect_all<'l>(&'l self) -> Result<HashMap<String, crate::Machine<'l>>, Report> {
+        self.connect_stream().try_collect().await
+    }
+
+    /// Establish SSH connections to the machines, and yield each one as soon as it is up.
+    ///
+    /// At most [`RegionLauncher::connect_concurrency`] connections are set up at a time.
+    pub fn connect_stream<'l>(
+        &'l self,
+    ) -> impl Stream<Item = Result<(String, crate::Machine<'l>), Report>> + Send + 'l {
+        futures_util::stream::iter(self.instances.iter())
+            .map(move |(instance_id, info)| self.connect_instance(instance_id, info))
+            .buffer_unordered(self.connect_concurrency.unwrap_or(CONNECT_CONCURRENCY))
+    }
+
+    fn connect_instance<'l>(
+        &'l self,
+        instance_id: &'l str,
+        info: &'l TaggedSetup,
+    ) -> impl Future<Output = Result<(String, crate::Machine<'l>), Report>> + Send + 'l {
         let private_key_path = self.private_key_path.as_ref().unwrap();
-        futures_util::future::join_all(self.instances.values().map(|info| {
-            let instance_span = tracing::trace_span!("instance", name = %info.name);
-            async move {
-                match info {
-                    TaggedSetup {
-                        name,
-                        setup: Setup { username, .. },
-                        ip_info:
-                            Some(IpInfo {
-                                public_dns,
-                                public_ip,
-                                private_ip,
-                            }),
-                    } => {
-                        let m = crate::MachineDescriptor {
-                            public_dns: Some(public_dns.clone()),
-                            public_ip: public_ip.clone(),
-                            private_ip: Some(private_ip.clone()),
-                            nickname: name.clone(),
-                            _tsunami: Default::default(),
-                        };
+        let instance_span = tracing::trace_span!("instance", name = %info.name);
+        let m = self.descriptor(instance_id, info);
+        async move {
+            match m {
+                Some(m) => {
+                    let m = m
+                        .connect_ssh(
+                            &info.setup.username,
+                            Some(private_key_path.path()),
+                            None,
+                            22,
+                        )
+                        .await?;
+                    Ok((info.name.clone(), m))
+                }
+                None => eyre::bail!("machine has no ip information"),
+            }
+        }
+        .instrument(instance_span)
+    }
 
-                        let m = m
-                            .connect_ssh(&username, Some(private_key_path.path()), None, 22)
-                            .await?;
-                        Ok((name.clone(), m))
-                    }
-                    _ => eyre::bail!("machine has no ip information"),
+    /// Describe how to reach the machines, without connecting to them yet. The `Ok` value is a
+    /// `HashMap` associating the friendly name for each `Setup` with the corresponding machine.
+    ///
+    /// Each machine opens its SSH session the first time it is used. See [`crate::LazyMachine`].
+    pub fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        let private_key_path = self.private_key_path.as_ref().unwrap();
+        self.instances
+            .iter()
+            .map(|(instance_id, info)| {
+                let m: crate::MachineDescriptor<'l> = self
+                    .descriptor(instance_id, info)
+                    .ok_or_else(|| eyre!("machine {} has no ip information", info.name))?;
+                let m = m.lazy(&info.setup.username, Some(private_key_path.path()), 22);
+                Ok((info.name.clone(), m))
+            })
+            .collect()
+    }
+
+    /// Query EC2 for the state of every machine this `RegionLauncher` has launched.
+    ///
+    /// Machines whose spot request has not yet been fulfilled are [`MachineState::Pending`].
+    /// Running machines are probed over SSH to distinguish [`MachineState::Ready`] from
+    /// [`MachineState::Unreachable`].
+    #[instrument(level = "debug")]
+    pub async fn status(&self) -> Result<HashMap<String, MachineState>, Report> {
+        let mut states: HashMap<String, MachineState> = self
+            .spot_requests
+            .values()
+            .map(|s| (s.name.clone(), MachineState::Pending))
+            .collect();
+        if self.instances.is_empty() {
+            return Ok(states);
+        }
+
+        // a filter rather than a list of ids, so that an instance EC2 has forgotten about shows
+        // up as terminated instead of failing the request with `InvalidInstanceID.NotFound`.
+        let desc_req = rusoto_ec2::DescribeInstancesRequest {
+            filters: Some(vec![rusoto_ec2::Filter {
+                name: Some("instance-id".to_string()),
+                values: Some(self.instances.keys().cloned().collect()),
+            }]),
+            ..Default::default()
+        };
+        let mut codes: HashMap<String, i64> = HashMap::new();
+        for reservation in self
+            .client
+            .as_ref()
+            .unwrap()
+            .describe_instances(desc_req)
+            .await
+            .wrap_err("could not query AWS for instance state")?
+            .reservations
+            .unwrap_or_default()
+        {
+            for instance in reservation.instances.unwrap_or_default() {
+                if let rusoto_ec2::Instance {
+                    instance_id: Some(id),
+                    state:
+                        Some(rusoto_ec2::InstanceState {
+                            code: Some(code), ..
+                        }),
+                    ..
+                } = instance
+                {
+                    codes.insert(id, code);
                 }
             }
-            .instrument(instance_span)
+        }
+
+        let private_key_path = self.private_key_path.as_ref().map(|k| k.path());
+        let running = futures_util::future::join_all(self.instances.iter().map(|(id, info)| {
+            // https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_InstanceState.html
+            // the low byte is the state; the high byte is internal to AWS.
+            let code = codes.get(id).map(|c| c & 0xff);
+            let m = self.descriptor(id, info);
+            async move {
+                let state = match (code, info) {
+                    (Some(0), _) => MachineState::Booting,
+                    (Some(16), TaggedSetup { ip_info: None, .. }) => MachineState::Booting,
+                    (
+                        Some(16),
+                        TaggedSetup {
+                            setup_failed: true, ..
+                        },
+                    ) => MachineState::SetupFailed,
+                    (Some(16), TaggedSetup { setup, .. }) => match m {
+                        Some(m) => {
+                            super::machine_state(m, &setup.username, private_key_path, 22).await
+                        }
+                        None => MachineState::Booting,
+                    },
+                    // shutting-down, terminated, stopping, stopped, or gone entirely
+                    _ => MachineState::Terminated,
+                };
+                (info.name.clone(), state)
+            }
         }))
-        .await
-        .into_iter()
-        .collect()
+        .await;
+        states.extend(running);
+        Ok(states)
+    }
+
+    /// Whether this region launched the machine `nickname`.
+    pub fn has_machine(&self, nickname: &str) -> bool {
+        self.instances.values().any(|t| t.name == nickname)
+    }
+
+    /// Wind down the machine `nickname` and terminate it, and leave all other machines running.
+    ///
+    /// The machine's [teardown function](Setup::teardown) runs first, and then its file systems
+    /// are synced, so that background processes get to write out their results. Both together
+    /// get `grace`; if they fail or take longer, the machine is terminated anyway. Unlike
+    /// [`terminate_all`](super::Launcher::terminate_all), this does not snapshot the machine's
+    /// [results volumes](Setup::results_volume_at), so call
+    /// [`snapshot_results`](RegionLauncher::snapshot_results) first if it has any.
+    #[instrument(level = "debug", skip(self), fields(region = %self.region.name()))]
+    pub async fn terminate_gracefully(
+        &mut self,
+        nickname: &str,
+        grace: time::Duration,
+    ) -> Result<(), Report> {
+        let instance_id = self
+            .instances
+            .iter()
+            .find(|(_, t)| t.name == nickname)
+            .map(|(id, _)| id.clone())
+            .ok_or_else(|| eyre!("no machine named {}", nickname))?;
+
+        let info = &self.instances[&instance_id];
+        let drain = async {
+            let (_, m) = self.connect_instance(&instance_id, info).await?;
+            if let Some(ref f) = info.setup.teardown_fn {
+                f(&m).await.wrap_err("teardown procedure failed")?;
+            }
+            let status = m
+                .ssh
+                .command("sync")
+                .status()
+                .await
+                .wrap_err("failed to run sync")?;
+            eyre::ensure!(status.success(), "failed to sync file systems");
+            Ok::<_, Report>(())
+        };
+        match tokio::time::timeout(grace, drain).await {
+            Ok(Ok(())) => tracing::debug!("machine wound down"),
+            Ok(Err(e)) => tracing::warn!("terminating machine that did not wind down: {:?}", e),
+            Err(_) => tracing::warn!(?grace, "terminating machine that is still winding down"),
+        }
+
+        // a persistent spot request would replace the instance
+        let persistent: Vec<_> = self
+            .spot_requests
+            .iter()
+            .filter(|(_, t)| t.name == nickname)
+            .filter(|(_, t)| t.setup.interruption_behavior != InterruptionBehavior::Terminate)
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !persistent.is_empty() {
+            let req = rusoto_ec2::CancelSpotInstanceRequestsRequest {
+                spot_instance_request_ids: persistent.clone(),
+                ..Default::default()
+            };
+            self.client
+                .as_ref()
+                .unwrap()
+                .cancel_spot_instance_requests(req)
+                .await
+                .wrap_err("failed to cancel persistent spot request")?;
+            for id in persistent {
+                self.spot_requests.remove(&id);
+            }
+        }
+
+        self.terminate_instances(vec![instance_id.clone()])
+            .await
+            .wrap_err_with(|| format!("failed to terminate {}", nickname))?;
+        self.instances.remove(&instance_id);
+        self.wait_for_termination(vec![instance_id.clone()], time::Duration::from_secs(5 * 60))
+            .await?;
+
+        if let Some(allocation_id) = self.addresses.remove(&instance_id) {
+            let req = rusoto_ec2::ReleaseAddressRequest {
+                allocation_id: Some(allocation_id.clone()),
+                ..Default::default()
+            };
+            if let Err(e) = self.client.as_ref().unwrap().release_address(req).await {
+                tracing::warn!(%allocation_id, "failed to release elastic ip: {}", e);
+            }
+        }
+        tracing::info!("machine terminated");
+        Ok(())
     }
 
     /// Terminate all running instances.
     ///
     /// Additionally deletes ephemeral keys and security groups. Sometimes, this deletion can fail
     /// for various reasons. This method deletes things in this order:
-    /// 1. Try to delete the key pair, but emit a log message and continue if it fails.
-    /// 2. Try to terminate the instances, and short-circuits to return the error if it fails.
-    /// 3. Try to delete the security group. This can fail as the security groups are still
-    ///    "attached" to the instances we just terminated in step 2. So, we retry for 2 minutes
+    /// 1. [Snapshot](RegionLauncher::snapshot_results) any results volumes, but emit a log message
+    ///    and continue if that fails, so that the instances do not keep running. The error is
+    ///    returned once everything else has been cleaned up.
+    /// 2. Record and log the [final state](RegionLauncher::instance_reports) of every instance,
+    ///    but emit a log message and continue if it fails.
+    /// 3. Try to delete the key pair, but emit a log message and continue if it fails.
+    /// 4. Try to terminate the instances, and short-circuits to return the error if it fails.
+    /// 5. Wait for EC2 to report the instances as terminated. If some have not terminated after 5
+    ///    minutes, return an error that lists their ids so they can be followed up on.
+    /// 6. Try to delete the placement groups tsunami created, and to release the [Elastic IP
+    ///    addresses](Launcher::elastic_ips) it allocated, but emit a log message and continue if
+    ///    that fails.
+    /// 7. Try to delete the security group. This can fail as the security groups are still
+    ///    "attached" to the instances we just terminated in step 4. So, we retry for 2 minutes
     ///    before giving up and returning an error.
     #[instrument(level = "debug")]
     pub async fn terminate_all(&mut self) -> Result<(), Report> {
+        // new volumes are deleted along with their instance, so this has to happen first.
+        let snapshotted = self
+            .snapshot_results()
+            .await
+            .map(drop)
+            .wrap_err("failed to snapshot results volumes");
+        if let Err(ref e) = snapshotted {
+            tracing::warn!(
+                "terminating instances whose results were not saved: {:?}",
+                e
+            );
+        }
+
+        match self.instance_reports().await {
+            Ok(reports) => {
+                for (name, r) in reports {
+                    tracing::info!(
+                        nickname = %name,
+                        instance_id = %r.instance_id,
+                        state = %r.state,
+                        reason = ?r.state_reason,
+                        uptime = ?r.uptime,
+                        interrupted = r.interrupted,
+                        "final instance state"
+                    );
+                }
+            }
+            Err(e) => tracing::warn!("failed to record final instance state: {}", e),
+        }
+
         let client = self.client.as_ref().unwrap();
 
-        if !self.ssh_key_name.trim().is_empty() {
+        if !self.existing_key_pair() && !self.ssh_key_name.trim().is_empty() {
             let key_span = tracing::trace_span!("key", name = %self.ssh_key_name);
             async {
                 tracing::trace!("removing keypair");
@@ -1463,21 +6852,65 @@ impl RegionLauncher {
             .await;
         }
 
+        // persistent spot requests would otherwise replace the instances we terminate below
+        let persistent: Vec<_> = self
+            .spot_requests
+            .iter()
+            .filter(|(_, t)| t.setup.interruption_behavior != InterruptionBehavior::Terminate)
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !persistent.is_empty() {
+            tracing::debug!("cancelling persistent spot requests");
+            let req = rusoto_ec2::CancelSpotInstanceRequestsRequest {
+                spot_instance_request_ids: persistent,
+                ..Default::default()
+            };
+            client
+                .cancel_spot_instance_requests(req)
+                .await
+                .wrap_err("failed to cancel persistent spot requests")?;
+        }
+
         // terminate instances
         if !self.instances.is_empty() {
             tracing::info!("terminating instances");
-            let instance_ids = self.instances.keys().cloned().collect();
+            let instance_ids: Vec<_> = self.instances.keys().cloned().collect();
             self.instances.clear();
             // Why is `?` here ok? either:
             // 1. there was no spot capacity. So self.instances will be empty, and this
             //    block will get skipped, so sg will get cleaned up below.
             // 2. there were instances, but we couldn't terminate them. Then, the sg will
             //    still be attached to them, so there's no point trying to delete it.
-            self.terminate_instances(instance_ids).await?;
+            self.terminate_instances(instance_ids.clone()).await?;
+            self.wait_for_termination(instance_ids, time::Duration::from_secs(5 * 60))
+                .await?;
+        }
+
+        for name in std::mem::take(&mut self.placement_groups) {
+            tracing::trace!(%name, "removing placement group");
+            let req = rusoto_ec2::DeletePlacementGroupRequest {
+                group_name: name.clone(),
+            };
+            if let Err(e) = client.delete_placement_group(req).await {
+                tracing::warn!(%name, "failed to clean up placement group: {}", e);
+            }
+        }
+        self.auto_placement_group = None;
+
+        // the addresses were disassociated when their instances terminated
+        for (instance_id, allocation_id) in std::mem::take(&mut self.addresses) {
+            tracing::trace!(%allocation_id, %instance_id, "releasing elastic ip");
+            let req = rusoto_ec2::ReleaseAddressRequest {
+                allocation_id: Some(allocation_id.clone()),
+                ..Default::default()
+            };
+            if let Err(e) = client.release_address(req).await {
+                tracing::warn!(%allocation_id, "failed to release elastic ip: {}", e);
+            }
         }
 
         use rusoto_core::RusotoError;
-        if !self.security_group_id.trim().is_empty() {
+        if !self.existing_security_group && !self.security_group_id.trim().is_empty() {
             let group_span =
                 tracing::trace_span!("removing security group", id = %self.security_group_id);
             async {
@@ -1517,20 +6950,120 @@ impl RegionLauncher {
                     }
                 }
 
-                tracing::trace!("cleaned up temporary security group");
-                Ok::<_, Report>(())
+                tracing::trace!("cleaned up temporary security group");
+                Ok::<_, Report>(())
+            }
+            .instrument(group_span)
+            .await?;
+        }
+
+        snapshotted
+    }
+
+    // Cancels the spot requests of, and terminates, the machines in `names`. Unlike
+    // `terminate_all`, this leaves the key pair, security group, and all other machines alone.
+    #[instrument(level = "debug", skip(self))]
+    async fn roll_back(&mut self, names: &HashSet<String>) -> Result<(), Report> {
+        let requests: Vec<_> = self
+            .spot_requests
+            .iter()
+            .filter(|(_, t)| names.contains(&t.name))
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !requests.is_empty() {
+            // open requests would otherwise still be fulfilled later
+            let req = rusoto_ec2::CancelSpotInstanceRequestsRequest {
+                spot_instance_request_ids: requests.clone(),
+                ..Default::default()
+            };
+            self.client
+                .as_ref()
+                .unwrap()
+                .cancel_spot_instance_requests(req)
+                .await
+                .wrap_err("failed to cancel spot requests")?;
+            for id in &requests {
+                self.spot_requests.remove(id);
+            }
+        }
+
+        let instance_ids: Vec<_> = self
+            .instances
+            .iter()
+            .filter(|(_, t)| names.contains(&t.name))
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !instance_ids.is_empty() {
+            tracing::info!("terminating instances");
+            self.terminate_instances(instance_ids.clone()).await?;
+            for id in &instance_ids {
+                self.instances.remove(id);
+            }
+            self.wait_for_termination(instance_ids, time::Duration::from_secs(5 * 60))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Poll EC2 until all of `instance_ids` are terminated, or `max_wait` elapses.
+    #[instrument(level = "debug", skip(self))]
+    async fn wait_for_termination(
+        &self,
+        instance_ids: Vec<String>,
+        max_wait: time::Duration,
+    ) -> Result<(), Report> {
+        let client = self.client.as_ref().unwrap();
+        let start = time::Instant::now();
+        let mut remaining = instance_ids;
+        loop {
+            // filtering by id rather than listing the ids keeps EC2 from failing the whole
+            // request with `InvalidInstanceID.NotFound` once it forgets about an instance.
+            let desc_req = rusoto_ec2::DescribeInstancesRequest {
+                filters: Some(vec![rusoto_ec2::Filter {
+                    name: Some("instance-id".to_string()),
+                    values: Some(remaining.clone()),
+                }]),
+                ..Default::default()
+            };
+            let instances = client
+                .describe_instances(desc_req)
+                .await
+                .wrap_err("could not query AWS for instance state")?
+                .reservations
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|r| r.instances.unwrap_or_default());
+
+            // code 48 means "terminated"; instances EC2 no longer knows about are gone too.
+            remaining = instances
+                .filter(|i| {
+                    let code = i.state.as_ref().and_then(|s| s.code);
+                    code.map(|c| c & 0xff) != Some(48)
+                })
+                .filter_map(|i| i.instance_id)
+                .collect();
+            if remaining.is_empty() {
+                tracing::debug!("all instances terminated");
+                return Ok(());
             }
-            .instrument(group_span)
-            .await?;
-        }
 
-        Ok(())
+            if start.elapsed() > max_wait {
+                eyre::bail!(
+                    "{} instance(s) in {} did not terminate within {:?}: {}",
+                    remaining.len(),
+                    self.region.name(),
+                    max_wait,
+                    remaining.join(", ")
+                );
+            }
+
+            tracing::trace!(n = remaining.len(), "waiting for instances to terminate");
+            tokio::time::sleep(time::Duration::from_secs(5)).await;
+        }
     }
 
     #[instrument(level = "debug")]
-    async fn describe_spot_instance_requests(
-        &self,
-    ) -> Result<Vec<(String, String, String, Option<String>)>, Report> {
+    async fn describe_spot_instance_requests(&self) -> Result<Vec<SpotRequestStatus>, Report> {
         let client = self.client.as_ref().unwrap();
         let request_ids = self.spot_requests.keys().cloned().collect();
         let req = rusoto_ec2::DescribeSpotInstanceRequestsRequest {
@@ -1567,19 +7100,29 @@ impl RegionLauncher {
                         .expect("spot request did not have state specified");
                     let status = sir
                         .status
-                        .expect("spot request did not have status specified")
+                        .expect("spot request did not have status specified");
+                    let code = status
                         .code
                         .expect("spot request status did not have status code");
-                    let instance_id = sir.instance_id;
-                    (request_id, state, status, instance_id)
+                    SpotRequestStatus {
+                        request_id,
+                        state,
+                        code,
+                        message: status.message,
+                        instance_id: sir.instance_id,
+                        zone: sir
+                            .launched_availability_zone
+                            .or_else(|| sir.launch_specification?.placement?.availability_zone),
+                    }
                 })
                 .collect();
             break Ok(instances);
         }
     }
 
+    // Cancels the spot requests, terminates any instances they got, and forgets about them.
     #[instrument(level = "debug")]
-    async fn cancel_spot_instance_requests(&self) -> Result<(), Report> {
+    async fn cancel_spot_instance_requests(&mut self) -> Result<(), Report> {
         tracing::warn!("wait time exceeded for -- cancelling run");
         if self.spot_requests.is_empty() {
             return Ok(());
@@ -1601,13 +7144,14 @@ impl RegionLauncher {
             tracing::trace!("checking spot request status");
             let instances = self.describe_spot_instance_requests().await?;
 
-            let all_cancelled = instances.iter().all(|(request_id, state, _, instance_id)| {
+            let all_cancelled = instances.iter().all(|sir| {
+                let state = &sir.state;
                 if state == "closed"
                     || state == "cancelled"
                     || state == "failed"
                     || state == "completed"
                 {
-                    tracing::trace!(%request_id, ?instance_id, "spot instance request {}", state);
+                    tracing::trace!(request_id = %sir.request_id, instance_id = ?sir.instance_id, "spot instance request {}", state);
                     true
                 } else {
                     false
@@ -1619,9 +7163,10 @@ impl RegionLauncher {
                 // find instances with an id assigned and terminate them
                 let instance_ids = instances
                     .into_iter()
-                    .filter_map(|(_, _, _, instance_id)| instance_id)
+                    .filter_map(|sir| sir.instance_id)
                     .collect();
                 self.terminate_instances(instance_ids).await?;
+                self.spot_requests.clear();
                 break;
             }
 
@@ -1655,17 +7200,292 @@ impl RegionLauncher {
     }
 }
 
+// The subnet of a VPC to launch into: one in the availability zone, if one is specified, that
+// gives instances public addresses, if there is one.
+fn pick_subnet(
+    subnets: Vec<rusoto_ec2::Subnet>,
+    availability_zone: &AvailabilityZoneSpec,
+) -> Option<String> {
+    subnets
+        .into_iter()
+        .filter(|s| match availability_zone {
+            AvailabilityZoneSpec::Specify(az) => s.availability_zone.as_ref() == Some(az),
+            _ => true,
+        })
+        .filter_map(|s| Some((!s.map_public_ip_on_launch.unwrap_or(false), s.subnet_id?)))
+        .min()
+        .map(|(_, id)| id)
+}
+
+// How many rounds of spot requests have to fail for lack of capacity in an availability zone
+// before machines that may go in any zone stop being launched there.
+const SPOT_ZONE_STRIKES: usize = 2;
+
+// The first availability zone, by name, in which all of `types` are offered, given the offered
+// (instance type, zone) pairs.
+fn common_zone(
+    offerings: impl IntoIterator<Item = (String, String)>,
+    types: &[String],
+) -> Option<String> {
+    let mut zones: HashMap<String, HashSet<String>> = HashMap::new();
+    for (t, az) in offerings {
+        zones.entry(az).or_default().insert(t);
+    }
+    zones
+        .into_iter()
+        .filter(|(_, offered)| types.iter().all(|t| offered.contains(t)))
+        .map(|(az, _)| az)
+        .min()
+}
+
+// The address ranges on the other side of each of `vpc`'s peering connections.
+fn peered_cidrs(vpc: &str, peerings: &[rusoto_ec2::VpcPeeringConnection]) -> Vec<String> {
+    let mut cidrs: Vec<_> = peerings
+        .iter()
+        .filter_map(|p| {
+            let (requester, accepter) = (
+                p.requester_vpc_info.as_ref()?,
+                p.accepter_vpc_info.as_ref()?,
+            );
+            let other = if requester.vpc_id.as_deref() == Some(vpc) {
+                accepter
+            } else {
+                requester
+            };
+            other.cidr_block.clone()
+        })
+        .collect();
+    cidrs.sort();
+    cidrs.dedup();
+    cidrs
+}
+
+/// The public IPv4 address that the machine running tsunami reaches the Internet from, as seen by
+/// AWS.
+///
+/// This asks `checkip.amazonaws.com`. See [`Launcher::restrict_ssh`].
+pub async fn caller_ip() -> Result<std::net::Ipv4Addr, Report> {
+    use rusoto_core::DispatchSignedRequest;
+    const CHECKIP: &str = "checkip.amazonaws.com";
+
+    let mut req =
+        rusoto_core::signature::SignedRequest::new("GET", "checkip", &Region::UsEast1, "/");
+    req.set_hostname(Some(CHECKIP.to_string()));
+    let res = HttpClient::new()
+        .wrap_err("failed to construct new http client")?
+        .dispatch(req, Some(time::Duration::from_secs(10)))
+        .await
+        .wrap_err_with(|| format!("failed to reach {}", CHECKIP))?
+        .buffer()
+        .await
+        .wrap_err_with(|| format!("failed to read the response of {}", CHECKIP))?;
+    eyre::ensure!(
+        res.status.is_success(),
+        "{} responded with {}",
+        CHECKIP,
+        res.status
+    );
+    let body = res.body_as_str().trim();
+    body.parse()
+        .wrap_err_with(|| format!("{} responded with {:?}", CHECKIP, body))
+}
+
+// Whether the instance at `ip` accepts SSH directly, or only through `ssm_proxy`.
+async fn probe_ssh(
+    ip: &IpInfo,
+    username: &str,
+    private_key_path: &std::path::Path,
+    ssm_proxy: Option<&str>,
+    timeout: time::Duration,
+) -> Result<bool, Report> {
+    let connect = move |public_ip: &str, proxy_command: Option<&str>| {
+        crate::MachineDescriptor {
+            nickname: Default::default(),
+            public_dns: None,
+            public_ip: public_ip.to_string(),
+            private_ip: None,
+            os: None,
+            proxy_command: proxy_command.map(String::from),
+            check_host_key: true,
+            _tsunami: Default::default(),
+        }
+        .connect_ssh(username, Some(private_key_path), Some(timeout), 22)
+    };
+
+    let direct = if ip.public_ip.is_empty() {
+        Err(eyre!("instance has no public ip"))
+    } else {
+        connect(&ip.public_ip, None).await.map(drop)
+    };
+    match (direct, ssm_proxy) {
+        (Ok(()), _) => Ok(false),
+        (Err(e), Some(proxy)) => {
+            tracing::trace!("ssh failed: {}; trying session manager", e);
+            // over session manager, the address only names the host to ssh.
+            connect(&ip.private_ip, Some(proxy))
+                .await
+                .wrap_err("ssh over session manager failed")?;
+            Ok(true)
+        }
+        (Err(e), None) => Err(e),
+    }
+}
+
+// Retries `f` a few times if its request may not have reached EC2.
+//
+// Only use this for requests that carry a client token, since otherwise a request that did reach
+// EC2 would be executed twice.
+// The id of a snapshot that EC2 reports as created.
+fn snapshot_id(s: rusoto_ec2::Snapshot) -> Result<String, Report> {
+    s.snapshot_id
+        .ok_or_else(|| eyre!("EC2 created a snapshot without an id"))
+}
+
+// The tags that name an instance after its nickname.
+fn name_tags(nickname: &str) -> Vec<rusoto_ec2::Tag> {
+    ["Name", NICKNAME_TAG]
+        .iter()
+        .map(|k| rusoto_ec2::Tag {
+            key: Some(k.to_string()),
+            value: Some(nickname.to_string()),
+        })
+        .collect()
+}
+
+// The zones in which the spot requests of `statuses` found no capacity: those of the requests that
+// failed for lack of it, and, if `stuck`, of the open ones that EC2 is holding for lack of it.
+// `zone` stands in for requests that do not say which zone they are in.
+fn short_zones(statuses: &[SpotRequestStatus], stuck: bool, zone: Option<&String>) -> Vec<String> {
+    statuses
+        .iter()
+        .filter(|sir| is_capacity_code(&sir.code))
+        .filter(|sir| match &*sir.state {
+            "open" => stuck,
+            "active" => false,
+            _ => true,
+        })
+        .filter_map(|sir| sir.zone.clone().or_else(|| zone.cloned()))
+        .collect()
+}
+
+// Whether machines whose spot requests failed with `e` should be launched as on-demand instances
+// instead. `fallback_first` says whether the wait for the requests ended because of
+// `Launcher::spot_fallback_after` rather than `max_wait`.
+fn fall_back_on_demand(mode: &LaunchMode, fallback_first: bool, e: &Report) -> bool {
+    if let Some(e) = e.downcast_ref::<SpotRequestError>() {
+        // a malformed request will not succeed as on-demand either.
+        return matches!(mode, LaunchMode::TrySpot { .. })
+            && e.failures.iter().any(SpotRequestFailure::is_capacity);
+    }
+    if e.downcast_ref::<WaitLimitError>().is_some() && fallback_first {
+        return true;
+    }
+    matches!(mode, LaunchMode::TrySpot { .. })
+}
+
+// The error code of an EC2 error response, e.g. `InvalidInstanceID.NotFound`. rusoto only
+// parses the codes it knows about for each request, and leaves the rest in the raw body.
+fn ec2_error_code(body: &str) -> Option<&str> {
+    let start = body.find("<Code>")? + "<Code>".len();
+    let len = body[start..].find("</Code>")?;
+    Some(&body[start..start + len])
+}
+
+async fn retry_dispatch<T, E, F, Fut>(mut f: F) -> Result<T, rusoto_core::RusotoError<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, rusoto_core::RusotoError<E>>>,
+{
+    let mut tries = 0;
+    loop {
+        match f().await {
+            Err(rusoto_core::RusotoError::HttpDispatch(e)) if tries < 3 => {
+                tries += 1;
+                tracing::debug!(%e, "retrying idempotent request");
+                tokio::time::sleep(time::Duration::from_secs(1)).await;
+            }
+            r => return r,
+        }
+    }
+}
+
+// A user data script that shuts the instance down `d` after it boots, rounded up to the minute.
+// Linux calls powering off `-P`, and FreeBSD calls it `-p`.
+fn shutdown_script(d: time::Duration) -> String {
+    let minutes = (d.as_secs() + 59) / 60;
+    format!(
+        "#!/bin/sh\nshutdown -P +{m} 2>/dev/null || shutdown -p +{m}\n",
+        m = minutes
+    )
+}
+
+// The EC2 architecture (`x86_64` or `arm64`) of `instance_type`. ARM instance families have a `g`
+// (for Graviton) after their generation, as in `c6g`, `m7gd`, `t4g`, or `im4gn`, except for the
+// first generation `a1`.
+fn architecture(instance_type: &str) -> &'static str {
+    let family = instance_type.split('.').next().unwrap_or_default();
+    let attributes = family
+        .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+        .trim_start_matches(|c: char| c.is_ascii_digit());
+    if family == "a1" || attributes.starts_with('g') {
+        "arm64"
+    } else {
+        "x86_64"
+    }
+}
+
+// How Debian-style image names and Ubuntu's image list call the EC2 architecture `arch`.
+fn debian_arch(arch: &str) -> &str {
+    match arch {
+        "x86_64" => "amd64",
+        arch => arch,
+    }
+}
+
+// The owner and name pattern of the AMIs for `arch` that match `spec`, or `None` if `spec` is an
+// AMI id.
+fn ami_query(spec: &crate::image::ImageSpec, arch: &str) -> Option<(&'static str, String)> {
+    use crate::image::ImageSpec;
+    // Canonical and Debian publish their images from these accounts.
+    const CANONICAL: &str = "099720109477";
+    const DEBIAN: &str = "136693071363";
+    let arch = debian_arch(arch);
+    Some(match spec {
+        ImageSpec::Ubuntu2004 => (
+            CANONICAL,
+            format!("ubuntu/images/hvm-ssd/ubuntu-focal-20.04-{}-server-*", arch),
+        ),
+        ImageSpec::Ubuntu2204 => (
+            CANONICAL,
+            format!("ubuntu/images/hvm-ssd/ubuntu-jammy-22.04-{}-server-*", arch),
+        ),
+        ImageSpec::Debian11 => (DEBIAN, format!("debian-11-{}-*", arch)),
+        ImageSpec::CustomByName(name) => ("self", name.clone()),
+        _ => return None,
+    })
+}
+
+// The Ubuntu release codename of `spec`, for looking it up in Ubuntu's image list.
+fn ubuntu_release(spec: &crate::image::ImageSpec) -> Option<&'static str> {
+    use crate::image::ImageSpec;
+    match spec {
+        ImageSpec::Ubuntu2004 => Some("focal"),
+        ImageSpec::Ubuntu2204 => Some("jammy"),
+        _ => None,
+    }
+}
+
 struct UbuntuAmi(String);
 
 impl UbuntuAmi {
-    async fn new(r: Region) -> Result<Self, Report> {
+    async fn new(r: Region, release: &str, arch: &str) -> Result<Self, Report> {
         Ok(UbuntuAmi(
             ubuntu_ami::get_latest(
-                &r.name(),
-                Some("bionic"),
+                r.name(),
+                Some(release),
                 None,
                 Some("hvm:ebs-ssd"),
-                Some("amd64"),
+                Some(debian_arch(arch)),
             )
             .await
             .map_err(|e| eyre!(e))?,
@@ -1737,13 +7557,773 @@ mod test {
             if let Err(e) = do_make_machine_and_ssh_setupfn(&mut l).await {
                 // failed test.
                 l.terminate_all().await.unwrap();
-                panic!(e);
+                panic!("{}", e);
             } else {
                 l.terminate_all().await.unwrap();
             }
         })
     }
 
+    #[cfg(feature = "cloudwatch")]
+    #[test]
+    fn metrics_as_csv() {
+        let samples = vec![
+            MetricSample {
+                timestamp: "2020-01-01T00:00:00Z".to_string(),
+                average: Some(12.5),
+                maximum: Some(40.0),
+                unit: Some("Percent".to_string()),
+            },
+            MetricSample {
+                timestamp: "2020-01-01T00:05:00Z".to_string(),
+                average: None,
+                maximum: Some(3.0),
+                unit: None,
+            },
+        ];
+        assert_eq!(
+            super::metrics_csv(&samples),
+            "2020-01-01T00:00:00Z,12.5,40,Percent\n2020-01-01T00:05:00Z,,3,\n"
+        );
+    }
+
+    #[test]
+    fn placed_keeps_custom_ami() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let p = crate::placement::Placement {
+            region: "eu-west-1".to_string(),
+            zone_group: Some(0),
+        };
+        rt.block_on(async {
+            let s = Setup::default()
+                .ami("ami-custom", "centos")
+                .placed(&p)
+                .await
+                .unwrap();
+            assert_eq!(s.region, Region::EuWest1);
+            assert_eq!(s.ami, "ami-custom");
+            assert_eq!(s.image, None);
+            assert_eq!(s.username, "centos");
+            assert_eq!(s.availability_zone, AvailabilityZoneSpec::Cluster(0));
+
+            let s = Setup::default().placed(&p).await.unwrap();
+            assert_eq!(s.region, Region::EuWest1);
+            assert_eq!(s.image, Some(crate::image::ImageSpec::Ubuntu2204));
+            assert_eq!(s.username, "ubuntu");
+        });
+    }
+
+    #[test]
+    fn budget() {
+        let m = |t: &str, p| Setup::default().instance_type(t).priority(p);
+        let ms = vec![
+            (String::from("a"), m("c5.2xlarge", Priority::Critical)),
+            (String::from("b"), m("c5.2xlarge", Priority::BestEffort)),
+            (String::from("c"), m("c5.xlarge", Priority::BestEffort)),
+        ];
+        let budget = Budget::per_hour(0.55)
+            .price("c5.2xlarge", 0.34)
+            .price("c5.xlarge", 0.17)
+            .price("c5.large", 0.085);
+        assert!(budget.fit(ms.clone()).is_err());
+        assert!(Budget::per_hour(1.0).fit(ms.clone()).is_err());
+
+        let budget = budget.downsize_to(vec!["c5.2xlarge", "c5.xlarge", "c5.large"]);
+        let (fit, changes) = budget.fit(ms.clone()).unwrap();
+        let resized = |n: &str, from: &str, to: &str| Downsized::Resized {
+            nickname: n.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        assert_eq!(
+            changes,
+            vec![
+                resized("b", "c5.2xlarge", "c5.xlarge"),
+                resized("b", "c5.xlarge", "c5.large"),
+                resized("c", "c5.xlarge", "c5.large"),
+            ]
+        );
+        assert_eq!(fit.len(), 3);
+        assert!(budget.estimate(&fit).unwrap() <= 0.55);
+
+        // best-effort machines go before critical machines shrink
+        let (fit, changes) = Budget {
+            per_hour: 0.3,
+            ..budget
+        }
+        .fit(ms)
+        .unwrap();
+        assert_eq!(
+            changes.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "resized b from c5.2xlarge to c5.xlarge",
+                "resized b from c5.xlarge to c5.large",
+                "resized c from c5.xlarge to c5.large",
+                "dropped b (c5.large)",
+                "dropped c (c5.large)",
+                "resized a from c5.2xlarge to c5.xlarge",
+            ]
+        );
+        assert_eq!(fit.len(), 1);
+    }
+
+    #[test]
+    fn budget_prices() {
+        let ms = vec![
+            (
+                String::from("a"),
+                Setup::default().instance_type("c5.xlarge"),
+            ),
+            (
+                String::from("b"),
+                Setup::default().instance_type("c5.xlarge").on_demand(),
+            ),
+        ];
+        let budget = Budget::per_hour(0.3)
+            .price("c5.xlarge", 0.17)
+            .spot_price("c5.xlarge", 0.07);
+        assert!((budget.estimate(&ms).unwrap() - 0.34).abs() < 1e-9);
+        assert!(budget.fit(ms.clone()).is_err());
+        // only the machine that is not on-demand gets the spot price
+        let budget = budget.spot();
+        assert!((budget.estimate(&ms).unwrap() - 0.24).abs() < 1e-9);
+        assert!(budget.fit(ms.clone()).is_ok());
+
+        let mut l = Launcher::default();
+        l.budget(
+            Budget::per_hour(0.3)
+                .price("c5.xlarge", 0.17)
+                .spot_price("c5.xlarge", 0.07),
+        );
+        assert!(l.fit_budget(ms.clone()).is_ok());
+        l.set_mode(LaunchMode::on_demand());
+        assert!(l.fit_budget(ms.clone()).is_err());
+        assert!(l.downsized().is_empty());
+
+        for bad in vec![f64::NAN, -1.0, f64::INFINITY] {
+            assert!(Budget::per_hour(bad)
+                .price("c5.xlarge", 0.17)
+                .estimate(&ms)
+                .is_err());
+            assert!(Budget::per_hour(1.0)
+                .price("c5.xlarge", bad)
+                .fit(ms.clone())
+                .is_err());
+            assert!(Budget::per_hour(1.0)
+                .price("c5.xlarge", 0.17)
+                .spot_price("c5.xlarge", bad)
+                .fit(ms.clone())
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn fleet_launch_template() {
+        let m = template_block_device(ebs_mapping(
+            String::from("/dev/sdf"),
+            100,
+            VolumeType::Gp3,
+            false,
+        ));
+        assert_eq!(m.device_name.as_deref(), Some("/dev/sdf"));
+        let ebs = m.ebs.unwrap();
+        assert_eq!(ebs.volume_size, Some(100));
+        assert_eq!(ebs.delete_on_termination, Some(false));
+        assert_eq!(ebs.volume_type.as_deref(), Some("gp3"));
+
+        // machines that may use different instance types go in different fleets
+        let m = Setup::default().instance_type("c5.large");
+        let alt = m.clone().alternative_instance_types(vec!["c5a.large"]);
+        assert_ne!(RequestGroup::of(&m), RequestGroup::of(&alt));
+    }
+
+    #[test]
+    fn spot_request_error() {
+        let failure = |code: &str| SpotRequestFailure {
+            nickname: String::from("server"),
+            request_id: String::from("sir-1"),
+            state: String::from("closed"),
+            code: code.to_string(),
+            message: None,
+        };
+        assert!(failure("capacity-not-available").is_capacity());
+        assert!(!failure("bad-parameters").is_capacity());
+        // fleets report their errors with different codes
+        assert!(failure("InsufficientInstanceCapacity").is_capacity());
+
+        let e = Report::new(SpotRequestError {
+            failures: vec![failure("price-too-low")],
+        })
+        .wrap_err("launch failed");
+        let e = e.downcast_ref::<SpotRequestError>().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "1 spot request(s) failed: server (request sir-1 closed: price-too-low)"
+        );
+    }
+
+    #[test]
+    fn spot_fallback() {
+        let failed = |code: &str| {
+            Report::new(SpotRequestError {
+                failures: vec![SpotRequestFailure {
+                    nickname: String::from("server"),
+                    request_id: String::from("sir-1"),
+                    state: String::from("closed"),
+                    code: code.to_string(),
+                    message: None,
+                }],
+            })
+            .wrap_err("failed while waiting for spot instances fulfilment")
+        };
+        let timed_out = || {
+            Report::new(WaitLimitError {
+                waited: time::Duration::from_secs(60),
+            })
+            .wrap_err("failed while waiting for spot instances fulfilment")
+        };
+        let try_spot = LaunchMode::TrySpot { hours: 1 };
+        let spot = LaunchMode::DefinedDuration { hours: 1 };
+
+        assert!(fall_back_on_demand(
+            &try_spot,
+            false,
+            &failed("capacity-not-available")
+        ));
+        assert!(!fall_back_on_demand(
+            &try_spot,
+            true,
+            &failed("bad-parameters")
+        ));
+        assert!(!fall_back_on_demand(
+            &spot,
+            true,
+            &failed("capacity-not-available")
+        ));
+
+        // only the fallback wait, not `max_wait`, falls back in every mode
+        assert!(fall_back_on_demand(&spot, true, &timed_out()));
+        assert!(!fall_back_on_demand(&spot, false, &timed_out()));
+        assert!(!fall_back_on_demand(&spot, true, &eyre!("throttled")));
+        assert!(fall_back_on_demand(&try_spot, false, &timed_out()));
+    }
+
+    #[test]
+    fn region_spec_roundtrip() {
+        for az in [
+            AvailabilityZoneSpec::Any,
+            AvailabilityZoneSpec::Cluster(3),
+            AvailabilityZoneSpec::Specify(String::from("us-east-1a")),
+        ] {
+            let mut r = RegionSpec {
+                region: Region::UsEast1,
+                availability_zone: az,
+                near: None,
+                security_group: None,
+            };
+            assert_eq!(r.to_string().parse::<RegionSpec>().unwrap(), r);
+            r.near = Some(Affinity::tagged("Name", "db@prod"));
+            assert_eq!(r.to_string().parse::<RegionSpec>().unwrap(), r);
+            r.security_group = Some(String::from("sg-0123"));
+            assert_eq!(r.to_string().parse::<RegionSpec>().unwrap(), r);
+            r.near = None;
+            assert_eq!(r.to_string().parse::<RegionSpec>().unwrap(), r);
+        }
+        assert!("us-east-1#".parse::<RegionSpec>().is_err());
+        assert!("mars-north-1".parse::<RegionSpec>().is_err());
+        assert!("us-east-1@i-0123".parse::<RegionSpec>().is_err());
+    }
+
+    #[test]
+    fn spot_notice_parsing() {
+        let out = "rebalance {\"noticeTime\": \"2021-03-01T17:32:13Z\"}\n\
+                   interruption {\"action\": \"terminate\", \"time\": \"2021-03-01T17:34:13Z\"}\n";
+        let at = |s| parse_timestamp(s).unwrap();
+        assert_eq!(
+            parse_spot_notices(out),
+            vec![
+                SpotNotice::Rebalance {
+                    issued: at("2021-03-01T17:32:13Z")
+                },
+                SpotNotice::Interruption {
+                    action: String::from("terminate"),
+                    at: at("2021-03-01T17:34:13Z"),
+                },
+            ]
+        );
+        assert_eq!(parse_spot_notices("rebalance \ninterruption \n"), vec![]);
+        // a key that also shows up as a value
+        assert_eq!(
+            parse_spot_notices(
+                "interruption {\"action\": \"time\", \"time\": \"2021-03-01T17:34:13Z\"}"
+            ),
+            vec![SpotNotice::Interruption {
+                action: String::from("time"),
+                at: at("2021-03-01T17:34:13Z"),
+            }]
+        );
+    }
+
+    #[test]
+    fn ingress_rules() {
+        assert_eq!(
+            IngressRule::tcp(80..=80).from_anywhere().to_string(),
+            "tcp 80 from 0.0.0.0/0"
+        );
+        assert_eq!(
+            IngressRule::udp(9000..=9100).to_string(),
+            "udp 9000-9100 from the machines' network"
+        );
+        assert_eq!(
+            IngressRule::icmp().from_cidr("10.0.0.0/8").to_string(),
+            "icmp from 10.0.0.0/8"
+        );
+        assert_eq!(IngressRule::all("tcp"), IngressRule::tcp(0..=65535));
+    }
+
+    #[test]
+    fn affinity() {
+        for a in [
+            Affinity::Instance(String::from("i-0123")),
+            Affinity::Subnet(String::from("subnet-0123")),
+            Affinity::Vpc(String::from("vpc-0123")),
+            Affinity::tagged("Name", "db"),
+        ] {
+            assert_eq!(a.to_string().parse::<Affinity>().unwrap(), a);
+        }
+        assert!("tag:Name".parse::<Affinity>().is_err());
+
+        let subnet = |id: &str, az: &str, public: bool| rusoto_ec2::Subnet {
+            subnet_id: Some(id.to_string()),
+            availability_zone: Some(az.to_string()),
+            map_public_ip_on_launch: Some(public),
+            ..Default::default()
+        };
+        let subnets = vec![
+            subnet("subnet-c", "us-east-1a", false),
+            subnet("subnet-b", "us-east-1b", true),
+            subnet("subnet-a", "us-east-1a", true),
+        ];
+        assert_eq!(
+            pick_subnet(subnets.clone(), &AvailabilityZoneSpec::Any).as_deref(),
+            Some("subnet-a")
+        );
+        let az = AvailabilityZoneSpec::Specify(String::from("us-east-1b"));
+        assert_eq!(pick_subnet(subnets, &az).as_deref(), Some("subnet-b"));
+
+        let side = |vpc: &str, cidr: &str| rusoto_ec2::VpcPeeringConnectionVpcInfo {
+            vpc_id: Some(vpc.to_string()),
+            cidr_block: Some(cidr.to_string()),
+            ..Default::default()
+        };
+        let peering = |requester, accepter| rusoto_ec2::VpcPeeringConnection {
+            requester_vpc_info: Some(requester),
+            accepter_vpc_info: Some(accepter),
+            ..Default::default()
+        };
+        let peerings = vec![
+            peering(side("vpc-a", "10.0.0.0/16"), side("vpc-b", "10.1.0.0/16")),
+            peering(side("vpc-c", "10.2.0.0/16"), side("vpc-a", "10.0.0.0/16")),
+        ];
+        assert_eq!(
+            peered_cidrs("vpc-a", &peerings),
+            vec![String::from("10.1.0.0/16"), String::from("10.2.0.0/16")]
+        );
+    }
+
+    #[test]
+    fn tags() {
+        let tags = |ts: &[(&str, &str)]| -> BTreeMap<String, String> {
+            ts.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        assert!(check_tags(&tags(&[("owner", "alice"), ("cost-center", "")])).is_ok());
+        assert!(check_tags(&tags(&[("aws:createdBy", "me")])).is_err());
+        assert!(check_tags(&tags(&[("Name", "db")])).is_err());
+        assert!(check_tags(&tags(&[(RUN_TAG, "run-0")])).is_err());
+        assert!(check_tags(&tags(&[("", "x")])).is_err());
+        let many: Vec<_> = (0..48).map(|i| (i.to_string(), String::new())).collect();
+        assert!(check_tags(&many.into_iter().collect()).is_err());
+
+        let mut l = Launcher::default();
+        l.with_tags(vec![("owner", "alice")])
+            .with_tags(vec![("owner", "bob"), ("project", "tsunami")]);
+        let mut r = RegionLauncher::default();
+        r.run_id = String::from("run-0");
+        r.tags = l.tags.clone();
+        let spec = r.run_tags("instance").remove(0);
+        assert_eq!(spec.resource_type.as_deref(), Some("instance"));
+        let got: Vec<_> = spec
+            .tags
+            .unwrap()
+            .into_iter()
+            .map(|t| (t.key.unwrap(), t.value.unwrap()))
+            .collect();
+        let want: Vec<_> = vec![(RUN_TAG, "run-0"), ("owner", "bob"), ("project", "tsunami")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(got, want);
+
+        let names: Vec<_> = name_tags("server")
+            .into_iter()
+            .map(|t| (t.key.unwrap(), t.value.unwrap()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (String::from("Name"), String::from("server")),
+                (NICKNAME_TAG.to_string(), String::from("server")),
+            ]
+        );
+    }
+
+    #[test]
+    fn snapshot_ids() {
+        let s = rusoto_ec2::Snapshot {
+            snapshot_id: Some(String::from("snap-0123")),
+            ..Default::default()
+        };
+        assert_eq!(snapshot_id(s).unwrap(), "snap-0123");
+        assert!(snapshot_id(rusoto_ec2::Snapshot::default()).is_err());
+    }
+
+    #[test]
+    fn error_codes() {
+        let body = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Response><Errors><Error><Code>InvalidInstanceID.NotFound</Code><Message>The instance ID 'i-0123' does not exist</Message></Error></Errors><RequestID>abc</RequestID></Response>"#;
+        assert_eq!(ec2_error_code(body), Some("InvalidInstanceID.NotFound"));
+        assert_eq!(ec2_error_code("bad gateway"), None);
+    }
+
+    #[test]
+    fn zone_pinning() {
+        let offer = |t: &str, az: &str| (t.to_string(), az.to_string());
+        let offerings = vec![
+            offer("c5.large", "us-east-1c"),
+            offer("c5.large", "us-east-1b"),
+            offer("c5.large", "us-east-1a"),
+            offer("p3.2xlarge", "us-east-1c"),
+            offer("p3.2xlarge", "us-east-1b"),
+        ];
+        let types = |ts: &[&str]| ts.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            common_zone(offerings.clone(), &types(&["c5.large"])).as_deref(),
+            Some("us-east-1a")
+        );
+        assert_eq!(
+            common_zone(offerings.clone(), &types(&["c5.large", "p3.2xlarge"])).as_deref(),
+            Some("us-east-1b")
+        );
+        assert_eq!(common_zone(offerings, &types(&["m5.large"])), None);
+    }
+
+    #[test]
+    fn zone_strikes() {
+        let mut rl = RegionLauncher::default();
+        let zones = |zs: &[&str]| zs.iter().map(|z| z.to_string()).collect::<Vec<_>>();
+        // many requests failing in one round count once
+        rl.strike_zones(zones(&["us-east-1a", "us-east-1a", "us-east-1b"]));
+        assert_eq!(rl.avoided_zones().count(), 0);
+        rl.strike_zones(zones(&["us-east-1a"]));
+        assert_eq!(rl.avoided_zones().collect::<Vec<_>>(), vec!["us-east-1a"]);
+    }
+
+    #[test]
+    fn short_zones_of_requests() {
+        let status = |state: &str, code: &str, zone: Option<&str>| SpotRequestStatus {
+            request_id: String::from("sir-1"),
+            state: state.to_string(),
+            code: code.to_string(),
+            message: None,
+            instance_id: None,
+            zone: zone.map(String::from),
+        };
+        let statuses = vec![
+            status("closed", "capacity-not-available", Some("us-east-1a")),
+            status("closed", "bad-parameters", Some("us-east-1b")),
+            status("open", "capacity-oversubscribed", None),
+            status("open", "pending-fulfillment", Some("us-east-1d")),
+            status("active", "fulfilled", Some("us-east-1e")),
+        ];
+        let asked = String::from("us-east-1c");
+        assert_eq!(
+            short_zones(&statuses, false, Some(&asked)),
+            vec!["us-east-1a"]
+        );
+        // requests still open at the wait limit count against the zone they were asked for
+        assert_eq!(
+            short_zones(&statuses, true, Some(&asked)),
+            vec!["us-east-1a", "us-east-1c"]
+        );
+        assert_eq!(short_zones(&statuses, true, None), vec!["us-east-1a"]);
+
+        // two rounds of stuck requests in the zone that was asked for steer away from it
+        let mut rl = RegionLauncher::default();
+        rl.strike_zones(short_zones(&statuses[2..], true, Some(&asked)));
+        assert_eq!(rl.avoided_zones().count(), 0);
+        rl.strike_zones(short_zones(&statuses[2..], true, Some(&asked)));
+        assert_eq!(rl.avoided_zones().collect::<Vec<_>>(), vec!["us-east-1c"]);
+    }
+
+    #[test]
+    fn instance_reports() {
+        let t = |s| time::UNIX_EPOCH + time::Duration::from_secs(s);
+        assert_eq!(
+            parse_timestamp("2000-02-29T11:59:59.000Z"),
+            Some(t(951_825_599))
+        );
+        assert_eq!(
+            parse_timestamp("2023-11-14 22:13:20"),
+            Some(t(1_700_000_000))
+        );
+        assert_eq!(parse_timestamp("2023-13-14 22:13:20"), None);
+
+        let instance = rusoto_ec2::Instance {
+            instance_id: Some(String::from("i-0123")),
+            instance_type: Some(String::from("t3.small")),
+            instance_lifecycle: Some(String::from("spot")),
+            launch_time: Some(String::from("2023-11-14T21:13:20.000Z")),
+            state: Some(rusoto_ec2::InstanceState {
+                code: Some(48),
+                name: Some(String::from("terminated")),
+            }),
+            state_reason: Some(rusoto_ec2::StateReason {
+                code: Some(String::from("Server.SpotInstanceTermination")),
+                message: Some(String::from(
+                    "Server.SpotInstanceTermination: Spot instance termination",
+                )),
+            }),
+            state_transition_reason: Some(String::from(
+                "Service initiated (2023-11-14 21:43:20 GMT)",
+            )),
+            ..Default::default()
+        };
+        let r = InstanceReport::from_instance(instance.clone(), t(1_700_000_000)).unwrap();
+        assert!(r.spot && r.interrupted);
+        assert_eq!(r.state, "terminated");
+        assert_eq!(r.uptime, Some(time::Duration::from_secs(30 * 60)));
+
+        let running = rusoto_ec2::Instance {
+            state: Some(rusoto_ec2::InstanceState {
+                code: Some(16),
+                name: Some(String::from("running")),
+            }),
+            state_reason: None,
+            state_transition_reason: None,
+            ..instance
+        };
+        let r = InstanceReport::from_instance(running, t(1_700_000_000)).unwrap();
+        assert!(!r.interrupted);
+        assert_eq!(r.state_reason, None);
+        assert_eq!(r.uptime, Some(time::Duration::from_secs(60 * 60)));
+    }
+
+    #[test]
+    fn volume_devices() {
+        assert_eq!(volume_device(0).as_deref(), Some("/dev/sdf"));
+        assert_eq!(volume_device(2).as_deref(), Some("/dev/sdh"));
+        assert_eq!(volume_device(20).as_deref(), Some("/dev/sdz"));
+        assert_eq!(volume_device(21), None);
+        assert_eq!(free_devices(&HashSet::new()).count(), 21);
+        assert!(data_volume_devices(&vec![DataVolume::new(1, VolumeType::Gp3); 22]).is_err());
+        let vs = vec![
+            DataVolume::new(10, VolumeType::Gp3),
+            DataVolume::new(10, VolumeType::Gp3).device("/dev/sdf"),
+            DataVolume::new(10, VolumeType::Gp3),
+        ];
+        assert_eq!(
+            data_volume_devices(&vs).unwrap(),
+            vec!["/dev/sdg", "/dev/sdf", "/dev/sdh"]
+        );
+        let taken = data_volume_devices(&vs).unwrap().into_iter().collect();
+        assert_eq!(free_devices(&taken).next().as_deref(), Some("/dev/sdi"));
+        assert!(data_volume_devices(&[vs[1].clone(), vs[1].clone()]).is_err());
+        assert!(data_volume_devices(&[DataVolume::new(1, VolumeType::Gp2).device("sdf")]).is_err());
+        let script = mount_script("vol-0abc", "/dev/sdf", "/data");
+        assert!(script.contains("nvme-Amazon_Elastic_Block_Store_vol0abc /dev/sdf /dev/xvdf;"));
+        assert!(script.contains("sudo mount \"$dev\" '/data'"));
+    }
+
+    #[test]
+    fn burstable() {
+        assert!(is_burstable("t3.small"));
+        assert!(is_burstable("t4g.nano"));
+        assert!(is_burstable("t2.micro"));
+        assert!(!is_burstable("c5.xlarge"));
+        assert!(!is_burstable("trn1.2xlarge"));
+        assert!(!is_burstable("té.small"));
+        assert!(!is_burstable("t"));
+    }
+
+    #[test]
+    fn request_groups() {
+        let machines = vec![
+            ("c".to_string(), Setup::default()),
+            (
+                "b".to_string(),
+                Setup::default().cpu_credits(CpuCredits::Unlimited),
+            ),
+            (
+                "a".to_string(),
+                Setup::default().attach_volume(Volume::Existing("vol-0abc".into())),
+            ),
+        ];
+        let groups = |request_size| -> Vec<_> {
+            RegionLauncher::for_each_machine_group(machines.clone(), request_size)
+                .map(|(g, reqs)| {
+                    let names: Vec<_> = reqs.into_iter().map(|(name, _)| name).collect();
+                    (g.cpu_credits, names)
+                })
+                .collect()
+        };
+        assert_eq!(
+            groups(None),
+            vec![
+                (None, vec!["a".to_string(), "c".to_string()]),
+                (Some(CpuCredits::Unlimited), vec!["b".to_string()]),
+            ]
+        );
+        assert_eq!(
+            groups(Some(1)),
+            vec![
+                (None, vec!["a".to_string()]),
+                (None, vec!["c".to_string()]),
+                (Some(CpuCredits::Unlimited), vec!["b".to_string()]),
+            ]
+        );
+
+        let arn = "arn:aws:iam::123456789012:instance-profile/s3-access";
+        let profile = instance_profile(arn);
+        assert_eq!(profile.arn.as_deref(), Some(arn));
+        assert_eq!(profile.name, None);
+        assert_eq!(
+            instance_profile("s3-access").name.as_deref(),
+            Some("s3-access")
+        );
+        assert_ne!(
+            RequestGroup::of(&Setup::default().instance_profile("s3-access")),
+            RequestGroup::of(&Setup::default())
+        );
+    }
+
+    #[test]
+    fn spot_prices() {
+        let mut l = Launcher::default();
+        l.spot_price(0.5);
+        let price = |m| l.with_defaults(m).map(|m| RequestGroup::of(&m).spot_price);
+        assert_eq!(price(Setup::default()).unwrap().as_deref(), Some("0.5"));
+        assert_eq!(
+            price(Setup::default().spot_price(0.25)).unwrap().as_deref(),
+            Some("0.25")
+        );
+        assert!(price(Setup::default().spot_price(0.0)).is_err());
+        assert!(price(Setup::default().spot_price(-1.0)).is_err());
+        assert!(price(Setup::default().spot_price(f64::NAN)).is_err());
+        assert!(price(Setup::default().spot_price(f64::INFINITY)).is_err());
+
+        l.spot_price(f64::NAN);
+        assert!(l.with_defaults(Setup::default()).is_err());
+    }
+
+    #[test]
+    fn launch_batches() {
+        let machines: Vec<_> = (0..5)
+            .map(|i| (format!("m{}", i), Setup::default()))
+            .collect();
+        let sizes = |size| -> Vec<usize> {
+            batches(machines.clone(), size)
+                .iter()
+                .map(Vec::len)
+                .collect()
+        };
+        assert_eq!(sizes(None), vec![5]);
+        assert_eq!(sizes(Some(2)), vec![2, 2, 1]);
+        assert!(batches(Vec::new(), Some(2)).is_empty());
+        assert_eq!(Setup::default().priority, Priority::Critical);
+
+        let ms = hybrid(5, "m", 2, Setup::default().priority(Priority::BestEffort));
+        let kinds: Vec<_> = ms
+            .iter()
+            .map(|(name, m)| (name.as_str(), m.on_demand, m.priority))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("m-0", true, Priority::Critical),
+                ("m-1", true, Priority::Critical),
+                ("m-2", false, Priority::BestEffort),
+                ("m-3", false, Priority::BestEffort),
+                ("m-4", false, Priority::BestEffort),
+            ]
+        );
+    }
+
+    #[test]
+    fn image_specs() {
+        use crate::image::ImageSpec;
+        let (owner, name) = ami_query(&ImageSpec::Ubuntu2204, "x86_64").unwrap();
+        assert_eq!(owner, "099720109477");
+        assert!(name.contains("jammy-22.04-amd64"));
+        let (_, name) = ami_query(&ImageSpec::Ubuntu2204, "arm64").unwrap();
+        assert!(name.contains("jammy-22.04-arm64"));
+        let (_, name) = ami_query(&ImageSpec::Debian11, "arm64").unwrap();
+        assert_eq!(name, "debian-11-arm64-*");
+        assert_eq!(ubuntu_release(&ImageSpec::Ubuntu2204), Some("jammy"));
+        assert_eq!(ubuntu_release(&ImageSpec::Debian11), None);
+        assert_eq!(
+            ami_query(&ImageSpec::CustomByName(String::from("golden-*")), "arm64"),
+            Some(("self", String::from("golden-*")))
+        );
+        assert_eq!(
+            ami_query(
+                &ImageSpec::ProviderSpecific(String::from("ami-0")),
+                "x86_64"
+            ),
+            None
+        );
+
+        let s = Setup::default().image_spec(ImageSpec::Debian11);
+        assert_eq!(s.username, "admin");
+        assert_eq!(s.os, Some(crate::OsFamily::Debian));
+        let s = s.ami("ami-0", "me");
+        assert_eq!(s.image, None);
+        assert_eq!(s.username, "me");
+        let s = s.os(crate::OsFamily::AmazonLinux);
+        assert_eq!(s.username, "ec2-user");
+        let s = s.username("lab");
+        assert_eq!(s.username, "lab");
+        assert_eq!(s.os, Some(crate::OsFamily::AmazonLinux));
+    }
+
+    #[test]
+    fn instance_architectures() {
+        for t in &[
+            "t4g.small",
+            "c6g.large",
+            "c6gn.16xlarge",
+            "m7gd.xlarge",
+            "im4gn.large",
+            "g5g.xlarge",
+            "a1.medium",
+        ] {
+            assert_eq!(architecture(t), "arm64", "{}", t);
+        }
+        for t in &[
+            "t3.small",
+            "c5n.18xlarge",
+            "g4dn.xlarge",
+            "m7i-flex.large",
+            "i3en.large",
+            "p4d.24xlarge",
+        ] {
+            assert_eq!(architecture(t), "x86_64", "{}", t);
+        }
+        assert_eq!(debian_arch("x86_64"), "amd64");
+        assert_eq!(debian_arch("arm64"), "arm64");
+    }
+
     #[test]
     #[ignore]
     fn make_key() -> Result<(), Report> {
@@ -1761,8 +8341,10 @@ mod test {
             assert!(!ec2.ssh_key_name.is_empty());
             assert!(ec2.private_key_path.as_ref().unwrap().path().exists());
 
-            let mut req = rusoto_ec2::DeleteKeyPairRequest::default();
-            req.key_name = Some(ec2.ssh_key_name.clone());
+            let req = rusoto_ec2::DeleteKeyPairRequest {
+                key_name: Some(ec2.ssh_key_name.clone()),
+                ..Default::default()
+            };
             ec2.client
                 .as_mut()
                 .unwrap()
@@ -1777,22 +8359,18 @@ mod test {
         })
     }
 
-    fn do_multi_instance_spot_request<'l>(
-        ec2: &'l mut super::RegionLauncher,
-    ) -> impl Future<Output = Result<(), Report>> + 'l {
-        async move {
-            let names = (1..).map(|x| format!("{}", x));
-            let setup = Setup::default();
-            let ms: Vec<(String, Setup)> = names.zip(itertools::repeat_n(setup, 5)).collect();
+    async fn do_multi_instance_spot_request(ec2: &mut super::RegionLauncher) -> Result<(), Report> {
+        let names = (1..).map(|x| format!("{}", x));
+        let setup = Setup::default();
+        let ms: Vec<(String, Setup)> = names.zip(itertools::repeat_n(setup, 5)).collect();
 
-            tracing::debug!(num = %ms.len(), "make spot instance requests");
-            ec2.make_spot_instance_requests(60 as _, ms).await?;
-            assert_eq!(ec2.spot_requests.len(), 5);
-            tracing::debug!("wait for spot instance requests");
-            ec2.wait_for_spot_instance_requests(None).await?;
+        tracing::debug!(num = %ms.len(), "make spot instance requests");
+        ec2.make_spot_instance_requests(60 as _, ms).await?;
+        assert_eq!(ec2.spot_requests.len(), 5);
+        tracing::debug!("wait for spot instance requests");
+        ec2.wait_for_spot_instance_requests(None).await?;
 
-            Ok(())
-        }
+        Ok(())
     }
 
     #[test]
@@ -1809,7 +8387,7 @@ mod test {
 
             if let Err(e) = do_multi_instance_spot_request(&mut ec2).await {
                 ec2.terminate_all().await.unwrap();
-                panic!(e);
+                panic!("{}", e);
             } else {
                 ec2.terminate_all().await.unwrap();
             }
@@ -1817,4 +8395,13 @@ mod test {
             Ok(())
         })
     }
+
+    #[test]
+    fn shutdown_scripts() {
+        assert_eq!(
+            shutdown_script(time::Duration::from_secs(3600)),
+            "#!/bin/sh\nshutdown -P +60 2>/dev/null || shutdown -p +60\n"
+        );
+        assert!(shutdown_script(time::Duration::from_secs(61)).contains("-P +2 "));
+    }
 }
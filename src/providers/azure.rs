@@ -11,10 +11,18 @@
 //! You can find such resource groups using:
 //! `az group list`.
 //!
-//! To use this provider, you must [install the Azure
-//! CLI](https://docs.microsoft.com/en-us/cli/azure/install-azure-cli?view=azure-cli-latest),
-//! and the `az` command must be in your `$PATH`.
-//! Also, you must run `az login` to authenticate with Microsoft.
+//! To scale down, [`Launcher::terminate_gracefully`] deletes a single machine and the resources that were
+//! created for it, and leaves the rest of the resource group running.
+//!
+//! The network security group of each region admits all inbound traffic. Unlike on EC2, there is
+//! no way to tighten it once the machines are set up.
+//!
+//! This provider talks to the Azure Resource Manager API directly. To authenticate as a service
+//! principal, set `AZURE_TENANT_ID`, `AZURE_CLIENT_ID`, `AZURE_CLIENT_SECRET`, and
+//! `AZURE_SUBSCRIPTION_ID`. Otherwise, the login of the [Azure
+//! CLI](https://docs.microsoft.com/en-us/cli/azure/install-azure-cli?view=azure-cli-latest) is
+//! used, so run `az login` first; `AZURE_SUBSCRIPTION_ID` then picks a subscription other than
+//! the CLI's default. Each region gets a fresh SSH key, generated with the local `ssh-keygen`.
 //!
 //! # Example
 //! ```rust,no_run
@@ -90,18 +98,34 @@
 //! }
 //! ```
 
+use super::MachineState;
 use color_eyre::{
     eyre::{self, eyre, WrapErr},
     Help, Report,
 };
 use educe::Educe;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
 use tracing::instrument;
 use tracing_futures::Instrument;
 
+/// A curated set of regions that together cover every continent Azure operates in.
+///
+/// Use with [`Setup::in_each_region`] for geo-distributed measurements.
+pub const GLOBAL_COVERAGE: &[Region] = &[
+    Region::EastUs,
+    Region::WestUs2,
+    Region::BrazilSouth,
+    Region::WestEurope,
+    Region::SouthAfricaNorth,
+    Region::CentralIndia,
+    Region::JapanEast,
+    Region::SouthEastAsia,
+    Region::AustraliaEast,
+];
+
 /// A descriptor for a single Azure VM type.
 ///
 /// The default is an UbuntuLTS, Standard_DS1_V2 VM in the East US region.
@@ -111,7 +135,10 @@ pub struct Setup {
     region: Region,
     instance_type: String,
     image: String,
+    image_spec: Option<crate::image::ImageSpec>,
     username: String,
+    os: Option<crate::OsFamily>,
+    near: Option<Affinity>,
     #[educe(Debug(ignore))]
     setup_fn: Option<
         Arc<
@@ -132,7 +159,10 @@ impl Default for Setup {
             region: "eastus".parse().unwrap(),
             instance_type: "Standard_B1s".to_string(),
             image: "UbuntuLTS".to_string(),
+            image_spec: None,
             username: "ubuntu".to_string(),
+            os: Some(crate::OsFamily::Ubuntu),
+            near: None,
             setup_fn: None,
         }
     }
@@ -153,6 +183,24 @@ impl Setup {
         self
     }
 
+    /// Make one copy of this descriptor for each of `regions`.
+    ///
+    /// Each machine is nicknamed after its region. [`GLOBAL_COVERAGE`] is a reasonable set of
+    /// regions for geo-distributed measurements.
+    pub fn in_each_region(self, regions: impl IntoIterator<Item = Region>) -> Vec<(String, Self)> {
+        regions
+            .into_iter()
+            .map(|r| (r.to_string(), self.clone().region(r)))
+            .collect()
+    }
+
+    /// Apply a [`Placement`](crate::placement::Placement) computed by the placement planner.
+    ///
+    /// Azure machines are not pinned to availability zones, so only the region is used.
+    pub fn placed(self, p: &crate::placement::Placement) -> Result<Self, Report> {
+        Ok(self.region(p.region.parse()?))
+    }
+
     /// To view the available sizes in the relevant region, use:
     /// ```bash
     /// az vm list-sizes -l <region_name>
@@ -170,8 +218,45 @@ impl Setup {
     /// az vm image list
     /// ```
     /// shows the valid options.
+    ///
+    /// This clears any previously set [`Setup::os`].
     pub fn image(mut self, image: String) -> Self {
         self.image = image;
+        self.image_spec = None;
+        self.os = None;
+        self
+    }
+
+    /// Use the image described by `image`, which is resolved when the machine is launched.
+    ///
+    /// Images of a known operating system also set [`Setup::os`].
+    pub fn image_spec(mut self, image: crate::image::ImageSpec) -> Self {
+        self.os = image.os();
+        self.image_spec = Some(image);
+        self
+    }
+
+    /// Launch from an image in an Azure Compute Gallery (formerly Shared Image Gallery).
+    ///
+    /// With `version` set to `None`, the latest version of the image is used. Images published
+    /// with [`Launcher::publish_image`] can be launched this way.
+    ///
+    /// This clears any previously set [`Setup::os`].
+    pub fn gallery_image(self, image: &GalleryImage, version: Option<&str>) -> Self {
+        let id = match version {
+            Some(v) => image.version(v),
+            None => image.id(),
+        };
+        self.image(id)
+    }
+
+    /// Declare which operating system family the image runs.
+    ///
+    /// Azure creates the admin user from [`Setup::username`], so unlike on AWS this does not
+    /// change the username. The family is available to setup functions as
+    /// [`crate::Machine::os`].
+    pub fn os(mut self, os: crate::OsFamily) -> Self {
+        self.os = Some(os);
         self
     }
 
@@ -181,6 +266,21 @@ impl Setup {
         self
     }
 
+    /// Launch the VM into the same subnet as `affinity`, an existing resource in the VM's region,
+    /// such as a long-lived database server, rather than into the region's own virtual network.
+    ///
+    /// The VM's network interface gets tsunami's network security group, so it is reachable over
+    /// SSH as long as the subnet's own security group lets the traffic through.
+    ///
+    /// ```rust
+    /// use tsunami::providers::azure::{Affinity, Setup};
+    /// let m = Setup::default().near(Affinity::tagged("role", "experiment-db"));
+    /// ```
+    pub fn near(mut self, affinity: Affinity) -> Self {
+        self.near = Some(affinity);
+        self
+    }
+
     /// The provided callback, `setup`, is called once for every spawned instances of this type with a handle
     /// to the target machine. Use [`crate::Machine::ssh`] to issue
     /// commands on the host in question.
@@ -214,25 +314,100 @@ impl Setup {
         self.setup_fn = Some(Arc::new(setup));
         self
     }
+
+    /// Check that the machine matches `expect` once it is up, before running the
+    /// [`setup`](Setup::setup) function.
+    ///
+    /// See the [`verify`](crate::verify) module for what happens when the check fails, and for
+    /// when to call this.
+    pub fn verify(mut self, expect: crate::verify::Expectations) -> Self {
+        self.setup_fn = Some(super::before_setup(
+            expect.into_setup_fn(),
+            self.setup_fn.take(),
+        ));
+        self
+    }
 }
 
 /// Launcher type for the Microsoft Azure cloud.
 ///
 /// This is a lower-level API. Most users will use [`crate::TsunamiBuilder::spawn`].
 ///
-/// This implementation relies on the [Azure
-/// CLI](https://docs.microsoft.com/en-us/cli/azure/install-azure-cli?view=azure-cli-latest).
-/// It also assumes you have previously run `az login` to authenticate.
-/// The Azure CLI will generate `~/.ssh/id_rsa.pub` if it does not exist, and use it to
-/// authenticate to the machine. This file won't automatically be deleted if Azure created it.
+/// See the [module documentation](self) for how to authenticate.
 ///
-/// While the regions are initialized serially, the setup functions for each machine are executed
-/// in parallel (within each region).
+/// Regions are initialized and launched concurrently, and the setup functions for each machine
+/// are executed in parallel within each region.
 #[derive(Debug, Default)]
 pub struct Launcher {
+    max_experiment_duration: Option<std::time::Duration>,
+    all_or_nothing: bool,
     regions: HashMap<Region, RegionLauncher>,
 }
 
+impl Launcher {
+    /// Give each VM an [auto-shutdown
+    /// policy](https://learn.microsoft.com/en-us/azure/virtual-machines/auto-shutdown-vm) that
+    /// deallocates it once `d` has passed since it was created.
+    ///
+    /// This is a safety net in case the controller crashes and never gets to
+    /// [`terminate_all`](super::Launcher::terminate_all). A deallocated VM no longer costs
+    /// compute time, but its disk and public IP still cost money until the resource group is
+    /// deleted. Azure only supports daily shutdown times, so `d` must be shorter than 24 hours,
+    /// or launching fails; the VM shuts down again at the same time every day if it is started
+    /// again. The time is rounded up to the minute.
+    pub fn max_experiment_duration(&mut self, d: std::time::Duration) -> &mut Self {
+        self.max_experiment_duration = Some(d);
+        self
+    }
+
+    /// Either launch every machine of a `launch` or `spawn`, or none of them.
+    ///
+    /// Azure has no way to allocate a set of individual VMs atomically, so with
+    /// `all_or_nothing(true)`, a failed launch instead deletes every VM it created, in all regions,
+    /// before returning the error. Use this for experiments that need exactly the requested
+    /// number of machines. By default, the machines that came up stay running.
+    ///
+    /// Machines from earlier, successful launches are not affected.
+    pub fn all_or_nothing(&mut self, enabled: bool) -> &mut Self {
+        self.all_or_nothing = enabled;
+        self
+    }
+
+    /// Wind down the machine `nickname` and delete it, and leave all other machines running.
+    ///
+    /// Use this to scale an experiment down. See [`RegionLauncher::terminate_gracefully`].
+    pub async fn terminate_gracefully(
+        &mut self,
+        nickname: &str,
+        grace: std::time::Duration,
+    ) -> Result<(), Report> {
+        let r = self
+            .regions
+            .values_mut()
+            .find(|r| r.has_machine(nickname))
+            .ok_or_else(|| eyre!("no machine named {}", nickname))?;
+        r.terminate_gracefully(nickname, grace).await
+    }
+
+    /// Publish the machine `nickname` as version `version` of the gallery image `image`, for
+    /// later launches with [`Setup::gallery_image`].
+    ///
+    /// This consumes the machine. See [`RegionLauncher::publish_image`].
+    pub async fn publish_image(
+        &mut self,
+        nickname: &str,
+        image: &GalleryImage,
+        version: &str,
+    ) -> Result<String, Report> {
+        let r = self
+            .regions
+            .values_mut()
+            .find(|r| r.has_machine(nickname))
+            .ok_or_else(|| eyre!("no machine named {}", nickname))?;
+        r.publish_image(nickname, image, version).await
+    }
+}
+
 impl super::Launcher for Launcher {
     type MachineDescriptor = Setup;
 
@@ -243,8 +418,6 @@ impl super::Launcher for Launcher {
     ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
         Box::pin(
             async move {
-                azcmd::check_az().await?;
-
                 use std::collections::hash_map::Entry;
                 let mut region = self.regions.entry(l.region);
                 let region = match region {
@@ -257,6 +430,8 @@ impl super::Launcher for Launcher {
                         v.insert(az_region)
                     }
                 };
+                region.max_experiment_duration = self.max_experiment_duration;
+                region.all_or_nothing = self.all_or_nothing;
 
                 let region_span = tracing::debug_span!("region", region = %l.region);
                 region.launch(l).instrument(region_span).await?;
@@ -266,6 +441,66 @@ impl super::Launcher for Launcher {
         )
     }
 
+    #[instrument(level = "debug", skip(self, max_wait))]
+    fn spawn<'l, I>(
+        &'l mut self,
+        descriptors: I,
+        max_wait: Option<std::time::Duration>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>>
+    where
+        I: IntoIterator<Item = (String, Self::MachineDescriptor)> + Send + 'static,
+        I: std::fmt::Debug,
+        I::IntoIter: Send,
+    {
+        Box::pin(
+            async move {
+                tracing::info!("spinning up tsunami");
+                arm::Client::default().check().await?;
+
+                let max_experiment_duration = self.max_experiment_duration;
+                let all_or_nothing = self.all_or_nothing;
+                let plan = super::plan_descriptors(descriptors, max_wait)?;
+                let names: HashSet<_> = plan
+                    .iter()
+                    .flat_map(|d| d.machines.iter().map(|(name, _)| name.clone()))
+                    .collect();
+                let res = super::spawn_regions(
+                    &mut self.regions,
+                    plan,
+                    RegionLauncher::new,
+                    |mut region_launcher, d| async move {
+                        region_launcher.max_experiment_duration = max_experiment_duration;
+                        region_launcher.all_or_nothing = all_or_nothing;
+                        let res = super::Launcher::launch(&mut region_launcher, d).await;
+                        (region_launcher, res)
+                    },
+                )
+                .await;
+
+                match res {
+                    // the region that failed has already cleaned up after itself, and said so in
+                    // its error, but the other regions have not.
+                    Err(e) if all_or_nothing => {
+                        let names = &names;
+                        let rollback = futures_util::future::join_all(
+                            self.regions.values_mut().map(|r| r.roll_back(names)),
+                        )
+                        .await
+                        .into_iter()
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(drop);
+                        Err(match rollback {
+                            Ok(()) => e,
+                            rollback => super::rolled_back(e, rollback),
+                        })
+                    }
+                    r => r,
+                }
+            }
+            .in_current_span(),
+        )
+    }
+
     #[instrument(level = "debug")]
     fn connect_all<'l>(
         &'l self,
@@ -275,6 +510,41 @@ impl super::Launcher for Launcher {
         Box::pin(async move { collect!(self.regions) }.in_current_span())
     }
 
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        let mut machines = HashMap::new();
+        for r in self.regions.values() {
+            machines.extend(r.describe_all()?);
+        }
+        Ok(machines)
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        Ok(self
+            .regions
+            .values()
+            .flat_map(|r| &r.machines)
+            .filter_map(|d| Some((d.name.clone(), d.setup_fn.clone()?)))
+            .collect())
+    }
+
+    #[instrument(level = "debug")]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<Box<dyn Future<Output = Result<HashMap<String, MachineState>, Report>> + Send + 'l>>
+    {
+        Box::pin(
+            async move {
+                let states =
+                    futures_util::future::join_all(self.regions.values().map(|r| r.status()))
+                        .await
+                        .into_iter()
+                        .collect::<Result<Vec<_>, Report>>()?;
+                Ok(states.into_iter().flatten().collect())
+            }
+            .in_current_span(),
+        )
+    }
+
     #[instrument(level = "debug")]
     fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
         Box::pin(
@@ -293,48 +563,229 @@ impl super::Launcher for Launcher {
 
 #[derive(Debug, Clone)]
 pub(crate) struct IpInfo {
+    public_dns: Option<String>,
     public_ip: String,
     private_ip: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone, Educe)]
+#[educe(Debug)]
 struct Descriptor {
     name: String,
+    vm_name: String,
     username: String,
+    os: Option<crate::OsFamily>,
     ip: IpInfo,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<super::SetupFn>,
+}
+
+impl Descriptor {
+    fn machine<'l>(&self) -> crate::MachineDescriptor<'l> {
+        crate::MachineDescriptor {
+            nickname: self.name.clone(),
+            public_dns: self.ip.public_dns.clone(),
+            public_ip: self.ip.public_ip.clone(),
+            private_ip: Some(self.ip.private_ip.clone()),
+            os: self.os,
+            proxy_command: None,
+            check_host_key: true,
+            _tsunami: Default::default(),
+        }
+    }
 }
 
 /// Region-specific connection to Azure.
 ///
-/// Each instance of this type creates one Azure
-/// "resource group" and deletes the group on `terminate_all()`. See also [`Launcher`].
-///
-/// This implementation relies on the [Azure
-/// CLI](https://docs.microsoft.com/en-us/cli/azure/install-azure-cli?view=azure-cli-latest).
-/// It also assumes you have previously run `az login` to authenticate with Microsoft.
-/// The Azure CLI will generate `~/.ssh/id_rsa.pub` if it does not exist, and use it to
-/// authenticate to the machine. This file won't automatically be deleted if Azure created it.
+/// Each instance of this type creates one Azure "resource group", with a virtual network and an
+/// SSH key shared by its machines, and deletes the group on `terminate_all()`. See also
+/// [`Launcher`].
 #[derive(Debug, Default)]
 pub struct RegionLauncher {
     /// The region this [`RegionLauncher`] is connected to.
     pub region: Region,
+    client: arm::Client,
     resource_group_name: String,
+    subnet_id: String,
+    key: Option<(tempfile::TempDir, std::path::PathBuf)>,
+    public_key: String,
+    max_experiment_duration: Option<std::time::Duration>,
+    all_or_nothing: bool,
     machines: Vec<Descriptor>,
 }
 
 impl RegionLauncher {
     /// Create a new instance of RegionLauncher.
     pub async fn new(region: Region) -> Result<Self, Report> {
-        let rg_name = super::rand_name("resourcegroup");
+        let client = arm::Client::default();
+        client.check().await?;
 
-        azcmd::create_resource_group(region, &rg_name).await?;
+        let (key_dir, key, public_key) = super::generate_key().await?;
+
+        let rg_name = super::rand_name("resourcegroup");
+        client.create_resource_group(region, &rg_name).await?;
+        let subnet_id = client.create_network(region, &rg_name).await?;
 
         Ok(Self {
             region,
+            client,
             resource_group_name: rg_name,
+            subnet_id,
+            key: Some((key_dir, key)),
+            public_key,
+            max_experiment_duration: None,
+            all_or_nothing: false,
             machines: vec![],
         })
     }
+
+    /// Deallocate each VM once `d` has passed since it was created. See
+    /// [`Launcher::max_experiment_duration`].
+    pub fn max_experiment_duration(&mut self, d: std::time::Duration) -> &mut Self {
+        self.max_experiment_duration = Some(d);
+        self
+    }
+
+    /// Either launch every machine of a `launch`, or none of them. See
+    /// [`Launcher::all_or_nothing`].
+    pub fn all_or_nothing(&mut self, enabled: bool) -> &mut Self {
+        self.all_or_nothing = enabled;
+        self
+    }
+
+    /// The path to the private key used to log into this region's machines.
+    pub fn private_key_path(&self) -> Option<&std::path::Path> {
+        self.key.as_ref().map(|(_, k)| k.as_path())
+    }
+
+    /// Publish the machine `nickname` as version `version` of the gallery image `image`, and
+    /// return the version's resource id.
+    ///
+    /// The machine is deprovisioned with `waagent`, generalized, captured, and then deleted, so it
+    /// cannot be used afterwards. The gallery image definition must be for generalized Linux
+    /// images, and be in this region.
+    #[instrument(level = "debug", skip(self), fields(region = %self.region))]
+    pub async fn publish_image(
+        &mut self,
+        nickname: &str,
+        image: &GalleryImage,
+        version: &str,
+    ) -> Result<String, Report> {
+        let i = self
+            .machines
+            .iter()
+            .position(|d| d.name == nickname)
+            .ok_or_else(|| eyre!("no machine named {}", nickname))?;
+        let desc = &self.machines[i];
+
+        let m = desc
+            .machine()
+            .connect_ssh(&desc.username, self.private_key_path(), None, 22)
+            .await?;
+        // keeps the user and its home directory, but removes the host keys, the ssh key, and
+        // other machine-specific state.
+        let out = m
+            .ssh
+            .shell("sudo waagent -deprovision -force")
+            .output()
+            .await
+            .wrap_err("failed to run waagent")?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to deprovision {}: {}",
+            nickname,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+        let _ = m.ssh.close().await;
+
+        let id = self
+            .client
+            .publish_image_version(
+                self.region,
+                &self.resource_group_name,
+                &desc.vm_name,
+                &image.id(),
+                version,
+            )
+            .await
+            .wrap_err_with(|| format!("failed to publish {}", nickname))?;
+        tracing::info!(%id, "published image");
+        self.delete_machine(nickname).await?;
+        Ok(id)
+    }
+
+    /// Whether this region launched the machine `nickname`.
+    pub fn has_machine(&self, nickname: &str) -> bool {
+        self.machines.iter().any(|d| d.name == nickname)
+    }
+
+    /// Wind down the machine `nickname`, and then delete it along with its network interface,
+    /// public IP, disk, and auto-shutdown schedule.
+    ///
+    /// The machine's file systems are synced first, so that background processes get to write out
+    /// their results; if that fails or takes longer than `grace`, the machine is deleted anyway.
+    /// The rest of the region, including its other machines, keeps running.
+    #[instrument(level = "debug", skip(self), fields(region = %self.region))]
+    pub async fn terminate_gracefully(
+        &mut self,
+        nickname: &str,
+        grace: std::time::Duration,
+    ) -> Result<(), Report> {
+        let desc = self
+            .machines
+            .iter()
+            .find(|d| d.name == nickname)
+            .ok_or_else(|| eyre!("no machine named {}", nickname))?;
+        let drain = async {
+            let m = desc
+                .machine()
+                .connect_ssh(&desc.username, self.private_key_path(), None, 22)
+                .await?;
+            let status = m
+                .ssh
+                .command("sync")
+                .status()
+                .await
+                .wrap_err("failed to run sync")?;
+            eyre::ensure!(status.success(), "failed to sync file systems");
+            Ok::<_, Report>(())
+        };
+        match tokio::time::timeout(grace, drain).await {
+            Ok(Ok(())) => tracing::debug!("machine wound down"),
+            Ok(Err(e)) => tracing::warn!("deleting machine that did not wind down: {:?}", e),
+            Err(_) => tracing::warn!(?grace, "deleting machine that is still winding down"),
+        }
+        self.delete_machine(nickname).await
+    }
+
+    async fn delete_machine(&mut self, nickname: &str) -> Result<(), Report> {
+        let i = self
+            .machines
+            .iter()
+            .position(|d| d.name == nickname)
+            .ok_or_else(|| eyre!("no machine named {}", nickname))?;
+        self.client
+            .delete_vm(&self.resource_group_name, &self.machines[i].vm_name)
+            .await
+            .wrap_err_with(|| format!("failed to terminate {}", nickname))?;
+        self.machines.remove(i);
+        tracing::info!("machine terminated");
+        Ok(())
+    }
+
+    // Deletes the machines in `names` that this region launched.
+    async fn roll_back(&mut self, names: &HashSet<String>) -> Result<(), Report> {
+        let names: Vec<_> = self
+            .machines
+            .iter()
+            .filter(|d| names.contains(&d.name))
+            .map(|d| d.name.clone())
+            .collect();
+        for name in names {
+            self.delete_machine(&name).await?;
+        }
+        Ok(())
+    }
 }
 
 impl super::Launcher for RegionLauncher {
@@ -347,57 +798,145 @@ impl super::Launcher for RegionLauncher {
     ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
         Box::pin(
             async move {
+                if let Some(d) = self.max_experiment_duration {
+                    eyre::ensure!(
+                        d < arm::MAX_SHUTDOWN_DELAY,
+                        "max_experiment_duration must be shorter than 24 hours on Azure, since auto-shutdown schedules are daily (got {:?})",
+                        d
+                    );
+                }
+
+                // machines near the same resource share a subnet, so look each one up only once.
+                let mut subnets = HashMap::new();
+                for (_, desc) in &l.machines {
+                    if let Some(ref near) = desc.near {
+                        if !subnets.contains_key(near) {
+                            let subnet = self.client.resolve_subnet(self.region, near).await?;
+                            subnets.insert(near.clone(), subnet);
+                        }
+                    }
+                }
+
                 let max_wait = l.max_wait;
-                self.machines = futures_util::future::join_all(l.machines.into_iter().map(
+                let subnets = &subnets;
+                let launched = futures_util::future::join_all(l.machines.into_iter().map(
                     |(nickname, desc)| {
                         let machine_span = tracing::debug_span!("machine", %nickname, ?desc);
-                        async {
-                            let vm_name = super::rand_name_sep("vm", "-");
+                        let vm_name = super::rand_name_sep("vm", "-");
+                        let vm = vm_name.clone();
+                        let launched = async {
                             tracing::debug!(%vm_name, "setting up instance");
+                            super::report_progress(&nickname, MachineState::Booting);
 
-                            let ipinfo = azcmd::create_vm(
-                                &self.resource_group_name,
-                                &vm_name,
-                                &desc.instance_type,
-                                &desc.image,
-                                &desc.username,
-                            )
-                            .await?;
-                            azcmd::open_ports(&self.resource_group_name, &vm_name).await?;
+                            let image = match desc.image_spec {
+                                Some(ref spec) => self.client.resolve_image(spec).await?,
+                                None => desc.image.clone(),
+                            };
+                            let subnet = match desc.near {
+                                Some(ref near) => subnets[near].clone(),
+                                None => self.subnet_id.clone(),
+                            };
+                            let ipinfo = self
+                                .client
+                                .create_vm(
+                                    self.region,
+                                    &self.resource_group_name,
+                                    &subnet,
+                                    &vm_name,
+                                    &desc.instance_type,
+                                    &image,
+                                    &desc.username,
+                                    &self.public_key,
+                                    max_wait,
+                                )
+                                .await?;
+
+                            if let Some(d) = self.max_experiment_duration {
+                                self.client
+                                    .schedule_shutdown(
+                                        self.region,
+                                        &self.resource_group_name,
+                                        &vm_name,
+                                        std::time::SystemTime::now() + d,
+                                    )
+                                    .await
+                                    .wrap_err("failed to set up auto-shutdown")?;
+                            }
 
                             if let Setup {
                                 ref username,
+                                os,
                                 setup_fn: Some(ref f),
                                 ..
                             } = desc
                             {
+                                let m = crate::MachineDescriptor {
+                                    nickname: Default::default(),
+                                    public_dns: ipinfo.public_dns.clone(),
+                                    public_ip: ipinfo.public_ip.clone(),
+                                    private_ip: Some(ipinfo.private_ip.clone()),
+                                    os,
+                                    proxy_command: None,
+                                    check_host_key: true,
+                                    _tsunami: Default::default(),
+                                };
                                 super::setup_machine(
                                     &nickname,
-                                    None,
-                                    &ipinfo.public_ip,
-                                    Some(&ipinfo.private_ip),
-                                    &username,
+                                    m,
+                                    username,
                                     max_wait,
-                                    None,
+                                    self.private_key_path(),
                                     f.as_ref(),
                                 )
                                 .await?;
+                            } else {
+                                super::report_progress(&nickname, MachineState::Ready);
                             }
 
                             Ok::<_, Report>(Descriptor {
                                 name: nickname,
+                                vm_name,
                                 username: desc.username,
+                                os: desc.os,
                                 ip: ipinfo,
+                                setup_fn: desc.setup_fn,
                             })
                         }
-                        .instrument(machine_span)
+                        .instrument(machine_span);
+                        async move { (vm, launched.await) }
                     },
                 ))
-                .await
-                .into_iter()
-                .collect::<Result<Vec<_>, Report>>()?;
+                .await;
 
-                Ok(())
+                let mut res = Ok(());
+                let mut vm_names = Vec::with_capacity(launched.len());
+                let mut machines = Vec::with_capacity(launched.len());
+                for (vm_name, r) in launched {
+                    vm_names.push(vm_name);
+                    match r {
+                        Ok(d) => machines.push(d),
+                        Err(e) if res.is_ok() => res = Err(e),
+                        Err(_) => {}
+                    }
+                }
+                match res {
+                    // delete the machines that failed too, since they may exist in part.
+                    Err(e) if self.all_or_nothing => {
+                        let rollback =
+                            futures_util::future::join_all(vm_names.iter().map(|vm_name| {
+                                self.client.delete_vm(&self.resource_group_name, vm_name)
+                            }))
+                            .await
+                            .into_iter()
+                            .collect::<Result<Vec<_>, _>>()
+                            .map(drop);
+                        Err(super::rolled_back(e, rollback))
+                    }
+                    res => {
+                        self.machines.extend(machines);
+                        res
+                    }
+                }
             }
             .in_current_span(),
         )
@@ -413,33 +952,80 @@ impl super::Launcher for RegionLauncher {
             async move {
                 futures_util::future::join_all(self.machines.iter().map(|desc| {
                     let machine_span = tracing::debug_span!("machine", name = %desc.name, ?desc);
+                    async move {
+                        let m = desc
+                            .machine()
+                            .connect_ssh(&desc.username, self.private_key_path(), None, 22)
+                            .await?;
+                        Ok::<_, Report>((desc.name.clone(), m))
+                    }
+                    .instrument(machine_span)
+                }))
+                .await
+                .into_iter()
+                .collect::<Result<HashMap<_, _>, Report>>()
+            }
+            .in_current_span(),
+        )
+    }
 
-                    let Descriptor {
-                        name,
-                        username,
-                        ip:
-                            IpInfo {
-                                public_ip,
-                                private_ip,
-                            },
-                    } = desc;
-                    let m = crate::MachineDescriptor {
-                        nickname: name.clone(),
-                        public_dns: None,
-                        public_ip: public_ip.clone(),
-                        private_ip: Some(private_ip.clone()),
-                        _tsunami: Default::default(),
-                    };
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        Ok(self
+            .machines
+            .iter()
+            .map(|desc| {
+                let m = desc
+                    .machine()
+                    .lazy(&desc.username, self.private_key_path(), 22);
+                (desc.name.clone(), m)
+            })
+            .collect())
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        Ok(self
+            .machines
+            .iter()
+            .filter_map(|d| Some((d.name.clone(), d.setup_fn.clone()?)))
+            .collect())
+    }
 
+    #[instrument(level = "debug")]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<Box<dyn Future<Output = Result<HashMap<String, MachineState>, Report>> + Send + 'l>>
+    {
+        Box::pin(
+            async move {
+                futures_util::future::join_all(self.machines.iter().map(|desc| {
+                    let machine_span = tracing::debug_span!("machine", name = %desc.name);
                     async move {
-                        let m = m.connect_ssh(username, None, None, 22).await?;
-                        Ok::<_, Report>((name.clone(), m))
+                        let power = self
+                            .client
+                            .power_state(&self.resource_group_name, &desc.vm_name)
+                            .await?;
+                        let state = match power.as_deref() {
+                            Some("PowerState/running") => {
+                                super::ssh_state(
+                                    &desc.ip.public_ip,
+                                    &desc.username,
+                                    self.private_key_path(),
+                                    22,
+                                    desc.machine().check_host_key,
+                                )
+                                .await
+                            }
+                            Some("PowerState/starting") => MachineState::Booting,
+                            // stopping, stopped, deallocating, deallocated, or gone entirely
+                            _ => MachineState::Terminated,
+                        };
+                        Ok::<_, Report>((desc.name.clone(), state))
                     }
                     .instrument(machine_span)
                 }))
                 .await
                 .into_iter()
-                .collect::<Result<HashMap<_, _>, Report>>()
+                .collect()
             }
             .in_current_span(),
         )
@@ -448,9 +1034,10 @@ impl super::Launcher for RegionLauncher {
     #[instrument(level = "debug")]
     fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
         let name = self.resource_group_name;
+        let client = self.client;
         Box::pin(
             async move {
-                azcmd::delete_resource_group(&name).await?;
+                client.delete_resource_group(&name).await?;
                 Ok(())
             }
             .in_current_span(),
@@ -462,8 +1049,9 @@ impl super::Launcher for RegionLauncher {
 ///
 /// See https://azure.microsoft.com/en-us/global-infrastructure/locations/ for more information.
 #[allow(missing_docs)]
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
 pub enum Region {
+    #[default]
     EastUs,
     EastUs2,
     WestUs,
@@ -497,12 +1085,6 @@ pub enum Region {
     GermanyWestCentral,
 }
 
-impl Default for Region {
-    fn default() -> Self {
-        Region::EastUs
-    }
-}
-
 impl AsRef<str> for Region {
     fn as_ref(&self) -> &str {
         match self {
@@ -587,129 +1169,1326 @@ impl std::str::FromStr for Region {
     }
 }
 
-mod azcmd {
-    use super::IpInfo;
-    use super::Region;
-    use super::*;
-    use serde::{Deserialize, Serialize};
-    use tokio::process::Command;
+/// An error returned by the Azure Resource Manager API.
+///
+/// Errors from the Azure provider can be downcast to this type to find out what went wrong, e.g.
+/// to retry in another region when the instance type is not available.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ApiError {
+    /// The HTTP status of the response.
+    pub status: u16,
+    /// Azure's error code, such as `QuotaExceeded` or `SkuNotAvailable`.
+    pub code: String,
+    /// The description of the error.
+    pub message: String,
+}
 
-    pub(crate) async fn check_az() -> Result<(), Report> {
-        eyre::ensure!(
-            Command::new("az").arg("account").arg("show").status().await.wrap_err("az account show")?.success(), 
-            "Azure CLI not found. See https://docs.microsoft.com/en-us/cli/azure/install-azure-cli?view=azure-cli-latest for installation, then run `az login`.",
-        );
-        Ok(())
+impl ApiError {
+    fn from_json(status: u16, body: &serde_json::Value) -> Self {
+        let e = &body["error"];
+        ApiError {
+            status,
+            code: e["code"].as_str().unwrap_or("Unknown").to_string(),
+            message: e["message"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| body.to_string()),
+        }
     }
 
-    #[instrument(level = "trace")]
-    pub(crate) async fn create_resource_group(r: Region, name: &str) -> Result<(), Report> {
-        let out = Command::new("az")
-            .args(&[
-                "group",
-                "create",
-                "--name",
-                name,
-                "--location",
-                &r.to_string(),
+    fn from_body(status: u16, body: &str) -> Self {
+        match serde_json::from_str(body) {
+            Ok(v) => Self::from_json(status, &v),
+            Err(_) => ApiError {
+                status,
+                code: String::from("Unknown"),
+                message: body.trim().to_string(),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.code, self.status, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// An existing Azure resource whose network new VMs should join. See [`Setup::near`].
+///
+/// Resources are given by their full resource id, as shown by `az resource show --query id`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Affinity {
+    /// The subnet of the VM with this id.
+    Vm(String),
+    /// The first subnet of the virtual network with this id.
+    VirtualNetwork(String),
+    /// The subnet with this id.
+    Subnet(String),
+    /// The VM or virtual network tagged `key=value`, looked for in that order.
+    Tagged {
+        /// The tag name.
+        key: String,
+        /// The tag value.
+        value: String,
+    },
+}
+
+impl Affinity {
+    /// The resource tagged `key=value`.
+    pub fn tagged(key: impl ToString, value: impl ToString) -> Self {
+        Affinity::Tagged {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// An image definition in an Azure Compute Gallery (formerly Shared Image Gallery).
+///
+/// The gallery and the image definition have to exist already, e.g. from
+/// `az sig create` and `az sig image-definition create`. See [`Setup::gallery_image`] and
+/// [`Launcher::publish_image`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GalleryImage {
+    subscription: Option<String>,
+    resource_group: String,
+    gallery: String,
+    image: String,
+}
+
+impl GalleryImage {
+    /// The image definition `image` in the gallery `gallery` of the resource group
+    /// `resource_group`.
+    pub fn new(
+        resource_group: impl ToString,
+        gallery: impl ToString,
+        image: impl ToString,
+    ) -> Self {
+        GalleryImage {
+            subscription: None,
+            resource_group: resource_group.to_string(),
+            gallery: gallery.to_string(),
+            image: image.to_string(),
+        }
+    }
+
+    /// Use a gallery in another subscription than the one machines are launched in.
+    pub fn subscription(mut self, subscription: impl ToString) -> Self {
+        self.subscription = Some(subscription.to_string());
+        self
+    }
+
+    /// The resource id of the image definition.
+    ///
+    /// Without [`GalleryImage::subscription`], the id starts at `/resourceGroups/`, and is
+    /// completed with the subscription machines are launched in.
+    pub fn id(&self) -> String {
+        let sub = self
+            .subscription
+            .as_ref()
+            .map(|s| format!("/subscriptions/{}", s))
+            .unwrap_or_default();
+        format!(
+            "{}/resourceGroups/{}/providers/Microsoft.Compute/galleries/{}/images/{}",
+            sub, self.resource_group, self.gallery, self.image
+        )
+    }
+
+    /// The resource id of the image version `version`, such as `1.0.0`.
+    pub fn version(&self, version: &str) -> String {
+        format!("{}/versions/{}", self.id(), version)
+    }
+}
+
+// The image name for the `ImageSpec`s that do not need a lookup.
+fn spec_image(spec: &crate::image::ImageSpec) -> Option<String> {
+    use crate::image::ImageSpec;
+    Some(match spec {
+        ImageSpec::Ubuntu2004 => {
+            String::from("Canonical:0001-com-ubuntu-server-focal:20_04-lts-gen2:latest")
+        }
+        ImageSpec::Ubuntu2204 => String::from("Ubuntu2204"),
+        ImageSpec::Debian11 => String::from("Debian11"),
+        ImageSpec::ProviderSpecific(image) => image.clone(),
+        _ => return None,
+    })
+}
+
+// Turns an image name into an ARM image reference. The names `az vm create --image` accepts are
+// a resource id, a `publisher:offer:sku:version` URN, or one of a few aliases.
+fn image_reference(image: &str) -> Result<serde_json::Value, Report> {
+    const ALIASES: &[(&str, &str)] = &[
+        ("UbuntuLTS", "Canonical:UbuntuServer:18.04-LTS:latest"),
+        (
+            "Ubuntu2204",
+            "Canonical:0001-com-ubuntu-server-jammy:22_04-lts-gen2:latest",
+        ),
+        ("Debian11", "Debian:debian-11:11-gen2:latest"),
+    ];
+
+    if image.starts_with('/') {
+        return Ok(serde_json::json!({ "id": image }));
+    }
+    let urn = ALIASES
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(image))
+        .map(|(_, urn)| *urn)
+        .unwrap_or(image);
+    match urn.split(':').collect::<Vec<_>>()[..] {
+        [publisher, offer, sku, version] => Ok(serde_json::json!({
+            "publisher": publisher,
+            "offer": offer,
+            "sku": sku,
+            "version": version,
+        })),
+        _ => Err(eyre!("unknown azure image {}", image)).suggestion(
+            "Use a publisher:offer:sku:version URN, an image resource id, \
+             or one of UbuntuLTS, Ubuntu2204, Debian11",
+        ),
+    }
+}
+
+// A client for the Azure Resource Manager REST API, and for Blob Storage.
+pub(crate) mod arm {
+    use super::{ApiError, IpInfo, Region};
+    use color_eyre::{
+        eyre::{self, WrapErr},
+        Help, Report,
+    };
+    use educe::Educe;
+    use reqwest::{Method, RequestBuilder, Response, StatusCode};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tracing::instrument;
+
+    const MANAGEMENT: &str = "https://management.azure.com";
+    // The resources that tokens are requested for.
+    const MANAGEMENT_RESOURCE: &str = "https://management.azure.com/";
+    const STORAGE_RESOURCE: &str = "https://storage.azure.com/";
+    // The API versions are pinned so that the shapes of requests and responses do not change
+    // underneath us.
+    const RESOURCES_API: &str = "2021-04-01";
+    const NETWORK_API: &str = "2021-05-01";
+    const COMPUTE_API: &str = "2021-11-01";
+    const DEVTESTLAB_API: &str = "2018-09-15";
+    const DISKS_API: &str = "2021-08-01";
+    const GALLERY_API: &str = "2021-10-01";
+    const STORAGE_API: &str = "2021-08-06";
+
+    // How long to wait for a long-running operation before giving up on it. Image captures are
+    // the slowest operations, and take a few minutes.
+    const OPERATION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+    // Auto-shutdown schedules are daily, so they cannot be more than a day ahead.
+    pub(super) const MAX_SHUTDOWN_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    // Whether the `location` of a resource is region `r`. Resources may give their location by
+    // display name, e.g. `East US` for `eastus`.
+    pub(super) fn in_region(location: &str, r: Region) -> bool {
+        location.replace(' ', "").eq_ignore_ascii_case(r.as_ref())
+    }
+
+    // Auto-shutdown schedules are named after the VM they shut down.
+    fn shutdown_schedule(rg: &str, vm_name: &str) -> String {
+        format!(
+            "/resourceGroups/{}/providers/microsoft.devtestlab/schedules/shutdown-computevm-{}",
+            rg, vm_name
+        )
+    }
+
+    // The UTC time of day of `at` as auto-shutdown schedules give it, e.g. `1830`, rounded up to
+    // the minute.
+    pub(super) fn shutdown_time(at: std::time::SystemTime) -> String {
+        let secs = at
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let minute = (secs + 59) / 60 % (24 * 60);
+        format!("{:02}{:02}", minute / 60, minute % 60)
+    }
+
+    fn is_not_found(e: &Report) -> bool {
+        matches!(e.downcast_ref::<ApiError>(), Some(e) if e.status == 404)
+    }
+
+    // Makes `path` start at the subscription, unless it does already.
+    fn absolute(subscription: &str, path: &str) -> String {
+        if path.starts_with("/subscriptions/") {
+            path.to_string()
+        } else {
+            format!("/subscriptions/{}{}", subscription, path)
+        }
+    }
+
+    // The path of the resource with id `id`, relative to its subscription.
+    pub(super) fn resource_path(id: &str) -> Option<&str> {
+        let rest = id.strip_prefix("/subscriptions/")?;
+        rest.find('/').map(|i| &rest[i..])
+    }
+
+    #[derive(Clone)]
+    struct Token {
+        // Only Resource Manager calls need a subscription.
+        subscription: Option<String>,
+        bearer: String,
+        expires: Instant,
+    }
+
+    #[derive(Clone, Default, Educe)]
+    #[educe(Debug)]
+    pub(crate) struct Client {
+        http: reqwest::Client,
+        #[educe(Debug(ignore))]
+        token: Arc<Mutex<Option<Token>>>,
+        #[educe(Debug(ignore))]
+        storage_token: Arc<Mutex<Option<Token>>>,
+    }
+
+    fn subscription_override() -> Option<String> {
+        std::env::var("AZURE_SUBSCRIPTION_ID").ok()
+    }
+
+    #[instrument(level = "trace", skip(http))]
+    async fn service_principal_token(
+        http: &reqwest::Client,
+        tenant: &str,
+        client_id: &str,
+        secret: &str,
+        resource: &str,
+    ) -> Result<Token, Report> {
+        let scope = format!("{}.default", resource);
+        let resp = http
+            .post(format!(
+                "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+                tenant
+            ))
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id),
+                ("client_secret", secret),
+                ("scope", scope.as_str()),
             ])
-            .status()
+            .send()
             .await
-            .context("az group create")?;
-
-        eyre::ensure!(out.success(), "failed to create resource group");
-        Ok(())
+            .wrap_err("failed to reach Azure AD")?;
+        let status = resp.status();
+        let body = resp.text().await?;
+        if !status.is_success() {
+            let v: Value = serde_json::from_str(&body).unwrap_or_default();
+            eyre::bail!(
+                "failed to authenticate with Azure AD: {}",
+                v["error_description"].as_str().unwrap_or(&body)
+            );
+        }
+        let v: Value = serde_json::from_str(&body).wrap_err("invalid Azure AD response")?;
+        Ok(Token {
+            subscription: subscription_override(),
+            bearer: v["access_token"]
+                .as_str()
+                .ok_or_else(|| eyre::eyre!("Azure AD returned no access token"))?
+                .to_string(),
+            expires: Instant::now() + Duration::from_secs(v["expires_in"].as_u64().unwrap_or(300)),
+        })
     }
 
     #[instrument(level = "trace")]
-    pub(crate) async fn create_vm(
-        rg: &str,
-        name: &str,
-        size: &str,
-        image: &str,
-        username: &str,
-    ) -> Result<IpInfo, Report> {
-        #[allow(non_snake_case)]
-        #[derive(Debug, Deserialize, Serialize)]
-        struct VmCreateOut {
-            powerState: String,
-            publicIpAddress: String,
-            privateIpAddress: String,
-            resourceGroup: String,
-        }
-
-        let out = Command::new("az")
-            .args(&[
-                "vm",
-                "create",
-                "--resource-group",
-                rg,
-                "--name",
-                name,
-                "--image",
-                image,
-                "--size",
-                size,
-                "--admin-username",
-                username,
-                "--generate-ssh-keys",
+    async fn cli_token(resource: &str) -> Result<Token, Report> {
+        let out = tokio::process::Command::new("az")
+            .args([
+                "account",
+                "get-access-token",
+                "--resource",
+                resource,
+                "--output",
+                "json",
             ])
             .output()
             .await
-            .wrap_err("az vm create")?;
-
+            .wrap_err("no Azure credentials found")
+            .suggestion(
+                "Set AZURE_TENANT_ID, AZURE_CLIENT_ID, AZURE_CLIENT_SECRET, and \
+                 AZURE_SUBSCRIPTION_ID, or install the Azure CLI and run `az login`",
+            )?;
         eyre::ensure!(
             out.status.success(),
-            "failed to create vm: {}",
-            String::from_utf8_lossy(&out.stderr)
+            "failed to get a token from the Azure CLI: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
         );
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let t = parse_cli_token(&out.stdout, now)?;
+        Ok(Token {
+            subscription: subscription_override().or(t.subscription),
+            bearer: t.bearer,
+            expires: Instant::now() + Duration::from_secs(t.expires_in),
+        })
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub(super) struct CliToken {
+        pub(super) bearer: String,
+        pub(super) subscription: Option<String>,
+        pub(super) expires_in: u64,
+    }
+
+    // The output of `az account get-access-token` has changed across versions of az: 2.0 has
+    // `expiresIn`, 2.54 added `expires_on`, and all of them have `expiresOn` in local time, which
+    // we cannot interpret. Anything else in the output is ignored.
+    pub(super) fn parse_cli_token(out: &[u8], now: u64) -> Result<CliToken, Report> {
+        let v: Value = serde_json::from_slice(out).wrap_err("invalid az output")?;
+        let number = |k: &str| match &v[k] {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        };
+        let expires_in = number("expires_on")
+            .and_then(|t| t.checked_sub(now))
+            .or_else(|| number("expiresIn"))
+            // assume the shortest lifetime Azure hands out.
+            .unwrap_or(300);
+        Ok(CliToken {
+            bearer: v["accessToken"]
+                .as_str()
+                .or_else(|| v["access_token"].as_str())
+                .ok_or_else(|| eyre::eyre!("az returned no access token"))?
+                .to_string(),
+            subscription: v["subscription"].as_str().map(String::from),
+            expires_in,
+        })
+    }
 
-        let vm: VmCreateOut = serde_json::from_slice(&out.stdout)?;
-        eyre::ensure!(vm.powerState == "VM running", "VM power state incorrect");
-        eyre::ensure!(vm.resourceGroup == rg, "VM resource group incorrect");
+    // The addresses of a VM, from its public ip and network interface resources.
+    pub(super) fn ip_info(ip: &Value, nic: &Value) -> Result<IpInfo, Report> {
+        let config = &nic["properties"]["ipConfigurations"][0]["properties"];
         Ok(IpInfo {
-            public_ip: vm.publicIpAddress,
-            private_ip: vm.privateIpAddress,
+            public_dns: ip["properties"]["dnsSettings"]["fqdn"]
+                .as_str()
+                .map(String::from),
+            public_ip: ip["properties"]["ipAddress"]
+                .as_str()
+                .ok_or_else(|| eyre::eyre!("vm has no public ip"))?
+                .to_string(),
+            private_ip: config["privateIPAddress"]
+                .as_str()
+                .ok_or_else(|| eyre::eyre!("vm has no private ip"))?
+                .to_string(),
         })
     }
 
-    #[instrument(level = "trace")]
-    pub(crate) async fn open_ports(rg: &str, vm_name: &str) -> Result<(), Report> {
-        let out = Command::new("az")
-            .args(&[
-                "vm",
-                "open-port",
-                "--port",
-                "0-65535",
-                "--resource-group",
-                rg,
-                "--name",
-                vm_name,
-            ])
-            .output()
-            .await
-            .wrap_err("az vm open-port")?;
+    // The virtual network a subnet belongs to.
+    pub(super) fn vnet_of(subnet: &str) -> Option<&str> {
+        subnet.rfind("/subnets/").map(|i| &subnet[..i])
+    }
 
-        eyre::ensure!(
-            out.status.success(),
-            "failed to open ports: {}",
-            String::from_utf8_lossy(&out.stderr)
-        );
+    // The address spaces of the virtual networks that a virtual network is connected to.
+    pub(super) fn peered_prefixes(vnet: &Value) -> Vec<String> {
+        vnet["properties"]["virtualNetworkPeerings"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|p| &p["properties"])
+            .filter(|p| p["peeringState"].as_str() == Some("Connected"))
+            .flat_map(|p| {
+                p["remoteAddressSpace"]["addressPrefixes"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+            })
+            .filter_map(|a| a.as_str().map(String::from))
+            .collect()
+    }
 
-        Ok(())
+    // The id of the first resource in a list that is tagged `key=value`.
+    pub(super) fn find_tagged<'v>(resources: &'v Value, key: &str, value: &str) -> Option<&'v str> {
+        resources["value"]
+            .as_array()?
+            .iter()
+            .find(|r| r["tags"][key].as_str() == Some(value))
+            .and_then(|r| r["id"].as_str())
     }
 
-    #[instrument(level = "trace")]
-    pub(crate) async fn delete_resource_group(rg: &str) -> Result<(), Report> {
-        let out = Command::new("az")
-            .args(&["group", "delete", "--name", rg, "--yes"])
-            .status()
+    // The first status code with the given prefix in a VM's instance view.
+    fn status_code(view: &Value, prefix: &str) -> Option<String> {
+        view["statuses"]
+            .as_array()?
+            .iter()
+            .filter_map(|s| s["code"].as_str())
+            .find(|c| c.starts_with(prefix))
+            .map(String::from)
+    }
+
+    // The `PowerState/...` code in a VM's instance view.
+    pub(super) fn power_code(view: &Value) -> Option<String> {
+        status_code(view, "PowerState/")
+    }
+
+    // Whether a VM with this instance view is running, still on its way there, or never will be.
+    pub(super) fn vm_ready(view: &Value) -> Result<bool, Report> {
+        if let Some(p) = status_code(view, "ProvisioningState/failed") {
+            eyre::bail!("vm provisioning failed: {}", p);
+        }
+        match power_code(view).as_deref() {
+            Some("PowerState/running") => Ok(true),
+            Some("PowerState/starting") | None => Ok(false),
+            Some(p) => eyre::bail!("vm is not starting: {}", p),
+        }
+    }
+
+    // The text of the first `<tag>` element in a Blob Storage response.
+    pub(super) fn xml_text<'x>(xml: &'x str, tag: &str) -> Option<&'x str> {
+        let open = format!("<{}>", tag);
+        let start = xml.find(&open)? + open.len();
+        let len = xml[start..].find(&format!("</{}>", tag))?;
+        Some(&xml[start..start + len])
+    }
+
+    // The names of the blobs in a page of a List Blobs response.
+    pub(super) fn blob_names(xml: &str) -> Vec<String> {
+        xml.split("<Name>")
+            .skip(1)
+            .filter_map(|s| s.find("</Name>").map(|i| &s[..i]))
+            .map(|name| {
+                name.replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&")
+            })
+            .collect()
+    }
+
+    impl Client {
+        // A Resource Manager token, and the subscription it is for.
+        async fn token(&self) -> Result<(String, String), Report> {
+            let t = self.cached_token(&self.token, MANAGEMENT_RESOURCE).await?;
+            match t.subscription {
+                Some(subscription) => Ok((subscription, t.bearer)),
+                None => Err(eyre::eyre!("no Azure subscription to launch machines in"))
+                    .suggestion("Set AZURE_SUBSCRIPTION_ID to the subscription to use"),
+            }
+        }
+
+        // A service principal from the environment if there is one, and the Azure CLI's login
+        // otherwise. Tokens are reused until shortly before they expire.
+        async fn cached_token(
+            &self,
+            slot: &Mutex<Option<Token>>,
+            resource: &str,
+        ) -> Result<Token, Report> {
+            let cached = slot.lock().unwrap().clone();
+            if let Some(t) = cached {
+                if t.expires > Instant::now() + Duration::from_secs(60) {
+                    return Ok(t);
+                }
+            }
+
+            let sp = (
+                std::env::var("AZURE_TENANT_ID"),
+                std::env::var("AZURE_CLIENT_ID"),
+                std::env::var("AZURE_CLIENT_SECRET"),
+            );
+            let t = match sp {
+                (Ok(tenant), Ok(client), Ok(secret)) => {
+                    service_principal_token(&self.http, &tenant, &client, &secret, resource).await?
+                }
+                _ => cli_token(resource).await?,
+            };
+            *slot.lock().unwrap() = Some(t.clone());
+            Ok(t)
+        }
+
+        /// Check that there are usable credentials.
+        pub(crate) async fn check(&self) -> Result<(), Report> {
+            self.token().await.map(drop)
+        }
+
+        async fn send(&self, req: RequestBuilder) -> Result<Response, Report> {
+            let resp = req
+                .send()
+                .await
+                .wrap_err("failed to reach Azure Resource Manager")?;
+            if resp.status().is_success() {
+                return Ok(resp);
+            }
+            let status = resp.status().as_u16();
+            let body = resp.text().await.unwrap_or_default();
+            Err(Report::new(ApiError::from_body(status, &body)))
+        }
+
+        // `path` is relative to the subscription.
+        async fn call(
+            &self,
+            method: Method,
+            path: &str,
+            api_version: &str,
+            body: Option<Value>,
+        ) -> Result<Response, Report> {
+            let (subscription, bearer) = self.token().await?;
+            let url = format!(
+                "{}{}?api-version={}",
+                MANAGEMENT,
+                absolute(&subscription, path),
+                api_version
+            );
+            let mut req = self.http.request(method, url).bearer_auth(&bearer);
+            if let Some(body) = body {
+                req = req.json(&body);
+            }
+            self.send(req).await
+        }
+
+        async fn get(&self, path: &str, api_version: &str) -> Result<Value, Report> {
+            let resp = self.call(Method::GET, path, api_version, None).await?;
+            Ok(resp.json().await?)
+        }
+
+        // Creates or updates a resource, waits for Azure to finish, and returns the resource.
+        async fn put(&self, path: &str, api_version: &str, body: Value) -> Result<Value, Report> {
+            let resp = self
+                .call(Method::PUT, path, api_version, Some(body))
+                .await?;
+            self.wait(resp).await?;
+            self.get(path, api_version).await
+        }
+
+        // Waits for a long-running operation to finish, or for `OPERATION_TIMEOUT` to elapse.
+        async fn wait(&self, resp: Response) -> Result<(), Report> {
+            let start = Instant::now();
+            let timed_out =
+                || eyre::eyre!("gave up on Azure operation after {:?}", start.elapsed());
+            let header = |name: &str| {
+                resp.headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(String::from)
+            };
+            let retry = Duration::from_secs(
+                header("retry-after")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(2)
+                    .clamp(1, 30),
+            );
+
+            if let Some(op) = header("azure-asyncoperation") {
+                loop {
+                    if start.elapsed() > OPERATION_TIMEOUT {
+                        return Err(timed_out());
+                    }
+                    tokio::time::sleep(retry).await;
+                    let (_, bearer) = self.token().await?;
+                    let v: Value = self
+                        .send(self.http.get(&op).bearer_auth(&bearer))
+                        .await?
+                        .json()
+                        .await?;
+                    match v["status"].as_str() {
+                        Some("Succeeded") => return Ok(()),
+                        Some("Failed") | Some("Canceled") => {
+                            return Err(Report::new(ApiError::from_json(200, &v)))
+                        }
+                        _ => {}
+                    }
+                }
+            } else if resp.status() == StatusCode::ACCEPTED {
+                if let Some(loc) = header("location") {
+                    loop {
+                        if start.elapsed() > OPERATION_TIMEOUT {
+                            return Err(timed_out());
+                        }
+                        tokio::time::sleep(retry).await;
+                        let (_, bearer) = self.token().await?;
+                        let r = self.send(self.http.get(&loc).bearer_auth(&bearer)).await?;
+                        if r.status() != StatusCode::ACCEPTED {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        #[instrument(level = "trace")]
+        pub(crate) async fn create_resource_group(
+            &self,
+            r: Region,
+            name: &str,
+        ) -> Result<(), Report> {
+            self.put(
+                &format!("/resourcegroups/{}", name),
+                RESOURCES_API,
+                json!({ "location": r.as_ref() }),
+            )
             .await
-            .wrap_err("az group delete")?;
+            .wrap_err("failed to create resource group")?;
+            Ok(())
+        }
 
-        eyre::ensure!(out.success(), "failed to delete resource group");
+        /// Create a virtual network whose firewall lets all traffic in, and return the id of its
+        /// subnet.
+        #[instrument(level = "trace")]
+        pub(crate) async fn create_network(&self, r: Region, rg: &str) -> Result<String, Report> {
+            let net = format!("/resourceGroups/{}/providers/Microsoft.Network", rg);
+            let nsg = self
+                .put(
+                    &format!("{}/networkSecurityGroups/tsunami-nsg", net),
+                    NETWORK_API,
+                    json!({
+                        "location": r.as_ref(),
+                        "properties": { "securityRules": [{
+                            "name": "allow-all-inbound",
+                            "properties": {
+                                "priority": 100,
+                                "direction": "Inbound",
+                                "access": "Allow",
+                                "protocol": "*",
+                                "sourceAddressPrefix": "*",
+                                "sourcePortRange": "*",
+                                "destinationAddressPrefix": "*",
+                                "destinationPortRange": "*",
+                            },
+                        }]},
+                    }),
+                )
+                .await
+                .wrap_err("failed to create network security group")?;
+            let vnet = self
+                .put(
+                    &format!("{}/virtualNetworks/tsunami-vnet", net),
+                    NETWORK_API,
+                    json!({
+                        "location": r.as_ref(),
+                        "properties": {
+                            "addressSpace": { "addressPrefixes": ["10.0.0.0/16"] },
+                            "subnets": [{
+                                "name": "default",
+                                "properties": {
+                                    "addressPrefix": "10.0.0.0/16",
+                                    "networkSecurityGroup": { "id": nsg["id"] },
+                                },
+                            }],
+                        },
+                    }),
+                )
+                .await
+                .wrap_err("failed to create virtual network")?;
+            vnet["properties"]["subnets"][0]["id"]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| eyre::eyre!("virtual network has no subnet"))
+        }
 
-        Ok(())
+        #[allow(clippy::too_many_arguments)]
+        #[instrument(level = "trace", skip(public_key))]
+        pub(crate) async fn create_vm(
+            &self,
+            r: Region,
+            rg: &str,
+            subnet: &str,
+            name: &str,
+            size: &str,
+            image: &str,
+            username: &str,
+            public_key: &str,
+            max_wait: Option<Duration>,
+        ) -> Result<IpInfo, Report> {
+            let (subscription, _) = self.token().await?;
+            let mut image = super::image_reference(image)?;
+            if let Some(id) = image["id"].as_str() {
+                let id = absolute(&subscription, id);
+                image["id"] = json!(id);
+            }
+            let net = format!("/resourceGroups/{}/providers/Microsoft.Network", rg);
+            // the subnet may not be tsunami's, and so may not have its security group.
+            let nsg = absolute(
+                &subscription,
+                &format!("{}/networkSecurityGroups/tsunami-nsg", net),
+            );
+
+            // DNS labels must be lowercase, and VM names are unique within the region anyway.
+            let ip_path = format!("{}/publicIPAddresses/{}-ip", net, name);
+            let ip = self
+                .put(
+                    &ip_path,
+                    NETWORK_API,
+                    json!({
+                        "location": r.as_ref(),
+                        "sku": { "name": "Standard" },
+                        "properties": {
+                            "publicIPAllocationMethod": "Static",
+                            "dnsSettings": { "domainNameLabel": name.to_lowercase() },
+                        },
+                    }),
+                )
+                .await
+                .wrap_err("failed to create public ip")?;
+
+            let nic_path = format!("{}/networkInterfaces/{}-nic", net, name);
+            let nic = self
+                .put(
+                    &nic_path,
+                    NETWORK_API,
+                    json!({
+                        "location": r.as_ref(),
+                        "properties": {
+                            "ipConfigurations": [{
+                                "name": "ipconfig1",
+                                "properties": {
+                                    "subnet": { "id": subnet },
+                                    "publicIPAddress": { "id": ip["id"] },
+                                    "privateIPAllocationMethod": "Dynamic",
+                                },
+                            }],
+                            "networkSecurityGroup": { "id": nsg },
+                        },
+                    }),
+                )
+                .await
+                .wrap_err("failed to create network interface")?;
+
+            self.put(
+                &format!(
+                    "/resourceGroups/{}/providers/Microsoft.Compute/virtualMachines/{}",
+                    rg, name
+                ),
+                COMPUTE_API,
+                json!({
+                    "location": r.as_ref(),
+                    "properties": {
+                        "hardwareProfile": { "vmSize": size },
+                        "storageProfile": {
+                            "imageReference": image,
+                            "osDisk": { "createOption": "FromImage", "deleteOption": "Delete" },
+                        },
+                        "osProfile": {
+                            "computerName": name,
+                            "adminUsername": username,
+                            "linuxConfiguration": {
+                                "disablePasswordAuthentication": true,
+                                "ssh": { "publicKeys": [{
+                                    "path": format!("/home/{}/.ssh/authorized_keys", username),
+                                    "keyData": public_key.trim(),
+                                }]},
+                            },
+                        },
+                        "networkProfile": { "networkInterfaces": [{
+                            "id": nic["id"],
+                            "properties": { "deleteOption": "Delete" },
+                        }]},
+                    },
+                }),
+            )
+            .await
+            .wrap_err("failed to create vm")?;
+
+            self.wait_running(rg, name, max_wait).await?;
+            let ip = self.get(&ip_path, NETWORK_API).await?;
+            ip_info(&ip, &nic)
+        }
+
+        /// Deallocate the VM `vm_name` every day at the time of day of `at`, rounded up to the
+        /// minute.
+        #[instrument(level = "trace", skip(self))]
+        pub(crate) async fn schedule_shutdown(
+            &self,
+            r: Region,
+            rg: &str,
+            vm_name: &str,
+            at: std::time::SystemTime,
+        ) -> Result<(), Report> {
+            let (subscription, _) = self.token().await?;
+            let vm = format!(
+                "/subscriptions/{}/resourceGroups/{}/providers/Microsoft.Compute/virtualMachines/{}",
+                subscription, rg, vm_name
+            );
+            self.put(
+                &shutdown_schedule(rg, vm_name),
+                DEVTESTLAB_API,
+                json!({
+                    "location": r.as_ref(),
+                    "properties": {
+                        "status": "Enabled",
+                        "taskType": "ComputeVmShutdownTask",
+                        "dailyRecurrence": { "time": shutdown_time(at) },
+                        "timeZoneId": "UTC",
+                        "targetResourceId": vm,
+                        "notificationSettings": { "status": "Disabled" },
+                    },
+                }),
+            )
+            .await?;
+            Ok(())
+        }
+
+        /// Poll the VM's instance view every two seconds until it is running, or `max_wait` (if
+        /// not `None`) elapses.
+        ///
+        /// Slow regions report `PowerState/starting` for a while after creation finishes.
+        #[instrument(level = "trace", skip(self, max_wait))]
+        async fn wait_running(
+            &self,
+            rg: &str,
+            vm_name: &str,
+            max_wait: Option<Duration>,
+        ) -> Result<(), Report> {
+            let start = Instant::now();
+            let path = format!(
+                "/resourceGroups/{}/providers/Microsoft.Compute/virtualMachines/{}/instanceView",
+                rg, vm_name
+            );
+            loop {
+                let view = self
+                    .get(&path, COMPUTE_API)
+                    .await
+                    .wrap_err("failed to get vm state")?;
+                if vm_ready(&view)? {
+                    return Ok(());
+                }
+                tracing::trace!(power = ?power_code(&view), "vm not running yet");
+
+                if let Some(wait_limit) = max_wait {
+                    eyre::ensure!(
+                        start.elapsed() <= wait_limit,
+                        "wait limit reached: vm is {}",
+                        power_code(&view).as_deref().unwrap_or("still provisioning")
+                    );
+                }
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+        }
+
+        /// The `PowerState/...` code of the VM, or `None` if the VM does not exist.
+        #[instrument(level = "trace")]
+        pub(crate) async fn power_state(
+            &self,
+            rg: &str,
+            vm_name: &str,
+        ) -> Result<Option<String>, Report> {
+            let view = self
+                .get(
+                    &format!(
+                        "/resourceGroups/{}/providers/Microsoft.Compute/virtualMachines/{}/instanceView",
+                        rg, vm_name
+                    ),
+                    COMPUTE_API,
+                )
+                .await;
+            let view = match view {
+                Ok(v) => v,
+                Err(e) if is_not_found(&e) => return Ok(None),
+                Err(e) => return Err(e.wrap_err("failed to get vm state")),
+            };
+            Ok(power_code(&view))
+        }
+
+        // Runs an action on a resource, and waits for Azure to finish.
+        async fn post(&self, path: &str, api_version: &str) -> Result<(), Report> {
+            let resp = self
+                .call(Method::POST, path, api_version, Some(json!({})))
+                .await?;
+            self.wait(resp).await
+        }
+
+        /// The image name (see `image_reference`) of `spec`.
+        ///
+        /// [`ImageSpec::CustomByName`](crate::image::ImageSpec::CustomByName) is looked up among
+        /// the subscription's managed images.
+        #[instrument(level = "trace")]
+        pub(crate) async fn resolve_image(
+            &self,
+            spec: &crate::image::ImageSpec,
+        ) -> Result<String, Report> {
+            if let Some(image) = super::spec_image(spec) {
+                return Ok(image);
+            }
+            let name = match spec {
+                crate::image::ImageSpec::CustomByName(name) => name,
+                _ => eyre::bail!("unsupported image {}", spec),
+            };
+            let images = self
+                .get("/providers/Microsoft.Compute/images", COMPUTE_API)
+                .await
+                .wrap_err("failed to list images")?;
+            images["value"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|i| i["name"].as_str() == Some(name))
+                .and_then(|i| i["id"].as_str())
+                .map(String::from)
+                .ok_or_else(|| eyre::eyre!("no managed image named {}", name))
+        }
+
+        /// The id of the subnet that VMs near `near` go into, which has to be in region `r`.
+        #[instrument(level = "trace")]
+        pub(crate) async fn resolve_subnet(
+            &self,
+            r: Region,
+            near: &super::Affinity,
+        ) -> Result<String, Report> {
+            use super::Affinity;
+            let subnet = match near {
+                Affinity::Subnet(id) => id.clone(),
+                Affinity::VirtualNetwork(id) => self.vnet_subnet(id).await?,
+                Affinity::Vm(id) => self.vm_subnet(id).await?,
+                Affinity::Tagged { key, value } => {
+                    let vms = self
+                        .get("/providers/Microsoft.Compute/virtualMachines", COMPUTE_API)
+                        .await
+                        .wrap_err("failed to list vms")?;
+                    let vnets = self
+                        .get("/providers/Microsoft.Network/virtualNetworks", NETWORK_API)
+                        .await
+                        .wrap_err("failed to list virtual networks")?;
+                    if let Some(vm) = find_tagged(&vms, key, value) {
+                        self.vm_subnet(vm).await?
+                    } else if let Some(vnet) = find_tagged(&vnets, key, value) {
+                        self.vnet_subnet(vnet).await?
+                    } else {
+                        eyre::bail!("no vm or virtual network tagged {}={}", key, value);
+                    }
+                }
+            };
+
+            let vnet_id =
+                vnet_of(&subnet).ok_or_else(|| eyre::eyre!("{} is not a subnet id", subnet))?;
+            let vnet = self
+                .get(vnet_id, NETWORK_API)
+                .await
+                .wrap_err("failed to get virtual network")?;
+            let location = vnet["location"].as_str().unwrap_or_default();
+            if !in_region(location, r) {
+                return Err(eyre::eyre!(
+                    "virtual network {} is in {}, not in {}",
+                    vnet_id,
+                    location,
+                    r
+                ))
+                .suggestion(
+                    "Launch the machines in the network's region, or near a network in their \
+                     region that is peered with it",
+                );
+            }
+            tracing::debug!(%subnet, peered = ?peered_prefixes(&vnet), "joining network");
+            Ok(subnet)
+        }
+
+        async fn vnet_subnet(&self, vnet: &str) -> Result<String, Report> {
+            let vnet = self
+                .get(vnet, NETWORK_API)
+                .await
+                .wrap_err("failed to get virtual network")?;
+            vnet["properties"]["subnets"][0]["id"]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| eyre::eyre!("virtual network has no subnet"))
+        }
+
+        async fn vm_subnet(&self, vm: &str) -> Result<String, Report> {
+            let vm = self
+                .get(vm, COMPUTE_API)
+                .await
+                .wrap_err("failed to get vm")?;
+            let nic = vm["properties"]["networkProfile"]["networkInterfaces"][0]["id"]
+                .as_str()
+                .ok_or_else(|| eyre::eyre!("vm has no network interface"))?;
+            let nic = self
+                .get(nic, NETWORK_API)
+                .await
+                .wrap_err("failed to get network interface")?;
+            nic["properties"]["ipConfigurations"][0]["properties"]["subnet"]["id"]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| eyre::eyre!("vm is not in a subnet"))
+        }
+
+        /// Turn the VM `vm_name` into version `version` of the gallery image `definition`, and
+        /// return the version's resource id.
+        ///
+        /// The VM has to be deprovisioned already. It is deallocated and generalized, after which
+        /// it can no longer be started.
+        #[instrument(level = "trace")]
+        pub(crate) async fn publish_image_version(
+            &self,
+            r: Region,
+            rg: &str,
+            vm_name: &str,
+            definition: &str,
+            version: &str,
+        ) -> Result<String, Report> {
+            let def = self
+                .get(definition, GALLERY_API)
+                .await
+                .wrap_err("failed to get gallery image definition")?;
+            let state = def["properties"]["osState"].as_str().unwrap_or_default();
+            if !state.eq_ignore_ascii_case("Generalized") {
+                return Err(eyre::eyre!(
+                    "gallery image definition is {}, not Generalized",
+                    state
+                ))
+                .suggestion("Create the image definition with --os-state Generalized");
+            }
+            let location = def["location"].as_str().unwrap_or_default();
+            eyre::ensure!(
+                in_region(location, r),
+                "gallery image is in {}, but the vm is in {}",
+                location,
+                r
+            );
+
+            let vm_path = format!(
+                "/resourceGroups/{}/providers/Microsoft.Compute/virtualMachines/{}",
+                rg, vm_name
+            );
+            self.post(&format!("{}/deallocate", vm_path), COMPUTE_API)
+                .await
+                .wrap_err("failed to deallocate vm")?;
+            self.post(&format!("{}/generalize", vm_path), COMPUTE_API)
+                .await
+                .wrap_err("failed to generalize vm")?;
+            let vm = self.get(&vm_path, COMPUTE_API).await?;
+
+            let v = self
+                .put(
+                    &format!("{}/versions/{}", definition, version),
+                    GALLERY_API,
+                    json!({
+                        "location": location,
+                        "properties": {
+                            "storageProfile": { "source": { "id": vm["id"] } },
+                        },
+                    }),
+                )
+                .await
+                .wrap_err("failed to create gallery image version")?;
+            v["id"]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| eyre::eyre!("gallery image version has no id"))
+        }
+
+        // Deletes a resource and waits for Azure to finish. Resources that do not exist are
+        // already deleted.
+        async fn delete(&self, path: &str, api_version: &str) -> Result<(), Report> {
+            match self.call(Method::DELETE, path, api_version, None).await {
+                Ok(resp) => self.wait(resp).await,
+                Err(e) if is_not_found(&e) => Ok(()),
+                Err(e) => Err(e),
+            }
+        }
+
+        /// Delete the VM `vm_name` along with its network interface, public IP, OS disk, and
+        /// auto-shutdown schedule.
+        ///
+        /// The network and its security group are shared by all VMs in the resource group, and are
+        /// left alone.
+        #[instrument(level = "trace")]
+        pub(crate) async fn delete_vm(&self, rg: &str, vm_name: &str) -> Result<(), Report> {
+            let vm_path = format!(
+                "/resourceGroups/{}/providers/Microsoft.Compute/virtualMachines/{}",
+                rg, vm_name
+            );
+            // the disk is deleted along with the VM, unless the VM was created with another
+            // deleteOption; look it up first so that it can be cleaned up either way.
+            let disk = match self.get(&vm_path, COMPUTE_API).await {
+                Ok(vm) => vm["properties"]["storageProfile"]["osDisk"]["managedDisk"]["id"]
+                    .as_str()
+                    .map(String::from),
+                Err(e) if is_not_found(&e) => None,
+                Err(e) => return Err(e.wrap_err("failed to get vm")),
+            };
+            self.delete(&vm_path, COMPUTE_API)
+                .await
+                .wrap_err("failed to delete vm")?;
+            // schedules outlive their VM, and would otherwise pile up in long-lived resource
+            // groups.
+            self.delete(&shutdown_schedule(rg, vm_name), DEVTESTLAB_API)
+                .await
+                .wrap_err("failed to delete auto-shutdown schedule")?;
+
+            // the public ip is in use until the network interface is gone.
+            let net = format!("/resourceGroups/{}/providers/Microsoft.Network", rg);
+            self.delete(
+                &format!("{}/networkInterfaces/{}-nic", net, vm_name),
+                NETWORK_API,
+            )
+            .await
+            .wrap_err("failed to delete network interface")?;
+            self.delete(
+                &format!("{}/publicIPAddresses/{}-ip", net, vm_name),
+                NETWORK_API,
+            )
+            .await
+            .wrap_err("failed to delete public ip")?;
+            if let Some(path) = disk.as_deref().and_then(resource_path) {
+                self.delete(path, DISKS_API)
+                    .await
+                    .wrap_err("failed to delete os disk")?;
+            }
+            Ok(())
+        }
+
+        #[instrument(level = "trace")]
+        pub(crate) async fn delete_resource_group(&self, rg: &str) -> Result<(), Report> {
+            let resp = self
+                .call(
+                    Method::DELETE,
+                    &format!("/resourcegroups/{}", rg),
+                    RESOURCES_API,
+                    None,
+                )
+                .await
+                .wrap_err("failed to delete resource group")?;
+            self.wait(resp)
+                .await
+                .wrap_err("failed to delete resource group")
+        }
+
+        // A Blob Storage request for `path` in the storage account `account`.
+        async fn blob_request(
+            &self,
+            method: Method,
+            account: &str,
+            path: &str,
+        ) -> Result<RequestBuilder, Report> {
+            let t = self
+                .cached_token(&self.storage_token, STORAGE_RESOURCE)
+                .await?;
+            let url = format!("https://{}.blob.core.windows.net/{}", account, path);
+            Ok(self
+                .http
+                .request(method, url)
+                .bearer_auth(&t.bearer)
+                .header("x-ms-version", STORAGE_API))
+        }
+
+        // Like `send`, but for Blob Storage, which reports errors in XML. Returns `None` if the
+        // blob does not exist.
+        async fn send_blob(&self, req: RequestBuilder) -> Result<Option<Response>, Report> {
+            let resp = req
+                .send()
+                .await
+                .wrap_err("failed to reach Azure Blob Storage")?;
+            if resp.status().is_success() {
+                return Ok(Some(resp));
+            }
+            let status = resp.status().as_u16();
+            let body = resp.text().await.unwrap_or_default();
+            let e = ApiError {
+                status,
+                code: xml_text(&body, "Code").unwrap_or("Unknown").to_string(),
+                message: xml_text(&body, "Message")
+                    .unwrap_or_else(|| body.trim())
+                    .to_string(),
+            };
+            if e.code == "BlobNotFound" {
+                Ok(None)
+            } else {
+                Err(Report::new(e))
+            }
+        }
+
+        #[instrument(level = "trace", skip(value))]
+        pub(crate) async fn put_blob(
+            &self,
+            account: &str,
+            container: &str,
+            name: &str,
+            value: Vec<u8>,
+        ) -> Result<(), Report> {
+            let req = self
+                .blob_request(Method::PUT, account, &format!("{}/{}", container, name))
+                .await?
+                .header("x-ms-blob-type", "BlockBlob")
+                .body(value);
+            self.send_blob(req)
+                .await
+                .wrap_err_with(|| format!("failed to upload {}", name))?;
+            Ok(())
+        }
+
+        /// The contents of the blob `name`, or `None` if there is no such blob.
+        #[instrument(level = "trace")]
+        pub(crate) async fn get_blob(
+            &self,
+            account: &str,
+            container: &str,
+            name: &str,
+        ) -> Result<Option<Vec<u8>>, Report> {
+            let req = self
+                .blob_request(Method::GET, account, &format!("{}/{}", container, name))
+                .await?;
+            match self
+                .send_blob(req)
+                .await
+                .wrap_err_with(|| format!("failed to download {}", name))?
+            {
+                Some(resp) => Ok(Some(resp.bytes().await?.to_vec())),
+                None => Ok(None),
+            }
+        }
+
+        /// The names of all blobs that start with `prefix`.
+        #[instrument(level = "trace")]
+        pub(crate) async fn list_blobs(
+            &self,
+            account: &str,
+            container: &str,
+            prefix: &str,
+        ) -> Result<Vec<String>, Report> {
+            let mut names = Vec::new();
+            let mut marker = None;
+            loop {
+                let mut req = self
+                    .blob_request(Method::GET, account, container)
+                    .await?
+                    .query(&[
+                        ("restype", "container"),
+                        ("comp", "list"),
+                        ("prefix", prefix),
+                    ]);
+                if let Some(ref marker) = marker {
+                    req = req.query(&[("marker", marker)]);
+                }
+                let page = self
+                    .send_blob(req)
+                    .await
+                    .wrap_err("failed to list blobs")?
+                    .ok_or_else(|| eyre::eyre!("failed to list blobs"))?
+                    .text()
+                    .await?;
+                names.extend(blob_names(&page));
+                // the last page has an empty `<NextMarker />`.
+                match xml_text(&page, "NextMarker") {
+                    Some(next) if !next.is_empty() => marker = Some(next.to_string()),
+                    _ => return Ok(names),
+                }
+            }
+        }
+
+        /// Delete the blob `name`. Deleting a blob that does not exist is not an error.
+        #[instrument(level = "trace")]
+        pub(crate) async fn delete_blob(
+            &self,
+            account: &str,
+            container: &str,
+            name: &str,
+        ) -> Result<(), Report> {
+            let req = self
+                .blob_request(Method::DELETE, account, &format!("{}/{}", container, name))
+                .await?;
+            self.send_blob(req)
+                .await
+                .wrap_err_with(|| format!("failed to delete {}", name))?;
+            Ok(())
+        }
     }
 }
 
@@ -718,17 +2497,306 @@ mod test {
     use super::*;
     use std::future::Future;
 
+    #[test]
+    fn images() {
+        let urn = image_reference("ubuntults").unwrap();
+        assert_eq!(urn["offer"], "UbuntuServer");
+        assert_eq!(urn["version"], "latest");
+        let urn =
+            image_reference("Canonical:0001-com-ubuntu-server-focal:20_04-lts:20.04.1").unwrap();
+        assert_eq!(urn["sku"], "20_04-lts");
+        let id = "/subscriptions/s/resourceGroups/g/providers/Microsoft.Compute/images/i";
+        assert_eq!(image_reference(id).unwrap()["id"], id);
+        assert!(image_reference("Windows").is_err());
+
+        let e = ApiError::from_body(
+            409,
+            r#"{"error": {"code": "SkuNotAvailable", "message": "no capacity"}}"#,
+        );
+        assert_eq!(e.code, "SkuNotAvailable");
+        assert_eq!(e.to_string(), "SkuNotAvailable (409): no capacity");
+        assert_eq!(
+            ApiError::from_body(502, "bad gateway").message,
+            "bad gateway"
+        );
+    }
+
+    // `az account get-access-token` as printed by az 2.0.81, 2.30.0, and 2.61.0.
+    const AZ_2_0: &str = r#"{
+        "_authority": "https://login.microsoftonline.com",
+        "_clientId": "04b07795-8ddb-461a-bbee-02f9e1bf7b46",
+        "accessToken": "eyJ0eXAi.old",
+        "expiresIn": 3599,
+        "expiresOn": "2020-03-02 10:44:21.582171",
+        "isMRRT": true,
+        "subscription": "0b1f6471-1bf0-4dda-aec3-cb9272f09590",
+        "tenant": "72f988bf-86f1-41af-91ab-2d7cd011db47",
+        "tokenType": "Bearer"
+    }"#;
+    const AZ_2_30: &str = r#"{
+        "accessToken": "eyJ0eXAi.mid",
+        "expiresOn": "2021-11-05 14:06:32.000000",
+        "subscription": "0b1f6471-1bf0-4dda-aec3-cb9272f09590",
+        "tenant": "72f988bf-86f1-41af-91ab-2d7cd011db47",
+        "tokenType": "Bearer"
+    }"#;
+    const AZ_2_61: &str = r#"{
+        "accessToken": "eyJ0eXAi.new",
+        "expiresOn": "2024-06-11 16:21:43.000000",
+        "expires_on": 1718122903,
+        "subscription": "0b1f6471-1bf0-4dda-aec3-cb9272f09590",
+        "tenant": "72f988bf-86f1-41af-91ab-2d7cd011db47",
+        "tokenType": "Bearer"
+    }"#;
+
+    #[test]
+    fn cli_tokens() {
+        let t = arm::parse_cli_token(AZ_2_0.as_bytes(), 0).unwrap();
+        assert_eq!(t.bearer, "eyJ0eXAi.old");
+        assert_eq!(t.expires_in, 3599);
+        let t = arm::parse_cli_token(AZ_2_30.as_bytes(), 0).unwrap();
+        assert_eq!(
+            t.subscription.as_deref(),
+            Some("0b1f6471-1bf0-4dda-aec3-cb9272f09590")
+        );
+        assert_eq!(t.expires_in, 300);
+        let t = arm::parse_cli_token(AZ_2_61.as_bytes(), 1718122903 - 1200).unwrap();
+        assert_eq!(t.expires_in, 1200);
+
+        assert!(arm::parse_cli_token(br#"{"tokenType": "Bearer"}"#, 0).is_err());
+        assert!(arm::parse_cli_token(b"ERROR: Please run 'az login'", 0).is_err());
+    }
+
+    #[test]
+    fn blob_list() {
+        let page = r#"<?xml version="1.0" encoding="utf-8"?>
+<EnumerationResults ServiceEndpoint="https://a.blob.core.windows.net/" ContainerName="runs">
+  <Prefix>r1/</Prefix>
+  <Blobs>
+    <Blob><Name>r1/audit</Name><Properties><Content-Length>12</Content-Length></Properties></Blob>
+    <Blob><Name>r1/a&amp;b</Name><Properties><Content-Length>3</Content-Length></Properties></Blob>
+  </Blobs>
+  <NextMarker>2!72!MDAwMDA3</NextMarker>
+</EnumerationResults>"#;
+        assert_eq!(arm::blob_names(page), vec!["r1/audit", "r1/a&b"]);
+        assert_eq!(arm::xml_text(page, "NextMarker"), Some("2!72!MDAwMDA3"));
+        assert_eq!(arm::xml_text(page, "Code"), None);
+        let last = "<EnumerationResults><Blobs /><NextMarker /></EnumerationResults>";
+        assert!(arm::blob_names(last).is_empty());
+        assert_eq!(arm::xml_text(last, "NextMarker"), None);
+    }
+
+    #[test]
+    fn resource_responses() {
+        // trimmed GET responses for a public ip and a network interface (API version 2021-05-01)
+        let ip: serde_json::Value = serde_json::from_str(
+            r#"{
+            "name": "tsunami-vm-abc-ip",
+            "id": "/subscriptions/s/resourceGroups/g/providers/Microsoft.Network/publicIPAddresses/tsunami-vm-abc-ip",
+            "location": "eastus",
+            "properties": {
+                "provisioningState": "Succeeded",
+                "ipAddress": "20.124.1.2",
+                "publicIPAddressVersion": "IPv4",
+                "publicIPAllocationMethod": "Static",
+                "dnsSettings": {
+                    "domainNameLabel": "tsunami-vm-abc",
+                    "fqdn": "tsunami-vm-abc.eastus.cloudapp.azure.com"
+                }
+            },
+            "sku": { "name": "Standard", "tier": "Regional" }
+        }"#,
+        )
+        .unwrap();
+        let nic: serde_json::Value = serde_json::from_str(
+            r#"{
+            "name": "tsunami-vm-abc-nic",
+            "properties": {
+                "provisioningState": "Succeeded",
+                "ipConfigurations": [{
+                    "name": "ipconfig1",
+                    "properties": {
+                        "privateIPAddress": "10.0.0.4",
+                        "privateIPAllocationMethod": "Dynamic",
+                        "primary": true
+                    }
+                }],
+                "enableAcceleratedNetworking": false
+            }
+        }"#,
+        )
+        .unwrap();
+        let info = arm::ip_info(&ip, &nic).unwrap();
+        assert_eq!(info.public_ip, "20.124.1.2");
+        assert_eq!(info.private_ip, "10.0.0.4");
+        assert_eq!(
+            info.public_dns.as_deref(),
+            Some("tsunami-vm-abc.eastus.cloudapp.azure.com")
+        );
+
+        // no dns label, and an address that has not been assigned yet
+        let mut ip = ip;
+        ip["properties"]
+            .as_object_mut()
+            .unwrap()
+            .remove("dnsSettings");
+        assert_eq!(arm::ip_info(&ip, &nic).unwrap().public_dns, None);
+        ip["properties"]
+            .as_object_mut()
+            .unwrap()
+            .remove("ipAddress");
+        assert!(arm::ip_info(&ip, &nic).is_err());
+
+        let view: serde_json::Value = serde_json::from_str(
+            r#"{
+            "computerName": "tsunami-vm-abc",
+            "osName": "ubuntu",
+            "statuses": [
+                { "code": "ProvisioningState/succeeded", "level": "Info" },
+                { "code": "PowerState/running", "level": "Info", "displayStatus": "VM running" }
+            ]
+        }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            arm::power_code(&view).as_deref(),
+            Some("PowerState/running")
+        );
+        assert!(arm::vm_ready(&view).unwrap());
+        let creating =
+            serde_json::json!({ "statuses": [{ "code": "ProvisioningState/creating" }] });
+        assert_eq!(arm::power_code(&creating), None);
+        assert!(!arm::vm_ready(&creating).unwrap());
+        let starting = serde_json::json!({ "statuses": [
+            { "code": "ProvisioningState/succeeded" },
+            { "code": "PowerState/starting" },
+        ]});
+        assert!(!arm::vm_ready(&starting).unwrap());
+        let failed = serde_json::json!({ "statuses": [
+            { "code": "ProvisioningState/failed/AllocationFailed" },
+        ]});
+        assert!(arm::vm_ready(&failed).is_err());
+
+        assert_eq!(
+            arm::resource_path(
+                "/subscriptions/0b1f/resourceGroups/g/providers/Microsoft.Compute/disks/d_OsDisk_1"
+            ),
+            Some("/resourceGroups/g/providers/Microsoft.Compute/disks/d_OsDisk_1")
+        );
+        assert_eq!(arm::resource_path("disks/d"), None);
+    }
+
+    #[test]
+    fn shutdown_times() {
+        let at = |secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        assert_eq!(arm::shutdown_time(at(18 * 3600 + 30 * 60)), "1830");
+        assert_eq!(arm::shutdown_time(at(18 * 3600 + 30 * 60 + 1)), "1831");
+        assert_eq!(arm::shutdown_time(at(24 * 3600 - 1)), "0000");
+    }
+
+    #[test]
+    fn locations() {
+        assert!(arm::in_region("eastus", Region::EastUs));
+        assert!(arm::in_region("East US", Region::EastUs));
+        assert!(!arm::in_region("East US 2", Region::EastUs));
+    }
+
+    #[test]
+    fn networks() {
+        let subnet = "/subscriptions/s/resourceGroups/db/providers/Microsoft.Network/virtualNetworks/prod/subnets/default";
+        assert_eq!(
+            arm::vnet_of(subnet),
+            Some("/subscriptions/s/resourceGroups/db/providers/Microsoft.Network/virtualNetworks/prod")
+        );
+        assert_eq!(arm::vnet_of("/subscriptions/s/resourceGroups/db"), None);
+
+        // a trimmed virtual network with one connected and one disconnected peering
+        let vnet: serde_json::Value = serde_json::from_str(
+            r#"{
+            "name": "prod",
+            "location": "eastus",
+            "properties": {
+                "addressSpace": { "addressPrefixes": ["10.1.0.0/16"] },
+                "virtualNetworkPeerings": [
+                    { "name": "to-analytics", "properties": {
+                        "peeringState": "Connected",
+                        "remoteAddressSpace": { "addressPrefixes": ["10.2.0.0/16", "10.3.0.0/24"] }
+                    }},
+                    { "name": "to-old", "properties": {
+                        "peeringState": "Disconnected",
+                        "remoteAddressSpace": { "addressPrefixes": ["10.9.0.0/16"] }
+                    }}
+                ]
+            }
+        }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            arm::peered_prefixes(&vnet),
+            vec![String::from("10.2.0.0/16"), String::from("10.3.0.0/24")]
+        );
+
+        let vms = serde_json::json!({ "value": [
+            { "id": "/subscriptions/s/vm1", "tags": { "role": "web" } },
+            { "id": "/subscriptions/s/vm2" },
+            { "id": "/subscriptions/s/vm3", "tags": { "role": "db" } },
+        ]});
+        assert_eq!(
+            arm::find_tagged(&vms, "role", "db"),
+            Some("/subscriptions/s/vm3")
+        );
+        assert_eq!(arm::find_tagged(&vms, "role", "cache"), None);
+    }
+
+    #[test]
+    fn gallery() {
+        let g = GalleryImage::new("images", "golden", "server");
+        assert_eq!(
+            g.id(),
+            "/resourceGroups/images/providers/Microsoft.Compute/galleries/golden/images/server"
+        );
+        let s = Setup::default().gallery_image(&g.clone().subscription("s"), Some("1.0.0"));
+        assert_eq!(
+            s.image,
+            "/subscriptions/s/resourceGroups/images/providers/Microsoft.Compute/galleries/golden/images/server/versions/1.0.0"
+        );
+        assert_eq!(s.os, None);
+        assert_eq!(image_reference(&g.id()).unwrap()["id"], g.id());
+    }
+
+    #[test]
+    fn image_specs() {
+        use crate::image::ImageSpec;
+        for spec in [
+            ImageSpec::Ubuntu2004,
+            ImageSpec::Ubuntu2204,
+            ImageSpec::Debian11,
+        ] {
+            assert!(image_reference(&spec_image(&spec).unwrap()).is_ok());
+        }
+        assert_eq!(
+            spec_image(&ImageSpec::CustomByName(String::from("golden"))),
+            None
+        );
+        let s = Setup::default().image_spec(ImageSpec::Debian11);
+        assert_eq!(s.os, Some(crate::OsFamily::Debian));
+        assert_eq!(s.image("UbuntuLTS".into()).image_spec, None);
+    }
+
     #[test]
     #[ignore]
     fn azure_resource_group() {
         let rt = tokio::runtime::Runtime::new().unwrap();
         static TEST_RG_NAME: &str = "test";
         rt.block_on(async move {
-            azcmd::create_resource_group(Region::EastUs, TEST_RG_NAME)
+            let client = arm::Client::default();
+            client
+                .create_resource_group(Region::EastUs, TEST_RG_NAME)
                 .await
                 .expect("create resource group test failed");
 
-            azcmd::delete_resource_group(TEST_RG_NAME)
+            client
+                .delete_resource_group(TEST_RG_NAME)
                 .await
                 .expect("delete resource group failed");
         })
@@ -760,9 +2828,7 @@ mod test {
             tracing::debug!("connecting");
             let vms = l.connect_all().await?;
             tracing::debug!("get machine");
-            let my_machine = vms
-                .get("foo")
-                .ok_or_else(|| eyre::format_err!("machine not found"))?;
+            let my_machine = vms.get("foo").ok_or_else(|| eyre!("machine not found"))?;
             tracing::debug!("running command");
             my_machine
                 .ssh
@@ -784,7 +2850,7 @@ mod test {
         rt.block_on(async move {
             if let Err(e) = do_make_machine_and_ssh_setupfn(&mut azure).await {
                 azure.terminate_all().await.unwrap();
-                panic!(e);
+                panic!("{}", e);
             } else {
                 azure.terminate_all().await.unwrap();
             }
@@ -2,15 +2,17 @@
 //!
 //! Use this to use machines that already exist.
 
+use crate::run::quote;
 use color_eyre::{
     eyre::{self, eyre, WrapErr},
-    Report,
+    Report, Section,
 };
 use educe::Educe;
 use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tracing::instrument;
 use tracing_futures::Instrument;
 
@@ -23,6 +25,11 @@ pub struct Setup {
     addr: Vec<std::net::SocketAddr>,
     username: String,
     key_path: Option<std::path::PathBuf>,
+    public_dns: Option<String>,
+    os: Option<crate::OsFamily>,
+    lease: Option<(String, Duration)>,
+    lease_path: String,
+    window: Option<Window>,
     #[educe(Debug(ignore))]
     setup_fn: Option<
         Arc<
@@ -37,10 +44,34 @@ pub struct Setup {
     >,
 }
 
+/// The [`MachineSetup::Region`](super::MachineSetup::Region) for [`Setup`].
+///
+/// Every baremetal machine is its own region. It is displayed as `bare:` followed by the
+/// machine's first address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Host(pub std::net::SocketAddr);
+
+impl std::fmt::Display for Host {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bare:{}", self.0)
+    }
+}
+
+impl std::str::FromStr for Host {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let addr = s
+            .strip_prefix("bare:")
+            .ok_or_else(|| eyre!("baremetal region {:?} does not start with bare:", s))?;
+        Ok(Host(addr.parse()?))
+    }
+}
+
 impl super::MachineSetup for Setup {
-    type Region = String;
+    type Region = Host;
     fn region(&self) -> Self::Region {
-        format!("bare:{}", self.addr[0])
+        Host(self.addr[0])
     }
 }
 
@@ -72,6 +103,11 @@ impl Setup {
             username,
             addr,
             key_path: None,
+            public_dns: None,
+            os: None,
+            lease: None,
+            lease_path: String::from(DEFAULT_LEASE_PATH),
+            window: None,
             setup_fn: None,
         })
     }
@@ -84,6 +120,86 @@ impl Setup {
         }
     }
 
+    /// Set the machine's public DNS name.
+    ///
+    /// If this is not set, the name is found with a reverse lookup of the machine's address.
+    pub fn public_dns(self, name: impl ToString) -> Self {
+        Self {
+            public_dns: Some(name.to_string()),
+            ..self
+        }
+    }
+
+    /// Declare which operating system family the machine runs.
+    ///
+    /// The family is available to setup functions as [`crate::Machine::os`]. If it is not set,
+    /// [`crate::OsFamily::detect`] can determine it.
+    pub fn os(self, os: crate::OsFamily) -> Self {
+        Self {
+            os: Some(os),
+            ..self
+        }
+    }
+
+    /// Lease the machine to `holder` for `ttl` before using it.
+    ///
+    /// Machines in a shared lab cluster are often used by several people, whose experiments
+    /// interfere if they run at the same time. With a lease, launching fails if someone else
+    /// holds an unexpired lease on the machine, and tells you who it is. Otherwise, the lease is
+    /// taken (or extended, if `holder` already held it), and it is given up when the tsunami is
+    /// terminated. Use [`Machine::renew_lease`] to hold on to the machine for longer than `ttl`.
+    ///
+    /// `holder` should identify you to the others, e.g. `"alice@lab-laptop"`. The lease is a file
+    /// on the machine, see [`lease_path`](Setup::lease_path).
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use tsunami::providers::baremetal::Setup;
+    ///
+    /// let m = Setup::new("127.0.0.1:22", None)
+    ///     .unwrap()
+    ///     .lease("alice", Duration::from_secs(2 * 60 * 60));
+    /// ```
+    pub fn lease(self, holder: impl ToString, ttl: Duration) -> Self {
+        Self {
+            lease: Some((holder.to_string(), ttl)),
+            ..self
+        }
+    }
+
+    /// Set where the [lease](Setup::lease) file is kept on the machine.
+    ///
+    /// Everyone sharing the machine must use the same path. Its directory is created if it does
+    /// not exist, and must be writable by all of them, but not sticky: in a sticky directory like
+    /// `/tmp`, one user cannot replace another's lease (and `fs.protected_regular` stops them from
+    /// writing it), so taking the lease fails. The default is [`DEFAULT_LEASE_PATH`].
+    pub fn lease_path(self, path: impl ToString) -> Self {
+        Self {
+            lease_path: path.to_string(),
+            ..self
+        }
+    }
+
+    /// Only use the machine during `window`.
+    ///
+    /// Launching fails outside the window. Once launched, use [`Machine::watch_window`] to be
+    /// warned before the window closes, and to stop the experiment when it does.
+    ///
+    /// ```rust
+    /// use tsunami::providers::baremetal::{Setup, Window};
+    ///
+    /// // nights only
+    /// let m = Setup::new("127.0.0.1:22", None)
+    ///     .unwrap()
+    ///     .window(Window::daily((22, 0), (6, 0)).unwrap());
+    /// ```
+    pub fn window(self, window: Window) -> Self {
+        Self {
+            window: Some(window),
+            ..self
+        }
+    }
+
     /// Specify instance setup.
     ///
     /// The provided callback, `setup`, is called once
@@ -119,6 +235,280 @@ impl Setup {
         self.setup_fn = Some(Arc::new(setup));
         self
     }
+
+    /// Check that the machine matches `expect` once it is up, before running the
+    /// [`setup`](Setup::setup) function.
+    ///
+    /// See the [`verify`](crate::verify) module for what happens when the check fails, and for
+    /// when to call this.
+    pub fn verify(mut self, expect: crate::verify::Expectations) -> Self {
+        self.setup_fn = Some(super::before_setup(
+            expect.into_setup_fn(),
+            self.setup_fn.take(),
+        ));
+        self
+    }
+}
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// A period during which a machine may be used. See [`Setup::window`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window(WindowKind);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WindowKind {
+    // seconds after midnight UTC
+    Daily { from: u64, to: u64 },
+    Between(SystemTime, SystemTime),
+}
+
+impl Window {
+    /// Every day from `from` until `to`, both given as `(hour, minute)` in UTC.
+    ///
+    /// If `to` is earlier than `from`, the window spans midnight. If they are the same, the
+    /// window is the whole day, and never closes. Fails if either is not a time of day.
+    pub fn daily(from: (u8, u8), to: (u8, u8)) -> Result<Self, Report> {
+        let secs = |(h, m): (u8, u8)| {
+            eyre::ensure!(h < 24 && m < 60, "{}:{:02} is not a time of day", h, m);
+            Ok(u64::from(h) * 3600 + u64::from(m) * 60)
+        };
+        Ok(Window(WindowKind::Daily {
+            from: secs(from)?,
+            to: secs(to)?,
+        }))
+    }
+
+    /// Once, from `start` until `end`, e.g. for a reservation on a shared testbed.
+    pub fn between(start: SystemTime, end: SystemTime) -> Self {
+        Window(WindowKind::Between(start, end))
+    }
+
+    /// How much of the window is left at `now`, or `None` if the window is closed at `now`.
+    ///
+    /// A window that is the whole day never closes, so this is [`Duration::MAX`] for it.
+    pub fn remaining(&self, now: SystemTime) -> Option<Duration> {
+        match self.0 {
+            WindowKind::Daily { from, to } if from == to => Some(Duration::MAX),
+            WindowKind::Daily { from, to } => {
+                let (len, offset) = daily_offset(from, to, now);
+                if offset < len {
+                    Some(Duration::from_secs(len - offset))
+                } else {
+                    None
+                }
+            }
+            WindowKind::Between(start, end) if start <= now => end
+                .duration_since(now)
+                .ok()
+                .filter(|d| *d > Duration::from_secs(0)),
+            WindowKind::Between(..) => None,
+        }
+    }
+
+    /// How long after `now` the window opens next, or `None` if it never opens again.
+    ///
+    /// This is zero if the window is open at `now`.
+    pub fn opens_in(&self, now: SystemTime) -> Option<Duration> {
+        if self.remaining(now).is_some() {
+            return Some(Duration::from_secs(0));
+        }
+        match self.0 {
+            WindowKind::Daily { from, to } => {
+                let (_, offset) = daily_offset(from, to, now);
+                Some(Duration::from_secs(DAY - offset))
+            }
+            WindowKind::Between(start, _) => start.duration_since(now).ok(),
+        }
+    }
+}
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            WindowKind::Daily { from, to } if from == to => write!(f, "all day"),
+            WindowKind::Daily { from, to } => write!(
+                f,
+                "daily from {:02}:{:02} to {:02}:{:02} UTC",
+                from / 3600,
+                from % 3600 / 60,
+                to / 3600,
+                to % 3600 / 60
+            ),
+            WindowKind::Between(start, end) => {
+                let secs = |t: SystemTime| {
+                    t.duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs()
+                };
+                write!(f, "from {} until {} (unix time)", secs(start), secs(end))
+            }
+        }
+    }
+}
+
+// the length of a daily window, and how far into the window (modulo a day) `now` is.
+fn daily_offset(from: u64, to: u64, now: SystemTime) -> (u64, u64) {
+    let t = now
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        % DAY;
+    let len = match (to + DAY - from) % DAY {
+        0 => DAY,
+        len => len,
+    };
+    (len, (t + DAY - from) % DAY)
+}
+
+/// Where [leases](Setup::lease) are kept on the machines by default.
+///
+/// The lease is in a directory of its own, which is created writable by everyone and without the
+/// sticky bit of `/tmp`, so that one user can take over another's expired lease.
+pub const DEFAULT_LEASE_PATH: &str = "/tmp/tsunami-lease/lease";
+
+// A lease on a machine. The lease file holds the unix time at which the lease expires, and the
+// holder. Changes to it are made under `flock` on `<path>.lock`, so that two launchers cannot both
+// take over an expired lease, and are written to a new file that is renamed into place.
+#[derive(Debug, Clone)]
+struct Lease {
+    holder: String,
+    ttl: Duration,
+    path: String,
+}
+
+// Takes the lock on the lease `$f` for the rest of the script.
+const LOCK_SCRIPT: &str = r#"command -v flock > /dev/null || { echo "flock is not installed" >&2; exit 2; }
+exec 9>> "$f.lock" || exit 2
+flock -w 10 9 || { echo "timed out waiting for $f.lock" >&2; exit 2; }"#;
+
+impl Lease {
+    // Takes the lease if it is free, expired, or already ours. Exits with 1 and prints the
+    // current lease if someone else holds it.
+    fn acquire_script(&self) -> String {
+        format!(
+            r#"f={path}; me={holder}; d=$(dirname "$f"); umask 0
+[ -d "$d" ] || mkdir -p -m 0777 "$d" || exit 2
+if [ -k "$d" ]; then echo "$d is sticky, so expired leases cannot be taken over" >&2; exit 2; fi
+{lock}
+now=$(date +%s); until=$((now + {ttl}))
+if [ -s "$f" ]; then
+  read -r t who < "$f" || exit 2
+  if [ "$who" != "$me" ] && [ "$t" -gt "$now" ]; then echo "$t $who"; exit 1; fi
+fi
+echo "$until $me" > "$f.$$" && mv -f "$f.$$" "$f""#,
+            path = quote(&self.path),
+            holder = quote(&self.holder),
+            lock = LOCK_SCRIPT,
+            ttl = self.ttl.as_secs(),
+        )
+    }
+
+    // Gives up the lease if it is ours.
+    fn release_script(&self) -> String {
+        format!(
+            r#"f={path}; me={holder}; umask 0
+[ -f "$f" ] || exit 0
+{lock}
+read -r t who < "$f" 2>/dev/null || exit 0
+if [ "$who" = "$me" ]; then rm -f "$f"; fi"#,
+            path = quote(&self.path),
+            holder = quote(&self.holder),
+            lock = LOCK_SCRIPT,
+        )
+    }
+
+    #[instrument(level = "debug", skip(self, m), fields(holder = %self.holder))]
+    async fn acquire(&self, m: &crate::Machine<'_>) -> Result<(), Report> {
+        let out = m
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(self.acquire_script())
+            .output()
+            .await
+            .wrap_err("failed to run lease script")?;
+        if out.status.code() == Some(1) {
+            let (holder, until) = parse_lease(&String::from_utf8_lossy(&out.stdout))
+                .ok_or_else(|| eyre!("machine is leased by someone else"))?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            return Err(eyre!(
+                "machine is leased by {} for another {} minutes",
+                holder,
+                until.saturating_sub(now) / 60 + 1
+            ))
+            .suggestion(
+                "Wait for the lease to expire, or ask its holder to terminate their tsunami",
+            );
+        }
+        eyre::ensure!(
+            out.status.success(),
+            "failed to take lease at {}: {}",
+            self.path,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+        tracing::debug!("took lease");
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip(self, m), fields(holder = %self.holder))]
+    async fn release(&self, m: &crate::Machine<'_>) -> Result<(), Report> {
+        let out = m
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(self.release_script())
+            .output()
+            .await
+            .wrap_err("failed to run lease script")?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to give up lease at {}: {}",
+            self.path,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+        tracing::debug!("gave up lease");
+        Ok(())
+    }
+}
+
+// parses the `<expiry> <holder>` line the lease script prints when someone else holds the lease.
+fn parse_lease(out: &str) -> Option<(String, u64)> {
+    let mut line = out.lines().next()?.splitn(2, ' ');
+    let until = line.next()?.parse().ok()?;
+    let holder = line.next()?.trim();
+    if holder.is_empty() {
+        return None;
+    }
+    Some((holder.to_string(), until))
+}
+
+// reverse-resolves `ip` using the system resolver (`getent hosts`).
+#[instrument(level = "trace")]
+async fn reverse_lookup(ip: std::net::IpAddr) -> Option<String> {
+    let out = tokio::process::Command::new("getent")
+        .arg("hosts")
+        .arg(ip.to_string())
+        .output()
+        .await
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    parse_getent(&String::from_utf8_lossy(&out.stdout))
+}
+
+// picks the canonical name out of e.g. `192.0.2.1       host.example.com host`
+fn parse_getent(out: &str) -> Option<String> {
+    out.lines()
+        .next()?
+        .split_whitespace()
+        .nth(1)
+        .filter(|name| name.contains('.'))
+        .map(String::from)
 }
 
 #[instrument(level = "trace", skip(s, max_wait))]
@@ -137,6 +527,9 @@ async fn try_addrs(
                 public_dns: None,
                 public_ip: addr.ip().to_string(),
                 private_ip: None,
+                os: s.os,
+                proxy_command: None,
+                check_host_key: true,
                 _tsunami: Default::default(),
             };
 
@@ -178,12 +571,107 @@ async fn try_addrs(
 /// be ignored, since it doesn't make sense to connect to the same machine twice.
 ///
 /// The `impl Drop` of this type is a no-op, since Tsunami can't terminate an existing machine.
-#[derive(Debug, Default)]
+#[derive(Default, Educe)]
+#[educe(Debug)]
 pub struct Machine {
     name: String,
     addr: Option<std::net::SocketAddr>,
     username: String,
     key_path: Option<std::path::PathBuf>,
+    public_dns: Option<String>,
+    os: Option<crate::OsFamily>,
+    lease: Option<Lease>,
+    window: Option<Window>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<super::SetupFn>,
+}
+
+impl Machine {
+    /// Extend the [lease](Setup::lease) on the machine by its `ttl`, starting now.
+    ///
+    /// This fails if the lease expired in the meantime and someone else took it.
+    #[instrument(level = "debug")]
+    pub async fn renew_lease(&self) -> Result<(), Report> {
+        let lease = self
+            .lease
+            .as_ref()
+            .ok_or_else(|| eyre!("machine was not leased"))?;
+        let m = self.connect().await?;
+        lease.acquire(&m).await
+    }
+
+    /// Wait until the machine's [usage window](Setup::window) closes, and then fail.
+    ///
+    /// A warning is logged `warn_before` the window closes. This does not stop anything by
+    /// itself: it is up to the caller to run it alongside the experiment, and to stop the
+    /// experiment and [terminate](super::Launcher::terminate_all) the tsunami when it fails. If
+    /// the machine has no usage window, or one that is the whole day, this never finishes.
+    ///
+    /// ```rust,no_run
+    /// # async fn f(m: tsunami::providers::baremetal::Machine) -> Result<(), color_eyre::Report> {
+    /// use std::time::Duration;
+    /// use tsunami::Tsunami;
+    ///
+    /// let experiment = async {
+    ///     // ... run the experiment on m ...
+    ///     Ok::<_, color_eyre::Report>(())
+    /// };
+    /// let res = tokio::select! {
+    ///     res = experiment => res,
+    ///     res = m.watch_window(Duration::from_secs(10 * 60)) => res,
+    /// };
+    /// m.terminate_all().await?;
+    /// res
+    /// # }
+    /// ```
+    #[instrument(level = "debug")]
+    pub async fn watch_window(&self, warn_before: Duration) -> Result<(), Report> {
+        let window = match self.window {
+            Some(w) if w.remaining(SystemTime::now()) != Some(Duration::MAX) => w,
+            _ => return std::future::pending().await,
+        };
+        let remaining = window
+            .remaining(SystemTime::now())
+            .ok_or_else(|| eyre!("usage window ({}) of {} is closed", window, self.name))?;
+        if let Some(until_warning) = remaining.checked_sub(warn_before) {
+            tokio::time::sleep(until_warning).await;
+        }
+        tracing::warn!(
+            name = %self.name,
+            "usage window closes in {} minutes",
+            window
+                .remaining(SystemTime::now())
+                .unwrap_or_default()
+                .as_secs()
+                / 60
+        );
+        if let Some(remaining) = window.remaining(SystemTime::now()) {
+            tokio::time::sleep(remaining).await;
+        }
+        Err(eyre!("usage window ({}) of {} closed", window, self.name))
+    }
+
+    async fn connect(&self) -> Result<crate::Machine<'_>, Report> {
+        let (m, port) = self.descriptor()?;
+        m.connect_ssh(&self.username, self.key_path.as_deref(), None, port)
+            .await
+    }
+
+    // How to reach the machine, and on which port.
+    fn descriptor<'l>(&self) -> Result<(crate::MachineDescriptor<'l>, u16), Report> {
+        let addr = self.addr.ok_or_else(|| eyre!("Address uninitialized"))?;
+        let m = crate::MachineDescriptor {
+            nickname: self.name.clone(),
+            public_dns: self.public_dns.clone(),
+            public_ip: addr.ip().to_string(),
+            private_ip: None,
+            os: self.os,
+            proxy_command: None,
+            check_host_key: true,
+            _tsunami: Default::default(),
+        };
+        Ok((m, addr.port()))
+    }
 }
 
 impl super::Launcher for Machine {
@@ -207,37 +695,89 @@ impl super::Launcher for Machine {
                 );
             }
 
+            if let Some(window) = setup.window {
+                let now = SystemTime::now();
+                if window.remaining(now).is_none() {
+                    let err = eyre!("{} may only be used {}", setup.addr[0], window);
+                    return Err(match window.opens_in(now) {
+                        Some(d) => err.suggestion(format!(
+                            "The window opens again in {} minutes",
+                            d.as_secs() / 60 + 1
+                        )),
+                        None => err.note("The window will not open again"),
+                    });
+                }
+            }
+
+            super::report_progress(&name, super::MachineState::Booting);
             let addr = try_addrs(&mut setup, l.max_wait)
                 .await
                 .wrap_err("failed to find valid baremetal address")?;
+            let public_dns = match setup.public_dns.take() {
+                Some(dns) => Some(dns),
+                None => reverse_lookup(addr.ip()).await,
+            };
 
-            if let Setup {
-                ref username,
-                ref key_path,
-                setup_fn: Some(ref f),
-                ..
-            } = setup
-            {
+            let lease = setup.lease.take().map(|(holder, ttl)| Lease {
+                holder,
+                ttl,
+                path: setup.lease_path.clone(),
+            });
+
+            if lease.is_some() || setup.setup_fn.is_some() {
+                let Setup {
+                    ref username,
+                    ref key_path,
+                    ref setup_fn,
+                    ..
+                } = setup;
                 let m = crate::MachineDescriptor {
                     nickname: Default::default(),
-                    public_dns: None,
+                    public_dns: public_dns.clone(),
                     public_ip: addr.ip().to_string(),
                     private_ip: None,
+                    os: setup.os,
+                    proxy_command: None,
+                    check_host_key: true,
                     _tsunami: Default::default(),
                 };
 
                 let mut m = m
-                    .connect_ssh(&username, key_path.as_deref(), l.max_wait, addr.port())
+                    .connect_ssh(username, key_path.as_deref(), l.max_wait, addr.port())
                     .await?;
 
-                f(&mut m).await.wrap_err("setup procedure failed")?;
+                if let Some(ref lease) = lease {
+                    lease
+                        .acquire(&m)
+                        .await
+                        .wrap_err_with(|| format!("failed to lease {}", addr))?;
+                }
+
+                if let Some(f) = setup_fn {
+                    super::report_progress(&name, super::MachineState::SettingUp);
+                    if let Err(e) = f(&mut m).await {
+                        super::report_progress(&name, super::MachineState::SetupFailed);
+                        if let Some(ref lease) = lease {
+                            if let Err(e) = lease.release(&m).await {
+                                tracing::warn!("failed to give up lease: {:?}", e);
+                            }
+                        }
+                        return Err(e.wrap_err("setup procedure failed"));
+                    }
+                }
             }
 
             tracing::info!("instance ready");
+            super::report_progress(&name, super::MachineState::Ready);
             self.name = name;
             self.addr = Some(addr);
             self.username = setup.username;
             self.key_path = setup.key_path;
+            self.public_dns = public_dns;
+            self.os = setup.os;
+            self.lease = lease;
+            self.window = setup.window;
+            self.setup_fn = setup.setup_fn;
             Ok(())
         })
     }
@@ -249,18 +789,7 @@ impl super::Launcher for Machine {
         Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
     > {
         Box::pin(async move {
-            let addr = self.addr.ok_or_else(|| eyre!("Address uninitialized"))?;
-            let m = crate::MachineDescriptor {
-                nickname: self.name.clone(),
-                public_dns: None,
-                public_ip: addr.ip().to_string(),
-                private_ip: None,
-                _tsunami: Default::default(),
-            };
-
-            let m = m
-                .connect_ssh(&self.username, self.key_path.as_deref(), None, addr.port())
-                .await?;
+            let m = self.connect().await?;
 
             let mut hmap: HashMap<String, crate::Machine<'l>> = Default::default();
             hmap.insert(self.name.clone(), m);
@@ -268,8 +797,58 @@ impl super::Launcher for Machine {
         })
     }
 
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        let (m, port) = self.descriptor()?;
+        let m = m.lazy(&self.username, self.key_path.as_deref(), port);
+        Ok(std::iter::once((self.name.clone(), m)).collect())
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        Ok(self
+            .setup_fn
+            .iter()
+            .map(|f| (self.name.clone(), f.clone()))
+            .collect())
+    }
+
+    #[instrument(level = "debug")]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, super::MachineState>, Report>> + Send + 'l>,
+    > {
+        Box::pin(async move {
+            let mut hmap = HashMap::new();
+            if let Some(addr) = self.addr {
+                let check_host_key = self.descriptor().map_or(true, |(m, _)| m.check_host_key);
+                let state = super::ssh_state(
+                    &addr.ip().to_string(),
+                    &self.username,
+                    self.key_path.as_deref(),
+                    addr.port(),
+                    check_host_key,
+                )
+                .await;
+                hmap.insert(self.name.clone(), state);
+            }
+            Ok(hmap)
+        })
+    }
+
     fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
-        Box::pin(async move { Ok(()) })
+        Box::pin(async move {
+            // the machine itself stays up, but we let others have it.
+            if let Some(ref lease) = self.lease {
+                let released = match self.connect().await {
+                    Ok(m) => lease.release(&m).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = released {
+                    tracing::warn!(addr = ?self.addr, "failed to give up lease: {:?}", e);
+                }
+            }
+            Ok(())
+        })
     }
 }
 
@@ -284,6 +863,99 @@ mod test {
     use super::*;
     use crate::providers::Launcher;
 
+    #[test]
+    fn host_roundtrip() {
+        let h = Host("127.0.0.1:22".parse().unwrap());
+        assert_eq!(h.to_string().parse::<Host>().unwrap(), h);
+        assert!("127.0.0.1:22".parse::<Host>().is_err());
+    }
+
+    #[test]
+    fn getent() {
+        assert_eq!(
+            parse_getent("192.0.2.1       host.example.com host\n"),
+            Some(String::from("host.example.com"))
+        );
+        assert_eq!(parse_getent("127.0.0.1       localhost\n"), None);
+        assert_eq!(parse_getent(""), None);
+    }
+
+    #[test]
+    fn lease() {
+        assert_eq!(
+            parse_lease("1700000000 alice@lab laptop\n"),
+            Some((String::from("alice@lab laptop"), 1700000000))
+        );
+        assert_eq!(parse_lease("1700000000\n"), None);
+        assert_eq!(parse_lease("soon alice\n"), None);
+
+        let lease = Lease {
+            holder: String::from("bob's"),
+            ttl: Duration::from_secs(3600),
+            path: String::from(DEFAULT_LEASE_PATH),
+        };
+        let script = lease.acquire_script();
+        assert!(script.starts_with("f='/tmp/tsunami-lease/lease'; me='bob'\\''s';"));
+        assert!(script.contains("flock -w 10 9"));
+        assert!(script.contains("$((now + 3600))"));
+        assert!(lease.release_script().contains("me='bob'\\''s'"));
+    }
+
+    #[test]
+    fn window() {
+        let at = |h: u64, m: u64| std::time::UNIX_EPOCH + Duration::from_secs(h * 3600 + m * 60);
+        let mins = |m: u64| Duration::from_secs(m * 60);
+
+        let day = Window::daily((9, 0), (17, 30)).unwrap();
+        assert_eq!(day.remaining(at(10, 0)), Some(mins(450)));
+        assert_eq!(day.remaining(at(17, 30)), None);
+        assert_eq!(day.opens_in(at(10, 0)), Some(mins(0)));
+        assert_eq!(day.opens_in(at(18, 0)), Some(mins(15 * 60)));
+        assert_eq!(day.opens_in(at(24 + 8, 0)), Some(mins(60)));
+        assert_eq!(day.to_string(), "daily from 09:00 to 17:30 UTC");
+
+        let night = Window::daily((22, 0), (6, 0)).unwrap();
+        assert_eq!(night.remaining(at(23, 0)), Some(mins(7 * 60)));
+        assert_eq!(night.remaining(at(24 + 5, 0)), Some(mins(60)));
+        assert_eq!(night.remaining(at(12, 0)), None);
+        assert_eq!(night.opens_in(at(12, 0)), Some(mins(10 * 60)));
+
+        let all_day = Window::daily((3, 0), (3, 0)).unwrap();
+        assert_eq!(all_day.remaining(at(2, 59)), Some(Duration::MAX));
+        assert_eq!(all_day.remaining(at(3, 0)), Some(Duration::MAX));
+        assert_eq!(all_day.opens_in(at(3, 0)), Some(mins(0)));
+        assert_eq!(all_day.to_string(), "all day");
+
+        assert!(Window::daily((24, 0), (6, 0)).is_err());
+        assert!(Window::daily((22, 0), (6, 60)).is_err());
+
+        let once = Window::between(at(1, 0), at(2, 0));
+        assert_eq!(once.remaining(at(0, 0)), None);
+        assert_eq!(once.opens_in(at(0, 0)), Some(mins(60)));
+        assert_eq!(once.remaining(at(1, 15)), Some(mins(45)));
+        assert_eq!(once.remaining(at(2, 0)), None);
+        assert_eq!(once.opens_in(at(3, 0)), None);
+    }
+
+    #[test]
+    fn describe_without_connecting() {
+        let mut m = Machine {
+            name: String::from("db"),
+            username: String::from("alice"),
+            ..Default::default()
+        };
+        assert!(m.describe_all().is_err());
+
+        m.addr = Some("192.0.2.1:2222".parse().unwrap());
+        let ms = m.describe_all().unwrap();
+        let db = &ms["db"];
+        assert_eq!(db.public_ip, "192.0.2.1");
+        assert_eq!(db.public_dns, "192.0.2.1");
+        assert_eq!(db.port, 2222);
+        assert_eq!(db.username, "alice");
+        assert!(!db.is_connected());
+    }
+
     #[test]
     #[ignore]
     fn localhost() -> Result<(), Report> {
@@ -291,7 +963,7 @@ mod test {
         let s = super::Setup::new("127.0.0.1:22", None)?;
         let mut m: super::Machine = Default::default();
         let desc = crate::providers::LaunchDescriptor {
-            region: String::from("localhost"),
+            region: Host("127.0.0.1:22".parse().unwrap()),
             max_wait: None,
             machines: vec![(String::from("self"), s)],
         };
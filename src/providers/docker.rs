@@ -0,0 +1,706 @@
+//! Local Docker backend for tsunami.
+//!
+//! Every machine is a container on the local Docker engine, so experiment setup functions can be
+//! tried out in seconds, and for free, before they are run on cloud machines.
+//!
+//! Containers run an image that is built on first use from [`Setup::base_image`] (by default
+//! `ubuntu:22.04`) with `sshd` and passwordless `sudo` added. They are reached over SSH like any
+//! other machine: each container's port 22 is published on a random port of `127.0.0.1`, which
+//! is available as [`crate::Machine::port`]. All containers of a launcher share a Docker network,
+//! and their addresses on it are their [`crate::Machine::private_ip`]s.
+//!
+//! The host keys are part of the built image, so every container of an image presents the same
+//! host key. If an image is rebuilt (e.g., after `docker image prune`), stale
+//! `[127.0.0.1]:<port>` entries in `~/.ssh/known_hosts` have to be removed.
+//!
+//! Containers and the network are removed by [`terminate_all`](super::Launcher::terminate_all).
+//! Leftovers can be found with `docker ps -a --filter label=tsunami` and
+//! `docker network ls --filter label=tsunami`.
+//!
+//! # Example
+//! ```rust,no_run
+//! use tsunami::providers::docker;
+//! use tsunami::Tsunami;
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     let mut l = docker::Launcher::default();
+//!     let m = docker::Setup::default().setup(|vm| {
+//!         Box::pin(async move {
+//!             vm.ssh
+//!                 .command("sudo")
+//!                 .arg("apt-get")
+//!                 .arg("update")
+//!                 .status()
+//!                 .await?;
+//!             Ok(())
+//!         })
+//!     });
+//!     l.spawn(tsunami::make_multiple(3, "vm", m), None).await?;
+//!     let vms = l.connect_all().await?;
+//!     assert_eq!(vms.len(), 3);
+//!     l.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use color_eyre::{
+    eyre::{self, eyre, WrapErr},
+    Report,
+};
+use educe::Educe;
+use std::collections::{hash_map::Entry, HashMap};
+use std::ffi::OsStr;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+/// Descriptor for a single container.
+///
+/// The default is an `ubuntu:22.04` container logged into as `ubuntu`.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct Setup {
+    base_image: String,
+    username: String,
+    os: Option<crate::OsFamily>,
+    args: Vec<String>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<
+        Arc<
+            dyn for<'r> Fn(
+                    &'r crate::Machine<'_>,
+                )
+                    -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >,
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Setup {
+            base_image: String::from("ubuntu:22.04"),
+            username: String::from("ubuntu"),
+            os: Some(crate::OsFamily::Ubuntu),
+            args: Vec::new(),
+            setup_fn: None,
+        }
+    }
+}
+
+/// The [`MachineSetup::Region`](super::MachineSetup::Region) for [`Setup`].
+///
+/// All containers run on the local Docker engine. It is displayed as `docker:local`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Local;
+
+impl std::fmt::Display for Local {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("docker:local")
+    }
+}
+
+impl std::str::FromStr for Local {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        eyre::ensure!(s == "docker:local", "unknown docker region {:?}", s);
+        Ok(Local)
+    }
+}
+
+impl super::MachineSetup for Setup {
+    type Region = Local;
+
+    fn region(&self) -> Self::Region {
+        Local
+    }
+}
+
+impl Setup {
+    /// Set the image that the container image is built from.
+    ///
+    /// The image must be Debian-based, since `sshd` and `sudo` are installed with `apt-get`.
+    ///
+    /// This clears any previously set [`Setup::os`].
+    pub fn base_image(mut self, image: impl ToString) -> Self {
+        self.base_image = image.to_string();
+        self.os = None;
+        self
+    }
+
+    /// Build the container image from the one described by `image`.
+    ///
+    /// [`ImageSpec::CustomByName`](crate::image::ImageSpec::CustomByName) and
+    /// [`ImageSpec::ProviderSpecific`](crate::image::ImageSpec::ProviderSpecific) name the base
+    /// image directly. Images of a known operating system also set [`Setup::os`].
+    pub fn image_spec(self, image: crate::image::ImageSpec) -> Self {
+        use crate::image::ImageSpec;
+        let os = image.os();
+        let base = match image {
+            ImageSpec::Ubuntu2004 => String::from("ubuntu:20.04"),
+            ImageSpec::Ubuntu2204 => String::from("ubuntu:22.04"),
+            ImageSpec::Debian11 => String::from("debian:11"),
+            ImageSpec::CustomByName(image) | ImageSpec::ProviderSpecific(image) => image,
+        };
+        Self {
+            os,
+            ..self.base_image(base)
+        }
+    }
+
+    /// Declare which operating system family the base image runs.
+    ///
+    /// The family is available to setup functions as [`crate::Machine::os`].
+    pub fn os(mut self, os: crate::OsFamily) -> Self {
+        self.os = Some(os);
+        self
+    }
+
+    /// Set the username.
+    ///
+    /// The user is created in the image if the base image does not have it already.
+    pub fn username(mut self, username: impl ToString) -> Self {
+        self.username = username.to_string();
+        self
+    }
+
+    /// Pass an extra argument to `docker run`, such as `--cpus=2` or `--memory=1g`.
+    pub fn arg(mut self, arg: impl ToString) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Specify container setup.
+    ///
+    /// The provided callback, `setup`, is called once for every spawned container of this type
+    /// with a handle to the container. Use [`crate::Machine::ssh`] to issue commands in it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use tsunami::providers::docker::Setup;
+    /// let m = Setup::default().setup(|vm| {
+    ///     Box::pin(async move {
+    ///         vm.ssh
+    ///             .command("sudo")
+    ///             .arg("apt-get")
+    ///             .arg("update")
+    ///             .status()
+    ///             .await?;
+    ///         Ok(())
+    ///     })
+    /// });
+    /// ```
+    pub fn setup(
+        mut self,
+        setup: impl for<'r> Fn(
+                &'r crate::Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.setup_fn = Some(Arc::new(setup));
+        self
+    }
+
+    /// Check that the container matches `expect` once it is up, before running the
+    /// [`setup`](Setup::setup) function.
+    ///
+    /// See the [`verify`](crate::verify) module for what happens when the check fails, and for
+    /// when to call this.
+    pub fn verify(mut self, expect: crate::verify::Expectations) -> Self {
+        self.setup_fn = Some(super::before_setup(
+            expect.into_setup_fn(),
+            self.setup_fn.take(),
+        ));
+        self
+    }
+
+    // The tag of the image built for this setup.
+    fn image_tag(&self) -> String {
+        let tag: String = format!("{}-{}", self.base_image, self.username)
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '.' | '-' => c,
+                _ => '-',
+            })
+            .take(128)
+            .collect();
+        format!("tsunami-sshd:{}", tag)
+    }
+
+    // The Dockerfile of the image built for this setup.
+    //
+    // The public key is passed in when a container starts, so that one image serves every
+    // launcher.
+    fn dockerfile(&self) -> String {
+        let u = &self.username;
+        format!(
+            r#"FROM {base}
+RUN apt-get update \
+ && DEBIAN_FRONTEND=noninteractive apt-get install -y --no-install-recommends openssh-server sudo \
+ && rm -rf /var/lib/apt/lists/* \
+ && mkdir -p /run/sshd
+RUN (id -u {u} >/dev/null 2>&1 || useradd --create-home --shell /bin/bash {u}) \
+ && echo '{u} ALL=(ALL) NOPASSWD:ALL' > /etc/sudoers.d/tsunami
+CMD mkdir -p /home/{u}/.ssh \
+ && echo "$TSUNAMI_AUTHORIZED_KEY" > /home/{u}/.ssh/authorized_keys \
+ && chown -R {u}: /home/{u}/.ssh \
+ && chmod 700 /home/{u}/.ssh \
+ && exec /usr/sbin/sshd -D -e
+"#,
+            base = self.base_image,
+            u = u,
+        )
+    }
+}
+
+// Runs `docker` with `args`, and returns its trimmed stdout.
+#[instrument(level = "trace", skip(args))]
+async fn docker<I, S>(what: &str, args: I) -> Result<String, Report>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let out = tokio::process::Command::new("docker")
+        .args(args)
+        .output()
+        .await
+        .wrap_err("failed to run docker")?;
+    eyre::ensure!(
+        out.status.success(),
+        "failed to {}: {}",
+        what,
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+// Builds the image for `s`, unless it already exists.
+#[instrument(level = "debug", skip(s))]
+async fn ensure_image(s: &Setup) -> Result<String, Report> {
+    let tag = s.image_tag();
+    if docker("inspect image", ["image", "inspect", &tag])
+        .await
+        .is_ok()
+    {
+        return Ok(tag);
+    }
+
+    tracing::info!(%tag, "building container image");
+    let dir = tempfile::tempdir().wrap_err("failed to create build directory")?;
+    std::fs::write(dir.path().join("Dockerfile"), s.dockerfile())
+        .wrap_err("failed to write Dockerfile")?;
+    docker(
+        "build image",
+        [
+            OsStr::new("build"),
+            OsStr::new("--label"),
+            OsStr::new("tsunami"),
+            OsStr::new("-t"),
+            OsStr::new(&tag),
+            dir.path().as_os_str(),
+        ],
+    )
+    .await?;
+    Ok(tag)
+}
+
+// Picks the port out of `docker port` output, e.g. `127.0.0.1:49153`.
+fn parse_port(out: &str) -> Option<u16> {
+    out.lines().next()?.rsplit(':').next()?.parse().ok()
+}
+
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+struct Container {
+    name: String,
+    id: String,
+    private_ip: Option<String>,
+    port: u16,
+    username: String,
+    os: Option<crate::OsFamily>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<super::SetupFn>,
+}
+
+impl Container {
+    async fn connect<'l>(
+        &self,
+        key_path: Option<&std::path::Path>,
+        timeout: Option<Duration>,
+    ) -> Result<crate::Machine<'l>, Report> {
+        self.descriptor()
+            .connect_ssh(&self.username, key_path, timeout, self.port)
+            .await
+    }
+
+    fn descriptor<'l>(&self) -> crate::MachineDescriptor<'l> {
+        crate::MachineDescriptor {
+            nickname: self.name.clone(),
+            public_dns: None,
+            public_ip: String::from("127.0.0.1"),
+            private_ip: self.private_ip.clone(),
+            os: self.os,
+            proxy_command: None,
+            // every container gets a fresh host key on a recycled local port.
+            check_host_key: false,
+            _tsunami: Default::default(),
+        }
+    }
+
+    // The container's state as reported by docker, e.g. `running` or `exited`.
+    async fn state(&self) -> Result<String, Report> {
+        docker(
+            "inspect container",
+            ["inspect", "-f", "{{.State.Status}}", &self.id],
+        )
+        .await
+    }
+
+    // Connects once sshd in the container is up, or fails once `max_wait` (if not `None`) has
+    // elapsed or the container has stopped.
+    #[instrument(level = "trace", skip(self, key_path, max_wait), fields(name = %self.name))]
+    async fn wait_for_ssh<'l>(
+        &self,
+        key_path: Option<&std::path::Path>,
+        max_wait: Option<Duration>,
+    ) -> Result<crate::Machine<'l>, Report> {
+        let start = Instant::now();
+        loop {
+            let e = match self.connect(key_path, Some(Duration::from_secs(1))).await {
+                Ok(m) => return Ok(m),
+                Err(e) => e,
+            };
+            tracing::trace!("ssh failed: {}", e);
+
+            let state = self.state().await?;
+            if state != "running" {
+                let logs = docker("get container logs", ["logs", "--tail", "20", &self.id])
+                    .await
+                    .unwrap_or_default();
+                eyre::bail!("container is {}:\n{}", state, logs);
+            }
+            if let Some(wait_limit) = max_wait {
+                if start.elapsed() > wait_limit {
+                    return Err(e.wrap_err("wait limit reached"));
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+}
+
+/// Launcher that runs machines as containers on the local Docker engine.
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Default)]
+pub struct Launcher {
+    network: Option<String>,
+    key: Option<(tempfile::TempDir, std::path::PathBuf)>,
+    public_key: String,
+    containers: Vec<Container>,
+}
+
+impl Launcher {
+    fn private_key_path(&self) -> Option<&std::path::Path> {
+        self.key.as_ref().map(|(_, p)| p.as_path())
+    }
+
+    // Starts the container for `name`, and returns it even if it did not come up, so that it can
+    // be removed later.
+    #[instrument(level = "debug", skip(self, s, max_wait))]
+    async fn start(
+        &self,
+        name: &str,
+        s: &Setup,
+        image: &str,
+        max_wait: Option<Duration>,
+    ) -> (Option<Container>, Result<(), Report>) {
+        super::report_progress(name, super::MachineState::Booting);
+        let network = self.network.as_deref().unwrap_or("bridge");
+        let docker_name = super::rand_name_sep("container", "-");
+        let mut args = vec![
+            String::from("run"),
+            String::from("-d"),
+            format!("--name={}", docker_name),
+            format!("--network={}", network),
+            String::from("--label=tsunami"),
+            format!("--label=tsunami.nickname={}", name),
+            format!("--env=TSUNAMI_AUTHORIZED_KEY={}", self.public_key.trim()),
+            String::from("--publish=127.0.0.1::22"),
+        ];
+        args.extend(s.args.iter().cloned());
+        args.push(image.to_string());
+        let id = match docker("start container", &args).await {
+            Ok(id) => id,
+            Err(e) => return (None, Err(e)),
+        };
+
+        let mut c = Container {
+            name: name.to_string(),
+            id,
+            private_ip: None,
+            port: 0,
+            username: s.username.clone(),
+            os: s.os,
+            setup_fn: s.setup_fn.clone(),
+        };
+        let res = async {
+            let port = docker("get container port", ["port", &c.id, "22/tcp"]).await?;
+            c.port = parse_port(&port).ok_or_else(|| eyre!("unexpected port {:?}", port))?;
+            let ip = docker(
+                "inspect container",
+                [
+                    "inspect",
+                    "-f",
+                    &format!(
+                        "{{{{(index .NetworkSettings.Networks {:?}).IPAddress}}}}",
+                        network
+                    ),
+                    &c.id,
+                ],
+            )
+            .await?;
+            c.private_ip = Some(ip).filter(|ip| !ip.is_empty());
+
+            let m = c.wait_for_ssh(self.private_key_path(), max_wait).await?;
+            if let Some(ref f) = s.setup_fn {
+                tracing::debug!("setting up container");
+                super::report_progress(name, super::MachineState::SettingUp);
+                if let Err(e) = f(&m).await {
+                    super::report_progress(name, super::MachineState::SetupFailed);
+                    return Err(e.wrap_err("setup procedure failed"));
+                }
+            }
+
+            tracing::info!("container ready");
+            super::report_progress(name, super::MachineState::Ready);
+            Ok(())
+        }
+        .await;
+        (Some(c), res)
+    }
+}
+
+impl super::Launcher for Launcher {
+    type MachineDescriptor = Setup;
+
+    #[instrument(level = "debug", skip(self))]
+    fn launch<'l>(
+        &'l mut self,
+        l: super::LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(
+            async move {
+                if self.key.is_none() {
+                    let (key_dir, key, public_key) = super::generate_key().await?;
+                    self.key = Some((key_dir, key));
+                    self.public_key = public_key;
+                }
+                if self.network.is_none() {
+                    let network = super::rand_name_sep("network", "-");
+                    docker(
+                        "create network",
+                        ["network", "create", "--label=tsunami", &network],
+                    )
+                    .await?;
+                    self.network = Some(network);
+                }
+
+                let mut images = HashMap::new();
+                for (_, s) in &l.machines {
+                    if let Entry::Vacant(e) = images.entry(s.image_tag()) {
+                        e.insert(ensure_image(s).await?);
+                    }
+                }
+
+                let this = &*self;
+                let started = futures_util::future::join_all(l.machines.iter().map(|(name, s)| {
+                    let container_span = tracing::debug_span!("container", %name);
+                    this.start(name, s, &images[&s.image_tag()], l.max_wait)
+                        .instrument(container_span)
+                }))
+                .await;
+
+                // remember the containers that did start, so that terminate_all removes them
+                // even if others failed.
+                let mut res = Ok(());
+                for (c, r) in started {
+                    self.containers.extend(c);
+                    if let (Err(e), true) = (r, res.is_ok()) {
+                        res = Err(e);
+                    }
+                }
+                res
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        Box::pin(
+            async move {
+                futures_util::future::join_all(self.containers.iter().map(|c| {
+                    let container_span = tracing::trace_span!("container", name = %c.name);
+                    async move {
+                        Ok::<_, Report>((
+                            c.name.clone(),
+                            c.connect(self.private_key_path(), None).await?,
+                        ))
+                    }
+                    .instrument(container_span)
+                }))
+                .await
+                .into_iter()
+                .collect()
+            }
+            .in_current_span(),
+        )
+    }
+
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        Ok(self
+            .containers
+            .iter()
+            .map(|c| {
+                let m = c
+                    .descriptor()
+                    .lazy(&c.username, self.private_key_path(), c.port);
+                (c.name.clone(), m)
+            })
+            .collect())
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        Ok(self
+            .containers
+            .iter()
+            .filter_map(|c| Some((c.name.clone(), c.setup_fn.clone()?)))
+            .collect())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, super::MachineState>, Report>> + Send + 'l>,
+    > {
+        Box::pin(
+            async move {
+                Ok(
+                    futures_util::future::join_all(self.containers.iter().map(|c| async move {
+                        let state = match c.state().await.as_deref() {
+                            Ok("running") => {
+                                super::ssh_state(
+                                    "127.0.0.1",
+                                    &c.username,
+                                    self.private_key_path(),
+                                    c.port,
+                                    c.descriptor().check_host_key,
+                                )
+                                .await
+                            }
+                            Ok("created") | Ok("restarting") => super::MachineState::Booting,
+                            Ok("paused") => super::MachineState::Unreachable,
+                            // exited, dead, or removed
+                            _ => super::MachineState::Terminated,
+                        };
+                        (c.name.clone(), state)
+                    }))
+                    .await
+                    .into_iter()
+                    .collect(),
+                )
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        Box::pin(
+            async move {
+                if !self.containers.is_empty() {
+                    let mut args = vec!["rm", "-f"];
+                    args.extend(self.containers.iter().map(|c| c.id.as_str()));
+                    docker("remove containers", args).await?;
+                }
+                if let Some(ref network) = self.network {
+                    docker("remove network", ["network", "rm", network]).await?;
+                }
+                Ok(())
+            }
+            .in_current_span(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::Launcher as _;
+
+    #[test]
+    fn image() {
+        let s = Setup::default();
+        assert_eq!(s.image_tag(), "tsunami-sshd:ubuntu-22.04-ubuntu");
+        let s = s.base_image("docker.io/library/debian:11").username("me");
+        assert_eq!(s.image_tag(), "tsunami-sshd:docker.io-library-debian-11-me");
+        let f = s.dockerfile();
+        assert!(f.starts_with("FROM docker.io/library/debian:11\n"));
+        assert!(f.contains("/home/me/.ssh/authorized_keys"));
+
+        let s = Setup::default().image_spec(crate::image::ImageSpec::Debian11);
+        assert_eq!(s.image_tag(), "tsunami-sshd:debian-11-ubuntu");
+        assert_eq!(s.os, Some(crate::OsFamily::Debian));
+    }
+
+    #[test]
+    fn ports() {
+        assert_eq!(parse_port("127.0.0.1:49153\n"), Some(49153));
+        assert_eq!(parse_port("127.0.0.1:32768\n[::1]:32768\n"), Some(32768));
+        assert_eq!(parse_port(""), None);
+        assert_eq!("docker:local".parse::<Local>().unwrap(), Local);
+    }
+
+    #[test]
+    #[ignore]
+    fn containers() -> Result<(), Report> {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let mut l = super::Launcher::default();
+            l.spawn(crate::make_multiple(2, "c", Setup::default()), None)
+                .await?;
+            let vms = l.connect_all().await?;
+            assert!(vms["c-1"].private_ip.is_some());
+            assert!(vms["c-0"]
+                .ssh
+                .command("sudo")
+                .arg("true")
+                .status()
+                .await?
+                .success());
+            let sorted = l.connect_all_sorted().await?;
+            assert_eq!(sorted.keys().collect::<Vec<_>>(), vec!["c-0", "c-1"]);
+            assert!(l
+                .status()
+                .await?
+                .values()
+                .all(|s| *s == crate::providers::MachineState::Ready));
+            l.terminate_all().await
+        })
+    }
+}
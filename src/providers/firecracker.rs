@@ -0,0 +1,914 @@
+//! Firecracker backend for tsunami.
+//!
+//! Every machine is a [Firecracker](https://firecracker-microvm.github.io/) microVM on the local
+//! host, booted from a kernel image and an ext4 root filesystem image. MicroVMs boot in about a
+//! second and need little memory, so hundreds of isolated hosts fit on one machine. This also
+//! makes the launcher a convenient way to benchmark lightweight virtualization itself.
+//!
+//! The launcher needs:
+//!
+//!  - the `firecracker` binary in `$PATH`, and read and write access to `/dev/kvm`;
+//!  - permission to create network devices, i.e., root or `CAP_NET_ADMIN`, since every microVM
+//!    gets a tap device;
+//!  - `debugfs` from e2fsprogs, to authorize the launcher's ssh key in the root filesystems.
+//!
+//! The root filesystem must start `sshd` at boot, and the kernel must configure its network from
+//! the `ip=` boot parameter (`CONFIG_IP_PNP`). The kernels and root filesystems of Firecracker's
+//! [getting started
+//! guide](https://github.com/firecracker-microvm/firecracker/blob/main/docs/getting-started.md)
+//! do both.
+//!
+//! Each microVM boots from a copy of the root filesystem of its own, so no changes leak between
+//! machines or runs. The copies are made with `cp --reflink=auto --sparse=always`, which is cheap
+//! on filesystems that support reflinks. The launcher's ssh key is added to the `authorized_keys`
+//! of [`Setup::username`] in the copy.
+//!
+//! All microVMs of a launcher are attached to a bridge on a private `10.x.0.0/16` network, and
+//! their addresses on it are both their [`crate::Machine::public_ip`] and their
+//! [`crate::Machine::private_ip`]. They can reach each other and the host, but not the internet,
+//! unless the host is set up to forward and masquerade their traffic.
+//!
+//! The microVMs and network devices are removed by
+//! [`terminate_all`](super::Launcher::terminate_all). If the launcher is dropped without it, the
+//! microVMs are killed, but the network devices remain; they are named `fcbr*` and `fc*-*`.
+//!
+//! # Example
+//! ```rust,no_run
+//! use tsunami::providers::firecracker;
+//! use tsunami::Tsunami;
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     let mut l = firecracker::Launcher::default();
+//!     let m = firecracker::Setup::new("vmlinux", "ubuntu-22.04.ext4")
+//!         .vcpus(1)
+//!         .memory(256);
+//!     l.spawn(tsunami::make_multiple(100, "vm", m), None).await?;
+//!     let vms = l.connect_all().await?;
+//!     assert_eq!(vms.len(), 100);
+//!     l.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use color_eyre::{
+    eyre::{self, eyre, WrapErr},
+    Help, Report,
+};
+use educe::Educe;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+/// Descriptor for a single microVM.
+///
+/// The microVM has one virtual CPU and 512 MiB of memory, and is logged into as `root`, unless
+/// configured otherwise.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct Setup {
+    kernel: PathBuf,
+    rootfs: PathBuf,
+    vcpus: u32,
+    memory_mib: u32,
+    username: String,
+    os: Option<crate::OsFamily>,
+    boot_args: Vec<String>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<
+        Arc<
+            dyn for<'r> Fn(
+                    &'r crate::Machine<'_>,
+                )
+                    -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >,
+}
+
+/// The [`MachineSetup::Region`](super::MachineSetup::Region) for [`Setup`].
+///
+/// All microVMs run on the local host. It is displayed as `firecracker:local`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Local;
+
+impl std::fmt::Display for Local {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("firecracker:local")
+    }
+}
+
+impl std::str::FromStr for Local {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        eyre::ensure!(
+            s == "firecracker:local",
+            "unknown firecracker region {:?}",
+            s
+        );
+        Ok(Local)
+    }
+}
+
+impl super::MachineSetup for Setup {
+    type Region = Local;
+
+    fn region(&self) -> Self::Region {
+        Local
+    }
+}
+
+impl Setup {
+    /// A microVM that boots the uncompressed kernel image `kernel` (a `vmlinux`) with the ext4
+    /// root filesystem image `rootfs`.
+    pub fn new(kernel: impl AsRef<Path>, rootfs: impl AsRef<Path>) -> Self {
+        Setup {
+            kernel: kernel.as_ref().to_path_buf(),
+            rootfs: rootfs.as_ref().to_path_buf(),
+            vcpus: 1,
+            memory_mib: 512,
+            username: String::from("root"),
+            os: None,
+            boot_args: Vec::new(),
+            setup_fn: None,
+        }
+    }
+
+    /// Give the microVM `vcpus` virtual CPUs.
+    pub fn vcpus(mut self, vcpus: u32) -> Self {
+        self.vcpus = vcpus;
+        self
+    }
+
+    /// Give the microVM `mib` MiB of memory.
+    pub fn memory(mut self, mib: u32) -> Self {
+        self.memory_mib = mib;
+        self
+    }
+
+    /// Set the username.
+    ///
+    /// The user must exist in the root filesystem, and have a home directory at `/home/<username>`
+    /// (or `/root` for `root`).
+    pub fn username(mut self, username: impl ToString) -> Self {
+        self.username = username.to_string();
+        self
+    }
+
+    /// Declare which operating system family the root filesystem holds.
+    ///
+    /// The family is available to setup functions as [`crate::Machine::os`].
+    pub fn os(mut self, os: crate::OsFamily) -> Self {
+        self.os = Some(os);
+        self
+    }
+
+    /// Add `arg` to the kernel command line, such as `init=/sbin/init` or `mitigations=off`.
+    pub fn boot_arg(mut self, arg: impl ToString) -> Self {
+        self.boot_args.push(arg.to_string());
+        self
+    }
+
+    /// Specify microVM setup.
+    ///
+    /// The provided callback, `setup`, is called once for every spawned microVM of this type
+    /// with a handle to the microVM. Use [`crate::Machine::ssh`] to issue commands on it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use tsunami::providers::firecracker::Setup;
+    /// let m = Setup::new("vmlinux", "rootfs.ext4").setup(|vm| {
+    ///     Box::pin(async move {
+    ///         vm.ssh
+    ///             .command("apt-get")
+    ///             .arg("update")
+    ///             .status()
+    ///             .await?;
+    ///         Ok(())
+    ///     })
+    /// });
+    /// ```
+    pub fn setup(
+        mut self,
+        setup: impl for<'r> Fn(
+                &'r crate::Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.setup_fn = Some(Arc::new(setup));
+        self
+    }
+
+    /// Check that the microVM matches `expect` once it is up, before running the
+    /// [`setup`](Setup::setup) function.
+    ///
+    /// See the [`verify`](crate::verify) module for what happens when the check fails, and for
+    /// when to call this.
+    pub fn verify(mut self, expect: crate::verify::Expectations) -> Self {
+        self.setup_fn = Some(super::before_setup(
+            expect.into_setup_fn(),
+            self.setup_fn.take(),
+        ));
+        self
+    }
+
+    fn home(&self) -> String {
+        if self.username == "root" {
+            String::from("/root")
+        } else {
+            format!("/home/{}", self.username)
+        }
+    }
+}
+
+// Runs `program` with `args`, and returns its stdout.
+#[instrument(level = "trace", skip(args))]
+async fn run<I, S>(what: &str, program: &str, args: I) -> Result<String, Report>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let out = tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .wrap_err_with(|| format!("failed to run {}", program))?;
+    eyre::ensure!(
+        out.status.success(),
+        "failed to {}: {}",
+        what,
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+// The address of the `i`th microVM on the network `10.<subnet>.0.0/16`. The host is `.0.1`.
+fn guest_ip(subnet: u8, i: usize) -> [u8; 4] {
+    let n = i + 2;
+    [10, subnet, (n / 256) as u8, (n % 256) as u8]
+}
+
+fn dotted(ip: [u8; 4]) -> String {
+    format!("{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3])
+}
+
+// The MAC address of the microVM with address `ip`, by the same convention as Firecracker's own
+// examples: a locally administered prefix, followed by the address.
+fn guest_mac(ip: [u8; 4]) -> String {
+    format!(
+        "06:00:{:02x}:{:02x}:{:02x}:{:02x}",
+        ip[0], ip[1], ip[2], ip[3]
+    )
+}
+
+// The owner and group of a file, from `debugfs -R "stat <file>"`.
+fn parse_owner(stat: &str) -> Option<(u32, u32)> {
+    let mut words = stat.split_whitespace();
+    let mut uid = None;
+    let mut gid = None;
+    while let Some(w) = words.next() {
+        match w {
+            "User:" => uid = words.next()?.parse().ok(),
+            "Group:" => gid = words.next()?.parse().ok(),
+            _ => {}
+        }
+    }
+    Some((uid?, gid?))
+}
+
+// The debugfs commands that install `keys` as the authorized keys in `home`, owned by `uid` and
+// `gid`. `mkdir` and `rm` fail harmlessly if the directory exists or the file does not.
+fn key_commands(home: &str, keys: &Path, uid: u32, gid: u32) -> String {
+    let ssh = format!("{}/.ssh", home);
+    let file = format!("{}/authorized_keys", ssh);
+    format!(
+        "mkdir {ssh}\n\
+         rm {file}\n\
+         write {keys} {file}\n\
+         set_inode_field {ssh} mode 040700\n\
+         set_inode_field {ssh} uid {uid}\n\
+         set_inode_field {ssh} gid {gid}\n\
+         set_inode_field {file} mode 0100600\n\
+         set_inode_field {file} uid {uid}\n\
+         set_inode_field {file} gid {gid}\n",
+        ssh = ssh,
+        file = file,
+        keys = keys.display(),
+        uid = uid,
+        gid = gid,
+    )
+}
+
+// Adds `public_key` to the authorized keys of `s.username` in the root filesystem `rootfs`, using
+// `dir` for scratch files.
+#[instrument(level = "trace", skip(s, public_key))]
+async fn authorize_key(
+    s: &Setup,
+    rootfs: &Path,
+    dir: &Path,
+    public_key: &str,
+) -> Result<(), Report> {
+    let debugfs = |what: &'static str, cmd: String| {
+        let args = vec![
+            OsStr::new("-R").to_os_string(),
+            cmd.into(),
+            rootfs.as_os_str().to_os_string(),
+        ];
+        async move {
+            run(what, "debugfs", args)
+                .await
+                .suggestion("Install e2fsprogs, which provides debugfs")
+        }
+    };
+
+    let home = s.home();
+    let (uid, gid) =
+        parse_owner(&debugfs("inspect home directory", format!("stat {}", home)).await?)
+            .ok_or_else(|| eyre!("root filesystem has no home directory {}", home))?;
+
+    // keep the keys that the image already authorizes
+    let keys = dir.join("authorized_keys");
+    debugfs(
+        "read authorized keys",
+        format!("dump {}/.ssh/authorized_keys {}", home, keys.display()),
+    )
+    .await?;
+    let mut authorized = std::fs::read_to_string(&keys).unwrap_or_default();
+    if !authorized.is_empty() && !authorized.ends_with('\n') {
+        authorized.push('\n');
+    }
+    authorized.push_str(public_key.trim());
+    authorized.push('\n');
+    std::fs::write(&keys, authorized).wrap_err("failed to write authorized keys")?;
+
+    let cmds = dir.join("debugfs-commands");
+    std::fs::write(&cmds, key_commands(&home, &keys, uid, gid))
+        .wrap_err("failed to write debugfs commands")?;
+    run(
+        "authorize ssh key",
+        "debugfs",
+        [
+            OsStr::new("-w"),
+            OsStr::new("-f"),
+            cmds.as_os_str(),
+            rootfs.as_os_str(),
+        ],
+    )
+    .await?;
+
+    // debugfs reports failed commands on stderr, but still exits successfully.
+    let stat = debugfs(
+        "inspect authorized keys",
+        format!("stat {}/.ssh/authorized_keys", home),
+    )
+    .await?;
+    eyre::ensure!(
+        parse_owner(&stat) == Some((uid, gid)),
+        "failed to add the ssh key to {}/.ssh/authorized_keys",
+        home
+    );
+    Ok(())
+}
+
+// The Firecracker configuration of a microVM.
+fn vm_config(s: &Setup, kernel: &Path, rootfs: &Path, tap: &str, ip: [u8; 4]) -> serde_json::Value {
+    let host = dotted([ip[0], ip[1], 0, 1]);
+    let mut boot_args = format!(
+        "console=ttyS0 reboot=k panic=1 pci=off ip={}::{}:255.255.0.0::eth0:off",
+        dotted(ip),
+        host
+    );
+    for arg in &s.boot_args {
+        boot_args.push(' ');
+        boot_args.push_str(arg);
+    }
+    serde_json::json!({
+        "boot-source": {
+            "kernel_image_path": kernel,
+            "boot_args": boot_args,
+        },
+        "drives": [{
+            "drive_id": "rootfs",
+            "path_on_host": rootfs,
+            "is_root_device": true,
+            "is_read_only": false,
+        }],
+        "machine-config": {
+            "vcpu_count": s.vcpus,
+            "mem_size_mib": s.memory_mib,
+        },
+        "network-interfaces": [{
+            "iface_id": "eth0",
+            "guest_mac": guest_mac(ip),
+            "host_dev_name": tap,
+        }],
+    })
+}
+
+#[derive(Educe)]
+#[educe(Debug)]
+struct Vm {
+    name: String,
+    tap: String,
+    ip: String,
+    username: String,
+    os: Option<crate::OsFamily>,
+    log: PathBuf,
+    process: Option<Mutex<tokio::process::Child>>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<super::SetupFn>,
+}
+
+impl Vm {
+    async fn connect<'l>(
+        &self,
+        key_path: Option<&Path>,
+        timeout: Option<Duration>,
+    ) -> Result<crate::Machine<'l>, Report> {
+        self.descriptor()
+            .connect_ssh(&self.username, key_path, timeout, 22)
+            .await
+    }
+
+    fn descriptor<'l>(&self) -> crate::MachineDescriptor<'l> {
+        crate::MachineDescriptor {
+            nickname: self.name.clone(),
+            public_dns: None,
+            public_ip: self.ip.clone(),
+            private_ip: Some(self.ip.clone()),
+            os: self.os,
+            proxy_command: None,
+            // every microVM boots from a copy of the same rootfs on a recycled tap address.
+            check_host_key: false,
+            _tsunami: Default::default(),
+        }
+    }
+
+    // Whether the firecracker process is still running.
+    fn is_running(&self) -> bool {
+        match self.process {
+            Some(ref p) => {
+                let mut p = p.lock().unwrap_or_else(|e| e.into_inner());
+                matches!(p.try_wait(), Ok(None))
+            }
+            None => false,
+        }
+    }
+
+    // The last lines of the microVM's console output.
+    fn log_tail(&self) -> String {
+        let log = std::fs::read_to_string(&self.log).unwrap_or_default();
+        let lines: Vec<_> = log.lines().collect();
+        lines[lines.len().saturating_sub(20)..].join("\n")
+    }
+
+    // Connects once sshd in the microVM is up, or fails once `max_wait` (if not `None`) has
+    // elapsed or the microVM has exited.
+    #[instrument(level = "trace", skip(self, key_path, max_wait), fields(name = %self.name))]
+    async fn wait_for_ssh<'l>(
+        &self,
+        key_path: Option<&Path>,
+        max_wait: Option<Duration>,
+    ) -> Result<crate::Machine<'l>, Report> {
+        let start = Instant::now();
+        loop {
+            let e = match self.connect(key_path, Some(Duration::from_secs(1))).await {
+                Ok(m) => return Ok(m),
+                Err(e) => e,
+            };
+            tracing::trace!("ssh failed: {}", e);
+
+            if !self.is_running() {
+                eyre::bail!("microVM exited:\n{}", self.log_tail());
+            }
+            if let Some(wait_limit) = max_wait {
+                if start.elapsed() > wait_limit {
+                    return Err(e.wrap_err("wait limit reached"));
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+}
+
+/// Launcher that runs machines as Firecracker microVMs on the local host.
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Default)]
+pub struct Launcher {
+    dir: Option<tempfile::TempDir>,
+    key: Option<(tempfile::TempDir, PathBuf)>,
+    public_key: String,
+    id: u16,
+    subnet: u8,
+    bridge: Option<String>,
+    next: usize,
+    vms: Vec<Vm>,
+}
+
+impl Launcher {
+    fn private_key_path(&self) -> Option<&Path> {
+        self.key.as_ref().map(|(_, p)| p.as_path())
+    }
+
+    // Creates the bridge the microVMs are attached to, on a random /16 of 10.200.0.0-10.254.0.0.
+    async fn make_bridge(&mut self) -> Result<(), Report> {
+        {
+            use rand::Rng;
+            let mut rng = rand::thread_rng();
+            self.id = rng.gen();
+            self.subnet = rng.gen_range(200..=254);
+        }
+        let bridge = format!("fcbr{:04x}", self.id);
+        run(
+            "create bridge",
+            "ip",
+            ["link", "add", "name", bridge.as_str(), "type", "bridge"],
+        )
+        .await
+        .suggestion("Creating network devices needs root or CAP_NET_ADMIN")?;
+        self.bridge = Some(bridge.clone());
+        let host = format!("10.{}.0.1/16", self.subnet);
+        run(
+            "address bridge",
+            "ip",
+            ["addr", "add", host.as_str(), "dev", bridge.as_str()],
+        )
+        .await?;
+        run(
+            "bring up bridge",
+            "ip",
+            ["link", "set", bridge.as_str(), "up"],
+        )
+        .await?;
+        Ok(())
+    }
+
+    // Boots the `i`th microVM as `name`, and returns it even if it did not come up, so that it can
+    // be removed later.
+    #[instrument(level = "debug", skip(self, s, max_wait))]
+    async fn start(
+        &self,
+        i: usize,
+        name: &str,
+        s: &Setup,
+        max_wait: Option<Duration>,
+    ) -> (Option<Vm>, Result<(), Report>) {
+        super::report_progress(name, super::MachineState::Booting);
+        let dir = self.dir.as_ref().unwrap().path().join(format!("vm-{}", i));
+        let bridge = self.bridge.as_deref().unwrap();
+        let ip = guest_ip(self.subnet, i);
+        let mut vm = Vm {
+            name: name.to_string(),
+            tap: format!("fc{:04x}-{}", self.id, i),
+            ip: dotted(ip),
+            username: s.username.clone(),
+            os: s.os,
+            log: dir.join("console.log"),
+            process: None,
+            setup_fn: s.setup_fn.clone(),
+        };
+
+        let tap = vm.tap.clone();
+        let created = run(
+            "create tap device",
+            "ip",
+            ["tuntap", "add", "dev", tap.as_str(), "mode", "tap"],
+        )
+        .await;
+        if let Err(e) = created {
+            return (None, Err(e));
+        }
+
+        let res = async {
+            run(
+                "attach tap device",
+                "ip",
+                ["link", "set", tap.as_str(), "master", bridge],
+            )
+            .await?;
+            run(
+                "bring up tap device",
+                "ip",
+                ["link", "set", tap.as_str(), "up"],
+            )
+            .await?;
+
+            std::fs::create_dir_all(&dir).wrap_err("failed to create microVM directory")?;
+            let kernel = std::fs::canonicalize(&s.kernel)
+                .wrap_err_with(|| format!("kernel image {} not found", s.kernel.display()))?;
+            let rootfs = dir.join("rootfs.ext4");
+            run(
+                "copy root filesystem",
+                "cp",
+                [
+                    OsStr::new("--reflink=auto"),
+                    OsStr::new("--sparse=always"),
+                    s.rootfs.as_os_str(),
+                    rootfs.as_os_str(),
+                ],
+            )
+            .await?;
+            authorize_key(s, &rootfs, &dir, &self.public_key).await?;
+
+            let config = dir.join("config.json");
+            std::fs::write(
+                &config,
+                vm_config(s, &kernel, &rootfs, &tap, ip).to_string(),
+            )
+            .wrap_err("failed to write firecracker configuration")?;
+            let log = std::fs::File::create(&vm.log).wrap_err("failed to create console log")?;
+            let child = tokio::process::Command::new("firecracker")
+                .arg("--no-api")
+                .arg("--config-file")
+                .arg(&config)
+                .current_dir(&dir)
+                .stdin(std::process::Stdio::null())
+                .stdout(log.try_clone()?)
+                .stderr(log)
+                .kill_on_drop(true)
+                .spawn()
+                .wrap_err("failed to run firecracker")
+                .suggestion(
+                    "Install firecracker from \
+                     https://github.com/firecracker-microvm/firecracker/releases",
+                )?;
+            vm.process = Some(Mutex::new(child));
+
+            let m = vm.wait_for_ssh(self.private_key_path(), max_wait).await?;
+            if let Some(ref f) = s.setup_fn {
+                tracing::debug!("setting up microVM");
+                super::report_progress(name, super::MachineState::SettingUp);
+                if let Err(e) = f(&m).await {
+                    super::report_progress(name, super::MachineState::SetupFailed);
+                    return Err(e.wrap_err("setup procedure failed"));
+                }
+            }
+
+            tracing::info!("microVM ready");
+            super::report_progress(name, super::MachineState::Ready);
+            Ok(())
+        }
+        .await;
+        (Some(vm), res)
+    }
+}
+
+impl super::Launcher for Launcher {
+    type MachineDescriptor = Setup;
+
+    #[instrument(level = "debug", skip(self))]
+    fn launch<'l>(
+        &'l mut self,
+        l: super::LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(
+            async move {
+                if self.key.is_none() {
+                    let (key_dir, key, public_key) = super::generate_key().await?;
+                    self.key = Some((key_dir, key));
+                    self.public_key = public_key;
+                }
+                if self.dir.is_none() {
+                    self.dir = Some(
+                        tempfile::Builder::new()
+                            .prefix("tsunami-firecracker")
+                            .tempdir()
+                            .wrap_err("failed to create microVM directory")?,
+                    );
+                }
+                if self.bridge.is_none() {
+                    self.make_bridge().await?;
+                }
+
+                let first = self.next;
+                eyre::ensure!(
+                    first + l.machines.len() <= 65000,
+                    "a firecracker launcher can run at most 65000 microVMs"
+                );
+                self.next += l.machines.len();
+
+                let this = &*self;
+                let started = futures_util::future::join_all(l.machines.iter().enumerate().map(
+                    |(i, (name, s))| {
+                        let vm_span = tracing::debug_span!("microvm", %name);
+                        this.start(first + i, name, s, l.max_wait)
+                            .instrument(vm_span)
+                    },
+                ))
+                .await;
+
+                // remember the microVMs that did start, so that terminate_all removes them even
+                // if others failed.
+                let mut res = Ok(());
+                for (vm, r) in started {
+                    self.vms.extend(vm);
+                    if let (Err(e), true) = (r, res.is_ok()) {
+                        res = Err(e);
+                    }
+                }
+                res
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        Box::pin(
+            async move {
+                futures_util::future::join_all(self.vms.iter().map(|vm| {
+                    let vm_span = tracing::trace_span!("microvm", name = %vm.name);
+                    async move {
+                        Ok::<_, Report>((
+                            vm.name.clone(),
+                            vm.connect(self.private_key_path(), None).await?,
+                        ))
+                    }
+                    .instrument(vm_span)
+                }))
+                .await
+                .into_iter()
+                .collect()
+            }
+            .in_current_span(),
+        )
+    }
+
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        Ok(self
+            .vms
+            .iter()
+            .map(|vm| {
+                let m = vm
+                    .descriptor()
+                    .lazy(&vm.username, self.private_key_path(), 22);
+                (vm.name.clone(), m)
+            })
+            .collect())
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        Ok(self
+            .vms
+            .iter()
+            .filter_map(|vm| Some((vm.name.clone(), vm.setup_fn.clone()?)))
+            .collect())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, super::MachineState>, Report>> + Send + 'l>,
+    > {
+        Box::pin(
+            async move {
+                Ok(
+                    futures_util::future::join_all(self.vms.iter().map(|vm| async move {
+                        let state = if vm.is_running() {
+                            super::ssh_state(
+                                &vm.ip,
+                                &vm.username,
+                                self.private_key_path(),
+                                22,
+                                vm.descriptor().check_host_key,
+                            )
+                            .await
+                        } else {
+                            super::MachineState::Terminated
+                        };
+                        (vm.name.clone(), state)
+                    }))
+                    .await
+                    .into_iter()
+                    .collect(),
+                )
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        Box::pin(
+            async move {
+                let mut res = Ok(());
+                for vm in self.vms {
+                    if let Some(p) = vm.process {
+                        let mut child = p.into_inner().unwrap_or_else(|e| e.into_inner());
+                        if let Err(e) = child.kill().await {
+                            tracing::warn!(name = %vm.name, "failed to kill microVM: {}", e);
+                        }
+                    }
+                    let r = run("remove tap device", "ip", ["link", "del", vm.tap.as_str()]).await;
+                    if let (Err(e), true) = (r, res.is_ok()) {
+                        res = Err(e);
+                    }
+                }
+                if let Some(ref bridge) = self.bridge {
+                    run("remove bridge", "ip", ["link", "del", bridge.as_str()]).await?;
+                }
+                res
+            }
+            .in_current_span(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::Launcher as _;
+
+    #[test]
+    fn addresses() {
+        assert_eq!(dotted(guest_ip(201, 0)), "10.201.0.2");
+        assert_eq!(dotted(guest_ip(201, 254)), "10.201.1.0");
+        assert_eq!(guest_mac(guest_ip(201, 300)), "06:00:0a:c9:01:2e");
+        assert_eq!("firecracker:local".parse::<Local>().unwrap(), Local);
+    }
+
+    #[test]
+    fn config() {
+        let s = Setup::new("vmlinux", "rootfs.ext4")
+            .vcpus(2)
+            .memory(256)
+            .boot_arg("mitigations=off");
+        let c = vm_config(
+            &s,
+            Path::new("/k/vmlinux"),
+            Path::new("/t/vm-0/rootfs.ext4"),
+            "fc00ab-0",
+            guest_ip(201, 0),
+        );
+        assert_eq!(c["boot-source"]["kernel_image_path"], "/k/vmlinux");
+        assert_eq!(
+            c["boot-source"]["boot_args"],
+            "console=ttyS0 reboot=k panic=1 pci=off \
+             ip=10.201.0.2::10.201.0.1:255.255.0.0::eth0:off mitigations=off"
+        );
+        assert_eq!(c["drives"][0]["path_on_host"], "/t/vm-0/rootfs.ext4");
+        assert_eq!(c["machine-config"]["vcpu_count"], 2);
+        assert_eq!(c["machine-config"]["mem_size_mib"], 256);
+        assert_eq!(c["network-interfaces"][0]["host_dev_name"], "fc00ab-0");
+    }
+
+    #[test]
+    fn authorized_keys() {
+        let stat = "Inode: 1234   Type: directory    Mode:  0750   Flags: 0x80000
+Generation: 0    Version: 0x00000000:00000000
+User:  1000   Group:  1001   Project:     0   Size: 4096
+";
+        assert_eq!(parse_owner(stat), Some((1000, 1001)));
+        assert_eq!(parse_owner("stat: File not found by ext2_lookup\n"), None);
+
+        let s = Setup::new("vmlinux", "rootfs.ext4");
+        assert_eq!(s.home(), "/root");
+        assert_eq!(s.username("ubuntu").home(), "/home/ubuntu");
+        let cmds = key_commands("/root", Path::new("/t/keys"), 0, 0);
+        assert!(cmds.starts_with("mkdir /root/.ssh\nrm /root/.ssh/authorized_keys\n"));
+        assert!(cmds.contains("write /t/keys /root/.ssh/authorized_keys\n"));
+        assert!(cmds.contains("set_inode_field /root/.ssh/authorized_keys mode 0100600\n"));
+    }
+
+    #[test]
+    #[ignore]
+    fn microvms() -> Result<(), Report> {
+        // e.g. the kernel and root filesystem from Firecracker's getting started guide
+        let kernel = std::env::var("TSUNAMI_FIRECRACKER_KERNEL")?;
+        let rootfs = std::env::var("TSUNAMI_FIRECRACKER_ROOTFS")?;
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let mut l = super::Launcher::default();
+            let m = Setup::new(kernel, rootfs);
+            l.spawn(crate::make_multiple(2, "f", m), None).await?;
+            let vms = l.connect_all().await?;
+            let ip = vms["f-1"].private_ip.clone().unwrap();
+            assert!(vms["f-0"]
+                .ssh
+                .command("ping")
+                .arg("-c1")
+                .arg(&ip)
+                .status()
+                .await?
+                .success());
+            l.terminate_all().await
+        })
+    }
+}
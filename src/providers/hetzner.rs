@@ -0,0 +1,1068 @@
+//! Hetzner Cloud backend for tsunami.
+//!
+//! Hetzner Cloud servers cost a fraction of comparable EC2 instances and are billed by the hour,
+//! which makes them a good fit for experiments that run for days.
+//!
+//! The provider talks to the [Hetzner Cloud API](https://docs.hetzner.cloud/) directly. It
+//! authenticates with an API token of the project to launch into, read from the `HCLOUD_TOKEN`
+//! environment variable (the same one the `hcloud` CLI uses), or set with
+//! [`Launcher::with_token`].
+//!
+//! Each launcher uploads a fresh SSH key, and creates one private network for each network zone
+//! it launches into, so that machines in the same zone can reach each other on their
+//! [`crate::Machine::private_ip`]s. All resources are labeled `tsunami=<run id>`, and
+//! `terminate_all()` deletes them. *If your tsunami crashes or you forget to call
+//! `terminate_all()`, you must delete them yourself*, e.g. with
+//! `hcloud server list -l tsunami` and `hcloud server delete`.
+//!
+//! # Example
+//! ```rust,no_run
+//! use tsunami::providers::hetzner;
+//! use tsunami::Tsunami;
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     let mut l = hetzner::Launcher::default();
+//!     let m = hetzner::Setup::default()
+//!         .location(hetzner::Location::Helsinki)
+//!         .server_type("cpx31");
+//!     l.spawn(tsunami::make_multiple(4, "worker", m), None).await?;
+//!     let vms = l.connect_all().await?;
+//!     for (name, vm) in &vms {
+//!         println!("{}: {}", name, vm.public_ip);
+//!     }
+//!     l.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use crate::providers::MachineState;
+use color_eyre::{
+    eyre::{self, eyre, WrapErr},
+    Help, Report,
+};
+use educe::Educe;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+/// A Hetzner Cloud location (data center).
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Location {
+    #[default]
+    Falkenstein,
+    Nuremberg,
+    Helsinki,
+    Ashburn,
+    Hillsboro,
+    Singapore,
+}
+
+impl Location {
+    /// Every location.
+    pub const ALL: &'static [Location] = &[
+        Location::Falkenstein,
+        Location::Nuremberg,
+        Location::Helsinki,
+        Location::Ashburn,
+        Location::Hillsboro,
+        Location::Singapore,
+    ];
+
+    /// The network zone of this location. Private networks cannot span network zones.
+    pub fn network_zone(&self) -> &'static str {
+        match self {
+            Location::Falkenstein | Location::Nuremberg | Location::Helsinki => "eu-central",
+            Location::Ashburn => "us-east",
+            Location::Hillsboro => "us-west",
+            Location::Singapore => "ap-southeast",
+        }
+    }
+}
+
+impl AsRef<str> for Location {
+    fn as_ref(&self) -> &str {
+        match self {
+            Location::Falkenstein => "fsn1",
+            Location::Nuremberg => "nbg1",
+            Location::Helsinki => "hel1",
+            Location::Ashburn => "ash",
+            Location::Hillsboro => "hil",
+            Location::Singapore => "sin",
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_ref())
+    }
+}
+
+impl std::str::FromStr for Location {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Location::ALL
+            .iter()
+            .find(|l| l.as_ref() == s)
+            .copied()
+            .ok_or_else(|| eyre!("unknown hetzner location {:?}", s))
+            .suggestion("Valid locations: fsn1, nbg1, hel1, ash, hil, sin")
+    }
+}
+
+/// A descriptor for a single Hetzner Cloud server.
+///
+/// The default is a `cx22` server running Ubuntu 22.04 in Falkenstein, logged into as `root`.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct Setup {
+    location: Location,
+    server_type: String,
+    image: String,
+    image_spec: Option<crate::image::ImageSpec>,
+    username: String,
+    os: Option<crate::OsFamily>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<
+        Arc<
+            dyn for<'r> Fn(
+                    &'r crate::Machine<'_>,
+                )
+                    -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >,
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Setup {
+            location: Location::default(),
+            server_type: String::from("cx22"),
+            image: String::from("ubuntu-22.04"),
+            image_spec: None,
+            username: String::from("root"),
+            os: Some(crate::OsFamily::Ubuntu),
+            setup_fn: None,
+        }
+    }
+}
+
+impl super::MachineSetup for Setup {
+    type Region = Location;
+
+    fn region(&self) -> Self::Region {
+        self.location
+    }
+}
+
+impl Setup {
+    /// Launch the server in `location`.
+    pub fn location(mut self, location: Location) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Set the server type, such as `cx22` or `ccx33`.
+    ///
+    /// `hcloud server-type list` shows the available types.
+    pub fn server_type(mut self, server_type: impl ToString) -> Self {
+        self.server_type = server_type.to_string();
+        self
+    }
+
+    /// Set the image, by name (such as `debian-12`) or id.
+    ///
+    /// `hcloud image list` shows the available images, including your snapshots.
+    ///
+    /// This clears any previously set [`Setup::os`].
+    pub fn image(mut self, image: impl ToString) -> Self {
+        self.image = image.to_string();
+        self.image_spec = None;
+        self.os = None;
+        self
+    }
+
+    /// Launch from the snapshot with id `id`.
+    ///
+    /// This clears any previously set [`Setup::os`].
+    pub fn snapshot(self, id: u64) -> Self {
+        self.image(id)
+    }
+
+    /// Use the image described by `image`, which is resolved when the server is launched.
+    ///
+    /// [`ImageSpec::CustomByName`](crate::image::ImageSpec::CustomByName) is the description of
+    /// one of your snapshots. Images of a known operating system also set [`Setup::os`].
+    pub fn image_spec(mut self, image: crate::image::ImageSpec) -> Self {
+        self.os = image.os();
+        self.image_spec = Some(image);
+        self
+    }
+
+    /// Declare which operating system family the image runs.
+    ///
+    /// Hetzner images are always logged into as `root`, so this does not change the username.
+    /// The family is available to setup functions as [`crate::Machine::os`].
+    pub fn os(mut self, os: crate::OsFamily) -> Self {
+        self.os = Some(os);
+        self
+    }
+
+    /// Set the username, for snapshots that allow logging in as someone other than `root`.
+    pub fn username(mut self, username: impl ToString) -> Self {
+        self.username = username.to_string();
+        self
+    }
+
+    /// Specify server setup.
+    ///
+    /// The provided callback, `setup`, is called once for every spawned server of this type with
+    /// a handle to the server. Use [`crate::Machine::ssh`] to issue commands on it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use tsunami::providers::hetzner::Setup;
+    /// let m = Setup::default().setup(|vm| {
+    ///     Box::pin(async move {
+    ///         vm.ssh
+    ///             .command("apt-get")
+    ///             .arg("update")
+    ///             .status()
+    ///             .await?;
+    ///         Ok(())
+    ///     })
+    /// });
+    /// ```
+    pub fn setup(
+        mut self,
+        setup: impl for<'r> Fn(
+                &'r crate::Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.setup_fn = Some(Arc::new(setup));
+        self
+    }
+
+    /// Check that the server matches `expect` once it is up, before running the
+    /// [`setup`](Setup::setup) function.
+    ///
+    /// See the [`verify`](crate::verify) module for what happens when the check fails, and for
+    /// when to call this.
+    pub fn verify(mut self, expect: crate::verify::Expectations) -> Self {
+        self.setup_fn = Some(super::before_setup(
+            expect.into_setup_fn(),
+            self.setup_fn.take(),
+        ));
+        self
+    }
+}
+
+/// An error returned by the Hetzner Cloud API.
+///
+/// Errors from the Hetzner provider can be downcast to this type to find out what went wrong,
+/// e.g. to retry in another location when a server type is sold out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ApiError {
+    /// The HTTP status of the response.
+    pub status: u16,
+    /// Hetzner's error code, such as `resource_unavailable` or `rate_limit_exceeded`.
+    pub code: String,
+    /// The description of the error.
+    pub message: String,
+}
+
+impl ApiError {
+    fn from_body(status: u16, body: &str) -> Self {
+        let v: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+        let e = &v["error"];
+        ApiError {
+            status,
+            code: e["code"].as_str().unwrap_or("unknown").to_string(),
+            message: e["message"]
+                .as_str()
+                .map(String::from)
+                .unwrap_or_else(|| body.trim().to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.code, self.status, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IpInfo {
+    public_ip: String,
+    public_dns: Option<String>,
+    private_ip: Option<String>,
+}
+
+// The addresses of a server, once it has its public address.
+fn ip_info(server: &serde_json::Value) -> Option<IpInfo> {
+    let v4 = &server["public_net"]["ipv4"];
+    Some(IpInfo {
+        public_ip: v4["ip"].as_str()?.to_string(),
+        public_dns: v4["dns_ptr"].as_str().map(String::from),
+        private_ip: server["private_net"][0]["ip"].as_str().map(String::from),
+    })
+}
+
+// The image name or id for the `ImageSpec`s that do not need a lookup.
+fn spec_image(spec: &crate::image::ImageSpec) -> Option<String> {
+    use crate::image::ImageSpec;
+    Some(match spec {
+        ImageSpec::Ubuntu2004 => String::from("ubuntu-20.04"),
+        ImageSpec::Ubuntu2204 => String::from("ubuntu-22.04"),
+        ImageSpec::Debian11 => String::from("debian-11"),
+        ImageSpec::ProviderSpecific(image) => image.clone(),
+        _ => return None,
+    })
+}
+
+// A client for the Hetzner Cloud API.
+mod api {
+    use super::ApiError;
+    use color_eyre::{
+        eyre::{self, WrapErr},
+        Help, Report,
+    };
+    use educe::Educe;
+    use reqwest::Method;
+    use serde_json::{json, Value};
+    use std::time::{Duration, Instant};
+    use tracing::instrument;
+
+    const API: &str = "https://api.hetzner.cloud/v1";
+    // How long to wait for an action before giving up on it. Snapshots are the slowest actions,
+    // and take a few minutes.
+    const ACTION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+    // The page after this one of a paginated list, if there is one.
+    pub(super) fn next_page(list: &Value) -> Option<u64> {
+        list["meta"]["pagination"]["next_page"].as_u64()
+    }
+
+    #[derive(Clone, Default, Educe)]
+    #[educe(Debug)]
+    pub(super) struct Client {
+        http: reqwest::Client,
+        #[educe(Debug(ignore))]
+        token: Option<String>,
+    }
+
+    pub(super) fn is_not_found(e: &Report) -> bool {
+        matches!(e.downcast_ref::<ApiError>(), Some(e) if e.status == 404)
+    }
+
+    impl Client {
+        pub(super) fn with_token(token: String) -> Self {
+            Client {
+                http: Default::default(),
+                token: Some(token),
+            }
+        }
+
+        fn token(&self) -> Result<String, Report> {
+            match self.token {
+                Some(ref t) => Ok(t.clone()),
+                None => std::env::var("HCLOUD_TOKEN")
+                    .wrap_err("no Hetzner Cloud API token")
+                    .suggestion("Set HCLOUD_TOKEN to an API token of your project"),
+            }
+        }
+
+        async fn call(
+            &self,
+            method: Method,
+            path: &str,
+            body: Option<Value>,
+        ) -> Result<Value, Report> {
+            let mut req = self
+                .http
+                .request(method, format!("{}{}", API, path))
+                .bearer_auth(self.token()?);
+            if let Some(body) = body {
+                req = req.json(&body);
+            }
+            let resp = req
+                .send()
+                .await
+                .wrap_err("failed to reach the Hetzner Cloud API")?;
+            let status = resp.status().as_u16();
+            let body = resp.text().await.unwrap_or_default();
+            eyre::ensure!(
+                (200..300).contains(&status),
+                ApiError::from_body(status, &body)
+            );
+            // deletions answer with an empty body
+            Ok(serde_json::from_str(&body).unwrap_or_default())
+        }
+
+        pub(super) async fn get(&self, path: &str) -> Result<Value, Report> {
+            self.call(Method::GET, path, None).await
+        }
+
+        pub(super) async fn post(&self, path: &str, body: Value) -> Result<Value, Report> {
+            self.call(Method::POST, path, Some(body)).await
+        }
+
+        // Deletes a resource, and waits for the deletion to finish if it is asynchronous.
+        // Resources that do not exist are already deleted.
+        pub(super) async fn delete(&self, path: &str) -> Result<(), Report> {
+            match self.call(Method::DELETE, path, None).await {
+                Ok(v) => match v["action"]["id"].as_u64() {
+                    Some(id) => self.wait_action(id).await,
+                    None => Ok(()),
+                },
+                Err(e) if is_not_found(&e) => Ok(()),
+                Err(e) => Err(e),
+            }
+        }
+
+        /// Wait for the action with id `id` to finish, or for `ACTION_TIMEOUT` to elapse.
+        #[instrument(level = "trace")]
+        pub(super) async fn wait_action(&self, id: u64) -> Result<(), Report> {
+            let start = Instant::now();
+            loop {
+                eyre::ensure!(
+                    start.elapsed() <= ACTION_TIMEOUT,
+                    "gave up on action {} after {:?}",
+                    id,
+                    start.elapsed()
+                );
+                let a = self.get(&format!("/actions/{}", id)).await?;
+                match a["action"]["status"].as_str() {
+                    Some("success") => return Ok(()),
+                    Some("error") => {
+                        let e = &a["action"]["error"];
+                        eyre::bail!(ApiError {
+                            status: 200,
+                            code: e["code"].as_str().unwrap_or("unknown").to_string(),
+                            message: e["message"].as_str().unwrap_or_default().to_string(),
+                        });
+                    }
+                    _ => tokio::time::sleep(Duration::from_secs(1)).await,
+                }
+            }
+        }
+
+        /// Check that the token works.
+        pub(super) async fn check(&self) -> Result<(), Report> {
+            self.get("/locations").await.map(drop)
+        }
+
+        /// Upload `public_key`, and return its id.
+        pub(super) async fn create_ssh_key(
+            &self,
+            name: &str,
+            public_key: &str,
+            run_id: &str,
+        ) -> Result<u64, Report> {
+            let k = self
+                .post(
+                    "/ssh_keys",
+                    json!({
+                        "name": name,
+                        "public_key": public_key.trim(),
+                        "labels": { "tsunami": run_id },
+                    }),
+                )
+                .await
+                .wrap_err("failed to upload ssh key")?;
+            k["ssh_key"]["id"]
+                .as_u64()
+                .ok_or_else(|| eyre::eyre!("ssh key has no id"))
+        }
+
+        /// Create a private network with one subnet in `zone`, and return its id.
+        pub(super) async fn create_network(
+            &self,
+            name: &str,
+            zone: &str,
+            run_id: &str,
+        ) -> Result<u64, Report> {
+            let n = self
+                .post(
+                    "/networks",
+                    json!({
+                        "name": name,
+                        "ip_range": "10.0.0.0/16",
+                        "subnets": [{
+                            "type": "cloud",
+                            "ip_range": "10.0.0.0/16",
+                            "network_zone": zone,
+                        }],
+                        "labels": { "tsunami": run_id },
+                    }),
+                )
+                .await
+                .wrap_err("failed to create network")?;
+            n["network"]["id"]
+                .as_u64()
+                .ok_or_else(|| eyre::eyre!("network has no id"))
+        }
+
+        /// The id of the snapshot whose description is `description`.
+        pub(super) async fn find_snapshot(&self, description: &str) -> Result<String, Report> {
+            let mut page = Some(1);
+            while let Some(p) = page {
+                let images = self
+                    .get(&format!("/images?type=snapshot&per_page=50&page={}", p))
+                    .await
+                    .wrap_err("failed to list snapshots")?;
+                let found = images["images"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .find(|i| i["description"].as_str() == Some(description))
+                    .and_then(|i| i["id"].as_u64());
+                if let Some(id) = found {
+                    return Ok(id.to_string());
+                }
+                page = next_page(&images);
+            }
+            eyre::bail!("no snapshot named {}", description)
+        }
+
+        /// Create a server, and return it.
+        #[allow(clippy::too_many_arguments)]
+        #[instrument(level = "trace")]
+        pub(super) async fn create_server(
+            &self,
+            name: &str,
+            location: &str,
+            server_type: &str,
+            image: &str,
+            ssh_key: u64,
+            network: u64,
+            run_id: &str,
+        ) -> Result<Value, Report> {
+            let s = self
+                .post(
+                    "/servers",
+                    json!({
+                        "name": name,
+                        "location": location,
+                        "server_type": server_type,
+                        "image": image,
+                        "ssh_keys": [ssh_key],
+                        "networks": [network],
+                        "labels": { "tsunami": run_id },
+                        "start_after_create": true,
+                    }),
+                )
+                .await
+                .wrap_err("failed to create server")?;
+            Ok(s["server"].clone())
+        }
+
+        /// The server with id `id`, or `None` if it does not exist.
+        pub(super) async fn server(&self, id: u64) -> Result<Option<Value>, Report> {
+            match self.get(&format!("/servers/{}", id)).await {
+                Ok(s) => Ok(Some(s["server"].clone())),
+                Err(e) if is_not_found(&e) => Ok(None),
+                Err(e) => Err(e.wrap_err("failed to get server")),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub(super) struct Descriptor {
+    name: String,
+    id: u64,
+    username: String,
+    os: Option<crate::OsFamily>,
+    ip: IpInfo,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<super::SetupFn>,
+}
+
+impl Descriptor {
+    fn machine<'l>(&self) -> crate::MachineDescriptor<'l> {
+        crate::MachineDescriptor {
+            nickname: self.name.clone(),
+            public_dns: self.ip.public_dns.clone(),
+            public_ip: self.ip.public_ip.clone(),
+            private_ip: self.ip.private_ip.clone(),
+            os: self.os,
+            proxy_command: None,
+            check_host_key: true,
+            _tsunami: Default::default(),
+        }
+    }
+}
+
+/// Launcher type for Hetzner Cloud.
+///
+/// See the [module documentation](self) for how to authenticate. Machines are launched
+/// concurrently, and their setup functions run in parallel.
+#[derive(Debug, Default)]
+pub struct Launcher {
+    client: api::Client,
+    run_id: String,
+    key: Option<(tempfile::TempDir, std::path::PathBuf)>,
+    ssh_key_id: Option<u64>,
+    networks: HashMap<&'static str, u64>,
+    all_or_nothing: bool,
+    machines: Vec<Descriptor>,
+}
+
+impl Launcher {
+    /// Use the API token `token` instead of the one in `HCLOUD_TOKEN`.
+    pub fn with_token(token: impl ToString) -> Self {
+        Launcher {
+            client: api::Client::with_token(token.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Either launch every machine of a `launch` or `spawn`, or none of them.
+    ///
+    /// With `all_or_nothing(true)`, a failed launch deletes every server it created, in all
+    /// locations, before returning the error. By default, the servers that came up stay running.
+    /// Servers from earlier, successful launches are not affected.
+    pub fn all_or_nothing(&mut self, enabled: bool) -> &mut Self {
+        self.all_or_nothing = enabled;
+        self
+    }
+
+    /// The path to the private key used to log into the machines.
+    pub fn private_key_path(&self) -> Option<&std::path::Path> {
+        self.key.as_ref().map(|(_, k)| k.as_path())
+    }
+
+    // Uploads the ssh key and creates the network for `location`, unless that has been done.
+    #[instrument(level = "debug", skip(self))]
+    async fn prepare(&mut self, location: Location) -> Result<(u64, u64), Report> {
+        if self.run_id.is_empty() {
+            self.client.check().await?;
+            self.run_id = super::rand_name_sep("run", "-").to_lowercase();
+        }
+        let ssh_key_id = match self.ssh_key_id {
+            Some(id) => id,
+            None => {
+                let (key_dir, key, public_key) = super::generate_key().await?;
+                self.key = Some((key_dir, key));
+                let id = self
+                    .client
+                    .create_ssh_key(&self.run_id, &public_key, &self.run_id)
+                    .await?;
+                self.ssh_key_id = Some(id);
+                id
+            }
+        };
+        let zone = location.network_zone();
+        let network = match self.networks.get(zone) {
+            Some(id) => *id,
+            None => {
+                let id = self
+                    .client
+                    .create_network(&format!("{}-{}", self.run_id, zone), zone, &self.run_id)
+                    .await?;
+                self.networks.insert(zone, id);
+                id
+            }
+        };
+        Ok((ssh_key_id, network))
+    }
+
+    // Polls the server every two seconds until it is running, or `max_wait` (if not `None`)
+    // elapses.
+    #[instrument(level = "trace", skip(self, max_wait))]
+    async fn wait_running(&self, id: u64, max_wait: Option<Duration>) -> Result<IpInfo, Report> {
+        let start = Instant::now();
+        loop {
+            let server = self
+                .client
+                .server(id)
+                .await?
+                .ok_or_else(|| eyre!("server disappeared"))?;
+            match server["status"].as_str() {
+                Some("running") => {
+                    return ip_info(&server).ok_or_else(|| eyre!("server has no public ip"))
+                }
+                Some("initializing") | Some("starting") => {}
+                s => eyre::bail!("server is {}", s.unwrap_or("in an unknown state")),
+            }
+            if let Some(wait_limit) = max_wait {
+                eyre::ensure!(start.elapsed() <= wait_limit, "wait limit reached");
+            }
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+    }
+
+    // Launches one server, and returns it even if it did not come up, so that it can be deleted
+    // later.
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(level = "debug", skip(self, s, max_wait))]
+    async fn start(
+        &self,
+        name: &str,
+        s: &Setup,
+        image: &str,
+        ssh_key: u64,
+        network: u64,
+        max_wait: Option<Duration>,
+    ) -> (Option<Descriptor>, Result<(), Report>) {
+        super::report_progress(name, MachineState::Booting);
+        // server names must be valid hostnames
+        let server_name = super::rand_name_sep("server", "-").to_lowercase();
+        let server = match self
+            .client
+            .create_server(
+                &server_name,
+                s.location.as_ref(),
+                &s.server_type,
+                image,
+                ssh_key,
+                network,
+                &self.run_id,
+            )
+            .await
+        {
+            Ok(server) => server,
+            Err(e) => return (None, Err(e)),
+        };
+        let id = match server["id"].as_u64() {
+            Some(id) => id,
+            None => return (None, Err(eyre!("server has no id"))),
+        };
+
+        let mut desc = Descriptor {
+            name: name.to_string(),
+            id,
+            username: s.username.clone(),
+            os: s.os,
+            setup_fn: s.setup_fn.clone(),
+            ip: IpInfo {
+                public_ip: String::new(),
+                public_dns: None,
+                private_ip: None,
+            },
+        };
+        let res = async {
+            desc.ip = self.wait_running(id, max_wait).await?;
+            super::set_up_when_reachable(
+                name,
+                desc.machine(),
+                &desc.username,
+                self.private_key_path(),
+                s.setup_fn.as_ref(),
+                max_wait,
+            )
+            .await
+        }
+        .await;
+        (Some(desc), res)
+    }
+}
+
+impl super::EachMachine for Launcher {
+    type Machine = Descriptor;
+
+    fn machines(&self) -> &[Descriptor] {
+        &self.machines
+    }
+
+    fn machines_mut(&mut self) -> &mut Vec<Descriptor> {
+        &mut self.machines
+    }
+
+    fn nickname(d: &Descriptor) -> &str {
+        &d.name
+    }
+
+    fn ssh<'l>(
+        &'l self,
+        d: &'l Descriptor,
+    ) -> (
+        crate::MachineDescriptor<'l>,
+        &'l str,
+        Option<&'l std::path::Path>,
+    ) {
+        (d.machine(), &d.username, self.private_key_path())
+    }
+
+    fn rolls_back(&self) -> bool {
+        self.all_or_nothing
+    }
+
+    fn delete<'l>(
+        &'l self,
+        gone: Vec<Descriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        let client = &self.client;
+        Box::pin(async move {
+            futures_util::future::join_all(
+                gone.iter()
+                    .map(|d| async move { client.delete(&format!("/servers/{}", d.id)).await }),
+            )
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err("failed to delete servers")?;
+            Ok(())
+        })
+    }
+}
+
+impl super::Launcher for Launcher {
+    type MachineDescriptor = Setup;
+
+    #[instrument(level = "debug", skip(self))]
+    fn launch<'l>(
+        &'l mut self,
+        l: super::LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(
+            async move {
+                let (ssh_key, network) = self.prepare(l.region).await?;
+
+                let mut images = Vec::with_capacity(l.machines.len());
+                for (_, s) in &l.machines {
+                    images.push(match s.image_spec {
+                        Some(ref spec) => match spec_image(spec) {
+                            Some(image) => image,
+                            None => match spec {
+                                crate::image::ImageSpec::CustomByName(name) => {
+                                    self.client.find_snapshot(name).await?
+                                }
+                                _ => eyre::bail!("unsupported image {}", spec),
+                            },
+                        },
+                        None => s.image.clone(),
+                    });
+                }
+
+                let this = &*self;
+                let started = futures_util::future::join_all(l.machines.iter().zip(&images).map(
+                    |((name, s), image)| {
+                        let machine_span = tracing::debug_span!("machine", %name);
+                        this.start(name, s, image, ssh_key, network, l.max_wait)
+                            .instrument(machine_span)
+                    },
+                ))
+                .await;
+
+                let names = l.machines.into_iter().map(|(name, _)| name);
+                super::keep_started(self, names, started).await
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self, max_wait))]
+    fn spawn<'l, I>(
+        &'l mut self,
+        descriptors: I,
+        max_wait: Option<std::time::Duration>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>>
+    where
+        I: IntoIterator<Item = (String, Self::MachineDescriptor)> + Send + 'static,
+        I: std::fmt::Debug,
+        I::IntoIter: Send,
+    {
+        Box::pin(super::spawn_each(self, descriptors, max_wait).in_current_span())
+    }
+
+    #[instrument(level = "debug")]
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        Box::pin(super::connect_each(self).in_current_span())
+    }
+
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        Ok(super::describe_each(self))
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        Ok(self
+            .machines
+            .iter()
+            .filter_map(|d| Some((d.name.clone(), d.setup_fn.clone()?)))
+            .collect())
+    }
+
+    #[instrument(level = "debug")]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<Box<dyn Future<Output = Result<HashMap<String, MachineState>, Report>> + Send + 'l>>
+    {
+        Box::pin(
+            async move {
+                futures_util::future::join_all(self.machines.iter().map(|desc| async move {
+                    let server = self.client.server(desc.id).await?;
+                    let state = match server.as_ref().and_then(|s| s["status"].as_str()) {
+                        Some("running") => {
+                            super::ssh_state(
+                                &desc.ip.public_ip,
+                                &desc.username,
+                                self.private_key_path(),
+                                22,
+                                desc.machine().check_host_key,
+                            )
+                            .await
+                        }
+                        Some("initializing") | Some("starting") => MachineState::Booting,
+                        // stopping, off, deleting, migrating, rebuilding, or gone entirely
+                        _ => MachineState::Terminated,
+                    };
+                    Ok::<_, Report>((desc.name.clone(), state))
+                }))
+                .await
+                .into_iter()
+                .collect()
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug")]
+    fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        Box::pin(
+            async move {
+                let client = &self.client;
+                futures_util::future::join_all(self.machines.iter().map(|desc| async move {
+                    client.delete(&format!("/servers/{}", desc.id)).await
+                }))
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()
+                .wrap_err("failed to delete servers")?;
+                // networks and keys can only go once no server uses them
+                for id in self.networks.values() {
+                    self.client
+                        .delete(&format!("/networks/{}", id))
+                        .await
+                        .wrap_err("failed to delete network")?;
+                }
+                if let Some(id) = self.ssh_key_id {
+                    self.client
+                        .delete(&format!("/ssh_keys/{}", id))
+                        .await
+                        .wrap_err("failed to delete ssh key")?;
+                }
+                Ok(())
+            }
+            .in_current_span(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locations() {
+        for l in Location::ALL {
+            assert_eq!(l.to_string().parse::<Location>().unwrap(), *l);
+        }
+        assert!("eu-central".parse::<Location>().is_err());
+        assert_eq!(Location::Helsinki.network_zone(), "eu-central");
+    }
+
+    #[test]
+    fn responses() {
+        // a trimmed `GET /servers/{id}` response
+        let server: serde_json::Value = serde_json::from_str(
+            r#"{
+            "id": 42,
+            "name": "tsunami-server-abc",
+            "status": "running",
+            "public_net": {
+                "ipv4": { "ip": "95.217.1.2", "blocked": false, "dns_ptr": "static.2.1.217.95.clients.your-server.de" },
+                "ipv6": { "ip": "2a01:4f9::/64", "blocked": false, "dns_ptr": [] },
+                "floating_ips": []
+            },
+            "private_net": [{ "network": 7, "ip": "10.0.0.2", "alias_ips": [] }]
+        }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ip_info(&server),
+            Some(IpInfo {
+                public_ip: String::from("95.217.1.2"),
+                public_dns: Some(String::from("static.2.1.217.95.clients.your-server.de")),
+                private_ip: Some(String::from("10.0.0.2")),
+            })
+        );
+        let mut initializing = server.clone();
+        initializing["public_net"]["ipv4"] = serde_json::Value::Null;
+        assert_eq!(ip_info(&initializing), None);
+
+        let e = ApiError::from_body(
+            412,
+            r#"{"error": {"code": "resource_unavailable", "message": "server type unavailable"}}"#,
+        );
+        assert_eq!(e.code, "resource_unavailable");
+        assert_eq!(
+            ApiError::from_body(502, "bad gateway").message,
+            "bad gateway"
+        );
+        let list: serde_json::Value = serde_json::from_str(
+            r#"{"images": [], "meta": {"pagination": {"page": 1, "next_page": 2, "last_page": 2}}}"#,
+        )
+        .unwrap();
+        assert_eq!(api::next_page(&list), Some(2));
+        let mut last = list.clone();
+        last["meta"]["pagination"]["next_page"] = serde_json::Value::Null;
+        assert_eq!(api::next_page(&last), None);
+    }
+
+    #[test]
+    fn images() {
+        use crate::image::ImageSpec;
+        assert_eq!(
+            spec_image(&ImageSpec::Debian11).as_deref(),
+            Some("debian-11")
+        );
+        assert_eq!(
+            spec_image(&ImageSpec::CustomByName(String::from("golden"))),
+            None
+        );
+        let s = Setup::default().snapshot(1234);
+        assert_eq!(s.image, "1234");
+        assert_eq!(s.os, None);
+    }
+
+    #[test]
+    #[ignore]
+    fn hetzner_servers() -> Result<(), Report> {
+        use crate::providers::Launcher as _;
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let mut l = super::Launcher::default();
+            l.spawn(crate::make_multiple(2, "s", Setup::default()), None)
+                .await?;
+            let vms = l.connect_all().await?;
+            assert!(vms["s-0"].private_ip.is_some());
+            let out = vms["s-1"].ssh.command("hostname").output().await?;
+            assert!(out.status.success());
+            l.terminate_all().await
+        })
+    }
+}
@@ -0,0 +1,270 @@
+//! Controller-side hooks around cleanup, for any backend.
+//!
+//! This launcher wraps another [`Launcher`](super::Launcher), and runs the registered hooks on
+//! the controller when it is terminated: [`Launcher::before_cleanup`] runs before any machine is
+//! terminated, and [`Launcher::after_cleanup`] runs after the inner launcher has cleaned up. Use
+//! them for a final sync of results, to print a summary, or to tell another system that the run
+//! is over.
+//!
+//! The hooks run even if an earlier phase failed. Errors from the hooks and from the inner
+//! launcher's cleanup are combined into the error that
+//! [`terminate_all`](super::Launcher::terminate_all) returns.
+//!
+//! # Example
+//! ```rust,no_run
+//! use tsunami::providers::{aws, hooks};
+//! use tsunami::Tsunami;
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     let mut l = hooks::Launcher::new(aws::Launcher::default());
+//!     l.before_cleanup(|vms, _| {
+//!         Box::pin(async move {
+//!             for vm in vms.values() {
+//!                 vm.ssh.command("sync").status().await?;
+//!             }
+//!             Ok(())
+//!         })
+//!     })
+//!     .after_cleanup(|states, err| {
+//!         Box::pin(async move {
+//!             println!("cleaned up {} machines: {:?}", states.len(), err);
+//!             Ok(())
+//!         })
+//!     });
+//!     l.spawn(vec![(String::from("my_vm"), aws::Setup::default())], None)
+//!         .await?;
+//!     l.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use super::MachineState;
+use color_eyre::Report;
+use educe::Educe;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+/// A hook that runs on the controller before the machines are terminated. See
+/// [`Launcher::before_cleanup`].
+pub type BeforeCleanupFn = Arc<
+    dyn for<'r> Fn(
+            &'r HashMap<String, crate::Machine<'_>>,
+            &'r HashMap<String, MachineState>,
+        ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+/// A hook that runs on the controller after the machines are terminated. See
+/// [`Launcher::after_cleanup`].
+pub type AfterCleanupFn = Arc<
+    dyn for<'r> Fn(
+            &'r HashMap<String, MachineState>,
+            Option<&'r Report>,
+        ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+/// Launcher that runs controller-side hooks around the cleanup of another launcher.
+///
+/// See the [module documentation](self) for details.
+#[derive(Educe)]
+#[educe(Debug(bound))]
+pub struct Launcher<L> {
+    inner: L,
+    #[educe(Debug(ignore))]
+    before_cleanup: Option<BeforeCleanupFn>,
+    #[educe(Debug(ignore))]
+    after_cleanup: Option<AfterCleanupFn>,
+}
+
+impl<L: Default> Default for Launcher<L> {
+    fn default() -> Self {
+        Self::new(L::default())
+    }
+}
+
+impl<L> Launcher<L> {
+    /// Wrap `inner`, which launches the machines.
+    pub fn new(inner: L) -> Self {
+        Launcher {
+            inner,
+            before_cleanup: None,
+            after_cleanup: None,
+        }
+    }
+
+    /// The wrapped launcher.
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// The wrapped launcher, e.g. to change its settings.
+    pub fn inner_mut(&mut self) -> &mut L {
+        &mut self.inner
+    }
+
+    /// Run `hook` on the controller when the launcher is terminated, before any machine is.
+    ///
+    /// The hook gets the machines by nickname, and the last [state](super::Launcher::status) of
+    /// every machine. If the machines cannot be reached, or their state cannot be queried, the
+    /// hook gets an empty map instead. If the hook fails, the machines are terminated anyway and
+    /// the error is returned afterwards.
+    pub fn before_cleanup(
+        &mut self,
+        hook: impl for<'r> Fn(
+                &'r HashMap<String, crate::Machine<'_>>,
+                &'r HashMap<String, MachineState>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> &mut Self {
+        self.before_cleanup = Some(Arc::new(hook));
+        self
+    }
+
+    /// Run `hook` on the controller when the launcher is terminated, after the machines are.
+    ///
+    /// The hook gets the state of every machine from before it was terminated, and the error
+    /// that cleaning up ran into, if any. It runs even if cleaning up or the
+    /// [`before_cleanup`](Launcher::before_cleanup) hook failed.
+    pub fn after_cleanup(
+        &mut self,
+        hook: impl for<'r> Fn(
+                &'r HashMap<String, MachineState>,
+                Option<&'r Report>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> &mut Self {
+        self.after_cleanup = Some(Arc::new(hook));
+        self
+    }
+}
+
+impl<L> super::Launcher for Launcher<L>
+where
+    L: super::Launcher + Sync + 'static,
+{
+    type MachineDescriptor = L::MachineDescriptor;
+
+    fn launch<'l>(
+        &'l mut self,
+        desc: super::LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        self.inner.launch(desc)
+    }
+
+    fn spawn<'l, I>(
+        &'l mut self,
+        descriptors: I,
+        max_wait: Option<std::time::Duration>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>>
+    where
+        I: IntoIterator<Item = (String, Self::MachineDescriptor)> + Send + 'static,
+        I: std::fmt::Debug,
+        I::IntoIter: Send,
+    {
+        self.inner.spawn(descriptors, max_wait)
+    }
+
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        self.inner.connect_all()
+    }
+
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        self.inner.describe_all()
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        self.inner.setup_fns()
+    }
+
+    fn restore_clean<'l>(
+        &'l self,
+        nicknames: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        self.inner.restore_clean(nicknames)
+    }
+
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<Box<dyn Future<Output = Result<HashMap<String, MachineState>, Report>> + Send + 'l>>
+    {
+        self.inner.status()
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        Box::pin(
+            async move {
+                let Launcher {
+                    inner,
+                    before_cleanup,
+                    after_cleanup,
+                } = self;
+
+                let states = if before_cleanup.is_some() || after_cleanup.is_some() {
+                    inner.status().await.unwrap_or_else(|e| {
+                        tracing::warn!("failed to query machine states: {:?}", e);
+                        Default::default()
+                    })
+                } else {
+                    Default::default()
+                };
+
+                let mut hook_errs = Vec::new();
+                if let Some(hook) = before_cleanup {
+                    let machines = inner.connect_all().await.unwrap_or_else(|e| {
+                        tracing::warn!("failed to connect to machines: {:?}", e);
+                        Default::default()
+                    });
+                    if let Err(e) = hook(&machines, &states).await {
+                        tracing::warn!("before_cleanup hook failed: {:?}", e);
+                        hook_errs.push(e.wrap_err("before_cleanup hook failed"));
+                    }
+                }
+
+                let cleanup = inner.terminate_all().await;
+
+                if let Some(hook) = after_cleanup {
+                    if let Err(e) = hook(&states, cleanup.as_ref().err()).await {
+                        tracing::warn!("after_cleanup hook failed: {:?}", e);
+                        hook_errs.push(e.wrap_err("after_cleanup hook failed"));
+                    }
+                }
+
+                let mut errs = cleanup.err().into_iter().chain(hook_errs);
+                match errs.next() {
+                    None => Ok(()),
+                    Some(first) => Err(errs.fold(first, |a, e| a.wrap_err(e))),
+                }
+            }
+            .in_current_span(),
+        )
+    }
+}
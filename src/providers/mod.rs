@@ -1,8 +1,7 @@
 //! Implements backend functionality to spawn machines.
 
-use color_eyre::{eyre::WrapErr, Report};
-use itertools::Itertools;
-use std::collections::HashMap;
+use color_eyre::{eyre, Report};
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
 use std::pin::Pin;
 use tracing::instrument;
@@ -24,16 +23,134 @@ pub struct LaunchDescriptor<M: MachineSetup + Send> {
     pub machines: Vec<(String, M)>,
 }
 
+/// Group `descriptors` into one [`LaunchDescriptor`] per region.
+///
+/// This is the grouping step of [`Launcher::spawn`], exposed so that it can be inspected without
+/// a launcher. Regions appear in the order their first machine appears in `descriptors`, and
+/// machines keep their relative order within each region. Duplicate nicknames are an error.
+///
+/// ```rust
+/// use tsunami::providers::{aws, plan_descriptors};
+/// let plan = plan_descriptors(
+///     vec![
+///         (String::from("a"), aws::Setup::default()),
+///         (String::from("b"), aws::Setup::default().region(aws::Region::EuWest1, "ami-0", "ubuntu")),
+///         (String::from("c"), aws::Setup::default()),
+///     ],
+///     None,
+/// )
+/// .unwrap();
+/// assert_eq!(plan.len(), 2);
+/// assert_eq!(plan[0].region.to_string(), "us-east-1");
+/// assert_eq!(plan[0].machines.len(), 2);
+/// ```
+pub fn plan_descriptors<M, I>(
+    descriptors: I,
+    max_wait: Option<std::time::Duration>,
+) -> Result<Vec<LaunchDescriptor<M>>, Report>
+where
+    M: MachineSetup + Send,
+    I: IntoIterator<Item = (String, M)>,
+{
+    let mut names = HashSet::new();
+    let mut plan: Vec<LaunchDescriptor<M>> = Vec::new();
+    for (name, setup) in descriptors {
+        eyre::ensure!(
+            names.insert(name.clone()),
+            "duplicate machine nickname {}",
+            name
+        );
+
+        let region = setup.region();
+        match plan.iter_mut().find(|d| d.region == region) {
+            Some(d) => d.machines.push((name, setup)),
+            None => plan.push(LaunchDescriptor {
+                region,
+                max_wait,
+                machines: vec![(name, setup)],
+            }),
+        }
+    }
+    Ok(plan)
+}
+
 /// This is used to group machines into connections
 /// to cloud providers. e.g., for AWS we need a separate
 /// connection to each region.
 pub trait MachineSetup {
     /// Grouping type.
-    type Region: Eq + std::hash::Hash + Clone + std::fmt::Display + Send;
+    ///
+    /// Each provider has its own typed region, which is displayed in logs and can be parsed back
+    /// from its `Display` form.
+    type Region: Eq
+        + std::hash::Hash
+        + Clone
+        + std::fmt::Debug
+        + std::fmt::Display
+        + std::str::FromStr
+        + Send;
     /// Get the region.
     fn region(&self) -> Self::Region;
 }
 
+/// The state of a single machine, as reported by [`Launcher::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MachineState {
+    /// The provider has accepted the request, but has not yet allocated the machine.
+    Pending,
+    /// The machine has been allocated, but is still starting up.
+    Booting,
+    /// The machine's setup function is running.
+    SettingUp,
+    /// The machine is running and accepts SSH connections.
+    Ready,
+    /// The machine is running, but its setup function returned an error.
+    SetupFailed,
+    /// The machine has been stopped or terminated.
+    Terminated,
+    /// The provider reports the machine as running, but it does not accept SSH connections.
+    Unreachable,
+}
+
+impl std::fmt::Display for MachineState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            MachineState::Pending => "pending",
+            MachineState::Booting => "booting",
+            MachineState::SettingUp => "setting-up",
+            MachineState::Ready => "ready",
+            MachineState::SetupFailed => "setup-failed",
+            MachineState::Terminated => "terminated",
+            MachineState::Unreachable => "unreachable",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The `tracing` target of the events that announce machine state changes during launch.
+///
+/// Each such event is at `INFO` level, and has a `nickname` field and a `state` field (the
+/// `Display` form of a [`MachineState`]). Progress displays such as the `tui` feature's
+/// `tsunami::tui::Progress` consume these events.
+pub const PROGRESS_TARGET: &str = "tsunami::progress";
+
+// announces that `nickname` has reached `state`; see `PROGRESS_TARGET`.
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+fn report_progress(nickname: &str, state: MachineState) {
+    tracing::info!(target: PROGRESS_TARGET, %nickname, %state);
+}
+
 /// Use this trait to implement support for launching machines in a cloud provider.
 ///
 /// If you just want to launch machines, use [`crate::Tsunami`] instead of this trait.
@@ -66,6 +183,73 @@ pub trait Launcher: Send {
     /// Shut down all instances.
     fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>>;
 
+    /// Report the current state of every machine that `launch` was asked to spawn.
+    ///
+    /// Implementations should refresh the state from the provider rather than rely on what they
+    /// remember from `launch`. The default implementation only knows about machines that
+    /// `connect_all` can reach, and reports all of them as [`MachineState::Ready`].
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<Box<dyn Future<Output = Result<HashMap<String, MachineState>, Report>> + Send + 'l>>
+    {
+        let machines = self.connect_all();
+        Box::pin(
+            async move {
+                Ok(machines
+                    .await?
+                    .into_keys()
+                    .map(|name| (name, MachineState::Ready))
+                    .collect())
+            }
+            .in_current_span(),
+        )
+    }
+
+    /// The setup function of every machine that `launch` spawned with one, by nickname.
+    ///
+    /// This is what [`crate::Tsunami::rerun_setup`] runs. The default implementation returns an
+    /// error, for launchers that do not keep their machines' setup functions.
+    fn setup_fns(&self) -> Result<HashMap<String, SetupFn>, Report> {
+        eyre::bail!("this launcher cannot re-run setup functions")
+    }
+
+    /// Return the machines in `nicknames` to the clean snapshot taken after they booted, and
+    /// before their setup functions first ran.
+    ///
+    /// This is what [`crate::Tsunami::rerun_setup_clean`] restores before it runs the setup
+    /// functions again. The default implementation returns an error, for launchers that do not
+    /// take clean snapshots.
+    fn restore_clean<'l>(
+        &'l self,
+        nicknames: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        drop(nicknames);
+        Box::pin(async {
+            Err::<(), _>(eyre::eyre!(
+                "this launcher cannot restore machines from a clean snapshot"
+            ))
+        })
+    }
+
+    /// Describe how to reach every machine that `launch` spawned, without connecting to them.
+    ///
+    /// This is what [`crate::Tsunami::describe_all`] returns. The default implementation returns
+    /// an error, for launchers that only learn how to reach a machine by connecting to it.
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        eyre::bail!("this launcher cannot describe its machines without connecting to them")
+    }
+
     /// Helper method to group `MachineDescriptor`s into regions and call `launch`.
     ///
     /// This implementation initializes each region serially. It may be useful for performance to
@@ -83,22 +267,10 @@ pub trait Launcher: Send {
     {
         Box::pin(
             async move {
-                let max_wait = max_wait;
-
                 tracing::info!("spinning up tsunami");
 
-                for (region_name, setups) in descriptors
-                    .into_iter()
-                    .map(|(name, setup)| (setup.region(), (name, setup)))
-                    .into_group_map()
-                {
-                    let region_span = tracing::debug_span!("region", region = %region_name);
-                    let dsc = LaunchDescriptor {
-                        region: region_name.clone(),
-                        max_wait,
-                        machines: setups,
-                    };
-
+                for dsc in plan_descriptors(descriptors, max_wait)? {
+                    let region_span = tracing::debug_span!("region", region = %dsc.region);
                     self.launch(dsc).instrument(region_span).await?;
                 }
 
@@ -109,9 +281,134 @@ pub trait Launcher: Send {
     }
 }
 
-// The aws and azure implementations use this helper macro, so it has to be declared before the
-// module declarations.
+// Launches the per-region `plan` into `regions` concurrently, creating the region launchers
+// that do not exist yet with `new_region`.
+//
+// This is the shared `spawn` of the launchers that keep one sub-launcher per region. If any
+// region fails, the first error is returned, but every region launcher is kept so that
+// `terminate_all` can still clean up.
 #[cfg(any(feature = "aws", feature = "azure"))]
+async fn spawn_regions<M, RL, N, NF, L, LF>(
+    regions: &mut HashMap<M::Region, RL>,
+    plan: Vec<LaunchDescriptor<M>>,
+    new_region: N,
+    launch: L,
+) -> Result<(), Report>
+where
+    M: MachineSetup + Send,
+    N: Fn(M::Region) -> NF,
+    NF: Future<Output = Result<RL, Report>>,
+    L: Fn(RL, LaunchDescriptor<M>) -> LF,
+    LF: Future<Output = (RL, Result<(), Report>)>,
+{
+    // separate into two lists:
+    // 1. we already have a region launcher
+    // 2. we don't
+    let (mut haves, have_nots): (Vec<_>, Vec<_>) = plan
+        .into_iter()
+        .partition(|d| regions.contains_key(&d.region));
+
+    let newly_initialized = futures_util::future::join_all(have_nots.iter().map(|d| {
+        let region_span = tracing::debug_span!("new_region", region = %d.region);
+        let rl = new_region(d.region.clone());
+        async move { Ok::<_, Report>((d.region.clone(), rl.await?)) }.instrument(region_span)
+    }))
+    .await;
+    regions.extend(
+        newly_initialized
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?,
+    );
+
+    // the have-nots are now haves
+    haves.extend(have_nots);
+
+    // Launch instances in the regions concurrently.
+    //
+    // The borrow checker can't know that each future only accesses one entry of the hashmap - for
+    // its region launcher (guaranteed by `plan_descriptors()`). So, we help it by taking the
+    // appropriate region launcher out of the hashmap, running `launch()`, then putting everything
+    // back later.
+    let launched = futures_util::future::join_all(haves.into_iter().map(|d| {
+        // unwrap ok because everything is a have now
+        let rl = regions.remove(&d.region).unwrap();
+        let region_name = d.region.clone();
+        let region_span = tracing::debug_span!("region", region = %region_name);
+        let launched = launch(rl, d);
+        async move {
+            let (rl, res) = launched.await;
+            (region_name, rl, res)
+        }
+        .instrument(region_span)
+    }))
+    .await;
+
+    // Put our stuff back where we found it.
+    let mut res = Ok(());
+    for (region_name, rl, r) in launched {
+        regions.insert(region_name, rl);
+        if let (Err(e), true) = (r, res.is_ok()) {
+            res = Err(e);
+        }
+    }
+    res
+}
+
+/// A machine setup function, as stored by the providers' `Setup` types.
+pub type SetupFn = std::sync::Arc<
+    dyn for<'r> Fn(
+            &'r crate::Machine<'_>,
+        ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+// Run `step` before `setup`, if any.
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "vagrant"
+))]
+fn before_setup(step: SetupFn, setup: Option<SetupFn>) -> SetupFn {
+    std::sync::Arc::new(move |vm| {
+        let step = step.clone();
+        let setup = setup.clone();
+        Box::pin(async move {
+            step(vm).await?;
+            if let Some(f) = setup {
+                f(vm).await?;
+            }
+            Ok(())
+        })
+    })
+}
+
+// The error of a launch that failed in all-or-nothing mode, given the outcome of terminating the
+// machines it had started.
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "hetzner"
+))]
+fn rolled_back(e: Report, rollback: Result<(), Report>) -> Report {
+    match rollback {
+        Ok(()) => e.wrap_err("all-or-nothing launch failed, so its machines were terminated"),
+        Err(re) => re
+            .wrap_err(e)
+            .wrap_err("all-or-nothing launch failed, and not all of its machines were terminated"),
+    }
+}
+
+// The azure implementation uses this helper macro, so it has to be declared before the module
+// declarations.
+#[cfg(feature = "azure")]
 macro_rules! collect {
     ($x: expr) => {{
         Ok({
@@ -125,24 +422,63 @@ macro_rules! collect {
     }};
 }
 
+#[cfg(feature = "aliyun")]
+pub mod aliyun;
 #[cfg(feature = "aws")]
 pub mod aws;
 #[cfg(feature = "azure")]
 pub mod azure;
 #[cfg(feature = "baremetal")]
 pub mod baremetal;
+#[cfg(feature = "docker")]
+pub mod docker;
+#[cfg(feature = "firecracker")]
+pub mod firecracker;
+#[cfg(feature = "hetzner")]
+pub mod hetzner;
+pub mod hooks;
+#[cfg(feature = "nested")]
+pub mod nested;
+#[cfg(feature = "terraform")]
+pub mod terraform;
+#[cfg(feature = "vagrant")]
+pub mod vagrant;
 
-#[cfg(any(feature = "aws", feature = "azure"))]
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "docker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
 struct Sep(&'static str);
 
-#[cfg(any(feature = "aws", feature = "azure"))]
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "docker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
 impl Default for Sep {
     fn default() -> Self {
         Sep("_")
     }
 }
 
-#[cfg(any(feature = "aws", feature = "azure"))]
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "docker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
 impl From<&'static str> for Sep {
     fn from(s: &'static str) -> Self {
         Sep(s)
@@ -154,7 +490,15 @@ fn rand_name(prefix: &str) -> String {
     rand_name_sep(prefix, "_")
 }
 
-#[cfg(any(feature = "aws", feature = "azure"))]
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "docker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
 fn rand_name_sep(prefix: &str, sep: impl Into<Sep>) -> String {
     use rand::Rng;
     let rng = rand::thread_rng();
@@ -170,14 +514,147 @@ fn rand_name_sep(prefix: &str, sep: impl Into<Sep>) -> String {
     name
 }
 
-#[allow(clippy::too_many_arguments)]
-#[cfg(any(feature = "aws", feature = "azure"))]
+// checks whether a machine the provider considers running accepts SSH connections. Host keys are
+// checked if `check_host_key`, as for the machine's own descriptor.
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+#[instrument(level = "trace", skip(private_key))]
+async fn ssh_state(
+    public_ip: &str,
+    username: &str,
+    private_key: Option<&std::path::Path>,
+    port: u16,
+    check_host_key: bool,
+) -> MachineState {
+    let m = crate::MachineDescriptor {
+        nickname: Default::default(),
+        public_dns: None,
+        public_ip: public_ip.to_string(),
+        private_ip: None,
+        os: None,
+        proxy_command: None,
+        check_host_key,
+        _tsunami: Default::default(),
+    };
+    machine_state(m, username, private_key, port).await
+}
+
+// like `ssh_state`, but for a machine that may need more than its ip to reach.
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+async fn machine_state(
+    m: crate::MachineDescriptor<'_>,
+    username: &str,
+    private_key: Option<&std::path::Path>,
+    port: u16,
+) -> MachineState {
+    match m
+        .connect_ssh(
+            username,
+            private_key,
+            Some(std::time::Duration::from_secs(10)),
+            port,
+        )
+        .await
+    {
+        Ok(_) => MachineState::Ready,
+        Err(e) => {
+            tracing::debug!("ssh failed: {}", e);
+            MachineState::Unreachable
+        }
+    }
+}
+
+// Formats `t` as an ISO 8601 timestamp in UTC, as CloudWatch and the Alibaba Cloud API expect.
+#[cfg(any(feature = "aliyun", feature = "cloudwatch"))]
+fn iso8601(t: std::time::SystemTime) -> String {
+    let secs = t
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+// Generates a fresh ssh key pair with the local `ssh-keygen`, and returns the directory that
+// holds it, the path of the private key, and the public key.
+#[cfg(any(
+    feature = "aliyun",
+    feature = "azure",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "vagrant"
+))]
+#[instrument(level = "trace")]
+async fn generate_key() -> Result<(tempfile::TempDir, std::path::PathBuf, String), Report> {
+    use color_eyre::eyre::WrapErr;
+
+    let key_dir = tempfile::tempdir().wrap_err("failed to create directory for ssh key")?;
+    let key = key_dir.path().join("id_rsa");
+    let out = tokio::process::Command::new("ssh-keygen")
+        .args(["-q", "-t", "rsa", "-b", "3072", "-N", ""])
+        .arg("-f")
+        .arg(&key)
+        .output()
+        .await
+        .wrap_err("failed to run ssh-keygen")?;
+    eyre::ensure!(
+        out.status.success(),
+        "failed to generate ssh key: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let public_key = std::fs::read_to_string(key.with_extension("pub"))
+        .wrap_err("failed to read generated ssh key")?;
+    Ok((key_dir, key, public_key))
+}
+
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "hetzner"
+))]
 #[instrument(skip(max_wait, private_key, f))]
 async fn setup_machine(
     nickname: &str,
-    public_dns: Option<&str>,
-    public_ip: &str,
-    private_ip: Option<&str>,
+    m: crate::MachineDescriptor<'_>,
     username: &str,
     max_wait: Option<std::time::Duration>,
     private_key: Option<&std::path::Path>,
@@ -187,18 +664,340 @@ async fn setup_machine(
           + Send
           + Sync),
 ) -> Result<(), Report> {
-    let m = crate::MachineDescriptor {
-        nickname: Default::default(),
-        public_dns: public_dns.map(String::from),
-        public_ip: public_ip.to_string(),
-        private_ip: private_ip.map(String::from),
-        _tsunami: Default::default(),
-    };
-
     let mut m = m.connect_ssh(username, private_key, max_wait, 22).await?;
 
     tracing::debug!("setting up instance");
-    f(&mut m).await.wrap_err("setup procedure failed")?;
+    report_progress(nickname, MachineState::SettingUp);
+    if let Err(e) = f(&mut m).await {
+        report_progress(nickname, MachineState::SetupFailed);
+        return Err(e.wrap_err("setup procedure failed"));
+    }
     tracing::info!("instance ready");
+    report_progress(nickname, MachineState::Ready);
     Ok(())
 }
+
+// A launcher that creates each of its machines with its own API call, and keeps them in a single
+// list. Such launchers share the create -> wait -> roll back flow of the functions below.
+#[cfg(any(feature = "aliyun", feature = "hetzner"))]
+trait EachMachine: Launcher + Sync {
+    // What the launcher remembers about one machine.
+    type Machine: Send + Sync;
+
+    fn machines(&self) -> &[Self::Machine];
+    fn machines_mut(&mut self) -> &mut Vec<Self::Machine>;
+    fn nickname(m: &Self::Machine) -> &str;
+    // How to reach `m`: its address, the user to log in as, and the private key.
+    fn ssh<'l>(
+        &'l self,
+        m: &'l Self::Machine,
+    ) -> (
+        crate::MachineDescriptor<'l>,
+        &'l str,
+        Option<&'l std::path::Path>,
+    );
+    // Whether a failed launch deletes all of the machines it was asked to launch.
+    fn rolls_back(&self) -> bool;
+    // Deletes the machines in `gone`, which are no longer in `machines`.
+    fn delete<'l>(
+        &'l self,
+        gone: Vec<Self::Machine>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>>;
+}
+
+// Deletes the machines in `names`.
+#[cfg(any(feature = "aliyun", feature = "hetzner"))]
+async fn roll_back<L: EachMachine>(l: &mut L, names: &HashSet<String>) -> Result<(), Report> {
+    let (gone, keep): (Vec<_>, Vec<_>) = std::mem::take(l.machines_mut())
+        .into_iter()
+        .partition(|m| names.contains(L::nickname(m)));
+    *l.machines_mut() = keep;
+    l.delete(gone).await
+}
+
+// Remembers the machines that a launch of `names` created, so that `terminate_all` deletes them
+// even if others failed, and returns the first error. In all-or-nothing mode, the launch is
+// rolled back first.
+#[cfg(any(feature = "aliyun", feature = "hetzner"))]
+async fn keep_started<L: EachMachine>(
+    l: &mut L,
+    names: impl IntoIterator<Item = String>,
+    started: Vec<(Option<L::Machine>, Result<(), Report>)>,
+) -> Result<(), Report> {
+    let mut res = Ok(());
+    for (m, r) in started {
+        l.machines_mut().extend(m);
+        if let (Err(e), true) = (r, res.is_ok()) {
+            res = Err(e);
+        }
+    }
+    match res {
+        Err(e) if l.rolls_back() => {
+            let names = names.into_iter().collect();
+            let rollback = roll_back(l, &names).await;
+            Err(rolled_back(e, rollback))
+        }
+        res => res,
+    }
+}
+
+// Launches `descriptors` one region at a time. In all-or-nothing mode, a failed region also rolls
+// back the regions that were launched before it.
+#[cfg(any(feature = "aliyun", feature = "hetzner"))]
+async fn spawn_each<L, I>(
+    l: &mut L,
+    descriptors: I,
+    max_wait: Option<std::time::Duration>,
+) -> Result<(), Report>
+where
+    L: EachMachine,
+    I: IntoIterator<Item = (String, L::MachineDescriptor)>,
+{
+    tracing::info!("spinning up tsunami");
+
+    let plan = plan_descriptors(descriptors, max_wait)?;
+    let names: HashSet<_> = plan
+        .iter()
+        .flat_map(|d| d.machines.iter().map(|(name, _)| name.clone()))
+        .collect();
+    for dsc in plan {
+        let region_span = tracing::debug_span!("region", region = %dsc.region);
+        if let Err(e) = l.launch(dsc).instrument(region_span).await {
+            // the failed launch has cleaned up after itself, but the earlier ones have not.
+            if l.rolls_back() {
+                return Err(match roll_back(l, &names).await {
+                    Ok(()) => e,
+                    rollback => rolled_back(e, rollback),
+                });
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// Waits for the freshly started machine `m` to accept ssh connections, and then runs its setup
+// function, if any. sshd comes up a little after providers report a machine as running.
+#[cfg(any(feature = "aliyun", feature = "hetzner"))]
+async fn set_up_when_reachable(
+    nickname: &str,
+    m: crate::MachineDescriptor<'_>,
+    username: &str,
+    private_key: Option<&std::path::Path>,
+    setup_fn: Option<&SetupFn>,
+    max_wait: Option<std::time::Duration>,
+) -> Result<(), Report> {
+    let start = std::time::Instant::now();
+    while machine_state(m.clone(), username, private_key, 22).await != MachineState::Ready {
+        if let Some(wait_limit) = max_wait {
+            eyre::ensure!(start.elapsed() <= wait_limit, "wait limit reached");
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+
+    match setup_fn {
+        Some(f) => setup_machine(nickname, m, username, max_wait, private_key, f.as_ref()).await,
+        None => {
+            report_progress(nickname, MachineState::Ready);
+            Ok(())
+        }
+    }
+}
+
+// Connects to every machine of `l`.
+#[cfg(any(feature = "aliyun", feature = "hetzner"))]
+async fn connect_each<L: EachMachine>(
+    l: &L,
+) -> Result<HashMap<String, crate::Machine<'_>>, Report> {
+    futures_util::future::join_all(l.machines().iter().map(|m| {
+        let (d, username, private_key) = l.ssh(m);
+        let machine_span = tracing::trace_span!("machine", name = %d.nickname);
+        async move {
+            let name = d.nickname.clone();
+            let m = d.connect_ssh(username, private_key, None, 22).await?;
+            Ok::<_, Report>((name, m))
+        }
+        .instrument(machine_span)
+    }))
+    .await
+    .into_iter()
+    .collect()
+}
+
+// Describes every machine of `l`, without connecting to them.
+#[cfg(any(feature = "aliyun", feature = "hetzner"))]
+fn describe_each<L: EachMachine>(l: &L) -> HashMap<String, crate::LazyMachine<'_>> {
+    l.machines()
+        .iter()
+        .map(|m| {
+            let (d, username, private_key) = l.ssh(m);
+            (d.nickname.clone(), d.lazy(username, private_key, 22))
+        })
+        .collect()
+}
+
+// Runs `setup_fns` again on the machines in `nicknames`; see `Tsunami::rerun_setup`.
+#[cfg(any(
+    feature = "aliyun",
+    feature = "aws",
+    feature = "azure",
+    feature = "baremetal",
+    feature = "docker",
+    feature = "firecracker",
+    feature = "hetzner",
+    feature = "nested",
+    feature = "vagrant"
+))]
+pub(crate) async fn rerun_setup<'l>(
+    nicknames: Vec<String>,
+    setup_fns: Result<HashMap<String, SetupFn>, Report>,
+    machines: impl Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>>,
+) -> Result<(), Report> {
+    use color_eyre::{eyre::WrapErr, Help};
+
+    let setup_fns = setup_fns?;
+    let machines = machines.await?;
+    for name in &nicknames {
+        if !machines.contains_key(name) {
+            return Err(eyre::eyre!("there is no machine named {}", name))
+                .suggestion("Only machines that connect_all returns can be set up again");
+        }
+    }
+
+    // machines without a setup function have nothing to re-run.
+    let setups = nicknames
+        .iter()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .filter_map(|name| Some((name, &machines[name], setup_fns.get(name)?)));
+    futures_util::future::join_all(setups.map(|(name, m, f)| {
+        let machine_span = tracing::debug_span!("machine", %name);
+        async move {
+            tracing::debug!("re-running setup");
+            report_progress(name, MachineState::SettingUp);
+            if let Err(e) = f(m).await {
+                report_progress(name, MachineState::SetupFailed);
+                return Err(e)
+                    .wrap_err("setup procedure failed")
+                    .wrap_err_with(|| format!("failed to set up {} again", name));
+            }
+            tracing::info!("machine set up again");
+            report_progress(name, MachineState::Ready);
+            Ok(())
+        }
+        .instrument(machine_span)
+    }))
+    .await
+    .into_iter()
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Fake(&'static str);
+
+    impl MachineSetup for Fake {
+        type Region = String;
+        fn region(&self) -> Self::Region {
+            self.0.to_string()
+        }
+    }
+
+    #[test]
+    fn plan_groups_in_order() {
+        let plan = plan_descriptors(
+            vec![
+                (String::from("x"), Fake("b")),
+                (String::from("y"), Fake("a")),
+                (String::from("z"), Fake("b")),
+            ],
+            None,
+        )
+        .unwrap();
+        let plan: Vec<_> = plan
+            .iter()
+            .map(|d| {
+                let names: Vec<_> = d.machines.iter().map(|(n, _)| n.as_str()).collect();
+                (d.region.as_str(), names)
+            })
+            .collect();
+        assert_eq!(plan, vec![("b", vec!["x", "z"]), ("a", vec!["y"])]);
+    }
+
+    #[test]
+    fn plan_rejects_duplicates() {
+        assert!(plan_descriptors(
+            vec![
+                (String::from("x"), Fake("a")),
+                (String::from("x"), Fake("b"))
+            ],
+            None,
+        )
+        .is_err());
+    }
+
+    #[test]
+    #[cfg(any(feature = "aliyun", feature = "cloudwatch"))]
+    fn iso8601_timestamps() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let t = |s| UNIX_EPOCH + Duration::from_secs(s);
+        assert_eq!(iso8601(t(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(iso8601(t(951_825_599)), "2000-02-29T11:59:59Z");
+        assert_eq!(iso8601(t(1_700_000_000)), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "hetzner"
+    ))]
+    fn rollback_errors() {
+        let e = rolled_back(eyre::eyre!("no capacity"), Ok(()));
+        let chain: Vec<_> = e.chain().map(|e| e.to_string()).collect();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[1], "no capacity");
+
+        // the launch failure is kept when terminating the machines fails too
+        let e = rolled_back(
+            eyre::eyre!("no capacity"),
+            Err(eyre::eyre!("failed to delete servers")),
+        );
+        let chain: Vec<_> = e.chain().map(|e| e.to_string()).collect();
+        assert_eq!(chain[1], "no capacity");
+        assert_eq!(chain[2], "failed to delete servers");
+    }
+
+    #[tokio::test]
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "nested",
+        feature = "vagrant"
+    ))]
+    async fn rerun_setup_errors() {
+        let none = || async { Ok(HashMap::new()) };
+        // nothing to do
+        assert!(rerun_setup(vec![], Ok(HashMap::new()), none())
+            .await
+            .is_ok());
+        // a launcher that does not keep setup functions
+        assert!(rerun_setup(vec![], Err(eyre::eyre!("unsupported")), none())
+            .await
+            .is_err());
+        // a machine that was never spawned
+        let e = rerun_setup(vec![String::from("x")], Ok(HashMap::new()), none())
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "there is no machine named x");
+    }
+}
@@ -0,0 +1,554 @@
+//! Nested backend for tsunami.
+//!
+//! This launcher wraps another [`Launcher`](super::Launcher), and after the inner launcher has
+//! brought up its machines, starts a number of containers on each of them. Every container is
+//! exposed as its own [`crate::Machine`] with its own nickname and SSH session, so a handful of
+//! VMs can emulate a much larger topology.
+//!
+//! Containers are started with `docker` on the host, so the host image must have docker
+//! installed (for example, by calling [`crate::OsFamily::install_packages`] with `docker.io` in
+//! the host's setup function). The container image must run `sshd` on port 22; the host user's
+//! `~/.ssh/authorized_keys` is copied into the container as `/root/.ssh/authorized_keys`, owned
+//! by root as `sshd` requires, so the same key that reaches the host also reaches its
+//! containers. Container `i` on a host is reachable on port [`Setup::base_port`]` + i` of the host, which must be reachable from the
+//! controller (on AWS, use [`aws::Launcher::open_ports`](super::aws::Launcher::open_ports)).
+//!
+//! Container `i` on the host with nickname `host` gets the nickname `host-i`. The hosts
+//! themselves remain in the machine map under their own nicknames.
+//!
+//! # Example
+//! ```rust,no_run
+//! use tsunami::providers::{aws, nested};
+//! use tsunami::Tsunami;
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     let mut inner = aws::Launcher::default();
+//!     inner.open_ports();
+//!     let mut l = nested::Launcher::new(inner);
+//!     let host = aws::Setup::default().setup(|vm| {
+//!         Box::pin(async move {
+//!             tsunami::OsFamily::Ubuntu
+//!                 .install_packages(&vm.ssh, &["docker.io"])
+//!                 .status()
+//!                 .await?;
+//!             Ok(())
+//!         })
+//!     });
+//!     l.spawn(
+//!         tsunami::make_multiple(20, "vm", nested::Setup::new(host, 50)),
+//!         None,
+//!     )
+//!     .await?;
+//!     let vms = l.connect_all().await?;
+//!     assert_eq!(vms.len(), 20 + 20 * 50);
+//!     l.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use color_eyre::{
+    eyre::{self, WrapErr},
+    Report,
+};
+use educe::Educe;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+/// Descriptor for a host machine and the containers to start on it.
+#[derive(Clone, Educe)]
+#[educe(Debug(bound))]
+pub struct Setup<S> {
+    host: S,
+    containers: usize,
+    image: String,
+    username: String,
+    base_port: u16,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<
+        Arc<
+            dyn for<'r> Fn(
+                    &'r crate::Machine<'_>,
+                )
+                    -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >,
+}
+
+impl<S: super::MachineSetup> super::MachineSetup for Setup<S> {
+    type Region = S::Region;
+
+    fn region(&self) -> Self::Region {
+        self.host.region()
+    }
+}
+
+impl<S> Setup<S> {
+    /// Start `containers` containers on each host described by `host`.
+    ///
+    /// By default, containers run the `rastasheep/ubuntu-sshd` image, are logged into as `root`,
+    /// and are exposed on host ports starting at 2200.
+    pub fn new(host: S, containers: usize) -> Self {
+        Setup {
+            host,
+            containers,
+            image: String::from("rastasheep/ubuntu-sshd"),
+            username: String::from("root"),
+            base_port: 2200,
+            setup_fn: None,
+        }
+    }
+
+    /// Set the container image.
+    ///
+    /// The image must run `sshd` on port 22 and accept keys from `/root/.ssh/authorized_keys`.
+    pub fn image(mut self, image: impl ToString) -> Self {
+        self.image = image.to_string();
+        self
+    }
+
+    /// Set the username used to ssh into the containers.
+    pub fn username(mut self, username: impl ToString) -> Self {
+        self.username = username.to_string();
+        self
+    }
+
+    /// Set the first host port that containers are exposed on.
+    pub fn base_port(mut self, port: u16) -> Self {
+        self.base_port = port;
+        self
+    }
+
+    /// Specify container setup.
+    ///
+    /// The provided callback, `setup`, is called once for every container, after the host's own
+    /// setup has completed.
+    pub fn setup(
+        mut self,
+        setup: impl for<'r> Fn(
+                &'r crate::Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.setup_fn = Some(Arc::new(setup));
+        self
+    }
+}
+
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+struct Container {
+    name: String,
+    host: String,
+    docker_name: String,
+    public_ip: String,
+    private_ip: Option<String>,
+    port: u16,
+    username: String,
+    key_path: Option<std::path::PathBuf>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<super::SetupFn>,
+}
+
+impl Container {
+    async fn connect<'l>(
+        &self,
+        max_wait: Option<std::time::Duration>,
+    ) -> Result<crate::Machine<'l>, Report> {
+        self.descriptor()
+            .connect_ssh(
+                &self.username,
+                self.key_path.as_deref(),
+                max_wait,
+                self.port,
+            )
+            .await
+    }
+
+    fn descriptor<'l>(&self) -> crate::MachineDescriptor<'l> {
+        crate::MachineDescriptor {
+            nickname: self.name.clone(),
+            public_dns: None,
+            public_ip: self.public_ip.clone(),
+            private_ip: self.private_ip.clone(),
+            os: None,
+            proxy_command: None,
+            check_host_key: true,
+            _tsunami: Default::default(),
+        }
+    }
+}
+
+/// Launcher that starts containers on the machines of another launcher.
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Default)]
+pub struct Launcher<L> {
+    inner: L,
+    containers: Vec<Container>,
+    // containers that were created but did not come up; they are only removed.
+    failed: Vec<Container>,
+}
+
+impl<L> Launcher<L> {
+    /// Wrap `inner`, which launches the hosts.
+    pub fn new(inner: L) -> Self {
+        Launcher {
+            inner,
+            containers: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+}
+
+#[instrument(level = "debug", skip(host))]
+async fn start_container(
+    host: &crate::Machine<'_>,
+    docker_name: &str,
+    hostname: &str,
+    image: &str,
+    port: u16,
+) -> Result<Option<String>, Report> {
+    let out = host
+        .ssh
+        .command("sudo")
+        .arg("docker")
+        .arg("run")
+        .arg("-d")
+        .arg("--name")
+        .arg(docker_name)
+        .arg("--hostname")
+        .arg(hostname)
+        .arg("-p")
+        .arg(format!("{}:22", port))
+        .arg(image)
+        .output()
+        .await
+        .wrap_err("docker run")?;
+    eyre::ensure!(
+        out.status.success(),
+        "failed to start container: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    // a bind mount would keep the host user as the owner, which sshd's StrictModes rejects.
+    let out = host
+        .ssh
+        .shell(format!(
+            "sudo docker exec -i {} sh -c 'mkdir -p /root/.ssh && cat > /root/.ssh/authorized_keys && chown -R root:root /root/.ssh && chmod 700 /root/.ssh && chmod 600 /root/.ssh/authorized_keys' < \"$HOME/.ssh/authorized_keys\"",
+            docker_name
+        ))
+        .output()
+        .await
+        .wrap_err("docker exec")?;
+    eyre::ensure!(
+        out.status.success(),
+        "failed to install authorized_keys in container: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+
+    let out = host
+        .ssh
+        .command("sudo")
+        .arg("docker")
+        .arg("inspect")
+        .arg("-f")
+        .arg("{{.NetworkSettings.IPAddress}}")
+        .arg(docker_name)
+        .output()
+        .await
+        .wrap_err("docker inspect")?;
+    let ip = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    Ok(if out.status.success() && !ip.is_empty() {
+        Some(ip)
+    } else {
+        None
+    })
+}
+
+impl<L> super::Launcher for Launcher<L>
+where
+    L: super::Launcher + Sync + 'static,
+    L::MachineDescriptor: std::fmt::Debug,
+{
+    type MachineDescriptor = Setup<L::MachineDescriptor>;
+
+    #[instrument(level = "debug", skip(self))]
+    fn launch<'l>(
+        &'l mut self,
+        l: super::LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(
+            async move {
+                let max_wait = l.max_wait;
+                let (hosts, specs): (Vec<_>, HashMap<_, _>) = l
+                    .machines
+                    .into_iter()
+                    .map(|(name, s)| {
+                        let Setup {
+                            host,
+                            containers,
+                            image,
+                            username,
+                            base_port,
+                            setup_fn,
+                        } = s;
+                        let spec = Setup {
+                            host: (),
+                            containers,
+                            image,
+                            username,
+                            base_port,
+                            setup_fn,
+                        };
+                        ((name.clone(), host), (name, spec))
+                    })
+                    .unzip();
+
+                self.inner
+                    .launch(super::LaunchDescriptor {
+                        region: l.region,
+                        max_wait,
+                        machines: hosts,
+                    })
+                    .await
+                    .wrap_err("failed to launch hosts")?;
+
+                let machines = self.inner.connect_all().await?;
+                let containers = futures_util::future::join_all(
+                    machines
+                        .iter()
+                        .filter_map(|(host_name, host)| {
+                            specs.get(host_name).map(|s| (host_name, host, s))
+                        })
+                        .flat_map(|(host_name, host, s)| {
+                            (0..s.containers).map(move |i| (host_name, host, s, i))
+                        })
+                        .map(|(host_name, host, s, i)| {
+                            let name = format!("{}-{}", host_name, i);
+                            let container_span = tracing::debug_span!("container", %name);
+                            async move {
+                                super::report_progress(&name, super::MachineState::Booting);
+                                let port = match u16::try_from(i)
+                                    .ok()
+                                    .and_then(|i| s.base_port.checked_add(i))
+                                {
+                                    Some(port) => port,
+                                    None => {
+                                        let e = eyre::eyre!(
+                                            "container {} needs port {} + {}, which is out of range",
+                                            name,
+                                            s.base_port,
+                                            i
+                                        );
+                                        return (None, Err(e));
+                                    }
+                                };
+                                // recorded before the container is started, so that
+                                // terminate_all removes it even if starting it fails halfway.
+                                let mut c = Container {
+                                    name,
+                                    host: host_name.clone(),
+                                    docker_name: super::rand_name_sep("container", "-"),
+                                    public_ip: host.public_ip.clone(),
+                                    private_ip: None,
+                                    port,
+                                    username: s.username.clone(),
+                                    key_path: host.private_key.clone(),
+                                    setup_fn: s.setup_fn.clone(),
+                                };
+
+                                let res = async {
+                                    c.private_ip =
+                                        start_container(host, &c.docker_name, &c.name, &s.image, port)
+                                            .await?;
+
+                                    if let Some(ref f) = s.setup_fn {
+                                        let m = c.connect(max_wait).await?;
+                                        tracing::debug!("setting up container");
+                                        super::report_progress(
+                                            &c.name,
+                                            super::MachineState::SettingUp,
+                                        );
+                                        if let Err(e) = f(&m).await {
+                                            super::report_progress(
+                                                &c.name,
+                                                super::MachineState::SetupFailed,
+                                            );
+                                            return Err(e.wrap_err("setup procedure failed"));
+                                        }
+                                    }
+                                    Ok::<_, Report>(())
+                                }
+                                .await;
+
+                                if res.is_ok() {
+                                    tracing::info!("container ready");
+                                    super::report_progress(&c.name, super::MachineState::Ready);
+                                }
+                                (Some(c), res)
+                            }
+                            .instrument(container_span)
+                        }),
+                )
+                .await;
+
+                // remember every container, so that terminate_all removes them even if some
+                // failed.
+                let mut res = Ok(());
+                for (c, r) in containers {
+                    match (c, r) {
+                        (Some(c), Ok(())) => self.containers.push(c),
+                        (c, Err(e)) => {
+                            self.failed.extend(c);
+                            if res.is_ok() {
+                                res = Err(e);
+                            }
+                        }
+                        (None, Ok(())) => unreachable!("containers without a port never start"),
+                    }
+                }
+
+                res
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        Box::pin(
+            async move {
+                let mut machines = self.inner.connect_all().await?;
+                let containers = futures_util::future::join_all(self.containers.iter().map(|c| {
+                    let container_span = tracing::trace_span!("container", name = %c.name);
+                    async move { Ok::<_, Report>((c.name.clone(), c.connect(None).await?)) }
+                        .instrument(container_span)
+                }))
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, Report>>()?;
+                machines.extend(containers);
+                Ok(machines)
+            }
+            .in_current_span(),
+        )
+    }
+
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        let mut machines = self.inner.describe_all()?;
+        machines.extend(self.containers.iter().map(|c| {
+            let m = c
+                .descriptor()
+                .lazy(&c.username, c.key_path.as_deref(), c.port);
+            (c.name.clone(), m)
+        }));
+        Ok(machines)
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        let mut fns = self.inner.setup_fns()?;
+        fns.extend(
+            self.containers
+                .iter()
+                .filter_map(|c| Some((c.name.clone(), c.setup_fn.clone()?))),
+        );
+        Ok(fns)
+    }
+
+    // containers are started fresh from their image, so only the outer machines have snapshots.
+    fn restore_clean<'l>(
+        &'l self,
+        nicknames: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        self.inner.restore_clean(nicknames)
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, super::MachineState>, Report>> + Send + 'l>,
+    > {
+        Box::pin(
+            async move {
+                let mut states = self.inner.status().await?;
+                let containers = futures_util::future::join_all(self.containers.iter().map(|c| {
+                    // a container can only be as alive as its host
+                    let host = states.get(&c.host).copied();
+                    async move {
+                        let state = match host {
+                            Some(super::MachineState::Ready) => {
+                                super::ssh_state(
+                                    &c.public_ip,
+                                    &c.username,
+                                    c.key_path.as_deref(),
+                                    c.port,
+                                    c.descriptor().check_host_key,
+                                )
+                                .await
+                            }
+                            Some(s) => s,
+                            None => super::MachineState::Terminated,
+                        };
+                        (c.name.clone(), state)
+                    }
+                }))
+                .await;
+                states.extend(containers);
+                Ok(states)
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        Box::pin(
+            async move {
+                // hosts that outlive the inner launcher (e.g., baremetal) would otherwise keep
+                // running our containers.
+                match self.inner.connect_all().await {
+                    Ok(hosts) => {
+                        for c in self.containers.iter().chain(&self.failed) {
+                            if let Some(host) = hosts.get(&c.host) {
+                                let out = host
+                                    .ssh
+                                    .command("sudo")
+                                    .arg("docker")
+                                    .arg("rm")
+                                    .arg("-f")
+                                    .arg(&c.docker_name)
+                                    .status()
+                                    .await;
+                                if !matches!(out, Ok(s) if s.success()) {
+                                    tracing::warn!(name = %c.name, "failed to remove container");
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        // the hosts still need to be terminated.
+                        tracing::warn!("failed to connect to hosts to remove containers: {:?}", e);
+                    }
+                }
+
+                self.inner.terminate_all().await
+            }
+            .in_current_span(),
+        )
+    }
+}
@@ -0,0 +1,278 @@
+//! Use hosts provisioned with Terraform.
+//!
+//! This backend does not create or destroy anything. It reads the addresses of existing hosts
+//! from a Terraform output, and connects to them like the [baremetal](super::baremetal) backend
+//! does. Declare an output that maps nicknames to addresses:
+//!
+//! ```hcl
+//! output "tsunami_hosts" {
+//!   value = {
+//!     server = aws_instance.server.public_ip
+//!     client = {
+//!       address  = aws_instance.client.public_ip
+//!       username = "ubuntu"
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! An address may be an IP address or host name, optionally with a `:port`. The object form also
+//! accepts `port`, `username`, and `private_key` (a path). A list of addresses is also accepted, in
+//! which case the machines are nicknamed `<output>-0`, `<output>-1`, and so on.
+//!
+//! ```rust,no_run
+//! use tsunami::providers::terraform;
+//! use tsunami::Tsunami;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     // runs `terraform output -json` in the given directory
+//!     let hosts = terraform::hosts("infra/", "tsunami_hosts")?;
+//!     let mut l = terraform::Launcher::default();
+//!     l.spawn(hosts, None).await?;
+//!     let vms = l.connect_all().await?;
+//!     // ...
+//!     l.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use super::baremetal;
+use color_eyre::{
+    eyre::{self, eyre, WrapErr},
+    Report,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use tracing::instrument;
+
+// Turns one entry of the hosts output into a baremetal Setup.
+fn host(nickname: &str, v: &Value) -> Result<baremetal::Setup, Report> {
+    let (address, port, username, key) = match v {
+        Value::String(a) => (a.as_str(), None, None, None),
+        Value::Object(o) => {
+            let field = |k: &str| o.get(k).and_then(Value::as_str);
+            let address = field("address")
+                .or_else(|| field("host"))
+                .ok_or_else(|| eyre!("{} has no address", nickname))?;
+            let port = match o.get("port") {
+                Some(Value::Number(n)) => n.as_u64(),
+                Some(Value::String(s)) => s.parse().ok(),
+                _ => None,
+            };
+            let port = port
+                .map(|p| {
+                    u16::try_from(p).map_err(|_| eyre!("{} has out of range port {}", nickname, p))
+                })
+                .transpose()?;
+            (address, port, field("username"), field("private_key"))
+        }
+        _ => eyre::bail!("{} is neither an address nor an object", nickname),
+    };
+
+    let addr = match (address.parse::<std::net::IpAddr>(), port) {
+        (Ok(ip), p) => std::net::SocketAddr::new(ip, p.unwrap_or(22)).to_string(),
+        (Err(_), Some(p)) => format!("{}:{}", address, p),
+        (Err(_), None) if address.parse::<std::net::SocketAddr>().is_ok() => address.to_string(),
+        (Err(_), None) => match address.rsplit_once(':') {
+            Some((_, p)) if p.parse::<u16>().is_ok() => address.to_string(),
+            _ => format!("{}:22", address),
+        },
+    };
+
+    let mut setup = baremetal::Setup::new(addr, username.map(String::from))
+        .wrap_err_with(|| format!("invalid address for {}", nickname))?;
+    if let Some(key) = key {
+        setup = setup.key_path(key);
+    }
+    Ok(setup)
+}
+
+/// Read the hosts in output `output` from Terraform's JSON, which is either the output of
+/// `terraform output -json` or a state file.
+pub fn hosts_from_json(
+    json: &str,
+    output: &str,
+) -> Result<Vec<(String, baremetal::Setup)>, Report> {
+    let json: Value = serde_json::from_str(json).wrap_err("invalid terraform json")?;
+    // state files keep the outputs in a field of their own.
+    let outputs = json.get("outputs").unwrap_or(&json);
+    let value = outputs
+        .get(output)
+        .and_then(|o| o.get("value"))
+        .ok_or_else(|| eyre!("terraform has no output named {}", output))?;
+
+    match value {
+        Value::Object(hosts) => hosts
+            .iter()
+            .map(|(name, v)| Ok((name.clone(), host(name, v)?)))
+            .collect(),
+        Value::Array(hosts) => hosts
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let name = format!("{}-{}", output, i);
+                let setup = host(&name, v)?;
+                Ok((name, setup))
+            })
+            .collect(),
+        _ => eyre::bail!("output {} is neither a map nor a list of hosts", output),
+    }
+}
+
+/// Read the hosts in output `output` from the Terraform state file at `path`.
+pub fn hosts_from_state(
+    path: impl AsRef<Path>,
+    output: &str,
+) -> Result<Vec<(String, baremetal::Setup)>, Report> {
+    let path = path.as_ref();
+    let json = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+    hosts_from_json(&json, output)
+}
+
+/// Read the hosts in output `output` by running `terraform output -json` in directory `dir`.
+///
+/// The `terraform` command must be in your `$PATH`.
+#[instrument(level = "debug", skip(dir))]
+pub fn hosts(
+    dir: impl AsRef<Path>,
+    output: &str,
+) -> Result<Vec<(String, baremetal::Setup)>, Report> {
+    let out = std::process::Command::new("terraform")
+        .args(["output", "-json"])
+        .current_dir(dir)
+        .output()
+        .wrap_err("failed to run terraform")?;
+    eyre::ensure!(
+        out.status.success(),
+        "terraform output failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    hosts_from_json(&String::from_utf8_lossy(&out.stdout), output)
+}
+
+/// Launcher type for hosts provisioned with Terraform.
+///
+/// This holds one [`baremetal::Machine`] per host. Terminating it leaves the hosts running; tear
+/// them down with `terraform destroy`.
+#[derive(Debug, Default)]
+pub struct Launcher {
+    hosts: Vec<baremetal::Machine>,
+}
+
+impl super::Launcher for Launcher {
+    type MachineDescriptor = baremetal::Setup;
+
+    #[instrument(level = "debug", skip(self))]
+    fn launch<'l>(
+        &'l mut self,
+        l: super::LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(async move {
+            let mut m = baremetal::Machine::default();
+            m.launch(l).await?;
+            self.hosts.push(m);
+            Ok(())
+        })
+    }
+
+    #[instrument(level = "debug")]
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        Box::pin(async move {
+            let ms = futures_util::future::join_all(self.hosts.iter().map(|h| h.connect_all()))
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, Report>>()?;
+            Ok(ms.into_iter().flatten().collect())
+        })
+    }
+
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        let mut machines = HashMap::new();
+        for h in &self.hosts {
+            machines.extend(super::Launcher::describe_all(h)?);
+        }
+        Ok(machines)
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        let fns = self
+            .hosts
+            .iter()
+            .map(super::Launcher::setup_fns)
+            .collect::<Result<Vec<_>, Report>>()?;
+        Ok(fns.into_iter().flatten().collect())
+    }
+
+    #[instrument(level = "debug")]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, super::MachineState>, Report>> + Send + 'l>,
+    > {
+        Box::pin(async move {
+            let states = futures_util::future::join_all(self.hosts.iter().map(|h| h.status()))
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, Report>>()?;
+            Ok(states.into_iter().flatten().collect())
+        })
+    }
+
+    fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        Box::pin(async move { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::MachineSetup;
+
+    #[test]
+    fn parse_outputs() {
+        let out = r#"{
+            "tsunami_hosts": {
+                "sensitive": false,
+                "type": ["object", {}],
+                "value": {
+                    "server": "10.0.0.1",
+                    "client": { "address": "10.0.0.2", "port": 2222, "username": "ubuntu" }
+                }
+            }
+        }"#;
+        let mut hosts = hosts_from_json(out, "tsunami_hosts").unwrap();
+        hosts.sort_by(|a, b| a.0.cmp(&b.0));
+        let regions: Vec<_> = hosts
+            .iter()
+            .map(|(n, s)| (n.as_str(), s.region().to_string()))
+            .collect();
+        assert_eq!(
+            regions,
+            vec![
+                ("client", String::from("bare:10.0.0.2:2222")),
+                ("server", String::from("bare:10.0.0.1:22"))
+            ]
+        );
+
+        let state = r#"{"version": 4, "outputs": {"ips": {"value": ["10.0.0.3:23"]}}}"#;
+        let hosts = hosts_from_json(state, "ips").unwrap();
+        assert_eq!(hosts[0].0, "ips-0");
+        assert_eq!(hosts[0].1.region().to_string(), "bare:10.0.0.3:23");
+
+        assert!(hosts_from_json(state, "missing").is_err());
+
+        let out = r#"{"hosts": {"value": {"big": {"address": "10.0.0.4", "port": 65558}}}}"#;
+        let err = hosts_from_json(out, "hosts").unwrap_err();
+        assert!(format!("{:?}", err).contains("big"));
+    }
+}
@@ -0,0 +1,826 @@
+//! Vagrant backend for tsunami.
+//!
+//! Every machine is a local virtual machine managed by [Vagrant](https://www.vagrantup.com/).
+//! This needs no cloud credentials, which makes it a good fit for tutorials and CI jobs that
+//! exercise multi-machine setups. Unlike [containers](super::docker), the machines run their own
+//! kernel, so kernel modules, sysctls, and reboots work as they do on cloud machines.
+//!
+//! Each launcher generates a Vagrantfile that defines all of its machines in a temporary
+//! directory, and brings them up with `vagrant up`. The machines are reached with the SSH
+//! parameters that `vagrant ssh-config` reports, which are usually a forwarded port on
+//! `127.0.0.1` and the box's private key. Vagrant reuses forwarded ports across machines, so like
+//! `vagrant ssh`, tsunami follows the `StrictHostKeyChecking` and `UserKnownHostsFile` settings
+//! from `vagrant ssh-config`, which by default skip host key checks and leave
+//! `~/.ssh/known_hosts` alone.
+//!
+//! All machines of a launcher are attached to a private network, and their addresses on it are
+//! their [`crate::Machine::private_ip`]s. The addresses are picked from `192.168.56.0/21`, which
+//! is the range VirtualBox allows for host-only networks by default.
+//!
+//! With [`Launcher::clean_snapshots`], every machine is snapshotted after it boots and before its
+//! setup function runs, so that [`Tsunami::rerun_setup_clean`](crate::Tsunami::rerun_setup_clean)
+//! can re-apply a changed setup recipe to a clean machine without booting a new one.
+//!
+//! The machines are destroyed by [`terminate_all`](super::Launcher::terminate_all). If the
+//! launcher is dropped without it, `vagrant global-status --prune` lists the leftovers.
+//!
+//! # Example
+//! ```rust,no_run
+//! use tsunami::providers::vagrant;
+//! use tsunami::Tsunami;
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     let mut l = vagrant::Launcher::default();
+//!     let m = vagrant::Setup::default().cpus(2).memory(2048);
+//!     l.spawn(tsunami::make_multiple(2, "vm", m), None).await?;
+//!     let vms = l.connect_all().await?;
+//!     assert_eq!(vms.len(), 2);
+//!     l.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use color_eyre::{
+    eyre::{self, WrapErr},
+    Help, Report,
+};
+use educe::Educe;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::instrument;
+use tracing_futures::Instrument;
+
+/// Descriptor for a single Vagrant machine.
+///
+/// The default is a `bento/ubuntu-22.04` box with the box's own CPU and memory settings, on
+/// Vagrant's default provider.
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub struct Setup {
+    vagrant_box: String,
+    provider: Option<String>,
+    cpus: Option<u32>,
+    memory: Option<u32>,
+    os: Option<crate::OsFamily>,
+    config: Vec<String>,
+    #[educe(Debug(ignore))]
+    setup_fn: Option<
+        Arc<
+            dyn for<'r> Fn(
+                    &'r crate::Machine<'_>,
+                )
+                    -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+                + Send
+                + Sync
+                + 'static,
+        >,
+    >,
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Setup {
+            vagrant_box: String::from("bento/ubuntu-22.04"),
+            provider: None,
+            cpus: None,
+            memory: None,
+            os: Some(crate::OsFamily::Ubuntu),
+            config: Vec::new(),
+            setup_fn: None,
+        }
+    }
+}
+
+/// The [`MachineSetup::Region`](super::MachineSetup::Region) for [`Setup`].
+///
+/// All machines run on the local host. It is displayed as `vagrant:local`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Local;
+
+impl std::fmt::Display for Local {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("vagrant:local")
+    }
+}
+
+impl std::str::FromStr for Local {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        eyre::ensure!(s == "vagrant:local", "unknown vagrant region {:?}", s);
+        Ok(Local)
+    }
+}
+
+impl super::MachineSetup for Setup {
+    type Region = Local;
+
+    fn region(&self) -> Self::Region {
+        Local
+    }
+}
+
+impl Setup {
+    /// Set the box, such as `generic/debian12`.
+    ///
+    /// This clears any previously set [`Setup::os`].
+    pub fn vagrant_box(mut self, vagrant_box: impl ToString) -> Self {
+        self.vagrant_box = vagrant_box.to_string();
+        self.os = None;
+        self
+    }
+
+    /// Use the box described by `image`.
+    ///
+    /// [`ImageSpec::CustomByName`](crate::image::ImageSpec::CustomByName) and
+    /// [`ImageSpec::ProviderSpecific`](crate::image::ImageSpec::ProviderSpecific) name the box
+    /// directly. Images of a known operating system also set [`Setup::os`].
+    pub fn image_spec(self, image: crate::image::ImageSpec) -> Self {
+        use crate::image::ImageSpec;
+        let os = image.os();
+        let vagrant_box = match image {
+            ImageSpec::Ubuntu2004 => String::from("bento/ubuntu-20.04"),
+            ImageSpec::Ubuntu2204 => String::from("bento/ubuntu-22.04"),
+            ImageSpec::Debian11 => String::from("bento/debian-11"),
+            ImageSpec::CustomByName(b) | ImageSpec::ProviderSpecific(b) => b,
+        };
+        Self {
+            os,
+            ..self.vagrant_box(vagrant_box)
+        }
+    }
+
+    /// Run the machine on the Vagrant provider `provider`, such as `virtualbox` or `libvirt`,
+    /// instead of the default one.
+    pub fn provider(mut self, provider: impl ToString) -> Self {
+        self.provider = Some(provider.to_string());
+        self
+    }
+
+    /// Give the machine `cpus` virtual CPUs.
+    ///
+    /// This is applied for the `virtualbox` and `libvirt` providers. Use [`Setup::config`] for
+    /// others.
+    pub fn cpus(mut self, cpus: u32) -> Self {
+        self.cpus = Some(cpus);
+        self
+    }
+
+    /// Give the machine `mb` megabytes of memory.
+    ///
+    /// This is applied for the `virtualbox` and `libvirt` providers. Use [`Setup::config`] for
+    /// others.
+    pub fn memory(mut self, mb: u32) -> Self {
+        self.memory = Some(mb);
+        self
+    }
+
+    /// Declare which operating system family the box runs.
+    ///
+    /// The family is available to setup functions as [`crate::Machine::os`].
+    pub fn os(mut self, os: crate::OsFamily) -> Self {
+        self.os = Some(os);
+        self
+    }
+
+    /// Add a line of Ruby to the machine's definition in the Vagrantfile, in which the machine is
+    /// `m`, such as `m.vm.provider "vmware_desktop" do |v| v.vmx["numvcpus"] = "4" end`.
+    pub fn config(mut self, line: impl ToString) -> Self {
+        self.config.push(line.to_string());
+        self
+    }
+
+    /// Specify machine setup.
+    ///
+    /// The provided callback, `setup`, is called once for every spawned machine of this type
+    /// with a handle to the machine. Use [`crate::Machine::ssh`] to issue commands on it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use tsunami::providers::vagrant::Setup;
+    /// let m = Setup::default().setup(|vm| {
+    ///     Box::pin(async move {
+    ///         vm.ssh
+    ///             .command("sudo")
+    ///             .arg("apt-get")
+    ///             .arg("update")
+    ///             .status()
+    ///             .await?;
+    ///         Ok(())
+    ///     })
+    /// });
+    /// ```
+    pub fn setup(
+        mut self,
+        setup: impl for<'r> Fn(
+                &'r crate::Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.setup_fn = Some(Arc::new(setup));
+        self
+    }
+
+    /// Check that the machine matches `expect` once it is up, before running the
+    /// [`setup`](Setup::setup) function.
+    ///
+    /// See the [`verify`](crate::verify) module for what happens when the check fails, and for
+    /// when to call this.
+    pub fn verify(mut self, expect: crate::verify::Expectations) -> Self {
+        self.setup_fn = Some(super::before_setup(
+            expect.into_setup_fn(),
+            self.setup_fn.take(),
+        ));
+        self
+    }
+}
+
+// `s` as a Ruby string literal.
+fn ruby_str(s: &str) -> String {
+    let mut lit = String::from("\"");
+    for c in s.chars() {
+        if matches!(c, '"' | '\\' | '#') {
+            lit.push('\\');
+        }
+        lit.push(c);
+    }
+    lit.push('"');
+    lit
+}
+
+// A hostname for the machine `name`.
+fn hostname(name: &str) -> String {
+    let h: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' => c,
+            'A'..='Z' => c.to_ascii_lowercase(),
+            _ => '-',
+        })
+        .take(63)
+        .collect();
+    let h = h.trim_matches('-');
+    if h.is_empty() {
+        String::from("tsunami")
+    } else {
+        h.to_string()
+    }
+}
+
+// The Vagrantfile that defines `machines`.
+fn vagrantfile(machines: &[Vm], max_wait: Option<Duration>) -> String {
+    let mut f = String::from("Vagrant.configure(\"2\") do |config|\n");
+    f.push_str("  config.vm.synced_folder \".\", \"/vagrant\", disabled: true\n");
+    for vm in machines {
+        let s = &vm.setup;
+        let _ = writeln!(f, "  config.vm.define {} do |m|", ruby_str(&vm.name));
+        let _ = writeln!(f, "    m.vm.box = {}", ruby_str(&s.vagrant_box));
+        let _ = writeln!(f, "    m.vm.hostname = {}", ruby_str(&hostname(&vm.name)));
+        let _ = writeln!(
+            f,
+            "    m.vm.network \"private_network\", ip: {}",
+            ruby_str(&vm.private_ip)
+        );
+        if let Some(t) = max_wait {
+            let _ = writeln!(f, "    m.vm.boot_timeout = {}", t.as_secs().max(1));
+        }
+        if s.cpus.is_some() || s.memory.is_some() {
+            for provider in ["virtualbox", "libvirt"] {
+                let _ = writeln!(f, "    m.vm.provider {} do |p|", ruby_str(provider));
+                if let Some(cpus) = s.cpus {
+                    let _ = writeln!(f, "      p.cpus = {}", cpus);
+                }
+                if let Some(memory) = s.memory {
+                    let _ = writeln!(f, "      p.memory = {}", memory);
+                }
+                f.push_str("    end\n");
+            }
+        }
+        for line in &s.config {
+            let _ = writeln!(f, "    {}", line);
+        }
+        f.push_str("  end\n");
+    }
+    f.push_str("end\n");
+    f
+}
+
+// Runs `vagrant` with `args` in `dir`, and returns its stdout.
+#[instrument(level = "trace", skip(args))]
+async fn vagrant<I, S>(what: &str, dir: &Path, args: I) -> Result<String, Report>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let out = tokio::process::Command::new("vagrant")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .await
+        .wrap_err("failed to run vagrant")
+        .suggestion("Install Vagrant from https://www.vagrantup.com/")?;
+    eyre::ensure!(
+        out.status.success(),
+        "failed to {}: {}",
+        what,
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SshConfig {
+    host: String,
+    port: u16,
+    user: String,
+    identity_file: Option<String>,
+    check_host_key: bool,
+}
+
+// Parses the output of `vagrant ssh-config` for one machine.
+fn parse_ssh_config(out: &str) -> Option<SshConfig> {
+    let mut opts = HashMap::new();
+    for line in out.lines() {
+        let mut kv = line.trim().splitn(2, char::is_whitespace);
+        if let (Some(k), Some(v)) = (kv.next(), kv.next()) {
+            // the first IdentityFile is the one vagrant uses
+            opts.entry(k.to_ascii_lowercase())
+                .or_insert_with(|| v.trim().trim_matches('"').to_string());
+        }
+    }
+    Some(SshConfig {
+        host: opts.remove("hostname")?,
+        port: opts.get("port").map_or(Some(22), |p| p.parse().ok())?,
+        user: opts.remove("user")?,
+        identity_file: opts.remove("identityfile"),
+        check_host_key: opts.get("stricthostkeychecking").map(String::as_str) != Some("no")
+            && opts.get("userknownhostsfile").map(String::as_str) != Some("/dev/null"),
+    })
+}
+
+// Parses `vagrant status --machine-readable` into each machine's state, e.g. `running`.
+fn parse_status(out: &str) -> HashMap<String, String> {
+    out.lines()
+        .filter_map(|l| {
+            let mut fields = l.splitn(4, ',').skip(1);
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(target), Some("state"), Some(state)) if !target.is_empty() => {
+                    Some((target.to_string(), state.trim().to_string()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+struct Vm {
+    name: String,
+    private_ip: String,
+    setup: Setup,
+    ssh: Option<SshConfig>,
+}
+
+impl Vm {
+    async fn connect<'l>(&self, timeout: Option<Duration>) -> Result<crate::Machine<'l>, Report> {
+        let (m, ssh) = self.descriptor()?;
+        m.connect_ssh(
+            &ssh.user,
+            ssh.identity_file.as_deref().map(Path::new),
+            timeout,
+            ssh.port,
+        )
+        .await
+    }
+
+    // How to reach the machine, and the ssh configuration to reach it with.
+    fn descriptor<'l>(&self) -> Result<(crate::MachineDescriptor<'l>, &SshConfig), Report> {
+        let ssh = self
+            .ssh
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("machine {} has no ssh configuration", self.name))?;
+        let m = crate::MachineDescriptor {
+            nickname: self.name.clone(),
+            public_dns: None,
+            public_ip: ssh.host.clone(),
+            private_ip: Some(self.private_ip.clone()),
+            os: self.setup.os,
+            proxy_command: None,
+            check_host_key: ssh.check_host_key,
+            _tsunami: Default::default(),
+        };
+        Ok((m, ssh))
+    }
+}
+
+/// Launcher that runs machines as local Vagrant virtual machines.
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug, Default)]
+pub struct Launcher {
+    dir: Option<tempfile::TempDir>,
+    subnet: u8,
+    clean_snapshots: bool,
+    machines: Vec<Vm>,
+}
+
+// The name of the snapshot taken before a machine's setup function first runs.
+const CLEAN_SNAPSHOT: &str = "tsunami-clean";
+
+impl Launcher {
+    /// Snapshot every machine after it boots, and before its setup function runs.
+    ///
+    /// [`Tsunami::rerun_setup_clean`](crate::Tsunami::rerun_setup_clean) restores these
+    /// snapshots. Taking them needs a Vagrant provider with snapshot support, such as VirtualBox
+    /// or libvirt, and slows down launches a little. Off by default.
+    pub fn clean_snapshots(&mut self, enabled: bool) -> &mut Self {
+        self.clean_snapshots = enabled;
+        self
+    }
+
+    /// The directory with the generated Vagrantfile, for running `vagrant` commands by hand.
+    pub fn project_dir(&self) -> Option<&Path> {
+        self.dir.as_ref().map(|d| d.path())
+    }
+
+    // Waits until the machine accepts ssh connections, or `max_wait` (if not `None`) elapses,
+    // then runs its setup function.
+    #[instrument(level = "debug", skip(vm, max_wait), fields(name = %vm.name))]
+    async fn set_up(vm: &Vm, max_wait: Option<Duration>) -> Result<(), Report> {
+        let start = Instant::now();
+        let m = loop {
+            match vm.connect(Some(Duration::from_secs(5))).await {
+                Ok(m) => break m,
+                Err(e) => {
+                    tracing::trace!("ssh failed: {}", e);
+                    if let Some(wait_limit) = max_wait {
+                        if start.elapsed() > wait_limit {
+                            return Err(e.wrap_err("wait limit reached"));
+                        }
+                    }
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        };
+
+        if let Some(ref f) = vm.setup.setup_fn {
+            tracing::debug!("setting up machine");
+            super::report_progress(&vm.name, super::MachineState::SettingUp);
+            if let Err(e) = f(&m).await {
+                super::report_progress(&vm.name, super::MachineState::SetupFailed);
+                return Err(e.wrap_err("setup procedure failed"));
+            }
+        }
+        tracing::info!("machine ready");
+        super::report_progress(&vm.name, super::MachineState::Ready);
+        Ok(())
+    }
+}
+
+impl super::Launcher for Launcher {
+    type MachineDescriptor = Setup;
+
+    #[instrument(level = "debug", skip(self))]
+    fn launch<'l>(
+        &'l mut self,
+        l: super::LaunchDescriptor<Self::MachineDescriptor>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(
+            async move {
+                if self.dir.is_none() {
+                    use rand::Rng;
+                    self.dir = Some(
+                        tempfile::Builder::new()
+                            .prefix("tsunami-vagrant")
+                            .tempdir()
+                            .wrap_err("failed to create vagrant project directory")?,
+                    );
+                    // a random /24 of 192.168.56.0/21, so that concurrent launchers do not clash
+                    self.subnet = rand::thread_rng().gen_range(56..64);
+                }
+                let first = self.machines.len();
+                eyre::ensure!(
+                    first + l.machines.len() <= 240,
+                    "a vagrant launcher can run at most 240 machines"
+                );
+                for (name, setup) in l.machines {
+                    super::report_progress(&name, super::MachineState::Booting);
+                    let private_ip =
+                        format!("192.168.{}.{}", self.subnet, 10 + self.machines.len());
+                    self.machines.push(Vm {
+                        name,
+                        private_ip,
+                        setup,
+                        ssh: None,
+                    });
+                }
+
+                let dir = self.dir.as_ref().unwrap().path();
+                std::fs::write(
+                    dir.join("Vagrantfile"),
+                    vagrantfile(&self.machines, l.max_wait),
+                )
+                .wrap_err("failed to write Vagrantfile")?;
+
+                // `vagrant up --provider` applies to all the machines it brings up.
+                let mut by_provider: BTreeMap<_, Vec<_>> = BTreeMap::new();
+                for vm in &self.machines[first..] {
+                    by_provider
+                        .entry(vm.setup.provider.clone())
+                        .or_default()
+                        .push(vm.name.clone());
+                }
+                for (provider, names) in by_provider {
+                    let mut args = vec![String::from("up")];
+                    args.extend(provider.map(|p| format!("--provider={}", p)));
+                    args.extend(names);
+                    tracing::info!("bringing up machines");
+                    vagrant("bring up machines", dir, &args).await?;
+                }
+
+                for vm in &mut self.machines[first..] {
+                    let out =
+                        vagrant("get ssh configuration", dir, ["ssh-config", &vm.name]).await?;
+                    vm.ssh = Some(parse_ssh_config(&out).ok_or_else(|| {
+                        eyre::eyre!("unexpected ssh configuration for {}:\n{}", vm.name, out)
+                    })?);
+                    if self.clean_snapshots {
+                        vagrant(
+                            "take clean snapshot",
+                            dir,
+                            ["snapshot", "save", "--force", &vm.name, CLEAN_SNAPSHOT],
+                        )
+                        .await?;
+                    }
+                }
+
+                let max_wait = l.max_wait;
+                futures_util::future::join_all(self.machines[first..].iter().map(|vm| {
+                    let machine_span = tracing::debug_span!("machine", name = %vm.name);
+                    Self::set_up(vm, max_wait).instrument(machine_span)
+                }))
+                .await
+                .into_iter()
+                .collect()
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn connect_all<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, crate::Machine<'l>>, Report>> + Send + 'l>,
+    > {
+        Box::pin(
+            async move {
+                futures_util::future::join_all(self.machines.iter().map(|vm| {
+                    let machine_span = tracing::trace_span!("machine", name = %vm.name);
+                    async move { Ok::<_, Report>((vm.name.clone(), vm.connect(None).await?)) }
+                        .instrument(machine_span)
+                }))
+                .await
+                .into_iter()
+                .collect()
+            }
+            .in_current_span(),
+        )
+    }
+
+    fn describe_all<'l>(&'l self) -> Result<HashMap<String, crate::LazyMachine<'l>>, Report> {
+        self.machines
+            .iter()
+            .map(|vm| {
+                let (m, ssh) = vm.descriptor()?;
+                let key = ssh.identity_file.as_deref().map(Path::new);
+                Ok((vm.name.clone(), m.lazy(&ssh.user, key, ssh.port)))
+            })
+            .collect()
+    }
+
+    fn setup_fns(&self) -> Result<HashMap<String, super::SetupFn>, Report> {
+        Ok(self
+            .machines
+            .iter()
+            .filter_map(|vm| Some((vm.name.clone(), vm.setup.setup_fn.clone()?)))
+            .collect())
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn restore_clean<'l>(
+        &'l self,
+        nicknames: Vec<String>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'l>> {
+        Box::pin(
+            async move {
+                if !self.clean_snapshots {
+                    return Err(eyre::eyre!("no clean snapshots were taken"))
+                        .suggestion("Enable them with Launcher::clean_snapshots before spawning");
+                }
+                let dir = self.dir.as_ref().map(|d| d.path());
+                for name in &nicknames {
+                    let dir = match dir {
+                        Some(dir) if self.machines.iter().any(|vm| &vm.name == name) => dir,
+                        _ => eyre::bail!("there is no machine named {}", name),
+                    };
+                    tracing::debug!(%name, "restoring clean snapshot");
+                    vagrant(
+                        "restore clean snapshot",
+                        dir,
+                        [
+                            "snapshot",
+                            "restore",
+                            "--no-provision",
+                            name,
+                            CLEAN_SNAPSHOT,
+                        ],
+                    )
+                    .await?;
+                }
+                Ok(())
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn status<'l>(
+        &'l self,
+    ) -> Pin<
+        Box<dyn Future<Output = Result<HashMap<String, super::MachineState>, Report>> + Send + 'l>,
+    > {
+        Box::pin(
+            async move {
+                let dir = match self.dir {
+                    Some(ref dir) if !self.machines.is_empty() => dir.path(),
+                    _ => return Ok(HashMap::new()),
+                };
+                let states = parse_status(
+                    &vagrant("get status", dir, ["status", "--machine-readable"]).await?,
+                );
+                let states = &states;
+                Ok(
+                    futures_util::future::join_all(self.machines.iter().map(|vm| async move {
+                        let state = match (states.get(&vm.name).map(String::as_str), &vm.ssh) {
+                            (Some("running"), Some(ssh)) => {
+                                super::ssh_state(
+                                    &ssh.host,
+                                    &ssh.user,
+                                    ssh.identity_file.as_deref().map(Path::new),
+                                    ssh.port,
+                                    ssh.check_host_key,
+                                )
+                                .await
+                            }
+                            (Some("running"), None) => super::MachineState::Booting,
+                            (Some("saved"), _) | (Some("paused"), _) => {
+                                super::MachineState::Unreachable
+                            }
+                            // not created, powered off, or aborted
+                            _ => super::MachineState::Terminated,
+                        };
+                        (vm.name.clone(), state)
+                    }))
+                    .await
+                    .into_iter()
+                    .collect(),
+                )
+            }
+            .in_current_span(),
+        )
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn terminate_all(self) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send>> {
+        Box::pin(
+            async move {
+                if let Some(ref dir) = self.dir {
+                    if !self.machines.is_empty() {
+                        vagrant("destroy machines", dir.path(), ["destroy", "-f"]).await?;
+                    }
+                }
+                Ok(())
+            }
+            .in_current_span(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::Launcher as _;
+
+    #[test]
+    fn vagrantfiles() {
+        assert_eq!(ruby_str(r#"a"b#{x}\"#), r#""a\"b\#{x}\\""#);
+        assert_eq!(hostname("Worker_0"), "worker-0");
+        assert_eq!(hostname("--"), "tsunami");
+
+        let vm = |name: &str, setup| Vm {
+            name: name.to_string(),
+            private_ip: String::from("192.168.57.10"),
+            setup,
+            ssh: None,
+        };
+        let f = vagrantfile(
+            &[
+                vm("server", Setup::default().cpus(2).memory(1024)),
+                vm(
+                    "client",
+                    Setup::default()
+                        .vagrant_box("generic/debian12")
+                        .config("m.vm.provider \"vmware_desktop\""),
+                ),
+            ],
+            Some(Duration::from_secs(300)),
+        );
+        assert!(f.starts_with("Vagrant.configure(\"2\") do |config|\n"));
+        assert!(f.contains(
+            "  config.vm.define \"server\" do |m|\n    m.vm.box = \"bento/ubuntu-22.04\"\n"
+        ));
+        assert!(f.contains("    m.vm.network \"private_network\", ip: \"192.168.57.10\"\n"));
+        assert!(f.contains("    m.vm.boot_timeout = 300\n"));
+        assert_eq!(f.matches("p.cpus = 2").count(), 2);
+        assert!(f.contains("    m.vm.box = \"generic/debian12\"\n"));
+        assert!(f.contains("    m.vm.provider \"vmware_desktop\"\n  end\n"));
+        assert!(f.ends_with("end\n"));
+    }
+
+    #[test]
+    fn vagrant_output() {
+        let config = r#"Host server
+  HostName 127.0.0.1
+  User vagrant
+  Port 2222
+  UserKnownHostsFile /dev/null
+  StrictHostKeyChecking no
+  IdentityFile "/tmp/tsunami vagrant/.vagrant/machines/server/virtualbox/private_key"
+  IdentityFile /home/me/.vagrant.d/insecure_private_key
+  IdentitiesOnly yes
+"#;
+        assert_eq!(
+            parse_ssh_config(config),
+            Some(SshConfig {
+                host: String::from("127.0.0.1"),
+                port: 2222,
+                user: String::from("vagrant"),
+                identity_file: Some(String::from(
+                    "/tmp/tsunami vagrant/.vagrant/machines/server/virtualbox/private_key"
+                )),
+                check_host_key: false,
+            })
+        );
+        assert!(
+            parse_ssh_config("Host server\n  HostName 127.0.0.1\n  User vagrant\n")
+                .unwrap()
+                .check_host_key
+        );
+        assert_eq!(parse_ssh_config("Host server\n"), None);
+
+        let status = "1700000000,server,metadata,provider,virtualbox
+1700000000,server,provider-name,virtualbox
+1700000000,server,state,running
+1700000000,client,state,poweroff
+1700000000,,ui,info,Current machine states:
+";
+        let states = parse_status(status);
+        assert_eq!(states.len(), 2);
+        assert_eq!(states["server"], "running");
+        assert_eq!(states["client"], "poweroff");
+        assert_eq!("vagrant:local".parse::<Local>().unwrap(), Local);
+    }
+
+    #[test]
+    #[ignore]
+    fn vagrant_machines() -> Result<(), Report> {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let mut l = super::Launcher::default();
+            l.spawn(crate::make_multiple(2, "v", Setup::default()), None)
+                .await?;
+            let vms = l.connect_all().await?;
+            let ip = vms["v-1"].private_ip.clone().unwrap();
+            assert!(vms["v-0"]
+                .ssh
+                .command("ping")
+                .arg("-c1")
+                .arg(&ip)
+                .status()
+                .await?
+                .success());
+            l.terminate_all().await
+        })
+    }
+}
@@ -0,0 +1,363 @@
+//! Spread a list of tasks over machines.
+//!
+//! A [`Queue`] turns the machines of a tsunami into a small batch cluster for embarrassingly
+//! parallel sweeps. It holds a list of shell commands, and each machine takes the next command
+//! whenever it has a free slot. Results are streamed back as the tasks finish. A machine that fails
+//! (say, its SSH connection breaks) gets no more tasks, and the task it was running goes back into
+//! the queue for another machine to pick up.
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::queue::Queue;
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! let queue = Queue::new((1..=64).map(|n| format!("./bench --threads {}", n))).slots(2);
+//! for (threads, out) in (1..=64).zip(queue.run_all(&vms).await?) {
+//!     println!("{} threads: {}", threads, out.stdout.trim());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Queue::run`] instead streams each task back as soon as it finishes, along with the machine
+//! that ran it.
+
+use crate::run::{CommandOutput, TimedOut};
+use color_eyre::{eyre::eyre, Report};
+use futures_util::stream::{FuturesUnordered, Stream, StreamExt};
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// A task that finished on some machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Finished {
+    /// The index of the task in the queue.
+    pub task: usize,
+    /// The nickname of the machine that ran the task.
+    pub machine: String,
+    /// How many times the task was started, including the time it finished.
+    pub attempts: usize,
+    /// What the task's command produced.
+    ///
+    /// A non-zero exit status does not make the task fail, so check
+    /// [`CommandOutput::success`].
+    pub output: CommandOutput,
+}
+
+/// A list of shell commands to run across machines.
+///
+/// See the [module documentation](self) for an example.
+#[derive(Debug, Clone)]
+pub struct Queue {
+    tasks: Vec<String>,
+    slots: usize,
+    attempts: usize,
+    timeout: Option<Duration>,
+}
+
+impl Queue {
+    /// A queue of `tasks`, each of which is run with `sh -c` on one of the machines.
+    ///
+    /// By default, each machine runs one task at a time, a task is started at most three times,
+    /// and tasks can run for as long as they like.
+    pub fn new<S: ToString>(tasks: impl IntoIterator<Item = S>) -> Self {
+        Queue {
+            tasks: tasks.into_iter().map(|t| t.to_string()).collect(),
+            slots: 1,
+            attempts: 3,
+            timeout: None,
+        }
+    }
+
+    /// Run up to `slots` tasks at once on each machine.
+    pub fn slots(mut self, slots: usize) -> Self {
+        self.slots = slots.max(1);
+        self
+    }
+
+    /// Give up on a task once it has been started `attempts` times.
+    ///
+    /// A task is started again when the machine running it fails, or when it runs out of time
+    /// (see [`Queue::timeout`]).
+    pub fn attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Kill tasks that have not finished after `timeout`, like
+    /// [`Machine::exec_timeout`](crate::Machine::exec_timeout) does.
+    ///
+    /// The task is put back into the queue, but the machine keeps getting tasks.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The command of task `i`.
+    pub fn task(&self, i: usize) -> &str {
+        &self.tasks[i]
+    }
+
+    /// Run the tasks on `machines`, and stream back each task as it finishes.
+    ///
+    /// `machines` is the map that [`connect_all`](crate::Tsunami::connect_all) or
+    /// [`connect_all_sorted`](crate::Tsunami::connect_all_sorted) returns, or any other nickname
+    /// and machine pairs. The machines take turns in the order of their nicknames, so the first
+    /// tasks go to the same machines in every run. Tasks are handed out in order, but finish in whatever order they finish in. A task that
+    /// cannot be finished, because it was started too many times or because every machine has
+    /// failed, yields an error, and the stream carries on with the other tasks.
+    pub fn run<'a, 't: 'a>(
+        &'a self,
+        machines: impl IntoIterator<Item = (&'a String, &'a crate::Machine<'t>)>,
+    ) -> impl Stream<Item = Result<Finished, Report>> + 'a {
+        let timeout = self.timeout;
+        drive(self, machines, move |vm, cmd| async move {
+            match timeout {
+                Some(timeout) => vm.exec_timeout(cmd, timeout).await,
+                None => Ok(CommandOutput::from(vm.ssh.shell(cmd).output().await?)),
+            }
+        })
+    }
+
+    /// Run the tasks on `machines` like [`Queue::run`], and return their outputs in the order of
+    /// the tasks.
+    ///
+    /// If any task cannot be finished, the others still run, and the error is that of the first
+    /// task that failed.
+    pub async fn run_all<'a, 't: 'a>(
+        &'a self,
+        machines: impl IntoIterator<Item = (&'a String, &'a crate::Machine<'t>)>,
+    ) -> Result<Vec<CommandOutput>, Report> {
+        let mut outputs = vec![None; self.tasks.len()];
+        let mut errors = Vec::new();
+        let mut results = Box::pin(self.run(machines));
+        while let Some(r) = results.next().await {
+            match r {
+                Ok(done) => outputs[done.task] = Some(done.output),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        let failed = errors.len();
+        match errors.into_iter().next() {
+            Some(e) => Err(e.wrap_err(format!("{} of {} tasks failed", failed, self.tasks.len()))),
+            None => Ok(outputs.into_iter().flatten().collect()),
+        }
+    }
+}
+
+type Attempt<'a> =
+    Pin<Box<dyn Future<Output = (usize, usize, usize, Result<CommandOutput, Report>)> + 'a>>;
+
+// The scheduler behind `Queue::run`, which runs tasks with `exec`.
+struct Drive<'a, M, F> {
+    queue: &'a Queue,
+    machines: Vec<(&'a String, &'a M)>,
+    exec: F,
+    // one entry, a machine index, for each free slot.
+    idle: VecDeque<usize>,
+    // tasks that still have to run, with how many times they were started.
+    pending: VecDeque<(usize, usize)>,
+    // (machine, task, attempts, result) of each running task.
+    running: FuturesUnordered<Attempt<'a>>,
+    failed: HashSet<usize>,
+}
+
+impl<'a, M, F, Fut> Drive<'a, M, F>
+where
+    F: Fn(&'a M, &'a str) -> Fut,
+    Fut: Future<Output = Result<CommandOutput, Report>> + 'a,
+{
+    async fn next(&mut self) -> Option<Result<Finished, Report>> {
+        loop {
+            while !self.pending.is_empty() {
+                let m = match self.idle.pop_front() {
+                    Some(m) => m,
+                    None => break,
+                };
+                if self.failed.contains(&m) {
+                    continue;
+                }
+                let (task, attempts) = self.pending.pop_front().expect("pending is not empty");
+                tracing::trace!(machine = %self.machines[m].0, task, "starting task");
+                let run = (self.exec)(self.machines[m].1, &self.queue.tasks[task]);
+                self.running
+                    .push(Box::pin(async move { (m, task, attempts + 1, run.await) }));
+            }
+
+            let (m, task, attempts, res) = match self.running.next().await {
+                Some(r) => r,
+                None => {
+                    // the tasks that are left have no machines to run on
+                    let (task, _) = self.pending.pop_front()?;
+                    return Some(Err(eyre!(
+                        "no machines left to run task {} ({})",
+                        task,
+                        self.queue.tasks[task]
+                    )));
+                }
+            };
+            let machine = self.machines[m].0;
+            match res {
+                Ok(output) => {
+                    self.idle.push_back(m);
+                    return Some(Ok(Finished {
+                        task,
+                        machine: machine.clone(),
+                        attempts,
+                        output,
+                    }));
+                }
+                Err(e) => {
+                    if e.downcast_ref::<TimedOut>().is_some() {
+                        self.idle.push_back(m);
+                    } else if self.failed.insert(m) {
+                        tracing::warn!(%machine, err = ?e, "machine failed, giving it no more tasks");
+                    }
+
+                    if attempts < self.queue.attempts {
+                        tracing::debug!(%machine, task, attempts, "re-queuing task");
+                        self.pending.push_back((task, attempts));
+                    } else {
+                        return Some(Err(e.wrap_err(format!(
+                            "task {} ({}) failed on {} after {} attempts",
+                            task, self.queue.tasks[task], machine, attempts
+                        ))));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn drive<'a, M, F, Fut>(
+    queue: &'a Queue,
+    machines: impl IntoIterator<Item = (&'a String, &'a M)>,
+    exec: F,
+) -> impl Stream<Item = Result<Finished, Report>> + 'a
+where
+    M: 'a,
+    F: Fn(&'a M, &'a str) -> Fut + 'a,
+    Fut: Future<Output = Result<CommandOutput, Report>> + 'a,
+{
+    let machines = crate::by_nickname(machines);
+    // take turns, so that the tasks are spread over all machines before any gets a second one.
+    let idle = (0..queue.slots).flat_map(|_| 0..machines.len()).collect();
+    let state = Drive {
+        queue,
+        machines,
+        exec,
+        idle,
+        pending: (0..queue.tasks.len()).map(|t| (t, 0)).collect(),
+        running: FuturesUnordered::new(),
+        failed: HashSet::new(),
+    };
+    futures_util::stream::unfold(state, |mut state| async move {
+        let next = state.next().await?;
+        Some((next, state))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use color_eyre::eyre;
+    use std::collections::{BTreeMap, HashMap};
+
+    fn output(stdout: &str) -> CommandOutput {
+        CommandOutput {
+            status: Some(0),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reassigns_tasks_of_failed_machines() {
+        let ms: HashMap<String, bool> = vec![
+            (String::from("a"), true),
+            (String::from("b"), false),
+            (String::from("c"), true),
+        ]
+        .into_iter()
+        .collect();
+        let queue = Queue::new((0..10).map(|i| i.to_string())).slots(2);
+        let results: Vec<_> = drive(&queue, &ms, |&healthy, cmd| async move {
+            tokio::task::yield_now().await;
+            eyre::ensure!(healthy, "connection lost");
+            Ok(output(cmd))
+        })
+        .collect()
+        .await;
+
+        assert_eq!(results.len(), 10);
+        let mut done: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+        done.sort_by_key(|f| f.task);
+        for (i, f) in done.iter().enumerate() {
+            assert_eq!(f.task, i);
+            assert_eq!(f.output.stdout, i.to_string());
+            assert_ne!(f.machine, "b");
+        }
+        // b took one task in each of its slots before it failed
+        assert_eq!(done.iter().filter(|f| f.attempts == 2).count(), 2);
+    }
+
+    #[tokio::test]
+    async fn takes_turns_by_nickname() {
+        let names = ["vm-2", "vm-10", "vm-1"];
+        let queue = Queue::new(vec!["x", "y", "z"]);
+        let machines = |finished: Vec<Result<Finished, Report>>| -> Vec<(usize, String)> {
+            let mut done: Vec<_> = finished
+                .into_iter()
+                .map(|f| f.map(|f| (f.task, f.machine)).unwrap())
+                .collect();
+            done.sort();
+            done
+        };
+
+        let ms: HashMap<String, ()> = names.iter().map(|n| (n.to_string(), ())).collect();
+        let unsorted: Vec<_> = drive(&queue, &ms, |_, cmd| async move { Ok(output(cmd)) })
+            .collect()
+            .await;
+        let ms: BTreeMap<String, ()> = names.iter().map(|n| (n.to_string(), ())).collect();
+        let sorted: Vec<_> = drive(&queue, &ms, |_, cmd| async move { Ok(output(cmd)) })
+            .collect()
+            .await;
+
+        let expected = vec![
+            (0, String::from("vm-1")),
+            (1, String::from("vm-10")),
+            (2, String::from("vm-2")),
+        ];
+        assert_eq!(machines(unsorted), expected);
+        assert_eq!(machines(sorted), expected);
+        assert_eq!(
+            crate::by_nickname(&ms)
+                .into_iter()
+                .map(|(n, _)| n.as_str())
+                .collect::<Vec<_>>(),
+            vec!["vm-1", "vm-10", "vm-2"]
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up() {
+        let ms: HashMap<String, usize> = vec![(String::from("a"), 0), (String::from("b"), 1)]
+            .into_iter()
+            .collect();
+        let queue = Queue::new(vec!["x", "y", "z"]).attempts(1);
+        let results: Vec<_> = drive(&queue, &ms, |_, _| async move {
+            Err::<CommandOutput, _>(eyre::eyre!("connection lost"))
+        })
+        .collect()
+        .await;
+
+        // a and b each fail one task, and then nothing is left to run z
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(Result::is_err));
+        assert!(format!("{}", results[2].as_ref().unwrap_err()).contains("no machines left"));
+    }
+}
@@ -0,0 +1,537 @@
+//! Run commands on the machines that match a target.
+//!
+//! A [`Target`] picks machines by nickname: either a glob such as `worker-*`, or a role such as
+//! `role=client`, which matches the machines [`make_multiple`](crate::make_multiple) names with
+//! that prefix (`client-0`, `client-1`, ...) as well as a machine named just `client`. Targets
+//! parse from strings, so they can come straight from command-line arguments.
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! let outs = tsunami::run::run_on(&vms, &"role=client".parse()?, "uptime").await?;
+//! for (name, out) in &outs {
+//!     println!("{}: {}", name, out.stdout.trim());
+//! }
+//! assert!(outs.values().all(|o| o.success()));
+//! # Ok(())
+//! # }
+//! ```
+
+use color_eyre::{eyre, Report};
+use std::collections::HashMap;
+
+/// A set of machines, selected by nickname.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Machines whose nickname matches a glob, where `*` matches any sequence of characters and
+    /// `?` matches any one character.
+    Nickname(String),
+    /// Machines named `<role>` or `<role>-<n>`.
+    Role(String),
+}
+
+impl std::str::FromStr for Target {
+    type Err = Report;
+
+    /// Parses `role=<role>` as a role, and anything else as a nickname glob.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let t = match s.strip_prefix("role=") {
+            Some(role) => Target::Role(role.to_string()),
+            None => Target::Nickname(s.to_string()),
+        };
+        match t {
+            Target::Role(ref r) | Target::Nickname(ref r) if r.is_empty() => {
+                eyre::bail!("empty target {:?}", s)
+            }
+            t => Ok(t),
+        }
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Nickname(g) => write!(f, "{}", g),
+            Target::Role(r) => write!(f, "role={}", r),
+        }
+    }
+}
+
+fn glob(pattern: &[u8], s: &[u8]) -> bool {
+    match (pattern.split_first(), s.split_first()) {
+        (None, _) => s.is_empty(),
+        (Some((b'*', p)), _) => glob(p, s) || (!s.is_empty() && glob(pattern, &s[1..])),
+        (Some((b'?', p)), Some((_, s))) => glob(p, s),
+        (Some((c, p)), Some((d, s))) if c == d => glob(p, s),
+        _ => false,
+    }
+}
+
+impl Target {
+    /// Whether the machine named `nickname` is part of this target.
+    pub fn matches(&self, nickname: &str) -> bool {
+        match self {
+            Target::Nickname(g) => glob(g.as_bytes(), nickname.as_bytes()),
+            Target::Role(r) => match nickname.strip_prefix(r.as_str()) {
+                Some("") => true,
+                Some(rest) => rest
+                    .strip_prefix('-')
+                    .map(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+                    .unwrap_or(false),
+                None => false,
+            },
+        }
+    }
+}
+
+/// The result of running a command on one machine.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct CommandOutput {
+    /// The exit code, or `None` if the command was killed by a signal.
+    pub status: Option<i32>,
+    /// Standard output, lossily decoded as UTF-8.
+    pub stdout: String,
+    /// Standard error, lossily decoded as UTF-8.
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    /// Whether the command exited with status 0.
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+impl From<std::process::Output> for CommandOutput {
+    fn from(o: std::process::Output) -> Self {
+        CommandOutput {
+            status: o.status.code(),
+            stdout: String::from_utf8_lossy(&o.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&o.stderr).into_owned(),
+        }
+    }
+}
+
+/// Machines that produced identical output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct OutputGroup {
+    /// The output all machines in the group produced.
+    pub output: CommandOutput,
+    /// The machines in the group, sorted by nickname.
+    pub machines: Vec<String>,
+}
+
+/// The outputs of one command across many machines, grouped by output.
+///
+/// This makes the odd machine out easy to find:
+///
+/// ```rust,no_run
+/// # async fn f(vms: std::collections::HashMap<String, tsunami::Machine<'_>>) -> Result<(), color_eyre::Report> {
+/// use tsunami::run::{run_on, Summary};
+/// let outs = run_on(&vms, &"*".parse()?, "uname -r").await?;
+/// let s = Summary::new(&outs);
+/// // e.g. "48 machines: exited with 0: 5.15.0-1019-aws"
+/// //      "2 machines (worker-3, worker-17): exited with 0: 5.4.0-1045-aws"
+/// println!("{}", s);
+/// for g in s.outliers() {
+///     eprintln!("check {:?}", g.machines);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    groups: Vec<OutputGroup>,
+}
+
+impl Summary {
+    /// Group `outputs`, as returned by [`run_on`], by output.
+    pub fn new(outputs: &HashMap<String, CommandOutput>) -> Self {
+        let mut by_output: HashMap<&CommandOutput, Vec<String>> = HashMap::new();
+        for (name, out) in outputs {
+            by_output.entry(out).or_default().push(name.clone());
+        }
+        let mut groups: Vec<_> = by_output
+            .into_iter()
+            .map(|(output, mut machines)| {
+                machines.sort();
+                OutputGroup {
+                    output: output.clone(),
+                    machines,
+                }
+            })
+            .collect();
+        // largest group first, and ties in nickname order so the result is deterministic.
+        groups.sort_by(|a, b| {
+            b.machines
+                .len()
+                .cmp(&a.machines.len())
+                .then_with(|| a.machines.cmp(&b.machines))
+        });
+        Summary { groups }
+    }
+
+    /// All groups, largest first.
+    pub fn groups(&self) -> &[OutputGroup] {
+        &self.groups
+    }
+
+    /// Whether every machine produced the same output.
+    pub fn is_uniform(&self) -> bool {
+        self.groups.len() <= 1
+    }
+
+    /// Every group except the largest, i.e., the machines that disagree with the majority.
+    pub fn outliers(&self) -> &[OutputGroup] {
+        self.groups.get(1..).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, g) in self.groups.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            let n = g.machines.len();
+            write!(f, "{} machine{}", n, if n == 1 { "" } else { "s" })?;
+            // naming every machine in the majority is just noise.
+            if i != 0 {
+                write!(f, " ({})", g.machines.join(", "))?;
+            }
+            match g.output.status {
+                Some(c) => write!(f, ": exited with {}", c)?,
+                None => write!(f, ": killed by a signal")?,
+            }
+            let out = g.output.stdout.trim();
+            let err = g.output.stderr.trim();
+            if !out.is_empty() {
+                write!(f, ": {}", out)?;
+            }
+            if !err.is_empty() {
+                write!(f, " [stderr: {}]", err)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The error returned by [`Machine::exec_timeout`](crate::Machine::exec_timeout) when the
+/// command does not finish in time.
+///
+/// By the time this is returned, the command and everything it started have been killed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct TimedOut {
+    /// The machine the command ran on.
+    pub nickname: String,
+    /// The command.
+    pub cmd: String,
+    /// How long the command was given.
+    pub timeout: std::time::Duration,
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} on {} did not finish within {:?}",
+            self.cmd, self.nickname, self.timeout
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// A command running in the background in its own process group on a machine.
+///
+/// Returned by [`Machine::spawn_group`](crate::Machine::spawn_group), and killed, along with
+/// every process it started, by [`Machine::kill`](crate::Machine::kill).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessGroup(u32);
+
+impl ProcessGroup {
+    /// The process group id on the remote machine.
+    pub fn id(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Which processes [`Machine::kill`](crate::Machine::kill) should kill.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kill {
+    /// Every process whose command line matches the pattern, as with `pkill -f`.
+    Pattern(String),
+    /// A process group started with [`Machine::spawn_group`](crate::Machine::spawn_group).
+    Group(ProcessGroup),
+}
+
+impl From<&str> for Kill {
+    fn from(pattern: &str) -> Self {
+        Kill::Pattern(pattern.to_string())
+    }
+}
+
+impl From<ProcessGroup> for Kill {
+    fn from(g: ProcessGroup) -> Self {
+        Kill::Group(g)
+    }
+}
+
+// Quotes `s` as a single word for sh.
+pub(crate) fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// The address the other machines reach `vm` at.
+pub(crate) fn address<'a>(vm: &'a crate::Machine<'_>) -> &'a str {
+    vm.private_ip.as_deref().unwrap_or(&vm.public_ip)
+}
+
+// Given `sig`, a shell function that sends signal $1 to the target processes and fails if there
+// are none, sends SIGTERM, and SIGKILL to whatever is still alive five seconds later.
+fn kill_script(sig: &str) -> String {
+    format!(
+        r#"sig() {{ {}; }}
+sig TERM || exit 0
+i=0
+while [ $i -lt 50 ]; do sleep 0.1; sig 0 || exit 0; i=$((i + 1)); done
+sig KILL
+exit 0
+"#,
+        sig
+    )
+}
+
+fn kill_group(pgid: &str) -> String {
+    kill_script(&format!("kill -$1 -{} 2>/dev/null", pgid))
+}
+
+impl Kill {
+    fn script(&self) -> String {
+        match self {
+            Kill::Group(g) => kill_group(&g.0.to_string()),
+            // the pattern is spelled out in octal so that it does not appear in this shell's own
+            // command line, which pgrep would otherwise match too.
+            Kill::Pattern(p) => {
+                let octal: String = p.bytes().map(|b| format!("\\{:03o}", b)).collect();
+                kill_script(&format!(
+                    "p=$(pgrep -f -- \"$(printf '{}')\"); [ -n \"$p\" ] && kill -$1 $p 2>/dev/null",
+                    octal
+                ))
+            }
+        }
+    }
+}
+
+impl crate::Machine<'_> {
+    /// Run `cmd` with `sh -c`, and kill it if it has not finished after `timeout`.
+    ///
+    /// The command runs in a process group of its own, and on timeout that whole group is killed,
+    /// so nothing the command started is left running. The error is then a [`TimedOut`]. Like
+    /// with [`run_on`], a non-zero exit status is not an error.
+    ///
+    /// ```rust,no_run
+    /// # async fn f(vm: &tsunami::Machine<'_>) -> Result<(), color_eyre::Report> {
+    /// use std::time::Duration;
+    /// let out = vm.exec_timeout("./bench --iters 1000", Duration::from_secs(600)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn exec_timeout(
+        &self,
+        cmd: &str,
+        timeout: std::time::Duration,
+    ) -> Result<CommandOutput, Report> {
+        let pidfile = format!("/tmp/tsunami-{:016x}.pgid", rand::random::<u64>());
+        let inner = format!("echo $$ > {}; exec sh -c {}", pidfile, quote(cmd));
+        let script = format!(
+            "setsid -w sh -c {}; s=$?; rm -f {}; exit $s",
+            quote(&inner),
+            pidfile
+        );
+
+        match tokio::time::timeout(timeout, self.ssh.shell(script).output()).await {
+            Ok(out) => Ok(CommandOutput::from(out?)),
+            Err(_) => {
+                tracing::warn!(nickname = %self.nickname, cmd, ?timeout, "command timed out");
+                let kill = format!(
+                    "g=$(cat {f} 2>/dev/null) || exit 0\nrm -f {f}\n{}",
+                    kill_group("$g"),
+                    f = pidfile,
+                );
+                let out = self.ssh.shell(kill).output().await?;
+                eyre::ensure!(
+                    out.status.success(),
+                    "failed to kill timed out command: {}",
+                    String::from_utf8_lossy(&out.stderr)
+                );
+                Err(Report::new(TimedOut {
+                    nickname: self.nickname.clone(),
+                    cmd: cmd.to_string(),
+                    timeout,
+                }))
+            }
+        }
+    }
+
+    /// Start `cmd` with `sh -c` in the background, in a process group of its own.
+    ///
+    /// The command's output is discarded, so redirect it to a file if it is needed. Stop it with
+    /// [`Machine::kill`].
+    pub async fn spawn_group(&self, cmd: &str) -> Result<ProcessGroup, Report> {
+        let inner = format!(
+            "echo $$; exec sh -c {} </dev/null >/dev/null 2>&1",
+            quote(cmd)
+        );
+        let out = self
+            .ssh
+            .shell(format!("setsid sh -c {} &", quote(&inner)))
+            .output()
+            .await?;
+        let pgid = String::from_utf8_lossy(&out.stdout);
+        let pgid = pgid.trim().parse().map_err(|_| {
+            eyre::eyre!(
+                "failed to start {:?}: {}",
+                cmd,
+                String::from_utf8_lossy(&out.stderr)
+            )
+        })?;
+        Ok(ProcessGroup(pgid))
+    }
+
+    /// Kill processes on this machine.
+    ///
+    /// `target` is either a pattern to match against command lines, or a [`ProcessGroup`]. The
+    /// processes get SIGTERM, and those still alive five seconds later get SIGKILL. It is not an
+    /// error if nothing matches.
+    ///
+    /// ```rust,no_run
+    /// # async fn f(vm: &tsunami::Machine<'_>) -> Result<(), color_eyre::Report> {
+    /// let server = vm.spawn_group("./server --port 8080").await?;
+    /// // ...
+    /// vm.kill(server).await?;
+    /// vm.kill("memcached").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn kill(&self, target: impl Into<Kill>) -> Result<(), Report> {
+        let target = target.into();
+        let out = self.ssh.shell(target.script()).output().await?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to kill {:?}: {}",
+            target,
+            String::from_utf8_lossy(&out.stderr)
+        );
+        Ok(())
+    }
+}
+
+// Shared by `run_on` and `Handle::run_on`, which keep their sessions in different places.
+pub(crate) async fn run_sessions<'s>(
+    sessions: impl IntoIterator<Item = (&'s String, &'s openssh::Session)>,
+    target: &Target,
+    cmd: &str,
+) -> Result<HashMap<String, CommandOutput>, Report> {
+    let targeted: Vec<_> = sessions
+        .into_iter()
+        .filter(|(name, _)| target.matches(name))
+        .collect();
+    eyre::ensure!(!targeted.is_empty(), "no machines match {}", target);
+    tracing::debug!(%target, machines = targeted.len(), cmd, "running command");
+
+    let limit = targeted.len();
+    crate::each::for_each(targeted, limit, |ssh| async move {
+        Ok(CommandOutput::from(ssh.shell(cmd).output().await?))
+    })
+    .await
+}
+
+/// Run `cmd` with `sh -c` on every machine in `machines` that matches `target`, and collect the
+/// outputs by nickname.
+///
+/// A command that exits with a non-zero status is not an error; check
+/// [`CommandOutput::success`]. It is an error if no machine matches `target`, and if any machine
+/// cannot run the command at all, in which case the error is an
+/// [`each::MachineErrors`](crate::each::MachineErrors).
+pub async fn run_on(
+    machines: &HashMap<String, crate::Machine<'_>>,
+    target: &Target,
+    cmd: &str,
+) -> Result<HashMap<String, CommandOutput>, Report> {
+    run_sessions(machines.iter().map(|(n, m)| (n, &m.ssh)), target, cmd).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        let t: Target = "worker-*".parse().unwrap();
+        assert!(t.matches("worker-0"));
+        assert!(t.matches("worker-"));
+        assert!(!t.matches("client-0"));
+
+        let t: Target = "w?-*-a".parse().unwrap();
+        assert!(t.matches("w1-east-a"));
+        assert!(!t.matches("w12-east-a"));
+
+        let t: Target = "role=client".parse().unwrap();
+        assert_eq!(t, Target::Role(String::from("client")));
+        assert_eq!(t.to_string(), "role=client");
+        assert!(t.matches("client"));
+        assert!(t.matches("client-12"));
+        assert!(!t.matches("client-"));
+        assert!(!t.matches("clients-1"));
+        assert!(!t.matches("client-a"));
+
+        assert!("role=".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn kill_scripts() {
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        let s = Kill::from(ProcessGroup(42)).script();
+        assert!(s.starts_with("sig() { kill -$1 -42 2>/dev/null; }\nsig TERM"));
+        assert!(s.contains("sig KILL"));
+        let s = Kill::from("ab c").script();
+        assert!(s.contains(r#"pgrep -f -- "$(printf '\141\142\040\143')""#));
+        assert!(!s.contains("ab c"));
+    }
+
+    #[test]
+    fn summary() {
+        let out = |status, stdout: &str| CommandOutput {
+            status: Some(status),
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        };
+        let outs: HashMap<_, _> = vec![
+            (String::from("w-0"), out(0, "5.15\n")),
+            (String::from("w-1"), out(0, "5.4\n")),
+            (String::from("w-2"), out(0, "5.15\n")),
+            (String::from("w-3"), out(1, "")),
+        ]
+        .into_iter()
+        .collect();
+
+        let s = Summary::new(&outs);
+        assert!(!s.is_uniform());
+        assert_eq!(s.groups()[0].machines, vec!["w-0", "w-2"]);
+        let outliers: Vec<_> = s.outliers().iter().map(|g| g.machines.clone()).collect();
+        assert_eq!(outliers, vec![vec!["w-1"], vec!["w-3"]]);
+        assert_eq!(
+            s.to_string(),
+            "2 machines: exited with 0: 5.15\n\
+             1 machine (w-1): exited with 0: 5.4\n\
+             1 machine (w-3): exited with 1"
+        );
+
+        assert!(Summary::new(&HashMap::new()).is_uniform());
+    }
+}
@@ -0,0 +1,737 @@
+//! Keep track of runs in a shared place.
+//!
+//! [`Runs`] keeps a record of each run in a [`Store`]. The record holds who started the run and
+//! when, the launcher configuration, the launched machines, and an audit log of what happened to
+//! the run. When the store is shared, e.g. a directory on a network file system, an S3 bucket, or
+//! an Azure Blob container, everyone on a team can see which runs are still up, and clean up
+//! after runs that are done.
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use std::time::Duration;
+//! use tsunami::runs::{Dir, Runs};
+//! use tsunami::Tsunami;
+//!
+//! let runs = Runs::new(Dir::new("/shared/tsunami/runs"));
+//! let id = aws.record_run(&runs).await?;
+//! // ... run the experiment ...
+//! aws.terminate_all().await?;
+//! runs.finish(&id).await?;
+//!
+//! for run in runs.list().await? {
+//!     if run.finished.is_none() {
+//!         println!("{} started by {} is still up", run.id, run.owner);
+//!     }
+//! }
+//! // forget about runs that finished more than a week ago
+//! runs.collect_garbage(Duration::from_secs(7 * 24 * 60 * 60)).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The store is a plain key-value store, so records can also be read without tsunami: each run
+//! `id` has the keys `id/meta`, `id/launcher`, `id/machines` (in the format of
+//! [`Tsunami::export_bundle`](crate::Tsunami::export_bundle)), and `id/audit`.
+
+use color_eyre::{eyre, eyre::WrapErr, Report};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A future returned by a [`Store`].
+pub type StoreFuture<'s, T> = Pin<Box<dyn Future<Output = Result<T, Report>> + Send + 's>>;
+
+/// Somewhere to keep run records.
+///
+/// Keys are `/`-separated paths of letters, digits, `-`, `_`, and `.`.
+pub trait Store: Send + Sync {
+    /// Set `key` to `value`, replacing what was there.
+    fn put<'s>(&'s self, key: &'s str, value: Vec<u8>) -> StoreFuture<'s, ()>;
+
+    /// The value of `key`, or `None` if it is not set.
+    fn get<'s>(&'s self, key: &'s str) -> StoreFuture<'s, Option<Vec<u8>>>;
+
+    /// All keys that start with `prefix`.
+    fn list<'s>(&'s self, prefix: &'s str) -> StoreFuture<'s, Vec<String>>;
+
+    /// Remove `key`. Removing a key that is not set is not an error.
+    fn delete<'s>(&'s self, key: &'s str) -> StoreFuture<'s, ()>;
+}
+
+/// A [`Store`] in a local directory, with one file per key.
+///
+/// Point it at a directory on a shared file system (e.g. NFS) to share the runs.
+#[derive(Debug, Clone)]
+pub struct Dir {
+    root: PathBuf,
+}
+
+impl Dir {
+    /// A store in the directory `root`, which is created when the first key is set.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Dir { root: root.into() }
+    }
+}
+
+impl Store for Dir {
+    fn put<'s>(&'s self, key: &'s str, value: Vec<u8>) -> StoreFuture<'s, ()> {
+        Box::pin(async move {
+            let path = self.root.join(key);
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .wrap_err_with(|| format!("failed to create {}", dir.display()))?;
+            }
+            // write and rename, so that readers never see half a file
+            let tmp = path.with_extension("tmp");
+            tokio::fs::write(&tmp, value)
+                .await
+                .wrap_err_with(|| format!("failed to write {}", tmp.display()))?;
+            tokio::fs::rename(&tmp, &path)
+                .await
+                .wrap_err_with(|| format!("failed to write {}", path.display()))
+        })
+    }
+
+    fn get<'s>(&'s self, key: &'s str) -> StoreFuture<'s, Option<Vec<u8>>> {
+        Box::pin(async move {
+            let path = self.root.join(key);
+            match tokio::fs::read(&path).await {
+                Ok(v) => Ok(Some(v)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).wrap_err_with(|| format!("failed to read {}", path.display())),
+            }
+        })
+    }
+
+    fn list<'s>(&'s self, prefix: &'s str) -> StoreFuture<'s, Vec<String>> {
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            let mut dirs = vec![(self.root.clone(), String::new())];
+            while let Some((dir, at)) = dirs.pop() {
+                let mut entries = match tokio::fs::read_dir(&dir).await {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => {
+                        return Err(e).wrap_err_with(|| format!("failed to list {}", dir.display()))
+                    }
+                };
+                while let Some(entry) = entries
+                    .next_entry()
+                    .await
+                    .wrap_err_with(|| format!("failed to list {}", dir.display()))?
+                {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    let key = format!("{}{}", at, name);
+                    let is_dir = tokio::fs::metadata(entry.path())
+                        .await
+                        .map(|m| m.is_dir())
+                        .unwrap_or(false);
+                    if is_dir {
+                        dirs.push((entry.path(), format!("{}/", key)));
+                    } else if key.starts_with(prefix) && !name.ends_with(".tmp") {
+                        keys.push(key);
+                    }
+                }
+            }
+            keys.sort();
+            Ok(keys)
+        })
+    }
+
+    fn delete<'s>(&'s self, key: &'s str) -> StoreFuture<'s, ()> {
+        Box::pin(async move {
+            let path = self.root.join(key);
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).wrap_err_with(|| format!("failed to remove {}", path.display()))
+                }
+            }
+            // leave no empty run directories behind
+            if let Some(dir) = path.parent() {
+                if dir != self.root {
+                    let _ = tokio::fs::remove_dir(dir).await;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A [`Store`] in an S3 bucket, with one object per key.
+#[cfg(feature = "s3")]
+#[derive(Clone)]
+pub struct S3 {
+    client: rusoto_s3::S3Client,
+    bucket: String,
+    prefix: String,
+}
+
+#[cfg(feature = "s3")]
+impl std::fmt::Debug for S3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3")
+            .field("bucket", &self.bucket)
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+#[cfg(feature = "s3")]
+impl S3 {
+    /// A store in `bucket`, which is in `region`, using the default AWS credentials.
+    pub fn new(region: rusoto_core::Region, bucket: impl ToString) -> Self {
+        Self::with_client(rusoto_s3::S3Client::new(region), bucket)
+    }
+
+    /// A store in `bucket`, accessed through `client`.
+    pub fn with_client(client: rusoto_s3::S3Client, bucket: impl ToString) -> Self {
+        S3 {
+            client,
+            bucket: bucket.to_string(),
+            prefix: String::new(),
+        }
+    }
+
+    /// Keep the records under `prefix` in the bucket, e.g. `tsunami/runs/`.
+    pub fn prefix(mut self, prefix: impl ToString) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Store for S3 {
+    fn put<'s>(&'s self, key: &'s str, value: Vec<u8>) -> StoreFuture<'s, ()> {
+        use rusoto_s3::S3 as _;
+        Box::pin(async move {
+            let req = rusoto_s3::PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key: format!("{}{}", self.prefix, key),
+                body: Some(value.into()),
+                ..Default::default()
+            };
+            self.client
+                .put_object(req)
+                .await
+                .wrap_err_with(|| format!("failed to write {} to s3://{}", key, self.bucket))?;
+            Ok(())
+        })
+    }
+
+    fn get<'s>(&'s self, key: &'s str) -> StoreFuture<'s, Option<Vec<u8>>> {
+        use futures_util::TryStreamExt;
+        use rusoto_s3::S3 as _;
+        Box::pin(async move {
+            let req = rusoto_s3::GetObjectRequest {
+                bucket: self.bucket.clone(),
+                key: format!("{}{}", self.prefix, key),
+                ..Default::default()
+            };
+            let body = match self.client.get_object(req).await {
+                Ok(o) => o.body,
+                Err(rusoto_core::RusotoError::Service(rusoto_s3::GetObjectError::NoSuchKey(_))) => {
+                    return Ok(None)
+                }
+                Err(e) => {
+                    return Err(e).wrap_err_with(|| {
+                        format!("failed to read {} from s3://{}", key, self.bucket)
+                    })
+                }
+            };
+            let value = match body {
+                Some(body) => body
+                    .map_ok(|b| b.to_vec())
+                    .try_concat()
+                    .await
+                    .wrap_err_with(|| {
+                        format!("failed to read {} from s3://{}", key, self.bucket)
+                    })?,
+                None => Vec::new(),
+            };
+            Ok(Some(value))
+        })
+    }
+
+    fn list<'s>(&'s self, prefix: &'s str) -> StoreFuture<'s, Vec<String>> {
+        use rusoto_s3::S3 as _;
+        Box::pin(async move {
+            let mut keys = Vec::new();
+            let mut continuation_token = None;
+            loop {
+                let req = rusoto_s3::ListObjectsV2Request {
+                    bucket: self.bucket.clone(),
+                    prefix: Some(format!("{}{}", self.prefix, prefix)),
+                    continuation_token,
+                    ..Default::default()
+                };
+                let res = self
+                    .client
+                    .list_objects_v2(req)
+                    .await
+                    .wrap_err_with(|| format!("failed to list s3://{}", self.bucket))?;
+                keys.extend(
+                    res.contents
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|o| Some(o.key?[self.prefix.len()..].to_string())),
+                );
+                continuation_token = res.next_continuation_token;
+                if continuation_token.is_none() {
+                    break;
+                }
+            }
+            keys.sort();
+            Ok(keys)
+        })
+    }
+
+    fn delete<'s>(&'s self, key: &'s str) -> StoreFuture<'s, ()> {
+        use rusoto_s3::S3 as _;
+        Box::pin(async move {
+            let req = rusoto_s3::DeleteObjectRequest {
+                bucket: self.bucket.clone(),
+                key: format!("{}{}", self.prefix, key),
+                ..Default::default()
+            };
+            self.client
+                .delete_object(req)
+                .await
+                .wrap_err_with(|| format!("failed to remove {} from s3://{}", key, self.bucket))?;
+            Ok(())
+        })
+    }
+}
+
+/// A [`Store`] in an Azure Blob Storage container, with one blob per key.
+///
+/// This uses the Blob Storage REST API, with the same credentials as the [Azure
+/// provider](crate::providers::azure): a service principal if `AZURE_TENANT_ID`,
+/// `AZURE_CLIENT_ID`, and `AZURE_CLIENT_SECRET` are set, and the login of the Azure CLI otherwise.
+/// Either needs a data role such as "Storage Blob Data Contributor" on the container.
+#[cfg(feature = "azure")]
+#[derive(Debug, Clone)]
+pub struct AzureBlob {
+    client: crate::providers::azure::arm::Client,
+    account: String,
+    container: String,
+    prefix: String,
+}
+
+#[cfg(feature = "azure")]
+impl AzureBlob {
+    /// A store in `container` of the storage account `account`.
+    pub fn new(account: impl ToString, container: impl ToString) -> Self {
+        AzureBlob {
+            client: Default::default(),
+            account: account.to_string(),
+            container: container.to_string(),
+            prefix: String::new(),
+        }
+    }
+
+    /// Keep the records under `prefix` in the container, e.g. `tsunami/runs/`.
+    pub fn prefix(mut self, prefix: impl ToString) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+}
+
+#[cfg(feature = "azure")]
+impl Store for AzureBlob {
+    fn put<'s>(&'s self, key: &'s str, value: Vec<u8>) -> StoreFuture<'s, ()> {
+        Box::pin(async move {
+            let name = format!("{}{}", self.prefix, key);
+            self.client
+                .put_blob(&self.account, &self.container, &name, value)
+                .await
+        })
+    }
+
+    fn get<'s>(&'s self, key: &'s str) -> StoreFuture<'s, Option<Vec<u8>>> {
+        Box::pin(async move {
+            let name = format!("{}{}", self.prefix, key);
+            self.client
+                .get_blob(&self.account, &self.container, &name)
+                .await
+        })
+    }
+
+    fn list<'s>(&'s self, prefix: &'s str) -> StoreFuture<'s, Vec<String>> {
+        Box::pin(async move {
+            let prefix = format!("{}{}", self.prefix, prefix);
+            let names = self
+                .client
+                .list_blobs(&self.account, &self.container, &prefix)
+                .await?;
+            let mut keys: Vec<_> = names
+                .into_iter()
+                .filter_map(|n| Some(n.strip_prefix(&self.prefix)?.to_string()))
+                .collect();
+            keys.sort();
+            Ok(keys)
+        })
+    }
+
+    fn delete<'s>(&'s self, key: &'s str) -> StoreFuture<'s, ()> {
+        Box::pin(async move {
+            let name = format!("{}{}", self.prefix, key);
+            self.client
+                .delete_blob(&self.account, &self.container, &name)
+                .await
+        })
+    }
+}
+
+/// A [`Store`] in a SQLite database, with one row per key.
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone)]
+pub struct Sqlite {
+    path: PathBuf,
+}
+
+#[cfg(feature = "sqlite")]
+impl Sqlite {
+    /// A store in the database file at `path`, which is created if it does not exist.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Sqlite { path: path.into() }
+    }
+
+    // Runs `f` against the database on a blocking thread, since rusqlite blocks.
+    async fn with_db<T, F>(&self, f: F) -> Result<T, Report>
+    where
+        T: Send + 'static,
+        F: FnOnce(rusqlite::Connection) -> Result<T, Report> + Send + 'static,
+    {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let db = rusqlite::Connection::open(&path)
+                .wrap_err_with(|| format!("failed to open {}", path.display()))?;
+            db.execute(
+                "CREATE TABLE IF NOT EXISTS runs (key TEXT PRIMARY KEY, value BLOB NOT NULL)",
+                rusqlite::params![],
+            )
+            .wrap_err("failed to create the runs table")?;
+            f(db)
+        })
+        .await
+        .wrap_err("sqlite task failed")?
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Store for Sqlite {
+    fn put<'s>(&'s self, key: &'s str, value: Vec<u8>) -> StoreFuture<'s, ()> {
+        let key = key.to_string();
+        Box::pin(self.with_db(move |db| {
+            db.execute(
+                "INSERT OR REPLACE INTO runs (key, value) VALUES (?1, ?2)",
+                rusqlite::params![key, value],
+            )
+            .wrap_err_with(|| format!("failed to write {}", key))?;
+            Ok(())
+        }))
+    }
+
+    fn get<'s>(&'s self, key: &'s str) -> StoreFuture<'s, Option<Vec<u8>>> {
+        use rusqlite::OptionalExtension;
+        let key = key.to_string();
+        Box::pin(self.with_db(move |db| {
+            db.query_row(
+                "SELECT value FROM runs WHERE key = ?1",
+                rusqlite::params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .wrap_err_with(|| format!("failed to read {}", key))
+        }))
+    }
+
+    fn list<'s>(&'s self, prefix: &'s str) -> StoreFuture<'s, Vec<String>> {
+        let prefix = prefix.to_string();
+        Box::pin(self.with_db(move |db| {
+            let mut q = db
+                .prepare("SELECT key FROM runs WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key")
+                .wrap_err("failed to list keys")?;
+            let keys = q
+                .query_map(rusqlite::params![prefix], |row| row.get(0))
+                .wrap_err("failed to list keys")?
+                .collect::<Result<_, _>>()
+                .wrap_err("failed to list keys")?;
+            Ok(keys)
+        }))
+    }
+
+    fn delete<'s>(&'s self, key: &'s str) -> StoreFuture<'s, ()> {
+        let key = key.to_string();
+        Box::pin(self.with_db(move |db| {
+            db.execute("DELETE FROM runs WHERE key = ?1", rusqlite::params![key])
+                .wrap_err_with(|| format!("failed to remove {}", key))?;
+            Ok(())
+        }))
+    }
+}
+
+/// The record of a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Run {
+    /// The id of the run, which is unique within the store.
+    pub id: String,
+    /// Who started the run (the `USER` that ran tsunami).
+    pub owner: String,
+    /// When the run was started.
+    pub started: SystemTime,
+    /// When the run was [finished](Runs::finish), if it was.
+    pub finished: Option<SystemTime>,
+    /// The launcher configuration, see [`Tsunami::export_bundle`](crate::Tsunami::export_bundle).
+    pub launcher: String,
+    /// One line per machine, see [`Tsunami::export_bundle`](crate::Tsunami::export_bundle).
+    pub machines: String,
+}
+
+impl Run {
+    /// A new run of `launcher` with `machines`, started now by the current user.
+    pub fn new(launcher: impl ToString, machines: impl ToString) -> Self {
+        use rand::Rng;
+        // records keep whole seconds
+        let started = UNIX_EPOCH + Duration::from_secs(secs(SystemTime::now()));
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(8)
+            .map(|c| char::from(c).to_ascii_lowercase())
+            .collect();
+        Run {
+            id: format!("{}-{}", secs(started), suffix),
+            owner: std::env::var("USER").unwrap_or_else(|_| String::from("unknown")),
+            started,
+            finished: None,
+            launcher: launcher.to_string(),
+            machines: machines.to_string(),
+        }
+    }
+
+    fn meta(&self) -> String {
+        let mut meta = format!("owner\t{}\nstarted\t{}\n", self.owner, secs(self.started));
+        if let Some(finished) = self.finished {
+            meta.push_str(&format!("finished\t{}\n", secs(finished)));
+        }
+        meta
+    }
+
+    fn parse_meta(&mut self, meta: &str) -> Result<(), Report> {
+        let time = |v: &str| -> Result<SystemTime, Report> {
+            Ok(UNIX_EPOCH + Duration::from_secs(v.parse().wrap_err("bad timestamp")?))
+        };
+        for line in meta.lines() {
+            match line.split_once('\t') {
+                Some(("owner", v)) => self.owner = v.to_string(),
+                Some(("started", v)) => self.started = time(v)?,
+                Some(("finished", v)) => self.finished = Some(time(v)?),
+                // fields written by later versions
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The runs kept in a [`Store`].
+///
+/// See the [module documentation](self) for an example.
+#[derive(Debug)]
+pub struct Runs<S> {
+    store: S,
+}
+
+impl<S: Store> Runs<S> {
+    /// The runs kept in `store`.
+    pub fn new(store: S) -> Self {
+        Runs { store }
+    }
+
+    /// Save `run`, replacing any earlier record of it.
+    ///
+    /// Usually [`Tsunami::record_run`](crate::Tsunami::record_run) does this.
+    pub async fn save(&self, run: &Run) -> Result<(), Report> {
+        let key = |k: &str| format!("{}/{}", run.id, k);
+        self.store
+            .put(&key("launcher"), run.launcher.clone().into_bytes())
+            .await?;
+        self.store
+            .put(&key("machines"), run.machines.clone().into_bytes())
+            .await?;
+        // meta goes last, since it is what makes the run show up.
+        self.store.put(&key("meta"), run.meta().into_bytes()).await
+    }
+
+    /// The record of run `id`, if there is one.
+    pub async fn get(&self, id: &str) -> Result<Option<Run>, Report> {
+        let read = |k: &'static str| async move {
+            let key = format!("{}/{}", id, k);
+            Ok::<_, Report>(
+                self.store
+                    .get(&key)
+                    .await?
+                    .map(|v| String::from_utf8_lossy(&v).into_owned()),
+            )
+        };
+        let meta = match read("meta").await? {
+            Some(meta) => meta,
+            None => return Ok(None),
+        };
+        let mut run = Run {
+            id: id.to_string(),
+            owner: String::new(),
+            started: UNIX_EPOCH,
+            finished: None,
+            launcher: read("launcher").await?.unwrap_or_default(),
+            machines: read("machines").await?.unwrap_or_default(),
+        };
+        run.parse_meta(&meta)
+            .wrap_err_with(|| format!("bad record of run {}", id))?;
+        Ok(Some(run))
+    }
+
+    /// Every run in the store, oldest first.
+    pub async fn list(&self) -> Result<Vec<Run>, Report> {
+        let mut runs = Vec::new();
+        for key in self.store.list("").await? {
+            if let Some(id) = key.strip_suffix("/meta") {
+                runs.extend(self.get(id).await?);
+            }
+        }
+        runs.sort_by(|a, b| a.started.cmp(&b.started).then_with(|| a.id.cmp(&b.id)));
+        Ok(runs)
+    }
+
+    /// Add `event` to the audit log of run `id`, with the time and the current user.
+    ///
+    /// Two processes that log to the same run at the same time may lose one of the entries.
+    pub async fn log(&self, id: &str, event: impl std::fmt::Display) -> Result<(), Report> {
+        let key = format!("{}/audit", id);
+        let mut log = self.store.get(&key).await?.unwrap_or_default();
+        let owner = std::env::var("USER").unwrap_or_else(|_| String::from("unknown"));
+        log.extend(format!("{}\t{}\t{}\n", secs(SystemTime::now()), owner, event).bytes());
+        self.store.put(&key, log).await
+    }
+
+    /// The audit log of run `id`, one `<unix time>\t<user>\t<event>` line per entry.
+    pub async fn audit_log(&self, id: &str) -> Result<Vec<String>, Report> {
+        let log = self.store.get(&format!("{}/audit", id)).await?;
+        Ok(String::from_utf8_lossy(&log.unwrap_or_default())
+            .lines()
+            .map(String::from)
+            .collect())
+    }
+
+    /// Mark run `id` as finished, e.g. once its machines are terminated.
+    pub async fn finish(&self, id: &str) -> Result<(), Report> {
+        let mut run = self
+            .get(id)
+            .await?
+            .ok_or_else(|| eyre::eyre!("no run {}", id))?;
+        run.finished = Some(SystemTime::now());
+        self.store
+            .put(&format!("{}/meta", id), run.meta().into_bytes())
+            .await?;
+        self.log(id, "finished").await
+    }
+
+    /// Remove every record of run `id`.
+    pub async fn remove(&self, id: &str) -> Result<(), Report> {
+        // meta goes first, so that a half-removed run no longer shows up.
+        self.store.delete(&format!("{}/meta", id)).await?;
+        for key in self.store.list(&format!("{}/", id)).await? {
+            self.store.delete(&key).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove the runs that finished more than `age` ago, and return them.
+    ///
+    /// Runs that were never finished are kept, since their machines may still be up. Check
+    /// [`Run::finished`] in [`Runs::list`] to find them.
+    pub async fn collect_garbage(&self, age: Duration) -> Result<Vec<Run>, Report> {
+        let now = SystemTime::now();
+        let mut removed = Vec::new();
+        for run in self.list().await? {
+            let old = run
+                .finished
+                .map_or(false, |t| now.duration_since(t).map_or(false, |d| d >= age));
+            if old {
+                tracing::debug!(id = %run.id, owner = %run.owner, "removing finished run");
+                self.remove(&run.id).await?;
+                removed.push(run);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn store() -> Dir {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("tsunami-runs-{}", Run::new("", "").id));
+        Dir::new(dir)
+    }
+
+    #[tokio::test]
+    async fn dir_store() {
+        let s = store();
+        assert_eq!(s.get("a/b").await.unwrap(), None);
+        s.put("a/b", b"1".to_vec()).await.unwrap();
+        s.put("a/c", b"2".to_vec()).await.unwrap();
+        s.put("d/b", b"3".to_vec()).await.unwrap();
+        assert_eq!(s.get("a/b").await.unwrap(), Some(b"1".to_vec()));
+        assert_eq!(s.list("").await.unwrap(), vec!["a/b", "a/c", "d/b"]);
+        assert_eq!(s.list("a/").await.unwrap(), vec!["a/b", "a/c"]);
+        s.delete("a/b").await.unwrap();
+        s.delete("a/b").await.unwrap();
+        assert_eq!(s.list("a/").await.unwrap(), vec!["a/c"]);
+        let _ = std::fs::remove_dir_all(&s.root);
+    }
+
+    #[tokio::test]
+    async fn runs() {
+        let runs = Runs::new(store());
+        let mut old = Run::new("launcher", "server\trunning\n");
+        old.started = UNIX_EPOCH + Duration::from_secs(1_000);
+        runs.save(&old).await.unwrap();
+        let new = Run::new("launcher", "");
+        runs.save(&new).await.unwrap();
+        assert_eq!(runs.list().await.unwrap(), vec![old.clone(), new.clone()]);
+
+        runs.log(&old.id, "launched").await.unwrap();
+        runs.finish(&old.id).await.unwrap();
+        let log = runs.audit_log(&old.id).await.unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log[1].ends_with("\tfinished"));
+        assert!(runs.get(&old.id).await.unwrap().unwrap().finished.is_some());
+
+        // just finished, so not yet garbage
+        assert!(runs
+            .collect_garbage(Duration::from_secs(60))
+            .await
+            .unwrap()
+            .is_empty());
+        let removed = runs.collect_garbage(Duration::ZERO).await.unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].id, old.id);
+        assert_eq!(runs.list().await.unwrap(), vec![new]);
+        assert_eq!(runs.get(&old.id).await.unwrap(), None);
+        let _ = std::fs::remove_dir_all(&runs.store.root);
+    }
+}
@@ -0,0 +1,598 @@
+//! Turn machines into a Spark or HDFS cluster.
+//!
+//! [`Cluster::bootstrap`] stands up a standalone [Spark](https://spark.apache.org/) cluster, an
+//! [HDFS](https://hadoop.apache.org/) file system, or both, across the machines of a tsunami. One
+//! machine is the master, which runs the Spark master and the HDFS namenode, and the others are
+//! workers, which run a Spark worker and an HDFS datanode each. It installs Java and the
+//! distributions, writes their configuration, starts the services, and waits until every worker
+//! has joined. The returned [`Spark`] then submits jobs to the cluster:
+//!
+//! ```rust,no_run
+//! # async fn f(aws: tsunami::providers::aws::Launcher) -> Result<(), color_eyre::Report> {
+//! use tsunami::spark::Cluster;
+//! use tsunami::Tsunami;
+//! let vms = aws.connect_all().await?;
+//! let spark = Cluster::spark().with_hdfs().bootstrap(&vms).await?;
+//! let out = spark
+//!     .submit("--class org.apache.spark.examples.SparkPi spark/examples/jars/spark-examples_*.jar 1000")
+//!     .await?;
+//! println!("{}", out.stdout);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::run::{address, quote, CommandOutput};
+use crate::{Machine, OsFamily};
+use color_eyre::{
+    eyre::{self, eyre, WrapErr},
+    Report,
+};
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+/// Where Spark is installed, relative to the ssh user's home directory.
+pub const SPARK_HOME: &str = "spark";
+
+/// Where Hadoop, and with it HDFS, is installed, relative to the ssh user's home directory.
+pub const HADOOP_HOME: &str = "hadoop";
+
+// Where HDFS keeps its data, relative to the ssh user's home directory.
+const HDFS_DATA: &str = ".tsunami/hdfs";
+
+const SPARK_PORT: u16 = 7077;
+const SPARK_UI_PORT: u16 = 8080;
+const HDFS_PORT: u16 = 9000;
+
+/// How to set up a Spark or HDFS cluster. See the [module documentation](self).
+#[derive(Debug, Clone)]
+pub struct Cluster {
+    spark: Option<String>,
+    hadoop: Option<String>,
+    master: Option<String>,
+    dedicated_master: bool,
+    properties: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl Cluster {
+    fn new() -> Self {
+        Cluster {
+            spark: None,
+            hadoop: None,
+            master: None,
+            dedicated_master: false,
+            properties: Vec::new(),
+            timeout: Duration::from_secs(180),
+        }
+    }
+
+    /// A standalone Spark cluster, of Spark 3.5.1.
+    pub fn spark() -> Self {
+        Self::new().spark_version("3.5.1")
+    }
+
+    /// An HDFS file system, of Hadoop 3.3.6.
+    pub fn hdfs() -> Self {
+        Self::new().hadoop_version("3.3.6")
+    }
+
+    /// Also run HDFS, of Hadoop 3.3.6, on the machines of a Spark cluster.
+    pub fn with_hdfs(self) -> Self {
+        if self.hadoop.is_some() {
+            return self;
+        }
+        self.hadoop_version("3.3.6")
+    }
+
+    /// Install Spark `version`, built for Hadoop 3.
+    pub fn spark_version(mut self, version: impl ToString) -> Self {
+        self.spark = Some(version.to_string());
+        self
+    }
+
+    /// Install Hadoop `version` for HDFS.
+    pub fn hadoop_version(mut self, version: impl ToString) -> Self {
+        self.hadoop = Some(version.to_string());
+        self
+    }
+
+    /// Make the machine with nickname `nickname` the master, rather than the one that comes first
+    /// by nickname.
+    pub fn master(mut self, nickname: impl ToString) -> Self {
+        self.master = Some(nickname.to_string());
+        self
+    }
+
+    /// Run no worker on the master.
+    ///
+    /// By default, every machine is a worker, including the master, so that small clusters do not
+    /// leave a machine idle.
+    pub fn dedicated_master(mut self) -> Self {
+        self.dedicated_master = true;
+        self
+    }
+
+    /// Set the Spark property `key` to `value` in `spark-defaults.conf`, e.g.
+    /// `spark.executor.memory`.
+    pub fn property(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.properties.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Give up if the workers have not all joined `timeout` after the services were started.
+    ///
+    /// The default is three minutes.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    // The package that provides a Java runtime on `os`.
+    fn java(os: OsFamily) -> &'static str {
+        match os {
+            OsFamily::AmazonLinux => "java-17-amazon-corretto-headless",
+            OsFamily::FreeBsd => "openjdk17",
+            _ => "openjdk-17-jre-headless",
+        }
+    }
+
+    // Installs Java, Spark, and Hadoop on `vm`, unless they are already there.
+    async fn install(&self, vm: &Machine<'_>) -> Result<(), Report> {
+        let os = match vm.os {
+            Some(os) => os,
+            None => OsFamily::detect(&vm.ssh).await?,
+        };
+        let mut script = String::from("set -e\n");
+        script.push_str("if ! command -v java >/dev/null; then\n");
+        if let OsFamily::Ubuntu | OsFamily::Debian = os {
+            script.push_str("sudo apt-get update -q\n");
+        }
+        let _ = writeln!(
+            script,
+            "sudo DEBIAN_FRONTEND=noninteractive {}\nfi",
+            os.install_command(&[Self::java(os)]).join(" ")
+        );
+        if let Some(ref v) = self.spark {
+            script.push_str(&unpack_script(SPARK_HOME, v, &spark_url(v)));
+        }
+        if let Some(ref v) = self.hadoop {
+            script.push_str(&unpack_script(HADOOP_HOME, v, &hadoop_url(v)));
+        }
+        sh(vm, &script).await.wrap_err("failed to install")?;
+        Ok(())
+    }
+
+    // The configuration files of the machine at `local`, as (path, contents). The distribution's
+    // own files are replaced, which only hold examples.
+    fn config(&self, master: &str, local: &str, datanodes: usize) -> Vec<(String, String)> {
+        let mut files = Vec::new();
+        if self.spark.is_some() {
+            files.push((
+                format!("{}/conf/spark-env.sh", SPARK_HOME),
+                format!(
+                    "SPARK_MASTER_HOST={}\nSPARK_LOCAL_IP={}\nexport JAVA_HOME=\"{}\"\n",
+                    master, local, JAVA_HOME
+                ),
+            ));
+            let mut defaults = format!("spark.master {}\n", spark_url_of(master));
+            if self.hadoop.is_some() {
+                let _ = writeln!(
+                    defaults,
+                    "spark.hadoop.fs.defaultFS {}",
+                    hdfs_url_of(master)
+                );
+            }
+            for (k, v) in &self.properties {
+                let _ = writeln!(defaults, "{} {}", k, v);
+            }
+            files.push((format!("{}/conf/spark-defaults.conf", SPARK_HOME), defaults));
+        }
+        if self.hadoop.is_some() {
+            files.push((
+                format!("{}/etc/hadoop/core-site.xml", HADOOP_HOME),
+                hadoop_xml(&[("fs.defaultFS", &hdfs_url_of(master))]),
+            ));
+            files.push((
+                format!("{}/etc/hadoop/hdfs-site.xml", HADOOP_HOME),
+                hadoop_xml(&[
+                    ("dfs.replication", &datanodes.min(3).to_string()),
+                    (
+                        "dfs.namenode.name.dir",
+                        &format!("file://${{user.home}}/{}/name", HDFS_DATA),
+                    ),
+                    (
+                        "dfs.datanode.data.dir",
+                        &format!("file://${{user.home}}/{}/data", HDFS_DATA),
+                    ),
+                    ("dfs.datanode.hostname", local),
+                    (
+                        "dfs.namenode.datanode.registration.ip-hostname-check",
+                        "false",
+                    ),
+                ]),
+            ));
+            files.push((
+                format!("{}/etc/hadoop/hadoop-env.sh", HADOOP_HOME),
+                format!("export JAVA_HOME=\"{}\"\n", JAVA_HOME),
+            ));
+        }
+        files
+    }
+
+    /// Set up `machines` as a cluster, start its services, and return a handle for it once every
+    /// worker has joined.
+    ///
+    /// `machines` is the map that [`connect_all`](crate::Tsunami::connect_all) or
+    /// [`connect_all_sorted`](crate::Tsunami::connect_all_sorted) returns, or any other nickname
+    /// and machine pairs.
+    ///
+    /// The machines reach each other over their private addresses where they have one, so the
+    /// master needs to admit traffic from the others (e.g., with
+    /// [`aws::IngressRule::all`](crate::providers::aws::IngressRule::all)). Bootstrapping again
+    /// restarts the services, and wipes HDFS.
+    #[tracing::instrument(level = "debug", skip(machines))]
+    pub async fn bootstrap<'m, 't>(
+        &self,
+        machines: impl IntoIterator<Item = (&'m String, &'m Machine<'t>)>,
+    ) -> Result<Spark<'m, 't>, Report> {
+        eyre::ensure!(
+            self.spark.is_some() || self.hadoop.is_some(),
+            "neither Spark nor HDFS to set up"
+        );
+        let vms = crate::by_nickname(machines);
+        let (master_name, master) = match self.master {
+            Some(ref name) => vms
+                .iter()
+                .copied()
+                .find(|(n, _)| *n == name)
+                .ok_or_else(|| eyre!("no machine {} to be the master", name))?,
+            None => *vms.first().ok_or_else(|| eyre!("no machines"))?,
+        };
+        let workers: Vec<_> = vms
+            .iter()
+            .copied()
+            .filter(|(name, _)| !self.dedicated_master || *name != master_name)
+            .collect();
+        eyre::ensure!(!workers.is_empty(), "no machines left to be workers");
+        let master_addr = address(master);
+
+        tracing::info!("installing Spark and Hadoop");
+        let limit = vms.len();
+        crate::each::for_each(vms.iter().copied(), limit, |vm| self.install(vm)).await?;
+
+        tracing::debug!("writing configuration");
+        let stop = self.stop_script();
+        let stop = &stop;
+        let datanodes = workers.len();
+        crate::each::for_each(vms.iter().copied(), limit, |vm| async move {
+            let mut script = String::from("set -e\n");
+            for (path, contents) in self.config(master_addr, address(vm), datanodes) {
+                let _ = writeln!(script, "printf '%s' {} > {}", quote(&contents), path);
+            }
+            // whatever an earlier bootstrap started has the old configuration.
+            script.push_str(stop);
+            sh(vm, &script).await?;
+            Ok(())
+        })
+        .await
+        .wrap_err("failed to configure")?;
+
+        let worker_limit = workers.len();
+        if self.hadoop.is_some() {
+            tracing::info!(master = %master_name, "starting HDFS");
+            crate::each::for_each(vms.iter().copied(), limit, |vm| async move {
+                sh(vm, &format!("rm -rf {}", HDFS_DATA)).await
+            })
+            .await?;
+            sh(
+                master,
+                &format!(
+                    "set -e\n{0}/bin/hdfs namenode -format -force -nonInteractive\n{0}/bin/hdfs --daemon start namenode",
+                    HADOOP_HOME
+                ),
+            )
+            .await
+            .wrap_err("failed to start the namenode")?;
+            crate::each::for_each(workers.iter().copied(), worker_limit, |vm| async move {
+                sh(
+                    vm,
+                    &format!("{}/bin/hdfs --daemon start datanode", HADOOP_HOME),
+                )
+                .await
+            })
+            .await
+            .wrap_err("failed to start the datanodes")?;
+        }
+        if self.spark.is_some() {
+            tracing::info!(master = %master_name, "starting Spark");
+            sh(master, &format!("{}/sbin/start-master.sh", SPARK_HOME))
+                .await
+                .wrap_err("failed to start the Spark master")?;
+            let url = spark_url_of(master_addr);
+            let url = &url;
+            crate::each::for_each(workers.iter().copied(), worker_limit, |vm| async move {
+                sh(vm, &format!("{}/sbin/start-worker.sh {}", SPARK_HOME, url)).await
+            })
+            .await
+            .wrap_err("failed to start the Spark workers")?;
+        }
+
+        let cluster = Spark {
+            master,
+            spark: self.spark.as_ref().map(|_| spark_url_of(master_addr)),
+            hdfs: self.hadoop.as_ref().map(|_| hdfs_url_of(master_addr)),
+            workers: workers.len(),
+        };
+        cluster.wait_ready(self.timeout).await?;
+        tracing::info!(workers = workers.len(), "cluster is up");
+        Ok(cluster)
+    }
+
+    // Stops the services that this cluster runs, if they are running.
+    fn stop_script(&self) -> String {
+        let mut script = String::new();
+        if self.spark.is_some() {
+            let _ = writeln!(
+                script,
+                "{0}/sbin/stop-worker.sh >/dev/null 2>&1 || true\n{0}/sbin/stop-master.sh >/dev/null 2>&1 || true",
+                SPARK_HOME
+            );
+        }
+        if self.hadoop.is_some() {
+            let _ = writeln!(
+                script,
+                "{0}/bin/hdfs --daemon stop datanode >/dev/null 2>&1 || true\n{0}/bin/hdfs --daemon stop namenode >/dev/null 2>&1 || true",
+                HADOOP_HOME
+            );
+        }
+        script
+    }
+}
+
+/// A handle for a bootstrapped [`Cluster`].
+#[derive(Debug)]
+pub struct Spark<'m, 't> {
+    master: &'m Machine<'t>,
+    spark: Option<String>,
+    hdfs: Option<String>,
+    workers: usize,
+}
+
+impl<'m, 't> Spark<'m, 't> {
+    /// The machine that runs the Spark master and the HDFS namenode.
+    pub fn master(&self) -> &'m Machine<'t> {
+        self.master
+    }
+
+    /// The number of workers.
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// The URL of the Spark master, `spark://<address>:7077`, if the cluster runs Spark.
+    pub fn master_url(&self) -> Option<&str> {
+        self.spark.as_deref()
+    }
+
+    /// The URL of the HDFS namenode, `hdfs://<address>:9000`, if the cluster runs HDFS.
+    pub fn hdfs_url(&self) -> Option<&str> {
+        self.hdfs.as_deref()
+    }
+
+    /// Run `spark-submit` with `args` on the master, and wait for it to finish.
+    ///
+    /// `args` are passed on to `spark-submit` as is, after `--master`, and paths in them are
+    /// relative to the ssh user's home directory on the master. Like with
+    /// [`run_on`](crate::run::run_on), a non-zero exit status is not an error.
+    pub async fn submit(&self, args: &str) -> Result<CommandOutput, Report> {
+        let url = self
+            .spark
+            .as_ref()
+            .ok_or_else(|| eyre!("the cluster does not run Spark"))?;
+        let cmd = format!("{}/bin/spark-submit --master {} {}", SPARK_HOME, url, args);
+        tracing::debug!(%cmd, "submitting Spark job");
+        Ok(CommandOutput::from(
+            self.master.ssh.shell(cmd).output().await?,
+        ))
+    }
+
+    /// Run `hdfs dfs` with `args` on the master, e.g. `-put input /input`, and wait for it to
+    /// finish.
+    pub async fn dfs(&self, args: &str) -> Result<CommandOutput, Report> {
+        eyre::ensure!(self.hdfs.is_some(), "the cluster does not run HDFS");
+        let cmd = format!("{}/bin/hdfs dfs {}", HADOOP_HOME, args);
+        Ok(CommandOutput::from(
+            self.master.ssh.shell(cmd).output().await?,
+        ))
+    }
+
+    // Waits until every worker has joined the Spark master and the namenode.
+    async fn wait_ready(&self, timeout: Duration) -> Result<(), Report> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let spark = match self.spark {
+                Some(_) => {
+                    let json = sh(
+                        self.master,
+                        &format!("curl -sf http://localhost:{}/json/", SPARK_UI_PORT),
+                    )
+                    .await
+                    .unwrap_or_default();
+                    alive_workers(&json).unwrap_or(0)
+                }
+                None => self.workers,
+            };
+            let hdfs = match self.hdfs {
+                Some(_) => {
+                    let report = sh(
+                        self.master,
+                        &format!("{}/bin/hdfs dfsadmin -report -live", HADOOP_HOME),
+                    )
+                    .await
+                    .unwrap_or_default();
+                    live_datanodes(&report).unwrap_or(0)
+                }
+                None => self.workers,
+            };
+            tracing::trace!(spark, hdfs, of = self.workers, "waiting for workers");
+            if spark >= self.workers && hdfs >= self.workers {
+                return Ok(());
+            }
+            eyre::ensure!(
+                Instant::now() < deadline,
+                "only {} Spark workers and {} datanodes of {} joined in time",
+                spark,
+                hdfs,
+                self.workers
+            );
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+    }
+}
+
+// Finds the Java installation from the `java` on the PATH, when sourced.
+const JAVA_HOME: &str = "$(dirname \"$(dirname \"$(readlink -f \"$(command -v java)\")\")\")";
+
+fn spark_url(version: &str) -> String {
+    format!(
+        "https://archive.apache.org/dist/spark/spark-{0}/spark-{0}-bin-hadoop3.tgz",
+        version
+    )
+}
+
+fn hadoop_url(version: &str) -> String {
+    format!(
+        "https://archive.apache.org/dist/hadoop/common/hadoop-{0}/hadoop-{0}.tar.gz",
+        version
+    )
+}
+
+fn spark_url_of(master: &str) -> String {
+    format!("spark://{}:{}", master, SPARK_PORT)
+}
+
+fn hdfs_url_of(master: &str) -> String {
+    format!("hdfs://{}:{}", master, HDFS_PORT)
+}
+
+// Downloads the tarball at `url` into `dir`, unless `version` is already there.
+fn unpack_script(dir: &str, version: &str, url: &str) -> String {
+    format!(
+        "if [ \"$(cat {0}/.tsunami-version 2>/dev/null)\" != {1} ]; then\n\
+         rm -rf {0} && mkdir {0}\n\
+         curl -sSfL {2} | tar -xz -C {0} --strip-components=1\n\
+         printf '%s' {1} > {0}/.tsunami-version\n\
+         fi\n",
+        dir,
+        quote(version),
+        quote(url)
+    )
+}
+
+// A Hadoop configuration file that sets `properties`.
+fn hadoop_xml(properties: &[(&str, &str)]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<configuration>\n");
+    for (name, value) in properties {
+        let _ = writeln!(
+            xml,
+            "  <property><name>{}</name><value>{}</value></property>",
+            name, value
+        );
+    }
+    xml.push_str("</configuration>\n");
+    xml
+}
+
+// The number of alive workers in the JSON status of a Spark master.
+fn alive_workers(json: &str) -> Option<usize> {
+    let status: serde_json::Value = serde_json::from_str(json).ok()?;
+    status.get("aliveworkers")?.as_u64().map(|n| n as usize)
+}
+
+// The number of live datanodes in the output of `hdfs dfsadmin -report`.
+fn live_datanodes(report: &str) -> Option<usize> {
+    report.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix("Live datanodes (")?
+            .strip_suffix("):")?
+            .parse()
+            .ok()
+    })
+}
+
+// Runs `script` with `sh` on `vm`, and returns what it printed.
+async fn sh(vm: &Machine<'_>, script: &str) -> Result<String, Report> {
+    let out = vm.ssh.command("sh").arg("-c").arg(script).output().await?;
+    eyre::ensure!(
+        out.status.success(),
+        "command failed: {}",
+        String::from_utf8_lossy(&out.stderr).trim()
+    );
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn config_files() {
+        let cluster = Cluster::spark()
+            .with_hdfs()
+            .property("spark.executor.memory", "4g");
+        let files: HashMap<_, _> = cluster
+            .config("10.0.0.1", "10.0.0.2", 5)
+            .into_iter()
+            .collect();
+        assert!(files["spark/conf/spark-env.sh"]
+            .starts_with("SPARK_MASTER_HOST=10.0.0.1\nSPARK_LOCAL_IP=10.0.0.2\n"));
+        assert_eq!(
+            files["spark/conf/spark-defaults.conf"],
+            "spark.master spark://10.0.0.1:7077\n\
+             spark.hadoop.fs.defaultFS hdfs://10.0.0.1:9000\n\
+             spark.executor.memory 4g\n"
+        );
+        assert!(files["hadoop/etc/hadoop/core-site.xml"].contains(
+            "<property><name>fs.defaultFS</name><value>hdfs://10.0.0.1:9000</value></property>"
+        ));
+        assert!(files["hadoop/etc/hadoop/hdfs-site.xml"]
+            .contains("<name>dfs.replication</name><value>3</value>"));
+
+        let files = Cluster::hdfs().config("10.0.0.1", "10.0.0.1", 2);
+        assert_eq!(files.len(), 3);
+        assert!(files[1]
+            .1
+            .contains("<name>dfs.replication</name><value>2</value>"));
+    }
+
+    #[test]
+    fn readiness() {
+        let json = r#"{ "url" : "spark://10.0.0.1:7077", "workers" : [ ], "aliveworkers" : 3, "cores" : 12 }"#;
+        assert_eq!(alive_workers(json), Some(3));
+        assert_eq!(alive_workers(""), None);
+
+        let report = "Configured Capacity: 0 (0 B)\n\
+                      -------------------------------------------------\n\
+                      Live datanodes (2):\n\
+                      \n\
+                      Name: 10.0.0.2:9866 (10.0.0.2)\n";
+        assert_eq!(live_datanodes(report), Some(2));
+        assert_eq!(live_datanodes("safe mode is ON"), None);
+    }
+
+    #[test]
+    fn install_scripts() {
+        assert_eq!(
+            spark_url("3.5.1"),
+            "https://archive.apache.org/dist/spark/spark-3.5.1/spark-3.5.1-bin-hadoop3.tgz"
+        );
+        let script = unpack_script(HADOOP_HOME, "3.3.6", &hadoop_url("3.3.6"));
+        assert!(script.starts_with(
+            "if [ \"$(cat hadoop/.tsunami-version 2>/dev/null)\" != '3.3.6' ]; then\n"
+        ));
+        assert!(script.contains(
+            "curl -sSfL 'https://archive.apache.org/dist/hadoop/common/hadoop-3.3.6/hadoop-3.3.6.tar.gz' | tar -xz -C hadoop --strip-components=1\n"
+        ));
+    }
+}
@@ -0,0 +1,223 @@
+//! Named setup steps that run at most once per machine.
+//!
+//! Setup functions often spend minutes installing packages before they get to the part that
+//! changes between runs. Wrapping each part in a named [`step`] records on the machine, under
+//! [`MARKER_DIR`], that the step completed. Running the setup again, whether after a transient
+//! failure, through [`Tsunami::rerun_setup`](crate::Tsunami::rerun_setup), or from a restarted
+//! harness that reconnects to the same machines, then skips the steps that are already done.
+//!
+//! [`Steps`] strings steps together into a setup function:
+//!
+//! ```rust,no_run
+//! use tsunami::providers::aws;
+//! use tsunami::steps::Steps;
+//! let steps = Steps::new()
+//!     .step("packages", |vm| {
+//!         Box::pin(async move {
+//!             vm.ssh
+//!                 .command("sudo")
+//!                 .arg("apt-get")
+//!                 .arg("install")
+//!                 .arg("-y")
+//!                 .arg("build-essential")
+//!                 .status()
+//!                 .await?;
+//!             Ok(())
+//!         })
+//!     })
+//!     .step("checkout", |vm| {
+//!         Box::pin(async move {
+//!             vm.ssh
+//!                 .command("git")
+//!                 .arg("clone")
+//!                 .arg("https://github.com/jonhoo/tsunami")
+//!                 .status()
+//!                 .await?;
+//!             Ok(())
+//!         })
+//!     });
+//! let m = aws::Setup::default().setup(move |vm| steps.run(vm));
+//! ```
+//!
+//! A step only counts as completed if it succeeds, so a failed step runs again next time. Use
+//! [`forget`] to make a step run again even though it succeeded.
+
+use crate::Machine;
+use color_eyre::{
+    eyre::{self, WrapErr},
+    Report,
+};
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// The directory that holds the completion markers, relative to the ssh user's home directory.
+pub const MARKER_DIR: &str = ".tsunami/steps";
+
+// The path of the completion marker of the step `name`.
+fn marker(name: &str) -> Result<String, Report> {
+    eyre::ensure!(
+        !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)),
+        "invalid step name {:?}: use letters, digits, '-', '_' and '.'",
+        name
+    );
+    Ok(format!("{}/{}", MARKER_DIR, name))
+}
+
+/// Whether the step `name` has completed on `vm`.
+pub async fn is_done(vm: &Machine<'_>, name: &str) -> Result<bool, Report> {
+    let status = vm
+        .ssh
+        .command("test")
+        .arg("-e")
+        .arg(marker(name)?)
+        .status()
+        .await
+        .wrap_err("failed to check for step marker")?;
+    Ok(status.success())
+}
+
+/// Run `f` on `vm`, unless the step `name` has already completed there.
+///
+/// Returns whether `f` ran. Step names may contain letters, digits, `-`, `_` and `.`, and should
+/// be unique across everything that is set up on the machine.
+pub async fn step<'r, 't, F, Fut>(vm: &'r Machine<'t>, name: &str, f: F) -> Result<bool, Report>
+where
+    F: FnOnce(&'r Machine<'t>) -> Fut,
+    Fut: Future<Output = Result<(), Report>>,
+{
+    let marker = marker(name)?;
+    if is_done(vm, name).await? {
+        tracing::debug!(step = %name, "step already done");
+        return Ok(false);
+    }
+
+    tracing::debug!(step = %name, "running step");
+    f(vm)
+        .await
+        .wrap_err_with(|| format!("step {} failed", name))?;
+    let status = vm
+        .ssh
+        .command("sh")
+        .arg("-c")
+        .arg(format!("mkdir -p {} && touch {}", MARKER_DIR, marker))
+        .status()
+        .await
+        .wrap_err("failed to record step completion")?;
+    eyre::ensure!(
+        status.success(),
+        "failed to record completion of step {}",
+        name
+    );
+    Ok(true)
+}
+
+/// Make the step `name` run again the next time setup runs on `vm`.
+pub async fn forget(vm: &Machine<'_>, name: &str) -> Result<(), Report> {
+    let status = vm
+        .ssh
+        .command("rm")
+        .arg("-f")
+        .arg(marker(name)?)
+        .status()
+        .await
+        .wrap_err("failed to remove step marker")?;
+    eyre::ensure!(status.success(), "failed to forget step {}", name);
+    Ok(())
+}
+
+type StepFn = Arc<
+    dyn for<'r> Fn(&'r Machine<'_>) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+/// An ordered list of named [`step`]s, to use as a setup function.
+///
+/// See the [module documentation](self) for an example.
+#[derive(Clone, Default)]
+pub struct Steps {
+    steps: Vec<(String, StepFn)>,
+}
+
+impl std::fmt::Debug for Steps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.steps.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl Steps {
+    /// An empty list of steps.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the step `name`, which runs `f`, after the steps added so far.
+    pub fn step(
+        mut self,
+        name: impl ToString,
+        f: impl for<'r> Fn(
+                &'r Machine<'_>,
+            ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.steps.push((name.to_string(), Arc::new(f)));
+        self
+    }
+
+    /// The names of the steps, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.steps.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Run the steps on `vm` in order, skipping the ones that have already completed there.
+    ///
+    /// This stops at the first step that fails.
+    pub fn run<'r>(
+        &self,
+        vm: &'r Machine<'_>,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Report>> + Send + 'r>> {
+        let steps = self.steps.clone();
+        Box::pin(async move {
+            let mut seen = HashSet::new();
+            for (name, _) in &steps {
+                marker(name)?;
+                eyre::ensure!(seen.insert(name), "step {} is listed twice", name);
+            }
+            for (name, f) in &steps {
+                step(vm, name, |vm| f(vm)).await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn markers() {
+        assert_eq!(marker("apt-install").unwrap(), ".tsunami/steps/apt-install");
+        assert_eq!(marker("v1.2_build").unwrap(), ".tsunami/steps/v1.2_build");
+        for bad in ["", ".", "..", "a/b", "a b", "$(reboot)"] {
+            assert!(marker(bad).is_err(), "{:?}", bad);
+        }
+
+        let steps = Steps::new()
+            .step("a", |_| Box::pin(async { Ok(()) }))
+            .step("b", |_| Box::pin(async { Ok(()) }));
+        assert_eq!(steps.names().collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(format!("{:?}", steps), r#"["a", "b"]"#);
+    }
+}
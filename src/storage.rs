@@ -0,0 +1,394 @@
+//! Set up local storage on machines.
+//!
+//! Instance types with local NVMe disks (such as EC2's `i3` or `c5d`) come with those disks
+//! unformatted and unmounted. [`mount_instance_store`] takes care of that, and can be called from
+//! a setup function, or run automatically with
+//! [`aws::Setup::instance_store_at`](crate::providers::aws::Setup::instance_store_at).
+//!
+//! For anything more involved, such as striping several disks together, describe the layout with
+//! a [`DiskLayout`] and [`apply`](DiskLayout::apply) it:
+//!
+//! ```rust,no_run
+//! use tsunami::providers::aws::Setup;
+//! use tsunami::storage::{DiskLayout, Filesystem, RaidLevel};
+//!
+//! let m = Setup::default().instance_type("i3.8xlarge").setup(|vm| {
+//!     Box::pin(async move {
+//!         DiskLayout::instance_store("/data")
+//!             .raid(RaidLevel::Raid0)
+//!             .filesystem(Filesystem::Xfs)
+//!             .mount_options("noatime")
+//!             .apply(vm)
+//!             .await
+//!     })
+//! });
+//! ```
+
+use color_eyre::{eyre, eyre::WrapErr, Report};
+
+// Sets $devs to the instance-store devices.
+const INSTANCE_STORE_DEVICES: &str =
+    "devs=$(lsblk -dpno NAME,MODEL | awk '/Instance Storage/ {print $1}')";
+
+// Lists the instance-store devices, formats and mounts each of them, and prints the mount points.
+fn mount_script(path: &str) -> String {
+    format!(
+        r#"set -e
+{devices}
+n=$(echo "$devs" | grep -c . || true)
+i=0
+for d in $devs; do
+  if [ "$n" -eq 1 ]; then m='{path}'; else m='{path}'/$i; fi
+  sudo mkfs.ext4 -q -E nodiscard "$d"
+  sudo mkdir -p "$m"
+  sudo mount "$d" "$m"
+  sudo chown "$(id -u):$(id -g)" "$m"
+  echo "$m"
+  i=$((i + 1))
+done
+"#,
+        devices = INSTANCE_STORE_DEVICES,
+        path = path
+    )
+}
+
+/// Format and mount the instance-store NVMe devices of an EC2 instance.
+///
+/// If the instance has a single such device, it is mounted at `path`. If it has several, they are
+/// mounted at `path/0`, `path/1`, and so on. The mount points are owned by the ssh user, and are
+/// returned in order. Instances without an instance store are left alone, and yield no mount
+/// points.
+///
+/// The devices are formatted unconditionally, so only call this once per machine.
+///
+/// ```rust,no_run
+/// use tsunami::providers::aws::Setup;
+///
+/// let m = Setup::default().instance_type("i3.2xlarge").setup(|vm| {
+///     Box::pin(async move {
+///         let mounts = tsunami::storage::mount_instance_store(vm, "/data").await?;
+///         color_eyre::eyre::ensure!(!mounts.is_empty(), "no instance store");
+///         Ok(())
+///     })
+/// });
+/// ```
+pub async fn mount_instance_store(
+    m: &crate::Machine<'_>,
+    path: &str,
+) -> Result<Vec<String>, Report> {
+    let out = m
+        .ssh
+        .command("sh")
+        .arg("-c")
+        .arg(mount_script(path))
+        .output()
+        .await?;
+    eyre::ensure!(
+        out.status.success(),
+        "failed to mount instance store: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let mounts: Vec<_> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(String::from)
+        .collect();
+    tracing::debug!(?mounts, "mounted instance store");
+    Ok(mounts)
+}
+
+/// The RAID level to combine the devices of a [`DiskLayout`] with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaidLevel {
+    /// Striping.
+    Raid0,
+    /// Mirroring.
+    Raid1,
+    /// Striped mirrors. Needs at least four devices.
+    Raid10,
+}
+
+impl RaidLevel {
+    fn as_str(self) -> &'static str {
+        match self {
+            RaidLevel::Raid0 => "raid0",
+            RaidLevel::Raid1 => "raid1",
+            RaidLevel::Raid10 => "raid10",
+        }
+    }
+
+    fn min_devices(self) -> usize {
+        match self {
+            RaidLevel::Raid0 | RaidLevel::Raid1 => 2,
+            RaidLevel::Raid10 => 4,
+        }
+    }
+}
+
+/// The filesystem to create for a [`DiskLayout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filesystem {
+    /// ext4.
+    Ext4,
+    /// XFS. The image must have `mkfs.xfs`, e.g. from Ubuntu's `xfsprogs`.
+    Xfs,
+}
+
+impl Filesystem {
+    fn as_str(self) -> &'static str {
+        match self {
+            Filesystem::Ext4 => "ext4",
+            Filesystem::Xfs => "xfs",
+        }
+    }
+
+    fn mkfs(self) -> &'static str {
+        match self {
+            Filesystem::Ext4 => "mkfs.ext4 -q -F",
+            Filesystem::Xfs => "mkfs.xfs -q -f",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Devices {
+    InstanceStore,
+    Paths(Vec<String>),
+}
+
+// The md device a layout's RAID array is created as.
+const MD_DEVICE: &str = "/dev/md/tsunami";
+
+/// A description of how to turn a machine's disks into a mounted filesystem.
+///
+/// The devices are optionally combined into a software RAID array with `mdadm`, formatted, and
+/// mounted at the mount point, which is owned by the ssh user. [`DiskLayout::apply`] then checks
+/// that the result is what was asked for. See the [module documentation](self) for an example.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskLayout {
+    devices: Devices,
+    raid: Option<RaidLevel>,
+    filesystem: Filesystem,
+    mount_point: String,
+    mount_options: Option<String>,
+}
+
+impl DiskLayout {
+    /// Lay out the given devices, e.g. `/dev/nvme1n1`, and mount the result at `mount_point`.
+    pub fn new<I, S>(devices: I, mount_point: impl ToString) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: ToString,
+    {
+        Self::with_devices(
+            Devices::Paths(devices.into_iter().map(|d| d.to_string()).collect()),
+            mount_point,
+        )
+    }
+
+    /// Lay out all of an EC2 instance's instance-store devices, and mount the result at
+    /// `mount_point`.
+    pub fn instance_store(mount_point: impl ToString) -> Self {
+        Self::with_devices(Devices::InstanceStore, mount_point)
+    }
+
+    fn with_devices(devices: Devices, mount_point: impl ToString) -> Self {
+        DiskLayout {
+            devices,
+            raid: None,
+            filesystem: Filesystem::Ext4,
+            mount_point: mount_point.to_string(),
+            mount_options: None,
+        }
+    }
+
+    /// Combine the devices into a RAID array.
+    ///
+    /// Without this, the layout must have exactly one device.
+    pub fn raid(mut self, level: RaidLevel) -> Self {
+        self.raid = Some(level);
+        self
+    }
+
+    /// Create `fs` instead of the default ext4.
+    pub fn filesystem(mut self, fs: Filesystem) -> Self {
+        self.filesystem = fs;
+        self
+    }
+
+    /// Mount with the given options, e.g. `noatime`.
+    pub fn mount_options(mut self, opts: impl ToString) -> Self {
+        self.mount_options = Some(opts.to_string());
+        self
+    }
+
+    // `os` is where mdadm is installed from if the layout needs it and the machine lacks it.
+    fn script(&self, os: Option<crate::OsFamily>) -> Result<String, Report> {
+        let devices = match self.devices {
+            Devices::InstanceStore => INSTANCE_STORE_DEVICES.to_string(),
+            Devices::Paths(ref ps) => {
+                eyre::ensure!(!ps.is_empty(), "disk layout has no devices");
+                match self.raid {
+                    None => eyre::ensure!(ps.len() == 1, "{} devices need a RAID level", ps.len()),
+                    Some(level) => eyre::ensure!(
+                        ps.len() >= level.min_devices(),
+                        "{} needs at least {} devices, but the layout has {}",
+                        level.as_str(),
+                        level.min_devices(),
+                        ps.len()
+                    ),
+                }
+                format!("devs='{}'", ps.join(" "))
+            }
+        };
+        let combine = match self.raid {
+            Some(level) => {
+                let os = os.ok_or_else(|| {
+                    eyre::eyre!("installing mdadm needs the machine's operating system")
+                })?;
+                format!(
+                    r#"[ "$n" -ge {min} ] || {{ echo "{level} needs at least {min} devices, found $n" >&2; exit 1; }}
+command -v mdadm >/dev/null || sudo {install}
+sudo mdadm --create {md} --run --level={level} --raid-devices=$n $devs
+dev={md}"#,
+                    min = level.min_devices(),
+                    install = os.install_command(&["mdadm"]).join(" "),
+                    md = MD_DEVICE,
+                    level = level.as_str(),
+                )
+            }
+            None => String::from(
+                r#"[ "$n" -eq 1 ] || { echo "$n devices need a RAID level" >&2; exit 1; }
+dev=$devs"#,
+            ),
+        };
+        let opts = match self.mount_options {
+            Some(ref o) => format!(" -o '{}'", o),
+            None => String::new(),
+        };
+        Ok(format!(
+            r#"set -e
+{devices}
+n=$(echo $devs | wc -w)
+[ "$n" -gt 0 ] || {{ echo "no devices to lay out" >&2; exit 1; }}
+{combine}
+sudo {mkfs} "$dev"
+sudo mkdir -p '{mnt}'
+sudo mount{opts} "$dev" '{mnt}'
+sudo chown "$(id -u):$(id -g)" '{mnt}'
+"#,
+            devices = devices,
+            combine = combine,
+            mkfs = self.filesystem.mkfs(),
+            mnt = self.mount_point,
+            opts = opts,
+        ))
+    }
+
+    // Prints the filesystem type at the mount point, and the level of the RAID array, if any.
+    fn check_script(&self) -> String {
+        let mut s = format!("findmnt -no FSTYPE '{}'\n", self.mount_point);
+        if self.raid.is_some() {
+            s.push_str(&format!(
+                "cat /sys/block/$(basename $(readlink -f {}))/md/level\n",
+                MD_DEVICE
+            ));
+        }
+        s
+    }
+
+    fn check(&self, out: &str) -> Result<(), Report> {
+        let mut lines = out.lines().map(str::trim);
+        let fs = lines.next().unwrap_or_default();
+        eyre::ensure!(
+            fs == self.filesystem.as_str(),
+            "{} has filesystem {:?}, expected {}",
+            self.mount_point,
+            fs,
+            self.filesystem.as_str()
+        );
+        if let Some(level) = self.raid {
+            let got = lines.next().unwrap_or_default();
+            eyre::ensure!(
+                got == level.as_str(),
+                "RAID array has level {:?}, expected {}",
+                got,
+                level.as_str()
+            );
+        }
+        Ok(())
+    }
+
+    /// Set up the disks of `m` according to this layout, and check the result.
+    ///
+    /// The devices are formatted unconditionally, so only call this once per machine.
+    pub async fn apply(&self, m: &crate::Machine<'_>) -> Result<(), Report> {
+        let os = match (self.raid, m.os) {
+            (None, _) => None,
+            (Some(_), Some(os)) => Some(os),
+            (Some(_), None) => Some(crate::OsFamily::detect(&m.ssh).await?),
+        };
+        let out = m
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(self.script(os)?)
+            .output()
+            .await?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to set up disks: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+
+        let out = m
+            .ssh
+            .command("sh")
+            .arg("-c")
+            .arg(self.check_script())
+            .output()
+            .await?;
+        self.check(&String::from_utf8_lossy(&out.stdout))
+            .wrap_err("disk layout was not applied correctly")?;
+        tracing::debug!(mount_point = %self.mount_point, "applied disk layout");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn script() {
+        let s = super::mount_script("/data");
+        assert!(s.contains("then m='/data'; else m='/data'/$i; fi"));
+        assert!(s.contains("awk '/Instance Storage/ {print $1}'"));
+    }
+
+    #[test]
+    fn layout() {
+        assert!(DiskLayout::new(vec!["/dev/a", "/dev/b"], "/data")
+            .script(None)
+            .is_err());
+        assert!(DiskLayout::new(vec!["/dev/a", "/dev/b"], "/data")
+            .raid(RaidLevel::Raid10)
+            .script(Some(crate::OsFamily::Ubuntu))
+            .is_err());
+
+        let l = DiskLayout::new(vec!["/dev/a", "/dev/b"], "/data")
+            .raid(RaidLevel::Raid0)
+            .filesystem(Filesystem::Xfs)
+            .mount_options("noatime");
+        assert!(l.script(None).is_err());
+        let s = l.script(Some(crate::OsFamily::AmazonLinux)).unwrap();
+        assert!(s.contains("devs='/dev/a /dev/b'"));
+        assert!(s.contains("|| sudo yum install -y mdadm\n"));
+        assert!(s.contains("--level=raid0 --raid-devices=$n $devs"));
+        assert!(s.contains("sudo mkfs.xfs -q -f \"$dev\""));
+        assert!(s.contains("sudo mount -o 'noatime' \"$dev\" '/data'"));
+
+        assert!(l.check("xfs\nraid0\n").is_ok());
+        assert!(l.check("ext4\nraid0\n").is_err());
+        assert!(l.check("xfs\nraid1\n").is_err());
+    }
+}
@@ -0,0 +1,251 @@
+//! A live terminal view of launch progress.
+//!
+//! [`Progress`] is a [`tracing_subscriber::Layer`] that listens for the events the providers emit
+//! on [`PROGRESS_TARGET`](crate::providers::PROGRESS_TARGET), and keeps a table of every machine,
+//! its current [`MachineState`](crate::providers::MachineState), and how long it has been
+//! launching, along with the most recent warnings and errors. The table is redrawn in place on the
+//! terminal whenever it changes, so it is best used instead of a log formatter rather than
+//! alongside one.
+//!
+//! ```rust,no_run
+//! use tracing_subscriber::prelude::*;
+//! use tsunami::Tsunami;
+//!
+//! #[tokio::main]
+//! async fn main() -> Result<(), color_eyre::Report> {
+//!     let progress = tsunami::tui::Progress::stderr();
+//!     tracing_subscriber::registry().with(progress.clone()).init();
+//!
+//!     let mut aws = tsunami::providers::aws::Launcher::default();
+//!     let ms = tsunami::make_multiple(50, "worker", Default::default());
+//!     aws.spawn(ms, None).await?;
+//!     progress.redraw();
+//!     aws.terminate_all().await?;
+//!     Ok(())
+//! }
+//! ```
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+// how many warnings and errors to keep on screen.
+const RECENT_ERRORS: usize = 5;
+
+#[derive(Debug)]
+struct Row {
+    nickname: String,
+    state: String,
+    started: Instant,
+    finished: Option<Instant>,
+}
+
+struct Table {
+    machines: Vec<Row>,
+    errors: VecDeque<String>,
+    drawn_lines: usize,
+    out: Box<dyn Write + Send>,
+}
+
+impl Table {
+    fn update(&mut self, nickname: &str, state: &str, now: Instant) {
+        let row = match self.machines.iter_mut().find(|r| r.nickname == nickname) {
+            Some(r) => r,
+            None => {
+                self.machines.push(Row {
+                    nickname: nickname.to_string(),
+                    state: String::new(),
+                    started: now,
+                    finished: None,
+                });
+                self.machines.last_mut().unwrap()
+            }
+        };
+
+        row.state = state.to_string();
+        row.finished = match state {
+            "ready" | "setup-failed" | "terminated" => Some(now),
+            _ => None,
+        };
+    }
+
+    fn error(&mut self, msg: String) {
+        if self.errors.len() == RECENT_ERRORS {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(msg);
+    }
+
+    fn render(&self, now: Instant) -> String {
+        let width = self
+            .machines
+            .iter()
+            .map(|r| r.nickname.len())
+            .chain(std::iter::once("MACHINE".len()))
+            .max()
+            .unwrap();
+
+        let mut s = String::new();
+        let _ = writeln!(s, "{:width$}  {:12}  ELAPSED", "MACHINE", "STATE");
+        for r in &self.machines {
+            let elapsed = r.finished.unwrap_or(now).duration_since(r.started);
+            let _ = writeln!(
+                s,
+                "{:width$}  {:12}  {}",
+                r.nickname,
+                r.state,
+                fmt_elapsed(elapsed)
+            );
+        }
+
+        if !self.errors.is_empty() {
+            let _ = writeln!(s, "\nrecent errors:");
+            for e in &self.errors {
+                let _ = writeln!(s, "  {}", e);
+            }
+        }
+        s
+    }
+
+    fn redraw(&mut self) {
+        let frame = self.render(Instant::now());
+        // move back over the previous frame and clear it.
+        if self.drawn_lines > 0 {
+            let _ = write!(self.out, "\x1b[{}A\x1b[J", self.drawn_lines);
+        }
+        let _ = self.out.write_all(frame.as_bytes());
+        let _ = self.out.flush();
+        self.drawn_lines = frame.lines().count();
+    }
+}
+
+fn fmt_elapsed(d: Duration) -> String {
+    let s = d.as_secs();
+    format!("{}:{:02}", s / 60, s % 60)
+}
+
+/// A [`Layer`] that renders launch progress as a live table.
+///
+/// See the [module documentation](self) for an example. Clones share the same table, so keep a
+/// clone around to call [`Progress::redraw`] after installing the layer.
+#[derive(Clone)]
+pub struct Progress(Arc<Mutex<Table>>);
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let t = self.0.lock().unwrap();
+        f.debug_struct("Progress")
+            .field("machines", &t.machines)
+            .field("errors", &t.errors)
+            .finish()
+    }
+}
+
+impl Progress {
+    /// Draw the table on standard error.
+    pub fn stderr() -> Self {
+        Self::to_writer(std::io::stderr())
+    }
+
+    /// Draw the table on `out`, which should be a terminal that understands ANSI escape codes.
+    pub fn to_writer(out: impl Write + Send + 'static) -> Self {
+        Progress(Arc::new(Mutex::new(Table {
+            machines: Vec::new(),
+            errors: VecDeque::new(),
+            drawn_lines: 0,
+            out: Box::new(out),
+        })))
+    }
+
+    /// Redraw the table, e.g. to update the elapsed times.
+    pub fn redraw(&self) {
+        self.0.lock().unwrap().redraw();
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    nickname: Option<String>,
+    state: Option<String>,
+    message: Option<String>,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let v = format!("{:?}", value);
+        match field.name() {
+            "nickname" | "name" => self.nickname = Some(v),
+            "state" => self.state = Some(v),
+            "message" => self.message = Some(v),
+            _ => {}
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{}", value))
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Progress {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let progress = meta.target() == crate::providers::PROGRESS_TARGET;
+        if !progress && *meta.level() > Level::WARN {
+            return;
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let mut t = self.0.lock().unwrap();
+        match fields {
+            Fields {
+                nickname: Some(nickname),
+                state: Some(state),
+                ..
+            } if progress => t.update(&nickname, &state, Instant::now()),
+            Fields {
+                nickname, message, ..
+            } => {
+                let message = message.unwrap_or_default();
+                t.error(match nickname {
+                    Some(n) => format!("{} {}: {}", meta.level(), n, message),
+                    None => format!("{} {}", meta.level(), message),
+                });
+            }
+        }
+        t.redraw();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let p = Progress::to_writer(std::io::sink());
+        let mut t = p.0.lock().unwrap();
+        let start = Instant::now();
+        t.update("server", "booting", start);
+        t.update("client-0", "pending", start);
+        t.update("server", "ready", start + Duration::from_secs(61));
+        t.error(String::from("WARN client-0: spot request failed"));
+
+        let frame = t.render(start + Duration::from_secs(100));
+        assert_eq!(
+            frame,
+            "MACHINE   STATE         ELAPSED\n\
+             server    ready         1:01\n\
+             client-0  pending       1:40\n\
+             \n\
+             recent errors:\n  \
+             WARN client-0: spot request failed\n"
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Check that launched machines are what was asked for.
+//!
+//! Cloud providers occasionally hand out something other than what was requested: a different
+//! CPU generation, a kernel update baked into the image, or a NIC that negotiated a lower speed.
+//! Any of those silently skews benchmark results. [`Expectations`] describes what a machine
+//! should look like, and [`Expectations::verify`] fails with a report of every mismatch.
+//!
+//! The simplest way to use this is to have the provider run the check before the machine's setup
+//! function, with the `verify` method every provider's `Setup` has, e.g.
+//! [`aws::Setup::verify`](crate::providers::aws::Setup::verify). If the check fails, the machine
+//! is treated like one whose setup failed. Call `verify` after `setup`, since `setup` replaces
+//! any earlier setup function:
+//!
+//! ```rust
+//! use tsunami::providers::aws;
+//! use tsunami::verify::Expectations;
+//!
+//! let m = aws::Setup::default()
+//!     .instance_type("c5.4xlarge")
+//!     .verify(
+//!         Expectations::default()
+//!             .instance_type("c5.4xlarge")
+//!             .min_vcpus(16)
+//!             .numa_nodes(1)
+//!             .kernel("5.4."),
+//!     );
+//! ```
+
+use color_eyre::{eyre, Report};
+use std::collections::HashMap;
+
+// Prints one `key=value` line per fact. The instance type comes from the EC2 metadata service
+// (with an IMDSv2 token if one can be had), and is empty elsewhere.
+const GATHER: &str = r#"
+echo "vcpus=$(nproc)"
+echo "numa=$(ls -d /sys/devices/system/node/node* 2>/dev/null | wc -l)"
+echo "kernel=$(uname -r)"
+for i in /sys/class/net/*; do echo "nic.$(basename "$i")=$(cat "$i/speed" 2>/dev/null)"; done
+t=$(curl -sf -m 2 -X PUT -H 'X-aws-ec2-metadata-token-ttl-seconds: 60' http://169.254.169.254/latest/api/token)
+echo "itype=$(curl -sf -m 2 -H "X-aws-ec2-metadata-token: $t" http://169.254.169.254/latest/meta-data/instance-type)"
+"#;
+
+/// What a machine turned out to be.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Facts {
+    /// The instance type, if the machine is on EC2.
+    pub instance_type: Option<String>,
+    /// The number of CPUs available.
+    pub vcpus: Option<usize>,
+    /// The number of NUMA nodes.
+    pub numa_nodes: Option<usize>,
+    /// The kernel release, as reported by `uname -r`.
+    pub kernel: Option<String>,
+    /// The link speed of each network interface in Mbit/s, for interfaces that report one.
+    pub nic_speeds: HashMap<String, u64>,
+}
+
+impl Facts {
+    /// Inspect `m`.
+    pub async fn gather(m: &crate::Machine<'_>) -> Result<Self, Report> {
+        let out = m.ssh.command("sh").arg("-c").arg(GATHER).output().await?;
+        eyre::ensure!(
+            out.status.success(),
+            "failed to inspect machine: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+        Ok(Self::parse(&String::from_utf8_lossy(&out.stdout)))
+    }
+
+    fn parse(out: &str) -> Self {
+        let mut f = Facts::default();
+        for (k, v) in out.lines().filter_map(|l| l.split_once('=')) {
+            let v = v.trim();
+            if v.is_empty() {
+                continue;
+            }
+            match k {
+                "vcpus" => f.vcpus = v.parse().ok(),
+                "numa" => f.numa_nodes = v.parse().ok().filter(|&n| n > 0),
+                "kernel" => f.kernel = Some(v.to_string()),
+                "itype" => f.instance_type = Some(v.to_string()),
+                k => {
+                    // virtual interfaces report -1
+                    if let (Some(nic), Ok(speed)) = (k.strip_prefix("nic."), v.parse()) {
+                        f.nic_speeds.insert(nic.to_string(), speed);
+                    }
+                }
+            }
+        }
+        f
+    }
+}
+
+/// A description of what a machine should look like.
+///
+/// Only the properties that are set are checked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Expectations {
+    instance_type: Option<String>,
+    min_vcpus: Option<usize>,
+    numa_nodes: Option<usize>,
+    kernel: Option<String>,
+    nic_speed: Option<(String, u64)>,
+}
+
+impl Expectations {
+    /// The machine must be an EC2 instance of type `t`.
+    pub fn instance_type(mut self, t: impl ToString) -> Self {
+        self.instance_type = Some(t.to_string());
+        self
+    }
+
+    /// The machine must have at least `n` CPUs.
+    pub fn min_vcpus(mut self, n: usize) -> Self {
+        self.min_vcpus = Some(n);
+        self
+    }
+
+    /// The machine must have exactly `n` NUMA nodes.
+    pub fn numa_nodes(mut self, n: usize) -> Self {
+        self.numa_nodes = Some(n);
+        self
+    }
+
+    /// The kernel release must start with `prefix`, e.g. `5.4.`.
+    pub fn kernel(mut self, prefix: impl ToString) -> Self {
+        self.kernel = Some(prefix.to_string());
+        self
+    }
+
+    /// Network interface `iface` must have a link speed of at least `mbps` Mbit/s.
+    pub fn nic_speed(mut self, iface: impl ToString, mbps: u64) -> Self {
+        self.nic_speed = Some((iface.to_string(), mbps));
+        self
+    }
+
+    /// Check `facts` against these expectations, and list every mismatch in the error.
+    pub fn check(&self, facts: &Facts) -> Result<(), Report> {
+        let mut wrong = Vec::new();
+        if let Some(ref t) = self.instance_type {
+            if facts.instance_type.as_ref() != Some(t) {
+                wrong.push(format!(
+                    "instance type is {:?}, expected {}",
+                    facts.instance_type, t
+                ));
+            }
+        }
+        if let Some(n) = self.min_vcpus {
+            if !matches!(facts.vcpus, Some(v) if v >= n) {
+                wrong.push(format!("{:?} vcpus, expected at least {}", facts.vcpus, n));
+            }
+        }
+        if let Some(n) = self.numa_nodes {
+            if facts.numa_nodes != Some(n) {
+                wrong.push(format!("{:?} numa nodes, expected {}", facts.numa_nodes, n));
+            }
+        }
+        if let Some(ref k) = self.kernel {
+            if !matches!(facts.kernel, Some(ref v) if v.starts_with(k.as_str())) {
+                wrong.push(format!("kernel is {:?}, expected {}*", facts.kernel, k));
+            }
+        }
+        if let Some((ref iface, mbps)) = self.nic_speed {
+            let speed = facts.nic_speeds.get(iface);
+            if !matches!(speed, Some(&s) if s >= mbps) {
+                wrong.push(format!(
+                    "{} link speed is {:?} Mbit/s, expected at least {}",
+                    iface, speed, mbps
+                ));
+            }
+        }
+
+        eyre::ensure!(
+            wrong.is_empty(),
+            "machine does not match expectations: {}",
+            wrong.join("; ")
+        );
+        Ok(())
+    }
+
+    /// Inspect `m` and check it against these expectations.
+    pub async fn verify(&self, m: &crate::Machine<'_>) -> Result<(), Report> {
+        let facts = Facts::gather(m).await?;
+        tracing::debug!(?facts, "inspected machine");
+        self.check(&facts)
+    }
+
+    #[cfg(any(
+        feature = "aliyun",
+        feature = "aws",
+        feature = "azure",
+        feature = "baremetal",
+        feature = "docker",
+        feature = "firecracker",
+        feature = "hetzner",
+        feature = "vagrant"
+    ))]
+    pub(crate) fn into_setup_fn(self) -> crate::providers::SetupFn {
+        std::sync::Arc::new(move |vm| {
+            let expect = self.clone();
+            Box::pin(async move { expect.verify(vm).await })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_check() {
+        let facts = Facts::parse(
+            "vcpus=16\nnuma=1\nkernel=5.4.0-1045-aws\nnic.lo=\nnic.ens5=25000\nnic.docker0=-1\nitype=c5.4xlarge\n",
+        );
+        assert_eq!(facts.vcpus, Some(16));
+        assert_eq!(facts.nic_speeds.len(), 1);
+
+        let e = Expectations::default()
+            .instance_type("c5.4xlarge")
+            .min_vcpus(16)
+            .numa_nodes(1)
+            .kernel("5.4.")
+            .nic_speed("ens5", 10000);
+        assert!(e.check(&facts).is_ok());
+
+        let err = e.min_vcpus(32).kernel("5.10.").check(&facts).unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("vcpus"));
+        assert!(err.contains("kernel"));
+        assert!(!err.contains("instance type"));
+    }
+}